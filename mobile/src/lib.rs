@@ -1,11 +1,12 @@
 use std::convert::TryFrom;
 use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
 
 use tracing::{error, info};
 
 use metrics::Metrics;
 use mycelium::endpoint::Endpoint;
-use mycelium::{crypto, metrics, Config, Node};
+use mycelium::{crypto, metrics, Config, Node, DEFAULT_READY_MIN_PEERS};
 use once_cell::sync::Lazy;
 use tokio::sync::{mpsc, Mutex};
 
@@ -76,23 +77,55 @@ pub async fn start_mycelium(peers: Vec<String>, tun_fd: i32, priv_key: Vec<u8>)
         node_key: secret_key,
         peers: endpoints,
         no_tun: false,
-        tcp_listen_port: DEFAULT_TCP_LISTEN_PORT,
+        tcp_listen_addrs: vec![SocketAddr::new(
+            Ipv6Addr::UNSPECIFIED.into(),
+            DEFAULT_TCP_LISTEN_PORT,
+        )],
         quic_listen_port: None,
         peer_discovery_port: None, // disable multicast discovery
         #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
         tun_name: "tun0".to_string(),
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        tun_mtu: mycelium::DEFAULT_TUN_MTU,
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        tun_route: Default::default(),
 
         metrics: NoMetrics,
         private_network_config: None,
+        peering_key: None,
         firewall_mark: None,
+        inbound_connection_limits: Default::default(),
+        dial_timeouts: Default::default(),
+        proxy: None,
+        peer_access_list: Default::default(),
+        route_access_list: Default::default(),
+        split_horizon: Default::default(),
+        route_selector: Box::new(mycelium::route_selection::MetricSelector::default()),
+        route_hold_time_multiplier: mycelium::router::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER,
+        intervals: mycelium::router::Intervals::default(),
+        queue_sizes: mycelium::router::QueueSizes::default(),
+        flap_damping: Default::default(),
+        hello_history: Default::default(),
+        metric_offsets: Default::default(),
+        max_routes: None,
+        event_log_size: mycelium::event_log::DEFAULT_EVENT_LOG_SIZE,
+        ipv4_mappings: Default::default(),
+        exit_node: false,
+        exit_source_prefixes: vec![],
+        accept_default_route: false,
+        compress_data: false,
+        packet_workers: 1,
+        packet_capture: None,
+        echo_reply: Some(Default::default()),
+        ready_min_peers: DEFAULT_READY_MIN_PEERS,
         #[cfg(any(target_os = "android", target_os = "ios"))]
         tun_fd: Some(tun_fd),
     };
-    let _node = Node::new(config).await;
+    let node = Node::new(config).await;
 
-    match _node {
+    match node {
         Ok(_) => info!("node successfully created"),
-        Err(err) => error!("failed to create mycelium node: {err}"),
+        Err(ref err) => error!("failed to create mycelium node: {err}"),
     };
 
     let mut rx = CHANNEL.1.lock().await;
@@ -104,6 +137,9 @@ pub async fn start_mycelium(peers: Vec<String>, tun_fd: i32, priv_key: Vec<u8>)
             info!("Received stop channel, stopping mycelium node");
         }
     }
+    if let Ok(node) = node {
+        node.shutdown().await;
+    }
     info!("mycelium stopped");
 }
 
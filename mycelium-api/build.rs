@@ -0,0 +1,10 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        // Use a vendored protoc, so it doesn't need to be installed to build the gRPC API.
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::configure().compile_protos(&["proto/admin.proto"], &["proto"])?;
+    }
+
+    Ok(())
+}
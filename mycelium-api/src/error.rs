@@ -0,0 +1,241 @@
+//! Errors returned by the handlers of the API.
+//!
+//! Errors are returned as problem details, as described in
+//! [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807), with the `application/problem+json` media
+//! type. Besides the standard members, the body has a machine readable [`ErrorCode`], so clients
+//! can handle errors without matching on the human readable detail.
+
+use core::fmt;
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "message")]
+use mycelium::message::PushMessageError;
+use mycelium::{
+    peer_manager::{PeerExists, PeerNotFound, ReplacePeerError, SetMtuError},
+    pinned_route::{InvalidPinnedRoute, PinnedRouteNotFound},
+};
+
+/// Media type of the body of error responses.
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem type of all errors. The kind of error is identified by the [`ErrorCode`] instead.
+const PROBLEM_TYPE: &str = "about:blank";
+
+/// Machine readable identifier of the kind of an [`ApiError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is malformed, e.g. it has an invalid endpoint or subnet.
+    InvalidRequest,
+    /// The API key is missing or invalid.
+    Unauthorized,
+    /// No peer identified by the endpoint exists.
+    PeerNotFound,
+    /// A peer identified by the endpoint already exists.
+    PeerExists,
+    /// There is no pinned route for the subnet.
+    PinnedRouteNotFound,
+    /// There is no route for the IP.
+    RouteNotFound,
+    /// The public key of the node which owns the IP is not known.
+    PublicKeyNotFound,
+    /// There is no message with the id.
+    MessageNotFound,
+    /// The node failed to handle a valid request.
+    Internal,
+}
+
+impl ErrorCode {
+    /// The HTTP status of responses with this code.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::InvalidRequest => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::PeerNotFound
+            | Self::PinnedRouteNotFound
+            | Self::RouteNotFound
+            | Self::PublicKeyNotFound
+            | Self::MessageNotFound => StatusCode::NOT_FOUND,
+            Self::PeerExists => StatusCode::CONFLICT,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Body of an error response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProblemDetails {
+    /// URI identifying the type of problem. This is always `about:blank`, the `code` identifies
+    /// the kind of error.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the problem, this is the reason phrase of the status.
+    pub title: String,
+    /// HTTP status of the response.
+    pub status: u16,
+    /// Human readable explanation of the error.
+    pub detail: String,
+    /// Machine readable identifier of the kind of error.
+    pub code: ErrorCode,
+}
+
+/// An error returned by a handler of the API, which is converted to a response with
+/// [`ProblemDetails`] as body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    code: ErrorCode,
+    detail: String,
+}
+
+impl ApiError {
+    /// Create a new `ApiError` with the given code and human readable detail.
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    /// Create a new `ApiError` for a malformed request, with the reason as detail.
+    pub fn invalid_request(reason: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::InvalidRequest, reason.to_string())
+    }
+
+    /// The code of the error.
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The [`ProblemDetails`] sent as body of the response.
+    pub fn problem_details(&self) -> ProblemDetails {
+        let status = self.code.status();
+        ProblemDetails {
+            problem_type: PROBLEM_TYPE.to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: self.detail.clone(),
+            code: self.code,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.code.status(),
+            // This overrides the content type set by the JSON body.
+            [(header::CONTENT_TYPE, PROBLEM_JSON_CONTENT_TYPE)],
+            Json(self.problem_details()),
+        )
+            .into_response()
+    }
+}
+
+impl From<PeerNotFound> for ApiError {
+    fn from(_: PeerNotFound) -> Self {
+        Self::new(
+            ErrorCode::PeerNotFound,
+            "A peer identified by that endpoint does not exist",
+        )
+    }
+}
+
+impl From<PeerExists> for ApiError {
+    fn from(_: PeerExists) -> Self {
+        Self::new(
+            ErrorCode::PeerExists,
+            "A peer identified by that endpoint already exists",
+        )
+    }
+}
+
+impl From<ReplacePeerError> for ApiError {
+    fn from(e: ReplacePeerError) -> Self {
+        match e {
+            ReplacePeerError::NotFound(e) => e.into(),
+            ReplacePeerError::Exists(PeerExists) => Self::new(
+                ErrorCode::PeerExists,
+                "A peer identified by the new endpoint already exists",
+            ),
+        }
+    }
+}
+
+impl From<SetMtuError> for ApiError {
+    fn from(e: SetMtuError) -> Self {
+        match e {
+            SetMtuError::NotFound(e) => e.into(),
+            SetMtuError::TooSmall => Self::invalid_request(e),
+        }
+    }
+}
+
+impl From<InvalidPinnedRoute> for ApiError {
+    fn from(e: InvalidPinnedRoute) -> Self {
+        Self::invalid_request(e)
+    }
+}
+
+impl From<PinnedRouteNotFound> for ApiError {
+    fn from(e: PinnedRouteNotFound) -> Self {
+        Self::new(ErrorCode::PinnedRouteNotFound, e.to_string())
+    }
+}
+
+#[cfg(feature = "message")]
+impl From<PushMessageError> for ApiError {
+    fn from(e: PushMessageError) -> Self {
+        Self::invalid_request(e)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header, StatusCode},
+        response::IntoResponse,
+    };
+
+    use super::{ApiError, ErrorCode, PROBLEM_JSON_CONTENT_TYPE};
+
+    #[test]
+    fn api_error_is_problem_json() {
+        let response = ApiError::from(mycelium::peer_manager::PeerNotFound).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            PROBLEM_JSON_CONTENT_TYPE
+        );
+
+        let body = serde_json::to_value(
+            ApiError::new(ErrorCode::PeerExists, "Peer exists").problem_details(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Conflict",
+                "status": 409,
+                "detail": "Peer exists",
+                "code": "peer_exists",
+            })
+        );
+    }
+}
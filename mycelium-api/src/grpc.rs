@@ -0,0 +1,437 @@
+//! gRPC API, exposing the same operations as the admin endpoints of the HTTP API. The service is
+//! defined in `proto/admin.proto`.
+
+// `Status` is the error type of the tonic APIs, so there is no point in boxing it.
+#![allow(clippy::result_large_err)]
+
+use std::{net::IpAddr, net::SocketAddr, str::FromStr, sync::Arc};
+
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{debug, error};
+
+use mycelium::{
+    crypto::Cipher,
+    endpoint::Endpoint,
+    metrics::Metrics,
+    peer_manager::{
+        ConnectionState, DisconnectReason, PeerExists, PeerNotFound, PeerState, PeerStats, PeerType,
+    },
+};
+
+use crate::{ApiMode, Metric, PeerListQuery, Route, RouteEvent};
+
+/// Types and service definitions generated from `proto/admin.proto`.
+pub mod proto {
+    tonic::include_proto!("mycelium.admin.v1");
+}
+
+use proto::admin_server::{Admin, AdminServer};
+
+/// Amount of route events buffered for a subscriber, before events are read from the route event
+/// subscription again.
+const ROUTE_EVENT_STREAM_BUFFER: usize = 16;
+
+/// gRPC API server handle. The server is spawned in a background task. If this handle is dropped,
+/// the server is terminated.
+pub struct Grpc {
+    /// Channel to send cancellation to the gRPC api server. We just keep a reference to it since
+    /// dropping it will also cancel the receiver and thus the server.
+    _cancel_tx: oneshot::Sender<()>,
+}
+
+impl Grpc {
+    /// Spawns a new gRPC API server on the provided listening address. The node can be shared
+    /// with an HTTP API server spawned with [`Http::spawn_shared`](crate::Http::spawn_shared).
+    ///
+    /// If an API key is given, requests must present it as bearer token in the `authorization`
+    /// metadata. Other requests are rejected with an `UNAUTHENTICATED` status. In
+    /// [`ApiMode::ReadOnly`], methods which modify the node are rejected with an `UNIMPLEMENTED`
+    /// status.
+    pub fn spawn<M>(
+        node: Arc<Mutex<mycelium::Node<M>>>,
+        listen_addr: SocketAddr,
+        api_key: Option<String>,
+        mode: ApiMode,
+    ) -> Self
+    where
+        M: Metrics + Clone + Send + Sync + 'static,
+    {
+        let api_key = api_key.map(Arc::<str>::from);
+        let service = AdminServer::with_interceptor(
+            AdminService { node, mode },
+            move |request: Request<()>| require_api_key(api_key.as_deref(), request),
+        );
+
+        let (_cancel_tx, cancel_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let server =
+                Server::builder()
+                    .add_service(service)
+                    .serve_with_shutdown(listen_addr, async {
+                        cancel_rx.await.ok();
+                    });
+            if let Err(e) = server.await {
+                error!("gRPC API server error: {e}");
+            }
+        });
+
+        Grpc { _cancel_tx }
+    }
+}
+
+/// Reject requests which don't present the configured API key as bearer token, if there is one.
+fn require_api_key(api_key: Option<&str>, request: Request<()>) -> Result<Request<()>, Status> {
+    let Some(api_key) = api_key else {
+        return Ok(request);
+    };
+    match request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(crate::parse_bearer_token)
+    {
+        Some(token) if crate::constant_time_eq(token.as_bytes(), api_key.as_bytes()) => Ok(request),
+        _ => {
+            debug!("Rejecting gRPC request without valid API key");
+            Err(Status::unauthenticated("missing or invalid API key"))
+        }
+    }
+}
+
+/// Reject methods which modify the node, unless all methods are served.
+fn require_full_mode(mode: ApiMode) -> Result<(), Status> {
+    match mode {
+        ApiMode::Full => Ok(()),
+        ApiMode::ReadOnly => Err(Status::unimplemented("the API is read only")),
+    }
+}
+
+/// Implementation of the [`Admin`] service.
+struct AdminService<M> {
+    node: Arc<Mutex<mycelium::Node<M>>>,
+    mode: ApiMode,
+}
+
+#[tonic::async_trait]
+impl<M> Admin for AdminService<M>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    type SubscribeRouteEventsStream = ReceiverStream<Result<proto::RouteEvent, Status>>;
+
+    async fn get_info(
+        &self,
+        _: Request<proto::GetInfoRequest>,
+    ) -> Result<Response<proto::Info>, Status> {
+        let info = self.node.lock().await.info();
+        Ok(Response::new(proto::Info {
+            node_subnet: info.node_subnet.to_string(),
+            node_pubkey: info.node_pubkey.to_string(),
+            uptime: info.uptime.as_secs(),
+            version: info.version.to_string(),
+            connected_peers: info.connected_peers as u64,
+            selected_routes: info.selected_routes as u64,
+            router_seqno: info.router_seqno.into(),
+            tcp_listen_addresses: info
+                .tcp_listen_addrs
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }))
+    }
+
+    async fn list_peers(
+        &self,
+        request: Request<proto::ListPeersRequest>,
+    ) -> Result<Response<proto::ListPeersResponse>, Status> {
+        debug!("Fetching peer stats");
+        let request = request.into_inner();
+        let state = match request.state.map(proto::ConnectionState::try_from) {
+            None => None,
+            Some(Ok(proto::ConnectionState::Alive)) => Some(ConnectionState::Alive),
+            Some(Ok(proto::ConnectionState::Connecting)) => Some(ConnectionState::Connecting),
+            Some(Ok(proto::ConnectionState::Dead)) => Some(ConnectionState::Dead),
+            Some(Ok(proto::ConnectionState::Unspecified)) | Some(Err(_)) => {
+                return Err(Status::invalid_argument("invalid connection state"))
+            }
+        };
+        let query = PeerListQuery {
+            limit: request.limit.map(|limit| limit as usize),
+            offset: request.offset.map(|offset| offset as usize),
+            state,
+        };
+
+        let peers = self.node.lock().await.peer_info();
+        let (total, peers) = crate::paginate_peers(peers, &query);
+        Ok(Response::new(proto::ListPeersResponse {
+            peers: peers.into_iter().map(Into::into).collect(),
+            total: total as u64,
+        }))
+    }
+
+    async fn add_peer(
+        &self,
+        request: Request<proto::AddPeerRequest>,
+    ) -> Result<Response<proto::AddPeerResponse>, Status> {
+        require_full_mode(self.mode)?;
+        let endpoint = parse_endpoint(&request.into_inner().endpoint)?;
+        debug!("Attempting to add peer {endpoint} to the system");
+
+        match self.node.lock().await.add_peer(endpoint) {
+            Ok(()) => Ok(Response::new(proto::AddPeerResponse {})),
+            Err(PeerExists) => Err(Status::already_exists(
+                "A peer identified by that endpoint already exists",
+            )),
+        }
+    }
+
+    async fn remove_peer(
+        &self,
+        request: Request<proto::RemovePeerRequest>,
+    ) -> Result<Response<proto::RemovePeerResponse>, Status> {
+        require_full_mode(self.mode)?;
+        let endpoint = parse_endpoint(&request.into_inner().endpoint)?;
+        debug!("Attempting to remove peer {endpoint} from the system");
+
+        match self.node.lock().await.remove_peer(endpoint) {
+            Ok(()) => Ok(Response::new(proto::RemovePeerResponse {})),
+            Err(PeerNotFound) => Err(Status::not_found(
+                "A peer identified by that endpoint does not exist",
+            )),
+        }
+    }
+
+    async fn list_selected_routes(
+        &self,
+        _: Request<proto::ListRoutesRequest>,
+    ) -> Result<Response<proto::ListRoutesResponse>, Status> {
+        debug!("Loading selected routes");
+        let routes = crate::selected_routes(&*self.node.lock().await);
+        Ok(Response::new(proto::ListRoutesResponse {
+            routes: routes.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn list_fallback_routes(
+        &self,
+        _: Request<proto::ListRoutesRequest>,
+    ) -> Result<Response<proto::ListRoutesResponse>, Status> {
+        debug!("Loading fallback routes");
+        let routes = crate::fallback_routes(&*self.node.lock().await);
+        Ok(Response::new(proto::ListRoutesResponse {
+            routes: routes.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn get_public_key_from_ip(
+        &self,
+        request: Request<proto::GetPublicKeyFromIpRequest>,
+    ) -> Result<Response<proto::GetPublicKeyFromIpResponse>, Status> {
+        let ip = IpAddr::from_str(&request.into_inner().ip)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        match self.node.lock().await.get_pubkey_from_ip(ip) {
+            Some(pubkey) => Ok(Response::new(proto::GetPublicKeyFromIpResponse {
+                public_key: pubkey.to_string(),
+            })),
+            None => Err(Status::not_found("No public key known for that IP")),
+        }
+    }
+
+    async fn subscribe_route_events(
+        &self,
+        _: Request<proto::SubscribeRouteEventsRequest>,
+    ) -> Result<Response<Self::SubscribeRouteEventsStream>, Status> {
+        debug!("Streaming route events");
+        // Subscribe before loading the selected routes, so no change is missed in between.
+        let (mut events, routes) = {
+            let node = self.node.lock().await;
+            (node.subscribe_route_events(), crate::selected_routes(&node))
+        };
+
+        let (tx, rx) = mpsc::channel(ROUTE_EVENT_STREAM_BUFFER);
+        tokio::spawn(async move {
+            if tx
+                .send(Ok(RouteEvent::Snapshot { routes }.into()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    // Stop once the subscriber is gone, even if there are no events.
+                    _ = tx.closed() => return,
+                };
+                let event = match event {
+                    Ok(event) => Ok(RouteEvent::from(&event).into()),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Route event subscriber missed {missed} events, disconnecting");
+                        let _ = tx
+                            .send(Err(Status::resource_exhausted(
+                                "Route events are not processed fast enough",
+                            )))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Parse an [`Endpoint`] given in a request.
+fn parse_endpoint(endpoint: &str) -> Result<Endpoint, Status> {
+    Endpoint::from_str(endpoint).map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+impl From<PeerStats> for proto::PeerStats {
+    fn from(ps: PeerStats) -> Self {
+        proto::PeerStats {
+            endpoint: ps.endpoint.to_string(),
+            r#type: match ps.pt {
+                PeerType::Static => proto::PeerType::Static,
+                PeerType::LinkLocalDiscovery => proto::PeerType::LinkLocalDiscovery,
+                PeerType::Inbound => proto::PeerType::Inbound,
+            }
+            .into(),
+            connection_state: match ps.connection_state {
+                ConnectionState::Alive => proto::ConnectionState::Alive,
+                ConnectionState::Connecting => proto::ConnectionState::Connecting,
+                ConnectionState::Dead => proto::ConnectionState::Dead,
+            }
+            .into(),
+            tx_bytes: ps.tx_bytes,
+            rx_bytes: ps.rx_bytes,
+            connection_identifier: ps.connection_identifier,
+            overlay_address: ps.overlay_address.map(|ip| ip.to_string()),
+            link_cost: ps.link_cost.map(Into::into),
+            hello_reception: ps.hello_reception,
+            connection_attempts: ps.connection_attempts as u64,
+            connect_timeouts: ps.connect_timeouts,
+            handshake_timeouts: ps.handshake_timeouts,
+            connections_refused: ps.connections_refused,
+            next_connection_attempt: ps.next_connection_attempt,
+            last_seen: ps.last_seen,
+            selected_routes: ps.selected_routes as u64,
+            tx_rate_limit: ps.rate_limit.tx,
+            rx_rate_limit: ps.rate_limit.rx,
+            tx_rate: ps.tx_rate,
+            rx_rate: ps.rx_rate,
+            tx_dropped_bytes: ps.tx_dropped_bytes,
+            rx_dropped_bytes: ps.rx_dropped_bytes,
+            last_disconnect_reason: ps.last_disconnect_reason.map(|reason| {
+                match reason {
+                    DisconnectReason::RemoteClosed => proto::DisconnectReason::RemoteClosed,
+                    DisconnectReason::Closed => proto::DisconnectReason::Closed,
+                    DisconnectReason::Keepalive => proto::DisconnectReason::Keepalive,
+                    DisconnectReason::HelloTimeout => proto::DisconnectReason::HelloTimeout,
+                    DisconnectReason::ConnectionError => proto::DisconnectReason::ConnectionError,
+                }
+                .into()
+            }),
+            state: match ps.state {
+                PeerState::Connecting => proto::PeerState::Connecting,
+                PeerState::Handshaking => proto::PeerState::Handshaking,
+                PeerState::Alive => proto::PeerState::Alive,
+                PeerState::Degraded => proto::PeerState::Degraded,
+                PeerState::Dead => proto::PeerState::Dead,
+            }
+            .into(),
+            state_changed: ps.state_changed,
+            cipher: ps.cipher.map(|cipher| {
+                match cipher {
+                    Cipher::Aes256Gcm => proto::Cipher::Aes256Gcm,
+                    Cipher::ChaCha20Poly1305 => proto::Cipher::Chacha20Poly1305,
+                }
+                .into()
+            }),
+            mtu: ps.mtu.map(u32::from),
+            listen_address: ps.listen_address.map(|addr| addr.to_string()),
+            decryption_failures: ps.decryption_failures,
+            priority: ps.priority.into(),
+        }
+    }
+}
+
+impl From<Route> for proto::Route {
+    fn from(route: Route) -> Self {
+        proto::Route {
+            subnet: route.subnet,
+            source_prefix: route.source_prefix,
+            next_hop: route.next_hop,
+            next_hop_connection: route.next_hop_connection,
+            metric: match route.metric {
+                Metric::Value(metric) => Some(metric.into()),
+                Metric::Infinite => None,
+            },
+            seqno: route.seqno.into(),
+            equal_cost_paths: route.equal_cost_paths.map(|paths| paths as u32),
+        }
+    }
+}
+
+impl From<RouteEvent> for proto::RouteEvent {
+    fn from(event: RouteEvent) -> Self {
+        use proto::route_event::Event;
+
+        proto::RouteEvent {
+            event: Some(match event {
+                RouteEvent::Snapshot { routes } => Event::Snapshot(proto::RouteSnapshot {
+                    routes: routes.into_iter().map(Into::into).collect(),
+                }),
+                RouteEvent::Added { route } => Event::Added(route.into()),
+                RouteEvent::Changed { route } => Event::Changed(route.into()),
+                RouteEvent::Removed { route } => Event::Removed(route.into()),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::{metadata::MetadataValue, Code, Request};
+
+    use super::{require_api_key, require_full_mode};
+    use crate::ApiMode;
+
+    #[test]
+    fn api_key_required_when_configured() {
+        assert!(require_api_key(None, Request::new(())).is_ok());
+
+        let status = require_api_key(Some("secret"), Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", MetadataValue::from_static("Bearer wrong"));
+        let status = require_api_key(Some("secret"), request).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", MetadataValue::from_static("Bearer secret"));
+        assert!(require_api_key(Some("secret"), request).is_ok());
+    }
+
+    #[test]
+    fn read_only_mode_rejects_modifications() {
+        assert!(require_full_mode(ApiMode::Full).is_ok());
+
+        let status = require_full_mode(ApiMode::ReadOnly).unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+}
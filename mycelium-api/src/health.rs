@@ -0,0 +1,63 @@
+//! Endpoints for the liveness and readiness probes of orchestrators, like Kubernetes. These don't
+//! require authentication, and only use a [`NodeHealth`] handle, so they never wait for the lock
+//! on the node.
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+
+use mycelium::NodeHealth;
+
+/// Return a router which has the health endpoints and their handlers mounted.
+pub fn health_router(health: NodeHealth) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(health)
+}
+
+/// Check if the node is alive, i.e. it still processes packets.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/healthz",
+        tag = "Health",
+        responses(
+            (status = 200, description = "The node is alive", body = String, content_type = "text/plain"),
+            (status = 503, description = "The node stopped processing packets", body = String, content_type = "text/plain"),
+        )
+    )
+)]
+async fn healthz(State(health): State<NodeHealth>) -> (StatusCode, &'static str) {
+    if health.is_alive() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not alive")
+    }
+}
+
+/// Check if the node is ready, i.e. it is alive and connected to enough peers.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/readyz",
+        tag = "Health",
+        responses(
+            (status = 200, description = "The node is ready", body = String, content_type = "text/plain"),
+            (status = 503, description = "The node is not ready yet", body = String, content_type = "text/plain"),
+        )
+    )
+)]
+async fn readyz(State(health): State<NodeHealth>) -> (StatusCode, &'static str) {
+    if health.is_ready() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+/// The health endpoints, merged in the OpenAPI document of the API.
+#[cfg(feature = "openapi")]
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(healthz, readyz))]
+pub(crate) struct HealthApi;
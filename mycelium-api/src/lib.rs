@@ -1,272 +1,2769 @@
 use core::fmt;
-use std::{net::IpAddr, net::SocketAddr, str::FromStr, sync::Arc};
+use std::{
+    collections::BTreeMap, future::Future, net::IpAddr, net::SocketAddr, path::PathBuf, pin::Pin,
+    str::FromStr, sync::Arc, time::UNIX_EPOCH,
+};
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{delete, get},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Path, Query, Request, State,
+    },
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Json, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use serde::{de, Deserialize, Deserializer, Serialize};
-use tokio::sync::Mutex;
-use tracing::{debug, error};
+use tokio::sync::{broadcast, oneshot, Mutex};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{debug, error, info, warn};
 
 use mycelium::{
     crypto::PublicKey,
     endpoint::Endpoint,
     metrics::Metrics,
-    peer_manager::{PeerExists, PeerNotFound, PeerStats},
+    peer_manager::{ConnectionState, PeerExists, PeerNotFound, PeerStats, RateLimit},
+    router::{RouteEntry, RouteEventKind},
+    stats::Traffic,
+    subnet::Subnet,
 };
 
 const INFINITE_STR: &str = "infinite";
 
+/// Name of the header containing the total amount of items matching a query, regardless of
+/// pagination.
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+/// Default amount of peers returned when listing peers, if no limit is given.
+const DEFAULT_PEER_LIST_LIMIT: usize = 100;
+
+/// Maximum amount of peers returned when listing peers. Larger limits are clamped to this value.
+pub const MAX_PEER_LIST_LIMIT: usize = 1000;
+
+/// Time allowed to connect to an endpoint when validating it.
+const PEER_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Allowed origin which allows cross-origin requests from any origin.
+const ANY_ORIGIN: &str = "*";
+
+/// Time to wait before accepting new connections on a Unix socket after accepting failed.
+#[cfg(unix)]
+const UNIX_ACCEPT_ERROR_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Content type of the prometheus text exposition format.
+#[cfg(feature = "prometheus")]
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "grpc")]
+pub use grpc::{proto, Grpc};
+mod error;
+pub use error::{ApiError, ErrorCode, ProblemDetails, PROBLEM_JSON_CONTENT_TYPE};
+mod health;
 #[cfg(feature = "message")]
 mod message;
+#[cfg(feature = "openapi")]
+mod openapi;
 #[cfg(feature = "message")]
 pub use message::{MessageDestination, MessageReceiveInfo, MessageSendInfo, PushMessageResponse};
 
 /// Http API server handle. The server is spawned in a background task. If this handle is dropped,
-/// the server is terminated.
+/// the server is terminated. Use [`Http::shutdown`] to also shut down the node.
 pub struct Http {
     /// Channel to send cancellation to the http api server. We just keep a reference to it since
     /// dropping it will also cancel the receiver and thus the server.
     _cancel_tx: tokio::sync::oneshot::Sender<()>,
+    /// Shuts down the node served by the API.
+    shutdown_node: Box<dyn FnOnce() -> ShutdownFuture + Send + Sync>,
+}
+
+/// Future returned when shutting down the node served by the [`Http`] API.
+type ShutdownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The address the HTTP API server listens on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// Listen on a TCP socket.
+    Tcp(SocketAddr),
+    /// Listen on a TCP socket, and serve the API over HTTPS. On Unix platforms, the certificate
+    /// and key are reloaded from their files when the process receives `SIGHUP`.
+    Tls {
+        /// Address of the socket.
+        addr: SocketAddr,
+        /// Path of the PEM encoded certificate chain.
+        cert_path: PathBuf,
+        /// Path of the PEM encoded private key.
+        key_path: PathBuf,
+    },
+    /// Listen on a Unix domain socket at the given path. An existing socket at the path is
+    /// replaced.
+    #[cfg(unix)]
+    Unix {
+        /// Path of the socket.
+        path: PathBuf,
+        /// Permissions to set on the socket file, e.g. `0o600` to only allow the current user to
+        /// connect. If not set, the permissions are derived from the umask of the process.
+        mode: Option<u32>,
+    },
+}
+
+/// Selects which endpoints the HTTP API serves, and which methods the gRPC API serves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiMode {
+    /// Serve all endpoints.
+    #[default]
+    Full,
+    /// Only serve endpoints which expose the state of the node, e.g. for dashboards. Endpoints
+    /// which modify the node, or otherwise act on its behalf, are not mounted at all. This
+    /// includes the message endpoints, since receiving a message removes it from the queue.
+    ReadOnly,
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(value: SocketAddr) -> Self {
+        Self::Tcp(value)
+    }
+}
+
+#[derive(Clone)]
+/// Shared state accessible in HTTP endpoint handlers.
+struct HttpServerState<M> {
+    /// Access to the (`node`)(mycelium::Node) state.
+    node: Arc<Mutex<mycelium::Node<M>>>,
+}
+
+impl Http {
+    /// Spawns a new HTTP API server on the provided listening address.
+    ///
+    /// If an API key is given, requests to the `/admin` endpoints must present it as bearer token
+    /// in the `Authorization` header. Other requests are rejected with a `401 Unauthorized`
+    /// status.
+    ///
+    /// Browsers are only allowed to make cross-origin requests to the API from the given origins,
+    /// e.g. `https://dashboard.example.com`. An origin of `*` allows requests from any origin. If
+    /// no origins are given, no cross-origin requests are allowed.
+    ///
+    /// The `mode` selects which endpoints are served, see [`ApiMode`].
+    pub fn spawn<M>(
+        node: mycelium::Node<M>,
+        listen_addr: ListenAddr,
+        api_key: Option<String>,
+        allowed_origins: Vec<String>,
+        mode: ApiMode,
+    ) -> Self
+    where
+        M: Metrics + Clone + Send + Sync + 'static,
+    {
+        Self::spawn_shared(
+            Arc::new(Mutex::new(node)),
+            listen_addr,
+            api_key,
+            allowed_origins,
+            mode,
+        )
+    }
+
+    /// Spawns a new HTTP API server like [`Http::spawn`], for a node which is shared with other
+    /// API servers, e.g. the gRPC API server.
+    pub fn spawn_shared<M>(
+        node: Arc<Mutex<mycelium::Node<M>>>,
+        listen_addr: ListenAddr,
+        api_key: Option<String>,
+        allowed_origins: Vec<String>,
+        mode: ApiMode,
+    ) -> Self
+    where
+        M: Metrics + Clone + Send + Sync + 'static,
+    {
+        let server_state = HttpServerState { node };
+        let health_node = server_state.node.clone();
+        let node = server_state.node.clone();
+        let shutdown_node = Box::new(move || {
+            Box::pin(async move { node.lock().await.shutdown().await }) as ShutdownFuture
+        });
+
+        let admin_routes = Router::new()
+            .route("/admin", get(get_info))
+            .route("/admin/stats", get(get_stats))
+            .route("/admin/peers", get(get_peers))
+            .route("/admin/peers/:endpoint", get(get_peer))
+            .route("/admin/neighbours", get(get_neighbours))
+            .route("/admin/access/peers", get(get_peer_access_list))
+            .route("/admin/access/routes", get(get_route_access_list))
+            .route("/admin/routes/selected", get(get_selected_routes))
+            .route("/admin/routes/fallback", get(get_fallback_routes))
+            .route("/admin/routes/suppressed", get(get_suppressed_routes))
+            .route("/admin/routes/all", get(get_all_routes))
+            .route("/admin/routes/offsets", get(get_metric_offsets))
+            .route("/admin/routes/pinned", get(get_pinned_routes))
+            .route("/admin/routes/query/:ip", get(query_route))
+            .route("/admin/path/:ip", get(query_path))
+            .route("/admin/routes/events", get(route_events))
+            .route("/admin/events", get(get_routing_events))
+            .route("/admin/drain", get(get_drain_status));
+        // Method routers of the same path are merged, so this adds the other methods to the
+        // existing paths.
+        let admin_routes = match mode {
+            ApiMode::Full => admin_routes
+                .route("/admin/peers", post(add_peer))
+                .route("/admin/peers/batch", post(add_peers))
+                .route("/admin/peers/validate", post(validate_peer))
+                .route(
+                    "/admin/peers/:endpoint",
+                    delete(delete_peer).put(replace_peer),
+                )
+                .route("/admin/peers/:endpoint/ratelimit", put(set_peer_rate_limit))
+                .route("/admin/peers/:endpoint/mtu", put(set_peer_mtu))
+                .route("/admin/peers/:endpoint/priority", put(set_peer_priority))
+                .route(
+                    "/admin/peers/:endpoint/routes/dump",
+                    post(dump_routes_to_peer),
+                )
+                .route("/admin/access/peers", put(set_peer_access_list))
+                .route("/admin/access/routes", put(set_route_access_list))
+                .route("/admin/routes/offsets", put(set_metric_offsets))
+                .route("/admin/routes/pinned", post(add_pinned_routes))
+                .route("/admin/routes/pinned/:subnet", delete(delete_pinned_route))
+                .route("/admin/routes", delete(flush_routes))
+                .route("/admin/drain", post(start_drain)),
+            ApiMode::ReadOnly => admin_routes,
+        };
+        #[cfg(feature = "prometheus")]
+        let admin_routes = admin_routes.route("/admin/metrics", get(get_metrics));
+        #[cfg(feature = "testing")]
+        let admin_routes = match mode {
+            ApiMode::Full => admin_routes.route(
+                "/admin/testing/faults",
+                get(get_fault_injection).put(set_fault_injection),
+            ),
+            ApiMode::ReadOnly => {
+                admin_routes.route("/admin/testing/faults", get(get_fault_injection))
+            }
+        };
+        // The layer only applies to routes which are already added, so routes which don't require
+        // authentication must be added after this.
+        let admin_routes = match api_key {
+            Some(api_key) => admin_routes.route_layer(middleware::from_fn_with_state(
+                Arc::<str>::from(api_key),
+                require_api_key,
+            )),
+            None => admin_routes,
+        };
+        let admin_routes = admin_routes
+            .route("/pubkey/:ip", get(get_pubk_from_ip))
+            // The router requires parameters at the same position to have the same name, but this
+            // one is the public key.
+            .route("/pubkey/:ip/ip", get(get_ip_from_pubk))
+            .with_state(server_state.clone());
+        let app = Router::new().nest("/api/v1", admin_routes);
+        #[cfg(feature = "message")]
+        let app = match mode {
+            ApiMode::Full => app.nest("/api/v1", message::message_router_v1(server_state)),
+            ApiMode::ReadOnly => app,
+        };
+        // The description of the API does not contain any node specific info, so it is served
+        // without authentication.
+        #[cfg(feature = "openapi")]
+        let app = app.merge(openapi::router(mode));
+        // This is not a route layer, so preflight requests are answered before the API key is
+        // checked, as browsers don't send it with those.
+        let app = match cors_layer(allowed_origins) {
+            Some(cors) => app.layer(cors),
+            None => app,
+        };
+
+        let (_cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+
+        tokio::spawn(async move {
+            // The health endpoints don't require authentication, and don't lock the node, so they
+            // keep responding while other requests wait for the node.
+            let health = health_node.lock().await.health();
+            let app = app.merge(health::health_router(health));
+            match listen_addr {
+                ListenAddr::Tcp(addr) => serve_tcp(app, addr, cancel_rx).await,
+                ListenAddr::Tls {
+                    addr,
+                    cert_path,
+                    key_path,
+                } => serve_tls(app, addr, cert_path, key_path, cancel_rx).await,
+                #[cfg(unix)]
+                ListenAddr::Unix { path, mode } => serve_unix(app, path, mode, cancel_rx).await,
+            }
+        });
+        Http {
+            _cancel_tx,
+            shutdown_node,
+        }
+    }
+
+    /// Terminate the server and shut down the node, which closes the connections to all peers
+    /// after retracting the routes advertised to them. This returns once the connections are
+    /// closed.
+    pub async fn shutdown(self) {
+        let Http {
+            _cancel_tx: cancel_tx,
+            shutdown_node,
+        } = self;
+        // Stop serving requests first, so they don't hold up the shutdown of the node.
+        drop(cancel_tx);
+        shutdown_node().await;
+    }
+}
+
+/// Build the layer which adds the CORS headers for the allowed origins. Origins which are not
+/// valid header values are ignored. If there are no (valid) origins, no layer is needed, as
+/// browsers deny cross-origin requests without CORS headers.
+fn cors_layer(allowed_origins: Vec<String>) -> Option<CorsLayer> {
+    let allow_origin = if allowed_origins.iter().any(|origin| origin == ANY_ORIGIN) {
+        AllowOrigin::any()
+    } else {
+        let origins = allowed_origins
+            .into_iter()
+            .filter_map(|origin| match HeaderValue::try_from(&origin) {
+                Ok(origin) => Some(origin),
+                Err(_) => {
+                    warn!("Ignoring invalid allowed origin {origin:?}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if origins.is_empty() {
+            return None;
+        }
+        AllowOrigin::list(origins)
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+            .expose_headers([header::HeaderName::from_static(TOTAL_COUNT_HEADER)]),
+    )
+}
+
+/// Serve the API on a TCP socket, until the cancel channel is closed.
+async fn serve_tcp(app: Router, listen_addr: SocketAddr, cancel_rx: oneshot::Receiver<()>) {
+    let listener = match tokio::net::TcpListener::bind(listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind listener for Http Api server: {e}");
+            error!("API disabled");
+            return;
+        }
+    };
+
+    let server = axum::serve(listener, app.into_make_service()).with_graceful_shutdown(async {
+        cancel_rx.await.ok();
+    });
+
+    if let Err(e) = server.await {
+        error!("Http API server error: {e}");
+    }
+}
+
+/// Serve the API over HTTPS on a TCP socket, until the cancel channel is closed.
+async fn serve_tls(
+    app: Router,
+    listen_addr: SocketAddr,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    cancel_rx: oneshot::Receiver<()>,
+) {
+    let config = match RustlsConfig::from_pem_file(&cert_path, &key_path).await {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load TLS certificate and key for Http Api server: {e}");
+            error!("API disabled");
+            return;
+        }
+    };
+
+    #[cfg(unix)]
+    let reload = tokio::spawn(reload_tls_on_sighup(config.clone(), cert_path, key_path));
+
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            cancel_rx.await.ok();
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    if let Err(e) = axum_server::bind_rustls(listen_addr, config)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+    {
+        error!("Http API server error: {e}");
+    }
+
+    #[cfg(unix)]
+    reload.abort();
+}
+
+/// Reload the TLS certificate and key from their files every time the process receives `SIGHUP`.
+/// If they can't be loaded, the current certificate and key are kept.
+#[cfg(unix)]
+async fn reload_tls_on_sighup(config: RustlsConfig, cert_path: PathBuf, key_path: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            warn!("Failed to install SIGHUP handler, TLS certificate won't be reloaded: {e}");
+            return;
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        match config.reload_from_pem_file(&cert_path, &key_path).await {
+            Ok(()) => info!("Reloaded TLS certificate of Http Api server"),
+            Err(e) => error!("Failed to reload TLS certificate of Http Api server: {e}"),
+        }
+    }
+}
+
+/// Serve the API on a Unix domain socket, until the cancel channel is closed. The socket file is
+/// removed afterwards.
+#[cfg(unix)]
+async fn serve_unix(
+    app: Router,
+    path: PathBuf,
+    mode: Option<u32>,
+    mut cancel_rx: oneshot::Receiver<()>,
+) {
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
+
+    let listener = match bind_unix_socket(&path, mode) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Failed to bind listener for Http Api server on {}: {e}",
+                path.display()
+            );
+            error!("API disabled");
+            return;
+        }
+    };
+
+    loop {
+        let socket = tokio::select! {
+            res = listener.accept() => match res {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    // Errors are generally resource exhaustion, so back off for a bit.
+                    warn!("Failed to accept Http Api connection: {e}");
+                    tokio::time::sleep(UNIX_ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut cancel_rx => break,
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                .await
+            {
+                debug!("Failed to serve Http Api connection: {e}");
+            }
+        });
+    }
+
+    if let Err(e) = std::fs::remove_file(&path) {
+        debug!("Failed to remove Http Api socket {}: {e}", path.display());
+    }
+}
+
+/// Bind a Unix domain socket at the given path, replacing a socket which is already there.
+///
+/// If a mode is given, the socket is bound on a temporary path first, and only moved to the final
+/// path after the permissions are set. This way there is no window where a connection can be made
+/// with the default permissions.
+#[cfg(unix)]
+fn bind_unix_socket(
+    path: &std::path::Path,
+    mode: Option<u32>,
+) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // Remove a socket left behind by a previous run, otherwise binding fails. Don't touch other
+    // types of files, so we don't destroy anything if the path is misconfigured.
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+
+    let Some(mode) = mode else {
+        return tokio::net::UnixListener::bind(path);
+    };
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", std::process::id()));
+    let listener = tokio::net::UnixListener::bind(&tmp_path)?;
+    let res = std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(mode))
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    if let Err(e) = res {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    Ok(listener)
+}
+
+/// Reject requests which don't present the configured API key as bearer token.
+async fn require_api_key(
+    State(api_key): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match bearer_token(request.headers()) {
+        Some(token) if constant_time_eq(token.as_bytes(), api_key.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            debug!(
+                "Rejecting request to {} without valid API key",
+                request.uri()
+            );
+            (
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                ApiError::new(ErrorCode::Unauthorized, "Missing or invalid API key"),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Extract the bearer token from the `Authorization` header, if there is one.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    parse_bearer_token(headers.get(header::AUTHORIZATION)?.to_str().ok()?)
+}
+
+/// Extract the token from the value of an `Authorization` header, if it uses the bearer scheme.
+fn parse_bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Compare 2 byte slices in constant time, so the time taken does not leak how many leading bytes
+/// are equal. Only the length of the slices can be inferred.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    // Black box the result so the compiler can't turn this into a short circuiting comparison.
+    std::hint::black_box(a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y))) == 0
+}
+
+/// Query parameters to paginate and filter the list of peers.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerListQuery {
+    /// Maximum amount of peers to return. Defaults to [`DEFAULT_PEER_LIST_LIMIT`], and is
+    /// clamped to [`MAX_PEER_LIST_LIMIT`].
+    pub limit: Option<usize>,
+    /// Amount of (matching) peers to skip.
+    pub offset: Option<usize>,
+    /// Only return peers in this connection state.
+    pub state: Option<ConnectionState>,
+}
+
+/// Get the stats of the current known peers
+///
+/// The total amount of peers matching the filter is returned in the `X-Total-Count` header.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/peers",
+        tag = "Admin",
+        params(PeerListQuery),
+        responses(
+            (status = 200, description = "Stats of the peers", body = Vec<PeerStats>, headers(("x-total-count" = usize, description = "Amount of peers matching the filter"))),
+        )
+    )
+)]
+async fn get_peers<M>(
+    State(state): State<HttpServerState<M>>,
+    Query(query): Query<PeerListQuery>,
+) -> ([(&'static str, String); 1], Json<Vec<PeerStats>>)
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Fetching peer stats");
+    let peers = state.node.lock().await.peer_info();
+    let (total, peers) = paginate_peers(peers, &query);
+    ([(TOTAL_COUNT_HEADER, total.to_string())], Json(peers))
+}
+
+/// Filter a list of peers according to the given [`PeerListQuery`]. Returns the total amount of
+/// peers matching the filter, and the requested page of those peers.
+fn paginate_peers(peers: Vec<PeerStats>, query: &PeerListQuery) -> (usize, Vec<PeerStats>) {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PEER_LIST_LIMIT)
+        .min(MAX_PEER_LIST_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+
+    let matching = peers
+        .into_iter()
+        .filter(|ps| match query.state {
+            Some(cs) => ps.connection_state == cs,
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    let total = matching.len();
+
+    (
+        total,
+        matching.into_iter().skip(offset).take(limit).collect(),
+    )
+}
+
+/// Get the stats of a single peer
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/peers/{endpoint}",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        responses(
+            (status = 200, description = "Stats of the peer", body = PeerStats),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn get_peer<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+) -> Result<Json<PeerStats>, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Fetching stats of peer {endpoint}");
+    let endpoint = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+
+    match state.node.lock().await.peer_info_for(&endpoint) {
+        Some(stats) => Ok(Json(stats)),
+        None => Err(PeerNotFound.into()),
+    }
+}
+
+/// Payload of an add_peer request
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddPeer {
+    /// The endpoint used to connect to the peer
+    pub endpoint: String,
+}
+
+/// Query parameters of an add_peer request.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+#[serde(rename_all = "camelCase")]
+pub struct AddPeerQuery {
+    /// Return the [`PeerStats`] of the added peer, as if the request accepts `application/json`.
+    #[serde(default)]
+    pub stats: bool,
+}
+
+/// Add a new peer to the system
+///
+/// If the request accepts `application/json`, or the `stats` query parameter is set, the stats of
+/// the new peer are returned with status 201, so the caller learns its initial state. Otherwise
+/// the response is empty.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/admin/peers",
+        tag = "Admin",
+        params(AddPeerQuery),
+        request_body = AddPeer,
+        responses(
+            (status = 201, description = "Peer added", body = PeerStats),
+            (status = 204, description = "Peer added"),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 409, description = "A peer identified by the endpoint already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn add_peer<M>(
+    State(state): State<HttpServerState<M>>,
+    Query(query): Query<AddPeerQuery>,
+    headers: HeaderMap,
+    Json(payload): Json<AddPeer>,
+) -> Result<Response, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Attempting to add peer {} to  the system", payload.endpoint);
+    let endpoint = Endpoint::from_str(&payload.endpoint).map_err(ApiError::invalid_request)?;
+
+    let node = state.node.lock().await;
+    node.add_peer(endpoint.clone())?;
+
+    if !(query.stats || accepts_json(&headers)) {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    match node.peer_info_for(&endpoint) {
+        Some(stats) => Ok((StatusCode::CREATED, Json(stats)).into_response()),
+        // The peer can only be gone if it was removed in the meantime.
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+/// Checks if the `Accept` header of a request explicitly accepts `application/json`.
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .is_some_and(|media_type| media_type.trim() == "application/json")
+        })
+}
+
+/// Outcome of adding a single peer as part of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum AddPeerStatus {
+    /// The peer was added.
+    Added,
+    /// A peer identified by the endpoint already exists.
+    AlreadyExists,
+    /// The endpoint could not be parsed.
+    ParseError,
+}
+
+/// Result of adding a single peer as part of a batch.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AddPeerResult {
+    /// The endpoint as it was submitted.
+    pub endpoint: String,
+    /// Whether the peer was added.
+    pub status: AddPeerStatus,
+    /// Reason the endpoint could not be parsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Add multiple peers to the system at once. Every endpoint is handled on its own, so invalid or
+/// existing endpoints don't prevent the other peers from being added. If not every peer is added,
+/// the response has status 207 (multi status).
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/admin/peers/batch",
+        tag = "Admin",
+        request_body(content = Vec<String>, description = "Endpoints of the peers to add"),
+        responses(
+            (status = 200, description = "All peers were added", body = Vec<AddPeerResult>),
+            (status = 207, description = "Not all peers were added", body = Vec<AddPeerResult>),
+        )
+    )
+)]
+async fn add_peers<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(endpoints): Json<Vec<String>>,
+) -> (StatusCode, Json<Vec<AddPeerResult>>)
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Attempting to add {} peers", endpoints.len());
+    let parsed = endpoints
+        .iter()
+        .map(|endpoint| Endpoint::from_str(endpoint))
+        .collect::<Vec<_>>();
+
+    let node = state.node.lock().await;
+    let results = endpoints
+        .into_iter()
+        .zip(parsed)
+        .map(|(endpoint, parsed)| match parsed {
+            Ok(parsed) => AddPeerResult {
+                endpoint,
+                status: match node.add_peer(parsed) {
+                    Ok(()) => AddPeerStatus::Added,
+                    Err(PeerExists) => AddPeerStatus::AlreadyExists,
+                },
+                error: None,
+            },
+            Err(e) => AddPeerResult {
+                endpoint,
+                status: AddPeerStatus::ParseError,
+                error: Some(e.to_string()),
+            },
+        })
+        .collect::<Vec<_>>();
+    drop(node);
+
+    let status = if results.iter().all(|r| r.status == AddPeerStatus::Added) {
+        StatusCode::OK
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+
+    (status, Json(results))
+}
+
+/// Result of validating a peer endpoint.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PeerValidation {
+    /// Whether the endpoint could be parsed.
+    pub parsed: bool,
+    /// Whether a connection to the endpoint could be established.
+    pub reachable: bool,
+    /// Time it took to establish the connection, in milliseconds, if the endpoint is reachable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+    /// Reason the endpoint is invalid or unreachable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Check if an endpoint is valid and reachable, without adding it as a peer.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/admin/peers/validate",
+        tag = "Admin",
+        request_body = AddPeer,
+        responses(
+            (status = 200, description = "Result of the validation", body = PeerValidation),
+        )
+    )
+)]
+async fn validate_peer<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(payload): Json<AddPeer>,
+) -> Json<PeerValidation>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Validating peer endpoint {}", payload.endpoint);
+    let endpoint = match Endpoint::from_str(&payload.endpoint) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            return Json(PeerValidation {
+                parsed: false,
+                reachable: false,
+                rtt_ms: None,
+                error: Some(e.to_string()),
+            })
+        }
+    };
+
+    // Only hold the lock to start the probe, as connecting can take a while.
+    let probe = state
+        .node
+        .lock()
+        .await
+        .probe_peer(endpoint, PEER_PROBE_TIMEOUT);
+    Json(match probe.await {
+        Ok(rtt) => PeerValidation {
+            parsed: true,
+            reachable: true,
+            rtt_ms: Some(rtt.as_secs_f64() * 1000.),
+            error: None,
+        },
+        Err(e) => PeerValidation {
+            parsed: true,
+            reachable: false,
+            rtt_ms: None,
+            error: Some(e.to_string()),
+        },
+    })
+}
+
+/// remove an existing peer from the system
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        delete,
+        path = "/api/v1/admin/peers/{endpoint}",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        responses(
+            (status = 204, description = "Peer removed"),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn delete_peer<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Attempting to remove peer {} to  the system", endpoint);
+    let endpoint = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+
+    state.node.lock().await.remove_peer(endpoint)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Payload of a replace_peer request
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReplacePeer {
+    /// The new endpoint used to connect to the peer
+    pub endpoint: String,
+}
+
+/// Replace the endpoint of an existing peer
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/peers/{endpoint}",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        request_body = ReplacePeer,
+        responses(
+            (status = 204, description = "Peer replaced"),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 409, description = "A peer identified by the new endpoint already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn replace_peer<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+    Json(payload): Json<ReplacePeer>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!(
+        "Attempting to replace peer {} with {}",
+        endpoint, payload.endpoint
+    );
+    let old = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+    let new = Endpoint::from_str(&payload.endpoint).map_err(ApiError::invalid_request)?;
+
+    state.node.lock().await.replace_peer(old, new)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set the bandwidth limits of the data exchanged with a peer
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/peers/{endpoint}/ratelimit",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        request_body = RateLimit,
+        responses(
+            (status = 204, description = "Rate limit set"),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_peer_rate_limit<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+    Json(limit): Json<RateLimit>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Setting rate limit of peer {endpoint} to {limit:?}");
+    let endpoint = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+
+    state
+        .node
+        .lock()
+        .await
+        .set_peer_rate_limit(endpoint, limit)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Payload of a set_peer_mtu request
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PeerMtu {
+    /// The MTU of the link to the peer, i.e. the maximum size of data packets sent to it, or null
+    /// to remove the limit. Must be at least 1280.
+    pub mtu: Option<u16>,
+}
+
+/// Set the MTU of the link to a peer
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/peers/{endpoint}/mtu",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        request_body = PeerMtu,
+        responses(
+            (status = 204, description = "MTU set"),
+            (status = 400, description = "The endpoint or MTU is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_peer_mtu<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+    Json(payload): Json<PeerMtu>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Setting MTU of peer {endpoint} to {:?}", payload.mtu);
+    let endpoint = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+
+    state
+        .node
+        .lock()
+        .await
+        .set_peer_mtu(endpoint, payload.mtu)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Payload of a set_peer_priority request
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PeerPriority {
+    /// The priority of the peer as next hop, higher is preferred. Peers have priority 0 unless it
+    /// is set.
+    pub priority: u8,
+}
+
+/// Set the priority of a peer as next hop
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/peers/{endpoint}/priority",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        request_body = PeerPriority,
+        responses(
+            (status = 204, description = "Priority set"),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_peer_priority<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+    Json(payload): Json<PeerPriority>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!(
+        "Setting priority of peer {endpoint} to {}",
+        payload.priority
+    );
+    let endpoint = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+
+    state
+        .node
+        .lock()
+        .await
+        .set_peer_priority(endpoint, payload.priority)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Send all selected routes to a connected peer right away.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/admin/peers/{endpoint}/routes/dump",
+        tag = "Admin",
+        params(("endpoint" = String, Path, description = "Endpoint of the peer, e.g. tcp://192.0.2.1:9651")),
+        responses(
+            (status = 204, description = "Routes sent"),
+            (status = 400, description = "The endpoint is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "A connected peer identified by the endpoint does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn dump_routes_to_peer<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(endpoint): Path<String>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Dumping routes to peer {endpoint}");
+    let endpoint = Endpoint::from_str(&endpoint).map_err(ApiError::invalid_request)?;
+
+    match state.node.lock().await.dump_routes_to_peer(endpoint) {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(PeerNotFound) => Err(ApiError::new(
+            ErrorCode::PeerNotFound,
+            "A connected peer identified by that endpoint does not exist",
+        )),
+    }
+}
+
+/// The state of the babel neighbour relationship with a connected peer. Durations are in
+/// milliseconds.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Neighbour {
+    /// Connection identifier of the neighbour, in the underlay.
+    pub neighbour: String,
+    /// Cost of the link as computed by this node, and sent to the neighbour in IHUs.
+    pub rx_cost: u16,
+    /// Cost of the link as computed by the neighbour, if it sent an IHU.
+    #[serde(default)]
+    pub tx_cost: Option<u16>,
+    /// Cost added to the metric of routes learned from the neighbour.
+    pub link_cost: u16,
+    /// Smoothed round trip time of the link.
+    pub rtt: u64,
+    /// Fraction of the recent Hellos sent by the neighbour which was received.
+    pub hello_reception: f64,
+    /// Sequence number of the last Hello received from the neighbour, if any.
+    #[serde(default)]
+    pub last_hello_seqno: Option<u16>,
+    /// Time since the last Hello was received from the neighbour, if any.
+    #[serde(default)]
+    pub last_hello: Option<u64>,
+    /// Interval advertised in the last Hello received from the neighbour, if any.
+    #[serde(default)]
+    pub hello_interval: Option<u64>,
+    /// Time since the last IHU was received from the neighbour, or since it connected.
+    pub last_ihu: u64,
+    /// Interval advertised in the last IHU received from the neighbour, if any.
+    #[serde(default)]
+    pub ihu_interval: Option<u64>,
+}
+
+impl From<mycelium::router::NeighbourInfo> for Neighbour {
+    fn from(info: mycelium::router::NeighbourInfo) -> Self {
+        let millis = |duration: std::time::Duration| duration.as_millis() as u64;
+        Self {
+            neighbour: info.neighbour,
+            rx_cost: info.rx_cost,
+            tx_cost: info.tx_cost,
+            link_cost: info.link_cost,
+            rtt: millis(info.rtt),
+            hello_reception: info.hello_reception,
+            last_hello_seqno: info.last_hello_seqno,
+            last_hello: info.last_hello.map(millis),
+            hello_interval: info.hello_interval.map(millis),
+            last_ihu: millis(info.last_ihu),
+            ihu_interval: info.ihu_interval.map(millis),
+        }
+    }
+}
+
+/// List the state of the babel neighbour relationship with all connected peers.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/neighbours",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "State of the neighbours", body = Vec<Neighbour>),
+        )
+    )
+)]
+async fn get_neighbours<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<Neighbour>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Loading neighbours");
+    Json(
+        state
+            .node
+            .lock()
+            .await
+            .neighbours()
+            .into_iter()
+            .map(Neighbour::from)
+            .collect(),
+    )
+}
+
+/// A list of allowed and denied subnets. Subnets are in CIDR notation, a plain IP address is a
+/// subnet containing only that address.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AccessList {
+    /// Allowed subnets. If this is empty, everything which is not denied is allowed.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Denied subnets. These take precedence over the allowed subnets.
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl From<mycelium::access_list::AccessList> for AccessList {
+    fn from(list: mycelium::access_list::AccessList) -> Self {
+        Self {
+            allow: list.allow.iter().map(ToString::to_string).collect(),
+            deny: list.deny.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl TryFrom<AccessList> for mycelium::access_list::AccessList {
+    type Error = ApiError;
+
+    fn try_from(list: AccessList) -> Result<Self, Self::Error> {
+        let parse = |subnets: Vec<String>| {
+            subnets
+                .iter()
+                .map(|subnet| {
+                    Subnet::from_str(subnet).map_err(|e| {
+                        ApiError::invalid_request(format!("Invalid subnet {subnet}: {e}"))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            allow: parse(list.allow)?,
+            deny: parse(list.deny)?,
+        })
+    }
+}
+
+/// Get the list of underlay IPs which are allowed to connect as inbound peer.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/access/peers",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The peer access list", body = AccessList),
+        )
+    )
+)]
+async fn get_peer_access_list<M>(State(state): State<HttpServerState<M>>) -> Json<AccessList>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    Json(state.node.lock().await.peer_access_list().into())
+}
+
+/// Replace the list of underlay IPs which are allowed to connect as inbound peer.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/access/peers",
+        tag = "Admin",
+        request_body = AccessList,
+        responses(
+            (status = 204, description = "Peer access list replaced"),
+            (status = 400, description = "A subnet is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_peer_access_list<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(payload): Json<AccessList>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let access_list = payload.try_into()?;
+    debug!("Setting peer access list to {access_list:?}");
+    state.node.lock().await.set_peer_access_list(access_list);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the list of overlay subnets for which routes are accepted.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/access/routes",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The route access list", body = AccessList),
+        )
+    )
+)]
+async fn get_route_access_list<M>(State(state): State<HttpServerState<M>>) -> Json<AccessList>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    Json(state.node.lock().await.route_access_list().into())
+}
+
+/// Replace the list of overlay subnets for which routes are accepted.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/access/routes",
+        tag = "Admin",
+        request_body = AccessList,
+        responses(
+            (status = 204, description = "Route access list replaced"),
+            (status = 400, description = "A subnet is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_route_access_list<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(payload): Json<AccessList>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let access_list = payload.try_into()?;
+    debug!("Setting route access list to {access_list:?}");
+    state.node.lock().await.set_route_access_list(access_list);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// An offset added to the metric of routes through peers with an overlay address in a subnet.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MetricOffset {
+    /// Overlay subnet of the peers, in CIDR notation.
+    pub subnet: String,
+    /// Value added to the metric of the routes.
+    pub offset: u16,
+}
+
+/// Get the offsets added to the metric of routes during route selection.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/offsets",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The metric offsets", body = Vec<MetricOffset>),
+        )
+    )
+)]
+async fn get_metric_offsets<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<MetricOffset>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    Json(
+        state
+            .node
+            .lock()
+            .await
+            .metric_offsets()
+            .offsets
+            .into_iter()
+            .map(|mo| MetricOffset {
+                subnet: mo.subnet.to_string(),
+                offset: mo.offset,
+            })
+            .collect(),
+    )
+}
+
+/// Replace the offsets added to the metric of routes during route selection.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/routes/offsets",
+        tag = "Admin",
+        request_body = Vec<MetricOffset>,
+        responses(
+            (status = 204, description = "Metric offsets replaced"),
+            (status = 400, description = "A subnet is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_metric_offsets<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(payload): Json<Vec<MetricOffset>>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let offsets = payload
+        .into_iter()
+        .map(|mo| {
+            Ok(mycelium::metric_offset::MetricOffset {
+                subnet: Subnet::from_str(&mo.subnet).map_err(|e| {
+                    ApiError::invalid_request(format!("Invalid subnet {}: {e}", mo.subnet))
+                })?,
+                offset: mo.offset,
+            })
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    debug!("Setting metric offsets to {offsets:?}");
+    state
+        .node
+        .lock()
+        .await
+        .set_metric_offsets(mycelium::metric_offset::MetricOffsets { offsets });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// A route for a subnet through a peer, which is selected whenever the peer is connected.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedRoute {
+    /// The IPv6 subnet which is routed, in CIDR notation.
+    pub subnet: String,
+    /// Overlay address of the peer used as next hop.
+    pub next_hop: String,
+}
+
+/// List the routes pinned by the operator.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/pinned",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The pinned routes", body = Vec<PinnedRoute>),
+        )
+    )
+)]
+async fn get_pinned_routes<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<PinnedRoute>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    Json(
+        state
+            .node
+            .lock()
+            .await
+            .pinned_routes()
+            .into_iter()
+            .map(|pr| PinnedRoute {
+                subnet: pr.subnet.to_string(),
+                next_hop: pr.next_hop.to_string(),
+            })
+            .collect(),
+    )
+}
+
+/// Add pinned routes, replacing existing pinned routes for the same subnets. If any route is
+/// invalid, none of the routes are added.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/admin/routes/pinned",
+        tag = "Admin",
+        request_body = Vec<PinnedRoute>,
+        responses(
+            (status = 204, description = "Pinned routes added"),
+            (status = 400, description = "A route is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn add_pinned_routes<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(payload): Json<Vec<PinnedRoute>>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let routes = payload
+        .into_iter()
+        .map(|pr| {
+            Ok(mycelium::pinned_route::PinnedRoute {
+                subnet: Subnet::from_str(&pr.subnet).map_err(|e| {
+                    ApiError::invalid_request(format!("Invalid subnet {}: {e}", pr.subnet))
+                })?,
+                next_hop: IpAddr::from_str(&pr.next_hop).map_err(|e| {
+                    ApiError::invalid_request(format!("Invalid next hop {}: {e}", pr.next_hop))
+                })?,
+            })
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    debug!("Adding pinned routes {routes:?}");
+    state.node.lock().await.add_pinned_routes(routes)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove the pinned route for a subnet.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        delete,
+        path = "/api/v1/admin/routes/pinned/{subnet}",
+        tag = "Admin",
+        params(("subnet" = String, Path, description = "Subnet of the pinned route, e.g. 400:1::/64")),
+        responses(
+            (status = 204, description = "Pinned route removed"),
+            (status = 400, description = "The subnet is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+            (status = 404, description = "There is no pinned route for the subnet", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn delete_pinned_route<M>(
+    State(state): State<HttpServerState<M>>,
+    Path(subnet): Path<String>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let subnet = Subnet::from_str(&subnet).map_err(ApiError::invalid_request)?;
+    debug!("Removing pinned route for {subnet}");
+
+    state.node.lock().await.remove_pinned_route(subnet)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Faults injected on the links to peers, for testing.
+#[cfg(feature = "testing")]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FaultInjection {
+    /// Fixed delay added to every packet sent to a peer, in milliseconds.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Maximum additional random delay added to every packet sent to a peer, in milliseconds.
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Probability between 0 and 1 that a packet sent to a peer is dropped.
+    #[serde(default)]
+    pub loss: f64,
+    /// Seed of the RNG used to pick the delay of packets and which packets are dropped.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Get the faults injected on the links to peers.
+#[cfg(feature = "testing")]
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/testing/faults",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The injected faults", body = FaultInjection),
+        )
+    )
+)]
+async fn get_fault_injection<M>(State(state): State<HttpServerState<M>>) -> Json<FaultInjection>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let config = state.node.lock().await.fault_injection();
+    Json(FaultInjection {
+        latency_ms: config.latency.as_millis() as u64,
+        jitter_ms: config.jitter.as_millis() as u64,
+        loss: config.loss,
+        seed: config.seed,
+    })
+}
+
+/// Replace the faults injected on the links to peers.
+#[cfg(feature = "testing")]
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        put,
+        path = "/api/v1/admin/testing/faults",
+        tag = "Admin",
+        request_body = FaultInjection,
+        responses(
+            (status = 204, description = "Injected faults replaced"),
+            (status = 400, description = "The loss is not between 0 and 1", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn set_fault_injection<M>(
+    State(state): State<HttpServerState<M>>,
+    Json(payload): Json<FaultInjection>,
+) -> Result<StatusCode, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let config = mycelium::fault_injection::FaultInjectionConfig {
+        latency: std::time::Duration::from_millis(payload.latency_ms),
+        jitter: std::time::Duration::from_millis(payload.jitter_ms),
+        loss: payload.loss,
+        seed: payload.seed,
+    };
+    if !config.is_valid() {
+        return Err(ApiError::invalid_request(format!(
+            "Loss {} is not between 0 and 1",
+            payload.loss
+        )));
+    }
+    warn!("Injecting faults on links to peers: {config:?}");
+    state.node.lock().await.set_fault_injection(config);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Alias to a [`Metric`](crate::metric::Metric) for serialization in the API.
+#[derive(Debug, PartialEq)]
+pub enum Metric {
+    /// Finite metric
+    Value(u16),
+    /// Infinite metric
+    Infinite,
+}
+
+/// Info about a route. This uses base types only to avoid having to introduce too many Serialize
+/// bounds in the core types.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Route {
+    /// We convert the [`subnet`](Subnet) to a string to avoid introducing a bound on the actual
+    /// type.
+    pub subnet: String,
+    /// Source prefix of the route, if this is a source-specific route. Only traffic originating
+    /// from this prefix is forwarded over the route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_prefix: Option<String>,
+    /// Next hop of the route. This is the overlay address of the neighbour if it is known, so it
+    /// is stable when the neighbour reconnects, and the underlay connection otherwise.
+    pub next_hop: String,
+    /// The underlay connection to the next hop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_hop_connection: Option<String>,
+    /// Computed metric of the route.
+    pub metric: Metric,
+    /// Sequence number of the route.
+    pub seqno: u16,
+    /// Amount of equal cost paths traffic for the route is spread over. This is only set for
+    /// selected routes, and is more than 1 if equal cost multipath forwarding is active.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equal_cost_paths: Option<usize>,
+}
+
+impl Route {
+    /// Describe a route in the routing table of the node. `equal_cost_paths` is only set for
+    /// selected routes.
+    fn from_entry(re: &RouteEntry, equal_cost_paths: Option<usize>) -> Self {
+        Route {
+            subnet: re.source().subnet().to_string(),
+            source_prefix: re.source().source_prefix().map(|sp| sp.to_string()),
+            next_hop: re.neighbour().identifier(),
+            next_hop_connection: Some(re.neighbour().connection_identifier().clone()),
+            metric: if re.metric().is_infinite() {
+                Metric::Infinite
+            } else {
+                Metric::Value(re.metric().into())
+            },
+            seqno: re.seqno().into(),
+            equal_cost_paths,
+        }
+    }
+}
+
+/// List all currently selected routes.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/selected",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The selected routes", body = Vec<Route>),
+        )
+    )
+)]
+async fn get_selected_routes<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<Route>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Loading selected routes");
+    Json(selected_routes(&*state.node.lock().await))
+}
+
+/// List all active fallback routes.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/fallback",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The fallback routes", body = Vec<Route>),
+        )
+    )
+)]
+async fn get_fallback_routes<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<Route>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Loading fallback routes");
+    Json(fallback_routes(&*state.node.lock().await))
+}
+
+/// Load the selected routes of the node.
+fn selected_routes<M>(node: &mycelium::Node<M>) -> Vec<Route>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    node.selected_routes()
+        .into_iter()
+        .map(|sr| {
+            let equal_cost_paths =
+                node.equal_cost_path_count(sr.source().subnet(), sr.source().source_prefix());
+            Route::from_entry(&sr, Some(equal_cost_paths))
+        })
+        .collect()
+}
+
+/// Load the fallback routes of the node.
+fn fallback_routes<M>(node: &mycelium::Node<M>) -> Vec<Route>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    node.fallback_routes()
+        .into_iter()
+        .map(|sr| Route::from_entry(&sr, None))
+        .collect()
+}
+
+/// The state of a route in the routing table.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum RouteState {
+    /// The route is selected, and used to forward packets.
+    Selected,
+    /// The route is feasible, but not selected.
+    Fallback,
+    /// The route is not feasible, so it can't be selected.
+    Unfeasible,
+    /// The route was retracted by the neighbour which advertised it.
+    Retracted,
+    /// The route is pinned by the operator, and selected while its next hop is connected.
+    Pinned,
+}
+
+impl From<mycelium::router::RouteState> for RouteState {
+    fn from(state: mycelium::router::RouteState) -> Self {
+        match state {
+            mycelium::router::RouteState::Selected => RouteState::Selected,
+            mycelium::router::RouteState::Fallback => RouteState::Fallback,
+            mycelium::router::RouteState::Unfeasible => RouteState::Unfeasible,
+            mycelium::router::RouteState::Retracted => RouteState::Retracted,
+            mycelium::router::RouteState::Pinned => RouteState::Pinned,
+        }
+    }
+}
+
+impl fmt::Display for RouteState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RouteState::Selected => "selected",
+            RouteState::Fallback => "fallback",
+            RouteState::Unfeasible => "unfeasible",
+            RouteState::Retracted => "retracted",
+            RouteState::Pinned => "pinned",
+        })
+    }
+}
+
+/// A route in the routing table of the node, regardless of whether it can be selected.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RouteTableEntry {
+    /// The subnet of the route.
+    pub subnet: String,
+    /// Source prefix of the route, if this is a source-specific route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_prefix: Option<String>,
+    /// Neighbour which advertised the route. This is the overlay address of the neighbour if it
+    /// is known, and the underlay connection otherwise.
+    pub next_hop: String,
+    /// The underlay connection to the neighbour which advertised the route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_hop_connection: Option<String>,
+    /// Router id of the node which originated the route.
+    pub router_id: String,
+    /// Metric of the route.
+    pub metric: Metric,
+    /// Sequence number of the route.
+    pub seqno: u16,
+    /// State of the route.
+    pub state: RouteState,
+}
+
+/// Query parameters to filter the routing table.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteTableQuery {
+    /// Only return routes for subnets which are contained in this subnet.
+    pub subnet: Option<String>,
+}
+
+/// List all routes in the routing table, including routes which can't be selected.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/all",
+        tag = "Admin",
+        params(RouteTableQuery),
+        responses(
+            (status = 200, description = "The routes in the routing table", body = Vec<RouteTableEntry>),
+            (status = 400, description = "The subnet filter is invalid", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn get_all_routes<M>(
+    State(state): State<HttpServerState<M>>,
+    Query(query): Query<RouteTableQuery>,
+) -> Result<Json<Vec<RouteTableEntry>>, ApiError>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Loading routing table");
+    let filter = query
+        .subnet
+        .as_deref()
+        .map(Subnet::from_str)
+        .transpose()
+        .map_err(ApiError::invalid_request)?;
+
+    Ok(Json(
+        state
+            .node
+            .lock()
+            .await
+            .routes()
+            .into_iter()
+            .filter(|(re, _)| match filter {
+                Some(filter) => filter.contains_subnet(&re.source().subnet()),
+                None => true,
+            })
+            .map(|(re, state)| RouteTableEntry {
+                subnet: re.source().subnet().to_string(),
+                source_prefix: re.source().source_prefix().map(|sp| sp.to_string()),
+                next_hop: re.neighbour().identifier(),
+                next_hop_connection: Some(re.neighbour().connection_identifier().clone()),
+                router_id: re.source().router_id().to_string(),
+                metric: if re.metric().is_infinite() {
+                    Metric::Infinite
+                } else {
+                    Metric::Value(re.metric().into())
+                },
+                seqno: re.seqno().into(),
+                state: state.into(),
+            })
+            .collect(),
+    ))
+}
+
+/// Info about a route which is suppressed because it changed too often.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SuppressedRoute {
+    /// The subnet of the route.
+    pub subnet: String,
+    /// Source prefix of the route, if this is a source-specific route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_prefix: Option<String>,
+    /// Next hop of the route. This is the overlay address of the neighbour, or its connection in
+    /// the underlay if its address is not known yet.
+    pub next_hop: String,
+    /// Current penalty of the route. The route can be selected again once this decays below the
+    /// reuse threshold.
+    pub penalty: u32,
 }
 
-#[derive(Clone)]
-/// Shared state accessible in HTTP endpoint handlers.
-struct HttpServerState<M> {
-    /// Access to the (`node`)(mycelium::Node) state.
-    node: Arc<Mutex<mycelium::Node<M>>>,
+/// List all routes which are suppressed because they changed too often.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/suppressed",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The suppressed routes", body = Vec<SuppressedRoute>),
+        )
+    )
+)]
+async fn get_suppressed_routes<M>(
+    State(state): State<HttpServerState<M>>,
+) -> Json<Vec<SuppressedRoute>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Loading suppressed routes");
+    Json(
+        state
+            .node
+            .lock()
+            .await
+            .suppressed_routes()
+            .into_iter()
+            .map(|sr| SuppressedRoute {
+                subnet: sr.subnet.to_string(),
+                source_prefix: sr.source_prefix.map(|sp| sp.to_string()),
+                next_hop: sr.neighbour,
+                penalty: sr.penalty,
+            })
+            .collect(),
+    )
 }
 
-impl Http {
-    /// Spawns a new HTTP API server on the provided listening address.
-    pub fn spawn<M>(node: mycelium::Node<M>, listen_addr: SocketAddr) -> Self
-    where
-        M: Metrics + Clone + Send + Sync + 'static,
-    {
-        let server_state = HttpServerState {
-            node: Arc::new(Mutex::new(node)),
-        };
-        let admin_routes = Router::new()
-            .route("/admin", get(get_info))
-            .route("/admin/peers", get(get_peers).post(add_peer))
-            .route("/admin/peers/:endpoint", delete(delete_peer))
-            .route("/admin/routes/selected", get(get_selected_routes))
-            .route("/admin/routes/fallback", get(get_fallback_routes))
-            .route("/pubkey/:ip", get(get_pubk_from_ip))
-            .with_state(server_state.clone());
-        let app = Router::new().nest("/api/v1", admin_routes);
-        #[cfg(feature = "message")]
-        let app = app.nest("/api/v1", message::message_router_v1(server_state));
+/// Result of flushing the routing table.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FlushedRoutes {
+    /// The amount of selected and fallback routes which were removed.
+    pub flushed: usize,
+}
 
-        let (_cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+/// Remove all selected and fallback routes, and request all peers to send their routes again.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        delete,
+        path = "/api/v1/admin/routes",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Routes flushed", body = FlushedRoutes),
+        )
+    )
+)]
+async fn flush_routes<M>(State(state): State<HttpServerState<M>>) -> Json<FlushedRoutes>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Flushing routes");
+    let flushed = state.node.lock().await.flush_routes();
 
-        tokio::spawn(async move {
-            let listener = match tokio::net::TcpListener::bind(listen_addr).await {
-                Ok(listener) => listener,
-                Err(e) => {
-                    error!("Failed to bind listener for Http Api server: {e}");
-                    error!("API disabled");
-                    return;
-                }
-            };
+    Json(FlushedRoutes { flushed })
+}
 
-            let server =
-                axum::serve(listener, app.into_make_service()).with_graceful_shutdown(async {
-                    cancel_rx.await.ok();
-                });
+/// Progress of draining the node before it is shut down.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DrainStatus {
+    /// Whether the node is draining.
+    pub draining: bool,
+    /// Time since draining started, in seconds. Only set if the node is draining.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draining_for: Option<u64>,
+    /// Amount of packets of other nodes forwarded since draining started.
+    pub transit_packets: u64,
+    /// Whether the node no longer forwarded packets of other nodes for some time, so it is no
+    /// longer used as next hop by its neighbours and can be shut down.
+    pub drained: bool,
+}
 
-            if let Err(e) = server.await {
-                error!("Http API server error: {e}");
-            }
-        });
-        Http { _cancel_tx }
+impl From<Option<mycelium::router::DrainStatus>> for DrainStatus {
+    fn from(status: Option<mycelium::router::DrainStatus>) -> Self {
+        match status {
+            Some(status) => Self {
+                draining: true,
+                draining_for: Some(status.draining_for.as_secs()),
+                transit_packets: status.transit_packets,
+                drained: status.drained,
+            },
+            None => Self {
+                draining: false,
+                draining_for: None,
+                transit_packets: 0,
+                drained: false,
+            },
+        }
     }
 }
 
-/// Get the stats of the current known peers
-async fn get_peers<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<PeerStats>>
+/// Get the progress of draining the node.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/drain",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Drain status of the node", body = DrainStatus),
+        )
+    )
+)]
+async fn get_drain_status<M>(State(state): State<HttpServerState<M>>) -> Json<DrainStatus>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    debug!("Fetching peer stats");
-    Json(state.node.lock().await.peer_info())
+    debug!("Loading drain status");
+    Json(state.node.lock().await.drain_status().into())
 }
 
-/// Payload of an add_peer request
-#[derive(Deserialize, Serialize)]
-pub struct AddPeer {
-    /// The endpoint used to connect to the peer
-    pub endpoint: String,
+/// Start draining the node before it is shut down. Routes to other nodes are advertised with a
+/// near infinite metric, so neighbours stop using this node as next hop, while the node itself
+/// stays reachable. Draining can't be stopped, other than by restarting the node.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/admin/drain",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The node is draining", body = DrainStatus),
+        )
+    )
+)]
+async fn start_drain<M>(State(state): State<HttpServerState<M>>) -> Json<DrainStatus>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Draining node");
+    let node = state.node.lock().await;
+    node.start_drain();
+
+    Json(node.drain_status().into())
 }
 
-/// Add a new peer to the system
-async fn add_peer<M>(
+/// Result of looking up the route for an IP.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QueriedRoute {
+    /// The selected route used to forward packets to the IP, if there is one.
+    pub selected: Option<Route>,
+    /// The best fallback route for the IP. This is only set if there is no selected route.
+    pub fallback: Option<Route>,
+}
+
+/// Look up the route which would be used to forward packets to the given IP.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/query/{ip}",
+        tag = "Admin",
+        params(("ip" = String, Path, description = "The IP to look up the route for")),
+        responses(
+            (status = 200, description = "The route for the IP", body = QueriedRoute),
+            (status = 404, description = "There is no route for the IP", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn query_route<M>(
     State(state): State<HttpServerState<M>>,
-    Json(payload): Json<AddPeer>,
-) -> Result<StatusCode, (StatusCode, String)>
+    Path(ip): Path<IpAddr>,
+) -> Result<Json<QueriedRoute>, ApiError>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    debug!("Attempting to add peer {} to  the system", payload.endpoint);
-    let endpoint = match Endpoint::from_str(&payload.endpoint) {
-        Ok(endpoint) => endpoint,
-        Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+    debug!("Looking up route for {ip}");
+    let node = state.node.lock().await;
+    let (sr, selected) = match node.selected_route_for_ip(ip) {
+        Some(sr) => (sr, true),
+        None => match node.fallback_route_for_ip(ip) {
+            Some(fr) => (fr, false),
+            None => {
+                return Err(ApiError::new(
+                    ErrorCode::RouteNotFound,
+                    format!("There is no route for {ip}"),
+                ))
+            }
+        },
     };
 
-    match state.node.lock().await.add_peer(endpoint) {
-        Ok(()) => Ok(StatusCode::NO_CONTENT),
-        Err(PeerExists) => Err((
-            StatusCode::CONFLICT,
-            "A peer identified by that endpoint already exists".to_string(),
-        )),
-    }
+    let equal_cost_paths = selected
+        .then(|| node.equal_cost_path_count(sr.source().subnet(), sr.source().source_prefix()));
+    let route = Route::from_entry(&sr, equal_cost_paths);
+
+    Ok(Json(if selected {
+        QueriedRoute {
+            selected: Some(route),
+            fallback: None,
+        }
+    } else {
+        QueriedRoute {
+            selected: None,
+            fallback: Some(route),
+        }
+    }))
 }
 
-/// remove an existing peer from the system
-async fn delete_peer<M>(
+/// A node on the path packets to an IP take through the overlay.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PathHop {
+    /// Identifier of the node. This is the overlay address of the node if its public key is known,
+    /// otherwise the identifier of the connection to it.
+    pub identifier: String,
+    /// The public key of the node, if it is known.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<PublicKey>,
+    /// The router id the node announces the route with. This is only set for the origin of the
+    /// route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub router_id: Option<String>,
+    /// Identifier of the connection to the node. This is only set for the next hop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    /// Cost of the link to the node. This is only set for the next hop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_cost: Option<u16>,
+}
+
+/// The path packets to an IP take through the overlay, as far as it is known by the local node.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QueriedPath {
+    /// The IP the path was looked up for.
+    pub destination: String,
+    /// Whether the IP is in the subnet of the local node, in which case packets are not forwarded
+    /// and there are no hops.
+    pub local: bool,
+    /// The subnet of the selected route for the IP. This is not set if the IP is local.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subnet: Option<String>,
+    /// Total metric of the path. This is not set if the IP is local.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metric: Option<Metric>,
+    /// Sequence number of the selected route. This is not set if the IP is local.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seqno: Option<u16>,
+    /// The known nodes on the path, starting with the next hop and ending with the origin of the
+    /// route, which owns the IP.
+    pub hops: Vec<PathHop>,
+    /// Whether `hops` is the full path. Routes only carry their origin, so the nodes between the
+    /// next hop and the origin are unknown, unless the next hop is the origin.
+    pub complete: bool,
+}
+
+/// Look up the path which packets to the given IP take through the overlay. Routes only carry
+/// their origin, so the path contains the next hop and the origin of the selected route, and only
+/// is complete if these are the same node.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/path/{ip}",
+        tag = "Admin",
+        params(("ip" = String, Path, description = "The IP to look up the path for")),
+        responses(
+            (status = 200, description = "The path to the IP", body = QueriedPath),
+            (status = 404, description = "There is no selected route for the IP", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
+async fn query_path<M>(
     State(state): State<HttpServerState<M>>,
-    Path(endpoint): Path<String>,
-) -> Result<StatusCode, (StatusCode, String)>
+    Path(ip): Path<IpAddr>,
+) -> Result<Json<QueriedPath>, ApiError>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    debug!("Attempting to remove peer {} to  the system", endpoint);
-    let endpoint = match Endpoint::from_str(&endpoint) {
-        Ok(endpoint) => endpoint,
-        Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+    debug!("Looking up path to {ip}");
+    let node = state.node.lock().await;
+    if node.info().node_subnet.contains_ip(ip) {
+        return Ok(Json(QueriedPath {
+            destination: ip.to_string(),
+            local: true,
+            subnet: None,
+            metric: None,
+            seqno: None,
+            hops: vec![],
+            complete: true,
+        }));
+    }
+
+    let Some(sr) = node.selected_route_for_ip(ip) else {
+        return Err(ApiError::new(
+            ErrorCode::RouteNotFound,
+            format!("There is no selected route for {ip}"),
+        ));
     };
 
-    match state.node.lock().await.remove_peer(endpoint) {
-        Ok(()) => Ok(StatusCode::NO_CONTENT),
-        Err(PeerNotFound) => Err((
-            StatusCode::NOT_FOUND,
-            "A peer identified by that endpoint does not exist".to_string(),
-        )),
-    }
+    let neighbour = sr.neighbour();
+    let next_hop = PathHop {
+        identifier: neighbour.identifier(),
+        public_key: neighbour.public_key(),
+        router_id: None,
+        connection: Some(neighbour.connection_identifier().clone()),
+        link_cost: Some(neighbour.link_cost()),
+    };
+    let origin = sr.source().router_id();
+    let (hops, complete) = path_hops(next_hop, origin.to_pubkey(), origin.to_string());
+
+    Ok(Json(QueriedPath {
+        destination: ip.to_string(),
+        local: false,
+        subnet: Some(sr.source().subnet().to_string()),
+        metric: Some(if sr.metric().is_infinite() {
+            Metric::Infinite
+        } else {
+            Metric::Value(sr.metric().into())
+        }),
+        seqno: Some(sr.seqno().into()),
+        hops,
+        complete,
+    }))
 }
 
-/// Alias to a [`Metric`](crate::metric::Metric) for serialization in the API.
-#[derive(Debug, PartialEq)]
-pub enum Metric {
-    /// Finite metric
-    Value(u16),
-    /// Infinite metric
-    Infinite,
+/// Build the known hops of a path from its next hop and the public key and router id of its
+/// origin. If the next hop is the origin, it is the only hop, and the path is complete.
+fn path_hops(mut next_hop: PathHop, origin: PublicKey, router_id: String) -> (Vec<PathHop>, bool) {
+    if next_hop.public_key == Some(origin) {
+        next_hop.router_id = Some(router_id);
+        return (vec![next_hop], true);
+    }
+
+    let origin = PathHop {
+        identifier: origin.address().to_string(),
+        public_key: Some(origin),
+        router_id: Some(router_id),
+        connection: None,
+        link_cost: None,
+    };
+    (vec![next_hop, origin], false)
 }
 
-/// Info about a route. This uses base types only to avoid having to introduce too many Serialize
-/// bounds in the core types.
+/// A change of the selected routes, as sent on the route events WebSocket.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(rename_all = "camelCase")]
-pub struct Route {
-    /// We convert the [`subnet`](Subnet) to a string to avoid introducing a bound on the actual
-    /// type.
-    pub subnet: String,
-    /// Next hop of the route, in the underlay.
-    pub next_hop: String,
-    /// Computed metric of the route.
-    pub metric: Metric,
-    /// Sequence number of the route.
-    pub seqno: u16,
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RouteEvent {
+    /// All selected routes at the time the subscription started. This is always the first event.
+    Snapshot { routes: Vec<Route> },
+    /// A route was selected for a subnet which did not have a selected route.
+    Added { route: Route },
+    /// The selected route of a subnet changed.
+    Changed { route: Route },
+    /// A subnet no longer has a selected route. This contains the previously selected route.
+    Removed { route: Route },
 }
 
-/// List all currently selected routes.
-async fn get_selected_routes<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<Route>>
+impl From<&mycelium::router::RouteEvent> for RouteEvent {
+    fn from(event: &mycelium::router::RouteEvent) -> Self {
+        let route = Route::from_entry(event.route(), None);
+        match event.kind() {
+            RouteEventKind::Added => RouteEvent::Added { route },
+            RouteEventKind::Changed => RouteEvent::Changed { route },
+            RouteEventKind::Removed => RouteEvent::Removed { route },
+        }
+    }
+}
+
+/// Stream changes of the selected routes over a WebSocket.
+///
+/// Every change is sent as a JSON encoded `RouteEvent` in a text message.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/routes/events",
+        tag = "Admin",
+        responses(
+            (status = 101, description = "Switching to the WebSocket protocol"),
+        )
+    )
+)]
+async fn route_events<M>(State(state): State<HttpServerState<M>>, ws: WebSocketUpgrade) -> Response
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    debug!("Loading selected routes");
-    let routes = state
-        .node
-        .lock()
-        .await
-        .selected_routes()
-        .into_iter()
-        .map(|sr| Route {
-            subnet: sr.source().subnet().to_string(),
-            next_hop: sr.neighbour().connection_identifier().clone(),
-            metric: if sr.metric().is_infinite() {
-                Metric::Infinite
-            } else {
-                Metric::Value(sr.metric().into())
-            },
-            seqno: sr.seqno().into(),
-        })
-        .collect();
-
-    Json(routes)
+    ws.on_upgrade(move |socket| stream_route_events(state, socket))
 }
 
-/// List all active fallback routes.
-async fn get_fallback_routes<M>(State(state): State<HttpServerState<M>>) -> Json<Vec<Route>>
+/// Send the currently selected routes on the socket, followed by all changes to them. Clients
+/// which can't keep up with the changes are disconnected.
+async fn stream_route_events<M>(state: HttpServerState<M>, mut socket: WebSocket)
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    debug!("Loading fallback routes");
-    let routes = state
-        .node
-        .lock()
-        .await
-        .fallback_routes()
-        .into_iter()
-        .map(|sr| Route {
-            subnet: sr.source().subnet().to_string(),
-            next_hop: sr.neighbour().connection_identifier().clone(),
-            metric: if sr.metric().is_infinite() {
-                Metric::Infinite
-            } else {
-                Metric::Value(sr.metric().into())
+    debug!("Streaming route events");
+    // Subscribe before loading the selected routes, so no change is missed in between.
+    let (mut events, routes) = {
+        let node = state.node.lock().await;
+        (node.subscribe_route_events(), selected_routes(&node))
+    };
+
+    let snapshot = RouteEvent::Snapshot { routes };
+    if send_route_event(&mut socket, &snapshot).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Route event subscriber missed {missed} events, disconnecting");
+                        let _ = socket
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::AGAIN,
+                                reason: "Route events are not processed fast enough".into(),
+                            })))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let event = RouteEvent::from(&event);
+                if send_route_event(&mut socket, &event).await.is_err() {
+                    return;
+                }
+            }
+            msg = socket.recv() => {
+                // We don't expect any messages from the client, but we need to read from the
+                // socket to notice when it is closed.
+                match msg {
+                    None | Some(Err(_)) | Some(Ok(Message::Close(_))) => {
+                        debug!("Route event subscriber disconnected");
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Send a [`RouteEvent`] as JSON text message on a WebSocket.
+async fn send_route_event(socket: &mut WebSocket, event: &RouteEvent) -> Result<(), axum::Error> {
+    let msg = serde_json::to_string(event).expect("Route events can always be serialized; qed");
+    socket.send(Message::Text(msg)).await
+}
+
+/// An event in the router, as kept in the event log of the node.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RoutingEvent {
+    /// A route through a new next hop got selected for a subnet.
+    #[serde(rename_all = "camelCase")]
+    RouteSelected {
+        subnet: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source_prefix: Option<String>,
+        /// The underlay connection to the next hop.
+        next_hop: String,
+        metric: u16,
+        seqno: u16,
+    },
+    /// A subnet no longer has a usable selected route.
+    #[serde(rename_all = "camelCase")]
+    RouteRetracted {
+        subnet: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source_prefix: Option<String>,
+        /// The underlay connection to the next hop of the previously selected route.
+        next_hop: String,
+    },
+    /// A peer connected.
+    PeerUp { connection: String },
+    /// A peer disconnected, or was removed.
+    PeerDown { connection: String },
+    /// The sequence number of the node was increased.
+    SeqnoBumped { seqno: u16 },
+}
+
+/// A [`RoutingEvent`] with the time it happened.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct LoggedEvent {
+    /// Identifier of the event, which increases by 1 for every event. Gaps between the returned
+    /// events mean older events were dropped from the log.
+    pub id: u64,
+    /// Unix timestamp, in milliseconds, at which the event happened.
+    pub timestamp: u64,
+    /// The event.
+    pub event: RoutingEvent,
+}
+
+impl From<mycelium::event_log::LoggedEvent> for LoggedEvent {
+    fn from(logged: mycelium::event_log::LoggedEvent) -> Self {
+        use mycelium::event_log::RoutingEvent as Event;
+
+        let event = match logged.event {
+            Event::RouteSelected {
+                subnet,
+                source_prefix,
+                next_hop,
+                metric,
+                seqno,
+            } => RoutingEvent::RouteSelected {
+                subnet: subnet.to_string(),
+                source_prefix: source_prefix.map(|sp| sp.to_string()),
+                next_hop,
+                metric,
+                seqno,
             },
-            seqno: sr.seqno().into(),
-        })
-        .collect();
+            Event::RouteRetracted {
+                subnet,
+                source_prefix,
+                next_hop,
+            } => RoutingEvent::RouteRetracted {
+                subnet: subnet.to_string(),
+                source_prefix: source_prefix.map(|sp| sp.to_string()),
+                next_hop,
+            },
+            Event::PeerUp { connection } => RoutingEvent::PeerUp { connection },
+            Event::PeerDown { connection } => RoutingEvent::PeerDown { connection },
+            Event::SeqnoBumped { seqno } => RoutingEvent::SeqnoBumped { seqno },
+        };
+        Self {
+            id: logged.id,
+            timestamp: logged
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            event,
+        }
+    }
+}
+
+/// Query parameters of the event log request.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingEventsQuery {
+    /// Only return events which happened after this Unix timestamp, in milliseconds.
+    pub since: Option<u64>,
+}
 
-    Json(routes)
+/// Get the recent routing events of the node, oldest first.
+///
+/// The node keeps a limited amount of events, so older events are dropped.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/events",
+        tag = "Admin",
+        params(RoutingEventsQuery),
+        responses(
+            (status = 200, description = "The recent routing events", body = Vec<LoggedEvent>),
+        )
+    )
+)]
+async fn get_routing_events<M>(
+    State(state): State<HttpServerState<M>>,
+    Query(query): Query<RoutingEventsQuery>,
+) -> Json<Vec<LoggedEvent>>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Loading routing events");
+    let since = match query.since {
+        Some(since) => match UNIX_EPOCH.checked_add(std::time::Duration::from_millis(since)) {
+            Some(since) => Some(since),
+            // No event happened after a time which can't even be represented.
+            None => return Json(vec![]),
+        },
+        None => None,
+    };
+    Json(
+        state
+            .node
+            .lock()
+            .await
+            .routing_events(since)
+            .into_iter()
+            .map(LoggedEvent::from)
+            .collect(),
+    )
 }
 
 /// General info about a node.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Info {
     /// The overlay subnet in use by the node.
     pub node_subnet: String,
+    /// The public key of the node.
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub node_pubkey: PublicKey,
+    /// Time since the node was started, in seconds.
+    pub uptime: u64,
+    /// Version of the node.
+    pub version: String,
+    /// Amount of peers the node currently has a working connection to.
+    pub connected_peers: usize,
+    /// Amount of subnets for which the node has a selected route.
+    pub selected_routes: usize,
+    /// Sequence number the node currently announces its own subnet with.
+    pub router_seqno: u16,
+    /// Addresses on which inbound TCP connections from peers are accepted.
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub tcp_listen_addresses: Vec<SocketAddr>,
+}
+
+impl From<mycelium::NodeInfo> for Info {
+    fn from(info: mycelium::NodeInfo) -> Self {
+        Self {
+            node_subnet: info.node_subnet.to_string(),
+            node_pubkey: info.node_pubkey,
+            uptime: info.uptime.as_secs(),
+            version: info.version.to_string(),
+            connected_peers: info.connected_peers,
+            selected_routes: info.selected_routes,
+            router_seqno: info.router_seqno,
+            tcp_listen_addresses: info.tcp_listen_addrs,
+        }
+    }
 }
 
 /// Get general info about the node.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Info about the node", body = Info),
+        )
+    )
+)]
 async fn get_info<M>(State(state): State<HttpServerState<M>>) -> Json<Info>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    Json(Info {
-        node_subnet: state.node.lock().await.info().node_subnet.to_string(),
-    })
+    Json(state.node.lock().await.info().into())
+}
+
+/// Node-wide counters of the traffic handled by the node. All counters are monotonic since the node
+/// started.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    /// Unix timestamp, in seconds, at which the node started.
+    pub started_at: u64,
+    /// Data packets sent to peers, both the ones sent by the node itself and transit packets.
+    pub forwarded: Traffic,
+    /// Data packets sent to peers which were received from another node. This is part of
+    /// `forwarded`.
+    pub transit: Traffic,
+    /// Data packets delivered to the node itself.
+    pub local: Traffic,
+    /// Data packets received from peers.
+    pub data_received: Traffic,
+    /// Data packets which were dropped, by reason.
+    pub dropped: BTreeMap<String, u64>,
+    /// Control TLVs sent to peers. The bytes include the TLV headers, but not the packet headers.
+    pub control_sent: Traffic,
+    /// Control TLVs received from peers. The bytes include the TLV headers, but not the packet
+    /// headers.
+    pub control_received: Traffic,
+    /// Route updates sent to peers.
+    pub updates_sent: u64,
+    /// Route updates received from peers.
+    pub updates_received: u64,
+}
+
+impl From<mycelium::stats::NodeStats> for Stats {
+    fn from(stats: mycelium::stats::NodeStats) -> Self {
+        Self {
+            started_at: stats
+                .started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            forwarded: stats.forwarded,
+            transit: stats.transit,
+            local: stats.local,
+            data_received: stats.data_received,
+            dropped: stats
+                .dropped
+                .into_iter()
+                .map(|(reason, count)| (reason.as_str().to_string(), count))
+                .collect(),
+            control_sent: stats.control_sent,
+            control_received: stats.control_received,
+            updates_sent: stats.updates_sent,
+            updates_received: stats.updates_received,
+        }
+    }
+}
+
+/// Get the node-wide counters of the traffic handled by the node.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/stats",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Counters of the node", body = Stats),
+        )
+    )
+)]
+async fn get_stats<M>(State(state): State<HttpServerState<M>>) -> Json<Stats>
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    Json(state.node.lock().await.stats().into())
+}
+
+/// Get the metrics of the node, in the prometheus text exposition format.
+#[cfg(feature = "prometheus")]
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/admin/metrics",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "The metrics of the node", body = String, content_type = "text/plain"),
+        )
+    )
+)]
+async fn get_metrics() -> ([(header::HeaderName, &'static str); 1], String) {
+    (
+        [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+        mycelium_metrics::encode_metrics(),
+    )
 }
 
 /// Public key from a node.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PubKey {
     /// The public key from the node
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub public_key: PublicKey,
 }
 
 /// Get public key from IP.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/pubkey/{ip}",
+        tag = "Admin",
+        params(("ip" = String, Path, description = "Overlay IP of the node")),
+        responses(
+            (status = 200, description = "Public key of the node", body = PubKey),
+            (status = 404, description = "The public key of the node is not known", body = ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
 async fn get_pubk_from_ip<M>(
     State(state): State<HttpServerState<M>>,
     Path(ip): Path<IpAddr>,
-) -> Result<Json<PubKey>, StatusCode>
+) -> Result<Json<PubKey>, ApiError>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
     match state.node.lock().await.get_pubkey_from_ip(ip) {
         Some(pubkey) => Ok(Json(PubKey { public_key: pubkey })),
-        None => Err(StatusCode::NOT_FOUND),
+        None => Err(ApiError::new(
+            ErrorCode::PublicKeyNotFound,
+            format!("The public key of the node with IP {ip} is not known"),
+        )),
     }
 }
 
+/// Overlay address and subnet of the node with a public key.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PubKeyAddress {
+    /// The overlay subnet the node announces.
+    pub subnet: String,
+    /// The overlay IP of the node.
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub ip: IpAddr,
+}
+
+/// Get the overlay IP and subnet of a node from its public key. These are derived from the key,
+/// so the node does not need to be known.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/pubkey/{ip}/ip",
+        tag = "Admin",
+        params(("ip" = String, Path, description = "Hex encoded public key of the node")),
+        responses(
+            (status = 200, description = "Overlay IP and subnet of the node", body = PubKeyAddress),
+        )
+    )
+)]
+async fn get_ip_from_pubk(Path(pubkey): Path<PublicKey>) -> Json<PubKeyAddress> {
+    let ip = pubkey.address();
+    let subnet = Subnet::new(
+        Subnet::new(ip.into(), 64)
+            .expect("64 is a valid IPv6 prefix size; qed")
+            .network(),
+        64,
+    )
+    .expect("64 is a valid IPv6 prefix size; qed");
+
+    Json(PubKeyAddress {
+        subnet: subnet.to_string(),
+        ip: ip.into(),
+    })
+}
+
 impl Serialize for Metric {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -372,11 +2869,252 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn route_event_serialization() {
+        let event = RouteEvent::Changed {
+            route: Route {
+                subnet: "400::/64".to_string(),
+                source_prefix: None,
+                next_hop: "TCP [::1]:9651 <-> [::1]:34567".to_string(),
+                next_hop_connection: None,
+                metric: Metric::Value(10),
+                seqno: 3,
+                equal_cost_paths: None,
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&event).expect("can encode route event"),
+            json!({
+                "type": "changed",
+                "route": {
+                    "subnet": "400::/64",
+                    "nextHop": "TCP [::1]:9651 <-> [::1]:34567",
+                    "metric": 10,
+                    "seqno": 3,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn routing_event_serialization() {
+        let event = LoggedEvent {
+            id: 7,
+            timestamp: 1_700_000_000_000,
+            event: RoutingEvent::RouteRetracted {
+                subnet: "400::/64".to_string(),
+                source_prefix: None,
+                next_hop: "TCP [::1]:9651 <-> [::1]:34567".to_string(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&event).expect("can encode routing event"),
+            json!({
+                "id": 7,
+                "timestamp": 1_700_000_000_000_u64,
+                "event": {
+                    "type": "routeRetracted",
+                    "subnet": "400::/64",
+                    "nextHop": "TCP [::1]:9651 <-> [::1]:34567",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn path_hops_merge_origin_into_next_hop() {
+        let origin = PublicKey::from([1; 32]);
+        let other = PublicKey::from([2; 32]);
+        let next_hop = |public_key| PathHop {
+            identifier: "400::1".to_string(),
+            public_key,
+            router_id: None,
+            connection: Some("TCP [::1]:9651 <-> [::1]:34567".to_string()),
+            link_cost: Some(10),
+        };
+
+        let (hops, complete) = path_hops(next_hop(Some(origin)), origin, "rid".to_string());
+        assert!(complete);
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].router_id.as_deref(), Some("rid"));
+
+        let (hops, complete) = path_hops(next_hop(Some(other)), origin, "rid".to_string());
+        assert!(!complete);
+        assert_eq!(hops.len(), 2);
+        assert_eq!(hops[0].router_id, None);
+        assert_eq!(hops[1].identifier, origin.address().to_string());
+        assert_eq!(hops[1].connection, None);
+        assert_eq!(
+            serde_json::to_value(&hops[1]).expect("can encode path hop"),
+            json!({
+                "identifier": origin.address().to_string(),
+                "publicKey": origin.to_string(),
+                "routerId": "rid",
+            })
+        );
+    }
+
+    #[test]
+    fn add_peer_result_serialization() {
+        let results = vec![
+            AddPeerResult {
+                endpoint: "tcp://[2001:db8::1]:9651".to_string(),
+                status: AddPeerStatus::Added,
+                error: None,
+            },
+            AddPeerResult {
+                endpoint: "tcp://[2001:db8::1]".to_string(),
+                status: AddPeerStatus::ParseError,
+                error: Some("Missing port".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            serde_json::to_value(&results).expect("can encode add peer results"),
+            json!([
+                {
+                    "endpoint": "tcp://[2001:db8::1]:9651",
+                    "status": "added",
+                },
+                {
+                    "endpoint": "tcp://[2001:db8::1]",
+                    "status": "parseError",
+                    "error": "Missing port",
+                },
+            ])
+        );
+        assert_eq!(
+            serde_json::from_value::<AddPeerStatus>(json!("alreadyExists"))
+                .expect("can decode add peer status"),
+            AddPeerStatus::AlreadyExists
+        );
+    }
+
+    #[test]
+    fn bearer_token_extraction() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(header::AUTHORIZATION, "Bearer s3cr3t".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("s3cr3t"));
+
+        headers.insert(header::AUTHORIZATION, "bearer s3cr3t".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("s3cr3t"));
+
+        headers.insert(header::AUTHORIZATION, "Basic czNjcjN0".parse().unwrap());
+        assert_eq!(bearer_token(&headers), None);
+    }
+
+    #[test]
+    fn accept_header_parsing() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_json(&headers));
+
+        headers.insert(header::ACCEPT, "*/*".parse().unwrap());
+        assert!(!accepts_json(&headers));
+
+        headers.insert(
+            header::ACCEPT,
+            "text/plain, application/json; q=0.9".parse().unwrap(),
+        );
+        assert!(accepts_json(&headers));
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"s3cr3t", b"s3cr3t"));
+        assert!(!constant_time_eq(b"s3cr3t", b"s3cr3T"));
+        assert!(!constant_time_eq(b"s3cr3t", b"s3cr3"));
+        assert!(!constant_time_eq(b"", b"s3cr3t"));
+    }
+
+    #[test]
+    fn cors_layer_only_with_valid_origins() {
+        assert!(cors_layer(vec![]).is_none());
+        assert!(cors_layer(vec!["https://invalid\n.example.com".to_string()]).is_none());
+        assert!(cors_layer(vec!["https://dashboard.example.com".to_string()]).is_some());
+        assert!(cors_layer(vec![ANY_ORIGIN.to_string()]).is_some());
+    }
+
+    fn peer_stats(port: u16, connection_state: ConnectionState) -> PeerStats {
+        PeerStats {
+            endpoint: Endpoint::from_str(&format!("tcp://[::1]:{port}")).unwrap(),
+            pt: mycelium::peer_manager::PeerType::Static,
+            connection_state,
+            tx_bytes: 0,
+            rx_bytes: 0,
+            connection_identifier: None,
+            overlay_address: None,
+            link_cost: None,
+            hello_reception: None,
+            connection_attempts: 0,
+            connect_timeouts: 0,
+            handshake_timeouts: 0,
+            connections_refused: 0,
+            decryption_failures: 0,
+            next_connection_attempt: None,
+            last_seen: None,
+            selected_routes: 0,
+            rate_limit: Default::default(),
+            tx_rate: 0,
+            rx_rate: 0,
+            tx_dropped_bytes: 0,
+            rx_dropped_bytes: 0,
+            last_disconnect_reason: None,
+            state: mycelium::peer_manager::PeerState::Dead,
+            state_changed: 0,
+            cipher: None,
+            mtu: None,
+            priority: 0,
+            listen_address: None,
+        }
+    }
+
+    #[test]
+    fn paginate_peers_filters_and_clamps() {
+        let peers = (0..10)
+            .map(|i| {
+                peer_stats(
+                    9651 + i,
+                    if i % 2 == 0 {
+                        ConnectionState::Alive
+                    } else {
+                        ConnectionState::Dead
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let query = PeerListQuery {
+            limit: Some(2),
+            offset: Some(1),
+            state: Some(ConnectionState::Alive),
+        };
+        let (total, page) = paginate_peers(peers, &query);
+        assert_eq!(total, 5);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].endpoint.address().port(), 9653);
+        assert_eq!(page[1].endpoint.address().port(), 9655);
+
+        let peers = (0..(MAX_PEER_LIST_LIMIT as u16 + 10))
+            .map(|i| peer_stats(i, ConnectionState::Connecting))
+            .collect::<Vec<_>>();
+        let query = PeerListQuery {
+            limit: Some(usize::MAX),
+            ..Default::default()
+        };
+        let (total, page) = paginate_peers(peers, &query);
+        assert_eq!(total, MAX_PEER_LIST_LIMIT + 10);
+        assert_eq!(page.len(), MAX_PEER_LIST_LIMIT);
+    }
+
     #[test]
     fn test_deserialize_route() {
         let json_data = r#"
         [
-            {"subnet":"406:1d77:2438:aa7c::/64","nextHop":"TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651","metric":20,"seqno":0},
+            {"subnet":"406:1d77:2438:aa7c::/64","nextHop":"TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651","metric":20,"seqno":0,"equalCostPaths":2},
             {"subnet":"407:8458:dbf5:4ed7::/64","nextHop":"TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651","metric":174,"seqno":0},
             {"subnet":"408:7ba3:3a4d:808a::/64","nextHop":"TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651","metric":"infinite","seqno":0}
         ]
@@ -386,23 +3124,32 @@ mod tests {
 
         assert_eq!(routes[0], Route {
             subnet: "406:1d77:2438:aa7c::/64".to_string(),
+            source_prefix: None,
             next_hop: "TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651".to_string(),
+            next_hop_connection: None,
             metric: Metric::Value(20),
-            seqno: 0
+            seqno: 0,
+            equal_cost_paths: Some(2),
         });
 
         assert_eq!(routes[1], Route {
             subnet: "407:8458:dbf5:4ed7::/64".to_string(),
+            source_prefix: None,
             next_hop: "TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651".to_string(),
+            next_hop_connection: None,
             metric: Metric::Value(174),
-            seqno: 0
+            seqno: 0,
+            equal_cost_paths: None,
         });
 
         assert_eq!(routes[2], Route {
             subnet: "408:7ba3:3a4d:808a::/64".to_string(),
+            source_prefix: None,
             next_hop: "TCP [2a02:1811:d584:7400:c503:ff39:de03:9e44]:45694 <-> [2a01:4f8:212:fa6::2]:9651".to_string(),
+            next_hop_connection: None,
             metric: Metric::Infinite,
-            seqno: 0
+            seqno: 0,
+            equal_cost_paths: None,
         });
     }
 }
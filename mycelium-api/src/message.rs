@@ -1,8 +1,12 @@
 use std::{net::IpAddr, ops::Deref, time::Duration};
 
 use axum::{
-    extract::{Path, Query, State},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
     http::StatusCode,
+    response::Response,
     routing::{get, post},
     Json, Router,
 };
@@ -11,11 +15,11 @@ use tracing::debug;
 
 use mycelium::{
     crypto::PublicKey,
-    message::{MessageId, MessageInfo},
+    message::{DeliveryStatus, MessageId, MessageInfo, MessageSubscription, ReceivedMessage},
     metrics::Metrics,
 };
 
-use super::HttpServerState;
+use super::{ApiError, ErrorCode, HttpServerState};
 
 /// Default amount of time to try and send a message if it is not explicitly specified.
 const DEFAULT_MESSAGE_TRY_DURATION: Duration = Duration::from_secs(60 * 5);
@@ -27,46 +31,83 @@ where
 {
     Router::new()
         .route("/messages", get(get_message).post(push_message))
+        .route("/messages/subscribe", get(subscribe_messages))
         .route("/messages/status/:id", get(message_status))
         .route("/messages/reply/:id", post(reply_message))
         .with_state(server_state)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MessageSendInfo {
     pub dst: MessageDestination,
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(with = "base64::optional_binary")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
     pub topic: Option<Vec<u8>>,
     #[serde(with = "base64::binary")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub payload: Vec<u8>,
+    /// Wait until the receiver acknowledged reception of the message, or until sending the
+    /// message timed out, before responding.
+    #[serde(default)]
+    pub await_ack: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum MessageDestination {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     Ip(IpAddr),
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     Pk(PublicKey),
 }
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MessageReceiveInfo {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub id: MessageId,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub src_ip: IpAddr,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub src_pk: PublicKey,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub dst_ip: IpAddr,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub dst_pk: PublicKey,
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(with = "base64::optional_binary")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
     pub topic: Option<Vec<u8>>,
     #[serde(with = "base64::binary")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub payload: Vec<u8>,
 }
 
+impl From<ReceivedMessage> for MessageReceiveInfo {
+    fn from(m: ReceivedMessage) -> Self {
+        Self {
+            id: m.id,
+            src_ip: m.src_ip,
+            src_pk: m.src_pk,
+            dst_ip: m.dst_ip,
+            dst_pk: m.dst_pk,
+            topic: if m.topic.is_empty() {
+                None
+            } else {
+                Some(m.topic)
+            },
+            payload: m.data,
+        }
+    }
+}
+
 impl MessageDestination {
     /// Get the IP address of the destination.
     fn ip(self) -> IpAddr {
@@ -78,13 +119,21 @@ impl MessageDestination {
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
 struct GetMessageQuery {
+    /// Leave the message in the inbox.
     peek: Option<bool>,
+    /// Amount of seconds to wait for a message if there is none.
     timeout: Option<u64>,
     /// Optional filter for start of the message, base64 encoded.
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(with = "base64::optional_binary")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
     topic: Option<Vec<u8>>,
 }
 
@@ -100,6 +149,20 @@ impl GetMessageQuery {
     }
 }
 
+/// Get a message from the inbox.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/messages",
+        tag = "Message",
+        params(GetMessageQuery),
+        responses(
+            (status = 200, description = "A message", body = MessageReceiveInfo),
+            (status = 204, description = "No message was received before the timeout"),
+        )
+    )
+)]
 async fn get_message<M>(
     State(state): State<HttpServerState<M>>,
     Query(query): Query<GetMessageQuery>,
@@ -126,30 +189,100 @@ where
     )
     .await
     .or(Err(StatusCode::NO_CONTENT))
-    .map(|m| {
-        Json(MessageReceiveInfo {
-            id: m.id,
-            src_ip: m.src_ip,
-            src_pk: m.src_pk,
-            dst_ip: m.dst_ip,
-            dst_pk: m.dst_pk,
-            topic: if m.topic.is_empty() {
-                None
-            } else {
-                Some(m.topic)
-            },
-            payload: m.data,
-        })
-    })
+    .map(|m| Json(m.into()))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+struct SubscribeMessagesQuery {
+    /// Optional filter on the topics of the messages, as a comma separated list of base64 encoded
+    /// topics.
+    #[serde(default)]
+    #[serde(deserialize_with = "base64::binary_list::deserialize")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>))]
+    topic: Vec<Vec<u8>>,
+}
+
+/// Stream received messages over a WebSocket.
+///
+/// Every message is sent as a JSON encoded `MessageReceiveInfo` in a text message.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/messages/subscribe",
+        tag = "Message",
+        params(SubscribeMessagesQuery),
+        responses(
+            (status = 101, description = "Switching to the WebSocket protocol"),
+        )
+    )
+)]
+async fn subscribe_messages<M>(
+    State(state): State<HttpServerState<M>>,
+    Query(query): Query<SubscribeMessagesQuery>,
+    ws: WebSocketUpgrade,
+) -> Response
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    let subscription = state.node.lock().await.subscribe_messages(query.topic);
+    ws.on_upgrade(move |socket| stream_messages(subscription, socket))
+}
+
+/// Send received messages on the socket as they arrive. Messages are only removed from the inbox
+/// once they are sent, so messages which arrive while no client is connected are sent to the
+/// next client which subscribes.
+async fn stream_messages<M>(subscription: MessageSubscription<M>, mut socket: WebSocket)
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    debug!("Streaming received messages");
+
+    loop {
+        tokio::select! {
+            msg = subscription.next() => {
+                let info = MessageReceiveInfo::from(msg.clone());
+                let text = serde_json::to_string(&info)
+                    .expect("Received messages can always be serialized; qed");
+                if socket.send(Message::Text(text)).await.is_err() {
+                    debug!("Failed to send message to subscriber, returning it to the inbox");
+                    subscription.put_back(msg);
+                    return;
+                }
+            }
+            msg = socket.recv() => {
+                // We don't expect any messages from the client, but we need to read from the
+                // socket to notice when it is closed.
+                match msg {
+                    None | Some(Err(_)) | Some(Ok(Message::Close(_))) => {
+                        debug!("Message subscriber disconnected");
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MessageIdReply {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     id: MessageId,
+    /// Delivery status of the message at the time of the reply.
+    #[serde(default)]
+    status: DeliveryStatus,
 }
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(untagged)]
 pub enum PushMessageResponse {
@@ -158,7 +291,13 @@ pub enum PushMessageResponse {
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
 struct PushMessageQuery {
+    /// Wait this amount of seconds for a reply to the message.
     reply_timeout: Option<u64>,
 }
 
@@ -174,11 +313,28 @@ impl PushMessageQuery {
     }
 }
 
+/// Send a message to a node.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/messages",
+        tag = "Message",
+        params(PushMessageQuery),
+        request_body = MessageSendInfo,
+        responses(
+            (status = 200, description = "The reply to the message", body = PushMessageResponse),
+            (status = 201, description = "The message is queued, or delivered if an ack was awaited", body = PushMessageResponse),
+            (status = 400, description = "The message is invalid", body = crate::ProblemDetails, content_type = "application/problem+json"),
+            (status = 408, description = "No reply or ack was received in time", body = PushMessageResponse),
+        )
+    )
+)]
 async fn push_message<M>(
     State(state): State<HttpServerState<M>>,
     Query(query): Query<PushMessageQuery>,
     Json(message_info): Json<MessageSendInfo>,
-) -> Result<(StatusCode, Json<PushMessageResponse>), StatusCode>
+) -> Result<(StatusCode, Json<PushMessageResponse>), ApiError>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
@@ -188,24 +344,28 @@ where
         message_info.payload.len(),
     );
 
-    let (id, sub) = match state.node.lock().await.push_message(
+    let (id, sub) = state.node.lock().await.push_message(
         dst,
         message_info.payload,
         message_info.topic,
         DEFAULT_MESSAGE_TRY_DURATION,
         query.await_reply(),
-    ) {
-        Ok((id, sub)) => (id, sub),
-        Err(_) => {
-            return Err(StatusCode::BAD_REQUEST);
-        }
-    };
+    )?;
 
     if !query.await_reply() {
-        // If we don't wait for the reply just return here.
+        // If we don't wait for the reply just return here, possibly after waiting for the ACK.
+        let status = if message_info.await_ack {
+            await_delivery(&state, id).await
+        } else {
+            DeliveryStatus::Queued
+        };
+        let code = match status {
+            DeliveryStatus::TimedOut => StatusCode::REQUEST_TIMEOUT,
+            _ => StatusCode::CREATED,
+        };
         return Ok((
-            StatusCode::CREATED,
-            Json(PushMessageResponse::Id(MessageIdReply { id })),
+            code,
+            Json(PushMessageResponse::Id(MessageIdReply { id, status })),
         ));
     }
 
@@ -226,22 +386,38 @@ where
                         }))))
                     } else {
                         // This happens if a none value is send, which should not happen.
-                        Err(StatusCode::INTERNAL_SERVER_ERROR)
+                        Err(ApiError::new(ErrorCode::Internal, "Reply to the message is missing"))
                     }
                 }
                 Err(_)  => {
                     // This happens if the sender drops, which should not happen.
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
+                    Err(ApiError::new(ErrorCode::Internal, "Stopped waiting for the reply to the message"))
                 }
             }
         },
         _ = tokio::time::sleep(Duration::from_secs(query.timeout())) => {
             // Timeout expired while waiting for reply
-            Ok((StatusCode::REQUEST_TIMEOUT, Json(PushMessageResponse::Id(MessageIdReply { id  }))))
+            let status = delivery_status(&state, id).await;
+            Ok((StatusCode::REQUEST_TIMEOUT, Json(PushMessageResponse::Id(MessageIdReply { id, status }))))
         }
     }
 }
 
+/// Reply to a received message.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/v1/messages/reply/{id}",
+        tag = "Message",
+        params(("id" = String, Path, description = "Hex encoded id of the message to reply to")),
+        request_body = MessageSendInfo,
+        responses(
+            (status = 204, description = "The reply is queued, or delivered if an ack was awaited"),
+            (status = 408, description = "No ack was received in time"),
+        )
+    )
+)]
 async fn reply_message<M>(
     State(state): State<HttpServerState<M>>,
     Path(id): Path<MessageId>,
@@ -257,20 +433,76 @@ where
         message_info.payload.len(),
     );
 
-    state.node.lock().await.reply_message(
+    let reply_id = state.node.lock().await.reply_message(
         id,
         dst,
         message_info.payload,
         DEFAULT_MESSAGE_TRY_DURATION,
     );
 
+    if message_info.await_ack && await_delivery(&state, reply_id).await == DeliveryStatus::TimedOut
+    {
+        return StatusCode::REQUEST_TIMEOUT;
+    }
+
     StatusCode::NO_CONTENT
 }
 
+/// Wait until the receiver acknowledged reception of the message with the given id, or until
+/// sending it timed out.
+async fn await_delivery<M>(state: &HttpServerState<M>, id: MessageId) -> DeliveryStatus
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    // Don't hold the node lock while waiting.
+    let delivery = state.node.lock().await.subscribe_message_delivery(id);
+    let Some(mut delivery) = delivery else {
+        // Info of a message is only removed some time after it timed out.
+        return DeliveryStatus::TimedOut;
+    };
+    let status = match delivery
+        .wait_for(|status| *status != DeliveryStatus::Queued)
+        .await
+    {
+        Ok(status) => *status,
+        // The info of the message is removed, which only happens after it timed out.
+        Err(_) => DeliveryStatus::TimedOut,
+    };
+    status
+}
+
+/// Get the current delivery status of the message with the given id.
+async fn delivery_status<M>(state: &HttpServerState<M>, id: MessageId) -> DeliveryStatus
+where
+    M: Metrics + Clone + Send + Sync + 'static,
+{
+    state
+        .node
+        .lock()
+        .await
+        .subscribe_message_delivery(id)
+        .map(|delivery| *delivery.borrow())
+        .unwrap_or(DeliveryStatus::TimedOut)
+}
+
+/// Get the transmission state of a sent message.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/api/v1/messages/status/{id}",
+        tag = "Message",
+        params(("id" = String, Path, description = "Hex encoded id of the message")),
+        responses(
+            (status = 200, description = "Info about the message", body = MessageInfo),
+            (status = 404, description = "There is no message with this id", body = crate::ProblemDetails, content_type = "application/problem+json"),
+        )
+    )
+)]
 async fn message_status<M>(
     State(state): State<HttpServerState<M>>,
     Path(id): Path<MessageId>,
-) -> Result<Json<MessageInfo>, StatusCode>
+) -> Result<Json<MessageInfo>, ApiError>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
@@ -281,10 +513,27 @@ where
         .lock()
         .await
         .message_status(id)
-        .ok_or(StatusCode::NOT_FOUND)
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::MessageNotFound,
+                format!("There is no message with id {}", id.as_hex()),
+            )
+        })
         .map(Json)
 }
 
+/// The endpoints of the message subsystem, merged in the OpenAPI document of the API.
+#[cfg(feature = "openapi")]
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    get_message,
+    push_message,
+    subscribe_messages,
+    message_status,
+    reply_message
+))]
+pub(crate) struct MessageApi;
+
 /// Module to implement base64 decoding and encoding
 /// Sourced from https://users.rust-lang.org/t/serialize-a-vec-u8-to-json-as-base64/57781, with some
 /// addaptions to work with the new version of the base64 crate
@@ -316,6 +565,24 @@ mod base64 {
         }
     }
 
+    /// A comma separated list of base64 encoded values.
+    pub mod binary_list {
+        use super::B64ENGINE;
+        use base64::Engine;
+        use serde::{Deserialize, Deserializer};
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+            let list = String::deserialize(d)?;
+            list.split(',')
+                .map(|base64| {
+                    B64ENGINE
+                        .decode(base64.as_bytes())
+                        .map_err(serde::de::Error::custom)
+                })
+                .collect()
+        }
+    }
+
     pub mod optional_binary {
         use super::B64ENGINE;
         use base64::Engine;
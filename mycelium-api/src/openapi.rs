@@ -0,0 +1,234 @@
+//! OpenAPI description of the HTTP API, derived from the endpoint handlers and the types they
+//! use. The description is served as JSON, together with a Swagger UI to explore it.
+
+use axum::Router;
+use utoipa::{
+    openapi::{
+        schema::{ObjectBuilder, OneOfBuilder, Schema, Type},
+        security::{HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme},
+        OpenApi as OpenApiDoc, RefOr,
+    },
+    OpenApi, PartialSchema, ToSchema,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{ApiMode, Metric, INFINITE_STR};
+
+/// Path of the OpenAPI document.
+const OPENAPI_PATH: &str = "/api/v1/openapi.json";
+
+/// Path of the Swagger UI.
+const SWAGGER_UI_PATH: &str = "/api/v1/docs";
+
+/// Prefix of the paths of endpoints which require the API key, if one is configured.
+const ADMIN_PATH_PREFIX: &str = "/api/v1/admin";
+
+/// Name of the security scheme of the API key.
+const API_KEY_SCHEME: &str = "apiKey";
+
+/// The endpoints which are always available.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Mycelium management",
+        description = "API to perform administrative tasks on a mycelium node, and to use its \
+                       embedded message subsystem.",
+        license(
+            name = "Apache 2.0",
+            url = "https://github.com/threefoldtech/mycelium/blob/master/LICENSE"
+        )
+    ),
+    paths(
+        crate::get_info,
+        crate::get_stats,
+        crate::get_peers,
+        crate::add_peer,
+        crate::add_peers,
+        crate::validate_peer,
+        crate::get_peer,
+        crate::delete_peer,
+        crate::replace_peer,
+        crate::set_peer_rate_limit,
+        crate::set_peer_mtu,
+        crate::set_peer_priority,
+        crate::dump_routes_to_peer,
+        crate::get_neighbours,
+        crate::get_peer_access_list,
+        crate::set_peer_access_list,
+        crate::get_route_access_list,
+        crate::set_route_access_list,
+        crate::get_selected_routes,
+        crate::get_fallback_routes,
+        crate::get_suppressed_routes,
+        crate::get_all_routes,
+        crate::get_metric_offsets,
+        crate::set_metric_offsets,
+        crate::get_pinned_routes,
+        crate::add_pinned_routes,
+        crate::delete_pinned_route,
+        crate::query_route,
+        crate::query_path,
+        crate::flush_routes,
+        crate::get_drain_status,
+        crate::start_drain,
+        crate::route_events,
+        crate::get_routing_events,
+        crate::get_pubk_from_ip,
+        crate::get_ip_from_pubk,
+    ),
+    components(schemas(crate::RouteEvent)),
+    tags(
+        (name = "Admin", description = "Administrative operations"),
+        (name = "Message", description = "Operations on the embedded message subsystem"),
+        (name = "Health", description = "Liveness and readiness probes"),
+    )
+)]
+struct ApiDoc;
+
+/// The endpoint to export metrics.
+#[cfg(feature = "prometheus")]
+#[derive(OpenApi)]
+#[openapi(paths(crate::get_metrics))]
+struct MetricsApi;
+
+/// The endpoints to inject faults, for testing.
+#[cfg(feature = "testing")]
+#[derive(OpenApi)]
+#[openapi(paths(crate::get_fault_injection, crate::set_fault_injection))]
+struct TestingApi;
+
+/// Build a router which serves the OpenAPI document of the endpoints which are served in the given
+/// [`ApiMode`], and the Swagger UI.
+pub fn router(mode: ApiMode) -> Router {
+    SwaggerUi::new(SWAGGER_UI_PATH)
+        .url(OPENAPI_PATH, openapi(mode))
+        .into()
+}
+
+/// The OpenAPI document of the endpoints which are served in the given [`ApiMode`].
+fn openapi(mode: ApiMode) -> OpenApiDoc {
+    let mut doc = ApiDoc::openapi();
+    #[cfg(feature = "prometheus")]
+    doc.merge(MetricsApi::openapi());
+    #[cfg(feature = "testing")]
+    doc.merge(TestingApi::openapi());
+    doc.merge(crate::health::HealthApi::openapi());
+    #[cfg(feature = "message")]
+    if mode == ApiMode::Full {
+        doc.merge(crate::message::MessageApi::openapi());
+    }
+
+    if mode == ApiMode::ReadOnly {
+        // Only GET requests are served in read only mode.
+        doc.paths.paths.retain(|_, item| {
+            item.put = None;
+            item.post = None;
+            item.delete = None;
+            item.patch = None;
+            item.get.is_some()
+        });
+    }
+
+    doc.components
+        .get_or_insert_with(Default::default)
+        .add_security_scheme(
+            API_KEY_SCHEME,
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "Only required if the node is started with an API key.",
+                    ))
+                    .build(),
+            ),
+        );
+    for (_, item) in doc
+        .paths
+        .paths
+        .iter_mut()
+        .filter(|(path, _)| path.starts_with(ADMIN_PATH_PREFIX))
+    {
+        for operation in [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            operation.security = Some(vec![SecurityRequirement::new(
+                API_KEY_SCHEME,
+                Vec::<String>::new(),
+            )]);
+        }
+    }
+
+    doc
+}
+
+impl PartialSchema for Metric {
+    fn schema() -> RefOr<Schema> {
+        OneOfBuilder::new()
+            .item(
+                ObjectBuilder::new()
+                    .schema_type(Type::Integer)
+                    .minimum(Some(0))
+                    .maximum(Some(u16::MAX - 1)),
+            )
+            .item(
+                ObjectBuilder::new()
+                    .schema_type(Type::String)
+                    .enum_values(Some([INFINITE_STR])),
+            )
+            .description(Some(
+                "Metric of a route. Unreachable routes have an infinite metric.",
+            ))
+            .into()
+    }
+}
+
+impl ToSchema for Metric {}
+
+#[cfg(test)]
+mod tests {
+    use super::{openapi, API_KEY_SCHEME};
+    use crate::ApiMode;
+
+    #[test]
+    fn read_only_document_only_has_get_operations() {
+        let full = openapi(ApiMode::Full);
+        let peers = &full.paths.paths["/api/v1/admin/peers"];
+        assert!(peers.get.is_some());
+        assert!(peers.post.is_some());
+        assert!(peers.get.as_ref().unwrap().security.is_some());
+        assert!(full
+            .components
+            .as_ref()
+            .unwrap()
+            .security_schemes
+            .contains_key(API_KEY_SCHEME));
+        assert!(full.paths.paths["/api/v1/pubkey/{ip}"]
+            .get
+            .as_ref()
+            .unwrap()
+            .security
+            .is_none());
+        assert!(full.paths.paths["/readyz"]
+            .get
+            .as_ref()
+            .unwrap()
+            .security
+            .is_none());
+
+        let read_only = openapi(ApiMode::ReadOnly);
+        let peers = &read_only.paths.paths["/api/v1/admin/peers"];
+        assert!(peers.get.is_some());
+        assert!(peers.post.is_none());
+        assert!(!read_only
+            .paths
+            .paths
+            .contains_key("/api/v1/admin/peers/batch"));
+        assert!(!read_only.paths.paths.contains_key("/api/v1/messages"));
+    }
+}
@@ -1,6 +1,6 @@
-use std::net::IpAddr;
+use std::{io, net::IpAddr, path::Path};
 
-use mycelium::crypto::PublicKey;
+use mycelium::crypto::{PublicKey, SecretKey};
 use serde::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -8,15 +8,18 @@ struct InspectOutput {
     #[serde(rename = "publicKey")]
     public_key: PublicKey,
     address: IpAddr,
+    subnet: String,
 }
 
 /// Inspect the given pubkey, or the local key if no pubkey is given
 pub fn inspect(pubkey: PublicKey, json: bool) -> Result<(), Box<dyn std::error::Error>> {
     let address = pubkey.address().into();
+    let subnet = pubkey.subnet().to_string();
     if json {
         let out = InspectOutput {
             public_key: pubkey,
             address,
+            subnet,
         };
 
         let out_string = serde_json::to_string_pretty(&out)?;
@@ -24,7 +27,33 @@ pub fn inspect(pubkey: PublicKey, json: bool) -> Result<(), Box<dyn std::error::
     } else {
         println!("Public key: {pubkey}");
         println!("Address: {address}");
+        println!("Subnet: {subnet}");
     }
 
     Ok(())
 }
+
+/// Generate a new node key and save it to `path`, in the same format as the key file of the node.
+/// An existing file is only overwritten if `force` is set. The public key of the generated key is
+/// printed like [`inspect`] does.
+pub async fn keygen(
+    path: &Path,
+    force: bool,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !force && path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+        )
+        .into());
+    }
+
+    let secret_key = SecretKey::new();
+    secret_key.save_to_file(path).await?;
+
+    inspect(PublicKey::from(&secret_key), json)
+}
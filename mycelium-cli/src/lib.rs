@@ -4,8 +4,21 @@ mod message;
 mod peer;
 mod routes;
 
-pub use inspect::inspect;
+pub use inspect::{inspect, keygen};
 #[cfg(feature = "message")]
 pub use message::{recv_msg, send_msg};
-pub use peer::{add_peers, list_peers, remove_peers};
-pub use routes::{list_fallback_routes, list_selected_routes};
+pub use peer::{add_peers, list_neighbours, list_peers, remove_peers};
+pub use routes::{
+    list_all_routes, list_fallback_routes, list_selected_routes, list_suppressed_routes,
+};
+
+/// Add the API key as bearer token to a request to the admin API, if one is configured.
+fn with_api_key(
+    request: reqwest::RequestBuilder,
+    api_key: Option<&str>,
+) -> reqwest::RequestBuilder {
+    match api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request,
+    }
+}
@@ -153,6 +153,7 @@ pub async fn send_msg(
             dst: destination,
             topic: topic.map(String::into_bytes),
             payload: msg,
+            await_ack: false,
         })
         .send()
         .await
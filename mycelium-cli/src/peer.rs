@@ -1,5 +1,5 @@
 use mycelium::peer_manager::PeerStats;
-use mycelium_api::AddPeer;
+use mycelium_api::{AddPeer, Neighbour, PeerListQuery, MAX_PEER_LIST_LIMIT, TOTAL_COUNT_HEADER};
 use prettytable::{row, Table};
 use std::net::SocketAddr;
 use tracing::{debug, error};
@@ -7,54 +7,151 @@ use tracing::{debug, error};
 /// List the peers the current node is connected to
 pub async fn list_peers(
     server_addr: SocketAddr,
+    api_key: Option<&str>,
     json_print: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Make API call
-    let request_url = format!("http://{server_addr}/api/v1/admin/peers");
-    match reqwest::get(&request_url).await {
+    let peers = match fetch_peers(server_addr, api_key).await {
+        Ok(peers) => peers,
         Err(e) => {
             error!("Failed to retrieve peers");
+            return Err(e);
+        }
+    };
+
+    debug!("Listing connected peers");
+    if json_print {
+        // Print peers in JSON format
+        let json_output = serde_json::to_string_pretty(&peers)?;
+        println!("{json_output}");
+    } else {
+        // Print peers in table format
+        let mut table = Table::new();
+        table.add_row(row![
+            "Protocol",
+            "Socket",
+            "Type",
+            "Connection",
+            "Rx total",
+            "Tx total"
+        ]);
+        for peer in peers.iter() {
+            table.add_row(row![
+                peer.endpoint.proto(),
+                peer.endpoint.address(),
+                peer.pt,
+                peer.connection_state,
+                format_bytes(peer.rx_bytes),
+                format_bytes(peer.tx_bytes),
+            ]);
+        }
+        table.printstd();
+    }
+
+    Ok(())
+}
+
+/// Get the stats of all peers of the node. The peer list is paginated, so pages are requested
+/// until all peers counted in the `X-Total-Count` header are received. Peers which are added or
+/// removed while the pages are requested might be missed or listed twice.
+async fn fetch_peers(
+    server_addr: SocketAddr,
+    api_key: Option<&str>,
+) -> Result<Vec<PeerStats>, Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    let request_url = format!("http://{server_addr}/api/v1/admin/peers");
+    let mut peers = Vec::new();
+    loop {
+        let query = PeerListQuery {
+            limit: Some(MAX_PEER_LIST_LIMIT),
+            offset: Some(peers.len()),
+            state: None,
+        };
+        let resp = crate::with_api_key(client.get(&request_url).query(&query), api_key)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())?;
+        let total = resp
+            .headers()
+            .get(TOTAL_COUNT_HEADER)
+            .and_then(|total| total.to_str().ok())
+            .and_then(|total| total.parse::<usize>().ok());
+        let page = match resp.json::<Vec<PeerStats>>().await {
+            Ok(page) => page,
+            Err(e) => {
+                error!("Failed to load response json: {e}");
+                return Err(e.into());
+            }
+        };
+
+        // Nodes which don't paginate the peer list don't send the total. Also stop at an empty
+        // page, which can happen if peers were removed in the meantime.
+        let page_len = page.len();
+        peers.extend(page);
+        match total {
+            Some(total) if page_len > 0 && peers.len() < total => continue,
+            _ => return Ok(peers),
+        }
+    }
+}
+
+/// List the routing protocol state of the peers the current node is connected to
+pub async fn list_neighbours(
+    server_addr: SocketAddr,
+    api_key: Option<&str>,
+    json_print: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request_url = format!("http://{server_addr}/api/v1/admin/neighbours");
+    match crate::with_api_key(reqwest::Client::new().get(&request_url), api_key)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+    {
+        Err(e) => {
+            error!("Failed to retrieve neighbours");
             return Err(e.into());
         }
         Ok(resp) => {
-            debug!("Listing connected peers");
-            match resp.json::<Vec<PeerStats>>().await {
-                Err(e) => {
-                    error!("Failed to load response json: {e}");
-                    return Err(e.into());
-                }
-                Ok(peers) => {
-                    if json_print {
-                        // Print peers in JSON format
-                        let json_output = serde_json::to_string_pretty(&peers)?;
-                        println!("{json_output}");
-                    } else {
-                        // Print peers in table format
-                        let mut table = Table::new();
-                        table.add_row(row![
-                            "Protocol",
-                            "Socket",
-                            "Type",
-                            "Connection",
-                            "Rx total",
-                            "Tx total"
-                        ]);
-                        for peer in peers.iter() {
-                            table.add_row(row![
-                                peer.endpoint.proto(),
-                                peer.endpoint.address(),
-                                peer.pt,
-                                peer.connection_state,
-                                format_bytes(peer.rx_bytes),
-                                format_bytes(peer.tx_bytes),
-                            ]);
-                        }
-                        table.printstd();
-                    }
+            debug!("Listing neighbours");
+
+            if json_print {
+                // API call returns neighbours in JSON format by default
+                let neighbours = resp.text().await?;
+                println!("{neighbours}");
+            } else {
+                // Print neighbours in table format
+                let neighbours: Vec<Neighbour> = resp.json().await?;
+                let mut table = Table::new();
+                table.add_row(row![
+                    "Neighbour",
+                    "Rx cost",
+                    "Tx cost",
+                    "Link cost",
+                    "RTT",
+                    "Hello reception",
+                    "Last hello",
+                    "Last IHU"
+                ]);
+                for neighbour in neighbours.iter() {
+                    table.add_row(row![
+                        &neighbour.neighbour,
+                        neighbour.rx_cost,
+                        neighbour
+                            .tx_cost
+                            .map_or_else(|| "-".to_string(), |cost| cost.to_string()),
+                        neighbour.link_cost,
+                        format!("{} ms", neighbour.rtt),
+                        format!("{:.0}%", neighbour.hello_reception * 100.0),
+                        match (neighbour.last_hello_seqno, neighbour.last_hello) {
+                            (Some(seqno), Some(ago)) => format!("#{seqno}, {ago} ms ago"),
+                            _ => "-".to_string(),
+                        },
+                        format!("{} ms ago", neighbour.last_ihu),
+                    ]);
                 }
+                table.printstd();
             }
         }
-    };
+    }
 
     Ok(())
 }
@@ -72,6 +169,7 @@ fn format_bytes(bytes: u64) -> String {
 /// Remove peer(s) by (underlay) IP
 pub async fn remove_peers(
     server_addr: SocketAddr,
+    api_key: Option<&str>,
     peers: Vec<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
@@ -79,8 +177,7 @@ pub async fn remove_peers(
         // encode to pass in URL
         let peer_encoded = urlencoding::encode(peer);
         let request_url = format!("http://{server_addr}/api/v1/admin/peers/{peer_encoded}");
-        if let Err(e) = client
-            .delete(&request_url)
+        if let Err(e) = crate::with_api_key(client.delete(&request_url), api_key)
             .send()
             .await
             .and_then(|res| res.error_for_status())
@@ -96,13 +193,13 @@ pub async fn remove_peers(
 /// Add peer(s) by (underlay) IP
 pub async fn add_peers(
     server_addr: SocketAddr,
+    api_key: Option<&str>,
     peers: Vec<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
     for peer in peers.into_iter() {
         let request_url = format!("http://{server_addr}/api/v1/admin/peers");
-        if let Err(e) = client
-            .post(&request_url)
+        if let Err(e) = crate::with_api_key(client.post(&request_url), api_key)
             .json(&AddPeer { endpoint: peer })
             .send()
             .await
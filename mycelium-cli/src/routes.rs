@@ -1,4 +1,4 @@
-use mycelium_api::Route;
+use mycelium_api::{Route, RouteTableEntry, RouteTableQuery, SuppressedRoute};
 use prettytable::{row, Table};
 use std::net::SocketAddr;
 
@@ -6,10 +6,15 @@ use tracing::{debug, error};
 
 pub async fn list_selected_routes(
     server_addr: SocketAddr,
+    api_key: Option<&str>,
     json_print: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let request_url = format!("http://{server_addr}/api/v1/admin/routes/selected");
-    match reqwest::get(&request_url).await {
+    match crate::with_api_key(reqwest::Client::new().get(&request_url), api_key)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+    {
         Err(e) => {
             error!("Failed to retrieve selected routes");
             return Err(e.into());
@@ -25,14 +30,18 @@ pub async fn list_selected_routes(
                 // Print routes in table format
                 let routes: Vec<Route> = resp.json().await?;
                 let mut table = Table::new();
-                table.add_row(row!["Subnet", "Next Hop", "Metric", "Seq No"]);
+                table.add_row(row![
+                    "Subnet", "Source", "Next Hop", "Metric", "Seq No", "Paths"
+                ]);
 
                 for route in routes.iter() {
                     table.add_row(row![
                         &route.subnet,
+                        route.source_prefix.as_deref().unwrap_or("*"),
                         &route.next_hop,
                         route.metric,
                         route.seqno,
+                        route.equal_cost_paths.unwrap_or(1),
                     ]);
                 }
 
@@ -46,10 +55,15 @@ pub async fn list_selected_routes(
 
 pub async fn list_fallback_routes(
     server_addr: SocketAddr,
+    api_key: Option<&str>,
     json_print: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let request_url = format!("http://{server_addr}/api/v1/admin/routes/fallback");
-    match reqwest::get(&request_url).await {
+    match crate::with_api_key(reqwest::Client::new().get(&request_url), api_key)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+    {
         Err(e) => {
             error!("Failed to retrieve fallback routes");
             return Err(e.into());
@@ -65,11 +79,12 @@ pub async fn list_fallback_routes(
                 // Print routes in table format
                 let routes: Vec<Route> = resp.json().await?;
                 let mut table = Table::new();
-                table.add_row(row!["Subnet", "Next Hop", "Metric", "Seq No"]);
+                table.add_row(row!["Subnet", "Source", "Next Hop", "Metric", "Seq No"]);
 
                 for route in routes.iter() {
                     table.add_row(row![
                         &route.subnet,
+                        route.source_prefix.as_deref().unwrap_or("*"),
                         &route.next_hop,
                         route.metric,
                         route.seqno,
@@ -82,3 +97,108 @@ pub async fn list_fallback_routes(
     }
     Ok(())
 }
+
+pub async fn list_suppressed_routes(
+    server_addr: SocketAddr,
+    api_key: Option<&str>,
+    json_print: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request_url = format!("http://{server_addr}/api/v1/admin/routes/suppressed");
+    match crate::with_api_key(reqwest::Client::new().get(&request_url), api_key)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+    {
+        Err(e) => {
+            error!("Failed to retrieve suppressed routes");
+            return Err(e.into());
+        }
+        Ok(resp) => {
+            debug!("Listing suppressed routes");
+
+            if json_print {
+                // API call returns routes in JSON format by default
+                let suppressed_routes = resp.text().await?;
+                println!("{suppressed_routes}");
+            } else {
+                // Print routes in table format
+                let routes: Vec<SuppressedRoute> = resp.json().await?;
+                let mut table = Table::new();
+                table.add_row(row!["Subnet", "Source", "Next Hop", "Penalty"]);
+
+                for route in routes.iter() {
+                    table.add_row(row![
+                        &route.subnet,
+                        route.source_prefix.as_deref().unwrap_or("*"),
+                        &route.next_hop,
+                        route.penalty,
+                    ]);
+                }
+
+                table.printstd();
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn list_all_routes(
+    server_addr: SocketAddr,
+    api_key: Option<&str>,
+    subnet: Option<String>,
+    json_print: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request_url = format!("http://{server_addr}/api/v1/admin/routes/all");
+    match crate::with_api_key(
+        reqwest::Client::new()
+            .get(&request_url)
+            .query(&RouteTableQuery { subnet }),
+        api_key,
+    )
+    .send()
+    .await
+    .and_then(|res| res.error_for_status())
+    {
+        Err(e) => {
+            error!("Failed to retrieve routing table");
+            return Err(e.into());
+        }
+        Ok(resp) => {
+            debug!("Listing all routes");
+
+            if json_print {
+                // API call returns routes in JSON format by default
+                let routes = resp.text().await?;
+                println!("{routes}");
+            } else {
+                // Print routes in table format
+                let routes: Vec<RouteTableEntry> = resp.json().await?;
+                let mut table = Table::new();
+                table.add_row(row![
+                    "Subnet",
+                    "Source",
+                    "Next Hop",
+                    "Router ID",
+                    "Metric",
+                    "Seq No",
+                    "State"
+                ]);
+
+                for route in routes.iter() {
+                    table.add_row(row![
+                        &route.subnet,
+                        route.source_prefix.as_deref().unwrap_or("*"),
+                        &route.next_hop,
+                        &route.router_id,
+                        route.metric,
+                        route.seqno,
+                        route.state,
+                    ]);
+                }
+
+                table.printstd();
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,294 @@
+//! Async client for the HTTP API of a mycelium node.
+//!
+//! The request and response types are those of [`mycelium_api`], so they always match the API
+//! served by the node.
+
+use std::{fmt, net::IpAddr};
+
+use mycelium::{
+    crypto::PublicKey,
+    peer_manager::{PeerStats, RateLimit},
+};
+use mycelium_api::{
+    AddPeer, ErrorCode, Info, PeerListQuery, PeerMtu, ProblemDetails, PubKey, Route,
+    SuppressedRoute,
+};
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+/// Client for the HTTP API of a mycelium node.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    /// Base URL of the API, without trailing slash, e.g. `http://127.0.0.1:8989`.
+    base_url: String,
+    /// API key sent as bearer token with requests to the admin endpoints.
+    api_key: Option<String>,
+}
+
+/// Error returned by the [`Client`].
+#[derive(Debug)]
+pub enum Error {
+    /// A peer identified by the endpoint already exists.
+    PeerExists,
+    /// No peer identified by the endpoint exists.
+    PeerNotFound,
+    /// The node rejected the request as invalid, e.g. because an endpoint is malformed. Contains
+    /// the reason given by the node.
+    InvalidRequest(String),
+    /// The API key is missing or invalid.
+    Unauthorized,
+    /// The node returned a status which is not expected for the request, with the detail of the
+    /// error, or the body of the response if it does not describe the error.
+    UnexpectedStatus(StatusCode, String),
+    /// The request could not be sent, or the response could not be decoded.
+    Http(reqwest::Error),
+}
+
+impl Client {
+    /// Create a new `Client` for the API at `base_url`, e.g. `http://127.0.0.1:8989`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let mut base_url = base_url.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+
+        Self {
+            http: reqwest::Client::new(),
+            base_url,
+            api_key: None,
+        }
+    }
+
+    /// Send the given API key as bearer token with requests to the admin endpoints.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Get general info about the node.
+    pub async fn info(&self) -> Result<Info, Error> {
+        let response = self
+            .send(self.admin(self.http.get(self.url("/admin"))))
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// List the stats of the peers of the node, paginated and filtered according to `query`.
+    pub async fn list_peers(&self, query: &PeerListQuery) -> Result<Vec<PeerStats>, Error> {
+        let request = self.http.get(self.url("/admin/peers")).query(query);
+        let response = self.send(self.admin(request)).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Add a new peer identified by `endpoint`.
+    pub async fn add_peer(&self, endpoint: &str) -> Result<(), Error> {
+        let request = self.http.post(self.url("/admin/peers")).json(&AddPeer {
+            endpoint: endpoint.to_string(),
+        });
+        match self.send(self.admin(request)).await {
+            Err(Error::UnexpectedStatus(StatusCode::CONFLICT, _)) => Err(Error::PeerExists),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Remove the peer identified by `endpoint`.
+    pub async fn remove_peer(&self, endpoint: &str) -> Result<(), Error> {
+        let url = self.url(&format!("/admin/peers/{}", urlencoding::encode(endpoint)));
+        match self.send(self.admin(self.http.delete(url))).await {
+            Err(Error::UnexpectedStatus(StatusCode::NOT_FOUND, _)) => Err(Error::PeerNotFound),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Set the bandwidth limits of the data exchanged with the peer identified by `endpoint`.
+    pub async fn set_peer_rate_limit(&self, endpoint: &str, limit: RateLimit) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/admin/peers/{}/ratelimit",
+            urlencoding::encode(endpoint)
+        ));
+        match self.send(self.admin(self.http.put(url).json(&limit))).await {
+            Err(Error::UnexpectedStatus(StatusCode::NOT_FOUND, _)) => Err(Error::PeerNotFound),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Set the MTU of the link to the peer identified by `endpoint`, or remove the limit if
+    /// [`None`] is given.
+    pub async fn set_peer_mtu(&self, endpoint: &str, mtu: Option<u16>) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/admin/peers/{}/mtu",
+            urlencoding::encode(endpoint)
+        ));
+        match self
+            .send(self.admin(self.http.put(url).json(&PeerMtu { mtu })))
+            .await
+        {
+            Err(Error::UnexpectedStatus(StatusCode::NOT_FOUND, _)) => Err(Error::PeerNotFound),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Send all selected routes of the node to the connected peer identified by `endpoint` right
+    /// away.
+    pub async fn dump_routes_to_peer(&self, endpoint: &str) -> Result<(), Error> {
+        let url = self.url(&format!(
+            "/admin/peers/{}/routes/dump",
+            urlencoding::encode(endpoint)
+        ));
+        match self.send(self.admin(self.http.post(url))).await {
+            Err(Error::UnexpectedStatus(StatusCode::NOT_FOUND, _)) => Err(Error::PeerNotFound),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// List the selected routes of the node.
+    pub async fn selected_routes(&self) -> Result<Vec<Route>, Error> {
+        let request = self.http.get(self.url("/admin/routes/selected"));
+        let response = self.send(self.admin(request)).await?;
+        Ok(response.json().await?)
+    }
+
+    /// List the fallback routes of the node.
+    pub async fn fallback_routes(&self) -> Result<Vec<Route>, Error> {
+        let request = self.http.get(self.url("/admin/routes/fallback"));
+        let response = self.send(self.admin(request)).await?;
+        Ok(response.json().await?)
+    }
+
+    /// List the routes of the node which are suppressed because they changed too often.
+    pub async fn suppressed_routes(&self) -> Result<Vec<SuppressedRoute>, Error> {
+        let request = self.http.get(self.url("/admin/routes/suppressed"));
+        let response = self.send(self.admin(request)).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Get the public key of the node which owns `ip`. Returns [`None`] if the node does not know
+    /// a public key for the IP.
+    pub async fn pubkey_from_ip(&self, ip: IpAddr) -> Result<Option<PublicKey>, Error> {
+        let request = self.http.get(self.url(&format!("/pubkey/{ip}")));
+        match self.send(request).await {
+            Ok(response) => Ok(Some(response.json::<PubKey>().await?.public_key)),
+            Err(Error::UnexpectedStatus(StatusCode::NOT_FOUND, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the full URL of an API path.
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v1{path}", self.base_url)
+    }
+
+    /// Add the API key to a request to an admin endpoint, if one is configured.
+    fn admin(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    /// Send a request, and convert responses with an error status to an [`Error`].
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        // The body is only informational, so don't fail if it can't be read.
+        let body = response.text().await.unwrap_or_default();
+        Err(status_error(status, body))
+    }
+}
+
+/// Convert an error status returned by the API, and the body of the response, to an [`Error`].
+/// If the body holds [`ProblemDetails`], the error is derived from its code. Otherwise, statuses
+/// which only have a specific meaning for some requests are left to the caller.
+fn status_error(status: StatusCode, body: String) -> Error {
+    if let Ok(problem) = serde_json::from_str::<ProblemDetails>(&body) {
+        return match problem.code {
+            ErrorCode::InvalidRequest => Error::InvalidRequest(problem.detail),
+            ErrorCode::Unauthorized => Error::Unauthorized,
+            ErrorCode::PeerNotFound => Error::PeerNotFound,
+            ErrorCode::PeerExists => Error::PeerExists,
+            _ => Error::UnexpectedStatus(status, problem.detail),
+        };
+    }
+
+    match status {
+        StatusCode::BAD_REQUEST => Error::InvalidRequest(body),
+        StatusCode::UNAUTHORIZED => Error::Unauthorized,
+        _ => Error::UnexpectedStatus(status, body),
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PeerExists => f.write_str("A peer identified by that endpoint already exists"),
+            Self::PeerNotFound => f.write_str("A peer identified by that endpoint does not exist"),
+            Self::InvalidRequest(reason) => write!(f, "Invalid request: {reason}"),
+            Self::Unauthorized => f.write_str("Missing or invalid API key"),
+            Self::UnexpectedStatus(status, body) if body.is_empty() => {
+                write!(f, "Unexpected response status {status}")
+            }
+            Self::UnexpectedStatus(status, body) => {
+                write!(f, "Unexpected response status {status}: {body}")
+            }
+            Self::Http(e) => write!(f, "HTTP request failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::{status_error, Client, Error};
+
+    #[test]
+    fn urls_are_relative_to_base_url() {
+        let client = Client::new("http://127.0.0.1:8989/");
+        assert_eq!(
+            client.url("/admin/peers"),
+            "http://127.0.0.1:8989/api/v1/admin/peers"
+        );
+    }
+
+    #[test]
+    fn error_statuses_are_mapped() {
+        assert!(matches!(
+            status_error(StatusCode::BAD_REQUEST, "Missing port".to_string()),
+            Error::InvalidRequest(reason) if reason == "Missing port"
+        ));
+        assert!(matches!(
+            status_error(StatusCode::UNAUTHORIZED, String::new()),
+            Error::Unauthorized
+        ));
+        assert!(matches!(
+            status_error(StatusCode::CONFLICT, String::new()),
+            Error::UnexpectedStatus(StatusCode::CONFLICT, _)
+        ));
+
+        // Problem details are mapped by their code.
+        let problem = r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"No peer","code":"peer_not_found"}"#;
+        assert!(matches!(
+            status_error(StatusCode::NOT_FOUND, problem.to_string()),
+            Error::PeerNotFound
+        ));
+    }
+}
@@ -8,4 +8,4 @@ pub use noop::NoMetrics;
 #[cfg(feature = "prometheus")]
 mod prometheus;
 #[cfg(feature = "prometheus")]
-pub use prometheus::PrometheusExporter;
+pub use prometheus::{encode_metrics, PrometheusExporter};
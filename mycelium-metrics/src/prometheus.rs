@@ -1,8 +1,11 @@
 use axum::{routing::get, Router};
-use mycelium::metrics::Metrics;
+use mycelium::{
+    metrics::{DropReason, Metrics},
+    peer_manager::InboundRejected,
+};
 use prometheus::{
-    opts, register_int_counter, register_int_counter_vec, register_int_gauge, Encoder, IntCounter,
-    IntCounterVec, IntGauge, TextEncoder,
+    opts, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
 };
 use tracing::{error, info};
 
@@ -12,6 +15,10 @@ use std::net::SocketAddr;
 #[derive(Clone)]
 pub struct PrometheusExporter {
     router_processed_tlvs: IntCounterVec,
+    router_update_denied_by_filter: IntCounter,
+    router_update_origin_rejected: IntCounter,
+    router_route_evicted: IntCounter,
+    router_route_refused: IntCounter,
     router_peer_added: IntCounter,
     router_peer_removed: IntCounter,
     router_peer_died: IntCounter,
@@ -21,15 +28,25 @@ pub struct PrometheusExporter {
     router_selected_route_expired: IntCounter,
     router_triggered_update: IntCounter,
     router_route_packet: IntCounterVec,
+    packets_dropped: IntCounterVec,
     router_seqno_action: IntCounterVec,
     router_tlv_handling_time_spent: IntCounterVec,
     router_update_dead_peer: IntCounter,
     router_received_tlvs: IntCounter,
     router_tlv_source_died: IntCounter,
+    router_route_dumps_skipped: IntCounter,
     router_propage_selected_peers_time_spent: IntCounter,
     peer_manager_peer_added: IntCounterVec,
     peer_manager_known_peers: IntGauge,
     peer_manager_connection_attemps: IntCounterVec,
+    peer_manager_peer_tx_bytes: IntGaugeVec,
+    peer_manager_peer_rx_bytes: IntGaugeVec,
+    peer_manager_inbound_connections_rejected: IntCounterVec,
+    peer_manager_duplicate_connections_closed: IntCounter,
+    peer_manager_peer_authentications_failed: IntCounterVec,
+    data_plane_reassembly_failed: IntCounter,
+    data_plane_compression_input_bytes: IntCounter,
+    data_plane_compression_output_bytes: IntCounter,
 }
 
 impl PrometheusExporter {
@@ -42,6 +59,22 @@ impl PrometheusExporter {
                     "Amount of processed TLV's from peers, by type of TLV"
                 ), &["tlv_type"]
             ).expect("Can register int counter vec in default registry"),
+            router_update_denied_by_filter: register_int_counter!(
+                "mycelium_router_update_denied_by_filter",
+                "Amount of received updates which were dropped because an update filter denied them"
+            ).expect("Can register int counter in default registry"),
+            router_update_origin_rejected: register_int_counter!(
+                "mycelium_router_update_origin_rejected",
+                "Amount of received updates which were dropped because they announce a subnet which is not owned by their origin"
+            ).expect("Can register int counter in default registry"),
+            router_route_evicted: register_int_counter!(
+                "mycelium_router_route_evicted",
+                "Amount of routes evicted from the full routing table to make room for a more preferred route"
+            ).expect("Can register int counter in default registry"),
+            router_route_refused: register_int_counter!(
+                "mycelium_router_route_refused",
+                "Amount of new routes which were not learned because the routing table is full"
+            ).expect("Can register int counter in default registry"),
             router_peer_added: register_int_counter!(
                 "mycelium_router_peer_added",
                 "Amount of times a peer was added to the router"
@@ -89,6 +122,14 @@ impl PrometheusExporter {
                 &["verdict"],
             )
             .expect("Can register int counter vec in default registry"),
+            packets_dropped: register_int_counter_vec!(
+                opts!(
+                    "mycelium_packets_dropped",
+                    "Amount of data packets which were dropped, by reason"
+                ),
+                &["reason"],
+            )
+            .expect("Can register int counter vec in default registry"),
             router_seqno_action: register_int_counter_vec!(
                 opts!(
                     "mycelium_router_seqno_handling",
@@ -120,6 +161,11 @@ impl PrometheusExporter {
                 "Dropped TLV's which have been received, but where the peer has died before they could be processed",
             )
             .expect("Can register an int counter in default registry"),
+            router_route_dumps_skipped: register_int_counter!(
+                "mycelium_router_route_dumps_skipped",
+                "Dumps of the selected routes which were not sent to a peer because many control packets were still queued for it",
+            )
+            .expect("Can register an int counter in default registry"),
             router_propage_selected_peers_time_spent: register_int_counter!(
                 "mycelium_router_propagate_selected_route_time",
                 "Time spent in the propagate_selected_route task, which periodically announces selected routes to peers. Measurement is in nanoseconds",
@@ -146,6 +192,58 @@ impl PrometheusExporter {
                 &["connection_state"]
             )
             .expect("Can register int counter vec in the default registry"),
+            peer_manager_peer_tx_bytes: register_int_gauge_vec!(
+                opts!(
+                    "mycelium_peer_manager_peer_tx_bytes",
+                    "Amount of bytes sent to a connected peer, by endpoint"
+                ),
+                &["endpoint"]
+            )
+            .expect("Can register int gauge vec in the default registry"),
+            peer_manager_peer_rx_bytes: register_int_gauge_vec!(
+                opts!(
+                    "mycelium_peer_manager_peer_rx_bytes",
+                    "Amount of bytes received from a connected peer, by endpoint"
+                ),
+                &["endpoint"]
+            )
+            .expect("Can register int gauge vec in the default registry"),
+            peer_manager_inbound_connections_rejected: register_int_counter_vec!(
+                opts!(
+                    "mycelium_peer_manager_inbound_connections_rejected",
+                    "Inbound connections rejected by the peer manager, by reason"
+                ),
+                &["reason"]
+            )
+            .expect("Can register int counter vec in the default registry"),
+            peer_manager_duplicate_connections_closed: register_int_counter!(
+                "mycelium_peer_manager_duplicate_connections_closed",
+                "Amount of connections closed because there is another connection to the same node"
+            )
+            .expect("Can register int counter in the default registry"),
+            peer_manager_peer_authentications_failed: register_int_counter_vec!(
+                opts!(
+                    "mycelium_peer_manager_peer_authentications_failed",
+                    "Connections closed because the remote does not know the peering key, by direction"
+                ),
+                &["direction"]
+            )
+            .expect("Can register int counter vec in the default registry"),
+            data_plane_reassembly_failed: register_int_counter!(
+                "mycelium_data_plane_reassembly_failed",
+                "Amount of fragmented payloads which could not be reassembled, and were discarded"
+            )
+            .expect("Can register int counter in default registry"),
+            data_plane_compression_input_bytes: register_int_counter!(
+                "mycelium_data_plane_compression_input_bytes",
+                "Amount of bytes of data the data plane tried to compress"
+            )
+            .expect("Can register int counter in default registry"),
+            data_plane_compression_output_bytes: register_int_counter!(
+                "mycelium_data_plane_compression_output_bytes",
+                "Amount of bytes of data sent after compression by the data plane"
+            )
+            .expect("Can register int counter in default registry"),
         }
     }
 
@@ -174,6 +272,11 @@ impl PrometheusExporter {
 
 /// Expose prometheus formatted metrics
 async fn serve_metrics() -> String {
+    encode_metrics()
+}
+
+/// Encode all metrics gathered in the default registry in the prometheus text format.
+pub fn encode_metrics() -> String {
     let mut buffer = Vec::new();
     let encoder = TextEncoder::new();
 
@@ -223,6 +326,26 @@ impl Metrics for PrometheusExporter {
             .inc()
     }
 
+    #[inline]
+    fn router_update_denied_by_filter(&self) {
+        self.router_update_denied_by_filter.inc()
+    }
+
+    #[inline]
+    fn router_update_origin_rejected(&self) {
+        self.router_update_origin_rejected.inc()
+    }
+
+    #[inline]
+    fn router_route_evicted(&self) {
+        self.router_route_evicted.inc()
+    }
+
+    #[inline]
+    fn router_route_refused(&self) {
+        self.router_route_refused.inc()
+    }
+
     #[inline]
     fn router_peer_added(&self) {
         self.router_peer_added.inc()
@@ -290,6 +413,20 @@ impl Metrics for PrometheusExporter {
             .inc()
     }
 
+    #[inline]
+    fn router_route_packet_queue_full(&self) {
+        self.router_route_packet
+            .with_label_values(&["queue_full"])
+            .inc()
+    }
+
+    #[inline]
+    fn packet_dropped(&self, reason: DropReason) {
+        self.packets_dropped
+            .with_label_values(&[reason.as_str()])
+            .inc()
+    }
+
     #[inline]
     fn router_seqno_request_reply_local(&self) {
         self.router_seqno_action
@@ -354,6 +491,11 @@ impl Metrics for PrometheusExporter {
         self.router_tlv_source_died.inc()
     }
 
+    #[inline]
+    fn router_route_dump_skipped(&self) {
+        self.router_route_dumps_skipped.inc()
+    }
+
     #[inline]
     fn router_time_spent_periodic_propagating_selected_routes(
         &self,
@@ -393,6 +535,59 @@ impl Metrics for PrometheusExporter {
             .with_label_values(&["finished"])
             .inc()
     }
+
+    #[inline]
+    fn peer_manager_peer_traffic(&self, traffic: &[(String, u64, u64)]) {
+        // Clear previous values first so peers which are no longer connected don't linger.
+        self.peer_manager_peer_tx_bytes.reset();
+        self.peer_manager_peer_rx_bytes.reset();
+        for (endpoint, tx_bytes, rx_bytes) in traffic {
+            self.peer_manager_peer_tx_bytes
+                .with_label_values(&[endpoint])
+                .set(*tx_bytes as i64);
+            self.peer_manager_peer_rx_bytes
+                .with_label_values(&[endpoint])
+                .set(*rx_bytes as i64);
+        }
+    }
+
+    #[inline]
+    fn peer_manager_inbound_connection_rejected(&self, reason: InboundRejected) {
+        self.peer_manager_inbound_connections_rejected
+            .with_label_values(&[reason.as_str()])
+            .inc()
+    }
+
+    #[inline]
+    fn peer_manager_inbound_connection_denied(&self) {
+        self.peer_manager_inbound_connections_rejected
+            .with_label_values(&["access_list"])
+            .inc()
+    }
+
+    #[inline]
+    fn peer_manager_duplicate_connection_closed(&self) {
+        self.peer_manager_duplicate_connections_closed.inc()
+    }
+
+    #[inline]
+    fn peer_manager_peer_authentication_failed(&self, inbound: bool) {
+        self.peer_manager_peer_authentications_failed
+            .with_label_values(&[if inbound { "inbound" } else { "outbound" }])
+            .inc()
+    }
+
+    #[inline]
+    fn data_plane_reassembly_failed(&self) {
+        self.data_plane_reassembly_failed.inc()
+    }
+
+    fn data_plane_payload_compressed(&self, original_size: usize, compressed_size: usize) {
+        self.data_plane_compression_input_bytes
+            .inc_by(original_size as u64);
+        self.data_plane_compression_output_bytes
+            .inc_by(compressed_size as u64);
+    }
 }
 
 impl Default for PrometheusExporter {
@@ -0,0 +1,99 @@
+//! Measure the cost of forwarding data packets: decoding them from the read buffer of one
+//! connection, and encoding them in the write buffer of the next connection. Both the time and
+//! the amount of heap allocations per forwarded packet are reported.
+//!
+//! Run with `cargo bench -p mycelium --bench forward_packet`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    net::Ipv6Addr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use bytes::BytesMut;
+use mycelium::packet::{Codec, DataPacket, Packet};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Amount of packets forwarded per round.
+const PACKETS: usize = 10_000;
+/// Amount of rounds.
+const ROUNDS: usize = 10;
+/// Size of the encrypted data in a packet, this is a full sized packet including the AES tag and
+/// nonce.
+const RAW_DATA_SIZE: usize = 1400 + 4 + 16 + 12;
+
+/// Allocator which counts the amount of allocations, and otherwise defers to the system
+/// allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let src_ip = Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1);
+    let dst_ip = Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 2);
+
+    let mut codec = Codec::new();
+    let mut allocations = 0;
+    let mut elapsed = std::time::Duration::ZERO;
+
+    for _ in 0..ROUNDS {
+        // Wire representation of the packets, as if they are read from a connection.
+        let mut read_buf = BytesMut::with_capacity(PACKETS * (RAW_DATA_SIZE + 40));
+        for _ in 0..PACKETS {
+            codec
+                .encode(
+                    Packet::DataPacket(DataPacket {
+                        raw_data: BytesMut::from(&[0x42; RAW_DATA_SIZE][..]),
+                        hop_limit: 64,
+                        flow: 0,
+                        src_ip,
+                        dst_ip,
+                    }),
+                    &mut read_buf,
+                )
+                .expect("Can encode data packet");
+        }
+        let mut write_buf = BytesMut::with_capacity(read_buf.len());
+
+        let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        let mut forwarded = 0;
+        while let Some(packet) = codec.decode(&mut read_buf).expect("Can decode data packet") {
+            codec
+                .encode(packet, &mut write_buf)
+                .expect("Can encode data packet");
+            forwarded += 1;
+        }
+        elapsed += start.elapsed();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+
+        assert_eq!(forwarded, PACKETS);
+    }
+
+    let packets = PACKETS * ROUNDS;
+    println!(
+        "Forwarded {packets} packets of {RAW_DATA_SIZE} bytes in {elapsed:?}: {:?} and {:.3} allocations per packet",
+        elapsed / packets as u32,
+        allocations as f64 / packets as f64,
+    );
+}
@@ -0,0 +1,85 @@
+//! Lists of allowed and denied subnets, used to restrict which underlay IPs can connect to the
+//! node, and for which overlay subnets routes are accepted.
+
+use std::net::IpAddr;
+
+use crate::subnet::Subnet;
+
+/// A list of allowed and denied [`subnets`](Subnet).
+///
+/// Denied subnets take precedence over allowed subnets. If no subnets are allowed explicitly,
+/// everything which is not denied is allowed. An empty `AccessList` thus allows everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessList {
+    /// Allowed subnets. If this is not empty, only these subnets are allowed.
+    pub allow: Vec<Subnet>,
+    /// Denied subnets.
+    pub deny: Vec<Subnet>,
+}
+
+impl AccessList {
+    /// Checks if the `AccessList` allows the given [`IpAddr`]. IPv4 mapped IPv6 addresses are
+    /// checked as the IPv4 address they map to.
+    pub fn allows_ip(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        !self.deny.iter().any(|subnet| subnet.contains_ip(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|subnet| subnet.contains_ip(ip)))
+    }
+
+    /// Checks if the `AccessList` allows the given [`Subnet`]. A `Subnet` is only allowed if it is
+    /// fully contained in an allowed subnet, and does not overlap with any denied subnet.
+    pub fn allows_subnet(&self, subnet: &Subnet) -> bool {
+        !self
+            .deny
+            .iter()
+            .any(|denied| denied.contains_subnet(subnet) || subnet.contains_subnet(denied))
+            && (self.allow.is_empty()
+                || self
+                    .allow
+                    .iter()
+                    .any(|allowed| allowed.contains_subnet(subnet)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::AccessList;
+
+    #[test]
+    fn empty_list_allows_everything() {
+        let list = AccessList::default();
+
+        assert!(list.allows_ip(Ipv4Addr::new(203, 0, 113, 2).into()));
+        assert!(list.allows_subnet(&"400::/64".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let list = AccessList {
+            allow: vec!["203.0.113.0/24".parse().unwrap()],
+            deny: vec!["203.0.113.128/25".parse().unwrap()],
+        };
+
+        assert!(list.allows_ip(Ipv4Addr::new(203, 0, 113, 2).into()));
+        assert!(!list.allows_ip(Ipv4Addr::new(203, 0, 113, 200).into()));
+        assert!(!list.allows_ip(Ipv4Addr::new(198, 51, 100, 2).into()));
+        // IPv4 mapped addresses, as reported by dual stack sockets.
+        assert!(!list.allows_ip(IpAddr::V6(Ipv4Addr::new(203, 0, 113, 200).to_ipv6_mapped())));
+        assert!(!list.allows_ip(Ipv6Addr::LOCALHOST.into()));
+    }
+
+    #[test]
+    fn subnets_overlapping_denied_subnet_are_denied() {
+        let list = AccessList {
+            allow: vec!["400::/7".parse().unwrap()],
+            deny: vec!["400:1::/32".parse().unwrap()],
+        };
+
+        assert!(list.allows_subnet(&"400:2::/64".parse().unwrap()));
+        assert!(!list.allows_subnet(&"400:1::/64".parse().unwrap()));
+        assert!(!list.allows_subnet(&"400::/16".parse().unwrap()));
+        assert!(!list.allows_subnet(&"200::/7".parse().unwrap()));
+    }
+}
@@ -4,12 +4,14 @@
 //! our specific use case. For reference, the implementation is based on [this
 //! RFC](https://datatracker.ietf.org/doc/html/rfc8966).
 
-use std::io;
+use std::{io, net::IpAddr};
 
 use bytes::{Buf, BufMut};
 use tokio_util::codec::{Decoder, Encoder};
 use tracing::trace;
 
+use crate::subnet::Subnet;
+
 pub use self::{
     hello::Hello, ihu::Ihu, route_request::RouteRequest, seqno_request::SeqNoRequest,
     update::Update,
@@ -31,6 +33,13 @@ const BABEL_VERSION: u8 = 2;
 
 /// Size of a babel header on the wire.
 const HEADER_WIRE_SIZE: usize = 4;
+/// Size of the header of a TLV on the wire.
+const TLV_HEADER_WIRE_SIZE: usize = 2;
+
+/// Maximum size of the body of a babel packet holding multiple TLVs. This leaves room for the
+/// packet headers and the headers of the transport, so a packed packet fits in a single IPv6
+/// packet on links with the minimum MTU of 1280 bytes.
+pub const MAX_PACKED_BODY_SIZE: usize = 1200;
 
 /// TLV type for the [`Hello`] tlv
 const TLV_TYPE_HELLO: u8 = 4;
@@ -43,6 +52,15 @@ const TLV_TYPE_ROUTE_REQUEST: u8 = 9;
 /// TLV type for the [`SeqNoRequest`] tlv
 const TLV_TYPE_SEQNO_REQUEST: u8 = 10;
 
+/// Sub-TLV type for the Pad1 sub-TLV, which is a single byte of padding.
+const SUB_TLV_TYPE_PAD1: u8 = 0;
+/// Sub-TLV type for the source prefix sub-TLV, as defined in [RFC
+/// 9079](https://datatracker.ietf.org/doc/html/rfc9079#name-source-prefix-sub-tlv).
+const SUB_TLV_TYPE_SOURCE_PREFIX: u8 = 128;
+/// Sub-TLV types with this bit set are mandatory. A TLV holding a mandatory sub-TLV which is not
+/// understood must be ignored entirely.
+const SUB_TLV_MANDATORY_BIT: u8 = 0x80;
+
 /// Wildcard address, the value is empty (0 bytes length).
 const AE_WILDCARD: u8 = 0;
 /// IPv4 address, the value is _at most_ 4 bytes long.
@@ -56,20 +74,174 @@ const AE_IPV6_LL: u8 = 3;
 #[derive(Debug, Clone)]
 pub struct Codec {
     header: Option<Header>,
+    /// Amount of bytes of the body of the current packet which are not decoded yet.
+    body_remaining: usize,
+    /// Set if the last call to [`Decoder::decode`] consumed the last bytes of a packet.
+    packet_complete: bool,
 }
 
 impl Codec {
     /// Create a new `BabelCodec`.
     pub fn new() -> Self {
-        Self { header: None }
+        Self {
+            header: None,
+            body_remaining: 0,
+            packet_complete: false,
+        }
     }
 
     /// Resets the `BabelCodec` to its default state.
     pub fn reset(&mut self) {
         self.header = None;
+        self.body_remaining = 0;
+    }
+
+    /// Checks if the last call to [`Decoder::decode`] consumed the last bytes of a packet. If it
+    /// returned `Ok(None)`, this means the packet did not contain any usable TLV.
+    pub fn packet_complete(&self) -> bool {
+        self.packet_complete
+    }
+
+    /// Encode multiple TLVs in a single packet. The caller must make sure the TLVs fit in a single
+    /// packet, see [`pack`].
+    pub fn encode_packed(&mut self, items: Vec<Tlv>, dst: &mut bytes::BytesMut) -> io::Result<()> {
+        let body_length = items
+            .iter()
+            .map(|item| item.wire_size() as usize + TLV_HEADER_WIRE_SIZE)
+            .sum::<usize>();
+        if body_length > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TLVs don't fit in a single babel packet",
+            ));
+        }
+
+        // Write header
+        dst.put_u8(BABEL_MAGIC);
+        dst.put_u8(BABEL_VERSION);
+        dst.put_u16(body_length as u16);
+
+        for item in items {
+            // TLV header
+            match item {
+                Tlv::Hello(_) => dst.put_u8(TLV_TYPE_HELLO),
+                Tlv::Ihu(_) => dst.put_u8(TLV_TYPE_IHU),
+                Tlv::Update(_) => dst.put_u8(TLV_TYPE_UPDATE),
+                Tlv::RouteRequest(_) => dst.put_u8(TLV_TYPE_ROUTE_REQUEST),
+                Tlv::SeqNoRequest(_) => dst.put_u8(TLV_TYPE_SEQNO_REQUEST),
+            }
+            dst.put_u8(item.wire_size());
+            item.write_bytes(dst);
+        }
+
+        Ok(())
     }
 }
 
+/// Split `tlvs` in groups which each fit in the body of a single babel packet of at most
+/// [`MAX_PACKED_BODY_SIZE`] bytes. The order of the TLVs is preserved.
+pub fn pack(tlvs: impl IntoIterator<Item = Tlv>) -> Vec<Vec<Tlv>> {
+    let mut packets: Vec<Vec<Tlv>> = Vec::new();
+    let mut body_size = 0;
+    for tlv in tlvs {
+        let tlv_size = tlv.wire_size() as usize + TLV_HEADER_WIRE_SIZE;
+        match packets.last_mut() {
+            Some(packet) if body_size + tlv_size <= MAX_PACKED_BODY_SIZE => {
+                body_size += tlv_size;
+                packet.push(tlv);
+            }
+            _ => {
+                body_size = tlv_size;
+                packets.push(vec![tlv]);
+            }
+        }
+    }
+    packets
+}
+
+/// Size on the wire of a source prefix sub-TLV for the given source prefix, including the sub-TLV
+/// header. This is 0 if there is no source prefix.
+fn source_prefix_sub_tlv_wire_size(source_prefix: Option<Subnet>) -> u8 {
+    source_prefix
+        .map(|sp| TLV_HEADER_WIRE_SIZE as u8 + 1 + (sp.prefix_len() + 7) / 8)
+        .unwrap_or_default()
+}
+
+/// Encode a source prefix sub-TLV for the given source prefix, if there is one. The source prefix
+/// must have the same address family as the destination prefix of the TLV.
+fn write_source_prefix_sub_tlv(source_prefix: Option<Subnet>, dst: &mut bytes::BytesMut) {
+    let Some(source_prefix) = source_prefix else {
+        return;
+    };
+    let prefix_len = ((source_prefix.prefix_len() + 7) / 8) as usize;
+    dst.put_u8(SUB_TLV_TYPE_SOURCE_PREFIX);
+    dst.put_u8(1 + prefix_len as u8);
+    dst.put_u8(source_prefix.prefix_len());
+    match source_prefix.address() {
+        IpAddr::V4(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
+        IpAddr::V6(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
+    }
+}
+
+/// Read the sub-TLVs in the last `len` bytes of a TLV body, and return the source prefix if there
+/// is a source prefix sub-TLV. The source prefix is decoded in the address family of `ipv4`. This
+/// always consumes `len` bytes.
+///
+/// Returns [`None`] if the TLV must be ignored, because a sub-TLV is malformed, the source prefix
+/// is present more than once, or there is a mandatory sub-TLV which is not understood.
+fn read_source_prefix_sub_tlv(
+    src: &mut bytes::BytesMut,
+    len: usize,
+    ipv4: bool,
+) -> Option<Option<Subnet>> {
+    let mut sub_tlvs = src.split_to(len);
+    let mut source_prefix = None;
+    while sub_tlvs.has_remaining() {
+        let sub_tlv_type = sub_tlvs.get_u8();
+        if sub_tlv_type == SUB_TLV_TYPE_PAD1 {
+            continue;
+        }
+        if !sub_tlvs.has_remaining() {
+            return None;
+        }
+        let sub_tlv_len = sub_tlvs.get_u8() as usize;
+        if sub_tlv_len > sub_tlvs.remaining() {
+            return None;
+        }
+        let mut body = sub_tlvs.split_to(sub_tlv_len);
+        match sub_tlv_type {
+            SUB_TLV_TYPE_SOURCE_PREFIX => {
+                if source_prefix.is_some() || !body.has_remaining() {
+                    return None;
+                }
+                let plen = body.get_u8();
+                let prefix_size = ((plen + 7) / 8) as usize;
+                if plen > if ipv4 { 32 } else { 128 } || prefix_size != body.remaining() {
+                    return None;
+                }
+                let address = if ipv4 {
+                    let mut raw_ip = [0; 4];
+                    raw_ip[..prefix_size].copy_from_slice(&body);
+                    IpAddr::from(raw_ip)
+                } else {
+                    let mut raw_ip = [0; 16];
+                    raw_ip[..prefix_size].copy_from_slice(&body);
+                    IpAddr::from(raw_ip)
+                };
+                source_prefix = Some(Subnet::new(address, plen).ok()?);
+            }
+            t if t & SUB_TLV_MANDATORY_BIT != 0 => {
+                trace!("Ignoring tlv with unknown mandatory sub tlv {t}");
+                return None;
+            }
+            // Unknown sub-TLVs which are not mandatory, including PadN, are skipped.
+            _ => {}
+        }
+    }
+
+    Some(source_prefix)
+}
+
 /// The header for a babel packet. This follows the definition of the header [in the
 /// RFC](https://datatracker.ietf.org/doc/html/rfc8966#name-packet-format). Since the header
 /// contains only hard-coded fields and the length of an encoded body, there is no need for users
@@ -90,69 +262,104 @@ impl Decoder for Codec {
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // Read a header if we don't have one yet.
-        let header = if let Some(header) = self.header.take() {
-            trace!("Continue from stored header");
-            header
-        } else {
-            if src.remaining() < HEADER_WIRE_SIZE {
-                trace!("Insufficient bytes to read a babel header");
+        self.packet_complete = false;
+
+        if self.body_remaining == 0 {
+            // Read a header if we don't have one yet.
+            let header = if let Some(header) = self.header.take() {
+                trace!("Continue from stored header");
+                header
+            } else {
+                if src.remaining() < HEADER_WIRE_SIZE {
+                    trace!("Insufficient bytes to read a babel header");
+                    return Ok(None);
+                }
+
+                trace!("Read babel header");
+
+                Header {
+                    magic: src.get_u8(),
+                    version: src.get_u8(),
+                    body_length: src.get_u16(),
+                }
+            };
+
+            if src.remaining() < header.body_length as usize {
+                trace!("Insufficient bytes to read babel body");
+                self.header = Some(header);
                 return Ok(None);
             }
 
-            trace!("Read babel header");
-
-            Header {
-                magic: src.get_u8(),
-                version: src.get_u8(),
-                body_length: src.get_u16(),
+            // Siltently ignore packets which don't have the correct values set, as defined in the
+            // spec. Note that we consume the amount of bytes indentified so we leave the parser in
+            // the correct state for the next packet.
+            if header.magic != BABEL_MAGIC || header.version != BABEL_VERSION {
+                trace!("Dropping babel packet with wrong magic or version");
+                src.advance(header.body_length as usize);
+                self.reset();
+                self.packet_complete = true;
+                return Ok(None);
             }
-        };
-
-        if src.remaining() < header.body_length as usize {
-            trace!("Insufficient bytes to read babel body");
-            self.header = Some(header);
-            return Ok(None);
-        }
 
-        // Siltently ignore packets which don't have the correct values set, as defined in the
-        // spec. Note that we consume the amount of bytes indentified so we leave the parser in the
-        // correct state for the next packet.
-        if header.magic != BABEL_MAGIC || header.version != BABEL_VERSION {
-            trace!("Dropping babel packet with wrong magic or version");
-            src.advance(header.body_length as usize);
-            self.reset();
-            return Ok(None);
+            self.body_remaining = header.body_length as usize;
         }
 
-        // at this point we have a whole body loaded in the buffer. We currently don't support sub
-        // TLV's
-
-        trace!("Read babel TLV body");
-
-        // TODO: Technically we need to loop here as we can have multiple TLVs.
+        // At this point we have a whole body loaded in the buffer, which can hold multiple TLVs.
+        while self.body_remaining >= TLV_HEADER_WIRE_SIZE {
+            trace!("Read babel TLV body");
+
+            // TLV header
+            let tlv_type = src.get_u8();
+            let body_len = src.get_u8();
+            self.body_remaining -= TLV_HEADER_WIRE_SIZE;
+            if body_len as usize > self.body_remaining {
+                trace!("Dropping truncated tlv");
+                break;
+            }
 
-        // TLV header
-        let tlv_type = src.get_u8();
-        let body_len = src.get_u8();
-        // TLV payload
-        let tlv = match tlv_type {
-            TLV_TYPE_HELLO => Some(Hello::from_bytes(src).into()),
-            TLV_TYPE_IHU => Ihu::from_bytes(src, body_len).map(From::from),
-            TLV_TYPE_UPDATE => Update::from_bytes(src, body_len).map(From::from),
-            TLV_TYPE_ROUTE_REQUEST => RouteRequest::from_bytes(src, body_len).map(From::from),
-            TLV_TYPE_SEQNO_REQUEST => SeqNoRequest::from_bytes(src, body_len).map(From::from),
-            _ => {
-                // unrecoginized body type, silently drop
-                trace!("Dropping unrecognized tlv");
-                // We already read 2 bytes
-                src.advance(header.body_length as usize - 2);
-                self.reset();
-                return Ok(None);
+            // TLV payload
+            let before = src.remaining();
+            let tlv = match tlv_type {
+                TLV_TYPE_HELLO => Some(Hello::from_bytes(src).into()),
+                TLV_TYPE_IHU => Ihu::from_bytes(src, body_len).map(From::from),
+                TLV_TYPE_UPDATE => Update::from_bytes(src, body_len).map(From::from),
+                TLV_TYPE_ROUTE_REQUEST => RouteRequest::from_bytes(src, body_len).map(From::from),
+                TLV_TYPE_SEQNO_REQUEST => SeqNoRequest::from_bytes(src, body_len).map(From::from),
+                _ => {
+                    // unrecoginized body type, silently drop
+                    trace!("Dropping unrecognized tlv");
+                    None
+                }
+            };
+
+            // Skip the part of the TLV which was not parsed, so the next TLV is read from the
+            // right offset.
+            let consumed = before - src.remaining();
+            if consumed > body_len as usize {
+                trace!("Tlv parser read past the end of the tlv, dropping rest of the packet");
+                self.body_remaining = self
+                    .body_remaining
+                    .saturating_sub(consumed - body_len as usize);
+                break;
             }
-        };
+            src.advance(body_len as usize - consumed);
+            self.body_remaining -= body_len as usize;
+
+            if let Some(tlv) = tlv {
+                if self.body_remaining < TLV_HEADER_WIRE_SIZE {
+                    src.advance(self.body_remaining);
+                    self.reset();
+                    self.packet_complete = true;
+                }
+                return Ok(Some(tlv));
+            }
+        }
 
-        Ok(tlv)
+        // Drop whatever is left of the body.
+        src.advance(self.body_remaining);
+        self.reset();
+        self.packet_complete = true;
+        Ok(None)
     }
 }
 
@@ -160,25 +367,7 @@ impl Encoder<Tlv> for Codec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Tlv, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
-        // Write header
-        dst.put_u8(BABEL_MAGIC);
-        dst.put_u8(BABEL_VERSION);
-        dst.put_u16(item.wire_size() as u16 + 2); // tlv payload + tlv header
-
-        // Write TLV's, TODO: currently only 1 TLV/body
-
-        // TLV header
-        match item {
-            Tlv::Hello(_) => dst.put_u8(TLV_TYPE_HELLO),
-            Tlv::Ihu(_) => dst.put_u8(TLV_TYPE_IHU),
-            Tlv::Update(_) => dst.put_u8(TLV_TYPE_UPDATE),
-            Tlv::RouteRequest(_) => dst.put_u8(TLV_TYPE_ROUTE_REQUEST),
-            Tlv::SeqNoRequest(_) => dst.put_u8(TLV_TYPE_SEQNO_REQUEST),
-        }
-        dst.put_u8(item.wire_size());
-        item.write_bytes(dst);
-
-        Ok(())
+        self.encode_packed(vec![item], dst)
     }
 }
 
@@ -187,6 +376,7 @@ mod tests {
     use std::{net::Ipv6Addr, time::Duration};
 
     use futures::{SinkExt, StreamExt};
+    use tokio::io::AsyncWriteExt;
     use tokio_util::codec::Framed;
 
     use crate::subnet::Subnet;
@@ -254,13 +444,46 @@ mod tests {
             .send(update.clone().into())
             .await
             .expect("Send on a non-networked buffer can never fail; qed");
-        println!("Sent update packet");
         let recv_update = receiver
             .next()
             .await
             .expect("Buffer isn't closed so this is always `Some`; qed")
             .expect("Can decode the previously encoded value");
-        println!("Received update packet");
+        assert_eq!(super::Tlv::from(update), recv_update);
+    }
+
+    #[tokio::test]
+    async fn codec_source_specific_update() {
+        let (tx, rx) = tokio::io::duplex(1024);
+        let mut sender = Framed::new(tx, super::Codec::new());
+        let mut receiver = Framed::new(rx, super::Codec::new());
+
+        let update = super::Update::new(
+            Duration::from_secs(400),
+            16.into(),
+            25.into(),
+            Subnet::new(Ipv6Addr::new(0x400, 1, 2, 3, 0, 0, 0, 0).into(), 64)
+                .expect("64 is a valid IPv6 prefix size; qed"),
+            [
+                1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
+                24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40,
+            ]
+            .into(),
+        )
+        .with_source_prefix(Some(
+            Subnet::new(Ipv6Addr::new(0x500, 0xab, 0, 0, 0, 0, 0, 0).into(), 32)
+                .expect("32 is a valid IPv6 prefix size; qed"),
+        ));
+
+        sender
+            .send(update.clone().into())
+            .await
+            .expect("Send on a non-networked buffer can never fail; qed");
+        let recv_update = receiver
+            .next()
+            .await
+            .expect("Buffer isn't closed so this is always `Some`; qed")
+            .expect("Can decode the previously encoded value");
         assert_eq!(super::Tlv::from(update), recv_update);
     }
 
@@ -315,4 +538,50 @@ mod tests {
             .expect("Can decode the previously encoded value");
         assert_eq!(super::Tlv::from(rr), recv_update);
     }
+
+    #[tokio::test]
+    async fn codec_packed_tlvs() {
+        let (tx, rx) = tokio::io::duplex(1024);
+        let mut sender = Framed::new(tx, super::Codec::new());
+        let mut receiver = Framed::new(rx, super::Codec::new());
+
+        let tlvs: Vec<super::Tlv> = vec![
+            super::Hello::new_unicast(15.into(), 400).into(),
+            super::Ihu::new(27.into(), 400, None).into(),
+            super::RouteRequest::new(Some(
+                Subnet::new(Ipv6Addr::new(0x400, 1, 2, 3, 0, 0, 0, 0).into(), 64)
+                    .expect("64 is a valid IPv6 prefix size; qed"),
+            ))
+            .into(),
+        ];
+
+        let packets = super::pack(tlvs.clone());
+        assert_eq!(packets.len(), 1);
+        let mut buf = bytes::BytesMut::new();
+        for packet in packets {
+            sender
+                .codec_mut()
+                .encode_packed(packet, &mut buf)
+                .expect("Encoding into a buffer can never fail; qed");
+        }
+        sender
+            .get_mut()
+            .write_all(&buf)
+            .await
+            .expect("Write on a non-networked buffer can never fail; qed");
+        // A single TLV sent afterwards is still decoded correctly.
+        sender
+            .send(tlvs[0].clone())
+            .await
+            .expect("Send on a non-networked buffer can never fail; qed");
+
+        for tlv in tlvs.iter().chain(&tlvs[..1]) {
+            let recv_tlv = receiver
+                .next()
+                .await
+                .expect("Buffer isn't closed so this is always `Some`; qed")
+                .expect("Can decode the previously encoded value");
+            assert_eq!(tlv, &recv_tlv);
+        }
+    }
 }
@@ -1,5 +1,7 @@
 //! The babel [Hello TLV](https://datatracker.ietf.org/doc/html/rfc8966#section-4.6.5).
 
+use std::time::Duration;
+
 use bytes::{Buf, BufMut};
 use tracing::trace;
 
@@ -7,9 +9,17 @@ use crate::sequence_number::SeqNo;
 
 /// Flag bit indicating a [`Hello`] is sent as unicast hello.
 const HELLO_FLAG_UNICAST: u16 = 0x8000;
+/// Flag bit indicating the sender of a [`Hello`] can decode packets holding multiple TLVs. The
+/// spec reserves this bit, and requires nodes to ignore it, so nodes which don't support packed
+/// TLVs are not affected.
+const HELLO_FLAG_PACKED_TLVS: u16 = 0x0001;
+/// Flag bit indicating the sender of a [`Hello`] can decode TLVs holding a source prefix sub-TLV.
+/// Nodes which don't support source-specific routes can't skip this sub-TLV, so such TLVs are
+/// only sent to nodes which set this flag.
+const HELLO_FLAG_SOURCE_SPECIFIC_ROUTES: u16 = 0x0002;
 
 /// Mask to apply to [`Hello`] flags, leaving only valid flags.
-const FLAG_MASK: u16 = 0b10000000_00000000;
+const FLAG_MASK: u16 = 0b10000000_00000011;
 
 /// Wire size of a [`Hello`] TLV without TLV header.
 const HELLO_WIRE_SIZE: u8 = 6;
@@ -32,6 +42,39 @@ impl Hello {
         }
     }
 
+    /// Announce that the sender of this `Hello` can decode packets holding multiple TLVs.
+    pub fn with_packed_tlvs(mut self) -> Self {
+        self.flags |= HELLO_FLAG_PACKED_TLVS;
+        self
+    }
+
+    /// Checks if the sender of this `Hello` can decode packets holding multiple TLVs.
+    pub fn accepts_packed_tlvs(&self) -> bool {
+        self.flags & HELLO_FLAG_PACKED_TLVS != 0
+    }
+
+    /// Announce that the sender of this `Hello` can decode source-specific TLVs.
+    pub fn with_source_specific_routes(mut self) -> Self {
+        self.flags |= HELLO_FLAG_SOURCE_SPECIFIC_ROUTES;
+        self
+    }
+
+    /// Checks if the sender of this `Hello` can decode source-specific TLVs.
+    pub fn accepts_source_specific_routes(&self) -> bool {
+        self.flags & HELLO_FLAG_SOURCE_SPECIFIC_ROUTES != 0
+    }
+
+    /// Returns the [`SeqNo`] of this `Hello`.
+    pub fn seqno(&self) -> SeqNo {
+        self.seqno
+    }
+
+    /// Get the time until the next `Hello` is sent by the sender.
+    pub fn interval(&self) -> Duration {
+        // Interval is expressed as centiseconds on the wire.
+        Duration::from_millis(self.interval as u64 * 10)
+    }
+
     /// Calculates the size on the wire of this `Hello`.
     pub fn wire_size(&self) -> u8 {
         HELLO_WIRE_SIZE
@@ -159,4 +202,23 @@ mod tests {
         assert_eq!(hello_src, decoded);
         assert_eq!(buf.remaining(), 0);
     }
+
+    #[test]
+    fn capability_flags_roundtrip() {
+        let mut buf = bytes::BytesMut::new();
+
+        let hello_src = super::Hello::new_unicast(16.into(), 400).with_source_specific_routes();
+        hello_src.write_bytes(&mut buf);
+        let decoded = super::Hello::from_bytes(&mut buf);
+
+        assert!(decoded.accepts_source_specific_routes());
+        assert!(!decoded.accepts_packed_tlvs());
+
+        let hello_src = super::Hello::new_unicast(16.into(), 400).with_packed_tlvs();
+        hello_src.write_bytes(&mut buf);
+        let decoded = super::Hello::from_bytes(&mut buf);
+
+        assert!(!decoded.accepts_source_specific_routes());
+        assert!(decoded.accepts_packed_tlvs());
+    }
 }
@@ -1,6 +1,9 @@
 //! The babel [IHU TLV](https://datatracker.ietf.org/doc/html/rfc8966#name-ihu).
 
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
 
 use bytes::{Buf, BufMut};
 use tracing::trace;
@@ -35,6 +38,17 @@ impl Ihu {
         }
     }
 
+    /// The cost of the link as seen by the sender of this `Ihu`.
+    pub fn rx_cost(&self) -> Metric {
+        self.rx_cost
+    }
+
+    /// Get the time until the next `Ihu` is sent by the sender.
+    pub fn interval(&self) -> Duration {
+        // Interval is expressed as centiseconds on the wire.
+        Duration::from_millis(self.interval as u64 * 10)
+    }
+
     /// Calculates the size on the wire of this `Ihu`.
     pub fn wire_size(&self) -> u8 {
         IHU_BASE_WIRE_SIZE
@@ -15,6 +15,8 @@ const ROUTE_REQUEST_BASE_WIRE_SIZE: u8 = 2;
 pub struct RouteRequest {
     /// The prefix being requested
     prefix: Option<Subnet>,
+    /// The source prefix of the requested route, if a source-specific route is requested.
+    source_prefix: Option<Subnet>,
 }
 
 impl RouteRequest {
@@ -23,7 +25,21 @@ impl RouteRequest {
     ///
     /// [`prefix`]: Subnet
     pub fn new(prefix: Option<Subnet>) -> Self {
-        Self { prefix }
+        Self {
+            prefix,
+            source_prefix: None,
+        }
+    }
+
+    /// Request the source-specific route for the given source prefix, as described in [RFC
+    /// 9079](https://datatracker.ietf.org/doc/html/rfc9079). The source prefix is ignored for a
+    /// full route table dump, and a source prefix which matches all addresses requests the regular
+    /// destination-only route.
+    pub fn with_source_prefix(mut self, source_prefix: Option<Subnet>) -> Self {
+        self.source_prefix = source_prefix
+            .filter(|sp| sp.prefix_len() != 0)
+            .filter(|_| self.prefix.is_some());
+        self
     }
 
     /// Return the [`prefix`](Subnet) associated with this `RouteRequest`.
@@ -31,6 +47,11 @@ impl RouteRequest {
         self.prefix
     }
 
+    /// Return the source prefix of the requested route, if a source-specific route is requested.
+    pub fn source_prefix(&self) -> Option<Subnet> {
+        self.source_prefix
+    }
+
     /// Calculates the size on the wire of this `RouteRequest`.
     pub fn wire_size(&self) -> u8 {
         ROUTE_REQUEST_BASE_WIRE_SIZE
@@ -39,6 +60,7 @@ impl RouteRequest {
             } else {
                 0
             })
+            + super::source_prefix_sub_tlv_wire_size(self.source_prefix)
     }
 
     /// Construct a `RouteRequest` from wire bytes.
@@ -48,6 +70,7 @@ impl RouteRequest {
     /// This function will panic if there are insufficient bytes present in the provided buffer to
     /// decode a complete `RouteRequest`.
     pub fn from_bytes(src: &mut bytes::BytesMut, len: u8) -> Option<Self> {
+        let start = src.remaining();
         let ae = src.get_u8();
         let plen = src.get_u8();
 
@@ -94,9 +117,26 @@ impl RouteRequest {
 
         let prefix = prefix_ip.and_then(|prefix| Subnet::new(prefix, plen).ok());
 
+        let consumed = start - src.remaining();
+        if consumed > len as usize {
+            return None;
+        }
+        let source_prefix =
+            super::read_source_prefix_sub_tlv(src, len as usize - consumed, ae == AE_IPV4)?;
+        // A full route table dump can't be restricted to a source prefix.
+        if prefix.is_none() && source_prefix.is_some() {
+            return None;
+        }
+
         trace!("Read route_request tlv body");
 
-        Some(RouteRequest { prefix })
+        Some(
+            RouteRequest {
+                prefix,
+                source_prefix: None,
+            }
+            .with_source_prefix(source_prefix),
+        )
     }
 
     /// Encode this `RouteRequest` tlv as part of a packet.
@@ -112,6 +152,7 @@ impl RouteRequest {
                 IpAddr::V4(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
                 IpAddr::V6(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
             }
+            super::write_source_prefix_sub_tlv(self.source_prefix, dst);
         } else {
             dst.put_u8(AE_WILDCARD);
             // Prefix len MUST be 0 for wildcard requests
@@ -137,6 +178,7 @@ mod tests {
                 Subnet::new(Ipv6Addr::new(512, 25, 26, 27, 28, 0, 0, 29).into(), 64)
                     .expect("64 is a valid IPv6 prefix size; qed"),
             ),
+            source_prefix: None,
         };
 
         rr.write_bytes(&mut buf);
@@ -151,6 +193,7 @@ mod tests {
                 Subnet::new(Ipv4Addr::new(10, 101, 4, 1).into(), 32)
                     .expect("32 is a valid IPv4 prefix size; qed"),
             ),
+            source_prefix: None,
         };
 
         rr.write_bytes(&mut buf);
@@ -160,7 +203,10 @@ mod tests {
 
         let mut buf = bytes::BytesMut::new();
 
-        let rr = super::RouteRequest { prefix: None };
+        let rr = super::RouteRequest {
+            prefix: None,
+            source_prefix: None,
+        };
 
         rr.write_bytes(&mut buf);
 
@@ -172,7 +218,10 @@ mod tests {
     fn decoding() {
         let mut buf = bytes::BytesMut::from(&[0, 0][..]);
 
-        let rr = super::RouteRequest { prefix: None };
+        let rr = super::RouteRequest {
+            prefix: None,
+            source_prefix: None,
+        };
 
         let buf_len = buf.len();
         assert_eq!(
@@ -188,6 +237,7 @@ mod tests {
                 Subnet::new(Ipv4Addr::new(10, 15, 19, 0).into(), 24)
                     .expect("24 is a valid IPv4 prefix size; qed"),
             ),
+            source_prefix: None,
         };
 
         let buf_len = buf.len();
@@ -204,6 +254,7 @@ mod tests {
                 Subnet::new(Ipv6Addr::new(10, 20, 30, 40, 0, 0, 0, 0).into(), 64)
                     .expect("64 is a valid IPv6 prefix size; qed"),
             ),
+            source_prefix: None,
         };
 
         let buf_len = buf.len();
@@ -220,6 +271,7 @@ mod tests {
                 Subnet::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 10, 20, 30, 40).into(), 64)
                     .expect("64 is a valid IPv6 prefix size; qed"),
             ),
+            source_prefix: None,
         };
 
         let buf_len = buf.len();
@@ -269,4 +321,35 @@ mod tests {
         assert_eq!(Some(seqno_src), decoded);
         assert_eq!(buf.remaining(), 0);
     }
+
+    #[test]
+    fn source_specific_roundtrip() {
+        let mut buf = bytes::BytesMut::new();
+
+        let rr_src = super::RouteRequest::new(Some(
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+                .expect("0 is a valid IPv6 prefix size; qed"),
+        ))
+        .with_source_prefix(Some(
+            Subnet::new(Ipv6Addr::new(0x400, 0x1, 0, 0, 0, 0, 0, 0).into(), 32)
+                .expect("32 is a valid IPv6 prefix size; qed"),
+        ));
+        rr_src.write_bytes(&mut buf);
+        let buf_len = buf.len();
+        assert_eq!(buf_len, rr_src.wire_size() as usize);
+        assert_eq!(buf[..], [2, 0, 128, 5, 32, 4, 0, 0, 1]);
+        let decoded = super::RouteRequest::from_bytes(&mut buf, buf_len as u8);
+
+        assert_eq!(Some(rr_src), decoded);
+        assert_eq!(buf.remaining(), 0);
+
+        // A full route table dump can't be source-specific.
+        let mut buf = bytes::BytesMut::from(&[0, 0, 128, 5, 32, 4, 0, 0, 1][..]);
+        let buf_len = buf.len();
+        assert_eq!(
+            super::RouteRequest::from_bytes(&mut buf, buf_len as u8),
+            None
+        );
+        assert_eq!(buf.remaining(), 0);
+    }
 }
@@ -28,6 +28,8 @@ pub struct SeqNoRequest {
     router_id: RouterId,
     /// The prefix being requested
     prefix: Subnet,
+    /// The source prefix of the requested route, if this is a request for a source-specific route.
+    source_prefix: Option<Subnet>,
 }
 
 impl SeqNoRequest {
@@ -39,14 +41,29 @@ impl SeqNoRequest {
             hop_count: DEFAULT_HOP_COUNT,
             router_id,
             prefix,
+            source_prefix: None,
         }
     }
 
+    /// Request a new [`SeqNo`] for the source-specific route with the given source prefix, as
+    /// described in [RFC 9079](https://datatracker.ietf.org/doc/html/rfc9079). A source prefix
+    /// which matches all addresses requests the regular destination-only route.
+    pub fn with_source_prefix(mut self, source_prefix: Option<Subnet>) -> Self {
+        self.source_prefix = source_prefix.filter(|sp| sp.prefix_len() != 0);
+        self
+    }
+
     /// Return the [`prefix`](Subnet) associated with this `SeqNoRequest`.
     pub fn prefix(&self) -> Subnet {
         self.prefix
     }
 
+    /// Return the source prefix of the requested route, if this is a request for a
+    /// source-specific route.
+    pub fn source_prefix(&self) -> Option<Subnet> {
+        self.source_prefix
+    }
+
     /// Return the [`RouterId`] associated with this `SeqNoRequest`.
     pub fn router_id(&self) -> RouterId {
         self.router_id
@@ -77,7 +94,9 @@ impl SeqNoRequest {
 
     /// Calculates the size on the wire of this `Update`.
     pub fn wire_size(&self) -> u8 {
-        SEQNO_REQUEST_BASE_WIRE_SIZE + (self.prefix.prefix_len() + 7) / 8
+        SEQNO_REQUEST_BASE_WIRE_SIZE
+            + (self.prefix.prefix_len() + 7) / 8
+            + super::source_prefix_sub_tlv_wire_size(self.source_prefix)
         // TODO: Wildcard should be encoded differently
     }
 
@@ -88,6 +107,7 @@ impl SeqNoRequest {
     /// This function will panic if there are insufficient bytes present in the provided buffer to
     /// decode a complete `SeqNoRequest`.
     pub fn from_bytes(src: &mut bytes::BytesMut, len: u8) -> Option<Self> {
+        let start = src.remaining();
         let ae = src.get_u8();
         let plen = src.get_u8();
         let seqno = src.get_u16().into();
@@ -150,6 +170,13 @@ impl SeqNoRequest {
 
         let prefix = Subnet::new(prefix, plen).ok()?;
 
+        let consumed = start - src.remaining();
+        if consumed > len as usize {
+            return None;
+        }
+        let source_prefix =
+            super::read_source_prefix_sub_tlv(src, len as usize - consumed, ae == AE_IPV4)?;
+
         trace!("Read seqno_request tlv body");
 
         // Make sure hop_count is valid
@@ -160,12 +187,16 @@ impl SeqNoRequest {
             return None;
         };
 
-        Some(SeqNoRequest {
-            seqno,
-            hop_count,
-            router_id,
-            prefix,
-        })
+        Some(
+            SeqNoRequest {
+                seqno,
+                hop_count,
+                router_id,
+                prefix,
+                source_prefix: None,
+            }
+            .with_source_prefix(source_prefix),
+        )
     }
 
     /// Encode this `SeqNoRequest` tlv as part of a packet.
@@ -185,6 +216,7 @@ impl SeqNoRequest {
             IpAddr::V4(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
             IpAddr::V6(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
         }
+        super::write_source_prefix_sub_tlv(self.source_prefix, dst);
     }
 }
 
@@ -208,6 +240,7 @@ mod tests {
             prefix: Subnet::new(Ipv6Addr::new(512, 25, 26, 27, 28, 0, 0, 29).into(), 64)
                 .expect("64 is a valid IPv6 prefix size; qed"),
             router_id: RouterId::from([1u8; RouterId::BYTE_SIZE]),
+            source_prefix: None,
         };
 
         snr.write_bytes(&mut buf);
@@ -229,6 +262,7 @@ mod tests {
             prefix: Subnet::new(Ipv4Addr::new(10, 101, 4, 1).into(), 32)
                 .expect("32 is a valid IPv4 prefix size; qed"),
             router_id: RouterId::from([2u8; RouterId::BYTE_SIZE]),
+            source_prefix: None,
         };
 
         snr.write_bytes(&mut buf);
@@ -258,6 +292,7 @@ mod tests {
             prefix: Subnet::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0).into(), 0)
                 .expect("0 is a valid IPv6 prefix size; qed"),
             router_id: RouterId::from([3u8; RouterId::BYTE_SIZE]),
+            source_prefix: None,
         };
 
         let buf_len = buf.len();
@@ -281,6 +316,7 @@ mod tests {
             prefix: Subnet::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 10, 20, 30, 40).into(), 64)
                 .expect("92 is a valid IPv6 prefix size; qed"),
             router_id: RouterId::from([4u8; RouterId::BYTE_SIZE]),
+            source_prefix: None,
         };
 
         let buf_len = buf.len();
@@ -353,4 +389,27 @@ mod tests {
         assert_eq!(Some(seqno_src), decoded);
         assert_eq!(buf.remaining(), 0);
     }
+
+    #[test]
+    fn source_specific_roundtrip() {
+        let mut buf = bytes::BytesMut::new();
+
+        let seqno_src = super::SeqNoRequest::new(
+            64.into(),
+            RouterId::from([6; RouterId::BYTE_SIZE]),
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+                .expect("0 is a valid IPv6 prefix size; qed"),
+        )
+        .with_source_prefix(Some(
+            Subnet::new(Ipv6Addr::new(0x400, 0x1, 0, 0, 0, 0, 0, 0).into(), 32)
+                .expect("32 is a valid IPv6 prefix size; qed"),
+        ));
+        seqno_src.write_bytes(&mut buf);
+        let buf_len = buf.len();
+        assert_eq!(buf_len, seqno_src.wire_size() as usize);
+        let decoded = super::SeqNoRequest::from_bytes(&mut buf, buf_len as u8);
+
+        assert_eq!(Some(seqno_src), decoded);
+        assert_eq!(buf.remaining(), 0);
+    }
 }
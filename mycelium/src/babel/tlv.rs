@@ -1,5 +1,6 @@
 pub use super::{hello::Hello, ihu::Ihu, update::Update};
 use super::{route_request::RouteRequest, SeqNoRequest};
+use crate::subnet::Subnet;
 
 /// A single `Tlv` in a babel packet body.
 #[derive(Debug, Clone, PartialEq)]
@@ -29,6 +30,16 @@ impl Tlv {
         }
     }
 
+    /// The source prefix carried by this `Tlv`, if it refers to a source-specific route.
+    pub fn source_prefix(&self) -> Option<Subnet> {
+        match self {
+            Self::Hello(_) | Self::Ihu(_) => None,
+            Self::Update(update) => update.source_prefix(),
+            Self::RouteRequest(route_request) => route_request.source_prefix(),
+            Self::SeqNoRequest(seqno_request) => seqno_request.source_prefix(),
+        }
+    }
+
     /// Encode this `Tlv` as part of a packet.
     pub fn write_bytes(&self, dst: &mut bytes::BytesMut) {
         match self {
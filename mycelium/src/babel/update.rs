@@ -25,6 +25,12 @@ const FLAG_MASK: u8 = 0b1100_0000;
 const UPDATE_BASE_WIRE_SIZE: u8 = 10 + RouterId::BYTE_SIZE as u8;
 
 /// Update TLV body as defined in https://datatracker.ietf.org/doc/html/rfc8966#name-update.
+///
+/// An `Update` can optionally carry a source prefix, making the route source-specific as described
+/// in [RFC 9079](https://datatracker.ietf.org/doc/html/rfc9079). The source prefix is encoded in
+/// a source prefix sub-TLV. Nodes which don't support source-specific routes can't decode that
+/// sub-TLV, so such updates are only sent to peers which announce support in their
+/// [`Hello`](super::Hello)s.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Update {
     /// Flags set in the TLV.
@@ -38,9 +44,14 @@ pub struct Update {
     /// The [`Subnet`] contained in this update. An update packet itself can contain any allowed
     /// subnet.
     subnet: Subnet,
+    /// The source prefix of the route, if this is a source-specific route.
+    source_prefix: Option<Subnet>,
     /// Router id of the sender. Importantly this is not part of the update itself, though we do
     /// transmit it for now as such.
     router_id: RouterId,
+    /// Whether this `Update` uses the wildcard address encoding, i.e. it applies to all routes
+    /// advertised by the sender rather than a single [`Subnet`].
+    wildcard: bool,
 }
 
 impl Update {
@@ -60,10 +71,49 @@ impl Update {
             seqno,
             metric,
             subnet,
+            source_prefix: None,
             router_id,
+            wildcard: false,
         }
     }
 
+    /// Create a new wildcard retraction. This retracts all routes advertised by the sender at
+    /// once, as described in [RFC 8966 section
+    /// 4.6.9](https://datatracker.ietf.org/doc/html/rfc8966#section-4.6.9).
+    pub fn new_wildcard_retraction(seqno: SeqNo, router_id: RouterId) -> Self {
+        Self {
+            flags: 0,
+            // Retractions are not repeated.
+            interval: 0,
+            seqno,
+            metric: Metric::infinite(),
+            subnet: Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+                .expect("0 is a valid IPv6 prefix size; qed"),
+            source_prefix: None,
+            router_id,
+            wildcard: true,
+        }
+    }
+
+    /// Returns `true` if this `Update` is a wildcard retraction, which retracts all routes
+    /// advertised by the sender. Wildcard updates with a finite metric are not valid.
+    pub fn is_wildcard_retraction(&self) -> bool {
+        self.wildcard && self.metric.is_infinite()
+    }
+
+    /// Returns `true` if this `Update` uses the wildcard address encoding.
+    pub fn is_wildcard(&self) -> bool {
+        self.wildcard
+    }
+
+    /// Restrict this `Update` to traffic originating from the given source prefix. A source prefix
+    /// of [`None`], or one which matches all addresses, results in a regular destination-only
+    /// route.
+    pub fn with_source_prefix(mut self, source_prefix: Option<Subnet>) -> Self {
+        self.source_prefix = source_prefix.filter(|sp| sp.prefix_len() != 0);
+        self
+    }
+
     /// Returns the [`SeqNo`] of the sender of this `Update`.
     pub fn seqno(&self) -> SeqNo {
         self.seqno
@@ -79,6 +129,11 @@ impl Update {
         self.subnet
     }
 
+    /// Return the source prefix in this `Update`, if this is an update for a source-specific route.
+    pub fn source_prefix(&self) -> Option<Subnet> {
+        self.source_prefix
+    }
+
     /// Return the [`router-id`](PublicKey) of the router who advertised this [`Prefix`](IpAddr).
     pub fn router_id(&self) -> RouterId {
         self.router_id
@@ -87,7 +142,9 @@ impl Update {
     /// Calculates the size on the wire of this `Update`.
     pub fn wire_size(&self) -> u8 {
         let address_bytes = (self.subnet.prefix_len() + 7) / 8;
-        UPDATE_BASE_WIRE_SIZE + address_bytes
+        UPDATE_BASE_WIRE_SIZE
+            + address_bytes
+            + super::source_prefix_sub_tlv_wire_size(self.source_prefix)
     }
 
     /// Get the time until a new `Update` for the [`Subnet`] is received at the latest.
@@ -103,6 +160,7 @@ impl Update {
     /// This function will panic if there are insufficient bytes present in the provided buffer to
     /// decode a complete `Update`.
     pub fn from_bytes(src: &mut bytes::BytesMut, len: u8) -> Option<Self> {
+        let start = src.remaining();
         let ae = src.get_u8();
         let flags = src.get_u8() & FLAG_MASK;
         let plen = src.get_u8();
@@ -117,8 +175,9 @@ impl Update {
                 if prefix_size != 0 {
                     return None;
                 }
-                // TODO: this is a temporary placeholder until we figure out how to handle this
-                Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0).into()
+                // The wildcard address doesn't have a subnet, use the catch-all subnet as
+                // placeholder. The update is marked as wildcard below.
+                Ipv6Addr::UNSPECIFIED.into()
             }
             AE_IPV4 => {
                 if plen > 32 {
@@ -165,21 +224,41 @@ impl Update {
 
         let router_id = RouterId::from(router_id_bytes);
 
+        let consumed = start - src.remaining();
+        if consumed > len as usize {
+            return None;
+        }
+        let source_prefix = super::read_source_prefix_sub_tlv(
+            src,
+            len as usize - consumed,
+            subnet.address().is_ipv4(),
+        )?;
+        // A wildcard update applies to all routes, so it can't be restricted to a source prefix.
+        if ae == AE_WILDCARD && source_prefix.is_some() {
+            return None;
+        }
+
         trace!("Read update tlv body");
 
-        Some(Update {
-            flags,
-            interval,
-            seqno,
-            metric,
-            subnet,
-            router_id,
-        })
+        Some(
+            Update {
+                flags,
+                interval,
+                seqno,
+                metric,
+                subnet,
+                source_prefix: None,
+                router_id,
+                wildcard: ae == AE_WILDCARD,
+            }
+            .with_source_prefix(source_prefix),
+        )
     }
 
     /// Encode this `Update` tlv as part of a packet.
     pub fn write_bytes(&self, dst: &mut bytes::BytesMut) {
         dst.put_u8(match self.subnet.address() {
+            _ if self.wildcard => AE_WILDCARD,
             IpAddr::V4(_) => AE_IPV4,
             IpAddr::V6(_) => AE_IPV6,
         });
@@ -195,7 +274,8 @@ impl Update {
             IpAddr::V4(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
             IpAddr::V6(ip) => dst.put_slice(&ip.octets()[..prefix_len]),
         }
-        dst.put_slice(&self.router_id.as_bytes()[..])
+        dst.put_slice(&self.router_id.as_bytes()[..]);
+        super::write_source_prefix_sub_tlv(self.source_prefix, dst);
     }
 }
 
@@ -220,7 +300,9 @@ mod tests {
             metric: 25.into(),
             subnet: Subnet::new(Ipv6Addr::new(512, 25, 26, 27, 28, 0, 0, 29).into(), 64)
                 .expect("64 is a valid IPv6 prefix size; qed"),
+            source_prefix: None,
             router_id: RouterId::from([1u8; RouterId::BYTE_SIZE]),
+            wildcard: false,
         };
 
         ihu.write_bytes(&mut buf);
@@ -244,7 +326,9 @@ mod tests {
             metric: 256.into(),
             subnet: Subnet::new(Ipv4Addr::new(10, 101, 4, 1).into(), 23)
                 .expect("23 is a valid IPv4 prefix size; qed"),
+            source_prefix: None,
             router_id: RouterId::from([2u8; RouterId::BYTE_SIZE]),
+            wildcard: false,
         };
 
         ihu.write_bytes(&mut buf);
@@ -275,7 +359,9 @@ mod tests {
             metric: 512.into(),
             subnet: Subnet::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0).into(), 0)
                 .expect("0 is a valid IPv6 prefix size; qed"),
+            source_prefix: None,
             router_id: RouterId::from([3u8; RouterId::BYTE_SIZE]),
+            wildcard: true,
         };
 
         let buf_len = buf.len();
@@ -300,7 +386,9 @@ mod tests {
             metric: 769.into(),
             subnet: Subnet::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 10, 20, 30, 40).into(), 64)
                 .expect("92 is a valid IPv6 prefix size; qed"),
+            source_prefix: None,
             router_id: RouterId::from([4u8; RouterId::BYTE_SIZE]),
+            wildcard: false,
         };
 
         let buf_len = buf.len();
@@ -349,7 +437,9 @@ mod tests {
             metric: 769.into(),
             subnet: Subnet::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 10, 20, 30, 40).into(), 64)
                 .expect("92 is a valid IPv6 prefix size; qed"),
+            source_prefix: None,
             router_id: RouterId::from([4u8; RouterId::BYTE_SIZE]),
+            wildcard: false,
         };
 
         let buf_len = buf.len();
@@ -382,4 +472,123 @@ mod tests {
         assert_eq!(Some(hello_src), decoded);
         assert_eq!(buf.remaining(), 0);
     }
+
+    #[test]
+    fn source_specific_roundtrip() {
+        let mut buf = bytes::BytesMut::new();
+
+        let update_src = super::Update::new(
+            Duration::from_secs(64),
+            10.into(),
+            25.into(),
+            Subnet::new(
+                Ipv6Addr::new(0x21f, 0x4025, 0xabcd, 0xdead, 0, 0, 0, 0).into(),
+                64,
+            )
+            .expect("64 is a valid IPv6 prefix size; qed"),
+            RouterId::from([6; RouterId::BYTE_SIZE]),
+        )
+        .with_source_prefix(Some(
+            Subnet::new(Ipv6Addr::new(0x400, 0x1, 0, 0, 0, 0, 0, 0).into(), 36)
+                .expect("36 is a valid IPv6 prefix size; qed"),
+        ));
+        update_src.write_bytes(&mut buf);
+        let buf_len = buf.len();
+        assert_eq!(buf_len, update_src.wire_size() as usize);
+        // The source prefix sub-TLV, with the mandatory bit set, follows the router id.
+        assert_eq!(buf[buf_len - 8..], [128, 6, 36, 4, 0, 0, 1, 0]);
+        let decoded = super::Update::from_bytes(&mut buf, buf_len as u8);
+
+        assert_eq!(Some(update_src), decoded);
+        assert_eq!(buf.remaining(), 0);
+    }
+
+    #[test]
+    fn decode_handles_sub_tlvs() {
+        let update = super::Update::new(
+            Duration::from_secs(64),
+            10.into(),
+            25.into(),
+            Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0).into(), 64)
+                .expect("64 is a valid IPv6 prefix size; qed"),
+            RouterId::from([6; RouterId::BYTE_SIZE]),
+        );
+
+        // Padding and unknown sub-TLVs which are not mandatory are skipped.
+        let mut buf = bytes::BytesMut::new();
+        update.write_bytes(&mut buf);
+        buf.extend_from_slice(&[0, 1, 2, 0, 0, 5, 1, 42]);
+        let buf_len = buf.len();
+        assert_eq!(
+            super::Update::from_bytes(&mut buf, buf_len as u8),
+            Some(update.clone())
+        );
+        assert_eq!(buf.remaining(), 0);
+
+        // An unknown mandatory sub-TLV means the update must be ignored.
+        let mut buf = bytes::BytesMut::new();
+        update.write_bytes(&mut buf);
+        buf.extend_from_slice(&[129, 1, 42]);
+        let buf_len = buf.len();
+        assert_eq!(super::Update::from_bytes(&mut buf, buf_len as u8), None);
+        assert_eq!(buf.remaining(), 0);
+
+        // As is a truncated sub-TLV.
+        let mut buf = bytes::BytesMut::new();
+        update.write_bytes(&mut buf);
+        buf.extend_from_slice(&[128, 9, 64, 4, 0]);
+        let buf_len = buf.len();
+        assert_eq!(super::Update::from_bytes(&mut buf, buf_len as u8), None);
+        assert_eq!(buf.remaining(), 0);
+    }
+
+    #[test]
+    fn catch_all_source_prefix_is_destination_only() {
+        let update = super::Update::new(
+            Duration::from_secs(64),
+            10.into(),
+            25.into(),
+            Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0).into(), 64)
+                .expect("64 is a valid IPv6 prefix size; qed"),
+            RouterId::from([6; RouterId::BYTE_SIZE]),
+        )
+        .with_source_prefix(Some(
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+                .expect("0 is a valid IPv6 prefix size; qed"),
+        ));
+
+        assert_eq!(update.source_prefix(), None);
+    }
+
+    #[test]
+    fn wildcard_retraction_roundtrip() {
+        let mut buf = bytes::BytesMut::new();
+
+        let update_src = super::Update::new_wildcard_retraction(
+            10.into(),
+            RouterId::from([7; RouterId::BYTE_SIZE]),
+        );
+        update_src.write_bytes(&mut buf);
+        let buf_len = buf.len();
+        assert_eq!(buf_len, update_src.wire_size() as usize);
+        // AE 0, no flags, prefix length 0.
+        assert_eq!(buf[..3], [0, 0, 0]);
+        let decoded = super::Update::from_bytes(&mut buf, buf_len as u8)
+            .expect("Can decode wildcard retraction");
+
+        assert!(decoded.is_wildcard_retraction());
+        assert_eq!(update_src, decoded);
+        assert_eq!(buf.remaining(), 0);
+
+        // A regular retraction for the catch-all subnet is not a wildcard retraction.
+        let update = super::Update::new(
+            Duration::from_secs(0),
+            10.into(),
+            crate::metric::Metric::infinite(),
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+                .expect("0 is a valid IPv6 prefix size; qed"),
+            RouterId::from([7; RouterId::BYTE_SIZE]),
+        );
+        assert!(!update.is_wildcard_retraction());
+    }
 }
@@ -0,0 +1,301 @@
+//! Capture of the plaintext IP packets exchanged with remote nodes over the overlay, written to
+//! pcap files, to debug connectivity of applications with tools like Wireshark.
+//!
+//! Packets are captured after they are decrypted, or before they are encrypted, so the capture
+//! files contain the full, unencrypted traffic of the node. This is disabled unless a
+//! [`PacketCaptureConfig`] is set, and the capture files are only readable by their owner. Only
+//! packets which originate or terminate at the local node can be captured, since packets which
+//! are merely forwarded can't be decrypted.
+//!
+//! Packets are handed to a separate writer task, so capturing never blocks the data plane. If
+//! the writer can't keep up, packets are left out of the capture. Once a capture file reaches
+//! the maximum size, it is rotated: older files get an increasing numeric suffix, and the oldest
+//! file is removed once the configured amount of files is exceeded.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::mpsc;
+use tracing::{error, trace};
+
+use crate::subnet::Subnet;
+
+/// Default maximum size of a single capture file.
+pub const DEFAULT_CAPTURE_MAX_FILE_SIZE: u64 = 16 << 20;
+
+/// Default amount of capture files which are kept, including the one currently written.
+pub const DEFAULT_CAPTURE_MAX_FILES: usize = 4;
+
+/// Amount of captured packets which can wait to be written, before new packets are left out of
+/// the capture.
+const CAPTURE_QUEUE_SIZE: usize = 1000;
+
+/// Magic number at the start of a pcap file, indicating microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Major version of the pcap file format.
+const PCAP_VERSION_MAJOR: u16 = 2;
+
+/// Minor version of the pcap file format.
+const PCAP_VERSION_MINOR: u16 = 4;
+
+/// Maximum size of a captured packet. Packets are never truncated, as they can't exceed this.
+const PCAP_SNAPLEN: u32 = 65535;
+
+/// Link type of packets which start directly with an IPv4 or IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+
+/// Size of the header at the start of a pcap file.
+const PCAP_FILE_HEADER_SIZE: u64 = 24;
+
+/// Size of the header in front of every packet in a pcap file.
+const PCAP_RECORD_HEADER_SIZE: u64 = 16;
+
+/// Configuration of the capture of overlay traffic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketCaptureConfig {
+    /// Path of the file packets are written to. Rotated files get this path with a numeric
+    /// suffix.
+    pub path: PathBuf,
+    /// Maximum size of a single capture file, in bytes. If writing a packet would exceed this,
+    /// the file is rotated first.
+    pub max_file_size: u64,
+    /// Maximum amount of capture files which are kept, including the one currently written. This
+    /// must be at least 1.
+    pub max_files: usize,
+    /// Overlay subnets of which traffic is captured. A packet is captured if its source or
+    /// destination is in any of these subnets. If empty, all traffic is captured.
+    pub filter: Vec<Subnet>,
+}
+
+impl PacketCaptureConfig {
+    /// Checks if a packet between the given overlay IPs should be captured.
+    fn matches(&self, src: IpAddr, dst: IpAddr) -> bool {
+        self.filter.is_empty()
+            || self
+                .filter
+                .iter()
+                .any(|subnet| subnet.contains_ip(src) || subnet.contains_ip(dst))
+    }
+}
+
+/// Hands packets which match the configured filter to the task writing the capture files.
+#[derive(Clone)]
+pub struct PacketCapture {
+    config: Arc<PacketCaptureConfig>,
+    tx: mpsc::Sender<(SystemTime, Vec<u8>)>,
+}
+
+impl PacketCapture {
+    /// Create a new `PacketCapture`, and start the task which writes the capture files. The first
+    /// capture file is created right away, so an invalid path is reported here.
+    pub fn new(config: PacketCaptureConfig) -> io::Result<Self> {
+        if config.max_files == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least 1 capture file must be kept",
+            ));
+        }
+        if config.max_file_size < PCAP_FILE_HEADER_SIZE + PCAP_RECORD_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "maximum capture file size is too small to hold a packet",
+            ));
+        }
+
+        let mut writer = CaptureWriter::new(config.clone())?;
+        let (tx, mut rx) = mpsc::channel::<(SystemTime, Vec<u8>)>(CAPTURE_QUEUE_SIZE);
+        tokio::task::spawn_blocking(move || {
+            while let Some((time, packet)) = rx.blocking_recv() {
+                if let Err(e) = writer.write(time, &packet) {
+                    error!("Failed to write packet capture, stopping capture: {e}");
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            config: Arc::new(config),
+            tx,
+        })
+    }
+
+    /// Capture a packet between the given overlay IPs, if it matches the filter. The packet must
+    /// start with its IP header.
+    pub fn capture(&self, src: IpAddr, dst: IpAddr, packet: &[u8]) {
+        if !self.config.matches(src, dst) {
+            return;
+        }
+        if self
+            .tx
+            .try_send((SystemTime::now(), packet.to_vec()))
+            .is_err()
+        {
+            trace!("Packet capture queue is full, leaving packet out of the capture");
+        }
+    }
+}
+
+/// Writes captured packets to pcap files, and rotates them.
+struct CaptureWriter {
+    config: PacketCaptureConfig,
+    file: BufWriter<File>,
+    /// Amount of bytes written to the current file.
+    written: u64,
+}
+
+impl CaptureWriter {
+    /// Create a new `CaptureWriter`, and open the first capture file. An existing file at the
+    /// configured path is rotated, so captures of previous runs are kept.
+    fn new(config: PacketCaptureConfig) -> io::Result<Self> {
+        if config.path.exists() {
+            rotate(&config.path, config.max_files)?;
+        }
+        let file = create_capture_file(&config.path)?;
+        Ok(Self {
+            config,
+            file,
+            written: PCAP_FILE_HEADER_SIZE,
+        })
+    }
+
+    /// Write a packet captured at `time` to the current file, rotating it first if it would grow
+    /// too large.
+    fn write(&mut self, time: SystemTime, packet: &[u8]) -> io::Result<()> {
+        let record_size = PCAP_RECORD_HEADER_SIZE + packet.len() as u64;
+        // A file which only has a header is not rotated, otherwise a single oversized packet
+        // would cause endless rotation.
+        if self.written + record_size > self.config.max_file_size
+            && self.written > PCAP_FILE_HEADER_SIZE
+        {
+            self.file.flush()?;
+            rotate(&self.config.path, self.config.max_files)?;
+            self.file = create_capture_file(&self.config.path)?;
+            self.written = PCAP_FILE_HEADER_SIZE;
+        }
+
+        self.file.write_all(&record_header(time, packet.len()))?;
+        self.file.write_all(packet)?;
+        // Flush every packet, so the capture can be inspected while it is running.
+        self.file.flush()?;
+        self.written += record_size;
+
+        Ok(())
+    }
+}
+
+/// Create a new capture file at `path`, which is only accessible by its owner, and write the pcap
+/// file header to it.
+fn create_capture_file(path: &Path) -> io::Result<BufWriter<File>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = BufWriter::new(options.open(path)?);
+    file.write_all(&file_header())?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Move the capture file at `path` to `path.1`, shifting existing rotated files up by one, and
+/// removing the ones which exceed `max_files`.
+fn rotate(path: &Path, max_files: usize) -> io::Result<()> {
+    for i in (1..max_files).rev() {
+        let from = if i == 1 {
+            path.to_path_buf()
+        } else {
+            rotated_path(path, i - 1)
+        };
+        match fs::rename(&from, rotated_path(path, i)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    if max_files == 1 {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// The path of the capture file which was rotated `n` times.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}"));
+    rotated.into()
+}
+
+/// The header at the start of a pcap file.
+fn file_header() -> [u8; PCAP_FILE_HEADER_SIZE as usize] {
+    let mut header = [0; PCAP_FILE_HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+    header[6..8].copy_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+    // Bytes 8..16 hold the timezone offset and timestamp accuracy, which are always 0.
+    header[16..20].copy_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    header
+}
+
+/// The header in front of a packet of `len` bytes captured at `time` in a pcap file.
+fn record_header(time: SystemTime, len: usize) -> [u8; PCAP_RECORD_HEADER_SIZE as usize] {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut header = [0; PCAP_RECORD_HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+    header[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&(len as u32).to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{
+        rotated_path, CaptureWriter, PacketCaptureConfig, LINKTYPE_RAW, PCAP_FILE_HEADER_SIZE,
+        PCAP_RECORD_HEADER_SIZE,
+    };
+
+    #[test]
+    fn capture_files_are_rotated() {
+        let dir = std::env::temp_dir().join(format!("mycelium-capture-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("overlay.pcap");
+        let config = PacketCaptureConfig {
+            path: path.clone(),
+            // Room for exactly 2 packets of 100 bytes.
+            max_file_size: PCAP_FILE_HEADER_SIZE + 2 * (PCAP_RECORD_HEADER_SIZE + 100),
+            max_files: 2,
+            filter: vec!["400:1::/64".parse().unwrap()],
+        };
+        assert!(config.matches("400:1::1".parse().unwrap(), "500:1::1".parse().unwrap()));
+        assert!(!config.matches("400:2::1".parse().unwrap(), "500:1::1".parse().unwrap()));
+
+        let mut writer = CaptureWriter::new(config).unwrap();
+        let time = UNIX_EPOCH + Duration::from_micros(1_500_000);
+        for i in 0..5 {
+            writer.write(time, &[i; 100]).unwrap();
+        }
+
+        // Packets 0 and 1 were in the file which was rotated out.
+        let current = std::fs::read(&path).unwrap();
+        let previous = std::fs::read(rotated_path(&path, 1)).unwrap();
+        assert!(!rotated_path(&path, 2).exists());
+        assert_eq!(current.len() as u64, PCAP_FILE_HEADER_SIZE + 116);
+        assert_eq!(previous.len() as u64, PCAP_FILE_HEADER_SIZE + 232);
+        assert_eq!(current[20..24], LINKTYPE_RAW.to_le_bytes());
+        assert_eq!(current[24..28], 1u32.to_le_bytes());
+        assert_eq!(current[28..32], 500_000u32.to_le_bytes());
+        assert_eq!(current[32..36], 100u32.to_le_bytes());
+        assert_eq!(current[40], 4);
+        assert_eq!(previous[40], 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
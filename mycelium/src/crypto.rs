@@ -4,18 +4,28 @@ use core::fmt;
 use std::{
     error::Error,
     fmt::Display,
+    io,
     net::Ipv6Addr,
     ops::{Deref, DerefMut},
+    path::Path,
 };
 
-use aes_gcm::{aead::OsRng, AeadCore, AeadInPlace, Aes256Gcm, Key, KeyInit};
+use aes_gcm::{aead::OsRng, AeadCore, AeadInPlace, Aes256Gcm, KeyInit};
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::ChaCha20Poly1305;
 use serde::{de::Visitor, Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use crate::subnet::Subnet;
 
 /// Default MTU for a packet. Ideally this would not be needed and the [`PacketBuffer`] takes a
 /// const generic argument which is then expanded with the needed extra space for the buffer,
 /// however as it stands const generics can only be used standalone and not in a constant
 /// expression. This _is_ possible on nightly rust, with a feature gate (generic_const_exprs).
-const PACKET_SIZE: usize = 1400;
+pub(crate) const PACKET_SIZE: usize = 1400;
 
 /// Size of an AES_GCM tag in bytes.
 const AES_TAG_SIZE: usize = 16;
@@ -23,9 +33,21 @@ const AES_TAG_SIZE: usize = 16;
 /// Size of an AES_GCM nonce in bytes.
 const AES_NONCE_SIZE: usize = 12;
 
+/// Size of the identifier of the [`Cipher`] appended to encrypted data.
+const CIPHER_ID_SIZE: usize = 1;
+
+/// Identifier appended to data encrypted with ChaCha20-Poly1305. Data encrypted with AES-256-GCM
+/// has no identifier, so it can be decrypted by nodes which only support AES-256-GCM.
+const CHACHA20_POLY1305_ID: u8 = 1;
+
 /// Size of user defined data header. This header will be part of the encrypted data.
 const DATA_HEADER_SIZE: usize = 4;
 
+/// Maximum amount of bytes added to the content of a [`PacketBuffer`] when it is encrypted,
+/// including the user data header.
+pub(crate) const ENCRYPTION_OVERHEAD: usize =
+    DATA_HEADER_SIZE + AES_TAG_SIZE + AES_NONCE_SIZE + CIPHER_ID_SIZE;
+
 /// Size of a `PacketBuffer`.
 const PACKET_BUFFER_SIZE: usize = PACKET_SIZE + AES_TAG_SIZE + AES_NONCE_SIZE + DATA_HEADER_SIZE;
 
@@ -47,6 +69,30 @@ pub struct SecretKey(x25519_dalek::StaticSecret);
 #[derive(Clone)]
 pub struct SharedSecret([u8; 32]);
 
+/// AEAD cipher used to encrypt data with a [`SharedSecret`]. Both ciphers use a 12 byte nonce and
+/// a 16 byte tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Cipher {
+    /// AES-256 in Galois/Counter mode. This is supported by all nodes, and is the fastest cipher
+    /// if the CPU has instructions for AES.
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// ChaCha20 with a Poly1305 authenticator. This is faster than AES-256-GCM on CPUs without
+    /// instructions for AES.
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+/// Support of a remote for the [`Cipher`]s, as announced by the remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteCiphers {
+    /// The remote can decrypt data encrypted with ChaCha20-Poly1305.
+    pub chacha20_poly1305: bool,
+    /// The CPU of the remote has instructions for AES.
+    pub aes_accelerated: bool,
+}
+
 /// A buffer for packets. This holds enough space to  encrypt a packet in place without
 /// reallocating.
 ///
@@ -81,6 +127,46 @@ impl Display for DecryptionError {
 
 impl Error for DecryptionError {}
 
+impl Cipher {
+    /// Select the cipher to encrypt data for a remote. AES-256-GCM is only used if both this node
+    /// and the remote have instructions for AES, or if the remote does not support
+    /// ChaCha20-Poly1305.
+    pub fn select(local_aes_accelerated: bool, remote: RemoteCiphers) -> Self {
+        if (local_aes_accelerated && remote.aes_accelerated) || !remote.chacha20_poly1305 {
+            Cipher::Aes256Gcm
+        } else {
+            Cipher::ChaCha20Poly1305
+        }
+    }
+}
+
+impl Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+        })
+    }
+}
+
+/// Checks if the CPU has instructions for AES, in which case AES-256-GCM is faster than
+/// ChaCha20-Poly1305.
+pub fn aes_accelerated() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::is_x86_feature_detected!("aes") && std::is_x86_feature_detected!("pclmulqdq")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("aes")
+            && std::arch::is_aarch64_feature_detected!("pmull")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
 impl SecretKey {
     /// Generate a new `StaticSecret` using [`OsRng`] as an entropy source.
     pub fn new() -> Self {
@@ -97,6 +183,41 @@ impl SecretKey {
     pub fn shared_secret(&self, other: &PublicKey) -> SharedSecret {
         SharedSecret(self.0.diffie_hellman(&other.0).to_bytes())
     }
+
+    /// Derive a symmetric key from this `SecretKey`, to encrypt data which is stored locally.
+    /// Different `context` values give unrelated keys.
+    #[cfg(feature = "message")]
+    pub(crate) fn derive_key(&self, context: &str) -> SharedSecret {
+        SharedSecret(blake3::derive_key(context, self.as_bytes()))
+    }
+
+    /// Load a `SecretKey` from a file. The file contains the raw 32 bytes of the key, as written
+    /// by [`SecretKey::save_to_file`].
+    pub async fn load_from_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path).await?;
+        let mut secret_bytes = [0u8; 32];
+        file.read_exact(&mut secret_bytes).await?;
+
+        Ok(Self::from(secret_bytes))
+    }
+
+    /// Save this `SecretKey` to a file, as the raw 32 bytes of the key. An existing file is
+    /// overwritten. On Unix platforms, the file is only readable and writable by its owner.
+    pub async fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        #[cfg(target_family = "unix")]
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600) // rw by the owner, not readable by group or others
+            .open(path)
+            .await?;
+        #[cfg(not(target_family = "unix"))]
+        let mut file = File::create(path).await?;
+
+        file.write_all(self.as_bytes()).await?;
+        file.sync_all().await
+    }
 }
 
 impl Default for SecretKey {
@@ -123,6 +244,19 @@ impl PublicKey {
         Ipv6Addr::from(buf)
     }
 
+    /// Get the `/64` subnet announced by the node with this `PublicKey`. This contains the
+    /// [`address`](Self::address) of the node.
+    pub fn subnet(&self) -> Subnet {
+        Subnet::new(
+            // Truncate last 64 bits of address.
+            Subnet::new(self.address().into(), 64)
+                .expect("64 is a valid IPv6 prefix size; qed")
+                .network(),
+            64,
+        )
+        .expect("64 is a valid IPv6 prefix size; qed")
+    }
+
     /// Convert this `PublicKey` to a byte array.
     pub fn to_bytes(self) -> [u8; 32] {
         self.0.to_bytes()
@@ -135,26 +269,50 @@ impl PublicKey {
 }
 
 impl SharedSecret {
-    /// Encrypt a [`PacketBuffer`] using the `SharedSecret` as key.
+    /// Encrypt a [`PacketBuffer`] using the `SharedSecret` as key, with AES-256-GCM.
     ///
     /// Internally, a new random nonce will be generated using the OS's crypto rng generator. This
-    /// nonce is appended to the encrypted data.
-    pub fn encrypt(&self, mut data: PacketBuffer) -> Vec<u8> {
-        let key: Key<Aes256Gcm> = self.0.into();
-        let nonce = Aes256Gcm::generate_nonce(OsRng);
+    /// nonce is appended to the encrypted data. The data is encrypted in place, and the buffer of
+    /// the [`PacketBuffer`] is reused for the result.
+    pub fn encrypt(&self, data: PacketBuffer) -> BytesMut {
+        self.encrypt_with(Cipher::Aes256Gcm, data)
+    }
 
-        let cipher = Aes256Gcm::new(&key);
-        let tag = cipher
-            .encrypt_in_place_detached(&nonce, &[], &mut data.buf[..data.size])
-            .expect("Encryption can't fail; qed.");
+    /// Encrypt a [`PacketBuffer`] using the `SharedSecret` as key, with the given [`Cipher`].
+    ///
+    /// This works like [`SharedSecret::encrypt`], but if the cipher is not AES-256-GCM, its
+    /// identifier is appended after the nonce. The identifier is part of the encrypted data, so it
+    /// is carried unchanged from this node to the receiver, whatever the nodes in between.
+    pub fn encrypt_with(&self, cipher: Cipher, mut data: PacketBuffer) -> BytesMut {
+        let (tag, nonce) = match cipher {
+            Cipher::Aes256Gcm => {
+                let nonce = Aes256Gcm::generate_nonce(OsRng);
+                let tag = Aes256Gcm::new(&self.0.into())
+                    .encrypt_in_place_detached(&nonce, &[], &mut data.buf[..data.size])
+                    .expect("Encryption can't fail; qed.");
+                (tag, nonce)
+            }
+            Cipher::ChaCha20Poly1305 => {
+                let nonce = ChaCha20Poly1305::generate_nonce(OsRng);
+                let tag = ChaCha20Poly1305::new(&self.0.into())
+                    .encrypt_in_place_detached(&nonce, &[], &mut data.buf[..data.size])
+                    .expect("Encryption can't fail; qed.");
+                (tag, nonce)
+            }
+        };
 
         data.buf[data.size..data.size + AES_TAG_SIZE].clone_from_slice(tag.as_slice());
         data.buf[data.size + AES_TAG_SIZE..data.size + AES_TAG_SIZE + AES_NONCE_SIZE]
             .clone_from_slice(&nonce);
 
         data.buf.truncate(data.size + AES_NONCE_SIZE + AES_TAG_SIZE);
+        if cipher == Cipher::ChaCha20Poly1305 {
+            // Buffers are allocated with spare capacity for this, so this does not reallocate.
+            data.buf.push(CHACHA20_POLY1305_ID);
+        }
 
-        data.buf
+        // The buffer is uniquely owned, so this conversion does not copy.
+        BytesMut::from(Bytes::from(data.buf))
     }
 
     /// Decrypt a message previously encrypted with an equivalent `SharedSecret`. In other words, a
@@ -164,38 +322,81 @@ impl SharedSecret {
     /// If the passed in data to decrypt does not contain a valid nonce, decryption fails and an
     /// opaque error is returned. As an extension to this, if the data is not of sufficient length
     /// to contain a valid nonce, an error is returned immediately.
-    pub fn decrypt(&self, mut data: Vec<u8>) -> Result<PacketBuffer, DecryptionError> {
+    ///
+    /// The data is decrypted in place. It is only copied if the buffer is shared, which is the
+    /// case if it is still part of the read buffer of a connection.
+    ///
+    /// Data encrypted with any [`Cipher`] can be decrypted. If the data ends with the identifier
+    /// of ChaCha20-Poly1305, that is tried first. Since data encrypted with AES-256-GCM has no
+    /// identifier, its nonce can end with the same byte, so if that fails AES-256-GCM is tried on
+    /// the full data. The tag is verified before anything is decrypted, so a failed attempt leaves
+    /// the data untouched.
+    pub fn decrypt(&self, mut data: BytesMut) -> Result<PacketBuffer, DecryptionError> {
         // Make sure we have sufficient data (i.e. a nonce).
         if data.len() < AES_NONCE_SIZE + AES_TAG_SIZE + DATA_HEADER_SIZE {
             return Err(DecryptionError);
         }
 
-        let data_len = data.len();
-
-        let key: Key<Aes256Gcm> = self.0.into();
+        if data.len() > AES_NONCE_SIZE + AES_TAG_SIZE + DATA_HEADER_SIZE
+            && data[data.len() - 1] == CHACHA20_POLY1305_ID
         {
-            let (data, nonce) = data.split_at_mut(data_len - AES_NONCE_SIZE);
-            let (data, tag) = data.split_at_mut(data.len() - AES_TAG_SIZE);
-
-            let cipher = Aes256Gcm::new(&key);
-            cipher
-                .decrypt_in_place_detached((&*nonce).into(), &[], data, (&*tag).into())
-                .map_err(|_| DecryptionError)?;
+            let data_len = data.len() - CIPHER_ID_SIZE;
+            if self
+                .decrypt_in_place(Cipher::ChaCha20Poly1305, &mut data[..data_len])
+                .is_ok()
+            {
+                return Ok(PacketBuffer {
+                    // We did not remove the scratch space used for TAG, NONCE and the identifier.
+                    size: data_len - AES_TAG_SIZE - AES_NONCE_SIZE,
+                    buf: data.into(),
+                });
+            }
         }
 
+        self.decrypt_in_place(Cipher::Aes256Gcm, &mut data)?;
+
         Ok(PacketBuffer {
             // We did not remove the scratch space used for TAG and NONCE.
             size: data.len() - AES_TAG_SIZE - AES_NONCE_SIZE,
-            buf: data,
+            buf: data.into(),
         })
     }
+
+    /// Decrypt data with a [`Cipher`], which is followed by the tag and nonce.
+    fn decrypt_in_place(&self, cipher: Cipher, data: &mut [u8]) -> Result<(), DecryptionError> {
+        let data_len = data.len();
+        let (data, nonce) = data.split_at_mut(data_len - AES_NONCE_SIZE);
+        let (data, tag) = data.split_at_mut(data.len() - AES_TAG_SIZE);
+
+        match cipher {
+            Cipher::Aes256Gcm => Aes256Gcm::new(&self.0.into()).decrypt_in_place_detached(
+                (&*nonce).into(),
+                &[],
+                data,
+                (&*tag).into(),
+            ),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(&self.0.into())
+                .decrypt_in_place_detached((&*nonce).into(), &[], data, (&*tag).into()),
+        }
+        .map_err(|_| DecryptionError)
+    }
 }
 
 impl PacketBuffer {
     /// Create a new blank `PacketBuffer`.
     pub fn new() -> Self {
         Self {
-            buf: vec![0; PACKET_BUFFER_SIZE],
+            buf: zeroed_buffer(PACKET_BUFFER_SIZE),
+            size: 0,
+        }
+    }
+
+    /// Create a new blank `PacketBuffer` which can hold `capacity` bytes of data. This is only
+    /// needed for data which does not fit in a regular `PacketBuffer`, which will need to be
+    /// fragmented before it is sent.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: zeroed_buffer(capacity + AES_TAG_SIZE + AES_NONCE_SIZE + DATA_HEADER_SIZE),
             size: 0,
         }
     }
@@ -237,6 +438,14 @@ impl PacketBuffer {
     }
 }
 
+/// Allocate a zeroed buffer of `size` bytes, with spare capacity to append the identifier of the
+/// [`Cipher`] once the content is encrypted.
+fn zeroed_buffer(size: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(size + CIPHER_ID_SIZE);
+    buf.resize(size, 0);
+    buf
+}
+
 impl Default for PacketBuffer {
     fn default() -> Self {
         Self::new()
@@ -369,7 +578,12 @@ impl fmt::Debug for PacketBuffer {
 
 #[cfg(test)]
 mod tests {
-    use super::{PacketBuffer, SecretKey, AES_NONCE_SIZE, AES_TAG_SIZE, DATA_HEADER_SIZE};
+    use bytes::BytesMut;
+
+    use super::{
+        Cipher, PacketBuffer, RemoteCiphers, SecretKey, SharedSecret, AES_NONCE_SIZE, AES_TAG_SIZE,
+        CIPHER_ID_SIZE, DATA_HEADER_SIZE,
+    };
 
     #[test]
     /// Test if encryption works in general. We just create some random value and encrypt it.
@@ -424,6 +638,96 @@ mod tests {
         assert_eq!(&*original, &data[..]);
     }
 
+    #[test]
+    /// Encrypt a value with every cipher and decrypt it again. Decryption must detect the cipher
+    /// by itself.
+    fn encrypt_decrypt_roundtrip_every_cipher() {
+        let k1 = SecretKey::new();
+        let k2 = SecretKey::new();
+
+        let ss1 = k1.shared_secret(&(&k2).into());
+        let ss2 = k2.shared_secret(&(&k1).into());
+
+        let data = b"a9 3kf0z 02n-cq34 zck2 1p";
+        let packet = || {
+            let mut pb = PacketBuffer::new();
+            pb.buffer_mut()[..data.len()].copy_from_slice(data);
+            pb.set_size(data.len());
+            pb
+        };
+
+        for (cipher, id_size) in [
+            (Cipher::Aes256Gcm, 0),
+            (Cipher::ChaCha20Poly1305, CIPHER_ID_SIZE),
+        ] {
+            let res = ss1.encrypt_with(cipher, packet());
+            assert_eq!(
+                res.len(),
+                data.len() + DATA_HEADER_SIZE + AES_TAG_SIZE + AES_NONCE_SIZE + id_size
+            );
+
+            let original = ss2.decrypt(res).expect("Decryption works");
+            assert_eq!(&*original, &data[..]);
+        }
+    }
+
+    #[test]
+    /// Decrypt fixed data for every cipher, so the format of encrypted data can't change by
+    /// accident. The AES-256-GCM nonce ends with the identifier of ChaCha20-Poly1305, which must
+    /// not prevent decryption.
+    fn decrypt_known_answer_every_cipher() {
+        let ss = SharedSecret([0x42; 32]);
+        // User data header followed by the data.
+        let plaintext = b"\0\0\0\0mycelium";
+
+        // Encrypted data, tag and nonce, which ends with the identifier of ChaCha20-Poly1305.
+        let aes_256_gcm = [
+            0xea, 0xf9, 0x84, 0x2e, 0x4a, 0x91, 0xd4, 0xbe, 0xab, 0x06, 0xca, 0xa0, 0xe1, 0xdf,
+            0x24, 0x76, 0xd9, 0xb3, 0xb2, 0x57, 0x09, 0x34, 0x03, 0x76, 0xf0, 0xa2, 0x43, 0x9e,
+            0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x01,
+        ];
+        // Encrypted data, tag, nonce and the identifier of the cipher.
+        let chacha20_poly1305 = [
+            0xcf, 0x70, 0xf9, 0x8c, 0x04, 0xbc, 0xfb, 0xef, 0x95, 0xe4, 0x7d, 0xab, 0x3c, 0xf9,
+            0x02, 0x33, 0xc8, 0x25, 0xc6, 0x4b, 0x08, 0x28, 0x40, 0x4a, 0xc4, 0x6b, 0x22, 0x22,
+            0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x01,
+        ];
+
+        for data in [&aes_256_gcm[..], &chacha20_poly1305[..]] {
+            let original = ss.decrypt(BytesMut::from(data)).expect("Decryption works");
+            assert_eq!(&original.header()[..], &plaintext[..DATA_HEADER_SIZE]);
+            assert_eq!(&*original, &plaintext[DATA_HEADER_SIZE..]);
+        }
+
+        // Without its identifier, ChaCha20-Poly1305 data is taken to be AES-256-GCM.
+        let data = &chacha20_poly1305[..chacha20_poly1305.len() - CIPHER_ID_SIZE];
+        assert!(ss.decrypt(BytesMut::from(data)).is_err());
+    }
+
+    #[test]
+    /// AES-256-GCM is only selected if both nodes have instructions for AES, or if the remote
+    /// does not support anything else.
+    fn select_cipher() {
+        let remote = |chacha20_poly1305, aes_accelerated| RemoteCiphers {
+            chacha20_poly1305,
+            aes_accelerated,
+        };
+
+        assert_eq!(Cipher::select(true, remote(true, true)), Cipher::Aes256Gcm);
+        assert_eq!(
+            Cipher::select(true, remote(true, false)),
+            Cipher::ChaCha20Poly1305
+        );
+        assert_eq!(
+            Cipher::select(false, remote(true, true)),
+            Cipher::ChaCha20Poly1305
+        );
+        assert_eq!(
+            Cipher::select(false, remote(false, false)),
+            Cipher::Aes256Gcm
+        );
+    }
+
     #[test]
     /// Test if PacketBufferHeaderMut actually modifies the PacketBuffer storage.
     fn modify_header() {
@@ -447,4 +751,40 @@ mod tests {
         assert_eq!(pb.buffer().len(), super::PACKET_SIZE);
         assert_eq!(pb.buffer_mut().len(), super::PACKET_SIZE);
     }
+
+    #[tokio::test]
+    /// Save a key to a file and load it again, the loaded key must result in the same address.
+    async fn save_load_key_file() {
+        let path = std::env::temp_dir().join(format!("mycelium-key-{}", std::process::id()));
+        let key = SecretKey::new();
+
+        key.save_to_file(&path).await.expect("Can save key file");
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let loaded = SecretKey::load_from_file(&path)
+            .await
+            .expect("Can load key file");
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.as_bytes(), key.as_bytes());
+        assert_eq!(
+            super::PublicKey::from(&loaded).address(),
+            super::PublicKey::from(&key).address()
+        );
+    }
+
+    #[test]
+    /// The subnet of a key is the /64 containing its address.
+    fn subnet_contains_address() {
+        let pk = super::PublicKey::from(&SecretKey::new());
+        let subnet = pk.subnet();
+
+        assert_eq!(subnet.prefix_len(), 64);
+        assert!(subnet.contains_ip(pk.address().into()));
+        assert_eq!(subnet.address(), subnet.network());
+    }
 }
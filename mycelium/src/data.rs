@@ -1,11 +1,44 @@
-use std::net::{IpAddr, Ipv6Addr};
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
-use etherparse::{icmpv6::DestUnreachableCode, Icmpv6Type, PacketBuilder};
+use bytes::BytesMut;
+use etherparse::{
+    icmpv6::{DestUnreachableCode, TimeExceededCode},
+    Icmpv6Type, PacketBuilder,
+};
 use futures::{Sink, SinkExt, Stream, StreamExt};
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    time::Instant,
+};
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, error, trace, warn};
 
-use crate::{crypto::PacketBuffer, metrics::Metrics, packet::DataPacket, router::Router};
+use crate::{
+    capture::PacketCapture,
+    crypto::{self, Cipher, PacketBuffer, RemoteCiphers, PACKET_SIZE},
+    echo::{EchoReplyConfig, EchoResponder},
+    in_global_subnet,
+    ipv4_mapping::{self, Ipv4Mappings, IPV4_MIN_HEADER_SIZE},
+    metrics::{DropReason, Metrics},
+    packet::DataPacket,
+    peer::Peer,
+    router::Router,
+};
+
+use fragment::{Reassembler, MAX_FRAGMENTED_PAYLOAD_SIZE};
+
+mod compression;
+mod fragment;
 
 /// Current version of the user data header.
 const USER_DATA_VERSION: u8 = 1;
@@ -20,6 +53,28 @@ const USER_DATA_MESSAGE_TYPE: u8 = 1;
 /// intermediate nodes send back icmp data, as the original data is encrypted.
 const USER_DATA_OOB_ICMP: u8 = 2;
 
+/// Type value indicating a fragment of a payload which does not fit in a single packet.
+const USER_DATA_FRAGMENT_TYPE: u8 = 3;
+
+/// Type value indicating an IPv4 packet carried over the overlay.
+const USER_DATA_L3_IPV4_TYPE: u8 = 4;
+
+/// Index of the flags byte in the user data header.
+const USER_DATA_FLAGS_INDEX: usize = 3;
+
+/// Flag set in the user data header by nodes which can decompress compressed content.
+const USER_DATA_FLAG_ACCEPTS_COMPRESSION: u8 = 0b0000_0001;
+
+/// Flag set in the user data header if the content is compressed.
+const USER_DATA_FLAG_COMPRESSED: u8 = 0b0000_0010;
+
+/// Flag set in the user data header by nodes which can decrypt content encrypted with
+/// ChaCha20-Poly1305.
+const USER_DATA_FLAG_ACCEPTS_CHACHA20_POLY1305: u8 = 0b0000_0100;
+
+/// Flag set in the user data header by nodes with CPU instructions for AES.
+const USER_DATA_FLAG_AES_ACCELERATED: u8 = 0b0000_1000;
+
 /// Minimum size in bytes of an IPv6 header.
 const IPV6_MIN_HEADER_SIZE: usize = 40;
 
@@ -38,18 +93,62 @@ const IP_VERSION_MASK: u8 = 0b1111_0000;
 /// must be masked first.
 const IPV6_VERSION_BYTE: u8 = 0b0110_0000;
 
+/// Version byte of an IP header indicating IPv4. Since the version is only 4 bits, the lower bits
+/// must be masked first.
+const IPV4_VERSION_BYTE: u8 = 0b0100_0000;
+
+/// Protocol number of TCP in an IP header.
+const TCP_PROTOCOL: u8 = 6;
+
+/// Protocol number of UDP in an IP header.
+const UDP_PROTOCOL: u8 = 17;
+
+/// Protocol number of ICMPv6 in an IPv6 header.
+const ICMPV6_PROTOCOL: u8 = 58;
+
+/// ICMPv6 types of the Neighbor Discovery messages defined in RFC 4861: Router Solicitation (133),
+/// Router Advertisement (134), Neighbor Solicitation (135), Neighbor Advertisement (136) and
+/// Redirect (137).
+const NEIGHBOR_DISCOVERY_TYPES: RangeInclusive<u8> = 133..=137;
+
+/// Mask applied to the second byte of an IPv6 header to extract the start of the flow label.
+const IPV6_FLOW_LABEL_MASK: u8 = 0b0000_1111;
+
 /// Default hop limit for message packets. For now this is set to 64 hops.
 ///
 /// For regular l3 packets, we copy the hop limit from the packet itself. We can't do that here, so
 /// 64 is used as sane default.
 const MESSAGE_HOP_LIMIT: u8 = 64;
 
+/// Hop limit of ICMP packets generated by this node.
+const ICMP_HOP_LIMIT: u8 = 64;
+
+/// Capacity of the queue of packets waiting to be processed by a single packet worker.
+const PACKET_WORKER_QUEUE_SIZE: usize = 1000;
+
+/// Minimum time between log messages about data packets which failed decryption.
+const DECRYPTION_FAILURE_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
 /// The DataPlane manages forwarding/receiving of local data packets to the [`Router`], and the
 /// encryption/decryption of them.
 ///
 /// DataPlane itself can be cloned, but this is not cheap on the router and should be avoided.
 pub struct DataPlane<M> {
     router: Router<M>,
+    /// ID of the next fragmented payload sent by this node.
+    fragment_id: Arc<AtomicU32>,
+    /// Whether to compress data sent to remotes which accept compressed content.
+    compress: bool,
+    /// Remotes which indicated they accept compressed content.
+    accepts_compression: Arc<RwLock<HashSet<Ipv6Addr>>>,
+    /// Whether the CPU has instructions for AES.
+    aes_accelerated: bool,
+    /// IPv4 addresses of nodes to which IPv4 packets are forwarded.
+    ipv4_mappings: Arc<Ipv4Mappings>,
+    /// Capture of the plaintext packets exchanged with remotes, if enabled.
+    capture: Option<PacketCapture>,
+    /// Replies to echo requests for the overlay address of the node, if enabled.
+    echo_responder: Option<Arc<EchoResponder>>,
 }
 
 impl<M> DataPlane<M>
@@ -60,30 +159,101 @@ where
     ///
     /// `l3_packet_stream` is a stream of l3 packets from the host, usually read from a TUN interface.
     /// `l3_packet_sink` is a sink for l3 packets received from a romte, usually send to a TUN interface,
+    /// If `compress` is set, data sent to remotes which support it is compressed if this makes it
+    /// smaller. Packets are processed by `workers` tasks in each direction, so encryption and
+    /// decryption is spread over multiple cores. Packets of the same flow are always processed by
+    /// the same worker, so they stay in order. IPv4 packets from the host are forwarded to the
+    /// nodes their destination is mapped to in `ipv4_mappings`, and IPv4 packets from remotes are
+    /// only accepted if their source is mapped to the remote. If `capture` is set, the plaintext
+    /// IP packets exchanged with remotes are written to it. If `echo_reply` is set, echo requests
+    /// for the overlay address of the node are answered by the node itself.
+    #[allow(clippy::too_many_arguments)]
     pub fn new<S, T, U>(
         router: Router<M>,
+        compress: bool,
+        workers: usize,
+        ipv4_mappings: Ipv4Mappings,
+        capture: Option<PacketCapture>,
+        echo_reply: Option<EchoReplyConfig>,
         l3_packet_stream: S,
         l3_packet_sink: T,
         message_packet_sink: U,
-        host_packet_source: UnboundedReceiver<DataPacket>,
+        host_packet_source: Receiver<(DataPacket, Option<Peer>)>,
     ) -> Self
     where
         S: Stream<Item = Result<PacketBuffer, std::io::Error>> + Send + Unpin + 'static,
         T: Sink<PacketBuffer> + Clone + Send + Unpin + 'static,
         T::Error: std::fmt::Display,
-        U: Sink<(PacketBuffer, IpAddr, IpAddr)> + Send + Unpin + 'static,
+        U: Sink<(PacketBuffer, IpAddr, IpAddr)> + Clone + Send + Unpin + 'static,
         U::Error: std::fmt::Display,
     {
-        let dp = Self { router };
+        let echo_responder = echo_reply.map(|config| {
+            Arc::new(EchoResponder::new(
+                router.node_public_key().address(),
+                config,
+            ))
+        });
+        let dp = Self {
+            router,
+            fragment_id: Arc::new(AtomicU32::new(rand::random())),
+            compress,
+            accepts_compression: Arc::new(RwLock::new(HashSet::new())),
+            aes_accelerated: crypto::aes_accelerated(),
+            ipv4_mappings: Arc::new(ipv4_mappings),
+            capture,
+            echo_responder,
+        };
 
-        tokio::spawn(
-            dp.clone()
-                .inject_l3_packet_loop(l3_packet_stream, l3_packet_sink.clone()),
-        );
-        tokio::spawn(dp.clone().extract_packet_loop(
-            l3_packet_sink,
-            message_packet_sink,
-            host_packet_source,
+        if workers <= 1 {
+            tokio::spawn(
+                dp.clone()
+                    .inject_l3_packet_loop(l3_packet_stream, l3_packet_sink.clone()),
+            );
+            tokio::spawn(dp.clone().extract_packet_loop(
+                l3_packet_sink,
+                message_packet_sink,
+                host_packet_source,
+            ));
+
+            return dp;
+        }
+
+        let mut inject_workers = Vec::with_capacity(workers);
+        let mut extract_workers = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let (inject_tx, inject_rx) = mpsc::channel(PACKET_WORKER_QUEUE_SIZE);
+            tokio::spawn(
+                dp.clone()
+                    .inject_l3_packet_loop(ReceiverStream::new(inject_rx), l3_packet_sink.clone()),
+            );
+            inject_workers.push(inject_tx);
+
+            let (extract_tx, extract_rx) = mpsc::channel(PACKET_WORKER_QUEUE_SIZE);
+            tokio::spawn(dp.clone().extract_packet_loop(
+                l3_packet_sink.clone(),
+                message_packet_sink.clone(),
+                extract_rx,
+            ));
+            extract_workers.push(extract_tx);
+        }
+
+        tokio::spawn(dispatch_packets(
+            l3_packet_stream,
+            inject_workers,
+            |packet: &Result<PacketBuffer, std::io::Error>| {
+                packet.as_ref().map(|p| flow_hash(p)).unwrap_or_default()
+            },
+        ));
+        // Fragments are reassembled per source, so all packets of a source must be processed by
+        // the same worker.
+        tokio::spawn(dispatch_packets(
+            ReceiverStream::new(host_packet_source),
+            extract_workers,
+            |(packet, _): &(DataPacket, Option<Peer>)| {
+                let mut hasher = DefaultHasher::new();
+                packet.src_ip.hash(&mut hasher);
+                hasher.finish()
+            },
         ));
 
         dp
@@ -113,6 +283,11 @@ where
 
             trace!("Received packet from tun");
 
+            if packet.first().map(|b| b & IP_VERSION_MASK) == Some(IPV4_VERSION_BYTE) {
+                self.inject_ipv4_packet(packet);
+                continue;
+            }
+
             // Parse an IPv6 header. We don't care about the full header in reality. What we want
             // to know is:
             // - This is an IPv6 header
@@ -127,11 +302,21 @@ where
 
             if packet.len() < IPV6_MIN_HEADER_SIZE {
                 trace!("Packet can't contain an IPv6 header");
+                self.router.packet_dropped(DropReason::Malformed);
                 continue;
             }
 
             if packet[0] & IP_VERSION_MASK != IPV6_VERSION_BYTE {
                 trace!("Packet is not IPv6");
+                self.router.packet_dropped(DropReason::Malformed);
+                continue;
+            }
+
+            // The TUN interface has no link layer, so there are no neighbors or routers to
+            // discover. Neighbor discovery messages of the host can't be routed in the overlay
+            // either, so they are dropped silently, rather than answered with an ICMP error.
+            if is_neighbor_discovery(&packet) {
+                trace!("Dropping neighbor discovery packet from host");
                 continue;
             }
 
@@ -147,37 +332,60 @@ where
             );
 
             trace!("Received packet from TUN with dest addr: {:?}", dst_ip);
-            // Check if the source address is part of 400::/7
-            let first_src_byte = src_ip.segments()[0] >> 8;
-            if !(0x04..0x06).contains(&first_src_byte) {
-                let mut icmp_packet = PacketBuffer::new();
-                let host = self.router.node_public_key().address().octets();
-                let icmp = PacketBuilder::ipv6(host, src_ip.octets(), 64).icmpv6(
-                    Icmpv6Type::DestinationUnreachable(
-                        DestUnreachableCode::SourceAddressFailedPolicy,
-                    ),
-                );
-                icmp_packet.set_size(icmp.size(packet.len().min(1280 - 48)));
-                let mut writer = &mut icmp_packet.buffer_mut()[..];
-                if let Err(e) = icmp.write(&mut writer, &packet[..packet.len().min(1280 - 48)]) {
-                    error!("Failed to construct ICMP packet: {e}");
-                    continue;
-                }
-                if let Err(e) = l3_packet_sink.send(icmp_packet).await {
-                    error!("Failed to send ICMP packet to host: {e}");
+            // Check if the source address is part of 400::/7. An exit node also accepts packets
+            // from outside of the global subnet, which its host forwards back to the nodes using
+            // it.
+            let valid_source = in_global_subnet(src_ip.into())
+                || (self.router.is_exit_node() && in_global_subnet(dst_ip.into()));
+            let unroutable = if !valid_source {
+                self.router.packet_dropped(DropReason::InvalidSource);
+                Some(Icmpv6Type::DestinationUnreachable(
+                    DestUnreachableCode::SourceAddressFailedPolicy,
+                ))
+            } else if hop_limit < 2 {
+                self.router.metrics().router_route_packet_ttl_expired();
+                self.router.packet_dropped(DropReason::HopLimitExceeded);
+                Some(Icmpv6Type::TimeExceeded(TimeExceededCode::HopLimitExceeded))
+            } else if !self
+                .router
+                .has_forwarding_route(dst_ip.into(), src_ip.into())
+            {
+                // No need to verify destination address, if it is not part of the global subnet
+                // there is no route for it.
+                self.router.metrics().router_route_packet_no_route();
+                self.router.packet_dropped(DropReason::NoRoute);
+                Some(Icmpv6Type::DestinationUnreachable(
+                    DestUnreachableCode::NoRoute,
+                ))
+            } else {
+                None
+            };
+
+            // The router can only send ICMP packets to remote nodes, so packets from the host
+            // which can't be forwarded are answered here instead of being dropped silently.
+            if let Some(icmp_type) = unroutable {
+                trace!("Can't forward packet from {src_ip} to {dst_ip}, replying with ICMP");
+                let host = self.router.node_public_key().address();
+                if let Some(icmp) = icmp_packet(icmp_type, host, src_ip, &packet) {
+                    if let Err(e) = l3_packet_sink.send(icmp).await {
+                        error!("Failed to send ICMP packet to host: {e}");
+                    }
                 }
                 continue;
             }
 
-            // No need to verify destination address, if it is not part of the global subnet there
-            // should not be a route for it, and therefore the route step will generate the
-            // appropriate ICMP.
+            if let Some(capture) = &self.capture {
+                capture.capture(src_ip.into(), dst_ip.into(), &packet);
+            }
 
             let mut header = packet.header_mut();
             header[0] = USER_DATA_VERSION;
             header[1] = USER_DATA_L3_TYPE;
 
-            if let Some(icmp) = self.encrypt_and_route_packet(src_ip, dst_ip, hop_limit, packet) {
+            let flow = flow_id(&packet);
+            if let Some(icmp) =
+                self.encrypt_and_route_packet(src_ip, dst_ip, hop_limit, flow, packet)
+            {
                 if let Err(e) = l3_packet_sink.send(icmp).await {
                     error!("Could not forward icmp packet back to TUN interface {e}");
                 }
@@ -187,6 +395,55 @@ where
         warn!("Data inject loop from host to router ended");
     }
 
+    /// Forward an IPv4 packet from the host to the node its destination is mapped to. IPv4 is
+    /// not routed in the overlay, so no ICMP is sent back if the packet can't be forwarded.
+    fn inject_ipv4_packet(&self, mut packet: PacketBuffer) {
+        if packet.len() < IPV4_MIN_HEADER_SIZE {
+            trace!("Packet can't contain an IPv4 header");
+            self.router.packet_dropped(DropReason::Malformed);
+            return;
+        }
+
+        let ttl = packet[8];
+        let dst_ip = Ipv4Addr::from(
+            <&[u8] as TryInto<[u8; 4]>>::try_into(&packet[16..20])
+                .expect("Static range bounds on slice are correct length"),
+        );
+        let Some(overlay_dst) = self.ipv4_mappings.overlay_ip(dst_ip) else {
+            trace!("Dropping IPv4 packet to {dst_ip}, which is not mapped to a node");
+            self.router.packet_dropped(DropReason::NoRoute);
+            return;
+        };
+        if ttl < 2 {
+            self.router.metrics().router_route_packet_ttl_expired();
+            self.router.packet_dropped(DropReason::HopLimitExceeded);
+            return;
+        }
+
+        let overlay_src = self.router.node_public_key().address();
+        if !self
+            .router
+            .has_forwarding_route(overlay_dst.into(), overlay_src.into())
+        {
+            trace!("Dropping IPv4 packet to {dst_ip}, there is no route to {overlay_dst}");
+            self.router.metrics().router_route_packet_no_route();
+            self.router.packet_dropped(DropReason::NoRoute);
+            return;
+        }
+
+        if let Some(capture) = &self.capture {
+            capture.capture(overlay_src.into(), overlay_dst.into(), &packet);
+        }
+
+        let mut header = packet.header_mut();
+        header[0] = USER_DATA_VERSION;
+        header[1] = USER_DATA_L3_IPV4_TYPE;
+
+        // An ICMPv6 reply would not make sense to the IPv4 sender, so it is dropped.
+        let flow = flow_id(&packet);
+        let _ = self.encrypt_and_route_packet(overlay_src, overlay_dst, ttl, flow, packet);
+    }
+
     /// Inject a new packet where the content is a `message` fragment.
     pub fn inject_message_packet(
         &self,
@@ -198,11 +455,12 @@ where
         header[0] = USER_DATA_VERSION;
         header[1] = USER_DATA_MESSAGE_TYPE;
 
-        self.encrypt_and_route_packet(src_ip, dst_ip, MESSAGE_HOP_LIMIT, packet);
+        self.encrypt_and_route_packet(src_ip, dst_ip, MESSAGE_HOP_LIMIT, 0, packet);
     }
 
     /// Encrypt the content of a packet based on the destination key, and then inject the packet
-    /// into the [`Router`] for processing.
+    /// into the [`Router`] for processing. Packets which are too large are fragmented first. The
+    /// `flow` is set on the injected packets, see [`flow_id`].
     ///
     /// If no key exists for the destination, the content can'be encrypted, the packet is not injected
     /// into the router, and a packet is returned containing an ICMP packet. Note that a return
@@ -212,7 +470,8 @@ where
         src_ip: Ipv6Addr,
         dst_ip: Ipv6Addr,
         hop_limit: u8,
-        packet: PacketBuffer,
+        flow: u8,
+        mut packet: PacketBuffer,
     ) -> Option<PacketBuffer> {
         // Get shared secret from node and dest address
         let shared_secret = match self.router.get_shared_secret_from_dest(dst_ip.into()) {
@@ -222,33 +481,74 @@ where
                     "No entry found for destination address {}, dropping packet",
                     dst_ip
                 );
+                self.router.packet_dropped(DropReason::NoRoute);
 
-                let mut pb = PacketBuffer::new();
-                // From self to self
-                let icmp = PacketBuilder::ipv6(src_ip.octets(), src_ip.octets(), hop_limit).icmpv6(
+                return icmp_packet(
                     Icmpv6Type::DestinationUnreachable(DestUnreachableCode::NoRoute),
+                    self.router.node_public_key().address(),
+                    src_ip,
+                    &packet,
                 );
-                // Scale to max size if needed
-                let orig_buf_end = packet
-                    .buffer()
-                    .len()
-                    .min(MIN_IPV6_MTU - IPV6_MIN_HEADER_SIZE - ICMP6_HEADER_SIZE);
-                pb.set_size(icmp.size(orig_buf_end));
-                let mut b = pb.buffer_mut();
-                if let Err(e) = icmp.write(&mut b, &packet.buffer()[..orig_buf_end]) {
-                    error!("Failed to construct no route to host ICMP packet {e}");
-                    return None;
-                }
-
-                return Some(pb);
             }
         };
 
+        // Remotes which never sent data only support AES-256-GCM, as far as we know.
+        let cipher = self.router.cipher(dst_ip).unwrap_or(Cipher::Aes256Gcm);
+
+        // Fragment the packet if it does not fit on the path to the destination, which can be
+        // smaller than a regular packet if a node on the path reported so.
+        let max_size = self.router.path_mtu(dst_ip).unwrap_or(PACKET_SIZE);
+        if packet.len() > max_size {
+            if packet.len() > MAX_FRAGMENTED_PAYLOAD_SIZE {
+                debug!(
+                    "Dropping packet of {} bytes, which is too large to fragment",
+                    packet.len()
+                );
+                self.router.packet_dropped(DropReason::TooLarge);
+                return None;
+            }
+
+            let id = self.fragment_id.fetch_add(1, Ordering::Relaxed);
+            for fragment in fragment::fragment(&packet, id, max_size) {
+                self.router.route_packet(DataPacket {
+                    dst_ip,
+                    src_ip,
+                    hop_limit,
+                    flow,
+                    raw_data: shared_secret.encrypt_with(cipher, fragment),
+                });
+            }
+
+            return None;
+        }
+
+        packet.header_mut()[USER_DATA_FLAGS_INDEX] |= USER_DATA_FLAG_ACCEPTS_COMPRESSION
+            | USER_DATA_FLAG_ACCEPTS_CHACHA20_POLY1305
+            | if self.aes_accelerated {
+                USER_DATA_FLAG_AES_ACCELERATED
+            } else {
+                0
+            };
+        if self.compress
+            && self
+                .accepts_compression
+                .read()
+                .expect("Accepts compression lock is not poisoned; qed")
+                .contains(&dst_ip)
+        {
+            let original_size = packet.len();
+            packet = compression::compress(packet);
+            self.router
+                .metrics()
+                .data_plane_payload_compressed(original_size, packet.len());
+        }
+
         self.router.route_packet(DataPacket {
             dst_ip,
             src_ip,
             hop_limit,
-            raw_data: shared_secret.encrypt(packet),
+            flow,
+            raw_data: shared_secret.encrypt_with(cipher, packet),
         });
 
         None
@@ -258,14 +558,17 @@ where
         self,
         mut l3_packet_sink: T,
         mut message_packet_sink: U,
-        mut host_packet_source: UnboundedReceiver<DataPacket>,
+        mut host_packet_source: Receiver<(DataPacket, Option<Peer>)>,
     ) where
         T: Sink<PacketBuffer> + Send + Unpin + 'static,
         T::Error: std::fmt::Display,
         U: Sink<(PacketBuffer, IpAddr, IpAddr)> + Send + Unpin + 'static,
         U::Error: std::fmt::Display,
     {
-        while let Some(data_packet) = host_packet_source.recv().await {
+        let mut reassembler = Reassembler::new(self.router.metrics().clone());
+        let mut decryption_failures = DecryptionFailureLog::default();
+
+        while let Some((data_packet, ingress)) = host_packet_source.recv().await {
             // decrypt & send to TUN interface
             let shared_secret = if let Some(ss) = self
                 .router
@@ -274,12 +577,27 @@ where
                 ss
             } else {
                 trace!("Received packet from unknown sender");
+                self.router.packet_dropped(DropReason::UnknownSource);
                 continue;
             };
-            let mut decrypted_packet = match shared_secret.decrypt(data_packet.raw_data) {
+            let decrypted_packet = match shared_secret.decrypt(data_packet.raw_data) {
                 Ok(data) => data,
                 Err(_) => {
-                    debug!("Dropping data packet with invalid encrypted content");
+                    // Corrupted or forged packets are dropped, they must not take down the
+                    // worker.
+                    self.router.packet_dropped(DropReason::DecryptionFailed);
+                    if let Some(peer) = &ingress {
+                        peer.decryption_failed();
+                    }
+                    if let Some(suppressed) = decryption_failures.record(Instant::now()) {
+                        let via = ingress
+                            .as_ref()
+                            .map_or("local node", |peer| peer.connection_identifier().as_str());
+                        warn!(
+                            "Data packet from {} via {via} failed decryption ({suppressed} more since last message)",
+                            data_packet.src_ip,
+                        );
+                    }
                     continue;
                 }
             };
@@ -288,21 +606,116 @@ where
             let header = decrypted_packet.header();
             if header[0] != USER_DATA_VERSION {
                 trace!("Dropping decrypted packet with unknown header version");
+                self.router.packet_dropped(DropReason::Malformed);
                 continue;
             }
 
+            if self.compress && header[1] != USER_DATA_FRAGMENT_TYPE {
+                self.update_accepts_compression(
+                    data_packet.src_ip,
+                    header[USER_DATA_FLAGS_INDEX] & USER_DATA_FLAG_ACCEPTS_COMPRESSION != 0,
+                );
+            }
+
+            if header[1] != USER_DATA_FRAGMENT_TYPE {
+                let flags = header[USER_DATA_FLAGS_INDEX];
+                let remote = RemoteCiphers {
+                    chacha20_poly1305: flags & USER_DATA_FLAG_ACCEPTS_CHACHA20_POLY1305 != 0,
+                    aes_accelerated: flags & USER_DATA_FLAG_AES_ACCELERATED != 0,
+                };
+                self.router.set_cipher(
+                    data_packet.src_ip,
+                    Cipher::select(self.aes_accelerated, remote),
+                );
+            }
+
+            // Fragments are only handled once the full payload is received.
+            let mut decrypted_packet = if header[1] == USER_DATA_FRAGMENT_TYPE {
+                match reassembler.insert(data_packet.src_ip, decrypted_packet) {
+                    Some(packet) => packet,
+                    None => continue,
+                }
+            } else {
+                decrypted_packet
+            };
+
+            if decrypted_packet.header()[USER_DATA_FLAGS_INDEX] & USER_DATA_FLAG_COMPRESSED != 0 {
+                decrypted_packet = match compression::decompress(&decrypted_packet) {
+                    Some(packet) => packet,
+                    None => {
+                        debug!(
+                            "Dropping packet from {} with invalid compressed content",
+                            data_packet.src_ip
+                        );
+                        self.router.packet_dropped(DropReason::Malformed);
+                        continue;
+                    }
+                };
+            }
+
             // Route based on packet type.
-            match header[1] {
+            match decrypted_packet.header()[1] {
                 USER_DATA_L3_TYPE => {
                     let real_packet = decrypted_packet.buffer_mut();
                     if real_packet.len() < IPV6_MIN_HEADER_SIZE {
                         debug!(
                             "Decrypted packet is too short, can't possibly be a valid IPv6 packet"
                         );
+                        self.router.packet_dropped(DropReason::Malformed);
                         continue;
                     }
                     // Adjust the hop limit in the decrypted packet to the new value.
                     real_packet[7] = data_packet.hop_limit;
+                    if let Some(capture) = &self.capture {
+                        capture.capture(
+                            data_packet.src_ip.into(),
+                            data_packet.dst_ip.into(),
+                            real_packet,
+                        );
+                    }
+                    if let Some(echo) = &self.echo_responder {
+                        if echo.is_echo_request(&decrypted_packet) {
+                            self.reply_to_echo_request(echo, &decrypted_packet);
+                            if !echo.forward_to_tun() {
+                                continue;
+                            }
+                        }
+                    }
+                    if let Err(e) = l3_packet_sink.send(decrypted_packet).await {
+                        error!("Failed to send packet on local TUN interface: {e}",);
+                        continue;
+                    }
+                }
+                USER_DATA_L3_IPV4_TYPE => {
+                    let real_packet = decrypted_packet.buffer_mut();
+                    if real_packet.len() < IPV4_MIN_HEADER_SIZE
+                        || real_packet[0] & IP_VERSION_MASK != IPV4_VERSION_BYTE
+                    {
+                        debug!("Decrypted packet is not a valid IPv4 packet");
+                        self.router.packet_dropped(DropReason::Malformed);
+                        continue;
+                    }
+                    let src_ip = Ipv4Addr::from(
+                        <&[u8] as TryInto<[u8; 4]>>::try_into(&real_packet[12..16])
+                            .expect("Static range bounds on slice are correct length"),
+                    );
+                    if !self.ipv4_mappings.is_mapped_to(src_ip, data_packet.src_ip) {
+                        debug!(
+                            "Dropping IPv4 packet from {src_ip}, which is not mapped to sender {}",
+                            data_packet.src_ip
+                        );
+                        self.router.packet_dropped(DropReason::InvalidSource);
+                        continue;
+                    }
+                    // Adjust the TTL in the decrypted packet to the new value.
+                    ipv4_mapping::set_ttl(real_packet, data_packet.hop_limit);
+                    if let Some(capture) = &self.capture {
+                        capture.capture(
+                            data_packet.src_ip.into(),
+                            data_packet.dst_ip.into(),
+                            real_packet,
+                        );
+                    }
                     if let Err(e) = l3_packet_sink.send(decrypted_packet).await {
                         error!("Failed to send packet on local TUN interface: {e}",);
                         continue;
@@ -327,10 +740,12 @@ where
                         debug!(
                             "Decrypted packet is too short, can't possibly be a valid IPv6 ICMP packet"
                         );
+                        self.router.packet_dropped(DropReason::Malformed);
                         continue;
                     }
                     if real_packet.len() > MIN_IPV6_MTU + 16 {
                         debug!("Discarding ICMP packet which is too large");
+                        self.router.packet_dropped(DropReason::Malformed);
                         continue;
                     }
 
@@ -344,6 +759,7 @@ where
                             key
                         } else {
                             debug!("Can't decrypt OOB ICMP packet from unknown host");
+                            self.router.packet_dropped(DropReason::UnknownSource);
                             continue;
                         };
 
@@ -356,6 +772,7 @@ where
                                 "Dropping malformed OOB ICMP packet from {} for {e}",
                                 data_packet.src_ip
                             );
+                            self.router.packet_dropped(DropReason::Malformed);
                             continue;
                         }
                     };
@@ -368,15 +785,25 @@ where
                                 "Dropping OOB ICMP packet from {} with malformed ICMP header ({e})",
                                 data_packet.src_ip
                             );
+                            self.router.packet_dropped(DropReason::Malformed);
                             continue;
                         }
                     };
 
+                    // The original packet is usually too large to be included, so it can't be
+                    // decrypted. Further data for the destination is fragmented to fit the
+                    // reported MTU, so the host does not need to know about it.
+                    if let Icmpv6Type::PacketTooBig { mtu } = header.icmp_type {
+                        self.router.update_path_mtu(dec_ip, mtu as usize);
+                        continue;
+                    }
+
                     // Where are the leftover bytes coming from
-                    let orig_pb = match key.decrypt(body[..body.len()].to_vec()) {
+                    let orig_pb = match key.decrypt(BytesMut::from(body)) {
                         Ok(pb) => pb,
                         Err(e) => {
                             warn!("Failed to decrypt ICMP data body {e}");
+                            self.router.packet_dropped(DropReason::DecryptionFailed);
                             continue;
                         }
                     };
@@ -404,6 +831,7 @@ where
                 }
                 _ => {
                     trace!("Dropping decrypted packet with unknown protocol type");
+                    self.router.packet_dropped(DropReason::Malformed);
                     continue;
                 }
             }
@@ -411,6 +839,187 @@ where
 
         warn!("Extract loop from router to host ended");
     }
+
+    /// Reply to an echo request for the overlay address of the node, unless the rate limit of
+    /// replies is exceeded.
+    fn reply_to_echo_request(&self, echo: &EchoResponder, request: &[u8]) {
+        let Some(mut reply) = echo.reply(request) else {
+            trace!("Dropping echo request, the rate limit of echo replies is exceeded");
+            return;
+        };
+
+        let dst_ip = Ipv6Addr::from(
+            <&[u8] as TryInto<[u8; 16]>>::try_into(&request[8..24])
+                .expect("Static range bounds on slice are correct length"),
+        );
+        let mut header = reply.header_mut();
+        header[0] = USER_DATA_VERSION;
+        header[1] = USER_DATA_L3_TYPE;
+
+        let flow = flow_id(&reply);
+        // If there is no route back to the sender, the reply is dropped.
+        let _ = self.encrypt_and_route_packet(
+            self.router.node_public_key().address(),
+            dst_ip,
+            ICMP_HOP_LIMIT,
+            flow,
+            reply,
+        );
+    }
+
+    /// Record whether `remote` accepts compressed content.
+    fn update_accepts_compression(&self, remote: Ipv6Addr, accepts: bool) {
+        // Avoid taking the write lock if nothing changes, which is by far the common case.
+        if self
+            .accepts_compression
+            .read()
+            .expect("Accepts compression lock is not poisoned; qed")
+            .contains(&remote)
+            == accepts
+        {
+            return;
+        }
+
+        let mut accepts_compression = self
+            .accepts_compression
+            .write()
+            .expect("Accepts compression lock is not poisoned; qed");
+        if accepts {
+            accepts_compression.insert(remote);
+        } else {
+            accepts_compression.remove(&remote);
+        }
+    }
+}
+
+/// Limits the rate of log messages about data packets which failed decryption, so a flood of bad
+/// packets does not flood the logs as well.
+#[derive(Debug, Default)]
+struct DecryptionFailureLog {
+    /// Time of the last log message, if any.
+    last_logged: Option<Instant>,
+    /// Amount of failures since the last log message which were not logged.
+    suppressed: u64,
+}
+
+impl DecryptionFailureLog {
+    /// Record a failure at `now`. If it should be logged, the amount of failures which were not
+    /// logged since the last log message is returned.
+    fn record(&mut self, now: Instant) -> Option<u64> {
+        if self
+            .last_logged
+            .is_some_and(|last| now.duration_since(last) < DECRYPTION_FAILURE_LOG_INTERVAL)
+        {
+            self.suppressed += 1;
+            return None;
+        }
+
+        self.last_logged = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+/// Send every packet of `source` to one of the `workers`, chosen by the hash of the flow the
+/// packet belongs to, so the packets of a flow are processed in order by the same worker.
+async fn dispatch_packets<S, P>(mut source: S, workers: Vec<mpsc::Sender<P>>, flow: fn(&P) -> u64)
+where
+    S: Stream<Item = P> + Unpin,
+{
+    while let Some(packet) = source.next().await {
+        let worker = (flow(&packet) % workers.len() as u64) as usize;
+        if workers[worker].send(packet).await.is_err() {
+            error!("Packet worker {worker} is gone, stop dispatching packets");
+            break;
+        }
+    }
+}
+
+/// Hash of the flow an IP packet belongs to, which is identified by its source and destination
+/// address. Packets which are too short to contain an IP header all have the same hash.
+pub(crate) fn flow_hash(packet: &[u8]) -> u64 {
+    let addresses = match packet.first().map(|b| b & IP_VERSION_MASK) {
+        Some(IPV4_VERSION_BYTE) if packet.len() >= IPV4_MIN_HEADER_SIZE => &packet[12..20],
+        _ if packet.len() >= IPV6_MIN_HEADER_SIZE => &packet[8..40],
+        _ => return 0,
+    };
+    let mut hasher = DefaultHasher::new();
+    addresses.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifier of the flow an IP packet belongs to, used to keep the packets of a flow on the same
+/// path if traffic is spread over multiple paths.
+///
+/// For IPv6 packets with a non-zero flow label, the flow is identified by the addresses and the
+/// flow label, so applications can choose which packets share a path. Otherwise, it is identified
+/// by the addresses, the protocol and, for TCP and UDP, the ports. The identifier is never 0, as
+/// that marks packets of which the flow is not known. Packets which are too short to contain an IP
+/// header get identifier 0.
+pub(crate) fn flow_id(packet: &[u8]) -> u8 {
+    let mut hasher = DefaultHasher::new();
+    match packet.first().map(|b| b & IP_VERSION_MASK) {
+        Some(IPV6_VERSION_BYTE) if packet.len() >= IPV6_MIN_HEADER_SIZE => {
+            packet[8..40].hash(&mut hasher);
+            let flow_label =
+                u32::from_be_bytes([0, packet[1] & IPV6_FLOW_LABEL_MASK, packet[2], packet[3]]);
+            if flow_label != 0 {
+                flow_label.hash(&mut hasher);
+            } else {
+                transport_key(packet, packet[6], IPV6_MIN_HEADER_SIZE).hash(&mut hasher);
+            }
+        }
+        Some(IPV4_VERSION_BYTE) if packet.len() >= IPV4_MIN_HEADER_SIZE => {
+            packet[12..20].hash(&mut hasher);
+            let header_size = (packet[0] & !IP_VERSION_MASK) as usize * 4;
+            transport_key(packet, packet[9], header_size).hash(&mut hasher);
+        }
+        _ => return 0,
+    }
+    (hasher.finish() % u8::MAX as u64) as u8 + 1
+}
+
+/// The protocol of a packet and, for TCP and UDP, the ports, if the transport header starts at
+/// `offset`. Extension headers are not skipped, so packets which have them are only identified by
+/// their protocol.
+fn transport_key(packet: &[u8], protocol: u8, offset: usize) -> (u8, Option<&[u8]>) {
+    let ports = match protocol {
+        TCP_PROTOCOL | UDP_PROTOCOL => packet.get(offset..offset + 4),
+        _ => None,
+    };
+    (protocol, ports)
+}
+
+/// Checks if an IPv6 packet is a Neighbor Discovery message, i.e. an ICMPv6 packet of one of the
+/// [`NEIGHBOR_DISCOVERY_TYPES`]. These are always sent without extension headers, so only packets
+/// where the ICMPv6 header directly follows the IPv6 header are considered.
+fn is_neighbor_discovery(packet: &[u8]) -> bool {
+    packet.len() >= IPV6_MIN_HEADER_SIZE + ICMP6_HEADER_SIZE
+        && packet[6] == ICMPV6_PROTOCOL
+        && NEIGHBOR_DISCOVERY_TYPES.contains(&packet[IPV6_MIN_HEADER_SIZE])
+}
+
+/// Construct an ICMPv6 packet of the given type from `src_ip` to `dst_ip`, quoting as much of
+/// the `original` packet as fits without exceeding the minimum IPv6 MTU.
+fn icmp_packet(
+    icmp_type: Icmpv6Type,
+    src_ip: Ipv6Addr,
+    dst_ip: Ipv6Addr,
+    original: &[u8],
+) -> Option<PacketBuffer> {
+    let icmp =
+        PacketBuilder::ipv6(src_ip.octets(), dst_ip.octets(), ICMP_HOP_LIMIT).icmpv6(icmp_type);
+    let original = &original[..original
+        .len()
+        .min(MIN_IPV6_MTU - IPV6_MIN_HEADER_SIZE - ICMP6_HEADER_SIZE)];
+
+    let mut pb = PacketBuffer::new();
+    pb.set_size(icmp.size(original.len()));
+    if let Err(e) = icmp.write(&mut pb.buffer_mut(), original) {
+        error!("Failed to construct ICMP packet: {e}");
+        return None;
+    }
+
+    Some(pb)
 }
 
 impl<M> Clone for DataPlane<M>
@@ -420,6 +1029,73 @@ where
     fn clone(&self) -> Self {
         Self {
             router: self.router.clone(),
+            fragment_id: self.fragment_id.clone(),
+            compress: self.compress,
+            accepts_compression: self.accepts_compression.clone(),
+            aes_accelerated: self.aes_accelerated,
+            ipv4_mappings: self.ipv4_mappings.clone(),
+            capture: self.capture.clone(),
+            echo_responder: self.echo_responder.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::{
+        is_neighbor_discovery, DecryptionFailureLog, DECRYPTION_FAILURE_LOG_INTERVAL,
+        ICMPV6_PROTOCOL, IPV6_MIN_HEADER_SIZE, UDP_PROTOCOL,
+    };
+
+    /// Create an IPv6 packet with the given next header, followed by 8 bytes of which the first
+    /// one is `first_byte`.
+    fn ipv6_packet(next_header: u8, first_byte: u8) -> Vec<u8> {
+        let mut packet = vec![0; IPV6_MIN_HEADER_SIZE + 8];
+        packet[0] = 0x60;
+        packet[6] = next_header;
+        packet[7] = 255;
+        packet[IPV6_MIN_HEADER_SIZE] = first_byte;
+        packet
+    }
+
+    #[test]
+    fn neighbor_discovery_is_recognized() {
+        // Router Solicitation, Neighbor Solicitation and Redirect.
+        for icmp_type in [133, 135, 137] {
+            assert!(is_neighbor_discovery(&ipv6_packet(
+                ICMPV6_PROTOCOL,
+                icmp_type
+            )));
+        }
+        // Echo request, and a UDP packet of which the first byte of the port looks like NDP.
+        assert!(!is_neighbor_discovery(&ipv6_packet(ICMPV6_PROTOCOL, 128)));
+        assert!(!is_neighbor_discovery(&ipv6_packet(UDP_PROTOCOL, 133)));
+        // Truncated ICMPv6 header.
+        assert!(!is_neighbor_discovery(
+            &ipv6_packet(ICMPV6_PROTOCOL, 133)[..IPV6_MIN_HEADER_SIZE + 4]
+        ));
+    }
+
+    #[test]
+    fn decryption_failures_are_logged_at_a_limited_rate() {
+        let mut log = DecryptionFailureLog::default();
+        let start = Instant::now();
+
+        assert_eq!(log.record(start), Some(0));
+        for _ in 0..100 {
+            assert_eq!(log.record(start), None);
+        }
+        assert_eq!(
+            log.record(start + DECRYPTION_FAILURE_LOG_INTERVAL / 2),
+            None
+        );
+        // The next message mentions the failures which were not logged.
+        assert_eq!(
+            log.record(start + DECRYPTION_FAILURE_LOG_INTERVAL),
+            Some(101)
+        );
+        assert_eq!(log.record(start + DECRYPTION_FAILURE_LOG_INTERVAL), None);
+    }
+}
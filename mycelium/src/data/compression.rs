@@ -0,0 +1,113 @@
+//! Compression of user data before it is encrypted.
+//!
+//! Like encryption, compression happens end to end: the source compresses the content of a
+//! packet, and only the destination decompresses it. Content is compressed as a single LZ4 block,
+//! and this is indicated by the [`USER_DATA_FLAG_COMPRESSED`] bit in the flags byte (the fourth
+//! byte) of the user data header. Since encrypted or otherwise random data does not compress, a
+//! packet is only sent compressed if this actually makes it smaller.
+//!
+//! Nodes which are able to decompress content set the [`USER_DATA_FLAG_ACCEPTS_COMPRESSION`] bit
+//! in the header of every packet they send. Content is only compressed for destinations from
+//! which such a packet was received, so nodes which don't support compression never receive
+//! compressed packets.
+
+use tracing::trace;
+
+use crate::crypto::PacketBuffer;
+
+use super::{USER_DATA_FLAGS_INDEX, USER_DATA_FLAG_COMPRESSED};
+
+/// Compress the content of `packet`. The content must fit in a regular [`PacketBuffer`]. If
+/// compression does not make the content smaller, `packet` is returned unchanged.
+pub fn compress(packet: PacketBuffer) -> PacketBuffer {
+    // The encoder requires space for the worst case, where the content does not compress at all.
+    let mut compressed =
+        PacketBuffer::with_capacity(lz4_flex::block::get_maximum_output_size(packet.len()));
+    let size = match lz4_flex::block::compress_into(&packet, compressed.buffer_mut()) {
+        Ok(size) if size < packet.len() => size,
+        // Compression did not make the content smaller.
+        Ok(_) => return packet,
+        Err(e) => {
+            trace!("Failed to compress packet content: {e}");
+            return packet;
+        }
+    };
+    compressed.set_size(size);
+    let mut header = compressed.header_mut();
+    header.copy_from_slice(&packet.header()[..]);
+    header[USER_DATA_FLAGS_INDEX] |= USER_DATA_FLAG_COMPRESSED;
+
+    compressed
+}
+
+/// Decompress the content of a compressed `packet`. Returns [`None`] if the content is not a
+/// valid LZ4 block, or if the decompressed content does not fit in a regular [`PacketBuffer`].
+pub fn decompress(packet: &PacketBuffer) -> Option<PacketBuffer> {
+    let mut decompressed = PacketBuffer::new();
+    let size = match lz4_flex::block::decompress_into(packet, decompressed.buffer_mut()) {
+        Ok(size) => size,
+        Err(e) => {
+            trace!("Failed to decompress packet content: {e}");
+            return None;
+        }
+    };
+    decompressed.set_size(size);
+    let mut header = decompressed.header_mut();
+    header.copy_from_slice(&packet.header()[..]);
+    header[USER_DATA_FLAGS_INDEX] &= !USER_DATA_FLAG_COMPRESSED;
+
+    Some(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{PacketBuffer, PACKET_SIZE};
+
+    use super::super::{USER_DATA_L3_TYPE, USER_DATA_VERSION};
+    use super::{USER_DATA_FLAGS_INDEX, USER_DATA_FLAG_COMPRESSED};
+
+    fn packet(content: &[u8]) -> PacketBuffer {
+        let mut packet = PacketBuffer::new();
+        packet.buffer_mut()[..content.len()].copy_from_slice(content);
+        packet.set_size(content.len());
+        let mut header = packet.header_mut();
+        header[0] = USER_DATA_VERSION;
+        header[1] = USER_DATA_L3_TYPE;
+        packet
+    }
+
+    #[test]
+    fn compress_roundtrip() {
+        let content = b"mycelium ".repeat(PACKET_SIZE / 9);
+        let compressed = super::compress(packet(&content));
+
+        assert!(compressed.len() < content.len());
+        assert_ne!(
+            compressed.header()[USER_DATA_FLAGS_INDEX] & USER_DATA_FLAG_COMPRESSED,
+            0
+        );
+
+        let decompressed = super::decompress(&compressed).expect("Can decompress content");
+        assert_eq!(&decompressed[..], &content[..]);
+        assert_eq!(decompressed.header()[..], packet(&content).header()[..]);
+    }
+
+    #[test]
+    fn incompressible_content_is_unchanged() {
+        let content = (0..PACKET_SIZE)
+            .map(|_| rand::random::<u8>())
+            .collect::<Vec<_>>();
+        let packet = super::compress(packet(&content));
+
+        assert_eq!(&packet[..], &content[..]);
+        assert_eq!(packet.header()[USER_DATA_FLAGS_INDEX], 0);
+    }
+
+    #[test]
+    fn decompress_rejects_oversized_content() {
+        // A block which decompresses to more than a single packet can hold.
+        let content = lz4_flex::block::compress(&[0; PACKET_SIZE + 1]);
+
+        assert!(super::decompress(&packet(&content)).is_none());
+    }
+}
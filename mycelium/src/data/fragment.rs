@@ -0,0 +1,369 @@
+//! Fragmentation and reassembly of user data which does not fit in a single [`PacketBuffer`].
+//!
+//! Fragmentation happens end to end: every fragment is encrypted on its own, and intermediate
+//! nodes forward fragments like any other data packet. Only the destination reassembles the
+//! original payload. The user data header of a fragment has type [`USER_DATA_FRAGMENT_TYPE`], and
+//! the third byte of the header holds the type of the original payload. The content of a fragment
+//! starts with a fragment header:
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                          Fragment ID                          |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |        Fragment offset        |          Total length         |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::Ipv6Addr,
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tracing::{debug, trace};
+
+use crate::{
+    crypto::{PacketBuffer, PACKET_SIZE},
+    metrics::Metrics,
+};
+
+use super::{USER_DATA_FRAGMENT_TYPE, USER_DATA_VERSION};
+
+/// Size of the fragment header at the start of the content of a fragment.
+const FRAGMENT_HEADER_SIZE: usize = 8;
+
+/// Maximum size of a payload which can be fragmented, limited by the size of the total length
+/// field in the fragment header.
+pub const MAX_FRAGMENTED_PAYLOAD_SIZE: usize = u16::MAX as usize;
+
+/// Time after the first fragment of a payload is received in which all other fragments must be
+/// received. If this is not the case, the received fragments are discarded.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum amount of fragments of incomplete payloads buffered for a single source. If a source
+/// exceeds this, its oldest incomplete payloads are discarded.
+const MAX_BUFFERED_FRAGMENTS_PER_SOURCE: usize = 256;
+
+/// Split a payload into fragments. The payload is the content of `packet`, and the type of it is
+/// taken from the user data header of `packet`. The content of every fragment is at most
+/// `max_size` bytes, so it fits on the path to the destination.
+///
+/// # Panics
+///
+/// This panics if the size of the payload is larger than [`MAX_FRAGMENTED_PAYLOAD_SIZE`], or if
+/// `max_size` is larger than [`PACKET_SIZE`] or does not leave room for any payload.
+pub fn fragment(
+    packet: &PacketBuffer,
+    id: u32,
+    max_size: usize,
+) -> impl Iterator<Item = PacketBuffer> + '_ {
+    assert!(
+        packet.len() <= MAX_FRAGMENTED_PAYLOAD_SIZE,
+        "Payload must fit the total length field of a fragment; qed"
+    );
+    assert!(
+        max_size > FRAGMENT_HEADER_SIZE && max_size <= PACKET_SIZE,
+        "Fragments must fit in a regular PacketBuffer; qed"
+    );
+    let payload_type = packet.header()[1];
+    let total_len = packet.len() as u16;
+    let max_payload_size = max_size - FRAGMENT_HEADER_SIZE;
+
+    packet
+        .chunks(max_payload_size)
+        .enumerate()
+        .map(move |(idx, chunk)| {
+            let offset = (idx * max_payload_size) as u16;
+
+            let mut fragment = PacketBuffer::new();
+            let mut header = fragment.header_mut();
+            header[0] = USER_DATA_VERSION;
+            header[1] = USER_DATA_FRAGMENT_TYPE;
+            header[2] = payload_type;
+
+            let buf = fragment.buffer_mut();
+            buf[..4].copy_from_slice(&id.to_be_bytes());
+            buf[4..6].copy_from_slice(&offset.to_be_bytes());
+            buf[6..8].copy_from_slice(&total_len.to_be_bytes());
+            buf[FRAGMENT_HEADER_SIZE..FRAGMENT_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+            fragment.set_size(FRAGMENT_HEADER_SIZE + chunk.len());
+
+            fragment
+        })
+}
+
+/// Reassembles fragmented payloads received from remote nodes.
+pub struct Reassembler<M> {
+    /// Fragments of incomplete payloads, by source IP and fragment ID.
+    sets: HashMap<(Ipv6Addr, u32), FragmentSet>,
+    /// Amount of buffered fragments per source IP.
+    buffered: HashMap<Ipv6Addr, usize>,
+    metrics: M,
+}
+
+/// The received fragments of a single payload.
+struct FragmentSet {
+    /// Time the first fragment of the payload was received.
+    first_seen: Instant,
+    payload_type: u8,
+    total_len: u16,
+    /// Amount of payload bytes received so far.
+    received: usize,
+    /// The received fragment payloads, by offset.
+    fragments: BTreeMap<u16, Vec<u8>>,
+}
+
+impl<M> Reassembler<M>
+where
+    M: Metrics,
+{
+    /// Create a new `Reassembler`.
+    pub fn new(metrics: M) -> Self {
+        Self {
+            sets: HashMap::new(),
+            buffered: HashMap::new(),
+            metrics,
+        }
+    }
+
+    /// Process a decrypted fragment sent by `src`. If this was the last missing fragment of a
+    /// payload, the reassembled payload is returned, with the original type set in its user data
+    /// header.
+    ///
+    /// Incomplete payloads are only expired when a new fragment is received.
+    pub fn insert(&mut self, src: Ipv6Addr, fragment: PacketBuffer) -> Option<PacketBuffer> {
+        let now = Instant::now();
+        self.expire(now);
+
+        if fragment.len() <= FRAGMENT_HEADER_SIZE {
+            debug!("Dropping fragment from {src} without payload");
+            self.metrics.data_plane_reassembly_failed();
+            return None;
+        }
+        let payload_type = fragment.header()[2];
+        let id = u32::from_be_bytes(fragment[..4].try_into().expect("4 bytes is a u32; qed"));
+        let offset = u16::from_be_bytes([fragment[4], fragment[5]]);
+        let total_len = u16::from_be_bytes([fragment[6], fragment[7]]);
+        let payload = &fragment[FRAGMENT_HEADER_SIZE..];
+
+        if payload_type == USER_DATA_FRAGMENT_TYPE
+            || offset as usize + payload.len() > total_len as usize
+        {
+            debug!("Dropping malformed fragment from {src}");
+            self.metrics.data_plane_reassembly_failed();
+            return None;
+        }
+
+        let set = self.sets.entry((src, id)).or_insert_with(|| FragmentSet {
+            first_seen: now,
+            payload_type,
+            total_len,
+            received: 0,
+            fragments: BTreeMap::new(),
+        });
+        if set.payload_type != payload_type || set.total_len != total_len {
+            debug!("Fragment {id} from {src} does not match previously received fragments");
+            self.discard(src, id);
+            return None;
+        }
+        if set.fragments.contains_key(&offset) {
+            trace!("Ignoring duplicate fragment {id} at offset {offset} from {src}");
+            return None;
+        }
+        set.received += payload.len();
+        set.fragments.insert(offset, payload.to_vec());
+        let complete = set.received >= total_len as usize;
+        *self.buffered.entry(src).or_default() += 1;
+
+        if complete {
+            return self.assemble(src, id);
+        }
+
+        // Enforce the limit of buffered fragments by discarding the oldest payloads of the source.
+        while self.buffered.get(&src).copied().unwrap_or_default()
+            > MAX_BUFFERED_FRAGMENTS_PER_SOURCE
+        {
+            let oldest = self
+                .sets
+                .iter()
+                .filter(|((set_src, _), _)| *set_src == src)
+                .min_by_key(|(_, set)| set.first_seen)
+                .map(|((_, id), _)| *id)
+                .expect("Source has buffered fragments so it has at least 1 set; qed");
+            debug!("Too many buffered fragments from {src}, discarding fragment {oldest}");
+            self.discard(src, oldest);
+        }
+
+        None
+    }
+
+    /// Discard all incomplete payloads for which the first fragment was received longer than
+    /// [`REASSEMBLY_TIMEOUT`] ago.
+    fn expire(&mut self, now: Instant) {
+        let expired = self
+            .sets
+            .iter()
+            .filter(|(_, set)| now.duration_since(set.first_seen) > REASSEMBLY_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for (src, id) in expired {
+            debug!("Reassembly of fragment {id} from {src} timed out");
+            self.discard(src, id);
+        }
+    }
+
+    /// Remove the fragments of an incomplete payload, and record the failed reassembly.
+    fn discard(&mut self, src: Ipv6Addr, id: u32) {
+        if self.remove(src, id).is_some() {
+            self.metrics.data_plane_reassembly_failed();
+        }
+    }
+
+    /// Remove a [`FragmentSet`], updating the amount of buffered fragments of the source.
+    fn remove(&mut self, src: Ipv6Addr, id: u32) -> Option<FragmentSet> {
+        let set = self.sets.remove(&(src, id))?;
+        if let Some(buffered) = self.buffered.get_mut(&src) {
+            *buffered = buffered.saturating_sub(set.fragments.len());
+            if *buffered == 0 {
+                self.buffered.remove(&src);
+            }
+        }
+        Some(set)
+    }
+
+    /// Assemble a payload of which all bytes have been received.
+    fn assemble(&mut self, src: Ipv6Addr, id: u32) -> Option<PacketBuffer> {
+        let set = self.remove(src, id)?;
+
+        let total_len = set.total_len as usize;
+        let mut packet = PacketBuffer::with_capacity(total_len);
+        let mut header = packet.header_mut();
+        header[0] = USER_DATA_VERSION;
+        header[1] = set.payload_type;
+
+        // Fragments can overlap if the sender is misbehaving, in which case some bytes are
+        // missing.
+        let mut next_offset = 0;
+        let buf = packet.buffer_mut();
+        for (offset, payload) in set.fragments {
+            if offset as usize != next_offset {
+                debug!("Fragments of {id} from {src} overlap, discarding them");
+                self.metrics.data_plane_reassembly_failed();
+                return None;
+            }
+            buf[next_offset..next_offset + payload.len()].copy_from_slice(&payload);
+            next_offset += payload.len();
+        }
+        packet.set_size(total_len);
+
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use tokio::time::Instant;
+
+    use crate::{
+        crypto::{PacketBuffer, PACKET_SIZE},
+        metrics::Metrics,
+    };
+
+    use super::{fragment, Reassembler, FRAGMENT_HEADER_SIZE, MAX_BUFFERED_FRAGMENTS_PER_SOURCE};
+
+    #[derive(Clone)]
+    struct NoMetrics;
+    impl Metrics for NoMetrics {}
+
+    const SRC: Ipv6Addr = Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1);
+
+    fn payload(size: usize) -> PacketBuffer {
+        let mut packet = PacketBuffer::with_capacity(size);
+        let mut header = packet.header_mut();
+        header[0] = super::USER_DATA_VERSION;
+        header[1] = 1;
+        for (i, b) in packet.buffer_mut()[..size].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        packet.set_size(size);
+        packet
+    }
+
+    #[test]
+    fn fragment_reassemble_roundtrip() {
+        let packet = payload(4000);
+        let fragments = fragment(&packet, 7, PACKET_SIZE).collect::<Vec<_>>();
+        assert_eq!(
+            fragments.len(),
+            4000 / (PACKET_SIZE - FRAGMENT_HEADER_SIZE) + 1
+        );
+
+        let mut reassembler = Reassembler::new(NoMetrics);
+        let mut reassembled = None;
+        // Deliver out of order.
+        for fragment in fragments.into_iter().rev() {
+            assert!(reassembled.is_none());
+            reassembled = reassembler.insert(SRC, fragment);
+        }
+
+        let reassembled = reassembled.expect("All fragments were delivered");
+        assert_eq!(reassembled.header()[1], 1);
+        assert_eq!(&*reassembled, &*packet);
+        assert!(reassembler.sets.is_empty());
+        assert!(reassembler.buffered.is_empty());
+    }
+
+    #[test]
+    fn fragments_fit_max_size() {
+        // A payload which fits in a regular packet, but not on the path to the destination.
+        let packet = payload(1300);
+        let fragments = fragment(&packet, 3, 1100).collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 2);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 1100));
+
+        let mut reassembler = Reassembler::new(NoMetrics);
+        let reassembled = fragments
+            .into_iter()
+            .filter_map(|fragment| reassembler.insert(SRC, fragment))
+            .next()
+            .expect("All fragments were delivered");
+        assert_eq!(&*reassembled, &*packet);
+    }
+
+    #[test]
+    fn incomplete_fragments_expire() {
+        let packet = payload(2000);
+        let first = fragment(&packet, 1, PACKET_SIZE).next().unwrap();
+
+        let mut reassembler = Reassembler::new(NoMetrics);
+        assert!(reassembler.insert(SRC, first).is_none());
+        assert_eq!(reassembler.buffered[&SRC], 1);
+
+        reassembler.expire(Instant::now() + super::REASSEMBLY_TIMEOUT * 2);
+
+        assert!(reassembler.sets.is_empty());
+        assert!(reassembler.buffered.is_empty());
+    }
+
+    #[test]
+    fn buffered_fragments_are_capped_per_source() {
+        let packet = payload(2000);
+
+        let mut reassembler = Reassembler::new(NoMetrics);
+        for id in 0..MAX_BUFFERED_FRAGMENTS_PER_SOURCE as u32 + 10 {
+            let first = fragment(&packet, id, PACKET_SIZE).next().unwrap();
+            assert!(reassembler.insert(SRC, first).is_none());
+        }
+
+        assert_eq!(
+            reassembler.buffered[&SRC],
+            MAX_BUFFERED_FRAGMENTS_PER_SOURCE
+        );
+        assert_eq!(reassembler.sets.len(), MAX_BUFFERED_FRAGMENTS_PER_SOURCE);
+    }
+}
@@ -0,0 +1,227 @@
+//! Replies to ICMPv6 echo requests for the overlay address of the node, so the node can be pinged
+//! over the overlay to check if it is reachable, without relying on the network stack of the
+//! host. This also works if the node runs without TUN interface.
+//!
+//! Replies are rate limited, so the node can't be used to amplify a ping flood. Echo requests
+//! which exceed the limit are dropped.
+
+use std::{net::Ipv6Addr, sync::Mutex};
+
+use tokio::time::Instant;
+
+use crate::crypto::{PacketBuffer, PACKET_SIZE};
+
+/// Default maximum amount of echo replies sent per second.
+pub const DEFAULT_ECHO_REPLY_RATE: u32 = 10;
+
+/// Size of an IPv6 header.
+const IPV6_HEADER_SIZE: usize = 40;
+
+/// Size of the header of an ICMPv6 echo message.
+const ICMP6_ECHO_HEADER_SIZE: usize = 8;
+
+/// Value of the next header field of an IPv6 header for ICMPv6.
+const ICMP6_NEXT_HEADER: u8 = 58;
+
+/// ICMPv6 type of an echo request.
+const ICMP6_ECHO_REQUEST: u8 = 128;
+
+/// ICMPv6 type of an echo reply.
+const ICMP6_ECHO_REPLY: u8 = 129;
+
+/// Hop limit of echo replies.
+const ECHO_REPLY_HOP_LIMIT: u8 = 64;
+
+/// Configuration of the replies to echo requests for the overlay address of the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoReplyConfig {
+    /// Maximum amount of replies sent per second. At most this many replies are sent in a burst.
+    pub max_rate: u32,
+    /// Also write the echo requests to the TUN interface, so the host sees them. If the host
+    /// replies as well, the sender receives duplicate replies.
+    pub forward_to_tun: bool,
+}
+
+impl Default for EchoReplyConfig {
+    fn default() -> Self {
+        Self {
+            max_rate: DEFAULT_ECHO_REPLY_RATE,
+            forward_to_tun: false,
+        }
+    }
+}
+
+/// Replies to echo requests for a single address, see the [module docs](self).
+pub(crate) struct EchoResponder {
+    address: Ipv6Addr,
+    config: EchoReplyConfig,
+    /// Amount of replies which can currently be sent, and the last time this was refilled.
+    tokens: Mutex<(f64, Option<Instant>)>,
+}
+
+impl EchoResponder {
+    /// Create a new `EchoResponder` for echo requests to `address`.
+    pub(crate) fn new(address: Ipv6Addr, config: EchoReplyConfig) -> Self {
+        Self {
+            address,
+            config,
+            // Start with a full bucket.
+            tokens: Mutex::new((config.max_rate as f64, None)),
+        }
+    }
+
+    /// Whether echo requests are also written to the TUN interface.
+    pub(crate) fn forward_to_tun(&self) -> bool {
+        self.config.forward_to_tun
+    }
+
+    /// Checks if an IPv6 packet is a valid echo request for the address of this responder.
+    pub(crate) fn is_echo_request(&self, packet: &[u8]) -> bool {
+        let Some(icmp) = icmp_message(packet) else {
+            return false;
+        };
+
+        packet[6] == ICMP6_NEXT_HEADER
+            && packet[24..40] == self.address.octets()
+            && icmp.len() >= ICMP6_ECHO_HEADER_SIZE
+            && icmp[0] == ICMP6_ECHO_REQUEST
+            && icmp[1] == 0
+            && icmp6_checksum(&packet[8..24], &packet[24..40], icmp) == 0
+    }
+
+    /// Create the echo reply to a `request`, which must be a valid echo request according to
+    /// [`EchoResponder::is_echo_request`]. This returns [`None`] if the rate limit is exceeded.
+    ///
+    /// The reply is the request with the addresses swapped and the type changed, so the
+    /// identifier, sequence number and data are echoed unmodified.
+    pub(crate) fn reply(&self, request: &[u8]) -> Option<PacketBuffer> {
+        if !self.allow(Instant::now()) {
+            return None;
+        }
+
+        let icmp_len = icmp_message(request)?.len();
+        let len = IPV6_HEADER_SIZE + icmp_len;
+        let mut pb = if len > PACKET_SIZE {
+            PacketBuffer::with_capacity(len)
+        } else {
+            PacketBuffer::new()
+        };
+        pb.set_size(len);
+
+        let reply = &mut pb.buffer_mut()[..len];
+        reply.copy_from_slice(&request[..len]);
+        reply[7] = ECHO_REPLY_HOP_LIMIT;
+        reply[8..24].copy_from_slice(&request[24..40]);
+        reply[24..40].copy_from_slice(&request[8..24]);
+        let (header, icmp) = reply.split_at_mut(IPV6_HEADER_SIZE);
+        icmp[0] = ICMP6_ECHO_REPLY;
+        icmp[2..4].fill(0);
+        let checksum = icmp6_checksum(&header[8..24], &header[24..40], icmp);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        Some(pb)
+    }
+
+    /// Take a token from the bucket if one is available, after refilling it.
+    fn allow(&self, now: Instant) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        let max_rate = self.config.max_rate as f64;
+        if let Some(last_refill) = tokens.1 {
+            let elapsed = now.saturating_duration_since(last_refill).as_secs_f64();
+            tokens.0 = (tokens.0 + elapsed * max_rate).min(max_rate);
+        }
+        tokens.1 = Some(now);
+
+        if tokens.0 < 1.0 {
+            return false;
+        }
+        tokens.0 -= 1.0;
+
+        true
+    }
+}
+
+/// Get the ICMPv6 message in an IPv6 packet, as indicated by the payload length in its header.
+/// Extension headers are not supported.
+fn icmp_message(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < IPV6_HEADER_SIZE || packet[0] >> 4 != 6 {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+
+    packet.get(IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + payload_len)
+}
+
+/// Compute the ICMPv6 checksum of a message between two addresses. If the checksum field of the
+/// message is set, this returns 0 if it is correct.
+fn icmp6_checksum(src: &[u8], dst: &[u8], icmp: &[u8]) -> u16 {
+    let len = (icmp.len() as u32).to_be_bytes();
+    let mut sum = [src, dst, &len, &[0, 0, 0, ICMP6_NEXT_HEADER], icmp]
+        .into_iter()
+        .flat_map(|part| part.chunks(2))
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, time::Duration};
+
+    use tokio::time::Instant;
+
+    use super::{EchoReplyConfig, EchoResponder};
+
+    /// Build an echo request from `src` to `dst`, with the given checksum.
+    fn echo_request(src: Ipv6Addr, dst: Ipv6Addr, checksum: u16) -> Vec<u8> {
+        let data = b"mycelium ping";
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(8 + data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[58, 12]);
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        packet.extend_from_slice(&[128, 0]);
+        packet.extend_from_slice(&checksum.to_be_bytes());
+        packet.extend_from_slice(&[0x12, 0x34, 0, 1]);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn echo_request_is_answered() {
+        let remote: Ipv6Addr = "400:1::1".parse().unwrap();
+        let node: Ipv6Addr = "400:2::1".parse().unwrap();
+        let responder = EchoResponder::new(
+            node,
+            EchoReplyConfig {
+                max_rate: 2,
+                forward_to_tun: false,
+            },
+        );
+
+        let request = echo_request(remote, node, 0xc1e1);
+        assert!(responder.is_echo_request(&request));
+        // Wrong checksum, or not for the address of the node.
+        assert!(!responder.is_echo_request(&echo_request(remote, node, 0xc1e2)));
+        assert!(!responder.is_echo_request(&echo_request(remote, remote, 0xc1e1)));
+
+        let reply = responder
+            .reply(&request)
+            .expect("Reply is not rate limited");
+        let mut expected = echo_request(node, remote, 0xc0e1);
+        expected[7] = 64;
+        expected[40] = 129;
+        assert_eq!(&*reply, &expected[..]);
+
+        // The burst is used up after the second reply, until the bucket is refilled.
+        let now = Instant::now();
+        assert!(responder.allow(now));
+        assert!(!responder.allow(now));
+        assert!(responder.allow(now + Duration::from_millis(500)));
+        assert!(!responder.allow(now + Duration::from_millis(500)));
+    }
+}
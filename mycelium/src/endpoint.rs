@@ -1,10 +1,10 @@
 use std::{
-    fmt,
+    fmt, io,
     net::{AddrParseError, SocketAddr},
     str::FromStr,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// Error generated while processing improperly formatted endpoints.
@@ -19,6 +19,7 @@ pub enum EndpointParseError {
 
 /// Protocol used by an endpoint.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum Protocol {
     /// Standard plain text Tcp.
@@ -29,18 +30,33 @@ pub enum Protocol {
     Quic,
 }
 
+/// The address of an [`Endpoint`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// A fixed IP address and port.
+    Socket(SocketAddr),
+    /// A hostname and port. The hostname is resolved every time a connection is made.
+    Host(String, u16),
+}
+
 /// An endpoint defines a address and a protocol to use when communicating with it.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Endpoint {
     proto: Protocol,
-    socket_addr: SocketAddr,
+    #[serde(rename = "socketAddr")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    address: Address,
 }
 
 impl Endpoint {
-    /// Create a new `Endpoint` with given [`Protocol`] and address.
+    /// Create a new `Endpoint` with given [`Protocol`] and socket address.
     pub fn new(proto: Protocol, socket_addr: SocketAddr) -> Self {
-        Self { proto, socket_addr }
+        Self {
+            proto,
+            address: Address::Socket(socket_addr),
+        }
     }
 
     /// Get the [`Protocol`] used by this `Endpoint`.
@@ -48,9 +64,104 @@ impl Endpoint {
         self.proto
     }
 
-    /// Get the [`SocketAddr`] used by this `Endpoint`.
-    pub fn address(&self) -> SocketAddr {
-        self.socket_addr
+    /// Get the [`Address`] used by this `Endpoint`.
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+}
+
+impl Address {
+    /// Get the port of this `Address`.
+    pub fn port(&self) -> u16 {
+        match self {
+            Self::Socket(socket_addr) => socket_addr.port(),
+            Self::Host(_, port) => *port,
+        }
+    }
+
+    /// Get the [`SocketAddr`] of this `Address`, if it is not a hostname.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Socket(socket_addr) => Some(*socket_addr),
+            Self::Host(_, _) => None,
+        }
+    }
+
+    /// Resolve this `Address` to the [`SocketAddr`]es it refers to. For hostnames, both A and AAAA
+    /// records are looked up.
+    pub async fn resolve(&self) -> Result<Vec<SocketAddr>, io::Error> {
+        match self {
+            Self::Socket(socket_addr) => Ok(vec![*socket_addr]),
+            Self::Host(host, port) => {
+                let addrs = tokio::net::lookup_host((host.as_str(), *port))
+                    .await?
+                    .collect::<Vec<_>>();
+                if addrs.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("hostname {host} does not resolve to any address"),
+                    ));
+                }
+                Ok(addrs)
+            }
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = match SocketAddr::from_str(s) {
+            Ok(socket_addr) => return Ok(Self::Socket(socket_addr)),
+            Err(err) => err,
+        };
+        // Not an IP address, so this must be a hostname. If it isn't a valid one either, return
+        // the original error, since that is likely the most relevant.
+        match s.rsplit_once(':') {
+            Some((host, port)) if is_valid_hostname(host) => match port.parse() {
+                Ok(port) => Ok(Self::Host(host.to_lowercase(), port)),
+                Err(_) => Err(err),
+            },
+            _ => Err(err),
+        }
+    }
+}
+
+/// Check if the given string is a valid hostname as defined in RFC 1123. The last label must not
+/// be numeric, to avoid confusing malformed IPv4 addresses for hostnames.
+fn is_valid_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && !host
+            .rsplit('.')
+            .next()
+            .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit()))
+}
+
+impl Serialize for Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Address::from_str(&s).map_err(de::Error::custom)
     }
 }
 
@@ -67,8 +178,8 @@ impl FromStr for Endpoint {
                     "tls" => Protocol::Tls,
                     _ => return Err(EndpointParseError::UnknownProtocol),
                 };
-                let socket_addr = SocketAddr::from_str(socket)?;
-                Ok(Endpoint { proto, socket_addr })
+                let address = Address::from_str(socket)?;
+                Ok(Endpoint { proto, address })
             }
         }
     }
@@ -76,7 +187,16 @@ impl FromStr for Endpoint {
 
 impl fmt::Display for Endpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_fmt(format_args!("{} {}", self.proto, self.socket_addr))
+        f.write_fmt(format_args!("{} {}", self.proto, self.address))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socket(socket_addr) => socket_addr.fmt(f),
+            Self::Host(host, port) => f.write_fmt(format_args!("{host}:{port}")),
+        }
     }
 }
 
@@ -114,3 +234,55 @@ impl From<AddrParseError> for EndpointParseError {
         Self::Address(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use super::{Address, Endpoint, Protocol};
+
+    #[test]
+    fn parse_ip_endpoints() {
+        let endpoint = Endpoint::from_str("tcp://[2001:db8::1]:9651").unwrap();
+        assert_eq!(endpoint.proto(), Protocol::Tcp);
+        assert_eq!(
+            endpoint.address(),
+            &Address::Socket(SocketAddr::from_str("[2001:db8::1]:9651").unwrap())
+        );
+
+        let endpoint = Endpoint::from_str("quic://192.0.2.1:9651").unwrap();
+        assert_eq!(endpoint.proto(), Protocol::Quic);
+        assert_eq!(
+            endpoint.address(),
+            &Address::Socket(SocketAddr::from_str("192.0.2.1:9651").unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_hostname_endpoints() {
+        let endpoint = Endpoint::from_str("tcp://Peer-1.Example.com:9651").unwrap();
+        assert_eq!(
+            endpoint.address(),
+            &Address::Host("peer-1.example.com".to_string(), 9651)
+        );
+        assert_eq!(endpoint.to_string(), "Tcp peer-1.example.com:9651");
+
+        assert!(Endpoint::from_str("tcp://localhost:9651").is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_addresses() {
+        for address in [
+            "tcp://2001:db8::1:9651",
+            "tcp://192.0.2:9651",
+            "tcp://192.0.2.1.5:9651",
+            "tcp://example.com",
+            "tcp://example.com:65536",
+            "tcp://-example.com:9651",
+            "tcp://exa_mple.com:9651",
+            "tcp://:9651",
+        ] {
+            assert!(Endpoint::from_str(address).is_err(), "{address} is invalid");
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! A bounded log of recent routing events, so operators can see what happened in the router after
+//! the fact, e.g. after the network reconverged. Unlike a subscription to route events, this is
+//! always kept, and only holds the most recent events.
+
+use std::{collections::VecDeque, sync::Mutex, time::SystemTime};
+
+use crate::subnet::Subnet;
+
+/// Default amount of events kept in the [`EventLog`].
+pub const DEFAULT_EVENT_LOG_SIZE: usize = 1000;
+
+/// An event in the router.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoutingEvent {
+    /// A route through a new next hop got selected for a subnet, either because it did not have a
+    /// usable route, or because a route through a different neighbour is now better. Changes of
+    /// only the metric or seqno of the selected route are not logged, since they happen all the
+    /// time.
+    RouteSelected {
+        /// The subnet of the route.
+        subnet: Subnet,
+        /// The source prefix of the route, if it is a source-specific route.
+        source_prefix: Option<Subnet>,
+        /// Connection identifier of the next hop.
+        next_hop: String,
+        /// Metric of the route.
+        metric: u16,
+        /// Sequence number of the route.
+        seqno: u16,
+    },
+    /// A subnet no longer has a usable selected route.
+    RouteRetracted {
+        /// The subnet of the route.
+        subnet: Subnet,
+        /// The source prefix of the route, if it is a source-specific route.
+        source_prefix: Option<Subnet>,
+        /// Connection identifier of the next hop of the previously selected route.
+        next_hop: String,
+    },
+    /// A peer connected to the router.
+    PeerUp {
+        /// Connection identifier of the peer.
+        connection: String,
+    },
+    /// A peer was removed from the router, because it died or was removed.
+    PeerDown {
+        /// Connection identifier of the peer.
+        connection: String,
+    },
+    /// The sequence number of the local router was increased.
+    SeqnoBumped {
+        /// The new sequence number.
+        seqno: u16,
+    },
+}
+
+/// A [`RoutingEvent`] with the time it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedEvent {
+    /// Identifier of the event, which increases by 1 for every logged event. This can be used to
+    /// detect if events were evicted from the log.
+    pub id: u64,
+    /// Time at which the event happened.
+    pub time: SystemTime,
+    /// The event itself.
+    pub event: RoutingEvent,
+}
+
+/// Ring buffer of the most recent [`RoutingEvent`]s. Once it is full, the oldest event is evicted
+/// for every new one.
+#[derive(Debug)]
+pub(crate) struct EventLog {
+    capacity: usize,
+    inner: Mutex<EventLogInner>,
+}
+
+#[derive(Debug)]
+struct EventLogInner {
+    events: VecDeque<LoggedEvent>,
+    /// Id of the next logged event.
+    next_id: u64,
+}
+
+impl EventLog {
+    /// Create a new `EventLog` which keeps at most `capacity` events. If `capacity` is 0, nothing
+    /// is logged.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(EventLogInner {
+                events: VecDeque::with_capacity(capacity),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Log an event which happened just now.
+    pub(crate) fn record(&self, event: RoutingEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.events.len() == self.capacity {
+            inner.events.pop_front();
+        }
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.events.push_back(LoggedEvent {
+            id,
+            time: SystemTime::now(),
+            event,
+        });
+    }
+
+    /// Get the logged events which happened after `since`, or all logged events if it is not set,
+    /// oldest first.
+    pub(crate) fn events(&self, since: Option<SystemTime>) -> Vec<LoggedEvent> {
+        let inner = self.inner.lock().unwrap();
+        // Events are logged in order, so their times only go back if the clock is changed. Rather
+        // than searching for the first newer event, filter all of them to be robust against that.
+        inner
+            .events
+            .iter()
+            .filter(|event| since.is_none_or(|since| event.time > since))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{EventLog, RoutingEvent};
+
+    #[test]
+    fn oldest_events_are_evicted() {
+        let log = EventLog::new(3);
+        for seqno in 0..5 {
+            log.record(RoutingEvent::SeqnoBumped { seqno });
+        }
+
+        let events = log.events(None);
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(events[0].event, RoutingEvent::SeqnoBumped { seqno: 2 });
+
+        assert!(log
+            .events(Some(SystemTime::now() + Duration::from_secs(60)))
+            .is_empty());
+        assert_eq!(
+            log.events(Some(SystemTime::now() - Duration::from_secs(60)))
+                .len(),
+            3
+        );
+
+        let disabled = EventLog::new(0);
+        disabled.record(RoutingEvent::SeqnoBumped { seqno: 1 });
+        assert!(disabled.events(None).is_empty());
+    }
+}
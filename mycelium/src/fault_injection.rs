@@ -0,0 +1,131 @@
+//! Injection of artificial latency, jitter and packet loss on the links to peers, to test the
+//! behaviour of the routing protocol under adverse network conditions.
+//!
+//! Faults are applied when a packet is queued to be sent to a [`Peer`](crate::peer::Peer), and
+//! apply to both data and control packets. The random choices are made with a seeded RNG, so a
+//! test run can be reproduced. This is only available with the `testing` feature, and must never
+//! be enabled in release builds.
+
+use std::{sync::Mutex, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Configuration of the faults injected on the links to peers. The default configuration doesn't
+/// inject any faults.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultInjectionConfig {
+    /// Fixed delay added to every packet sent to a peer.
+    pub latency: Duration,
+    /// Maximum additional random delay added to every packet sent to a peer. Since every packet
+    /// gets a different delay, packets can be reordered.
+    pub jitter: Duration,
+    /// Probability between 0 and 1 that a packet sent to a peer is dropped.
+    pub loss: f64,
+    /// Seed of the RNG used to pick the delay of packets and which packets are dropped.
+    pub seed: u64,
+}
+
+impl FaultInjectionConfig {
+    /// Checks if the loss is a valid probability.
+    pub fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.loss)
+    }
+
+    /// Checks if this configuration injects any faults.
+    fn is_noop(&self) -> bool {
+        self.latency.is_zero() && self.jitter.is_zero() && self.loss == 0.0
+    }
+}
+
+/// The fault to apply to a single packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    /// Send the packet as usual.
+    None,
+    /// Send the packet after the given delay.
+    Delay(Duration),
+    /// Drop the packet.
+    Drop,
+}
+
+/// Picks the [`Fault`] for packets sent to peers, according to a [`FaultInjectionConfig`].
+#[derive(Debug)]
+pub(crate) struct FaultInjector {
+    state: Mutex<(FaultInjectionConfig, StdRng)>,
+}
+
+impl FaultInjector {
+    /// Create a new `FaultInjector` which applies the given configuration.
+    pub fn new(config: FaultInjectionConfig) -> Self {
+        Self {
+            state: Mutex::new((config, StdRng::seed_from_u64(config.seed))),
+        }
+    }
+
+    /// The current configuration.
+    pub fn config(&self) -> FaultInjectionConfig {
+        self.state.lock().unwrap().0
+    }
+
+    /// Replace the configuration. The RNG is reseeded with the seed of the new configuration.
+    pub fn set_config(&self, config: FaultInjectionConfig) {
+        *self.state.lock().unwrap() = (config, StdRng::seed_from_u64(config.seed));
+    }
+
+    /// Pick the fault to apply to the next packet.
+    pub fn fault(&self) -> Fault {
+        let (config, rng) = &mut *self.state.lock().unwrap();
+        if config.is_noop() {
+            return Fault::None;
+        }
+        if config.loss > 0.0 && rng.gen_bool(config.loss.min(1.0)) {
+            return Fault::Drop;
+        }
+        let jitter = if config.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rng.gen_range(Duration::ZERO..=config.jitter)
+        };
+        match config.latency + jitter {
+            delay if delay.is_zero() => Fault::None,
+            delay => Fault::Delay(delay),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Fault, FaultInjectionConfig, FaultInjector};
+
+    #[test]
+    fn faults_are_reproducible() {
+        let config = FaultInjectionConfig {
+            latency: Duration::from_millis(10),
+            jitter: Duration::from_millis(5),
+            loss: 0.25,
+            seed: 42,
+        };
+        let injector = FaultInjector::new(config);
+        let faults = (0..100).map(|_| injector.fault()).collect::<Vec<_>>();
+
+        assert!(faults.contains(&Fault::Drop));
+        assert!(faults.iter().all(|fault| match fault {
+            Fault::Delay(delay) =>
+                *delay >= Duration::from_millis(10) && *delay <= Duration::from_millis(15),
+            Fault::Drop => true,
+            Fault::None => false,
+        }));
+
+        // Setting the configuration again reseeds the RNG.
+        injector.set_config(config);
+        assert_eq!(
+            faults,
+            (0..100).map(|_| injector.fault()).collect::<Vec<_>>()
+        );
+
+        injector.set_config(FaultInjectionConfig::default());
+        assert_eq!(injector.fault(), Fault::None);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{babel, subnet::Subnet};
+use std::sync::{Arc, RwLock};
+
+use crate::{access_list::AccessList, babel, subnet::Subnet};
 
 /// This trait is used to filter incoming updates from peers. Only updates which pass all
 /// configured filters on the local [`Router`](crate::router::Router) will actually be forwarded
@@ -37,17 +39,46 @@ impl RouteUpdateFilter for AllowedSubnet {
     }
 }
 
-/// Limit the announced subnets to those which contain the derived IP from the `RouterId`.
-///
-/// Since retractions can be sent by any node to indicate they don't have a route for the subnet,
-/// these are also allowed.
-pub struct RouterIdOwnsSubnet;
+/// Allow updates for the default route (`::/0`), which is announced by exit nodes, in addition to
+/// the updates allowed by the wrapped filter.
+pub struct OrDefaultRoute {
+    filter: Box<dyn RouteUpdateFilter + Send + Sync>,
+}
+
+impl OrDefaultRoute {
+    /// Create a new `OrDefaultRoute` filter, which allows updates for the default route, and
+    /// updates allowed by the given filter.
+    pub fn new(filter: Box<dyn RouteUpdateFilter + Send + Sync>) -> Self {
+        Self { filter }
+    }
+}
+
+impl RouteUpdateFilter for OrDefaultRoute {
+    fn allow(&self, update: &babel::Update) -> bool {
+        (update.subnet().address().is_ipv6() && update.subnet().prefix_len() == 0)
+            || self.filter.allow(update)
+    }
+}
+
+/// Limit the announced subnets to those allowed by an [`AccessList`]. The list is shared, so it can
+/// be changed while the [`Router`](crate::router::Router) is running.
+pub struct AllowedByAccessList {
+    access_list: Arc<RwLock<AccessList>>,
+}
+
+impl AllowedByAccessList {
+    /// Create a new `AllowedByAccessList` filter, which only allows updates for subnets allowed by
+    /// the current value of the given [`AccessList`].
+    pub fn new(access_list: Arc<RwLock<AccessList>>) -> Self {
+        Self { access_list }
+    }
+}
 
-impl RouteUpdateFilter for RouterIdOwnsSubnet {
+impl RouteUpdateFilter for AllowedByAccessList {
     fn allow(&self, update: &babel::Update) -> bool {
-        update.metric().is_infinite()
-            || update
-                .subnet()
-                .contains_ip(update.router_id().to_pubkey().address().into())
+        self.access_list
+            .read()
+            .expect("Access list lock is not poisoned; qed")
+            .allows_subnet(&update.subnet())
     }
 }
@@ -0,0 +1,228 @@
+//! Route flap damping, which stops the router from selecting routes that keep changing.
+//!
+//! Every time a route for a subnet through a neighbour gets selected, or stops being selected, a
+//! penalty is added for that combination. The penalty decays exponentially over time. Once it
+//! exceeds the suppress threshold, the route is no longer selected, unless it is the currently
+//! selected route, until the penalty decays below the reuse threshold.
+//!
+//! Neighbours are identified by their [stable identifier](crate::peer::Peer::identifier), i.e.
+//! their overlay address, so penalties are kept if the connection to a peer is reestablished,
+//! which typically uses another source port. A link which keeps going down is exactly what flap
+//! damping is meant to catch.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::{routing_table::RouteEntry, subnet::Subnet};
+
+/// Penalty added every time a route gets selected or unselected.
+pub const FLAP_PENALTY: u32 = 1000;
+
+/// The default penalty above which a route is suppressed.
+pub const DEFAULT_SUPPRESS_THRESHOLD: u32 = 3000;
+
+/// The default penalty below which a suppressed route can be selected again.
+pub const DEFAULT_REUSE_THRESHOLD: u32 = 1500;
+
+/// The default time after which a penalty has decayed to half its value.
+pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(60);
+
+/// Penalties which decayed below this value are forgotten.
+const FORGET_THRESHOLD: f64 = 1.0;
+
+/// Parameters of route flap damping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlapDampingConfig {
+    /// Penalty above which a route is suppressed. Every change of the route adds
+    /// [`FLAP_PENALTY`].
+    pub suppress_threshold: u32,
+    /// Penalty below which a suppressed route can be selected again. This must be lower than the
+    /// suppress threshold.
+    pub reuse_threshold: u32,
+    /// Time after which a penalty has decayed to half its value. This must not be zero.
+    pub half_life: Duration,
+}
+
+impl Default for FlapDampingConfig {
+    fn default() -> Self {
+        Self {
+            suppress_threshold: DEFAULT_SUPPRESS_THRESHOLD,
+            reuse_threshold: DEFAULT_REUSE_THRESHOLD,
+            half_life: DEFAULT_HALF_LIFE,
+        }
+    }
+}
+
+/// A route which is currently suppressed because it changed too often.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressedRoute {
+    /// The subnet of the route.
+    pub subnet: Subnet,
+    /// The source prefix of the route, if it is a source-specific route.
+    pub source_prefix: Option<Subnet>,
+    /// Identifier of the neighbour the route is learned from. This is its overlay address, or the
+    /// identifier of the connection if the address is not known yet.
+    pub neighbour: String,
+    /// Current penalty of the route.
+    pub penalty: u32,
+}
+
+/// Identifies the routes for which a penalty is tracked.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PenaltyKey {
+    subnet: Subnet,
+    source_prefix: Option<Subnet>,
+    neighbour: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Penalty {
+    /// Value of the penalty at `updated`.
+    value: f64,
+    updated: Instant,
+    suppressed: bool,
+}
+
+/// Tracks the penalties of routes, and decides which routes are suppressed.
+pub(crate) struct FlapDamping {
+    config: FlapDampingConfig,
+    penalties: Mutex<HashMap<PenaltyKey, Penalty>>,
+}
+
+impl Penalty {
+    /// Apply the decay since the last update, and lift the suppression if the penalty decayed
+    /// below the reuse threshold.
+    fn decay(&mut self, config: &FlapDampingConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.value *= 0.5_f64.powf(elapsed.as_secs_f64() / config.half_life.as_secs_f64());
+        self.updated = now;
+        if self.suppressed && self.value < config.reuse_threshold as f64 {
+            self.suppressed = false;
+        }
+    }
+}
+
+impl FlapDamping {
+    /// Create a new `FlapDamping` without any penalties.
+    pub fn new(config: FlapDampingConfig) -> Self {
+        Self {
+            config,
+            penalties: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a change of the route for the given subnet and source prefix through the given
+    /// neighbour, i.e. it got selected or it is no longer selected.
+    pub fn record_change(&self, subnet: Subnet, source_prefix: Option<Subnet>, neighbour: &str) {
+        let now = Instant::now();
+        let mut penalties = self.penalties.lock().unwrap();
+
+        // Changes are rare compared to lookups, so this is a good time to forget old penalties.
+        penalties.retain(|_, penalty| {
+            penalty.decay(&self.config, now);
+            penalty.suppressed || penalty.value >= FORGET_THRESHOLD
+        });
+
+        let penalty = penalties
+            .entry(PenaltyKey {
+                subnet,
+                source_prefix,
+                neighbour: neighbour.to_string(),
+            })
+            .or_insert(Penalty {
+                value: 0.,
+                updated: now,
+                suppressed: false,
+            });
+        penalty.value += FLAP_PENALTY as f64;
+        if !penalty.suppressed && penalty.value > self.config.suppress_threshold as f64 {
+            info!("Suppressing route to {subnet} via {neighbour} since it changes too often");
+            penalty.suppressed = true;
+        }
+    }
+
+    /// Check if a route is currently suppressed.
+    pub fn is_suppressed(&self, route: &RouteEntry) -> bool {
+        let mut penalties = self.penalties.lock().unwrap();
+        let Some(penalty) = penalties.get_mut(&PenaltyKey {
+            subnet: route.source().subnet(),
+            source_prefix: route.source().source_prefix(),
+            neighbour: route.neighbour().identifier(),
+        }) else {
+            return false;
+        };
+
+        penalty.decay(&self.config, Instant::now());
+        penalty.suppressed
+    }
+
+    /// List all currently suppressed routes.
+    pub fn suppressed_routes(&self) -> Vec<SuppressedRoute> {
+        let now = Instant::now();
+        self.penalties
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|(key, penalty)| {
+                penalty.decay(&self.config, now);
+                penalty.suppressed.then(|| SuppressedRoute {
+                    subnet: key.subnet,
+                    source_prefix: key.source_prefix,
+                    neighbour: key.neighbour.clone(),
+                    penalty: penalty.value as u32,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FlapDamping, FlapDampingConfig, PenaltyKey};
+
+    #[test]
+    fn route_is_suppressed_until_penalty_decays() {
+        let damping = FlapDamping::new(FlapDampingConfig {
+            suppress_threshold: 2500,
+            reuse_threshold: 1000,
+            half_life: Duration::from_secs(60),
+        });
+        let subnet = "400::/64".parse().unwrap();
+
+        damping.record_change(subnet, None, "tcp://[2001:db8::1]:9651");
+        damping.record_change(subnet, None, "tcp://[2001:db8::1]:9651");
+        assert!(damping.suppressed_routes().is_empty());
+
+        damping.record_change(subnet, None, "tcp://[2001:db8::1]:9651");
+        let suppressed = damping.suppressed_routes();
+        assert_eq!(suppressed.len(), 1);
+        assert_eq!(suppressed[0].subnet, subnet);
+        assert_eq!(suppressed[0].neighbour, "tcp://[2001:db8::1]:9651");
+
+        // Pretend the last change happened 1 half life ago. The penalty is then still above the
+        // reuse threshold.
+        let key = PenaltyKey {
+            subnet,
+            source_prefix: None,
+            neighbour: "tcp://[2001:db8::1]:9651".to_string(),
+        };
+        let backdate = |age: Duration| {
+            let mut penalties = damping.penalties.lock().unwrap();
+            let penalty = penalties.get_mut(&key).unwrap();
+            penalty.updated = Instant::now() - age;
+        };
+        backdate(Duration::from_secs(60));
+        assert_eq!(damping.suppressed_routes().len(), 1);
+
+        // 2 more half lives bring it below the reuse threshold.
+        backdate(Duration::from_secs(120));
+        assert!(damping.suppressed_routes().is_empty());
+    }
+}
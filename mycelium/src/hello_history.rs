@@ -0,0 +1,199 @@
+//! Tracking of the Hello messages received from a peer, to estimate the packet loss on the link
+//! to it, as described in [the babel rfc](https://datatracker.ietf.org/doc/html/rfc8966#appendix-A.1).
+//!
+//! Every Hello sent to a peer carries a sequence number which is 1 higher than the one of the
+//! previous Hello. A gap in the sequence numbers of the received Hellos, or a Hello which is not
+//! received in time, marks Hellos as lost. The fraction of the recent Hellos which was received is
+//! the reception fraction of the link. Links which lose Hellos get a higher link cost, and can be
+//! sent Hellos more often, so a failing link is detected sooner.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::sequence_number::SeqNo;
+
+/// The default amount of recent Hellos used to compute the reception fraction.
+pub const DEFAULT_HELLO_HISTORY_WINDOW: u8 = 16;
+
+/// The maximum amount of recent Hellos which can be used to compute the reception fraction.
+pub const MAX_HELLO_HISTORY_WINDOW: u8 = 64;
+
+/// The default cost added to a link on which all recent Hellos were lost.
+pub const DEFAULT_HELLO_LOSS_COST: u16 = 1000;
+
+/// A Hello is considered lost if it is not received within this multiple of the interval
+/// advertised in the previous Hello.
+const HELLO_TIMEOUT_FACTOR: f64 = 1.5;
+
+/// Parameters of the Hello reception tracking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HelloHistoryConfig {
+    /// Amount of recent Hellos used to compute the reception fraction. This must be between 1 and
+    /// [`MAX_HELLO_HISTORY_WINDOW`].
+    pub window: u8,
+    /// Cost added to a link on which all Hellos in the window were lost. Links with partial loss
+    /// get a proportional part of this cost.
+    pub loss_cost: u16,
+    /// Reception fraction below which Hellos are sent to a peer twice as often, if set. This must
+    /// be between 0 and 1.
+    pub fast_hello_threshold: Option<f64>,
+}
+
+impl Default for HelloHistoryConfig {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_HELLO_HISTORY_WINDOW,
+            loss_cost: DEFAULT_HELLO_LOSS_COST,
+            fast_hello_threshold: None,
+        }
+    }
+}
+
+impl HelloHistoryConfig {
+    /// The cost added to a link with the given reception fraction.
+    pub(crate) fn link_cost(&self, reception: f64) -> u16 {
+        (self.loss_cost as f64 * (1.0 - reception)).round() as u16
+    }
+
+    /// Checks if Hellos should be sent more often on a link with the given reception fraction.
+    pub(crate) fn wants_fast_hellos(&self, reception: f64) -> bool {
+        self.fast_hello_threshold
+            .is_some_and(|threshold| reception < threshold)
+    }
+}
+
+/// The recent history of Hellos received from a peer.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct HelloHistory {
+    /// Bit `i` is set if the `i`-th most recent Hello was received.
+    received: u64,
+    /// Amount of Hellos tracked in `received`.
+    len: u8,
+    /// Sequence number of the next expected Hello, if any Hello was received.
+    expected: Option<SeqNo>,
+    /// Time after which the next expected Hello is considered lost.
+    deadline: Option<Instant>,
+    /// Interval advertised in the last received Hello.
+    interval: Duration,
+    /// Sequence number and reception time of the last received Hello.
+    last_received: Option<(SeqNo, Instant)>,
+}
+
+impl HelloHistory {
+    /// Record a Hello with the given sequence number and advertised interval, received at `now`.
+    pub fn received(&mut self, seqno: SeqNo, interval: Duration, now: Instant) {
+        if let Some(expected) = self.expected {
+            let ahead = u16::from(seqno).wrapping_sub(expected.into());
+            let behind = u16::from(expected).wrapping_sub(seqno.into());
+            if ahead <= MAX_HELLO_HISTORY_WINDOW as u16 {
+                // Hellos in between were lost.
+                self.push(false, ahead);
+            } else if behind <= self.len as u16 {
+                // The Hello was late, and already marked as lost when it timed out.
+                self.received >>= behind;
+                self.len -= behind as u8;
+            } else {
+                // The peer most likely lost its sequence number, so start over.
+                *self = Self::default();
+            }
+        }
+        self.push(true, 1);
+        self.expected = Some(seqno + 1);
+        self.interval = interval;
+        self.deadline = Some(now + interval.mul_f64(HELLO_TIMEOUT_FACTOR));
+        self.last_received = Some((seqno, now));
+    }
+
+    /// The sequence number and reception time of the last received Hello, if any.
+    pub fn last_received(&self) -> Option<(SeqNo, Instant)> {
+        self.last_received
+    }
+
+    /// The interval advertised in the last received Hello, if any.
+    pub fn interval(&self) -> Option<Duration> {
+        self.last_received.map(|_| self.interval)
+    }
+
+    /// Mark the expected Hellos which were not received before `now` as lost.
+    pub fn check_timeout(&mut self, now: Instant) {
+        let (Some(expected), Some(deadline)) = (&mut self.expected, &mut self.deadline) else {
+            return;
+        };
+        let mut lost = 0;
+        while *deadline <= now && lost < MAX_HELLO_HISTORY_WINDOW as u16 {
+            *expected += 1;
+            // A zero interval would never move the deadline past `now`.
+            *deadline += self.interval.max(Duration::from_millis(10));
+            lost += 1;
+        }
+        self.push(false, lost);
+    }
+
+    /// The fraction of the last `window` Hellos which was received. If no Hellos are tracked yet,
+    /// the link is assumed to be lossless.
+    pub fn reception(&self, window: u8) -> f64 {
+        let len = self.len.min(window);
+        if len == 0 {
+            return 1.0;
+        }
+        let mask = u64::MAX >> (64 - len as u32);
+        (self.received & mask).count_ones() as f64 / len as f64
+    }
+
+    /// Add `amount` Hellos which were either received or lost to the history.
+    fn push(&mut self, received: bool, amount: u16) {
+        for _ in 0..amount.min(MAX_HELLO_HISTORY_WINDOW as u16) {
+            self.received = (self.received << 1) | received as u64;
+        }
+        self.len = (self.len as u16 + amount).min(MAX_HELLO_HISTORY_WINDOW as u16) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{HelloHistory, HelloHistoryConfig};
+
+    #[test]
+    fn reception_follows_lost_hellos() {
+        let interval = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut history = HelloHistory::default();
+        assert_eq!(history.reception(4), 1.0);
+
+        history.received(10.into(), interval, start);
+        history.received(11.into(), interval, start + interval);
+        // Hellos 12 and 13 are lost.
+        history.received(14.into(), interval, start + interval * 4);
+        assert_eq!(
+            history.last_received(),
+            Some((14.into(), start + interval * 4))
+        );
+        assert_eq!(history.interval(), Some(interval));
+        assert_eq!(history.reception(4), 0.5);
+        assert_eq!(history.reception(16), 0.6);
+
+        // Hello 15 times out, but then arrives late.
+        history.check_timeout(start + interval * 6);
+        assert_eq!(history.reception(2), 0.5);
+        history.received(15.into(), interval, start + interval * 6);
+        assert_eq!(history.reception(2), 1.0);
+
+        // A seqno far from the expected one resets the history.
+        history.received(1000.into(), interval, start + interval * 7);
+        assert_eq!(history.reception(16), 1.0);
+
+        let config = HelloHistoryConfig {
+            window: 4,
+            loss_cost: 100,
+            fast_hello_threshold: Some(0.75),
+        };
+        assert_eq!(config.link_cost(0.5), 50);
+        assert!(config.wants_fast_hellos(0.5));
+        assert!(!config.wants_fast_hellos(1.0));
+    }
+}
@@ -0,0 +1,107 @@
+//! Mapping of IPv4 addresses to nodes in the overlay, so IPv4 traffic can be carried over the IPv6
+//! overlay network (4in6).
+//!
+//! IPv4 packets read from the TUN interface are sent to the node which their destination address
+//! is mapped to, and written as is to the TUN interface of that node. A received IPv4 packet is
+//! only accepted if its source address is mapped to the node which sent it, so nodes can't spoof
+//! IPv4 addresses of other nodes. Both ends therefore need the mappings of each other. The IPv4
+//! addresses and routes on the TUN interface must be configured by the host.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Minimum size in bytes of an IPv4 header.
+pub(crate) const IPV4_MIN_HEADER_SIZE: usize = 20;
+
+/// Maps an IPv4 address to the overlay IP of the node which owns it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Mapping {
+    /// The mapped IPv4 address.
+    pub ipv4: Ipv4Addr,
+    /// Overlay IP of the node which owns the IPv4 address.
+    pub overlay_ip: Ipv6Addr,
+}
+
+/// A table of [`Ipv4Mapping`]s. If this is empty, IPv4 packets are not forwarded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ipv4Mappings {
+    /// The configured mappings.
+    pub mappings: Vec<Ipv4Mapping>,
+}
+
+impl Ipv4Mappings {
+    /// Checks if there are no mappings configured.
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// The overlay IP of the node an IPv4 address is mapped to, if any.
+    pub fn overlay_ip(&self, ipv4: Ipv4Addr) -> Option<Ipv6Addr> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.ipv4 == ipv4)
+            .map(|mapping| mapping.overlay_ip)
+    }
+
+    /// Checks if an IPv4 address is mapped to the node with the given overlay IP.
+    pub fn is_mapped_to(&self, ipv4: Ipv4Addr, overlay_ip: Ipv6Addr) -> bool {
+        self.mappings
+            .iter()
+            .any(|mapping| mapping.ipv4 == ipv4 && mapping.overlay_ip == overlay_ip)
+    }
+}
+
+/// Set the TTL of an IPv4 packet, and update the header checksum accordingly. The packet must be
+/// at least [`IPV4_MIN_HEADER_SIZE`] bytes long.
+pub(crate) fn set_ttl(packet: &mut [u8], ttl: u8) {
+    packet[8] = ttl;
+    let header_len = ((packet[0] & 0x0f) as usize * 4).clamp(IPV4_MIN_HEADER_SIZE, packet.len());
+    packet[10..12].fill(0);
+    let sum = packet[..header_len]
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    let sum = (sum & 0xffff) + (sum >> 16);
+    let sum = (sum & 0xffff) + (sum >> 16);
+    packet[10..12].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::{set_ttl, Ipv4Mapping, Ipv4Mappings};
+
+    #[test]
+    fn mappings_resolve_both_ways() {
+        let node = Ipv6Addr::new(0x400, 1, 0, 0, 0, 0, 0, 1);
+        let mappings = Ipv4Mappings {
+            mappings: vec![Ipv4Mapping {
+                ipv4: Ipv4Addr::new(10, 0, 0, 1),
+                overlay_ip: node,
+            }],
+        };
+
+        assert_eq!(mappings.overlay_ip(Ipv4Addr::new(10, 0, 0, 1)), Some(node));
+        assert_eq!(mappings.overlay_ip(Ipv4Addr::new(10, 0, 0, 2)), None);
+        assert!(mappings.is_mapped_to(Ipv4Addr::new(10, 0, 0, 1), node));
+        assert!(!mappings.is_mapped_to(
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv6Addr::new(0x400, 2, 0, 0, 0, 0, 0, 1)
+        ));
+    }
+
+    #[test]
+    fn setting_ttl_updates_checksum() {
+        // Header from the example in https://en.wikipedia.org/wiki/Internet_checksum.
+        let mut header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        set_ttl(&mut header, 0x40);
+        assert_eq!(header[10..12], [0xb8, 0x61]);
+
+        set_ttl(&mut header, 0x3f);
+        assert_eq!(header[8], 0x3f);
+        assert_eq!(header[10..12], [0xb9, 0x61]);
+    }
+}
@@ -0,0 +1,105 @@
+//! Randomized timing of periodic control packets.
+//!
+//! If all nodes send their periodic packets on the same cadence, they can synchronize, so the
+//! packets of the whole network arrive in bursts. To avoid this, every period is shortened by a
+//! random fraction. Periods are only shortened, since the interval advertised in Hellos and
+//! updates is an upper bound on the time until the next one is sent.
+
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::time::Instant;
+
+/// Fraction of the hello interval by which the time until the next Hello is shortened at most.
+pub const HELLO_JITTER: f64 = 0.5;
+/// Fraction of the update interval by which the time until the next periodic update is shortened
+/// at most.
+pub const UPDATE_JITTER: f64 = 0.1;
+
+/// Source of random shortened periods.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    /// Maximum fraction by which a period is shortened, between 0 and 1.
+    max: f64,
+    rng: StdRng,
+}
+
+impl Jitter {
+    /// Create a new `Jitter` which shortens periods by at most the `max` fraction, using a random
+    /// number generator seeded with `seed`. The same seed always gives the same periods.
+    pub fn new(max: f64, seed: u64) -> Self {
+        Self {
+            max: max.clamp(0.0, 1.0),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Get the next jittered `period`.
+    pub fn apply(&mut self, period: Duration) -> Duration {
+        period.mul_f64(1.0 - self.rng.gen_range(0.0..=self.max))
+    }
+}
+
+/// A periodic timer like [`tokio::time::Interval`], but every period is jittered. Like the
+/// intervals used by the router, the first tick only completes after a period, and missed ticks
+/// are skipped.
+#[derive(Debug)]
+pub struct JitteredInterval {
+    period: Duration,
+    jitter: Jitter,
+    /// Time at which the next tick completes.
+    next: Instant,
+}
+
+impl JitteredInterval {
+    /// Create a new `JitteredInterval` which ticks about every `period`.
+    pub fn new(period: Duration, mut jitter: Jitter) -> Self {
+        let next = Instant::now() + jitter.apply(period);
+        Self {
+            period,
+            jitter,
+            next,
+        }
+    }
+
+    /// Wait until the next tick.
+    pub async fn tick(&mut self) {
+        tokio::time::sleep_until(self.next).await;
+        // Schedule from the deadline rather than from now, so the ticks don't drift, unless a
+        // whole period was missed.
+        let now = Instant::now();
+        let base = if now > self.next + self.period {
+            now
+        } else {
+            self.next
+        };
+        self.next = base + self.jitter.apply(self.period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Jitter;
+
+    #[test]
+    fn periods_are_shortened_reproducibly() {
+        let period = Duration::from_secs(20);
+        let mut a = Jitter::new(0.5, 42);
+        let mut b = Jitter::new(0.5, 42);
+
+        let periods = (0..100).map(|_| a.apply(period)).collect::<Vec<_>>();
+        assert_eq!(
+            periods,
+            (0..100).map(|_| b.apply(period)).collect::<Vec<_>>()
+        );
+        assert!(periods.iter().all(|p| *p >= period / 2 && *p <= period));
+        // The periods are actually spread out.
+        assert!(periods.iter().any(|p| *p < period.mul_f64(0.75)));
+        assert!(periods.iter().any(|p| *p > period.mul_f64(0.75)));
+
+        let mut none = Jitter::new(0.0, 42);
+        assert_eq!(none.apply(period), period);
+    }
+}
@@ -1,48 +1,107 @@
-use std::net::{IpAddr, Ipv6Addr};
-#[cfg(feature = "message")]
-use std::{future::Future, time::Duration};
+use std::future::Future;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::tun::TunConfig;
+use access_list::AccessList;
 use bytes::BytesMut;
 use data::DataPlane;
 use endpoint::Endpoint;
 #[cfg(feature = "message")]
 use message::{
-    MessageId, MessageInfo, MessagePushResponse, MessageStack, PushMessageError, ReceivedMessage,
+    DeliveryStatus, MessageId, MessageInfo, MessagePushResponse, MessageStack, MessageSubscription,
+    PushMessageError, ReceivedMessage,
 };
 use metrics::Metrics;
-use peer_manager::{PeerExists, PeerNotFound, PeerStats, PrivateNetworkKey};
+use peer_manager::{
+    PeerExists, PeerNotFound, PeerStats, PrivateNetworkKey, ProbeError, ReplacePeerError,
+};
+use router::RouteEvent;
 use routing_table::RouteEntry;
 use subnet::Subnet;
+use tokio::sync::broadcast;
+#[cfg(feature = "message")]
+use tokio::sync::watch;
 use tracing::{error, info, warn};
 
+pub mod access_list;
 mod babel;
+pub mod capture;
 mod connection;
 pub mod crypto;
 pub mod data;
+pub mod echo;
 pub mod endpoint;
+pub mod event_log;
+#[cfg(feature = "testing")]
+pub mod fault_injection;
 pub mod filters;
+pub mod flap_damping;
+pub mod hello_history;
 mod interval;
+pub mod ipv4_mapping;
+mod jitter;
 #[cfg(feature = "message")]
 pub mod message;
 mod metric;
+pub mod metric_offset;
 pub mod metrics;
 pub mod packet;
+mod path_mtu;
 mod peer;
 pub mod peer_manager;
+pub mod pinned_route;
+pub mod route_selection;
 pub mod router;
 mod router_id;
 mod routing_table;
 mod seqno_cache;
 mod sequence_number;
 mod source_table;
+pub mod stats;
 pub mod subnet;
+mod triggered_updates;
 mod tun;
 
 /// The prefix of the global subnet used.
 pub const GLOBAL_SUBNET_ADDRESS: IpAddr = IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0));
 /// The prefix length of the global subnet used.
 pub const GLOBAL_SUBNET_PREFIX_LEN: u8 = 7;
+/// The default MTU of the TUN interface.
+pub const DEFAULT_TUN_MTU: u16 = 1400;
+/// The minimum MTU of the TUN interface, this is the minimum MTU required by IPv6.
+pub const MIN_TUN_MTU: u16 = 1280;
+/// The maximum amount of packet workers. This is the maximum amount of queues of a TUN interface
+/// on Linux.
+pub const MAX_PACKET_WORKERS: usize = 256;
+/// The default amount of connected peers a node needs to be ready.
+pub const DEFAULT_READY_MIN_PEERS: usize = 1;
+
+/// Checks if an IP is part of the global subnet, i.e. it is an overlay IP.
+pub(crate) fn in_global_subnet(ip: IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(ip) if (0x04..0x06).contains(&ip.octets()[0]))
+}
+
+/// Routing table and metric of the route to the global subnet installed for the TUN interface.
+/// These can only be set on Linux. If neither is set, the route is added by the kernel when the
+/// address of the node is assigned to the interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TunRouteConfig {
+    /// Routing table the route is added to, instead of the main table. Use a policy routing rule
+    /// to look up routes in this table.
+    pub table: Option<u32>,
+    /// Metric of the route, instead of the default metric of the kernel.
+    pub metric: Option<u32>,
+}
+
+impl TunRouteConfig {
+    /// Checks if a routing table or metric is set, in which case the route must be installed
+    /// explicitly.
+    pub fn is_custom(&self) -> bool {
+        self.table.is_some() || self.metric.is_some()
+    }
+}
 
 /// Config for a mycelium [`Node`].
 pub struct Config<M> {
@@ -52,8 +111,11 @@ pub struct Config<M> {
     pub peers: Vec<Endpoint>,
     /// Tun interface should be disabled.
     pub no_tun: bool,
-    /// Listen port for TCP connections.
-    pub tcp_listen_port: u16,
+    /// Addresses to listen on for TCP connections from peers, e.g. on multiple networks. If this
+    /// is empty, no inbound TCP connections are accepted, e.g. for nodes behind NAT which only
+    /// connect to peers themselves. Link local peer discovery beacons are only sent if one of the
+    /// addresses is the unspecified IPv6 address or a link local address.
+    pub tcp_listen_addrs: Vec<SocketAddr>,
     /// Listen port for Quic connections.
     pub quic_listen_port: Option<u16>,
     /// Udp port for peer discovery.
@@ -61,15 +123,115 @@ pub struct Config<M> {
     /// Name for the TUN device.
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
     pub tun_name: String,
+    /// MTU of the TUN device. Packets which are larger than a single data packet are fragmented
+    /// before they are sent to peers, and reassembled at the receiver.
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    pub tun_mtu: u16,
+    /// Routing table and metric of the route installed for the TUN device.
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    pub tun_route: TunRouteConfig,
 
     /// Configuration for a private network, if run in that mode. To enable private networking,
     /// this must be a name + a PSK.
     pub private_network_config: Option<(String, PrivateNetworkKey)>,
+    /// Pre-shared key remotes must prove knowledge of before they are accepted as peer. If this is
+    /// set, only nodes configured with the same key can peer with this node, over any transport.
+    pub peering_key: Option<peer_manager::PeeringKey>,
     /// Implementation of the `Metrics` trait, used to expose information about the system
     /// internals.
     pub metrics: M,
     /// Mark that's set on all packets that we send on the underlying network
     pub firewall_mark: Option<u32>,
+    /// Limits applied to inbound peer connections.
+    pub inbound_connection_limits: peer_manager::InboundConnectionLimits,
+    /// Timeouts applied when connecting to peers, so a connection attempt which hangs is retried.
+    /// Both timeouts must not be zero.
+    pub dial_timeouts: peer_manager::DialTimeouts,
+    /// SOCKS5 proxy through which outbound Tcp and Tls connections to peers are made, e.g. in
+    /// networks which only allow internet access through a proxy. Link local peers are still
+    /// connected to directly, and Quic peers can't be connected to while this is set. Inbound
+    /// connections are not affected. Proxy credentials must be between 1 and 255 bytes.
+    pub proxy: Option<peer_manager::Socks5Proxy>,
+    /// Underlay IPs which are allowed to connect to the node as inbound peer.
+    pub peer_access_list: AccessList,
+    /// Overlay subnets for which routes are accepted from peers.
+    pub route_access_list: AccessList,
+    /// How selected routes are advertised to the peer they are selected through.
+    pub split_horizon: router::SplitHorizon,
+    /// Policy used to select a route for a subnet out of the feasible routes. Use
+    /// [`MetricSelector`](route_selection::MetricSelector) for the default metric based selection.
+    pub route_selector: Box<dyn route_selection::RouteSelector + Send + Sync>,
+    /// Multiplier applied to the interval advertised in a route update, to get the time after
+    /// which the route expires if it is not refreshed. This must be at least 1. The default is
+    /// [`DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER`](router::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER).
+    pub route_hold_time_multiplier: f64,
+    /// Intervals at which Hello, IHU and route update packets are sent. Every interval must be
+    /// between [`MIN_INTERVAL`](router::MIN_INTERVAL) and [`MAX_INTERVAL`](router::MAX_INTERVAL),
+    /// except for the minimum time between triggered updates, which must not be larger than the
+    /// update interval. This also holds the amount of hello intervals without an IHU after which a peer is
+    /// considered dead, which must be at least 1.
+    pub intervals: router::Intervals,
+    /// Capacities of the queues between the tasks which handle packets. Every size must be at
+    /// least 1.
+    pub queue_sizes: router::QueueSizes,
+    /// Parameters of route flap damping, which suppresses routes that keep changing. The reuse
+    /// threshold must be lower than the suppress threshold, and the half life must not be zero.
+    pub flap_damping: flap_damping::FlapDampingConfig,
+    /// Parameters of the tracking of Hellos received from peers, which is used to estimate the
+    /// loss on the links to them. The window must be between 1 and
+    /// [`MAX_HELLO_HISTORY_WINDOW`](hello_history::MAX_HELLO_HISTORY_WINDOW), and the fast hello
+    /// threshold, if set, between 0 and 1.
+    pub hello_history: hello_history::HelloHistoryConfig,
+    /// Offsets added to the metric of routes through peers in some overlay subnets, so other
+    /// routes are preferred.
+    pub metric_offsets: metric_offset::MetricOffsets,
+    /// Maximum amount of routes in the routing table, including fallback routes. Once it is
+    /// reached, new routes are only learned if a less preferred route can be evicted. If not set,
+    /// the amount of routes is not limited. If set, this must not be zero.
+    pub max_routes: Option<usize>,
+    /// Amount of recent routing events kept in the event log, see [`Node::routing_events`]. The
+    /// default is [`DEFAULT_EVENT_LOG_SIZE`](event_log::DEFAULT_EVENT_LOG_SIZE). If this is 0, no
+    /// events are logged.
+    pub event_log_size: usize,
+    /// IPv4 addresses mapped to nodes in the overlay. IPv4 packets from the TUN interface are
+    /// forwarded to the node their destination is mapped to. If empty, IPv4 is not forwarded.
+    pub ipv4_mappings: ipv4_mapping::Ipv4Mappings,
+    /// Announce a default route (`::/0`), so other nodes can use this node as exit node to reach
+    /// IPv6 addresses outside of the overlay. Packets for those addresses are written to the TUN
+    /// interface, so the host must forward them to its uplink, and usually NAT them. This requires
+    /// a TUN interface.
+    pub exit_node: bool,
+    /// Source prefixes the default route of an exit node is restricted to. If not empty, the
+    /// default route is announced as a source-specific route for each of these prefixes, so only
+    /// traffic from them is sent through this node. Only used if `exit_node` is set.
+    pub exit_source_prefixes: Vec<Subnet>,
+    /// Accept default routes announced by exit nodes. The default route is only used for
+    /// addresses outside of the overlay, which the host must route to the TUN interface. If this
+    /// is not set, default routes are ignored, so other nodes can't attract traffic unexpectedly.
+    pub accept_default_route: bool,
+    /// Compress data sent to remote nodes which support it, if this makes the data smaller. This
+    /// saves bandwidth on slow links at the cost of some CPU time.
+    pub compress_data: bool,
+    /// Amount of tasks which process data packets in each direction, so encryption and decryption
+    /// is spread over multiple cores. On Linux, the TUN interface is opened with this many queues.
+    /// Packets of the same flow are always processed by the same task, so they stay in order. This
+    /// must be between 1 and [`MAX_PACKET_WORKERS`].
+    pub packet_workers: usize,
+    /// Persist received messages which are not read yet, so they are not lost if the node
+    /// restarts. The stored messages are encrypted with a key derived from the node key.
+    #[cfg(feature = "message")]
+    pub message_store: Option<message::MessageStoreConfig>,
+    /// Write the plaintext IP packets exchanged with remote nodes to pcap files, to debug
+    /// connectivity over the overlay. This exposes all traffic of the node unencrypted, so it
+    /// should only be set while debugging.
+    pub packet_capture: Option<capture::PacketCaptureConfig>,
+    /// Reply to ICMPv6 echo requests for the overlay address of the node, so it can be pinged
+    /// over the overlay without relying on the host. If not set, echo requests are handled like
+    /// any other packet.
+    pub echo_reply: Option<echo::EchoReplyConfig>,
+    /// Amount of peers the node must be connected to before it is ready, see
+    /// [`NodeHealth::is_ready`]. If 0, the node is ready as soon as it is running.
+    pub ready_min_peers: usize,
 
     // tun_fd is android and iOS specific option
     // We can't create TUN device from the Rust code in android and iOS.
@@ -83,14 +245,42 @@ pub struct Config<M> {
 pub struct Node<M> {
     router: router::Router<M>,
     peer_manager: peer_manager::PeerManager<M>,
+    /// Overlay subnets for which routes are accepted, shared with the update filter of the router.
+    route_access_list: Arc<RwLock<AccessList>>,
+    /// Time at which the node was started.
+    started: Instant,
+    /// Amount of peers the node must be connected to before it is ready.
+    ready_min_peers: usize,
     #[cfg(feature = "message")]
     message_stack: message::MessageStack<M>,
 }
 
+/// Cheap handle to check the health of a [`Node`], without access to the node itself. This is
+/// intended for liveness and readiness probes, which are checked often.
+#[derive(Clone)]
+pub struct NodeHealth {
+    router: router::RouterHealth,
+    ready_min_peers: usize,
+}
+
 /// General info about a node.
 pub struct NodeInfo {
     /// The overlay subnet in use by the node.
     pub node_subnet: Subnet,
+    /// The public key of the node.
+    pub node_pubkey: crypto::PublicKey,
+    /// Time since the node was started.
+    pub uptime: Duration,
+    /// Version of the mycelium library used by the node.
+    pub version: &'static str,
+    /// Amount of peers the node currently has a working connection to.
+    pub connected_peers: usize,
+    /// Amount of subnets for which the node has a selected route.
+    pub selected_routes: usize,
+    /// Sequence number the node currently announces its own subnet with.
+    pub router_seqno: u16,
+    /// Addresses on which inbound TCP connections from peers are accepted.
+    pub tcp_listen_addrs: Vec<SocketAddr>,
 }
 
 impl<M> Node<M>
@@ -99,6 +289,7 @@ where
 {
     /// Setup a new `Node` with the provided [`Config`].
     pub async fn new(config: Config<M>) -> Result<Self, Box<dyn std::error::Error>> {
+        let started = Instant::now();
         // If a private network is configured, validate network name
         if let Some((net_name, _)) = &config.private_network_config {
             if net_name.len() < 2 || net_name.len() > 64 {
@@ -109,35 +300,233 @@ where
                 .into());
             }
         }
+        if !(config.route_hold_time_multiplier >= 1.0
+            && config.route_hold_time_multiplier.is_finite())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "route hold time multiplier must be at least 1",
+            )
+            .into());
+        }
+        for (name, interval) in [
+            ("hello", config.intervals.hello),
+            ("IHU", config.intervals.ihu),
+            ("update", config.intervals.update),
+        ] {
+            if !(router::MIN_INTERVAL..=router::MAX_INTERVAL).contains(&interval) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "{name} interval must be between {} and {} seconds",
+                        router::MIN_INTERVAL.as_secs(),
+                        router::MAX_INTERVAL.as_secs()
+                    ),
+                )
+                .into());
+            }
+        }
+        if config.intervals.triggered_update > config.intervals.update {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "triggered update interval must not be larger than the update interval",
+            )
+            .into());
+        }
+        if config.intervals.dead_peer_hellos == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "dead peer hello count must be at least 1",
+            )
+            .into());
+        }
+        for (name, size) in [
+            ("router data", config.queue_sizes.router_data),
+            ("router control", config.queue_sizes.router_control),
+            ("peer data", config.queue_sizes.peer_data),
+            ("node TUN", config.queue_sizes.node_tun),
+            ("TUN", config.queue_sizes.tun),
+        ] {
+            if size == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{name} queue size must be at least 1"),
+                )
+                .into());
+            }
+        }
+        if !(1..=MAX_PACKET_WORKERS).contains(&config.packet_workers) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("packet workers must be between 1 and {MAX_PACKET_WORKERS}"),
+            )
+            .into());
+        }
+        if config.dial_timeouts.connect.is_zero() || config.dial_timeouts.handshake.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "dial timeouts must not be zero",
+            )
+            .into());
+        }
+        if config
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.credentials.as_ref())
+            .is_some_and(|credentials| !credentials.is_valid())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "proxy username and password must be between 1 and 255 bytes",
+            )
+            .into());
+        }
+        if config.max_routes == Some(0) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "maximum amount of routes must not be zero",
+            )
+            .into());
+        }
+        if config.flap_damping.reuse_threshold >= config.flap_damping.suppress_threshold
+            || config.flap_damping.half_life.is_zero()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "flap damping reuse threshold must be lower than the suppress threshold, and the half life must not be zero",
+            )
+            .into());
+        }
+        if !(1..=hello_history::MAX_HELLO_HISTORY_WINDOW).contains(&config.hello_history.window) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "hello history window must be between 1 and {}",
+                    hello_history::MAX_HELLO_HISTORY_WINDOW
+                ),
+            )
+            .into());
+        }
+        if config
+            .hello_history
+            .fast_hello_threshold
+            .is_some_and(|threshold| !(0.0..=1.0).contains(&threshold))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "fast hello threshold must be between 0 and 1",
+            )
+            .into());
+        }
+        #[cfg(any(target_os = "macos", target_os = "windows"))]
+        if config.tun_route.is_custom() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a routing table or metric for the TUN route can only be set on Linux",
+            )
+            .into());
+        }
+        if config.exit_node && config.no_tun {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "an exit node needs a TUN interface to forward traffic",
+            )
+            .into());
+        }
+        if config
+            .exit_source_prefixes
+            .iter()
+            .any(|sp| !sp.address().is_ipv6() || sp.prefix_len() == 0)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "exit source prefixes must be IPv6 subnets which don't contain all addresses",
+            )
+            .into());
+        }
+        if config.exit_node {
+            warn!("Running as exit node, other nodes can send traffic to IPv6 addresses outside of the overlay through this node");
+            #[cfg(target_os = "linux")]
+            if std::fs::read_to_string("/proc/sys/net/ipv6/conf/all/forwarding")
+                .is_ok_and(|forwarding| forwarding.trim() != "1")
+            {
+                warn!("IPv6 forwarding is disabled, traffic of other nodes can't leave this exit node");
+            }
+        }
+        #[cfg(feature = "message")]
+        let message_store = config
+            .message_store
+            .map(|store_config| message::MessageStore::new(store_config, &config.node_key));
+        let packet_capture = match config.packet_capture {
+            Some(capture_config) => {
+                warn!(
+                    "Packet capture enabled, unencrypted overlay traffic is written to {}",
+                    capture_config.path.display()
+                );
+                Some(capture::PacketCapture::new(capture_config)?)
+            }
+            None => None,
+        };
         let node_pub_key = crypto::PublicKey::from(&config.node_key);
         let node_addr = node_pub_key.address();
-        let (tun_tx, tun_rx) = tokio::sync::mpsc::unbounded_channel();
-
-        let node_subnet = Subnet::new(
-            // Truncate last 64 bits of address.
-            // TODO: find a better way to do this.
-            Subnet::new(node_addr.into(), 64)
-                .expect("64 is a valid IPv6 prefix size; qed")
-                .network(),
-            64,
-        )
-        .expect("64 is a valid IPv6 prefix size; qed");
+        let (tun_tx, tun_rx) = tokio::sync::mpsc::channel(config.queue_sizes.node_tun);
+
+        let node_subnet = node_pub_key.subnet();
+
+        let mut static_routes = vec![(node_subnet, None)];
+        if config.exit_node {
+            let default_route = Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+                .expect("Default route is properly defined; qed");
+            if config.exit_source_prefixes.is_empty() {
+                static_routes.push((default_route, None));
+            } else {
+                static_routes.extend(
+                    config
+                        .exit_source_prefixes
+                        .iter()
+                        .map(|sp| (default_route, Some(*sp))),
+                );
+            }
+        }
+
+        let mut update_filters: Vec<Box<dyn filters::RouteUpdateFilter + Send + Sync>> = vec![
+            Box::new(filters::AllowedSubnet::new(
+                Subnet::new(GLOBAL_SUBNET_ADDRESS, GLOBAL_SUBNET_PREFIX_LEN)
+                    .expect("Global subnet is properly defined; qed"),
+            )),
+            Box::new(filters::MaxSubnetSize::<64>),
+        ];
+        if config.accept_default_route {
+            update_filters = update_filters
+                .into_iter()
+                .map(|filter| Box::new(filters::OrDefaultRoute::new(filter)) as _)
+                .collect();
+        }
 
         // Creating a new Router instance
+        let route_access_list = Arc::new(RwLock::new(config.route_access_list));
+        update_filters.push(Box::new(filters::AllowedByAccessList::new(
+            route_access_list.clone(),
+        )));
         let router = match router::Router::new(
             tun_tx,
-            node_subnet,
-            vec![node_subnet],
-            (config.node_key, node_pub_key),
-            vec![
-                Box::new(filters::AllowedSubnet::new(
-                    Subnet::new(GLOBAL_SUBNET_ADDRESS, GLOBAL_SUBNET_PREFIX_LEN)
-                        .expect("Global subnet is properly defined; qed"),
-                )),
-                Box::new(filters::MaxSubnetSize::<64>),
-                Box::new(filters::RouterIdOwnsSubnet),
-            ],
-            config.metrics.clone(),
+            router::RouterConfig {
+                node_subnet,
+                static_routes,
+                node_keypair: (config.node_key, node_pub_key),
+                update_filters,
+                route_selector: config.route_selector,
+                split_horizon: config.split_horizon,
+                route_hold_time_multiplier: config.route_hold_time_multiplier,
+                intervals: config.intervals,
+                queue_sizes: config.queue_sizes,
+                flap_damping: config.flap_damping,
+                hello_history: config.hello_history,
+                metric_offsets: config.metric_offsets,
+                max_routes: config.max_routes,
+                event_log_size: config.event_log_size,
+                metrics: config.metrics.clone(),
+            },
         ) {
             Ok(router) => {
                 info!(
@@ -155,18 +544,20 @@ where
         // Creating a new PeerManager instance
         let pm = peer_manager::PeerManager::new(
             router.clone(),
-            config.peers,
-            config.tcp_listen_port,
-            config.quic_listen_port,
-            if let Some(port) = config.peer_discovery_port {
-                port
-            } else {
-                0
+            peer_manager::PeerManagerConfig {
+                static_peers: config.peers,
+                tcp_listen_addrs: config.tcp_listen_addrs,
+                quic_listen_port: config.quic_listen_port,
+                peer_discovery_port: config.peer_discovery_port,
+                private_network_config: config.private_network_config,
+                peering_key: config.peering_key,
+                metrics: config.metrics,
+                firewall_mark: config.firewall_mark,
+                inbound_connection_limits: config.inbound_connection_limits,
+                dial_timeouts: config.dial_timeouts,
+                proxy: config.proxy,
+                access_list: config.peer_access_list,
             },
-            config.peer_discovery_port.is_none(),
-            config.private_network_config,
-            config.metrics,
-            config.firewall_mark,
         )?;
         info!("Started peer manager");
 
@@ -183,6 +574,11 @@ where
             warn!("Starting data plane without TUN interface, L3 functionality disabled");
             DataPlane::new(
                 router.clone(),
+                config.compress_data,
+                config.packet_workers,
+                config.ipv4_mappings,
+                packet_capture,
+                config.echo_reply,
                 // No tun so create a dummy stream for L3 packets which never yields
                 tokio_stream::pending(),
                 // Similarly, create a sink which just discards every packet we would receive
@@ -216,25 +612,46 @@ where
                         .expect("64 is a valid subnet size for IPv6; qed"),
                     route_subnet: Subnet::new(GLOBAL_SUBNET_ADDRESS, GLOBAL_SUBNET_PREFIX_LEN)
                         .expect("Static configured TUN route is valid; qed"),
+                    mtu: config.tun_mtu,
+                    #[cfg(target_os = "linux")]
+                    queues: config.packet_workers,
+                    #[cfg(target_os = "linux")]
+                    route: config.tun_route,
+                    queue_size: config.queue_sizes.tun,
                 };
                 #[cfg(any(target_os = "android", target_os = "ios"))]
                 let tun_config = TunConfig {
                     tun_fd: config.tun_fd.unwrap(),
+                    queue_size: config.queue_sizes.tun,
                 };
 
                 let (rxhalf, txhalf) = tun::new(tun_config).await?;
 
                 info!("Node overlay IP: {node_addr}");
-                DataPlane::new(router.clone(), rxhalf, txhalf, msg_sender, tun_rx)
+                DataPlane::new(
+                    router.clone(),
+                    config.compress_data,
+                    config.packet_workers,
+                    config.ipv4_mappings,
+                    packet_capture,
+                    config.echo_reply,
+                    rxhalf,
+                    txhalf,
+                    msg_sender,
+                    tun_rx,
+                )
             }
         };
 
         #[cfg(feature = "message")]
-        let ms = MessageStack::new(_data_plane, msg_receiver);
+        let ms = MessageStack::new(_data_plane, msg_receiver, message_store);
 
         Ok(Node {
             router,
             peer_manager: pm,
+            route_access_list,
+            started,
+            ready_min_peers: config.ready_min_peers,
             #[cfg(feature = "message")]
             message_stack: ms,
         })
@@ -244,6 +661,26 @@ where
     pub fn info(&self) -> NodeInfo {
         NodeInfo {
             node_subnet: self.router.node_tun_subnet(),
+            node_pubkey: self.router.node_public_key(),
+            uptime: self.started.elapsed(),
+            version: env!("CARGO_PKG_VERSION"),
+            connected_peers: self.router.peer_interfaces().len(),
+            selected_routes: self.router.selected_route_count(),
+            router_seqno: self.router.router_seqno().into(),
+            tcp_listen_addrs: self.peer_manager.tcp_listen_addrs().to_vec(),
+        }
+    }
+
+    /// Get the node-wide counters of the traffic handled by the `Node`.
+    pub fn stats(&self) -> stats::NodeStats {
+        self.router.stats()
+    }
+
+    /// Get a [`NodeHealth`] handle to check the health of the `Node`.
+    pub fn health(&self) -> NodeHealth {
+        NodeHealth {
+            router: self.router.health(),
+            ready_min_peers: self.ready_min_peers,
         }
     }
 
@@ -252,6 +689,11 @@ where
         self.peer_manager.peers()
     }
 
+    /// Get information about the peer identified by an [`Endpoint`], if it exists.
+    pub fn peer_info_for(&self, endpoint: &Endpoint) -> Option<PeerStats> {
+        self.peer_manager.peer(endpoint)
+    }
+
     /// Add a new peer to the system identified by an [`Endpoint`].
     pub fn add_peer(&self, endpoint: Endpoint) -> Result<(), PeerExists> {
         self.peer_manager.add_peer(endpoint)
@@ -262,22 +704,235 @@ where
         self.peer_manager.delete_peer(&endpoint)
     }
 
+    /// Shut down the node. The connections to all peers are closed, after retracting the routes
+    /// advertised to them, and no new connections are set up. This returns once the connections
+    /// are closed. The node must not be used anymore afterwards, other than dropping it.
+    pub async fn shutdown(&self) {
+        self.peer_manager.shutdown().await
+    }
+
+    /// Replace the [`Endpoint`] of an existing peer, identified by the `old` [`Endpoint`].
+    pub fn replace_peer(&self, old: Endpoint, new: Endpoint) -> Result<(), ReplacePeerError> {
+        self.peer_manager.replace_peer(&old, new)
+    }
+
+    /// Set the bandwidth limits of the data exchanged with an existing peer, identified by an
+    /// [`Endpoint`].
+    pub fn set_peer_rate_limit(
+        &self,
+        endpoint: Endpoint,
+        limit: peer_manager::RateLimit,
+    ) -> Result<(), PeerNotFound> {
+        self.peer_manager.set_rate_limit(&endpoint, limit)
+    }
+
+    /// Set the MTU of the link to an existing peer, identified by an [`Endpoint`], or remove the
+    /// limit if [`None`] is given.
+    pub fn set_peer_mtu(
+        &self,
+        endpoint: Endpoint,
+        mtu: Option<u16>,
+    ) -> Result<(), peer_manager::SetMtuError> {
+        self.peer_manager.set_mtu(&endpoint, mtu)
+    }
+
+    /// Set the priority of an existing peer, identified by an [`Endpoint`], as next hop. Routes
+    /// through peers with a higher priority are preferred over routes with a slightly lower
+    /// metric.
+    pub fn set_peer_priority(&self, endpoint: Endpoint, priority: u8) -> Result<(), PeerNotFound> {
+        self.peer_manager.set_priority(&endpoint, priority)
+    }
+
+    /// Send all selected routes to the connected peer identified by an [`Endpoint`] right away,
+    /// so it can resync its routes through this node.
+    pub fn dump_routes_to_peer(&self, endpoint: Endpoint) -> Result<(), PeerNotFound> {
+        self.peer_manager.dump_routes(&endpoint)
+    }
+
+    /// Get the [`AccessList`] of underlay IPs which are allowed to connect as inbound peer.
+    pub fn peer_access_list(&self) -> AccessList {
+        self.peer_manager.access_list()
+    }
+
+    /// Replace the [`AccessList`] of underlay IPs which are allowed to connect as inbound peer.
+    /// Existing inbound peers which are no longer allowed are disconnected.
+    pub fn set_peer_access_list(&self, access_list: AccessList) {
+        self.peer_manager.set_access_list(access_list)
+    }
+
+    /// Get the [`AccessList`] of overlay subnets for which routes are accepted.
+    pub fn route_access_list(&self) -> AccessList {
+        self.route_access_list
+            .read()
+            .expect("Access list lock is not poisoned; qed")
+            .clone()
+    }
+
+    /// Replace the [`AccessList`] of overlay subnets for which routes are accepted. Existing
+    /// routes to subnets which are no longer allowed are removed, and retracted if they were
+    /// selected.
+    pub fn set_route_access_list(&self, access_list: AccessList) {
+        *self
+            .route_access_list
+            .write()
+            .expect("Access list lock is not poisoned; qed") = access_list.clone();
+        // The list is swapped first, so updates which are received while routes are removed are
+        // already checked against the new list.
+        self.router.remove_denied_routes(&access_list);
+    }
+
+    /// Check if an [`Endpoint`] is reachable without adding it as a peer, returning the time it
+    /// took to connect. The returned future does not borrow the `Node`, and fails with
+    /// [`ProbeError::Timeout`] if no connection is established within `timeout`.
+    pub fn probe_peer(
+        &self,
+        endpoint: Endpoint,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Duration, ProbeError>> + Send + 'static {
+        self.peer_manager.probe(endpoint, timeout)
+    }
+
+    /// List the state of the babel neighbour relationship with all connected peers.
+    pub fn neighbours(&self) -> Vec<router::NeighbourInfo> {
+        self.router.neighbours()
+    }
+
     /// List all selected [`routes`](RouteEntry) in the system.
     pub fn selected_routes(&self) -> Vec<RouteEntry> {
         self.router.load_selected_routes()
     }
 
+    /// Get the amount of equal cost paths traffic for the given subnet and source prefix is spread
+    /// over. This is more than 1 if equal cost multipath forwarding is active for the route, and 0
+    /// if there is no usable selected route.
+    pub fn equal_cost_path_count(&self, subnet: Subnet, source_prefix: Option<Subnet>) -> usize {
+        self.router.equal_cost_path_count(subnet, source_prefix)
+    }
+
     /// List all fallback [`routes`](RouteEntry) in the system.
     pub fn fallback_routes(&self) -> Vec<RouteEntry> {
         self.router.load_fallback_routes()
     }
 
+    /// List all [`routes`](RouteEntry) known to the router, including routes which can't be
+    /// selected, together with their [`RouteState`](router::RouteState).
+    pub fn routes(&self) -> Vec<(RouteEntry, router::RouteState)> {
+        self.router.load_routes()
+    }
+
+    /// List all routes which are suppressed because they changed too often.
+    pub fn suppressed_routes(&self) -> Vec<flap_damping::SuppressedRoute> {
+        self.router.suppressed_routes()
+    }
+
+    /// Get the configured [`MetricOffsets`](metric_offset::MetricOffsets).
+    pub fn metric_offsets(&self) -> metric_offset::MetricOffsets {
+        self.router.metric_offsets()
+    }
+
+    /// Replace the [`MetricOffsets`](metric_offset::MetricOffsets). This applies immediately to
+    /// all known routes.
+    pub fn set_metric_offsets(&self, metric_offsets: metric_offset::MetricOffsets) {
+        self.router.set_metric_offsets(metric_offsets)
+    }
+
+    /// Get the configured [`PinnedRoutes`](pinned_route::PinnedRoute).
+    pub fn pinned_routes(&self) -> Vec<pinned_route::PinnedRoute> {
+        self.router.pinned_routes()
+    }
+
+    /// Add [`PinnedRoutes`](pinned_route::PinnedRoute), replacing existing pinned routes for the
+    /// same subnets. If any route is invalid, none of the routes are added.
+    pub fn add_pinned_routes(
+        &self,
+        routes: Vec<pinned_route::PinnedRoute>,
+    ) -> Result<(), pinned_route::InvalidPinnedRoute> {
+        self.router.add_pinned_routes(routes)
+    }
+
+    /// Remove the [`PinnedRoute`](pinned_route::PinnedRoute) for a [`Subnet`].
+    pub fn remove_pinned_route(
+        &self,
+        subnet: Subnet,
+    ) -> Result<(), pinned_route::PinnedRouteNotFound> {
+        self.router.remove_pinned_route(subnet)
+    }
+
+    /// Get the [`FaultInjectionConfig`](fault_injection::FaultInjectionConfig) applied to packets
+    /// sent to peers.
+    #[cfg(feature = "testing")]
+    pub fn fault_injection(&self) -> fault_injection::FaultInjectionConfig {
+        self.router.fault_injection()
+    }
+
+    /// Replace the [`FaultInjectionConfig`](fault_injection::FaultInjectionConfig) applied to
+    /// packets sent to peers.
+    #[cfg(feature = "testing")]
+    pub fn set_fault_injection(&self, config: fault_injection::FaultInjectionConfig) {
+        self.router.set_fault_injection(config)
+    }
+
+    /// Get the recent routing events which happened after `since`, or all events in the event log
+    /// if it is not set, oldest first.
+    pub fn routing_events(&self, since: Option<SystemTime>) -> Vec<event_log::LoggedEvent> {
+        self.router.routing_events(since)
+    }
+
+    /// Subscribe to changes of the selected [`routes`](RouteEntry) in the system.
+    pub fn subscribe_route_events(&self) -> broadcast::Receiver<RouteEvent> {
+        self.router.subscribe_route_events()
+    }
+
+    /// Get the selected [`route`](RouteEntry) used to forward packets to the given IP, if any.
+    pub fn selected_route_for_ip(&self, ip: IpAddr) -> Option<RouteEntry> {
+        self.router.select_best_route(ip)
+    }
+
+    /// Start draining the node before it is shut down, so neighbours stop using it as transit hop
+    /// while it stays reachable itself. Use [`Node::drain_status`] to check when no transit
+    /// traffic is forwarded anymore.
+    pub fn start_drain(&self) {
+        self.router.start_drain()
+    }
+
+    /// Get the [`DrainStatus`](router::DrainStatus) of the node, if it is draining.
+    pub fn drain_status(&self) -> Option<router::DrainStatus> {
+        self.router.drain_status()
+    }
+
+    /// Remove all selected and fallback routes, and request all connected peers to send their
+    /// routes again. Returns the amount of removed routes.
+    ///
+    /// Packets can't be forwarded to remote subnets until their routes are learned again.
+    pub fn flush_routes(&self) -> usize {
+        self.router.flush_routes()
+    }
+
+    /// Get the best fallback [`route`](RouteEntry) for the given IP, if any.
+    pub fn fallback_route_for_ip(&self, ip: IpAddr) -> Option<RouteEntry> {
+        self.router.select_best_fallback_route(ip)
+    }
+
     /// Get public key from the IP of `Node`
     pub fn get_pubkey_from_ip(&self, ip: IpAddr) -> Option<crypto::PublicKey> {
         self.router.get_pubkey(ip)
     }
 }
 
+impl NodeHealth {
+    /// Checks if the node is alive, i.e. the tasks which process packets received from peers and
+    /// packets for the local node are still running.
+    pub fn is_alive(&self) -> bool {
+        self.router.is_running() && self.router.tun_is_up()
+    }
+
+    /// Checks if the node is ready to carry traffic, i.e. it is alive, and it is connected to at
+    /// least the configured amount of peers.
+    pub fn is_ready(&self) -> bool {
+        self.is_alive() && self.router.peer_count() >= self.ready_min_peers
+    }
+}
+
 #[cfg(feature = "message")]
 impl<M> Node<M>
 where
@@ -304,6 +959,14 @@ where
         async move { ms.message(pop, topic).await }
     }
 
+    /// Subscribe to messages arriving in the message stack. If `topics` is not empty, only
+    /// messages with exactly one of the given topics are returned. Messages are removed from the
+    /// internal queue as they are returned by the subscription. Unlike [`Node::get_message`], the
+    /// subscription does not borrow the `Node`.
+    pub fn subscribe_messages(&self, topics: Vec<Vec<u8>>) -> MessageSubscription<M> {
+        self.message_stack.subscribe(topics)
+    }
+
     /// Push a new message to the message stack.
     ///
     /// The system will attempt to transmit the message for `try_duration`. A message is considered
@@ -342,6 +1005,18 @@ where
         self.message_stack.message_info(id)
     }
 
+    /// Subscribe to the [`DeliveryStatus`] of a message sent previously. This resolves once the
+    /// receiver acknowledged reception of the message, or once sending the message timed out.
+    ///
+    /// Returns [`Option::None`] if no message is found with the given id, see
+    /// [`Node::message_status`].
+    pub fn subscribe_message_delivery(
+        &self,
+        id: MessageId,
+    ) -> Option<watch::Receiver<DeliveryStatus>> {
+        self.message_stack.subscribe_delivery(id)
+    }
+
     /// Send a reply to a previously received message.
     pub fn reply_message(
         &self,
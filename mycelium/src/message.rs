@@ -7,18 +7,18 @@
 
 use core::fmt;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap},
     marker::PhantomData,
     net::IpAddr,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
-    time::{self, Duration},
+    time::{self, Duration, SystemTime},
 };
 
-use futures::{Stream, StreamExt};
+use futures::{FutureExt, Stream, StreamExt};
 use rand::Fill;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
-use tokio::sync::watch;
+use tokio::sync::{watch, Notify};
 use tracing::{debug, error, trace, warn};
 
 use crate::{
@@ -31,6 +31,13 @@ use crate::{
 mod chunk;
 mod done;
 mod init;
+mod store;
+
+pub(crate) use store::MessageStore;
+use store::StoreOp;
+pub use store::{
+    MessageStoreConfig, DEFAULT_MESSAGE_STORE_MAX_AGE, DEFAULT_MESSAGE_STORE_MAX_SIZE,
+};
 
 /// The amount of time to try and send messages before we give up.
 const MESSAGE_SEND_WINDOW: Duration = Duration::from_secs(60 * 5);
@@ -41,6 +48,13 @@ const RETRANSMISSION_DELAY: Duration = Duration::from_secs(1);
 /// Amount of time between sweeps of the subscriber list to clear orphaned subscribers.
 const REPLY_SUBSCRIBER_CLEAR_DELAY: Duration = Duration::from_secs(60);
 
+/// Amount of time to wait after received messages changed before they are persisted, so changes
+/// in quick succession are written at once.
+const MESSAGE_STORE_WRITE_DELAY: Duration = Duration::from_secs(1);
+
+/// Amount of time between checks for persisted messages which exceed the maximum age.
+const MESSAGE_STORE_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
 /// The average size of a single chunk. This is mainly intended to preallocate the chunk array on
 /// the receiver size. This value should allow reasonable overhead for standard MTU.
 const AVERAGE_CHUNK_SIZE: usize = 1_300;
@@ -108,11 +122,31 @@ struct MessageInbox {
     // TODO: MessageID is part of ReceivedMessageInfo, rework this into HashSet?
     pending_msges: HashMap<MessageId, ReceivedMessageInfo>,
     /// Messages which have been completed.
-    complete_msges: VecDeque<ReceivedMessage>,
+    complete_msges: CompleteMessages,
     /// Notification sender used to allert subscribed listeners.
     notify: watch::Sender<()>,
 }
 
+/// Completed messages waiting to be read, in order of arrival, and indexed by topic so messages
+/// for specific topics can be found without scanning all messages.
+#[derive(Default)]
+struct CompleteMessages {
+    /// The messages and the time they were received, keyed by their position in the queue.
+    msges: BTreeMap<i64, (SystemTime, ReceivedMessage)>,
+    /// The positions of the messages for every topic.
+    topics: HashMap<Vec<u8>, BTreeSet<i64>>,
+    /// Position of the first message pushed to the front of the queue.
+    front: i64,
+    /// Position the next message pushed to the back of the queue gets.
+    back: i64,
+    /// Total size of the topics and payloads of the messages.
+    size: usize,
+    /// Notified every time messages are added or removed.
+    changed: Arc<Notify>,
+    /// Changes which are not persisted yet, if the messages are persisted in a [`MessageStore`].
+    journal: Option<Vec<StoreOp>>,
+}
+
 struct ReceivedMessageInfo {
     id: MessageId,
     is_reply: bool,
@@ -199,12 +233,131 @@ impl MessageInbox {
     fn new(notify: watch::Sender<()>) -> Self {
         Self {
             pending_msges: HashMap::new(),
-            complete_msges: VecDeque::new(),
+            complete_msges: CompleteMessages::default(),
             notify,
         }
     }
 }
 
+impl CompleteMessages {
+    /// Add a message at the back of the queue.
+    fn push_back(&mut self, msg: ReceivedMessage) {
+        let pos = self.back;
+        self.back += 1;
+        self.insert(pos, SystemTime::now(), msg);
+    }
+
+    /// Add a message at the front of the queue, which was received at the given time.
+    fn push_front(&mut self, received: SystemTime, msg: ReceivedMessage) {
+        self.front -= 1;
+        self.insert(self.front, received, msg);
+    }
+
+    /// Add a message loaded from the [`MessageStore`] at the front of the queue. The message is
+    /// already persisted, so this is not recorded in the journal.
+    fn restore(&mut self, received: SystemTime, msg: ReceivedMessage) {
+        let journal = self.journal.take();
+        self.push_front(received, msg);
+        self.journal = journal;
+    }
+
+    fn insert(&mut self, pos: i64, received: SystemTime, msg: ReceivedMessage) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(StoreOp::Add(received, msg.clone()));
+        }
+        self.topics
+            .entry(msg.topic.clone())
+            .or_default()
+            .insert(pos);
+        self.size += msg.topic.len() + msg.data.len();
+        self.msges.insert(pos, (received, msg));
+        self.changed.notify_one();
+    }
+
+    fn remove(&mut self, pos: i64) -> Option<ReceivedMessage> {
+        let (_, msg) = self.msges.remove(&pos)?;
+        if let Some(positions) = self.topics.get_mut(&msg.topic) {
+            positions.remove(&pos);
+            if positions.is_empty() {
+                self.topics.remove(&msg.topic);
+            }
+        }
+        self.size -= msg.topic.len() + msg.data.len();
+        if let Some(journal) = &mut self.journal {
+            journal.push(StoreOp::Remove(msg.id));
+        }
+        self.changed.notify_one();
+        Some(msg)
+    }
+
+    /// Check if a message with the given ID is in the queue.
+    fn contains(&self, id: MessageId) -> bool {
+        self.msges.values().any(|(_, m)| m.id == id)
+    }
+
+    /// Remove messages which are older than `max_age`, and the oldest messages until the total
+    /// size is at most `max_size`. Returns the amount of removed messages.
+    fn expire(&mut self, max_size: usize, max_age: Duration) -> usize {
+        let now = SystemTime::now();
+        let expired = self
+            .msges
+            .iter()
+            .filter(|(_, (received, _))| {
+                now.duration_since(*received).unwrap_or_default() > max_age
+            })
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        let mut removed = expired.len();
+        for pos in expired {
+            self.remove(pos);
+        }
+        while self.size > max_size {
+            let Some(pos) = self.msges.keys().next().copied() else {
+                break;
+            };
+            self.remove(pos);
+            removed += 1;
+        }
+
+        removed
+    }
+
+    /// Take the changes recorded in the journal since it was last taken.
+    fn take_journal(&mut self) -> Vec<StoreOp> {
+        self.journal
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Find the position of the first message with one of the given topics, or the first message
+    /// if no topics are given.
+    fn first(&self, topics: &[Vec<u8>]) -> Option<i64> {
+        if topics.is_empty() {
+            return self.msges.keys().next().copied();
+        }
+        topics
+            .iter()
+            .filter_map(|topic| self.topics.get(topic)?.first().copied())
+            .min()
+    }
+
+    /// Get the first message with one of the given topics, or the first message if no topics are
+    /// given.
+    fn front(&self, topics: &[Vec<u8>]) -> Option<&ReceivedMessage> {
+        self.first(topics)
+            .and_then(|pos| self.msges.get(&pos))
+            .map(|(_, msg)| msg)
+    }
+
+    /// Remove the first message with one of the given topics, or the first message if no topics
+    /// are given.
+    fn pop_front(&mut self, topics: &[Vec<u8>]) -> Option<ReceivedMessage> {
+        let pos = self.first(topics)?;
+        self.remove(pos)
+    }
+}
+
 impl MessageOutbox {
     /// Create a new `MessageOutbox` ready for use.
     fn new() -> Self {
@@ -225,8 +378,13 @@ where
 {
     /// Create a new `MessageStack`. This uses the provided [`DataPlane`] to inject message
     /// packets. Received packets must be injected into the `MessageStack` through the provided
-    /// [`Stream`].
-    pub fn new<S>(data_plane: DataPlane<M>, message_packet_stream: S) -> Self
+    /// [`Stream`]. If a [`MessageStore`] is given, received messages which are not read yet are
+    /// persisted in it, and messages persisted previously are loaded from it.
+    pub(crate) fn new<S>(
+        data_plane: DataPlane<M>,
+        message_packet_stream: S,
+        store: Option<MessageStore>,
+    ) -> Self
     where
         S: Stream<Item = (PacketBuffer, IpAddr, IpAddr)> + Send + Unpin + 'static,
     {
@@ -238,6 +396,11 @@ where
             subscriber,
             reply_subscribers: Arc::new(Mutex::new(HashMap::new())),
         };
+        if store.is_some() {
+            // Record changes from the start, so messages received while the store is loaded are
+            // persisted as well.
+            ms.inbox.lock().unwrap().complete_msges.journal = Some(Vec::new());
+        }
 
         tokio::task::spawn(
             ms.clone()
@@ -266,9 +429,68 @@ where
                 }
             });
         }
+
+        if let Some(store) = store {
+            tokio::task::spawn(ms.clone().persist_messages(store));
+        }
+
         ms
     }
 
+    /// Load the messages persisted in the [`MessageStore`], and keep the store up to date with
+    /// the received messages which are not read yet. Changes of the messages are recorded in a
+    /// journal, which is appended to the store, so the inbox is only locked to take the journal.
+    async fn persist_messages(self, mut store: MessageStore) {
+        let changed = self.inbox.lock().unwrap().complete_msges.changed.clone();
+
+        match store.load().await {
+            Ok(msges) => {
+                debug!("Loaded {} persisted messages", msges.len());
+                let mut inbox = self.inbox.lock().unwrap();
+                // Persisted messages were received before any new message, so they go in front.
+                for (received, msg) in msges.into_iter().rev() {
+                    inbox.complete_msges.restore(received, msg);
+                }
+                inbox.notify.send_replace(());
+            }
+            Err(e) => warn!(
+                "Could not load persisted messages from {}: {e}",
+                store.config().path.display()
+            ),
+        }
+
+        let mut expiry_interval = tokio::time::interval(MESSAGE_STORE_EXPIRY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = changed.notified() => {
+                    tokio::time::sleep(MESSAGE_STORE_WRITE_DELAY).await;
+                }
+                _ = expiry_interval.tick() => {}
+            }
+
+            let ops = {
+                let mut inbox = self.inbox.lock().unwrap();
+                let expired = inbox
+                    .complete_msges
+                    .expire(store.config().max_size, store.config().max_age);
+                if expired > 0 {
+                    debug!("Dropped {expired} received messages exceeding the retention limits");
+                }
+                // All changes up to now are included in the journal, including the ones made by
+                // expiring messages.
+                let _ = changed.notified().now_or_never();
+                inbox.complete_msges.take_journal()
+            };
+
+            if let Err(e) = store.append(&ops).await {
+                warn!(
+                    "Could not persist received messages to {}: {e}",
+                    store.config().path.display()
+                );
+            }
+        }
+    }
+
     /// Handle incoming messages from the [`DataPlane`].
     async fn handle_incoming_message_packets<S>(self, mut message_packet_stream: S)
     where
@@ -351,6 +573,7 @@ where
                     return;
                 }
                 message.state = TransmissionState::Received;
+                message.delivery.send_replace(DeliveryStatus::Delivered);
             }
         } else if flags.read() {
             // Ack for a read flag. Since the original read flag is sent by the receiver, this
@@ -375,7 +598,7 @@ where
             // We receive a new message with an ID. If we already have a complete message, ignore
             // it.
             let mut inbox = self.inbox.lock().unwrap();
-            if inbox.complete_msges.iter().any(|m| m.id == message_id) {
+            if inbox.complete_msges.contains(message_id) {
                 debug!("Dropping INIT message as we already have a complete message with this ID");
                 return;
             }
@@ -683,6 +906,7 @@ where
             len,
             msg,
             chunks: vec![], // leave Vec empty at start
+            delivery: watch::channel(DeliveryStatus::Queued).0,
         };
 
         let subscription = if subscribe {
@@ -902,6 +1126,7 @@ where
                             if let Some(msg) = message_stack.outbox.lock().unwrap().msges.get_mut(&id) {
                                 if matches!(msg.state, TransmissionState::Init | TransmissionState::InProgress) {
                                     msg.state = TransmissionState::Aborted;
+                                    msg.delivery.send_replace(DeliveryStatus::TimedOut);
 
                                     // Inform receiver of message abortion.
                                     let mut mp = MessagePacket::new(PacketBuffer::new());
@@ -984,36 +1209,44 @@ where
         })
     }
 
+    /// Subscribe to the [`DeliveryStatus`] of an outbound message. The status changes once the
+    /// receiver acknowledged full reception of the message, or once the message is aborted because
+    /// it could not be delivered in time.
+    ///
+    /// Returns [`Option::None`] if there is no outbound message with the given id. The sender of
+    /// the returned channel is dropped once the message info is removed.
+    pub fn subscribe_delivery(&self, id: MessageId) -> Option<watch::Receiver<DeliveryStatus>> {
+        self.outbox
+            .lock()
+            .unwrap()
+            .msges
+            .get(&id)
+            .map(|mi| mi.delivery.subscribe())
+    }
+
     /// A future which eventually resolves to a new (inbound message)[`ReceivedMessage`], if new messages come in.
     ///
     /// If pop is false, the message is not removed and the next call of this method will return
     /// the same message.
     pub async fn message(&self, pop: bool, topic: Option<Vec<u8>>) -> ReceivedMessage {
+        self.message_with_topics(pop, topic.as_slice()).await
+    }
+
+    /// Like [`MessageStack::message`], but only returns messages with one of the given `topics`.
+    /// If `topics` is empty, any message is returned.
+    async fn message_with_topics(&self, pop: bool, topics: &[Vec<u8>]) -> ReceivedMessage {
         // Copy the subscriber since we need mutable access to it.
         let mut subscriber = self.subscriber.clone();
 
         loop {
             // Scope to ensure we drop the lock after we checked for a message and don't hold
             // it while waiting for a new notification.
-            'check: {
+            {
                 let mut inbox = self.inbox.lock().unwrap();
-                // If a filter is set only check for those messages.
-                if let Some(ref topic) = topic {
-                    if let Some((idx, _)) = inbox
-                        .complete_msges
-                        .iter()
-                        .enumerate()
-                        .find(|(_, v)| &v.topic == topic)
-                    {
-                        return inbox.complete_msges.remove(idx).unwrap();
-                    } else {
-                        break 'check;
-                    }
-                }
                 if let Some(msg) = if pop {
-                    inbox.complete_msges.pop_front()
+                    inbox.complete_msges.pop_front(topics)
                 } else {
-                    inbox.complete_msges.front().cloned()
+                    inbox.complete_msges.front(topics).cloned()
                 } {
                     self.notify_read(&msg);
                     return msg;
@@ -1026,6 +1259,15 @@ where
         }
     }
 
+    /// Subscribe to messages arriving in the inbox. If `topics` is not empty, only messages with
+    /// exactly one of those topics are returned by the subscription.
+    pub fn subscribe(&self, topics: Vec<Vec<u8>>) -> MessageSubscription<M> {
+        MessageSubscription {
+            stack: self.clone(),
+            topics,
+        }
+    }
+
     /// Notify the sender of a message that it has been read.
     fn notify_read(&self, msg: &ReceivedMessage) {
         let mut mp = MessagePacket::new(PacketBuffer::new());
@@ -1051,6 +1293,35 @@ where
     }
 }
 
+/// A subscription to messages arriving in the inbox of a [`MessageStack`], optionally only those
+/// with specific topics. Messages are removed from the inbox as they are returned, so messages
+/// which arrive while nobody is subscribed are kept until the next subscription.
+pub struct MessageSubscription<M> {
+    stack: MessageStack<M>,
+    topics: Vec<Vec<u8>>,
+}
+
+impl<M> MessageSubscription<M>
+where
+    M: Metrics + Clone + Send + 'static,
+{
+    /// Wait for the next message matching the subscription, and remove it from the inbox.
+    ///
+    /// This is cancel safe, a message is only removed from the inbox if it is returned.
+    pub async fn next(&self) -> ReceivedMessage {
+        self.stack.message_with_topics(true, &self.topics).await
+    }
+
+    /// Return a message which was received through [`MessageSubscription::next`] to the inbox,
+    /// e.g. because it could not be handed to the application. It is placed in front of the
+    /// inbox, so it is the first message returned again.
+    pub fn put_back(&self, msg: ReceivedMessage) {
+        let mut inbox = self.stack.inbox.lock().unwrap();
+        inbox.complete_msges.push_front(SystemTime::now(), msg);
+        inbox.notify.send_replace(());
+    }
+}
+
 impl<M> Clone for MessageStack<M> {
     fn clone(&self) -> Self {
         Self {
@@ -1064,9 +1335,11 @@ impl<M> Clone for MessageStack<M> {
 }
 
 #[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MessageInfo {
     /// The receiver of this message.
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub dst: IpAddr,
     /// Transmission state of the message.
     pub state: TransmissionProgress,
@@ -1079,6 +1352,7 @@ pub struct MessageInfo {
 }
 
 #[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum TransmissionProgress {
     /// Pending transmission, the remote has not yet acknowledged our init message.
@@ -1101,6 +1375,21 @@ pub enum TransmissionProgress {
     Aborted,
 }
 
+/// Delivery status of an outbound message.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    /// The message is queued for transmission, the receiver has not acknowledged full reception
+    /// yet.
+    #[default]
+    Queued,
+    /// The receiver acknowledged full reception of the message, including checksum verification.
+    Delivered,
+    /// The message could not be delivered before the deadline, and has been aborted.
+    TimedOut,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MessageId([u8; MESSAGE_ID_SIZE]);
 
@@ -1449,6 +1738,8 @@ pub struct OutboundMessageInfo {
     msg: Message,
     /// Chunks of the message.
     chunks: Vec<ChunkState>,
+    /// Notifies subscribers once the message is delivered, or delivery timed out.
+    delivery: watch::Sender<DeliveryStatus>,
 }
 
 /// A message checksum. In practice this is a 32 byte blake3 digest of the entire message.
@@ -1475,8 +1766,94 @@ impl std::error::Error for PushMessageError {}
 
 #[cfg(test)]
 mod tests {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    use super::{
+        CompleteMessages, MessageId, MessagePacketHeaderMut, ReceivedMessage, MESSAGE_HEADER_SIZE,
+    };
+
+    fn received_message(topic: &[u8]) -> ReceivedMessage {
+        ReceivedMessage {
+            id: MessageId::new(),
+            is_reply: false,
+            src_ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            src_pk: [1; 32].into(),
+            dst_ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            dst_pk: [2; 32].into(),
+            topic: topic.to_vec(),
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn complete_messages_by_topic() {
+        let mut msges = CompleteMessages::default();
+        let a1 = received_message(b"a");
+        let b1 = received_message(b"b");
+        let a2 = received_message(b"a");
+        let c1 = received_message(b"c");
+        msges.push_back(a1.clone());
+        msges.push_back(b1.clone());
+        msges.push_back(a2.clone());
+        msges.push_back(c1.clone());
+
+        let topics = [b"c".to_vec(), b"b".to_vec()];
+        assert_eq!(
+            msges.front(&topics).map(|m| m.id.as_hex()),
+            Some(b1.id.as_hex())
+        );
+        assert_eq!(
+            msges.pop_front(&topics).map(|m| m.id.as_hex()),
+            Some(b1.id.as_hex())
+        );
+        assert_eq!(
+            msges.pop_front(&topics).map(|m| m.id.as_hex()),
+            Some(c1.id.as_hex())
+        );
+        assert!(msges.pop_front(&topics).is_none());
 
-    use super::{MessagePacketHeaderMut, MESSAGE_HEADER_SIZE};
+        // A message put back is returned first.
+        msges.push_front(std::time::SystemTime::now(), c1.clone());
+        assert_eq!(
+            msges.pop_front(&[]).map(|m| m.id.as_hex()),
+            Some(c1.id.as_hex())
+        );
+        assert_eq!(
+            msges.pop_front(&[b"a".to_vec()]).map(|m| m.id.as_hex()),
+            Some(a1.id.as_hex())
+        );
+        assert!(msges.contains(a2.id));
+        assert_eq!(
+            msges.pop_front(&[]).map(|m| m.id.as_hex()),
+            Some(a2.id.as_hex())
+        );
+        assert!(msges.msges.is_empty());
+        assert!(msges.topics.is_empty());
+    }
+
+    #[test]
+    fn complete_messages_expire() {
+        let mut msges = CompleteMessages::default();
+        let old = received_message(b"old");
+        let a = received_message(b"aaaa");
+        let b = received_message(b"bb");
+        msges.push_front(
+            std::time::SystemTime::now() - std::time::Duration::from_secs(120),
+            old,
+        );
+        msges.push_back(a);
+        msges.push_back(b.clone());
+        assert_eq!(msges.size, 9);
+
+        // The expired message is dropped, then the oldest until the size fits.
+        assert_eq!(msges.expire(3, std::time::Duration::from_secs(60)), 2);
+        assert_eq!(msges.size, 2);
+        assert_eq!(
+            msges.pop_front(&[]).map(|m| m.id.as_hex()),
+            Some(b.id.as_hex())
+        );
+        assert!(msges.topics.is_empty());
+    }
 
     #[test]
     fn set_init_flag() {
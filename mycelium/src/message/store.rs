@@ -0,0 +1,671 @@
+//! Persistence of received messages which are not read yet, so they survive a restart of the
+//! node.
+//!
+//! The store file is an append-only log. Every received message is appended as a record, and a
+//! record with the id of the message is appended once it is read or dropped. Every record is
+//! encrypted separately, with a key derived from the node's secret key. Once the log contains
+//! more records of removed messages than of stored messages, it is compacted by rewriting it with
+//! only the stored messages. This is also done when the log is loaded.
+//!
+//! If the node stops while a record is written, the log ends with an incomplete record. This is
+//! ignored when the log is loaded, and removed by the compaction.
+//!
+//! Records which can't be decrypted or decoded are skipped. The log is never overwritten while it
+//! holds such records, or if it could not be read at all: it is moved aside first, so the messages
+//! in it can still be recovered.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv6Addr},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BytesMut;
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+use crate::crypto::{PacketBuffer, PublicKey, SecretKey, SharedSecret};
+
+use super::{MessageId, ReceivedMessage, MESSAGE_ID_SIZE};
+
+/// Default maximum total size of the payloads and topics of stored messages.
+pub const DEFAULT_MESSAGE_STORE_MAX_SIZE: usize = 64 << 20;
+
+/// Default maximum age of stored messages.
+pub const DEFAULT_MESSAGE_STORE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Context used to derive the key to encrypt the message store with from the node key.
+const STORE_KEY_CONTEXT: &str = "mycelium message store v1";
+
+/// Header at the start of the store file, which identifies the format.
+const LOG_HEADER: &[u8; 8] = b"mycmsg\x00\x01";
+
+/// Size of the length prefix of a record in the log.
+const RECORD_LENGTH_SIZE: usize = 4;
+
+/// Record of a received message.
+const RECORD_ADD: u8 = 1;
+
+/// Record of a message which is removed.
+const RECORD_REMOVE: u8 = 2;
+
+/// Amount of records of removed messages the log must contain before it is compacted, so small
+/// logs are not rewritten over and over.
+const COMPACTION_MIN_GARBAGE: usize = 256;
+
+/// Size of the fixed part of an encoded message: receive time, id, reply flag, source IP and
+/// key, destination IP and key, topic length and data length.
+const ENCODED_MESSAGE_HEADER_SIZE: usize = 8 + MESSAGE_ID_SIZE + 1 + 16 + 32 + 16 + 32 + 2 + 4;
+
+/// Configuration of the persistence of received messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageStoreConfig {
+    /// Path of the file in which the messages are stored.
+    pub path: PathBuf,
+    /// Maximum total size of the payloads and topics of stored messages, in bytes. If this is
+    /// exceeded, the oldest messages are dropped.
+    pub max_size: usize,
+    /// Maximum time a message is kept if it is not read.
+    pub max_age: Duration,
+}
+
+/// A change of the stored messages, which is appended to the log.
+pub(crate) enum StoreOp {
+    /// A message is received at the given time.
+    Add(SystemTime, ReceivedMessage),
+    /// The message with the given id is read or dropped.
+    Remove(MessageId),
+}
+
+/// Reads and writes the log with persisted messages.
+pub(crate) struct MessageStore {
+    config: MessageStoreConfig,
+    key: SharedSecret,
+    /// The log file, opened for appending once the log is loaded. If this is not set, the
+    /// existing log is moved aside before a new one is written.
+    file: Option<tokio::fs::File>,
+    /// Size of the log up to the last completely written record.
+    len: u64,
+    /// Amount of messages in the log which are not removed.
+    live: usize,
+    /// Amount of records in the log which can be dropped by compacting it.
+    garbage: usize,
+}
+
+impl MessageStore {
+    /// Create a new `MessageStore`, encrypting the stored messages with a key derived from the
+    /// node key.
+    pub fn new(config: MessageStoreConfig, node_key: &SecretKey) -> Self {
+        Self {
+            key: node_key.derive_key(STORE_KEY_CONTEXT),
+            config,
+            file: None,
+            len: 0,
+            live: 0,
+            garbage: 0,
+        }
+    }
+
+    /// The configuration of this store.
+    pub fn config(&self) -> &MessageStoreConfig {
+        &self.config
+    }
+
+    /// Load the stored messages, together with the time they were received. Messages which
+    /// exceed the maximum age are skipped. If there is no store file yet, no messages are
+    /// returned. Afterwards, the log is compacted, and new records are appended to it.
+    pub async fn load(&mut self) -> io::Result<Vec<(SystemTime, ReceivedMessage)>> {
+        let messages = self.read_readable().await?;
+        self.rewrite(&messages).await?;
+        Ok(messages)
+    }
+
+    /// Append changes of the stored messages to the log. The log is compacted if it contains
+    /// more records of removed messages than of stored messages. If the log could not be loaded,
+    /// it is moved aside and replaced by a new one.
+    pub async fn append(&mut self, ops: &[StoreOp]) -> io::Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        if self.file.is_none() {
+            self.quarantine("it could not be loaded").await?;
+            self.rewrite(&[]).await?;
+        }
+
+        let mut buf = Vec::new();
+        for op in ops {
+            self.encode_record(op, &mut buf);
+            match op {
+                StoreOp::Add(..) => self.live += 1,
+                StoreOp::Remove(_) => {
+                    // Both the record of the message and the record of its removal are garbage.
+                    self.live = self.live.saturating_sub(1);
+                    self.garbage += 2;
+                }
+            }
+        }
+
+        let file = self
+            .file
+            .as_mut()
+            .expect("Log is opened by rewriting it above; qed");
+        let res = async {
+            file.write_all(&buf).await?;
+            file.sync_data().await
+        }
+        .await;
+        if let Err(e) = res {
+            // Drop the partially written records, so records appended later can be read.
+            let _ = file.set_len(self.len).await;
+            return Err(e);
+        }
+        self.len += buf.len() as u64;
+
+        if self.garbage >= COMPACTION_MIN_GARBAGE && self.garbage > self.live {
+            let messages = self.read_readable().await?;
+            self.rewrite(&messages).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the stored messages which can be read from the log. If the log has an unsupported
+    /// format or holds records which can't be read, it is moved aside, since it is replaced by a
+    /// log holding only the returned messages afterwards.
+    async fn read_readable(&self) -> io::Result<Vec<(SystemTime, ReceivedMessage)>> {
+        match self.read().await? {
+            Some((messages, 0)) => Ok(messages),
+            Some((messages, skipped)) => {
+                self.quarantine(&format!("{skipped} records could not be read"))
+                    .await?;
+                Ok(messages)
+            }
+            None => {
+                self.quarantine("it has an unsupported format").await?;
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Move the log aside, next to its original path, if it exists. This is done before a log
+    /// which can't be read completely is replaced, so the messages in it are not lost.
+    async fn quarantine(&self, reason: &str) -> io::Result<()> {
+        let mut path = self.config.path.clone().into_os_string();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        path.push(format!(".corrupt-{now}"));
+        let path = PathBuf::from(path);
+
+        match tokio::fs::rename(&self.config.path, &path).await {
+            Ok(()) => {
+                error!(
+                    "Moved message store {} to {}, since {reason}",
+                    self.config.path.display(),
+                    path.display()
+                );
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the stored messages from the log, and replay the changes recorded in it. Messages
+    /// which exceed the maximum age are skipped, as are records which can't be decrypted or
+    /// decoded. Returns the messages and the amount of skipped records, or [`None`] if the log has
+    /// an unsupported format.
+    async fn read(&self) -> io::Result<Option<(Vec<(SystemTime, ReceivedMessage)>, usize)>> {
+        let data = match tokio::fs::read(&self.config.path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some((vec![], 0))),
+            Err(e) => return Err(e),
+        };
+        if !data.starts_with(LOG_HEADER) {
+            return Ok(None);
+        }
+
+        // Messages in the order they were added, and the index of every message by id.
+        let mut messages = Vec::new();
+        let mut index = HashMap::new();
+        let mut skipped = 0;
+        let mut reader = Reader {
+            buf: &data[LOG_HEADER.len()..],
+        };
+        while !reader.buf.is_empty() {
+            // An incomplete record at the end is left by a write which was interrupted.
+            let Ok(len) = reader.take(RECORD_LENGTH_SIZE) else {
+                break;
+            };
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            let Ok(record) = reader.take(len) else {
+                break;
+            };
+            let Some(op) = self
+                .key
+                .decrypt(BytesMut::from(record))
+                .ok()
+                .and_then(|plaintext| decode_record(&plaintext).ok())
+            else {
+                skipped += 1;
+                continue;
+            };
+            match op {
+                StoreOp::Add(received, msg) => {
+                    index.insert(msg.id, messages.len());
+                    messages.push(Some((received, msg)));
+                }
+                StoreOp::Remove(id) => {
+                    if let Some(idx) = index.remove(&id) {
+                        messages[idx] = None;
+                    }
+                }
+            }
+        }
+
+        let now = SystemTime::now();
+        let messages = messages
+            .into_iter()
+            .flatten()
+            .filter(|(received, _)| {
+                now.duration_since(*received).unwrap_or_default() <= self.config.max_age
+            })
+            .collect();
+        Ok(Some((messages, skipped)))
+    }
+
+    /// Replace the log with one which only contains the given messages, and open it to append new
+    /// records.
+    async fn rewrite(&mut self, messages: &[(SystemTime, ReceivedMessage)]) -> io::Result<()> {
+        self.file = None;
+
+        let mut buf = LOG_HEADER.to_vec();
+        for (received, msg) in messages {
+            self.encode_record(&StoreOp::Add(*received, msg.clone()), &mut buf);
+        }
+
+        // Write to a temporary file first, so a crash while writing doesn't lose the existing
+        // messages.
+        let tmp_path = self.config.path.with_extension("tmp");
+        let mut file = open_options()
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .await?;
+        file.write_all(&buf).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&tmp_path, &self.config.path).await?;
+
+        self.file = Some(open_options().append(true).open(&self.config.path).await?);
+        self.len = buf.len() as u64;
+        self.live = messages.len();
+        self.garbage = 0;
+
+        Ok(())
+    }
+
+    /// Encrypt a record, and append it to `buf` with its length prefix.
+    fn encode_record(&self, op: &StoreOp, buf: &mut Vec<u8>) {
+        let plaintext = encode_record(op);
+        let mut buffer = PacketBuffer::with_capacity(plaintext.len());
+        buffer.buffer_mut()[..plaintext.len()].copy_from_slice(&plaintext);
+        buffer.set_size(plaintext.len());
+        let ciphertext = self.key.encrypt(buffer);
+
+        buf.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&ciphertext);
+    }
+}
+
+/// Options to open the store file with. The file is only accessible by its owner.
+fn open_options() -> tokio::fs::OpenOptions {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true);
+    #[cfg(target_family = "unix")]
+    options.mode(0o600); // rw by the owner, not readable by group or others
+    options
+}
+
+/// Encode a record of the log, before it is encrypted.
+fn encode_record(op: &StoreOp) -> Vec<u8> {
+    match op {
+        StoreOp::Add(received, msg) => {
+            let mut buf = Vec::with_capacity(
+                1 + ENCODED_MESSAGE_HEADER_SIZE + msg.topic.len() + msg.data.len(),
+            );
+            let received = received
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            buf.push(RECORD_ADD);
+            buf.extend_from_slice(&received.to_be_bytes());
+            buf.extend_from_slice(&msg.id.0);
+            buf.push(msg.is_reply as u8);
+            buf.extend_from_slice(&ipv6(msg.src_ip).octets());
+            buf.extend_from_slice(msg.src_pk.as_bytes());
+            buf.extend_from_slice(&ipv6(msg.dst_ip).octets());
+            buf.extend_from_slice(msg.dst_pk.as_bytes());
+            buf.extend_from_slice(&(msg.topic.len() as u16).to_be_bytes());
+            buf.extend_from_slice(&(msg.data.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&msg.topic);
+            buf.extend_from_slice(&msg.data);
+            buf
+        }
+        StoreOp::Remove(id) => {
+            let mut buf = Vec::with_capacity(1 + MESSAGE_ID_SIZE);
+            buf.push(RECORD_REMOVE);
+            buf.extend_from_slice(&id.0);
+            buf
+        }
+    }
+}
+
+/// Decode a decrypted record of the log.
+fn decode_record(buf: &[u8]) -> io::Result<StoreOp> {
+    let mut reader = Reader { buf };
+    let mut take = |len| reader.take(len);
+
+    match take(1)?[0] {
+        RECORD_ADD => {
+            let received = u64::from_be_bytes(take(8)?.try_into().unwrap());
+            let id = MessageId(take(MESSAGE_ID_SIZE)?.try_into().unwrap());
+            let is_reply = take(1)?[0] != 0;
+            let src_ip = <[u8; 16]>::try_from(take(16)?).unwrap();
+            let src_pk = <[u8; 32]>::try_from(take(32)?).unwrap();
+            let dst_ip = <[u8; 16]>::try_from(take(16)?).unwrap();
+            let dst_pk = <[u8; 32]>::try_from(take(32)?).unwrap();
+            let topic_len = u16::from_be_bytes(take(2)?.try_into().unwrap()) as usize;
+            let data_len = u32::from_be_bytes(take(4)?.try_into().unwrap()) as usize;
+            let topic = take(topic_len)?.to_vec();
+            let data = take(data_len)?.to_vec();
+
+            Ok(StoreOp::Add(
+                UNIX_EPOCH + Duration::from_secs(received),
+                ReceivedMessage {
+                    id,
+                    is_reply,
+                    src_ip: IpAddr::V6(Ipv6Addr::from(src_ip)),
+                    src_pk: PublicKey::from(src_pk),
+                    dst_ip: IpAddr::V6(Ipv6Addr::from(dst_ip)),
+                    dst_pk: PublicKey::from(dst_pk),
+                    topic,
+                    data,
+                },
+            ))
+        }
+        RECORD_REMOVE => Ok(StoreOp::Remove(MessageId(
+            take(MESSAGE_ID_SIZE)?.try_into().unwrap(),
+        ))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown message store record",
+        )),
+    }
+}
+
+/// Reads consecutive parts of a buffer.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Take the next `len` bytes of the buffer.
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message store is truncated",
+            ));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+}
+
+/// Messages are only exchanged between IPv6 addresses, but the type allows IPv4 as well.
+fn ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use super::{MessageStore, MessageStoreConfig, StoreOp, COMPACTION_MIN_GARBAGE};
+    use crate::{
+        crypto::SecretKey,
+        message::{MessageId, ReceivedMessage},
+    };
+
+    fn store_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "mycelium-message-store-test-{}",
+            MessageId::new().as_hex()
+        ))
+    }
+
+    /// Paths of the logs which were moved aside from `path`.
+    fn quarantined(path: &Path) -> Vec<PathBuf> {
+        let prefix = format!("{}.corrupt-", path.file_name().unwrap().to_string_lossy());
+        std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| {
+                p.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with(&prefix)
+            })
+            .collect()
+    }
+
+    fn msg(topic: &[u8], data: &[u8]) -> ReceivedMessage {
+        ReceivedMessage {
+            id: MessageId::new(),
+            is_reply: false,
+            src_ip: IpAddr::V6(Ipv6Addr::new(0x400, 1, 0, 0, 0, 0, 0, 1)),
+            src_pk: [1; 32].into(),
+            dst_ip: IpAddr::V6(Ipv6Addr::new(0x400, 2, 0, 0, 0, 0, 0, 1)),
+            dst_pk: [2; 32].into(),
+            topic: topic.to_vec(),
+            data: data.to_vec(),
+        }
+    }
+
+    /// The current time, with the second precision times are stored with.
+    fn now() -> SystemTime {
+        UNIX_EPOCH
+            + Duration::from_secs(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            )
+    }
+
+    #[tokio::test]
+    async fn messages_survive_append_and_load() {
+        let path = store_path();
+        let node_key = SecretKey::new();
+        let config = MessageStoreConfig {
+            path: path.clone(),
+            max_size: 1 << 20,
+            max_age: Duration::from_secs(60),
+        };
+        let mut store = MessageStore::new(config.clone(), &node_key);
+        assert!(store.load().await.unwrap().is_empty());
+
+        let now = now();
+        let fresh = msg(b"topic", b"hello");
+        let read = msg(b"topic", b"already read");
+        let expired = msg(b"", b"too old");
+        store
+            .append(&[
+                StoreOp::Add(now, fresh.clone()),
+                StoreOp::Add(now, read.clone()),
+                StoreOp::Add(now - Duration::from_secs(120), expired),
+            ])
+            .await
+            .unwrap();
+        store.append(&[StoreOp::Remove(read.id)]).await.unwrap();
+
+        // An interrupted write leaves an incomplete record at the end, which is ignored.
+        let mut data = std::fs::read(&path).unwrap();
+        data.extend_from_slice(&[0, 0, 1, 0, 42]);
+        std::fs::write(&path, data).unwrap();
+
+        let mut store = MessageStore::new(config, &node_key);
+        let loaded = store.load().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, now);
+        assert_eq!(loaded[0].1.id.as_hex(), fresh.id.as_hex());
+        assert_eq!(loaded[0].1.src_ip, fresh.src_ip);
+        assert_eq!(loaded[0].1.topic, fresh.topic);
+        assert_eq!(loaded[0].1.data, fresh.data);
+
+        // Records appended after loading are kept as well.
+        let later = msg(b"", b"later");
+        store
+            .append(&[StoreOp::Add(now, later.clone())])
+            .await
+            .unwrap();
+        let loaded = store.load().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].1.id.as_hex(), later.id.as_hex());
+    }
+
+    #[tokio::test]
+    async fn log_is_compacted_once_most_records_are_removed() {
+        let path = store_path();
+        let mut store = MessageStore::new(
+            MessageStoreConfig {
+                path: path.clone(),
+                max_size: 1 << 20,
+                max_age: Duration::from_secs(60),
+            },
+            &SecretKey::new(),
+        );
+        store.load().await.unwrap();
+
+        let now = now();
+        let kept = msg(b"", b"kept");
+        store
+            .append(&[StoreOp::Add(now, kept.clone())])
+            .await
+            .unwrap();
+        let compacted_len = store.len;
+
+        for _ in 0..COMPACTION_MIN_GARBAGE / 2 {
+            let read = msg(b"", b"read");
+            store
+                .append(&[StoreOp::Add(now, read.clone()), StoreOp::Remove(read.id)])
+                .await
+                .unwrap();
+        }
+
+        // Only the kept message remains in the log.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let loaded = store.load().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(len, compacted_len);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].1.id.as_hex(), kept.id.as_hex());
+    }
+
+    #[tokio::test]
+    async fn unreadable_records_are_skipped_and_log_is_kept() {
+        let path = store_path();
+        let node_key = SecretKey::new();
+        let config = MessageStoreConfig {
+            path: path.clone(),
+            max_size: 1 << 20,
+            max_age: Duration::from_secs(60),
+        };
+        let mut store = MessageStore::new(config.clone(), &node_key);
+        store.load().await.unwrap();
+
+        let now = now();
+        let corrupted = msg(b"", b"corrupted");
+        let kept = msg(b"", b"kept");
+        store
+            .append(&[
+                StoreOp::Add(now, corrupted),
+                StoreOp::Add(now, kept.clone()),
+            ])
+            .await
+            .unwrap();
+
+        // Corrupt the first record.
+        let mut data = std::fs::read(&path).unwrap();
+        data[super::LOG_HEADER.len() + super::RECORD_LENGTH_SIZE] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let mut store = MessageStore::new(config.clone(), &node_key);
+        let loaded = store.load().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].1.id.as_hex(), kept.id.as_hex());
+
+        // The original log is kept, next to the new one.
+        let moved = quarantined(&path);
+        assert_eq!(moved.len(), 1);
+        assert_eq!(std::fs::read(&moved[0]).unwrap(), data);
+        std::fs::remove_file(&moved[0]).unwrap();
+
+        // Without the node key nothing can be read, but the log is not lost either.
+        let data = std::fs::read(&path).unwrap();
+        let mut other_store = MessageStore::new(config, &SecretKey::new());
+        assert!(other_store.load().await.unwrap().is_empty());
+        let moved = quarantined(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(moved.len(), 1);
+        assert_eq!(std::fs::read(&moved[0]).unwrap(), data);
+        std::fs::remove_file(&moved[0]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn log_which_was_not_loaded_is_not_overwritten() {
+        let path = store_path();
+        let node_key = SecretKey::new();
+        let config = MessageStoreConfig {
+            path: path.clone(),
+            max_size: 1 << 20,
+            max_age: Duration::from_secs(60),
+        };
+        let mut store = MessageStore::new(config.clone(), &node_key);
+        store.load().await.unwrap();
+        store
+            .append(&[StoreOp::Add(now(), msg(b"", b"stored"))])
+            .await
+            .unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        // Appending without loading the log first, as is done if loading fails, moves the log
+        // aside.
+        let mut store = MessageStore::new(config, &node_key);
+        store
+            .append(&[StoreOp::Add(now(), msg(b"", b"new"))])
+            .await
+            .unwrap();
+        let loaded = store.load().await.unwrap();
+        let moved = quarantined(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].1.data, b"new");
+        assert_eq!(moved.len(), 1);
+        assert_eq!(std::fs::read(&moved[0]).unwrap(), data);
+        std::fs::remove_file(&moved[0]).unwrap();
+    }
+}
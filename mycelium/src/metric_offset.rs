@@ -0,0 +1,94 @@
+//! Offsets which are added to the metric of routes during route selection, so an operator can make
+//! routes through some nodes less preferable than otherwise equal routes.
+//!
+//! Offsets are configured per overlay subnet, and apply to routes which go through a neighbour
+//! with an overlay address in that subnet. For instance, giving the subnet of a transit node an
+//! offset makes the router prefer a direct peer over that transit for the destinations they can
+//! both reach. Offsets only influence local route selection, the metrics advertised to peers are
+//! not changed.
+
+use std::net::IpAddr;
+
+use crate::{metric::Metric, routing_table::RouteEntry, subnet::Subnet};
+
+/// An offset added to the metric of routes through neighbours in a [`Subnet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricOffset {
+    /// Overlay subnet of the neighbours the offset applies to.
+    pub subnet: Subnet,
+    /// Value added to the metric of the routes.
+    pub offset: u16,
+}
+
+/// A table of [`MetricOffset`]s. If multiple subnets contain the address of a neighbour, the
+/// offset of the most specific subnet applies. An empty table doesn't change any metrics.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricOffsets {
+    /// The configured offsets.
+    pub offsets: Vec<MetricOffset>,
+}
+
+impl MetricOffsets {
+    /// Checks if there are no offsets configured.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The offset which applies to routes through a neighbour with the given overlay address.
+    pub fn offset(&self, ip: IpAddr) -> u16 {
+        self.offsets
+            .iter()
+            .filter(|mo| mo.subnet.contains_ip(ip))
+            .max_by_key(|mo| mo.subnet.prefix_len())
+            .map(|mo| mo.offset)
+            .unwrap_or_default()
+    }
+
+    /// The offset which applies to a [`RouteEntry`]. Routes through a neighbour of which the
+    /// overlay address is not known yet don't get an offset.
+    pub(crate) fn route_offset(&self, route: &RouteEntry) -> Metric {
+        match route.neighbour().public_key() {
+            // The largest value is the infinite metric, which would make the route unusable.
+            Some(pk) if !self.is_empty() => {
+                Metric::new(self.offset(pk.address().into()).min(u16::MAX - 1))
+            }
+            _ => Metric::new(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::{MetricOffset, MetricOffsets};
+
+    #[test]
+    fn most_specific_subnet_applies() {
+        let offsets = MetricOffsets {
+            offsets: vec![
+                MetricOffset {
+                    subnet: "400::/7".parse().unwrap(),
+                    offset: 100,
+                },
+                MetricOffset {
+                    subnet: "400:1::/64".parse().unwrap(),
+                    offset: 20,
+                },
+            ],
+        };
+
+        assert_eq!(
+            offsets.offset(Ipv6Addr::new(0x400, 1, 0, 0, 0, 0, 0, 1).into()),
+            20
+        );
+        assert_eq!(
+            offsets.offset(Ipv6Addr::new(0x400, 2, 0, 0, 0, 0, 0, 1).into()),
+            100
+        );
+        assert_eq!(
+            offsets.offset(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into()),
+            0
+        );
+    }
+}
@@ -2,7 +2,57 @@
 //! interest is the [`Metrics`] trait. Users can provide their own implementation of this, or use
 //! the default provided implementation to disable gathering metrics.
 
-use crate::peer_manager::PeerType;
+use crate::peer_manager::{InboundRejected, PeerType};
+
+/// The reason a data packet was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// There is no route for the destination of the packet.
+    NoRoute,
+    /// The packet ran out of hops before it reached its destination.
+    HopLimitExceeded,
+    /// The packet is too large to be sent, even when fragmented.
+    TooLarge,
+    /// The content of a received packet could not be decrypted.
+    DecryptionFailed,
+    /// A packet was received from a node whose key is not known, so it can't be decrypted.
+    UnknownSource,
+    /// The source address of a packet from the host is not part of the overlay network, or the
+    /// IPv4 source address of a packet from a remote is not mapped to that remote.
+    InvalidSource,
+    /// The packet, or its decrypted content, is malformed.
+    Malformed,
+    /// The queue towards the next hop (a peer or the local TUN interface) was full.
+    QueueFull,
+}
+
+impl DropReason {
+    /// All reasons, in the order in which they are declared.
+    pub const ALL: [DropReason; 8] = [
+        DropReason::NoRoute,
+        DropReason::HopLimitExceeded,
+        DropReason::TooLarge,
+        DropReason::DecryptionFailed,
+        DropReason::UnknownSource,
+        DropReason::InvalidSource,
+        DropReason::Malformed,
+        DropReason::QueueFull,
+    ];
+
+    /// A short, stable name of the reason, suitable as label value.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::NoRoute => "no_route",
+            DropReason::HopLimitExceeded => "hop_limit_exceeded",
+            DropReason::TooLarge => "too_large",
+            DropReason::DecryptionFailed => "decryption_failed",
+            DropReason::UnknownSource => "unknown_source",
+            DropReason::InvalidSource => "invalid_source",
+            DropReason::Malformed => "malformed",
+            DropReason::QueueFull => "queue_full",
+        }
+    }
+}
 
 /// The collection of all metrics exported by a [`mycelium node`](crate::Node). It is up to the
 /// user to provide an implementation which implements the methods for metrics they are interested
@@ -39,6 +89,27 @@ pub trait Metrics {
     #[inline]
     fn router_process_update(&self) {}
 
+    /// The [`Router`](crate::router::Router) dropped a received update because it was denied by
+    /// one of the configured update filters.
+    #[inline]
+    fn router_update_denied_by_filter(&self) {}
+
+    /// The [`Router`](crate::router::Router) dropped a received update because it announces a
+    /// subnet which is not owned by the router which originated it, or because the peer which
+    /// sent it announced a direct route on behalf of another router.
+    #[inline]
+    fn router_update_origin_rejected(&self) {}
+
+    /// The [`Router`](crate::router::Router) evicted a route from its full routing table, to make
+    /// room for a more preferred route.
+    #[inline]
+    fn router_route_evicted(&self) {}
+
+    /// The [`Router`](crate::router::Router) dropped a received update for a new route, because
+    /// its routing table is full, and no less preferred route could be evicted.
+    #[inline]
+    fn router_route_refused(&self) {}
+
     /// The [`Router`](crate::router::Router) tried to send an update to a peer, but before sending
     /// it we found out the peer is actually already dead.
     ///
@@ -58,6 +129,11 @@ pub trait Metrics {
     #[inline]
     fn router_tlv_source_died(&self) {}
 
+    /// The [`Router`](crate::router::Router) did not send a dump of the selected routes to a peer,
+    /// as many control packets were still queued for the peer.
+    #[inline]
+    fn router_route_dump_skipped(&self) {}
+
     /// A [`Peer`](crate::peer::Peer) was added to the [`Router`](crate::router::Router).
     #[inline]
     fn router_peer_added(&self) {}
@@ -113,6 +189,17 @@ pub trait Metrics {
     #[inline]
     fn router_route_packet_no_route(&self) {}
 
+    /// The [`Router`](crate::router::Router) dropped a packet it was routing because the queue
+    /// towards the next hop (a peer or the local TUN interface) was full.
+    #[inline]
+    fn router_route_packet_queue_full(&self) {}
+
+    /// A data packet was dropped while it was sent, forwarded or received, for the given
+    /// [`DropReason`]. This is called in addition to the more specific router metrics, such as
+    /// [`Self::router_route_packet_no_route`].
+    #[inline]
+    fn packet_dropped(&self, _reason: DropReason) {}
+
     /// The [`Router`](crate::router::Router) replied to a seqno request with a local route, which
     /// is more recent (bigger seqno) than the request.
     #[inline]
@@ -174,4 +261,43 @@ pub trait Metrics {
     /// remote endpoint. The connection could have failed.
     #[inline]
     fn peer_manager_connection_finished(&self) {}
+
+    /// A snapshot of the amount of bytes sent to and received from every connected
+    /// [`Peer`](crate::peer::Peer), as `(endpoint, tx bytes, rx bytes)`. The
+    /// [`Endpoint`](crate::endpoint::Endpoint) of a configured peer doesn't change when it
+    /// reconnects. Peers which are not part of the snapshot are no longer connected.
+    #[inline]
+    fn peer_manager_peer_traffic(&self, _traffic: &[(String, u64, u64)]) {}
+
+    /// The [`PeerManager`](crate::peer_manager::PeerManager) rejected an inbound connection, for
+    /// the given reason.
+    #[inline]
+    fn peer_manager_inbound_connection_rejected(&self, _reason: InboundRejected) {}
+
+    /// The [`PeerManager`](crate::peer_manager::PeerManager) refused an inbound connection,
+    /// because the remote IP is not allowed by the peer access list.
+    #[inline]
+    fn peer_manager_inbound_connection_denied(&self) {}
+
+    /// The [`PeerManager`](crate::peer_manager::PeerManager) closed a connection, because there is
+    /// another connection to the same remote node.
+    #[inline]
+    fn peer_manager_duplicate_connection_closed(&self) {}
+
+    /// The [`PeerManager`](crate::peer_manager::PeerManager) closed a connection, because the
+    /// remote did not prove knowledge of the peering key. `_inbound` indicates if the remote
+    /// connected to us.
+    #[inline]
+    fn peer_manager_peer_authentication_failed(&self, _inbound: bool) {}
+
+    /// The [`DataPlane`](crate::data::DataPlane) failed to reassemble a fragmented payload. The
+    /// received fragments of the payload are discarded.
+    #[inline]
+    fn data_plane_reassembly_failed(&self) {}
+
+    /// The [`DataPlane`](crate::data::DataPlane) tried to compress a packet of `_original_size`
+    /// bytes, resulting in `_compressed_size` bytes. If compression did not make the packet
+    /// smaller, both sizes are equal.
+    #[inline]
+    fn data_plane_payload_compressed(&self, _original_size: usize, _compressed_size: usize) {}
 }
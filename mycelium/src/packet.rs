@@ -16,6 +16,11 @@ const PACKET_HEADER_SIZE: usize = 4;
 pub enum Packet {
     DataPacket(DataPacket),
     ControlPacket(ControlPacket),
+    /// Multiple control packets sent in a single packet. Only peers which announced support for
+    /// this can decode it, and the control packets must fit in a single packet, see
+    /// [`crate::babel::pack`]. Received packets are always decoded as separate
+    /// [`Packet::ControlPacket`]s.
+    ControlPackets(Vec<ControlPacket>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -97,14 +102,17 @@ impl Decoder for Codec {
                 }
             }
             PacketType::ControlPacket => {
-                match self.control_packet_codec.decode(src) {
-                    Ok(Some(p)) => {
-                        self.packet_type = None; // Reset state
-                        Ok(Some(Packet::ControlPacket(p)))
+                let packet = self.control_packet_codec.decode(src)?;
+                // A control packet can hold multiple TLVs, only reset the state once all of them
+                // are decoded.
+                if self.control_packet_codec.packet_complete() {
+                    self.packet_type = None; // Reset state
+                    if packet.is_none() {
+                        // The packet did not hold anything usable, but the next one might.
+                        return self.decode(src);
                     }
-                    Ok(None) => Ok(None),
-                    Err(e) => Err(e),
                 }
+                Ok(packet.map(Packet::ControlPacket))
             }
         }
     }
@@ -123,6 +131,10 @@ impl Encoder<Packet> for Codec {
                 dst.put_slice(&[PROTOCOL_VERSION, 1, 0, 0]);
                 self.control_packet_codec.encode(controlpacket, dst)
             }
+            Packet::ControlPackets(controlpackets) => {
+                dst.put_slice(&[PROTOCOL_VERSION, 1, 0, 0]);
+                self.control_packet_codec.encode_packed(controlpackets, dst)
+            }
         }
     }
 }
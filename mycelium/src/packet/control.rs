@@ -18,6 +18,8 @@ impl ControlPacket {
     pub fn new_hello(dest_peer: &Peer, interval: Duration) -> Self {
         let tlv: babel::Tlv =
             babel::Hello::new_unicast(dest_peer.hello_seqno(), (interval.as_millis() / 10) as u16)
+                .with_packed_tlvs()
+                .with_source_specific_routes()
                 .into();
         dest_peer.increment_hello_seqno();
         tlv
@@ -44,6 +46,20 @@ impl Codec {
             codec: babel::Codec::new(),
         }
     }
+
+    /// Checks if the last decoded bytes completed a packet, see [`babel::Codec::packet_complete`].
+    pub fn packet_complete(&self) -> bool {
+        self.codec.packet_complete()
+    }
+
+    /// Encode multiple control packets in a single packet, see [`babel::Codec::encode_packed`].
+    pub fn encode_packed(
+        &mut self,
+        messages: Vec<ControlPacket>,
+        buf: &mut BytesMut,
+    ) -> io::Result<()> {
+        self.codec.encode_packed(messages, buf)
+    }
 }
 
 impl Decoder for Codec {
@@ -11,13 +11,30 @@ const DATA_PACKET_LEN_MASK: u32 = (1 << 16) - 1;
 
 #[derive(Debug, Clone)]
 pub struct DataPacket {
-    pub raw_data: Vec<u8>, // encrypted data itself, then append the nonce
+    /// The encrypted data, followed by the AES tag and nonce. When decoded, this is a view into
+    /// the read buffer of the connection, so forwarding a packet does not copy it.
+    pub raw_data: BytesMut,
     /// Max amount of hops for the packet.
     pub hop_limit: u8,
+    /// Identifier of the flow the packet belongs to, set by the node which encrypted it. Nodes
+    /// which forward the packet can't decrypt it, so they use this to keep the packets of a flow
+    /// on the same path. 0 if the flow is not known. Nodes which don't know this field send it as
+    /// 0.
+    pub flow: u8,
     pub src_ip: Ipv6Addr,
     pub dst_ip: Ipv6Addr,
 }
 
+impl DataPacket {
+    /// Size of the headers added to the data of a [`DataPacket`] when it is sent to a peer.
+    pub const WIRE_OVERHEAD: usize = super::PACKET_HEADER_SIZE + DATA_PACKET_HEADER_SIZE + 16 + 16;
+
+    /// Size of the [`DataPacket`] when it is sent to a peer, including all headers.
+    pub fn wire_size(&self) -> usize {
+        Self::WIRE_OVERHEAD + self.raw_data.len()
+    }
+}
+
 pub struct Codec {
     header_vals: Option<HeaderValues>,
     src_ip: Option<Ipv6Addr>,
@@ -29,6 +46,7 @@ pub struct Codec {
 struct HeaderValues {
     len: u16,
     hop_limit: u8,
+    flow: u8,
 }
 
 impl Codec {
@@ -47,7 +65,11 @@ impl Decoder for Codec {
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         // Determine the length of the data
-        let HeaderValues { len, hop_limit } = if let Some(header_vals) = self.header_vals {
+        let HeaderValues {
+            len,
+            hop_limit,
+            flow,
+        } = if let Some(header_vals) = self.header_vals {
             header_vals
         } else {
             // Check we have enough data to decode
@@ -56,12 +78,14 @@ impl Decoder for Codec {
             }
 
             let raw_header = src.get_u32();
-            // Hop limit is the last 8 bits.
+            // Hop limit is the last 8 bits, and the flow the first 8 bits.
             let hop_limit = (raw_header & 0xFF) as u8;
             let data_len = ((raw_header >> 8) & DATA_PACKET_LEN_MASK) as u16;
+            let flow = (raw_header >> 24) as u8;
             let header_vals = HeaderValues {
                 len: data_len,
                 hop_limit,
+                flow,
             };
 
             self.header_vals = Some(header_vals);
@@ -112,10 +136,8 @@ impl Decoder for Codec {
             return Ok(None);
         }
 
-        // Decode octets
-        let mut data = vec![0u8; data_len];
-        data.copy_from_slice(&src[..data_len]);
-        src.advance(data_len);
+        // Split off the data without copying it.
+        let data = src.split_to(data_len);
 
         // Reset state
         self.header_vals = None;
@@ -125,6 +147,7 @@ impl Decoder for Codec {
         Ok(Some(DataPacket {
             raw_data: data,
             hop_limit,
+            flow,
             dst_ip: dest_ip,
             src_ip,
         }))
@@ -141,6 +164,8 @@ impl Encoder<DataPacket> for Codec {
         raw_header |= (item.raw_data.len() as u32) << 8;
         // And hop limit
         raw_header |= item.hop_limit as u32;
+        // And flow
+        raw_header |= (item.flow as u32) << 24;
         dst.put_u32(raw_header);
         // Write the source IP
         dst.put_slice(&item.src_ip.octets());
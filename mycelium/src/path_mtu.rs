@@ -0,0 +1,98 @@
+//! Cache of the MTU of the paths to remote nodes.
+//!
+//! Peers can be configured with a link MTU, in which case data packets which don't fit on the link
+//! are dropped by the node which forwards them, and an ICMPv6 Packet Too Big message is sent back
+//! to the node which sent the packet. That node records the MTU for the destination of the
+//! packet, and fragments further data for that destination so it fits. The MTU is the maximum
+//! size of the content of a [`PacketBuffer`](crate::crypto::PacketBuffer), i.e. the payload
+//! before encryption.
+//!
+//! Paths change over time, so entries expire after [`PATH_MTU_EXPIRY`], after which the regular
+//! packet size is tried again, as described in
+//! [RFC 8201](https://www.rfc-editor.org/rfc/rfc8201#section-4).
+
+use std::{collections::HashMap, net::Ipv6Addr, sync::RwLock, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::crypto::PACKET_SIZE;
+
+/// Time after which a path MTU learned from a Packet Too Big message expires.
+pub(crate) const PATH_MTU_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// Minimum path MTU which is accepted. Lower values in Packet Too Big messages are raised to this,
+/// so a misbehaving node can't make us fragment data into tiny pieces.
+pub(crate) const MIN_PATH_MTU: usize = 1024;
+
+/// MTU of the paths to remote nodes, by overlay address, see the [module docs](self).
+#[derive(Debug, Default)]
+pub(crate) struct PathMtuCache {
+    /// The MTU of the path and the time the entry expires.
+    entries: RwLock<HashMap<Ipv6Addr, (usize, Instant)>>,
+}
+
+impl PathMtuCache {
+    /// Create a new, empty, `PathMtuCache`.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the MTU of the path to `dst`, if it is known to be smaller than [`PACKET_SIZE`] and it
+    /// did not expire yet.
+    pub(crate) fn get(&self, dst: Ipv6Addr, now: Instant) -> Option<usize> {
+        self.entries
+            .read()
+            .expect("Path MTU lock is not poisoned; qed")
+            .get(&dst)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(mtu, _)| *mtu)
+    }
+
+    /// Record the MTU of the path to `dst`, as reported in a Packet Too Big message. An MTU which
+    /// is not smaller than the current one is ignored, as the message can come from any node on
+    /// the path. Expired entries are removed.
+    pub(crate) fn update(&self, dst: Ipv6Addr, mtu: usize, now: Instant) {
+        let mtu = mtu.clamp(MIN_PATH_MTU, PACKET_SIZE);
+        if mtu == PACKET_SIZE || self.get(dst, now).is_some_and(|current| current <= mtu) {
+            return;
+        }
+
+        let mut entries = self
+            .entries
+            .write()
+            .expect("Path MTU lock is not poisoned; qed");
+        entries.retain(|_, (_, expires)| *expires > now);
+        entries.insert(dst, (mtu, now + PATH_MTU_EXPIRY));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, time::Duration};
+
+    use tokio::time::Instant;
+
+    use super::{PathMtuCache, MIN_PATH_MTU, PATH_MTU_EXPIRY};
+
+    #[test]
+    fn path_mtu_is_lowered_and_expires() {
+        let dst: Ipv6Addr = "400:1::1".parse().unwrap();
+        let cache = PathMtuCache::new();
+        let now = Instant::now();
+        assert_eq!(cache.get(dst, now), None);
+
+        cache.update(dst, 1200, now);
+        assert_eq!(cache.get(dst, now), Some(1200));
+        // A larger MTU does not raise the known one, a smaller one lowers it.
+        cache.update(dst, 1300, now);
+        assert_eq!(cache.get(dst, now), Some(1200));
+        cache.update(dst, 100, now + Duration::from_secs(1));
+        assert_eq!(cache.get(dst, now), Some(MIN_PATH_MTU));
+
+        let expired = now + Duration::from_secs(1) + PATH_MTU_EXPIRY;
+        assert_eq!(cache.get(dst, expired), None);
+        // Expired entries are removed once a new MTU is recorded.
+        cache.update("400:2::1".parse().unwrap(), 1200, expired);
+        assert_eq!(cache.entries.read().unwrap().len(), 1);
+    }
+}
@@ -1,22 +1,31 @@
 use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use std::{
-    error::Error,
-    io,
+    fmt, io,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc, RwLock, Weak,
+        atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
     },
+    time::{Duration, SystemTime},
 };
 use tokio::{
     select,
-    sync::{mpsc, Notify},
+    sync::{mpsc, watch, Notify},
 };
 use tokio_util::codec::Framed;
 use tracing::{debug, error, info, trace};
 
+#[cfg(feature = "testing")]
+use crate::fault_injection::{Fault, FaultInjector};
 use crate::{
+    babel,
     connection::{self, Connection},
+    crypto::PublicKey,
+    hello_history::{HelloHistory, HelloHistoryConfig},
+    metric::Metric,
     packet::{self, Packet},
+    peer_manager::RateLimiter,
+    router::QueueSizes,
 };
 use crate::{
     packet::{ControlPacket, DataPacket},
@@ -27,17 +36,30 @@ use crate::{
 /// received.
 const PACKET_COALESCE_WINDOW: usize = 50;
 
-/// The default link cost assigned to new peers before their actual cost is known.
+/// Maximum amount of time to spend sending queued control packets to a peer when its connection
+/// is closed.
+const PEER_CLOSE_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The default round trip time assumed for new peers before their actual round trip time is
+/// measured.
 ///
-/// In theory, the best value would be U16::MAX - 1, however this value would take too long to be
-/// flushed out of the smoothed metric. A default of a 50 (50 ms) is still large enough, and
-/// also has a lower impact on the initial link cost when a peer connects for the route metrics.
-const DEFAULT_LINK_COST: u16 = 50;
+/// In theory, the best value would be one which results in a link cost of U16::MAX - 1, however
+/// this value would take too long to be flushed out of the smoothed RTT. A default of 50 ms is
+/// still large enough, and also has a lower impact on the initial link cost when a peer connects
+/// for the route metrics.
+const DEFAULT_RTT: Duration = Duration::from_millis(50);
+
+/// Multiplier for smoothed RTT calculation of the existing smoothed RTT.
+const EXISTING_RTT_FACTOR: u32 = 9;
+/// Divisor for smoothed RTT calcuation of the combined RTT. The weight of a new RTT sample is
+/// `(TOTAL_RTT_DIVISOR - EXISTING_RTT_FACTOR) / TOTAL_RTT_DIVISOR`.
+const TOTAL_RTT_DIVISOR: u32 = 10;
 
-/// Multiplier for smoothed metric calculation of the existing smoothed metric.
-const EXISTING_METRIC_FACTOR: u32 = 9;
-/// Divisor for smoothed metric calcuation of the combined metric
-const TOTAL_METRIC_DIVISOR: u32 = 10;
+/// The amount of round trip time which adds 1 to the link cost of a peer.
+const RTT_PER_LINK_COST_UNIT: Duration = Duration::from_millis(1);
+/// The maximum link cost which can be derived from the round trip time of a peer. This ensures a
+/// very slow link still results in a finite metric.
+const MAX_RTT_LINK_COST: u16 = 10_000;
 
 #[derive(Debug, Clone)]
 /// A peer represents a directly connected participant in the network.
@@ -45,43 +67,229 @@ pub struct Peer {
     inner: Arc<PeerInner>,
 }
 
+/// The reason the connection to a [`Peer`] was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum DisconnectReason {
+    /// The remote closed the connection.
+    RemoteClosed,
+    /// The connection was closed locally, e.g. because the peer was removed.
+    Closed,
+    /// The connection timed out, because keepalive probes were not answered.
+    Keepalive,
+    /// No IHU was received from the peer for too long, even though the connection itself seemed
+    /// fine.
+    HelloTimeout,
+    /// Sending or receiving data on the connection failed.
+    ConnectionError,
+}
+
+impl DisconnectReason {
+    /// Get the `DisconnectReason` for an error returned by the connection.
+    fn from_io_error(e: &io::Error) -> Self {
+        if e.kind() == io::ErrorKind::TimedOut {
+            DisconnectReason::Keepalive
+        } else {
+            DisconnectReason::ConnectionError
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RemoteClosed => f.write_str("closed by remote"),
+            Self::Closed => f.write_str("closed locally"),
+            Self::Keepalive => f.write_str("keepalive timeout"),
+            Self::HelloTimeout => f.write_str("hello timeout"),
+            Self::ConnectionError => f.write_str("connection error"),
+        }
+    }
+}
+
+/// The state in the lifecycle of the connection to a peer.
+///
+/// A connection starts out [`Connecting`](Self::Connecting), and moves to
+/// [`Handshaking`](Self::Handshaking) once the transport is set up. It is
+/// [`Alive`](Self::Alive) once the first IHU is received from the remote, which proves packets
+/// flow in both directions. If no IHU is received for a few Hello intervals, the connection is
+/// [`Degraded`](Self::Degraded) until the next IHU arrives. Once the connection is closed, or no
+/// IHU is received for too long, it is [`Dead`](Self::Dead).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum PeerState {
+    /// A connection to the peer is being set up.
+    Connecting,
+    /// The transport is set up, but the remote has not answered a Hello yet.
+    Handshaking,
+    /// Hello and IHU packets are exchanged with the remote.
+    Alive,
+    /// The connection is open, but no IHU was received from the remote recently.
+    Degraded,
+    /// There is no connection to the peer.
+    #[default]
+    Dead,
+}
+
+impl fmt::Display for PeerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connecting => f.write_str("connecting"),
+            Self::Handshaking => f.write_str("handshaking"),
+            Self::Alive => f.write_str("alive"),
+            Self::Degraded => f.write_str("degraded"),
+            Self::Dead => f.write_str("dead"),
+        }
+    }
+}
+
+/// The status of the connections to a peer. This is shared between the [`Peer`] of the current
+/// connection and the owner of the connections, and outlives individual connections.
+#[derive(Debug)]
+pub struct ConnectionStatus {
+    /// The current [`PeerState`], and the time of the last transition.
+    state: Mutex<(PeerState, SystemTime)>,
+    /// The reason the last connection was closed, if any.
+    disconnect_reason: Mutex<Option<DisconnectReason>>,
+}
+
+impl ConnectionStatus {
+    /// The current [`PeerState`], and the time it was entered.
+    pub fn state(&self) -> (PeerState, SystemTime) {
+        *self.state.lock().unwrap()
+    }
+
+    /// Move to a new [`PeerState`]. The time of the transition is only updated if the state
+    /// actually changes.
+    pub fn transition(&self, new_state: PeerState) {
+        let mut state = self.state.lock().unwrap();
+        if state.0 != new_state {
+            *state = (new_state, SystemTime::now());
+        }
+    }
+
+    /// The reason the last connection was closed, if it was ever closed.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        *self.disconnect_reason.lock().unwrap()
+    }
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new((PeerState::Connecting, SystemTime::now())),
+            disconnect_reason: Mutex::new(None),
+        }
+    }
+}
+
 /// A weak reference to a peer, which does not prevent it from being cleaned up. This can be used
 /// to check liveliness of the [`Peer`] instance it originated from.
 pub struct PeerRef {
     inner: Weak<PeerInner>,
 }
 
+/// The channels and shared state a [`Peer`] is created with, see [`Peer::new`].
+#[derive(Clone)]
+pub struct PeerConfig {
+    /// Channel on which data packets received from the `Peer` are sent to the router.
+    pub router_data_tx: mpsc::Sender<(DataPacket, Peer)>,
+    /// Channel on which control packets received from the `Peer` are sent to the router.
+    pub router_control_tx: mpsc::Sender<(ControlPacket, Peer)>,
+    /// Channel on which the `Peer` is sent once its connection is closed.
+    pub dead_peer_sink: mpsc::Sender<Peer>,
+    /// Amount of bytes written to the connection.
+    pub bytes_written: Arc<AtomicU64>,
+    /// Amount of bytes read from the connection.
+    pub bytes_read: Arc<AtomicU64>,
+    /// Bandwidth limits of the data packets exchanged with the `Peer`.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Records transitions of the [`PeerState`], and the reason the connection is closed.
+    pub status: Arc<ConnectionStatus>,
+    /// Amount of data packets received from the `Peer` which failed decryption.
+    pub decryption_failures: Arc<AtomicU64>,
+    /// Capacities of the queues of packets to be sent to the `Peer`.
+    pub queue_sizes: QueueSizes,
+}
+
+impl PeerConfig {
+    /// Create a new `PeerConfig` with the given channels to the router. The counters and status
+    /// of the connection are new, and the queues have their default capacities.
+    pub fn new(
+        router_data_tx: mpsc::Sender<(DataPacket, Peer)>,
+        router_control_tx: mpsc::Sender<(ControlPacket, Peer)>,
+        dead_peer_sink: mpsc::Sender<Peer>,
+    ) -> Self {
+        Self {
+            router_data_tx,
+            router_control_tx,
+            dead_peer_sink,
+            bytes_written: Arc::default(),
+            bytes_read: Arc::default(),
+            rate_limiter: Arc::default(),
+            status: Arc::default(),
+            decryption_failures: Arc::default(),
+            queue_sizes: QueueSizes::default(),
+        }
+    }
+}
+
 impl Peer {
+    /// Create a new `Peer` using the given connection, with the channels and state in `config`.
     pub fn new<C: Connection + Unpin + Send + 'static>(
-        router_data_tx: mpsc::Sender<DataPacket>,
-        router_control_tx: mpsc::UnboundedSender<(ControlPacket, Peer)>,
+        config: PeerConfig,
         connection: C,
-        dead_peer_sink: mpsc::Sender<Peer>,
-        bytes_written: Arc<AtomicU64>,
-        bytes_read: Arc<AtomicU64>,
     ) -> Result<Self, io::Error> {
+        let PeerConfig {
+            router_data_tx,
+            router_control_tx,
+            dead_peer_sink,
+            bytes_written,
+            bytes_read,
+            rate_limiter,
+            status,
+            decryption_failures,
+            queue_sizes,
+        } = config;
+
         // Wrap connection so we can get access to the counters.
         let connection = connection::Tracked::new(bytes_read, bytes_written, connection);
 
         // Data channel for peer
-        let (to_peer_data, mut from_routing_data) = mpsc::unbounded_channel::<DataPacket>();
-        // Control channel for peer
+        let (to_peer_data, mut from_routing_data) =
+            mpsc::channel::<DataPacket>(queue_sizes.peer_data);
+        // Control channel for peer. Control packets are never dropped, so this is unbounded. The
+        // router limits the amount of packets it queues here instead.
         let (to_peer_control, mut from_routing_control) =
             mpsc::unbounded_channel::<ControlPacket>();
         let death_notifier = Arc::new(Notify::new());
         let death_watcher = death_notifier.clone();
+        let (closed_tx, closed) = watch::channel(false);
         let peer = Peer {
             inner: Arc::new(PeerInner {
-                state: RwLock::new(PeerState::new()),
+                state: RwLock::new(PeerMutableState::new()),
                 to_peer_data,
                 to_peer_control,
+                queued_control_packets: AtomicUsize::new(0),
                 connection_identifier: connection.identifier()?,
+                status: status.clone(),
                 static_link_cost: connection.static_link_cost()?,
                 death_notifier,
+                death_reason: Mutex::new(None),
+                closed,
                 alive: AtomicBool::new(true),
+                packed_tlvs: AtomicBool::new(false),
+                source_specific_routes: AtomicBool::new(false),
+                mtu: AtomicU16::new(0),
+                priority: AtomicU8::new(0),
+                decryption_failures,
             }),
         };
 
+        status.transition(PeerState::Handshaking);
+
         // Framed for peer
         // Used to send and receive packets from a TCP stream
         let mut framed = Framed::new(connection, packet::Codec::new());
@@ -90,7 +298,7 @@ impl Peer {
             let peer = peer.clone();
 
             tokio::spawn(async move {
-                loop {
+                let reason = loop {
                     select! {
                         // Received over the TCP stream
                         frame = framed.next() => {
@@ -98,45 +306,60 @@ impl Peer {
                                 Some(Ok(packet)) => {
                                     match packet {
                                         Packet::DataPacket(packet) => {
+                                            if !rate_limiter.allow_rx(packet.raw_data.len()) {
+                                                trace!("Dropping data packet from {} exceeding the rate limit", peer.connection_identifier());
+                                                continue
+                                            }
                                             // An error here means the receiver is dropped/closed,
                                             // this is not recoverable.
-                                            if let Err(error) = router_data_tx.send(packet).await{
+                                            if let Err(error) = router_data_tx.send((packet, peer.clone())).await{
                                                 error!("Error sending to to_routing_data: {}", error);
-                                                break
+                                                break DisconnectReason::Closed
                                             }
                                         }
                                         Packet::ControlPacket(packet) => {
-                                            if let Err(error) = router_control_tx.send((packet, peer.clone())) {
+                                            if let Err(error) = router_control_tx.send((packet, peer.clone())).await {
                                                 // An error here means the receiver is dropped/closed,
                                                 // this is not recoverable.
                                                 error!("Error sending to to_routing_control: {}", error);
-                                                break
+                                                break DisconnectReason::Closed
                                             }
 
                                         }
+                                        // Packed control packets are decoded one by one, so this
+                                        // is never received.
+                                        Packet::ControlPackets(_) => {}
                                     }
                                 }
                                 Some(Err(e)) => {
                                     error!("Frame error from {}: {e}", peer.connection_identifier());
-                                    break;
+                                    break DisconnectReason::from_io_error(&e);
                                 },
                                 None => {
                                     info!("Stream to {} is closed", peer.connection_identifier());
-                                    break;
+                                    break DisconnectReason::RemoteClosed;
                                 }
                             }
                         }
 
                         Some(packet) = from_routing_data.recv() => {
+                            if !rate_limiter.allow_tx(packet.raw_data.len()) {
+                                trace!("Dropping data packet to {} exceeding the rate limit", peer.connection_identifier());
+                                continue
+                            }
                             if let Err(e) = framed.feed(Packet::DataPacket(packet)).await {
                                 error!("Failed to feed data packet to connection: {e}");
-                                break
+                                break DisconnectReason::from_io_error(&e)
                             }
 
                             for _ in 1..PACKET_COALESCE_WINDOW {
                                 // There can be 2 cases of errors here, empty channel and no more
                                 // senders. In both cases we don't really care at this point.
                                 if let Ok(packet) = from_routing_data.try_recv() {
+                                    if !rate_limiter.allow_tx(packet.raw_data.len()) {
+                                        trace!("Dropping data packet to {} exceeding the rate limit", peer.connection_identifier());
+                                        continue
+                                    }
                                     if let Err(e) = framed.feed(Packet::DataPacket(packet)).await {
                                         error!("Failed to feed data packet to connection: {e}");
                                         break
@@ -150,46 +373,68 @@ impl Peer {
 
                             if let Err(e) = framed.flush().await {
                                 error!("Failed to flush buffered peer connection data packets: {e}");
-                                break
+                                break DisconnectReason::from_io_error(&e)
                             }
                         }
 
                         Some(packet) = from_routing_control.recv() => {
-                            if let Err(e) = framed.feed(Packet::ControlPacket(packet)).await {
-                                error!("Failed to feed control packet to connection: {e}");
-                                break
+                            let mut packets = vec![packet];
+                            // There can be 2 cases of errors here, empty channel and no more
+                            // senders. In both cases we don't really care at this point.
+                            while packets.len() < PACKET_COALESCE_WINDOW {
+                                let Ok(packet) = from_routing_control.try_recv() else {
+                                    // No packets ready, send the ones we have
+                                    break
+                                };
+                                packets.push(packet);
                             }
+                            peer.inner.queued_control_packets.fetch_sub(packets.len(), Ordering::Relaxed);
 
-                            for _ in 1..PACKET_COALESCE_WINDOW {
-                                // There can be 2 cases of errors here, empty channel and no more
-                                // senders. In both cases we don't really care at this point.
-                                if let Ok(packet) = from_routing_control.try_recv() {
-                                    if let Err(e) = framed.feed(Packet::ControlPacket(packet)).await {
-                                        error!("Failed to feed data packet to connection: {e}");
-                                        break
-                                    }
-                                } else {
-                                    // No packets ready, flush currently buffered ones
-                                    break
-                                }
+                            if let Err(e) = feed_control_packets(&mut framed, packets, peer.accepts_packed_tlvs()).await {
+                                error!("Failed to feed control packet to connection: {e}");
+                                break DisconnectReason::from_io_error(&e)
                             }
 
                             if let Err(e) = framed.flush().await {
                                 error!("Failed to flush buffered peer connection control packets: {e}");
-                                break
+                                break DisconnectReason::from_io_error(&e)
                             }
                         }
 
                         _ = death_watcher.notified() => {
-                            break;
+                            // Try to send control packets which are still queued (e.g. route
+                            // retractions sent before the connection was closed on purpose), so
+                            // the remote learns about them.
+                            let flush = async {
+                                let mut packets = Vec::new();
+                                while let Ok(packet) = from_routing_control.try_recv() {
+                                    packets.push(packet);
+                                }
+                                feed_control_packets(&mut framed, packets, peer.accepts_packed_tlvs()).await?;
+                                framed.flush().await
+                            };
+                            match tokio::time::timeout(PEER_CLOSE_FLUSH_TIMEOUT, flush).await {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => {
+                                    debug!("Failed to flush control packets to closed connection: {e}")
+                                }
+                                Err(_) => {
+                                    debug!("Timed out flushing control packets to closed connection")
+                                }
+                            }
+                            break peer.inner.death_reason.lock().unwrap().unwrap_or(DisconnectReason::Closed);
                         }
                     }
-                }
+                };
 
                 // Notify router we are dead, also modify our internal state to declare that.
                 // Relaxed ordering is fine, we just care that the variable is set.
                 peer.inner.alive.store(false, Ordering::Relaxed);
                 let remote_id = peer.connection_identifier().clone();
+                info!("Connection to peer {remote_id} closed: {reason}");
+                *status.disconnect_reason.lock().unwrap() = Some(reason);
+                status.transition(PeerState::Dead);
+                closed_tx.send_replace(true);
                 debug!("Notifying router peer {remote_id} is dead");
                 if let Err(e) = dead_peer_sink.send(peer).await {
                     error!("Peer {remote_id} could not notify router of termination: {e}");
@@ -210,48 +455,282 @@ impl Peer {
         self.inner.state.write().unwrap().hello_seqno += 1;
     }
 
-    pub fn time_last_received_hello(&self) -> tokio::time::Instant {
-        self.inner.state.read().unwrap().time_last_received_hello
-    }
-
-    pub fn set_time_last_received_hello(&self, time: tokio::time::Instant) {
-        self.inner.state.write().unwrap().time_last_received_hello = time
+    /// Register that a Hello was sent to the `Peer` at the given time. When the next IHU is
+    /// received from the `Peer`, it is matched to this Hello to measure the round trip time.
+    ///
+    /// If a previous Hello did not get a reply yet, it is considered lost.
+    pub fn set_time_last_sent_hello(&self, time: tokio::time::Instant) {
+        self.inner.state.write().unwrap().time_last_sent_hello = Some(time)
     }
 
     /// For sending data packets towards a peer instance on this node.
     /// It's send over the to_peer_data channel and read from the corresponding receiver.
     /// The receiver sends the packet over the TCP stream towards the destined peer instance on another node
-    pub fn send_data_packet(&self, data_packet: DataPacket) -> Result<(), Box<dyn Error>> {
-        Ok(self.inner.to_peer_data.send(data_packet)?)
+    ///
+    /// This never waits. If the queue towards the peer is full, the packet is returned in a
+    /// [`TrySendError::Full`](mpsc::error::TrySendError::Full) error.
+    pub fn send_data_packet(
+        &self,
+        data_packet: DataPacket,
+    ) -> Result<(), mpsc::error::TrySendError<DataPacket>> {
+        #[cfg(feature = "testing")]
+        match self.fault() {
+            Fault::None => {}
+            Fault::Drop => return Ok(()),
+            Fault::Delay(delay) => {
+                let to_peer_data = self.inner.to_peer_data.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = to_peer_data.try_send(data_packet);
+                });
+                return Ok(());
+            }
+        }
+
+        self.inner.to_peer_data.try_send(data_packet)
     }
 
     /// For sending control packets towards a peer instance on this node.
     /// It's send over the to_peer_control channel and read from the corresponding receiver.
     /// The receiver sends the packet over the TCP stream towards the destined peer instance on another node
-    pub fn send_control_packet(&self, control_packet: ControlPacket) -> Result<(), Box<dyn Error>> {
-        Ok(self.inner.to_peer_control.send(control_packet)?)
+    ///
+    /// This never waits, and control packets are never dropped. An error is only returned if the
+    /// connection to the peer is closed.
+    pub fn send_control_packet(
+        &self,
+        control_packet: ControlPacket,
+    ) -> Result<(), mpsc::error::SendError<ControlPacket>> {
+        #[cfg(feature = "testing")]
+        match self.fault() {
+            Fault::None => {}
+            Fault::Drop => return Ok(()),
+            Fault::Delay(delay) => {
+                let peer = self.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = peer.queue_control_packet(control_packet);
+                });
+                return Ok(());
+            }
+        }
+
+        self.queue_control_packet(control_packet)
+    }
+
+    /// Add a control packet to the queue towards the peer, and count it as queued.
+    fn queue_control_packet(
+        &self,
+        control_packet: ControlPacket,
+    ) -> Result<(), mpsc::error::SendError<ControlPacket>> {
+        // Count the packet first, so the connection never sees more packets than are counted.
+        self.inner
+            .queued_control_packets
+            .fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = self.inner.to_peer_control.send(control_packet) {
+            self.inner
+                .queued_control_packets
+                .fetch_sub(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The amount of control packets which are queued, but not yet written to the connection.
+    pub fn queued_control_packets(&self) -> usize {
+        self.inner.queued_control_packets.load(Ordering::Relaxed)
+    }
+
+    /// Inject faults picked by `fault_injector` on packets sent to this `Peer`.
+    #[cfg(feature = "testing")]
+    pub(crate) fn set_fault_injector(&self, fault_injector: Arc<FaultInjector>) {
+        self.inner.state.write().unwrap().fault_injector = Some(fault_injector);
+    }
+
+    /// The fault to apply to the next packet sent to this `Peer`.
+    #[cfg(feature = "testing")]
+    fn fault(&self) -> Fault {
+        match &self.inner.state.read().unwrap().fault_injector {
+            Some(fault_injector) => fault_injector.fault(),
+            None => Fault::None,
+        }
     }
 
     /// Get the cost to use the peer, i.e. the additional impact on the [`crate::metric::Metric`]
     /// for using this `Peer`.
     ///
-    /// This is a smoothed value, which is calculated over the recent history of link cost.
+    /// This is derived from the smoothed round trip time of the link, see [`Peer::rtt`], the
+    /// static link cost of the connection, and the loss of Hellos on the link, see
+    /// [`Peer::hello_reception`].
     pub fn link_cost(&self) -> u16 {
-        self.inner.state.read().unwrap().link_cost + self.inner.static_link_cost
+        let inner = self.inner.state.read().unwrap();
+        (rtt_link_cost(inner.rtt) + self.inner.static_link_cost)
+            .saturating_add(inner.hello_loss_cost)
+    }
+
+    /// Register a Hello with the given sequence number and advertised interval received from the
+    /// `Peer`. This updates the reception fraction of the link, and the link cost derived from it
+    /// according to `config`.
+    pub fn hello_received(&self, seqno: SeqNo, interval: Duration, config: &HelloHistoryConfig) {
+        let mut inner = self.inner.state.write().unwrap();
+        inner
+            .hello_history
+            .received(seqno, interval, tokio::time::Instant::now());
+        inner.update_hello_reception(config);
+    }
+
+    /// Mark the Hellos which the `Peer` should have sent by now, but which were not received, as
+    /// lost.
+    pub fn check_hello_timeout(&self, config: &HelloHistoryConfig) {
+        let mut inner = self.inner.state.write().unwrap();
+        inner
+            .hello_history
+            .check_timeout(tokio::time::Instant::now());
+        inner.update_hello_reception(config);
+    }
+
+    /// Register if the `Peer` can decode control packets holding multiple TLVs, as announced in the
+    /// Hellos it sends.
+    pub fn set_accepts_packed_tlvs(&self, accepts: bool) {
+        self.inner.packed_tlvs.store(accepts, Ordering::Relaxed);
+    }
+
+    /// Checks if multiple control packets can be sent to the `Peer` in a single packet.
+    pub fn accepts_packed_tlvs(&self) -> bool {
+        self.inner.packed_tlvs.load(Ordering::Relaxed)
+    }
+
+    /// Register if the `Peer` can decode source-specific control packets, as announced in the
+    /// Hellos it sends. Returns the previous value.
+    pub fn set_accepts_source_specific_routes(&self, accepts: bool) -> bool {
+        self.inner
+            .source_specific_routes
+            .swap(accepts, Ordering::Relaxed)
+    }
+
+    /// Checks if control packets holding a source prefix can be sent to the `Peer`.
+    pub fn accepts_source_specific_routes(&self) -> bool {
+        self.inner.source_specific_routes.load(Ordering::Relaxed)
+    }
+
+    /// Set the MTU of the link to the `Peer`, i.e. the maximum size of a [`DataPacket`] sent to
+    /// it, or remove the limit if [`None`] is given.
+    pub fn set_mtu(&self, mtu: Option<u16>) {
+        self.inner.mtu.store(mtu.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The MTU of the link to the `Peer`, if it is limited.
+    pub fn mtu(&self) -> Option<u16> {
+        Some(self.inner.mtu.load(Ordering::Relaxed)).filter(|mtu| *mtu != 0)
+    }
+
+    /// Set the priority of the `Peer` as next hop. Route selection prefers routes through peers
+    /// with a higher priority, if their metric is close enough to the best route.
+    pub fn set_priority(&self, priority: u8) {
+        self.inner.priority.store(priority, Ordering::Relaxed);
     }
 
-    /// Sets the link cost based on the provided value.
+    /// The priority of the `Peer` as next hop, higher is preferred. This is 0 unless it is set.
+    pub fn priority(&self) -> u8 {
+        self.inner.priority.load(Ordering::Relaxed)
+    }
+
+    /// Get the fraction of the recent Hellos sent by the `Peer` which was received.
+    pub fn hello_reception(&self) -> f64 {
+        self.inner.state.read().unwrap().hello_reception
+    }
+
+    /// Record that a data packet received from the `Peer` failed decryption at this node.
+    pub fn decryption_failed(&self) {
+        self.inner
+            .decryption_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the round trip time of the link to this `Peer`.
     ///
-    /// The link cost is not set to the given value, but rather to an average of recent values.
-    /// This makes sure short-lived, hard spikes of the link cost of a peer don't influence the
-    /// routing.
-    pub fn set_link_cost(&self, new_link_cost: u16) {
-        // Calculate new link cost by multiplying (i.e. scaling) old and new link cost and
-        // averaging them.
+    /// This is a smoothed value, which is calculated over the recent history of measured round
+    /// trip times.
+    pub fn rtt(&self) -> Duration {
+        self.inner.state.read().unwrap().rtt
+    }
+
+    /// Register an IHU received from the `Peer` at the given time. If there is an outstanding
+    /// Hello sent to the `Peer`, the round trip time of the link is measured and used to update
+    /// the smoothed round trip time.
+    ///
+    /// The smoothed value is an average of recent values, rather than the last measured one. This
+    /// makes sure short-lived, hard spikes of the link cost of a peer don't influence the routing.
+    pub fn ihu_received(&self, time: tokio::time::Instant) {
+        if self.alive() {
+            if self.state() == PeerState::Degraded {
+                info!(
+                    "Peer {} is no longer degraded",
+                    self.connection_identifier()
+                );
+            }
+            self.inner.status.transition(PeerState::Alive);
+        }
         let mut inner = self.inner.state.write().unwrap();
-        inner.link_cost = (((inner.link_cost as u32) * EXISTING_METRIC_FACTOR
-            + (new_link_cost as u32) * (TOTAL_METRIC_DIVISOR - EXISTING_METRIC_FACTOR))
-            / TOTAL_METRIC_DIVISOR) as u16;
+        inner.time_last_received_ihu = time;
+        // Only the first IHU after a Hello is a reply to it, so don't measure later ones.
+        if let Some(sent) = inner.time_last_sent_hello.take() {
+            let rtt = time.duration_since(sent);
+            inner.rtt = (inner.rtt * EXISTING_RTT_FACTOR
+                + rtt * (TOTAL_RTT_DIVISOR - EXISTING_RTT_FACTOR))
+                / TOTAL_RTT_DIVISOR;
+        }
+    }
+
+    /// Register the cost of the link as seen by the `Peer`, and the interval of its IHUs, as
+    /// advertised in an IHU received from it.
+    pub fn set_tx_cost(&self, tx_cost: Metric, ihu_interval: Duration) {
+        let mut inner = self.inner.state.write().unwrap();
+        inner.tx_cost = Some(tx_cost);
+        inner.ihu_interval = Some(ihu_interval);
+    }
+
+    /// The cost of the link as seen by the `Peer`, as advertised in the last IHU received from it,
+    /// if any.
+    pub fn tx_cost(&self) -> Option<Metric> {
+        self.inner.state.read().unwrap().tx_cost
+    }
+
+    /// The interval advertised in the last IHU received from the `Peer`, if any.
+    pub fn ihu_interval(&self) -> Option<Duration> {
+        self.inner.state.read().unwrap().ihu_interval
+    }
+
+    /// The sequence number and reception time of the last Hello received from the `Peer`, if any.
+    pub fn last_hello(&self) -> Option<(SeqNo, tokio::time::Instant)> {
+        self.inner
+            .state
+            .read()
+            .unwrap()
+            .hello_history
+            .last_received()
+    }
+
+    /// The interval advertised in the last Hello received from the `Peer`, if any.
+    pub fn hello_interval(&self) -> Option<Duration> {
+        self.inner.state.read().unwrap().hello_history.interval()
+    }
+
+    /// The current [`PeerState`] of the connection to the `Peer`.
+    pub fn state(&self) -> PeerState {
+        self.inner.status.state().0
+    }
+
+    /// Mark the connection as degraded, because no IHU was received recently. This only applies
+    /// to connections which are alive.
+    pub fn degraded(&self) {
+        let mut state = self.inner.status.state.lock().unwrap();
+        if state.0 == PeerState::Alive {
+            *state = (PeerState::Degraded, SystemTime::now());
+            info!(
+                "Peer {} is degraded, no IHU received recently",
+                self.connection_identifier()
+            );
+        }
     }
 
     /// Identifier for the connection to the `Peer`.
@@ -259,24 +738,55 @@ impl Peer {
         &self.inner.connection_identifier
     }
 
-    pub fn time_last_received_ihu(&self) -> tokio::time::Instant {
-        self.inner.state.read().unwrap().time_last_received_ihu
+    /// Stable identifier of the node at the other end of the connection. This is the overlay
+    /// address of the node once its [`PublicKey`] is known, so it does not change when the node
+    /// reconnects. Until then, this is the [`connection identifier`](Self::connection_identifier).
+    pub fn identifier(&self) -> String {
+        match self.public_key() {
+            Some(pk) => pk.address().to_string(),
+            None => self.connection_identifier().clone(),
+        }
+    }
+
+    /// The [`PublicKey`] of the node at the other end of the connection, if it is known. This is
+    /// learned once the `Peer` announces its own subnet.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        self.inner.state.read().unwrap().public_key
     }
 
-    pub fn set_time_last_received_ihu(&self, time: tokio::time::Instant) {
-        self.inner.state.write().unwrap().time_last_received_ihu = time
+    /// Set the [`PublicKey`] of the node at the other end of the connection.
+    pub fn set_public_key(&self, public_key: PublicKey) {
+        self.inner.state.write().unwrap().public_key = Some(public_key);
     }
 
-    /// Notify this `Peer` that it died.
+    pub fn time_last_received_ihu(&self) -> tokio::time::Instant {
+        self.inner.state.read().unwrap().time_last_received_ihu
+    }
+
+    /// Notify this `Peer` that it died, for the given `reason`.
     ///
     /// While some [`Connection`] types can immediately detect that the connection itself is
     /// broken, not all of them can. In this scenario, we need to rely on an outside signal to tell
     /// us that we have, in fact, died.
-    pub fn died(&self) {
+    pub fn died(&self, reason: DisconnectReason) {
+        self.inner
+            .death_reason
+            .lock()
+            .unwrap()
+            .get_or_insert(reason);
         self.inner.alive.store(false, Ordering::Relaxed);
         self.inner.death_notifier.notify_one();
     }
 
+    /// Wait until the connection of this `Peer` is closed. If the connection was closed with
+    /// [`Peer::died`], this also waits until the control packets which were queued at that point
+    /// are sent, or [`PEER_CLOSE_FLUSH_TIMEOUT`] passed.
+    pub async fn closed(&self) {
+        let mut closed = self.inner.closed.clone();
+        // The sender is only dropped after it sent `true`, so an error means it is closed as well.
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
     /// Checks if the connection of this `Peer` is still alive.
     ///
     /// For connection types which don't have (real time) state information, this might return a
@@ -330,44 +840,190 @@ impl PartialEq for Peer {
 
 #[derive(Debug)]
 struct PeerInner {
-    state: RwLock<PeerState>,
-    to_peer_data: mpsc::UnboundedSender<DataPacket>,
+    state: RwLock<PeerMutableState>,
+    to_peer_data: mpsc::Sender<DataPacket>,
     to_peer_control: mpsc::UnboundedSender<ControlPacket>,
+    /// Amount of control packets in the queue of `to_peer_control`.
+    queued_control_packets: AtomicUsize,
     /// Used to identify peer based on its connection params.
     connection_identifier: String,
+    /// Status of the connections to the remote, shared with the owner of the connections.
+    status: Arc<ConnectionStatus>,
     /// Static cost of using this link, to be added to the announced metric for routes through this
     /// Peer.
     static_link_cost: u16,
     /// Channel to notify the connection of its decease.
     death_notifier: Arc<Notify>,
+    /// The reason given when the connection was notified of its decease.
+    death_reason: Mutex<Option<DisconnectReason>>,
+    /// Set once the connection task has finished.
+    closed: watch::Receiver<bool>,
     /// Keep track if the connection is alive.
     alive: AtomicBool,
+    /// Set if the remote announced it can decode control packets holding multiple TLVs.
+    packed_tlvs: AtomicBool,
+    /// Set if the remote announced it can decode control packets holding a source prefix.
+    source_specific_routes: AtomicBool,
+    /// MTU of the link to the remote, 0 if it is not limited.
+    mtu: AtomicU16,
+    /// Priority of the remote as next hop, higher is preferred.
+    priority: AtomicU8,
+    /// Amount of data packets received from the remote which failed decryption, shared with the
+    /// owner of the connections.
+    decryption_failures: Arc<AtomicU64>,
 }
 
 #[derive(Debug)]
-struct PeerState {
+struct PeerMutableState {
     hello_seqno: SeqNo,
-    time_last_received_hello: tokio::time::Instant,
-    link_cost: u16,
+    /// Time the last Hello was sent, if no IHU was received since then.
+    time_last_sent_hello: Option<tokio::time::Instant>,
+    /// Smoothed round trip time of the link.
+    rtt: Duration,
     time_last_received_ihu: tokio::time::Instant,
+    /// Cost of the link advertised by the remote in the last IHU received from it.
+    tx_cost: Option<Metric>,
+    /// Interval advertised by the remote in the last IHU received from it.
+    ihu_interval: Option<Duration>,
+    /// Public key of the remote node, if it is known.
+    public_key: Option<PublicKey>,
+    /// Recent history of Hellos received from the remote.
+    hello_history: HelloHistory,
+    /// Fraction of the Hellos in the history window which was received.
+    hello_reception: f64,
+    /// Cost added to the link because of lost Hellos.
+    hello_loss_cost: u16,
+    /// Injects faults on packets sent to the remote.
+    #[cfg(feature = "testing")]
+    fault_injector: Option<Arc<FaultInjector>>,
 }
 
-impl PeerState {
+impl PeerMutableState {
     /// Create a new `PeerInner`, holding the mutable state of a [`Peer`]
     fn new() -> Self {
         // Initialize last_sent_hello_seqno to 0
         let hello_seqno = SeqNo::default();
-        let link_cost = DEFAULT_LINK_COST;
-        // Initialize time_last_received_hello to now
-        let time_last_received_hello = tokio::time::Instant::now();
+        let rtt = DEFAULT_RTT;
         // Initialiwe time_last_send_ihu
         let time_last_received_ihu = tokio::time::Instant::now();
 
         Self {
             hello_seqno,
-            link_cost,
+            rtt,
             time_last_received_ihu,
-            time_last_received_hello,
+            time_last_sent_hello: None,
+            tx_cost: None,
+            ihu_interval: None,
+            public_key: None,
+            hello_history: HelloHistory::default(),
+            hello_reception: 1.0,
+            hello_loss_cost: 0,
+            #[cfg(feature = "testing")]
+            fault_injector: None,
+        }
+    }
+
+    /// Recompute the reception fraction and the derived link cost from the Hello history.
+    fn update_hello_reception(&mut self, config: &HelloHistoryConfig) {
+        self.hello_reception = self.hello_history.reception(config.window);
+        self.hello_loss_cost = config.link_cost(self.hello_reception);
+    }
+}
+
+/// Feed control packets to a connection without flushing it. If `packed` is set, as many control
+/// packets as possible are sent in a single packet.
+async fn feed_control_packets<C>(
+    framed: &mut Framed<C, packet::Codec>,
+    packets: Vec<ControlPacket>,
+    packed: bool,
+) -> io::Result<()>
+where
+    C: Connection + Unpin,
+{
+    if packed {
+        for packet in babel::pack(packets) {
+            framed.feed(Packet::ControlPackets(packet)).await?;
+        }
+    } else {
+        for packet in packets {
+            framed.feed(Packet::ControlPacket(packet)).await?;
         }
     }
+    Ok(())
+}
+
+/// Map a round trip time to a link cost.
+fn rtt_link_cost(rtt: Duration) -> u16 {
+    (rtt.as_nanos() / RTT_PER_LINK_COST_UNIT.as_nanos()).min(MAX_RTT_LINK_COST as u128) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::{ConnectionStatus, DisconnectReason, Peer, PeerConfig, PeerState};
+
+    /// Create a [`Peer`] on a duplex connection, and return it together with the remote end of
+    /// the connection, the dead peer stream, and the status of the connection.
+    fn dummy_peer() -> (
+        Peer,
+        tokio::io::DuplexStream,
+        mpsc::Receiver<Peer>,
+        Arc<ConnectionStatus>,
+    ) {
+        let (router_data_tx, _) = mpsc::channel(1);
+        let (router_control_tx, _) = mpsc::channel(1);
+        let (dead_peer_sink, dead_peer_stream) = mpsc::channel(1);
+        let (con, remote) = tokio::io::duplex(1500);
+        let config = PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink);
+        let status = config.status.clone();
+        let peer = Peer::new(config, con).expect("Can create a dummy peer");
+
+        (peer, remote, dead_peer_stream, status)
+    }
+
+    #[tokio::test]
+    async fn state_follows_ihus_and_death() {
+        let (peer, _remote, mut dead_peer_stream, status) = dummy_peer();
+        assert_eq!(peer.state(), PeerState::Handshaking);
+
+        // A connection can only be degraded once it was alive.
+        peer.degraded();
+        assert_eq!(peer.state(), PeerState::Handshaking);
+
+        peer.ihu_received(tokio::time::Instant::now());
+        assert_eq!(peer.state(), PeerState::Alive);
+        let (_, alive_since) = status.state();
+
+        peer.degraded();
+        assert_eq!(peer.state(), PeerState::Degraded);
+        peer.ihu_received(tokio::time::Instant::now());
+        assert_eq!(peer.state(), PeerState::Alive);
+        assert!(status.state().1 >= alive_since);
+
+        peer.died(DisconnectReason::Closed);
+        dead_peer_stream.recv().await.expect("Peer reports it died");
+        assert_eq!(status.state().0, PeerState::Dead);
+    }
+
+    #[tokio::test]
+    async fn disconnect_reason_is_recorded() {
+        let (peer, _remote, mut dead_peer_stream, status) = dummy_peer();
+        peer.died(DisconnectReason::HelloTimeout);
+        dead_peer_stream.recv().await.expect("Peer reports it died");
+        assert_eq!(
+            status.disconnect_reason(),
+            Some(DisconnectReason::HelloTimeout)
+        );
+
+        let (_peer, remote, mut dead_peer_stream, status) = dummy_peer();
+        drop(remote);
+        dead_peer_stream.recv().await.expect("Peer reports it died");
+        assert_eq!(
+            status.disconnect_reason(),
+            Some(DisconnectReason::RemoteClosed)
+        );
+    }
 }
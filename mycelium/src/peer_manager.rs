@@ -1,35 +1,57 @@
+use self::inbound_limiter::InboundLimiter;
+pub use self::inbound_limiter::{
+    InboundConnectionLimits, InboundRejected, DEFAULT_MAX_INBOUND_CONNECTIONS_PER_IP,
+    DEFAULT_MAX_INBOUND_HANDSHAKES,
+};
+#[cfg(feature = "private-network")]
+use self::peer_auth::tls_session_binding;
+pub use self::peer_auth::PeeringKey;
+use self::peer_auth::{authenticate, quic_session_binding, AuthenticationError, SessionBinding};
+pub use self::proxy::{ProxyCredentials, Socks5Proxy};
+pub use self::rate_limiter::RateLimit;
+pub(crate) use self::rate_limiter::RateLimiter;
+use crate::access_list::AccessList;
 use crate::connection::Quic;
-use crate::endpoint::{Endpoint, Protocol};
+use crate::crypto::{Cipher, PublicKey};
+use crate::endpoint::{Address, Endpoint, Protocol};
 use crate::metrics::Metrics;
-use crate::peer::{Peer, PeerRef};
+use crate::peer::{ConnectionStatus, Peer, PeerConfig, PeerRef};
+pub use crate::peer::{DisconnectReason, PeerState};
 use crate::router::Router;
 use crate::router_id::RouterId;
+use crate::subnet::Subnet;
 use futures::stream::FuturesUnordered;
 use futures::{FutureExt, StreamExt};
 #[cfg(feature = "private-network")]
 use openssl::ssl::{Ssl, SslAcceptor, SslConnector, SslMethod};
-use quinn::crypto::rustls::QuicClientConfig;
+use quinn::crypto::rustls::{NoInitialCipherSuite, QuicClientConfig};
 use quinn::{MtuDiscoveryConfig, ServerConfig, TransportConfig};
 use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::future::Future;
 use std::io;
-use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
 #[cfg(target_os = "linux")]
 use std::os::fd::AsFd;
 #[cfg(feature = "private-network")]
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::net::TcpStream;
-use tokio::net::{TcpListener, UdpSocket};
+use tokio::net::{TcpListener, TcpSocket, UdpSocket};
 use tokio::task::AbortHandle;
 use tokio::time::MissedTickBehavior;
 use tracing::{debug, error, info, trace, warn};
 
+mod inbound_limiter;
+mod peer_auth;
+mod proxy;
+mod rate_limiter;
+
 /// Magic bytes to identify a multicast UDP packet used in link local peer discovery.
 const MYCELIUM_MULTICAST_DISCOVERY_MAGIC: &[u8; 8] = b"mycelium";
 /// Size of a peer discovery beacon.
@@ -43,6 +65,29 @@ const PEER_CONNECT_INTERVAL: Duration = Duration::from_secs(5);
 /// The maximum amount of successive failures allowed when connecting to a local discovered peer,
 /// before it is forgotten.
 const MAX_FAILED_LOCAL_PEER_CONNECTION_ATTEMPTS: usize = 3;
+/// The time to wait for a connection attempt to an address of a peer to complete, before also
+/// trying the next address, as recommended in RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+/// The time to wait before reconnecting to a peer after the first failed connection attempt. This
+/// is doubled for every successive failure, up to [`MAX_RECONNECT_BACKOFF`].
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+/// The maximum time to wait before reconnecting to a peer after a failed connection attempt.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(300);
+/// Default time to wait for a connection to a peer to be established.
+pub const DEFAULT_DIAL_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time to wait for the handshake on a new connection to a peer to complete.
+pub const DEFAULT_DIAL_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum MTU which can be configured for the link to a peer, this is the minimum MTU of IPv6.
+pub const MIN_LINK_MTU: u16 = 1280;
+/// Time an inbound connection has to finish its handshake, before it is closed.
+const INBOUND_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a remote has to prove knowledge of the peering key, before the connection is closed.
+const PEER_AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a TCP connection to a peer must be idle before keepalive probes are sent.
+const TCP_KEEPALIVE_TIME: Duration = Duration::from_secs(30);
+/// Time between TCP keepalive probes, if the previous one is not answered. The OS closes the
+/// connection once a number of probes are not answered.
+const TCP_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
 
 /// The PeerManager creates new peers by connecting to configured addresses, and setting up the
 /// connection. Once a connection is established, the created [`Peer`] is handed over to the
@@ -55,6 +100,7 @@ pub struct PeerManager<M> {
 
 /// Details how the PeerManager learned about a remote.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum PeerType {
     /// Statically configured peer.
@@ -76,21 +122,92 @@ struct PeerInfo {
     /// Amount of failed times we tried to connect to this peer. This is reset after a successful
     /// connection.
     connection_attempts: usize,
+    /// Earliest time at which we try to connect to this peer again, if the last attempt failed.
+    next_connection_attempt: Option<tokio::time::Instant>,
     /// Keep track of the amount of bytes we've sent to and received from this peer.
     con_traffic: ConnectionTraffic,
+    /// The [`PublicKey`] announced in the discovery beacon, if this peer was found through link
+    /// local discovery.
+    discovered_public_key: Option<PublicKey>,
+    /// The connection which is kept if the connection to this peer was closed because it is a
+    /// duplicate. We don't reconnect as long as that connection is alive.
+    duplicate_of: PeerRef,
+    /// Configured MTU of the link to this peer, if it is limited.
+    mtu: Option<u16>,
+    /// Configured priority of this peer as next hop.
+    priority: u8,
+    /// Local address of the listener on which the connection was accepted, for inbound peers.
+    listen_addr: Option<SocketAddr>,
+}
+
+/// Timeouts applied when connecting to a peer. If a timeout expires, the connection attempt fails,
+/// and the connection is retried after the usual backoff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DialTimeouts {
+    /// Maximum time to establish the connection. For Quic, this includes the Quic handshake. If a
+    /// peer has multiple addresses, this applies to the attempts on all addresses together.
+    pub connect: Duration,
+    /// Maximum time to set up a connection once it is established. This covers the TLS handshake
+    /// in a private network, opening the Quic stream, and proving knowledge of the peering key.
+    pub handshake: Duration,
+}
+
+impl Default for DialTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: DEFAULT_DIAL_CONNECT_TIMEOUT,
+            handshake: DEFAULT_DIAL_HANDSHAKE_TIMEOUT,
+        }
+    }
 }
 
-/// Counters for the amount of traffic written to and received from a [`Peer`].
-#[derive(Debug, Clone)]
+/// Counters for the reasons connection attempts to a [`Peer`] failed.
+#[derive(Debug, Default)]
+struct DialFailures {
+    /// Attempts in which the connection was not established in time.
+    connect_timeouts: AtomicU64,
+    /// Attempts in which the handshake did not complete in time.
+    handshake_timeouts: AtomicU64,
+    /// Attempts in which the remote refused the connection.
+    refused: AtomicU64,
+}
+
+/// Counters for the amount of traffic written to and received from a [`Peer`], the limits on it,
+/// and the status of the connections to it.
+#[derive(Debug, Clone, Default)]
 struct ConnectionTraffic {
     /// Amount of bytes transmitted to this peer.
     tx_bytes: Arc<AtomicU64>,
     /// Amount of bytes received from this peer.
     rx_bytes: Arc<AtomicU64>,
+    /// Bandwidth limits of the data exchanged with this peer.
+    rate_limiter: Arc<RateLimiter>,
+    /// The state of the connection to this peer, and the reason the last connection was closed.
+    status: Arc<ConnectionStatus>,
+    /// The reasons connection attempts to this peer failed.
+    dial_failures: Arc<DialFailures>,
+    /// Amount of data packets received from this peer which failed decryption.
+    decryption_failures: Arc<AtomicU64>,
+}
+
+impl ConnectionTraffic {
+    /// Get the [`PeerConfig`] for a new [`Peer`] on a connection with this `ConnectionTraffic`,
+    /// from the given config of the router.
+    fn peer_config(&self, config: PeerConfig) -> PeerConfig {
+        PeerConfig {
+            bytes_written: self.tx_bytes.clone(),
+            bytes_read: self.rx_bytes.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            status: self.status.clone(),
+            decryption_failures: self.decryption_failures.clone(),
+            ..config
+        }
+    }
 }
 
 /// General state about a connection to a [`Peer`].
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum ConnectionState {
     /// There is a working connection to the [`Peer`].
@@ -103,6 +220,7 @@ pub enum ConnectionState {
 
 /// Identification and information/statistics for a specific [`Peer`]
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PeerStats {
     /// The endpoint of the [`Peer`].
@@ -116,9 +234,181 @@ pub struct PeerStats {
     pub tx_bytes: u64,
     /// Amount of bytes received from this [`Peer`].
     pub rx_bytes: u64,
+    /// Identifier of the current connection to this [`Peer`], if it is connected. This includes
+    /// the transport used for the connection.
+    #[serde(default)]
+    pub connection_identifier: Option<String>,
+    /// Overlay address of the node at the other end of the connection, if it is connected and has
+    /// announced its subnet. Unlike the connection identifier, this does not change when the
+    /// [`Peer`] reconnects.
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
+    pub overlay_address: Option<Ipv6Addr>,
+    /// Cost of the link to this [`Peer`], if it is connected. This is derived from the measured
+    /// round trip time of the link, and the loss of Hellos on the link.
+    #[serde(default)]
+    pub link_cost: Option<u16>,
+    /// Fraction of the recent Hellos sent by this [`Peer`] which was received, if it is
+    /// connected.
+    #[serde(default)]
+    pub hello_reception: Option<f64>,
+    /// Amount of successive failed attempts to connect to this [`Peer`]. This is reset once a
+    /// connection is established.
+    #[serde(default)]
+    pub connection_attempts: usize,
+    /// Amount of attempts to connect to this [`Peer`] which failed because the connection was not
+    /// established in time.
+    #[serde(default)]
+    pub connect_timeouts: u64,
+    /// Amount of attempts to connect to this [`Peer`] which failed because the handshake on the
+    /// connection did not complete in time.
+    #[serde(default)]
+    pub handshake_timeouts: u64,
+    /// Amount of attempts to connect to this [`Peer`] which failed because it refused the
+    /// connection.
+    #[serde(default)]
+    pub connections_refused: u64,
+    /// Amount of data packets received from this [`Peer`] which failed decryption at this node,
+    /// because they were corrupted, encrypted with the wrong key, or forged. A sudden increase
+    /// can indicate a misbehaving peer, or an attack.
+    #[serde(default)]
+    pub decryption_failures: u64,
+    /// Time of the next attempt to connect to this [`Peer`], as seconds since the UNIX epoch, if
+    /// the last connection attempt failed.
+    #[serde(default)]
+    pub next_connection_attempt: Option<u64>,
+    /// Time at which the last IHU was received from this [`Peer`], as seconds since the UNIX
+    /// epoch, if it is connected.
+    #[serde(default)]
+    pub last_seen: Option<u64>,
+    /// Amount of selected routes which use this [`Peer`] as next hop.
+    #[serde(default)]
+    pub selected_routes: usize,
+    /// Configured bandwidth limits of the data exchanged with this [`Peer`].
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    /// Current rate of data sent to this [`Peer`], in bytes per second.
+    #[serde(default)]
+    pub tx_rate: u64,
+    /// Current rate of data received from this [`Peer`], in bytes per second.
+    #[serde(default)]
+    pub rx_rate: u64,
+    /// Amount of bytes of data which were not sent to this [`Peer`] because they exceeded the
+    /// rate limit.
+    #[serde(default)]
+    pub tx_dropped_bytes: u64,
+    /// Amount of bytes of data received from this [`Peer`] which were dropped because they
+    /// exceeded the rate limit.
+    #[serde(default)]
+    pub rx_dropped_bytes: u64,
+    /// The reason the last connection to this [`Peer`] was closed, if it was ever closed.
+    #[serde(default)]
+    pub last_disconnect_reason: Option<DisconnectReason>,
+    /// The current [`PeerState`] of the connection to this [`Peer`].
+    #[serde(default)]
+    pub state: PeerState,
+    /// Time of the last transition of the [`state`](Self::state), as seconds since the UNIX
+    /// epoch.
+    #[serde(default)]
+    pub state_changed: u64,
+    /// [`Cipher`] used to encrypt data for the node at the other end of the connection, if it is
+    /// connected and this node received data from it. Data is encrypted end to end, so this is
+    /// only used for data of which this node is the origin, and that node the destination.
+    #[serde(default)]
+    pub cipher: Option<Cipher>,
+    /// Configured MTU of the link to this [`Peer`], i.e. the maximum size of data packets sent
+    /// to it, if it is limited.
+    #[serde(default)]
+    pub mtu: Option<u16>,
+    /// Configured priority of this [`Peer`] as next hop. Routes through peers with a higher
+    /// priority are preferred over routes with a slightly lower metric, see
+    /// [`MetricSelector`](crate::route_selection::MetricSelector).
+    #[serde(default)]
+    pub priority: u8,
+    /// Local address of the listener on which the connection of this inbound [`Peer`] was
+    /// accepted. A node can listen on multiple addresses, e.g. on different networks.
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
+    pub listen_address: Option<SocketAddr>,
 }
 
 impl PeerInfo {
+    /// Collect the [`PeerStats`] of this peer. `selected_routes` is the amount of selected routes
+    /// per connection identifier, and `ciphers` the [`Cipher`] negotiated with remote nodes, by
+    /// subnet.
+    fn stats(
+        &self,
+        endpoint: &Endpoint,
+        selected_routes: &HashMap<String, usize>,
+        ciphers: &HashMap<Subnet, Cipher>,
+    ) -> PeerStats {
+        let connection_state = if self.connecting {
+            ConnectionState::Connecting
+        } else if self.pr.alive() {
+            ConnectionState::Alive
+        } else {
+            ConnectionState::Dead
+        };
+        let peer = self.pr.upgrade().filter(|peer| peer.alive());
+        let (state, state_changed) = self.con_traffic.status.state();
+        let public_key = peer.as_ref().and_then(|peer| peer.public_key());
+        let overlay_address = public_key.map(|pk| pk.address());
+        PeerStats {
+            endpoint: endpoint.clone(),
+            pt: self.pt.clone(),
+            connection_state,
+            tx_bytes: self.written(),
+            rx_bytes: self.read(),
+            connection_identifier: peer
+                .as_ref()
+                .map(|peer| peer.connection_identifier().clone()),
+            overlay_address,
+            link_cost: peer.as_ref().map(|peer| peer.link_cost()),
+            hello_reception: peer.as_ref().map(|peer| peer.hello_reception()),
+            connection_attempts: self.connection_attempts,
+            connect_timeouts: self
+                .con_traffic
+                .dial_failures
+                .connect_timeouts
+                .load(Ordering::Relaxed),
+            handshake_timeouts: self
+                .con_traffic
+                .dial_failures
+                .handshake_timeouts
+                .load(Ordering::Relaxed),
+            connections_refused: self
+                .con_traffic
+                .dial_failures
+                .refused
+                .load(Ordering::Relaxed),
+            decryption_failures: self.con_traffic.decryption_failures.load(Ordering::Relaxed),
+            next_connection_attempt: self.next_connection_attempt.map(|next| {
+                let remaining = next.saturating_duration_since(tokio::time::Instant::now());
+                unix_timestamp(SystemTime::now() + remaining)
+            }),
+            last_seen: peer.as_ref().map(|peer| {
+                unix_timestamp(SystemTime::now() - peer.time_last_received_ihu().elapsed())
+            }),
+            selected_routes: peer
+                .as_ref()
+                .and_then(|peer| selected_routes.get(peer.connection_identifier()))
+                .copied()
+                .unwrap_or_default(),
+            rate_limit: self.con_traffic.rate_limiter.limit(),
+            tx_rate: self.con_traffic.rate_limiter.tx_rate(),
+            rx_rate: self.con_traffic.rate_limiter.rx_rate(),
+            tx_dropped_bytes: self.con_traffic.rate_limiter.tx_dropped(),
+            rx_dropped_bytes: self.con_traffic.rate_limiter.rx_dropped(),
+            last_disconnect_reason: self.con_traffic.status.disconnect_reason(),
+            state,
+            state_changed: unix_timestamp(state_changed),
+            cipher: public_key.and_then(|pk| ciphers.get(&pk.subnet()).copied()),
+            mtu: self.mtu,
+            priority: self.priority,
+            listen_address: self.listen_addr,
+        }
+    }
+
     /// Return the amount of bytes read from this peer.
     #[inline]
     fn read(&self) -> u64 {
@@ -128,7 +418,7 @@ impl PeerInfo {
     /// Return the amount of bytes written to this peer.
     #[inline]
     fn written(&self) -> u64 {
-        self.con_traffic.rx_bytes.load(Ordering::Relaxed)
+        self.con_traffic.tx_bytes.load(Ordering::Relaxed)
     }
 }
 
@@ -140,38 +430,119 @@ pub struct PeerExists;
 #[derive(Debug)]
 pub struct PeerNotFound;
 
+/// Error returned when the [`Endpoint`] of a peer can't be replaced.
+#[derive(Debug)]
+pub enum ReplacePeerError {
+    /// The peer to replace is not known.
+    NotFound(PeerNotFound),
+    /// A different peer identified by the new [`Endpoint`] already exists.
+    Exists(PeerExists),
+}
+
+/// Error returned when the MTU of the link to a peer can't be set.
+#[derive(Debug)]
+pub enum SetMtuError {
+    /// The peer is not known.
+    NotFound(PeerNotFound),
+    /// The MTU is smaller than [`MIN_LINK_MTU`].
+    TooSmall,
+}
+
+/// Error returned when probing an [`Endpoint`] fails.
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The node can't connect to endpoints using the protocol of the probed endpoint, e.g. Quic
+    /// while Quic is disabled.
+    UnsupportedProtocol(Protocol),
+    /// The address of the endpoint could not be resolved.
+    Resolve(io::Error),
+    /// None of the resolved addresses could be connected to.
+    Connect(String),
+    /// No connection was established within the allowed time.
+    Timeout,
+}
+
 /// PSK used to set up a shared network. Currently 32 bytes though this might change in the future.
 pub type PrivateNetworkKey = [u8; 32];
 
+/// Configuration of a [`PeerManager`], see [`PeerManager::new`].
+pub struct PeerManagerConfig<M> {
+    /// Statically configured peers.
+    pub static_peers: Vec<Endpoint>,
+    /// Addresses to accept inbound tcp connections on. If this is empty, no inbound tcp
+    /// connections are accepted.
+    pub tcp_listen_addrs: Vec<SocketAddr>,
+    /// Port to accept Quic connections on, if any. Quic is not used in a private network.
+    pub quic_listen_port: Option<u16>,
+    /// Udp port for local peer discovery. If this is not set, peers are not discovered.
+    pub peer_discovery_port: Option<u16>,
+    /// Name and key of the private network, if the node is part of one.
+    pub private_network_config: Option<(String, PrivateNetworkKey)>,
+    /// Key remotes must prove knowledge of before they are accepted as peer, if any.
+    pub peering_key: Option<PeeringKey>,
+    /// Records metrics of the peer manager.
+    pub metrics: M,
+    /// Mark set on all packets sent on the underlying network, if any.
+    pub firewall_mark: Option<u32>,
+    /// Limits applied to inbound connections.
+    pub inbound_connection_limits: InboundConnectionLimits,
+    /// Timeouts applied when connecting to peers.
+    pub dial_timeouts: DialTimeouts,
+    /// Proxy through which outbound connections to peers are made, if any.
+    pub proxy: Option<Socks5Proxy>,
+    /// Underlay IPs which are allowed to connect to the node.
+    pub access_list: AccessList,
+}
+
 struct Inner<M> {
     /// Router is unfortunately wrapped in a Mutex, because router is not Sync.
     router: Mutex<Router<M>>,
     peers: Mutex<HashMap<Endpoint, PeerInfo>>,
-    /// Listen port for new peer connections
-    tcp_listen_port: u16,
+    /// Addresses the listeners for inbound tcp connections are bound to. This is empty if inbound
+    /// tcp connections are not accepted.
+    tcp_listen_addrs: Vec<SocketAddr>,
     quic_socket: Option<quinn::Endpoint>,
     /// Identity and name of a private network, if one exists
     private_network_config: Option<(String, [u8; 32])>,
+    /// Key remotes must prove knowledge of before they are accepted as peer, if any.
+    peering_key: Option<PeeringKey>,
     metrics: M,
     firewall_mark: Option<u32>,
+    /// Limits applied to inbound connections.
+    inbound_limiter: InboundLimiter,
+    /// Timeouts applied when connecting to peers.
+    dial_timeouts: DialTimeouts,
+    /// Proxy through which outbound connections to peers are made, if any.
+    proxy: Option<Socks5Proxy>,
+    /// Underlay IPs which are allowed to connect to the node.
+    access_list: RwLock<AccessList>,
 }
 
 impl<M> PeerManager<M>
 where
     M: Metrics + Clone + Send + Sync + 'static,
 {
-    #[allow(clippy::too_many_arguments)]
+    /// Create a new `PeerManager` with the given [`PeerManagerConfig`], which adds the peers it
+    /// connects to to `router`. Listeners are bound right away, and connecting to peers starts in
+    /// the background.
     pub fn new(
         router: Router<M>,
-        static_peers_sockets: Vec<Endpoint>,
-        tcp_listen_port: u16,
-        quic_listen_port: Option<u16>,
-        peer_discovery_port: u16,
-        disable_peer_discovery: bool,
-        private_network_config: Option<(String, PrivateNetworkKey)>,
-        metrics: M,
-        firewall_mark: Option<u32>,
+        config: PeerManagerConfig<M>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let PeerManagerConfig {
+            static_peers: static_peers_sockets,
+            tcp_listen_addrs,
+            quic_listen_port,
+            peer_discovery_port,
+            private_network_config,
+            peering_key,
+            metrics,
+            firewall_mark,
+            inbound_connection_limits,
+            dial_timeouts,
+            proxy,
+            access_list,
+        } = config;
         let is_private_net = private_network_config.is_some();
 
         // Currently we don't support Quic when a private network is used.
@@ -189,6 +560,17 @@ where
             None
         };
 
+        // Bind the tcp listeners right away, like the quic socket, so failing to bind is reported
+        // to the caller, and the effective addresses are known if port 0 is used.
+        let tcp_listeners = tcp_listen_addrs
+            .into_iter()
+            .map(|addr| bind_tcp_listener(addr, firewall_mark))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tcp_listen_addrs = tcp_listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<Result<Vec<_>, _>>()?;
+
         // Set the initially configured peer count in metrics.
         metrics.peer_manager_known_peers(static_peers_sockets.len());
 
@@ -209,29 +591,42 @@ where
                                     connecting: false,
                                     pr: PeerRef::new(),
                                     connection_attempts: 0,
-                                    con_traffic: ConnectionTraffic {
-                                        tx_bytes: Arc::new(AtomicU64::new(0)),
-                                        rx_bytes: Arc::new(AtomicU64::new(0)),
-                                    },
+                                    next_connection_attempt: None,
+                                    con_traffic: ConnectionTraffic::default(),
+                                    discovered_public_key: None,
+                                    duplicate_of: PeerRef::new(),
+                                    mtu: None,
+                                    priority: 0,
+                                    listen_addr: None,
                                 },
                             )
                         })
                         .collect(),
                 ),
-                tcp_listen_port,
+                tcp_listen_addrs,
                 quic_socket,
                 private_network_config,
+                peering_key,
                 metrics,
                 firewall_mark,
+                inbound_limiter: InboundLimiter::new(inbound_connection_limits),
+                dial_timeouts,
+                proxy,
+                access_list: RwLock::new(access_list),
             }),
             abort_handles: vec![],
         };
 
         // Start listeners for inbound connections.
-        // Start the tcp listener, in case we are running a private network the tcp listener will
-        // actually be a tls listener.
-        let handle = tokio::spawn(peer_manager.inner.clone().tcp_listener());
-        peer_manager.abort_handles.push(handle.abort_handle());
+        // Start the tcp listeners, in case we are running a private network the tcp listeners will
+        // actually be tls listeners.
+        if tcp_listeners.is_empty() {
+            info!("Not accepting inbound tcp connections");
+        }
+        for listener in tcp_listeners {
+            let handle = tokio::spawn(peer_manager.inner.clone().tcp_listener(listener));
+            peer_manager.abort_handles.push(handle.abort_handle());
+        }
         if is_private_net {
             info!("Enabled private network mode");
         } else {
@@ -246,7 +641,7 @@ where
 
         // Discover local peers, this does not actually connect to them. That is handle by the
         // connect_to_peers task.
-        if !disable_peer_discovery {
+        if let Some(peer_discovery_port) = peer_discovery_port {
             let handle = tokio::spawn(
                 peer_manager
                     .inner
@@ -278,10 +673,13 @@ where
                 connecting: false,
                 pr: PeerRef::new(),
                 connection_attempts: 0,
-                con_traffic: ConnectionTraffic {
-                    tx_bytes: Arc::new(AtomicU64::new(0)),
-                    rx_bytes: Arc::new(AtomicU64::new(0)),
-                },
+                next_connection_attempt: None,
+                con_traffic: ConnectionTraffic::default(),
+                discovered_public_key: None,
+                duplicate_of: PeerRef::new(),
+                mtu: None,
+                priority: 0,
+                listen_addr: None,
             },
         );
 
@@ -290,7 +688,8 @@ where
 
     /// Delete a peer from the system.
     ///
-    /// The peer will be disconnected if it is currently connected.
+    /// The peer will be disconnected if it is currently connected. Before disconnecting, all
+    /// routes advertised to the peer are retracted.
     ///
     /// # Errors
     ///
@@ -299,33 +698,258 @@ where
         let mut peer_map = self.inner.peers.lock().unwrap();
         peer_map.remove(endpoint).ok_or(PeerNotFound).map(|pi| {
             // Make sure we kill the peer connection if one exists
-            if let Some(peer) = pi.pr.upgrade() {
-                peer.died();
-            }
+            self.inner.close_connection(&pi);
         })
     }
 
+    /// Get the [`AccessList`] which is applied to the IPs of inbound peers.
+    pub fn access_list(&self) -> AccessList {
+        self.inner
+            .access_list
+            .read()
+            .expect("Access list lock is not poisoned; qed")
+            .clone()
+    }
+
+    /// Replace the [`AccessList`] which is applied to the IPs of inbound peers. Inbound peers
+    /// which are no longer allowed are removed, and their connections are closed.
+    pub fn set_access_list(&self, access_list: AccessList) {
+        let mut peer_map = self.inner.peers.lock().unwrap();
+        // Swap the list first, so connections which are set up while the peer map is updated are
+        // checked against the new list. This is done while holding the peers lock, since inbound
+        // peers check the list again under that lock before they are added.
+        *self
+            .inner
+            .access_list
+            .write()
+            .expect("Access list lock is not poisoned; qed") = access_list;
+
+        let access_list = self
+            .inner
+            .access_list
+            .read()
+            .expect("Access list lock is not poisoned; qed");
+        peer_map.retain(|endpoint, pi| {
+            let allowed = pi.pt != PeerType::Inbound
+                || endpoint
+                    .address()
+                    .socket_addr()
+                    .is_none_or(|sa| access_list.allows_ip(sa.ip()));
+            if !allowed {
+                info!("Disconnecting inbound peer {endpoint} which is no longer allowed");
+                self.inner.close_connection(pi);
+            }
+            allowed
+        });
+        self.inner.metrics.peer_manager_known_peers(peer_map.len());
+    }
+
+    /// Shut down the `PeerManager`. No new connections are set up anymore, and the connections to
+    /// all peers are closed, after sending them a wildcard retraction for all routes advertised to
+    /// them. This returns once the connections are closed, so the retractions had a chance to be
+    /// sent.
+    pub async fn shutdown(&self) {
+        for ah in &self.abort_handles {
+            ah.abort();
+        }
+
+        let peers = self
+            .inner
+            .peers
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|pi| {
+                let peer = pi.pr.upgrade()?;
+                self.inner.close_connection(pi);
+                Some(peer)
+            })
+            .collect::<Vec<_>>();
+        futures::future::join_all(peers.iter().map(Peer::closed)).await;
+    }
+
+    /// Replace the [`Endpoint`] of an existing peer.
+    ///
+    /// The type of the peer and its traffic stats are preserved. If the peer is currently
+    /// connected, the connection is closed, and a new connection to the new [`Endpoint`] is
+    /// established by the reconnect loop. Replacing a peer with its current [`Endpoint`] does
+    /// nothing, so its connection is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no peer identified by the `old` [`Endpoint`], or if a
+    /// different peer identified by the `new` [`Endpoint`] already exists.
+    pub fn replace_peer(&self, old: &Endpoint, new: Endpoint) -> Result<(), ReplacePeerError> {
+        let mut peer_map = self.inner.peers.lock().unwrap();
+        if !peer_map.contains_key(old) {
+            return Err(ReplacePeerError::NotFound(PeerNotFound));
+        }
+        if *old == new {
+            return Ok(());
+        }
+        if peer_map.contains_key(&new) {
+            return Err(ReplacePeerError::Exists(PeerExists));
+        }
+
+        let pi = peer_map
+            .remove(old)
+            .expect("Peer is present in the map as we checked this while holding the lock; qed");
+        // The existing connection (if any) is to the old endpoint, so close it.
+        self.inner.close_connection(&pi);
+        peer_map.insert(
+            new,
+            PeerInfo {
+                pt: pi.pt,
+                connecting: false,
+                pr: PeerRef::new(),
+                connection_attempts: 0,
+                next_connection_attempt: None,
+                con_traffic: pi.con_traffic,
+                discovered_public_key: pi.discovered_public_key,
+                duplicate_of: PeerRef::new(),
+                mtu: pi.mtu,
+                priority: pi.priority,
+                listen_addr: pi.listen_addr,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Set the bandwidth limits of the data exchanged with a peer. The limits apply immediately,
+    /// and are kept if the peer reconnects.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no peer identified by the given [`Endpoint`].
+    pub fn set_rate_limit(
+        &self,
+        endpoint: &Endpoint,
+        limit: RateLimit,
+    ) -> Result<(), PeerNotFound> {
+        let peer_map = self.inner.peers.lock().unwrap();
+        let pi = peer_map.get(endpoint).ok_or(PeerNotFound)?;
+        pi.con_traffic.rate_limiter.set_limit(limit);
+
+        Ok(())
+    }
+
+    /// Set the MTU of the link to a peer, i.e. the maximum size of data packets sent to it, or
+    /// remove the limit if [`None`] is given. Data packets which exceed the MTU are dropped, and
+    /// the node which sent them is notified with an ICMPv6 Packet Too Big message, so it can send
+    /// smaller packets. The MTU applies immediately, and is kept if the peer reconnects.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no peer identified by the given [`Endpoint`], or if the MTU
+    /// is smaller than [`MIN_LINK_MTU`].
+    pub fn set_mtu(&self, endpoint: &Endpoint, mtu: Option<u16>) -> Result<(), SetMtuError> {
+        if mtu.is_some_and(|mtu| mtu < MIN_LINK_MTU) {
+            return Err(SetMtuError::TooSmall);
+        }
+
+        let mut peer_map = self.inner.peers.lock().unwrap();
+        let pi = peer_map
+            .get_mut(endpoint)
+            .ok_or(SetMtuError::NotFound(PeerNotFound))?;
+        pi.mtu = mtu;
+        if let Some(peer) = pi.pr.upgrade() {
+            peer.set_mtu(mtu);
+        }
+
+        Ok(())
+    }
+
+    /// Set the priority of a peer as next hop. Routes through peers with a higher priority are
+    /// preferred over routes with a slightly lower metric, see
+    /// [`MetricSelector`](crate::route_selection::MetricSelector). The routes are selected again
+    /// right away, and the priority is kept if the peer reconnects.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no peer identified by the given [`Endpoint`].
+    pub fn set_priority(&self, endpoint: &Endpoint, priority: u8) -> Result<(), PeerNotFound> {
+        let peer = {
+            let mut peer_map = self.inner.peers.lock().unwrap();
+            let pi = peer_map.get_mut(endpoint).ok_or(PeerNotFound)?;
+            pi.priority = priority;
+            pi.pr.upgrade()
+        };
+        if let Some(peer) = peer {
+            peer.set_priority(priority);
+            self.inner.router.lock().unwrap().reselect_routes();
+        }
+
+        Ok(())
+    }
+
+    /// Send all selected routes to the peer identified by an [`Endpoint`] right away, instead of
+    /// waiting for the periodic route propagation. This fails if the peer is not connected.
+    pub fn dump_routes(&self, endpoint: &Endpoint) -> Result<(), PeerNotFound> {
+        let peer = self
+            .inner
+            .peers
+            .lock()
+            .unwrap()
+            .get(endpoint)
+            .and_then(|pi| pi.pr.upgrade())
+            .filter(|peer| peer.alive())
+            .ok_or(PeerNotFound)?;
+        self.inner.router.lock().unwrap().dump_routes_to_peer(&peer);
+
+        Ok(())
+    }
+
+    /// Check if an [`Endpoint`] is reachable, without adding it as a peer. This opens a
+    /// connection to the endpoint, which is closed again immediately once it is established. On
+    /// success, the time it took to establish the connection is returned, which is roughly a
+    /// single round trip for both Tcp and Quic. For Tls, only the Tcp connection is established,
+    /// as completing the handshake would register a peer on the remote.
+    ///
+    /// The returned future does not borrow the `PeerManager`, and gives up after `timeout`.
+    /// Dropping it cancels the connection attempt.
+    pub fn probe(
+        &self,
+        endpoint: Endpoint,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Duration, ProbeError>> + Send + 'static {
+        let inner = self.inner.clone();
+        async move {
+            tokio::time::timeout(timeout, inner.probe(endpoint))
+                .await
+                .unwrap_or(Err(ProbeError::Timeout))
+        }
+    }
+
+    /// The addresses on which inbound tcp connections from peers are accepted. In private network
+    /// mode, these are tls connections.
+    pub fn tcp_listen_addrs(&self) -> &[SocketAddr] {
+        &self.inner.tcp_listen_addrs
+    }
+
     /// Get a view of all known peers and their stats.
     pub fn peers(&self) -> Vec<PeerStats> {
+        let (selected_routes, ciphers) = {
+            let router = self.inner.router.lock().unwrap();
+            (router.selected_routes_per_peer(), router.ciphers())
+        };
         let peer_map = self.inner.peers.lock().unwrap();
-        let mut pi = Vec::with_capacity(peer_map.len());
-        for (endpoint, peer_info) in peer_map.iter() {
-            let connection_state = if peer_info.connecting {
-                ConnectionState::Connecting
-            } else if peer_info.pr.alive() {
-                ConnectionState::Alive
-            } else {
-                ConnectionState::Dead
-            };
-            pi.push(PeerStats {
-                endpoint: *endpoint,
-                pt: peer_info.pt.clone(),
-                connection_state,
-                tx_bytes: peer_info.written(),
-                rx_bytes: peer_info.read(),
-            });
-        }
-        pi
+        peer_map
+            .iter()
+            .map(|(endpoint, peer_info)| peer_info.stats(endpoint, &selected_routes, &ciphers))
+            .collect()
+    }
+
+    /// Get the stats of the peer identified by the given [`Endpoint`], if it is known.
+    pub fn peer(&self, endpoint: &Endpoint) -> Option<PeerStats> {
+        let (selected_routes, ciphers) = {
+            let router = self.inner.router.lock().unwrap();
+            (router.selected_routes_per_peer(), router.ciphers())
+        };
+        let peer_map = self.inner.peers.lock().unwrap();
+        peer_map
+            .get(endpoint)
+            .map(|peer_info| peer_info.stats(endpoint, &selected_routes, &ciphers))
     }
 }
 
@@ -340,8 +964,31 @@ impl<M> Drop for PeerManager<M> {
 
 impl<M> Inner<M>
 where
-    M: Metrics + Clone + Send + 'static,
+    M: Metrics + Clone + Send + Sync + 'static,
 {
+    /// Check if an inbound connection from `remote` is allowed by the [`AccessList`].
+    fn inbound_allowed(&self, remote: SocketAddr) -> bool {
+        let allowed = self
+            .access_list
+            .read()
+            .expect("Access list lock is not poisoned; qed")
+            .allows_ip(remote.ip());
+        if !allowed {
+            debug!("Refusing inbound connection from {remote} which is not allowed");
+            self.metrics.peer_manager_inbound_connection_denied();
+        }
+        allowed
+    }
+
+    /// Close the connection to a peer, if there is one. All routes advertised to the peer are
+    /// retracted first.
+    fn close_connection(&self, pi: &PeerInfo) {
+        if let Some(peer) = pi.pr.upgrade() {
+            self.router.lock().unwrap().retract_routes_to_peer(&peer);
+            peer.died(DisconnectReason::Closed);
+        }
+    }
+
     /// Connect and if needed reconnect to known peers.
     async fn connect_to_peers(self: Arc<Self>) {
         let mut peer_check_interval = tokio::time::interval(PEER_CONNECT_INTERVAL);
@@ -368,10 +1015,15 @@ where
                             // We did find a new Peer, insert into router and keep track of it
                             // Use fully qualified call to aid compiler in type inference.
                             pi.pr = Peer::refer(&peer);
+                            peer.set_mtu(pi.mtu);
+                            peer.set_priority(pi.priority);
                             self.router.lock().unwrap().add_peer_interface(peer);
 
                             // We successfully connected, reset the connection_attempts counter to 0
+                            // and clear the backoff. Adding the peer to the router also requests a
+                            // route table dump, so we resync with the peer.
                             pi.connection_attempts = 0;
+                            pi.next_connection_attempt = None;
                         } else {
                             // Only log an error on the first connection failure, to avoid spamming the logs
                             if pi.connection_attempts == 0 {
@@ -382,7 +1034,11 @@ where
 
                             // Connection failed, add a failed attempt and forget about the peer if
                             // needed.
+                            pi.con_traffic.status.transition(PeerState::Dead);
                             pi.connection_attempts += 1;
+                            let backoff = reconnect_backoff(pi.connection_attempts);
+                            debug!("Retrying connection to {endpoint} in {}s", backoff.as_secs());
+                            pi.next_connection_attempt = Some(tokio::time::Instant::now() + backoff);
                             if pi.pt == PeerType::LinkLocalDiscovery
                                 && pi.connection_attempts >= MAX_FAILED_LOCAL_PEER_CONNECTION_ATTEMPTS {
                                 info!("Forgetting about locally discovered peer {endpoint} after failing to connect to it");
@@ -394,26 +1050,89 @@ where
                 _ = peer_check_interval.tick() => {
                     // Remove dead inbound peers
                     self.peers.lock().unwrap().retain(|_, v| v.pt != PeerType::Inbound || v.pr.alive());
+                    self.close_duplicate_connections();
                     debug!("Looking for dead peers");
+                    let mut traffic = Vec::new();
+                    let now = tokio::time::Instant::now();
                     // check if there is an entry for the peer in the router's peer list
                     for (endpoint, pi) in self.peers.lock().unwrap().iter_mut() {
+                        if pi.pr.upgrade().is_some_and(|peer| peer.alive()) {
+                            traffic.push((endpoint.to_string(), pi.written(), pi.read()));
+                        }
                         if !pi.connecting && !pi.pr.alive() {
                             debug!("Found dead peer {endpoint}");
                             if pi.pt == PeerType::Inbound {
                                 debug!("Refusing to reconnect to inbound peer");
                                 continue
                             }
+                            if pi.duplicate_of.alive() {
+                                trace!("Not reconnecting to {endpoint}, there is another connection to the same node");
+                                continue
+                            }
+                            if pi.next_connection_attempt.is_some_and(|next| next > now) {
+                                trace!("Backing off from reconnecting to {endpoint}");
+                                continue
+                            }
                             // Mark that we are connecting to the peer.
                             pi.connecting = true;
-                            connection_futures.push(self.clone().connect_peer(*endpoint, pi.con_traffic.clone()));
+                            pi.con_traffic.status.transition(PeerState::Connecting);
+                            connection_futures.push(self.clone().connect_peer(endpoint.clone(), pi.con_traffic.clone()));
                             self.metrics.peer_manager_connection_attempted();
                         }
                     }
+                    self.metrics.peer_manager_peer_traffic(&traffic);
                 }
             }
         }
     }
 
+    /// Close redundant connections to the same remote node. The remote node of a connection is
+    /// known once it announced its own subnet.
+    fn close_duplicate_connections(&self) {
+        let mut peers = self.peers.lock().unwrap();
+
+        // Alive connections per remote node, split in outbound and inbound connections.
+        type Connections = (Vec<(Endpoint, Peer)>, Vec<(Endpoint, Peer)>);
+        let mut connections = HashMap::<PublicKey, Connections>::new();
+        for (endpoint, pi) in peers.iter() {
+            let Some(peer) = pi.pr.upgrade().filter(|peer| peer.alive()) else {
+                continue;
+            };
+            let Some(public_key) = peer.public_key() else {
+                continue;
+            };
+            let (outbound, inbound) = connections.entry(public_key).or_default();
+            if pi.pt == PeerType::Inbound {
+                inbound.push((endpoint.clone(), peer));
+            } else {
+                outbound.push((endpoint.clone(), peer));
+            }
+        }
+
+        let node_public_key = self.router.lock().unwrap().node_public_key();
+        for (remote_public_key, (mut outbound, inbound)) in connections {
+            if outbound.len() + inbound.len() < 2 {
+                continue;
+            }
+            // Sort so the same connection is kept every time.
+            outbound.sort_by_cached_key(|(endpoint, _)| endpoint.to_string());
+            let Some(((_, kept), redundant)) =
+                redundant_connections(&node_public_key, &remote_public_key, outbound, inbound)
+            else {
+                continue;
+            };
+            for (endpoint, peer) in redundant {
+                info!("Closing connection to {endpoint}, there is another connection to node {remote_public_key}");
+                self.router.lock().unwrap().retract_routes_to_peer(&peer);
+                peer.died(DisconnectReason::Closed);
+                if let Some(pi) = peers.get_mut(&endpoint) {
+                    pi.duplicate_of = kept.refer();
+                }
+                self.metrics.peer_manager_duplicate_connection_closed();
+            }
+        }
+    }
+
     /// Create a new connection to a remote peer
     async fn connect_peer(
         self: Arc<Self>,
@@ -466,98 +1185,142 @@ where
             None
         };
 
-        match TcpStream::connect(endpoint.address())
-            .map(|result| result.and_then(|socket| set_fw_mark(socket, self.firewall_mark)))
+        let proxy = self.proxy_for(endpoint.address());
+        let addrs = match proxy
+            .map_or(endpoint.address(), |proxy| &proxy.address)
+            .resolve()
             .await
         {
-            Ok(peer_stream) => {
-                debug!("Opened connection to {endpoint}");
-                // Make sure Nagle's algorithm is disabled as it can cause latency spikes.
-                if let Err(e) = peer_stream.set_nodelay(true) {
-                    debug!("Couldn't disable Nagle's algorithm on stream {e}");
+            Ok(addrs) => addrs,
+            Err(e) if proxy.is_some() => {
+                debug!("Couldn't resolve address of proxy to connect to {endpoint}: {e}");
+                return (endpoint, None);
+            }
+            Err(e) => {
+                debug!("Couldn't resolve address of {endpoint}: {e}");
+                return (endpoint, None);
+            }
+        };
+
+        let connect = self.dial_tcp(addrs, endpoint.address(), proxy);
+        let (remote, mut peer_stream) =
+            match tokio::time::timeout(self.dial_timeouts.connect, connect).await {
+                Ok(Ok(con)) => con,
+                Ok(Err(e)) => {
+                    if e.kind() == io::ErrorKind::ConnectionRefused {
+                        ct.dial_failures.refused.fetch_add(1, Ordering::Relaxed);
+                    }
+                    debug!("Couldn't connect to {endpoint}: {e}");
+                    return (endpoint, None);
+                }
+                Err(_) => {
+                    debug!("Connecting to {endpoint} timed out");
+                    ct.dial_failures
+                        .connect_timeouts
+                        .fetch_add(1, Ordering::Relaxed);
                     return (endpoint, None);
                 }
+            };
+        debug!("Opened connection to {endpoint}");
+        ct.status.transition(PeerState::Handshaking);
+
+        let dial_failures = ct.dial_failures.clone();
+        let handshake = async {
+            // Make sure Nagle's algorithm is disabled as it can cause latency spikes.
+            if let Err(e) = peer_stream.set_nodelay(true) {
+                debug!("Couldn't disable Nagle's algorithm on stream {e}");
+                return None;
+            }
+            if let Err(e) = set_keepalive(&peer_stream) {
+                debug!("Couldn't enable TCP keepalive on stream to {endpoint}: {e}");
+            }
 
-                // Scope the MutexGuard, if we don't do this the future won't be Send
-                let (router_data_tx, router_control_tx, dead_peer_sink) = {
-                    let router = self.router.lock().unwrap();
-                    (
-                        router.router_data_tx(),
-                        router.router_control_tx(),
-                        router.dead_peer_sink().clone(),
-                    )
-                };
+            // Scope the MutexGuard, if we don't do this the future won't be Send
+            let peer_config = ct.peer_config(self.router.lock().unwrap().peer_config());
 
-                #[cfg(feature = "private-network")]
-                let res = {
-                    if let Some(connector) = connector {
-                        let ssl = match Ssl::new(connector.context()) {
-                            Ok(ssl) => ssl,
-                            Err(e) => {
-                                debug!("Failed to create SSL object from acceptor after connecting to remote {endpoint}: {e}");
-                                return (endpoint, None);
-                            }
-                        };
-                        let mut ssl_stream = match tokio_openssl::SslStream::new(ssl, peer_stream) {
-                            Ok(ssl_stream) => ssl_stream,
-                            Err(e) => {
-                                debug!("Failed to create TLS stream from tcp connection to {endpoint}: {e}");
-                                return (endpoint, None);
-                            }
-                        };
+            #[cfg(feature = "private-network")]
+            let res = {
+                if let Some(connector) = connector {
+                    let ssl = match Ssl::new(connector.context()) {
+                        Ok(ssl) => ssl,
+                        Err(e) => {
+                            debug!("Failed to create SSL object from acceptor after connecting to remote {endpoint}: {e}");
+                            return None;
+                        }
+                    };
+                    let mut ssl_stream = match tokio_openssl::SslStream::new(ssl, peer_stream) {
+                        Ok(ssl_stream) => ssl_stream,
+                        Err(e) => {
+                            debug!("Failed to create TLS stream from tcp connection to {endpoint}: {e}");
+                            return None;
+                        }
+                    };
 
-                        // Pin here is needed to call `connect`.
-                        let pinned_stream = Pin::new(&mut ssl_stream);
-                        if let Err(e) = pinned_stream.connect().await {
-                            // Error here is likely a misconfigured server.
-                            debug!("Could not initiate TLS stream to {endpoint} {e}");
-                            return (endpoint, None);
+                    // Pin here is needed to call `connect`.
+                    let pinned_stream = Pin::new(&mut ssl_stream);
+                    if let Err(e) = pinned_stream.connect().await {
+                        // Error here is likely a misconfigured server.
+                        debug!("Could not initiate TLS stream to {endpoint} {e}");
+                        return None;
+                    }
+                    debug!("Completed TLS handshake to {endpoint}");
+                    let session = match tls_session_binding(ssl_stream.ssl()) {
+                        Ok(session) => session,
+                        Err(e) => {
+                            debug!(
+                                "Could not bind authentication to TLS session with {endpoint}: {e}"
+                            );
+                            return None;
                         }
-                        debug!("Completed TLS handshake to {endpoint}");
-
-                        Peer::new(
-                            router_data_tx,
-                            router_control_tx,
-                            ssl_stream,
-                            dead_peer_sink,
-                            ct.tx_bytes,
-                            ct.rx_bytes,
-                        )
-                    } else {
-                        Peer::new(
-                            router_data_tx,
-                            router_control_tx,
-                            peer_stream,
-                            dead_peer_sink,
-                            ct.tx_bytes,
-                            ct.rx_bytes,
-                        )
+                    };
+                    if !self
+                        .authenticate_peer(&mut ssl_stream, remote, false, Some(session))
+                        .await
+                    {
+                        return None;
                     }
-                };
 
-                #[cfg(not(feature = "private-network"))]
-                let res = Peer::new(
-                    router_data_tx,
-                    router_control_tx,
-                    peer_stream,
-                    dead_peer_sink,
-                    ct.tx_bytes,
-                    ct.rx_bytes,
-                );
-
-                match res {
-                    Ok(new_peer) => {
-                        info!("Connected to new peer {}", endpoint);
-                        (endpoint, Some(new_peer))
-                    }
-                    Err(e) => {
-                        debug!("Failed to spawn peer {endpoint}: {e}");
-                        (endpoint, None)
+                    Peer::new(peer_config, ssl_stream)
+                } else {
+                    if !self
+                        .authenticate_peer(&mut peer_stream, remote, false, None)
+                        .await
+                    {
+                        return None;
                     }
+                    Peer::new(peer_config, peer_stream)
                 }
+            };
+
+            #[cfg(not(feature = "private-network"))]
+            if !self
+                .authenticate_peer(&mut peer_stream, remote, false, None)
+                .await
+            {
+                return None;
             }
-            Err(e) => {
-                debug!("Couldn't connect to {endpoint}: {e}");
+            #[cfg(not(feature = "private-network"))]
+            let res = Peer::new(peer_config, peer_stream);
+
+            match res {
+                Ok(new_peer) => Some(new_peer),
+                Err(e) => {
+                    debug!("Failed to spawn peer {endpoint}: {e}");
+                    None
+                }
+            }
+        };
+        match tokio::time::timeout(self.dial_timeouts.handshake, handshake).await {
+            Ok(Some(new_peer)) => {
+                info!("Connected to new peer {}", endpoint);
+                (endpoint, Some(new_peer))
+            }
+            Ok(None) => (endpoint, None),
+            Err(_) => {
+                debug!("Handshake with {endpoint} timed out");
+                dial_failures
+                    .handshake_timeouts
+                    .fetch_add(1, Ordering::Relaxed);
                 (endpoint, None)
             }
         }
@@ -574,87 +1337,161 @@ where
             debug!("Attempting to connect to quic peer while quic is disabled");
             return (endpoint, None);
         };
-        let provider = rustls::crypto::CryptoProvider::get_default()
-            .expect("We have a quic socket so there is a crypto provider installed");
-        let qcc = match QuicClientConfig::try_from(
-            rustls::ClientConfig::builder()
-                .dangerous()
-                .with_custom_certificate_verifier(SkipServerVerification::new(provider.clone()))
-                .with_no_client_auth(),
-        ) {
-            Ok(qcc) => qcc,
+        if self.proxy_for(endpoint.address()).is_some() {
+            debug!("Not connecting to quic peer {endpoint}, quic can't be used through the proxy");
+            return (endpoint, None);
+        }
+        let config = match quic_client_config() {
+            Ok(config) => config,
             Err(err) => {
                 debug!("Failed to build quic client config: {err}");
                 return (endpoint, None);
             }
         };
-        let mut config = quinn::ClientConfig::new(Arc::new(qcc));
-        // Todo: tweak transport config
-        let mut transport_config = TransportConfig::default();
-        transport_config.max_concurrent_uni_streams(0_u8.into());
-        // Larger than needed for now, just in case
-        transport_config.max_concurrent_bidi_streams(5_u8.into());
-        // Connection timeout, set to higher than Hello interval to ensure connection does not randomly
-        // time out.
-        transport_config.max_idle_timeout(Some(Duration::from_secs(60).try_into().unwrap()));
-        transport_config.mtu_discovery_config(Some(MtuDiscoveryConfig::default()));
-        transport_config.keep_alive_interval(Some(Duration::from_secs(20)));
-        // we don't use datagrams.
-        transport_config.datagram_receive_buffer_size(None);
-        transport_config.datagram_send_buffer_size(0);
-        config.transport_config(Arc::new(transport_config));
-
-        match quic_socket.connect_with(config, endpoint.address(), "dummy.mycelium") {
-            Ok(connecting) => match connecting.await {
-                Ok(con) => match con.open_bi().await {
-                    Ok((tx, rx)) => {
-                        let q_con = Quic::new(tx, rx, endpoint.address());
-                        let res = {
-                            let router = self.router.lock().unwrap();
-                            let router_data_tx = router.router_data_tx();
-                            let router_control_tx = router.router_control_tx();
-                            let dead_peer_sink = router.dead_peer_sink().clone();
-
-                            Peer::new(
-                                router_data_tx,
-                                router_control_tx,
-                                q_con,
-                                dead_peer_sink,
-                                ct.tx_bytes,
-                                ct.rx_bytes,
-                            )
-                        };
-                        match res {
-                            Ok(new_peer) => {
-                                info!("Connected to new peer {}", endpoint);
-                                (endpoint, Some(new_peer))
-                            }
-                            Err(e) => {
-                                debug!("Failed to spawn peer {endpoint}: {e}");
-                                (endpoint, None)
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        debug!("Couldn't open bidirectional quic stream to {endpoint}: {e}");
-                        (endpoint, None)
-                    }
-                },
+
+        let addrs = match endpoint.address().resolve().await {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                debug!("Couldn't resolve address of {endpoint}: {e}");
+                return (endpoint, None);
+            }
+        };
+
+        let connect = |addr| {
+            let config = config.clone();
+            async move {
+                match quic_socket.connect_with(config, addr, "dummy.mycelium") {
+                    Ok(connecting) => connecting
+                        .await
+                        .map_err(|e| format!("couldn't complete quic connection: {e}")),
+                    Err(e) => Err(format!("couldn't initiate connection: {e}")),
+                }
+            }
+        };
+
+        let (remote, con) =
+            match tokio::time::timeout(self.dial_timeouts.connect, happy_eyeballs(addrs, connect))
+                .await
+            {
+                Ok(Ok(con)) => con,
+                Ok(Err(e)) => {
+                    debug!("Couldn't connect to {endpoint}: {e}");
+                    return (endpoint, None);
+                }
+                Err(_) => {
+                    debug!("Connecting to {endpoint} timed out");
+                    ct.dial_failures
+                        .connect_timeouts
+                        .fetch_add(1, Ordering::Relaxed);
+                    return (endpoint, None);
+                }
+            };
+        ct.status.transition(PeerState::Handshaking);
+
+        let dial_failures = ct.dial_failures.clone();
+        let handshake = async {
+            let (tx, rx) = match con.open_bi().await {
+                Ok(stream) => stream,
                 Err(e) => {
-                    debug!("Couldn't complete quic connection to {endpoint}: {e}");
-                    (endpoint, None)
+                    debug!("Couldn't open bidirectional quic stream to {endpoint}: {e}");
+                    return None;
                 }
-            },
-            Err(e) => {
-                debug!("Couldn't initiate connection to {endpoint}: {e}");
+            };
+            let session = match quic_session_binding(&con) {
+                Ok(session) => session,
+                Err(e) => {
+                    debug!("Could not bind authentication to quic session with {endpoint}: {e}");
+                    return None;
+                }
+            };
+            let mut q_con = Quic::new(tx, rx, remote);
+            if !self
+                .authenticate_peer(&mut q_con, remote, false, Some(session))
+                .await
+            {
+                return None;
+            }
+            let peer_config = ct.peer_config(self.router.lock().unwrap().peer_config());
+            let res = Peer::new(peer_config, q_con);
+            match res {
+                Ok(new_peer) => Some(new_peer),
+                Err(e) => {
+                    debug!("Failed to spawn peer {endpoint}: {e}");
+                    None
+                }
+            }
+        };
+        match tokio::time::timeout(self.dial_timeouts.handshake, handshake).await {
+            Ok(Some(new_peer)) => {
+                info!("Connected to new peer {}", endpoint);
+                (endpoint, Some(new_peer))
+            }
+            Ok(None) => (endpoint, None),
+            Err(_) => {
+                debug!("Handshake with {endpoint} timed out");
+                dial_failures
+                    .handshake_timeouts
+                    .fetch_add(1, Ordering::Relaxed);
                 (endpoint, None)
             }
         }
     }
 
+    /// Open a connection to an [`Endpoint`] and close it again, returning the time it took to
+    /// establish the connection. See [`PeerManager::probe`].
+    async fn probe(self: Arc<Self>, endpoint: Endpoint) -> Result<Duration, ProbeError> {
+        let quic_config = match (endpoint.proto(), &self.quic_socket) {
+            (Protocol::Tls, _) if self.private_network_config.is_none() => {
+                return Err(ProbeError::UnsupportedProtocol(Protocol::Tls))
+            }
+            (Protocol::Quic, None) => return Err(ProbeError::UnsupportedProtocol(Protocol::Quic)),
+            (Protocol::Quic, Some(_)) if self.proxy_for(endpoint.address()).is_some() => {
+                return Err(ProbeError::UnsupportedProtocol(Protocol::Quic))
+            }
+            (Protocol::Quic, Some(_)) => {
+                Some(quic_client_config().map_err(|e| ProbeError::Connect(e.to_string()))?)
+            }
+            _ => None,
+        };
+
+        let proxy = self.proxy_for(endpoint.address());
+        let addrs = proxy
+            .map_or(endpoint.address(), |proxy| &proxy.address)
+            .resolve()
+            .await
+            .map_err(ProbeError::Resolve)?;
+
+        let start = tokio::time::Instant::now();
+        match (&self.quic_socket, quic_config) {
+            (Some(quic_socket), Some(config)) => {
+                let (_, con) = happy_eyeballs(addrs, |addr| {
+                    let config = config.clone();
+                    async move {
+                        quic_socket
+                            .connect_with(config, addr, "dummy.mycelium")
+                            .map_err(|e| format!("couldn't initiate connection: {e}"))?
+                            .await
+                            .map_err(|e| format!("couldn't complete quic connection: {e}"))
+                    }
+                })
+                .await
+                .map_err(ProbeError::Connect)?;
+                let rtt = start.elapsed();
+                con.close(0_u8.into(), b"probe");
+                Ok(rtt)
+            }
+            _ => {
+                self.dial_tcp(addrs, endpoint.address(), proxy)
+                    .await
+                    .map_err(|e| ProbeError::Connect(e.to_string()))?;
+                Ok(start.elapsed())
+            }
+        }
+    }
+
     /// Start listening for new peers on a tcp socket. If a private network is configured, this
     /// will instead listen for incoming tls connections.
-    async fn tcp_listener(self: Arc<Self>) {
+    async fn tcp_listener(self: Arc<Self>, listener: TcpListener) {
         // Setup TLS acceptor for private network, if required.
         #[cfg(feature = "private-network")]
         let acceptor = if let Some((net_name, net_key)) = self.private_network_config.clone() {
@@ -684,19 +1521,42 @@ where
         };
 
         // Take a copy of every channel here first so we avoid lock contention in the loop later.
-        let router_data_tx = self.router.lock().unwrap().router_data_tx();
-        let router_control_tx = self.router.lock().unwrap().router_control_tx();
-        let dead_peer_sink = self.router.lock().unwrap().dead_peer_sink().clone();
+        let peer_config = self.router.lock().unwrap().peer_config();
+        // Recorded for the peers accepted on this listener, as there can be multiple.
+        let listen_addr = listener.local_addr().ok();
 
-        let listener = TcpListener::bind(("::", self.tcp_listen_port))
-            .map(|result| result.and_then(|listener| set_fw_mark(listener, self.firewall_mark)));
+        loop {
+            match listener.accept().await {
+                Ok((mut stream, remote)) => {
+                    // Check the access list first, so denied remotes don't use up the
+                    // connection budget.
+                    if !self.inbound_allowed(remote) {
+                        continue;
+                    }
+                    if let Err(e) = set_keepalive(&stream) {
+                        debug!("Couldn't enable TCP keepalive on stream from {remote}: {e}");
+                    }
+                    let permit = match self.inbound_limiter.admit(remote.ip()) {
+                        Ok(permit) => permit,
+                        Err(e) => {
+                            debug!("Rejecting inbound connection from {remote}: {e}");
+                            self.metrics.peer_manager_inbound_connection_rejected(e);
+                            continue;
+                        }
+                    };
+
+                    let this = self.clone();
+                    let peer_config = peer_config.clone();
+                    #[cfg(feature = "private-network")]
+                    let acceptor = acceptor.clone();
 
-        match listener.await {
-            Ok(listener) => loop {
-                match listener.accept().await {
-                    Ok((stream, remote)) => {
-                        let tx_bytes = Arc::new(AtomicU64::new(0));
-                        let rx_bytes = Arc::new(AtomicU64::new(0));
+                    // Set up the connection in a separate task, so a slow remote can't block
+                    // other remotes from connecting.
+                    tokio::spawn(async move {
+                        // Hold the permit until the peer is set up.
+                        let _permit = permit;
+
+                        let ct = ConnectionTraffic::default();
 
                         #[cfg(feature = "private-network")]
                         let new_peer = if let Some(acceptor) = &acceptor {
@@ -704,67 +1564,84 @@ where
                                 Ok(ssl) => ssl,
                                 Err(e) => {
                                     error!("Failed to create SSL object from acceptor after {remote} connected: {e}");
-                                    continue;
+                                    return;
                                 }
                             };
                             let mut ssl_stream = match tokio_openssl::SslStream::new(ssl, stream) {
                                 Ok(ssl_stream) => ssl_stream,
                                 Err(e) => {
                                     error!("Failed to create TLS stream from tcp connection from {remote}: {e}");
-                                    continue;
+                                    return;
                                 }
                             };
 
                             // Pin here is needed to call `accept`.
                             let pinned_stream = Pin::new(&mut ssl_stream);
-                            if let Err(e) = pinned_stream.accept().await {
-                                // An error at this point generally means the handshake failed,
-                                // client error.
-                                debug!("Could not accept TLS stream from {remote} {e}");
-                                continue;
+                            match tokio::time::timeout(
+                                INBOUND_HANDSHAKE_TIMEOUT,
+                                pinned_stream.accept(),
+                            )
+                            .await
+                            {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => {
+                                    // An error at this point generally means the handshake
+                                    // failed, client error.
+                                    debug!("Could not accept TLS stream from {remote} {e}");
+                                    return;
+                                }
+                                Err(_) => {
+                                    debug!("TLS handshake with {remote} timed out");
+                                    return;
+                                }
                             }
                             debug!("Accepted TLS handshake from {remote}");
+                            let session = match tls_session_binding(ssl_stream.ssl()) {
+                                Ok(session) => session,
+                                Err(e) => {
+                                    debug!("Could not bind authentication to TLS session with {remote}: {e}");
+                                    return;
+                                }
+                            };
+                            if !this
+                                .authenticate_peer(&mut ssl_stream, remote, true, Some(session))
+                                .await
+                            {
+                                return;
+                            }
 
-                            Peer::new(
-                                router_data_tx.clone(),
-                                router_control_tx.clone(),
-                                ssl_stream,
-                                dead_peer_sink.clone(),
-                                tx_bytes.clone(),
-                                rx_bytes.clone(),
-                            )
+                            Peer::new(ct.peer_config(peer_config), ssl_stream)
                         } else {
-                            Peer::new(
-                                router_data_tx.clone(),
-                                router_control_tx.clone(),
-                                stream,
-                                dead_peer_sink.clone(),
-                                tx_bytes.clone(),
-                                rx_bytes.clone(),
-                            )
+                            if !this
+                                .authenticate_peer(&mut stream, remote, true, None)
+                                .await
+                            {
+                                return;
+                            }
+                            Peer::new(ct.peer_config(peer_config), stream)
                         };
 
                         #[cfg(not(feature = "private-network"))]
-                        let new_peer = Peer::new(
-                            router_data_tx.clone(),
-                            router_control_tx.clone(),
-                            stream,
-                            dead_peer_sink.clone(),
-                            tx_bytes.clone(),
-                            rx_bytes.clone(),
-                        );
+                        if !this
+                            .authenticate_peer(&mut stream, remote, true, None)
+                            .await
+                        {
+                            return;
+                        }
+                        #[cfg(not(feature = "private-network"))]
+                        let new_peer = Peer::new(ct.peer_config(peer_config), stream);
 
                         let new_peer = match new_peer {
                             Ok(peer) => peer,
                             Err(e) => {
                                 error!("Failed to spawn peer: {e}");
-                                continue;
+                                return;
                             }
                         };
                         info!("Accepted new inbound peer {}", remote);
-                        self.add_peer(
+                        this.add_peer(
                             Endpoint::new(
-                                if self.private_network_config.is_some() {
+                                if this.private_network_config.is_some() {
                                     Protocol::Tls
                                 } else {
                                     Protocol::Tcp
@@ -772,17 +1649,15 @@ where
                                 remote,
                             ),
                             PeerType::Inbound,
-                            ConnectionTraffic { tx_bytes, rx_bytes },
+                            ct,
                             Some(new_peer),
+                            listen_addr,
                         );
-                    }
-                    Err(e) => {
-                        error!("Error accepting connection: {}", e);
-                    }
+                    });
+                }
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
                 }
-            },
-            Err(e) => {
-                error!("Error starting listener: {}", e);
             }
         }
     }
@@ -796,75 +1671,216 @@ where
         // SAFETY: This is safe because this method only get's called if we have a quic socket.
         let quic_socket = self.quic_socket.as_ref().unwrap();
         // Take a copy of every channel here first so we avoid lock contention in the loop later.
-        let router_data_tx = self.router.lock().unwrap().router_data_tx();
-        let router_control_tx = self.router.lock().unwrap().router_control_tx();
-        let dead_peer_sink = self.router.lock().unwrap().dead_peer_sink().clone();
+        let peer_config = self.router.lock().unwrap().peer_config();
 
         loop {
-            let con = if let Some(con) = quic_socket.accept().await {
-                match con.await {
-                    Ok(con) => con,
-                    Err(e) => {
-                        debug!("Failed to accept quic connection: {e}");
-                        continue;
-                    }
-                }
-            } else {
+            let Some(incoming) = quic_socket.accept().await else {
                 // Con is closed
                 info!("Shutting down closed quic listener");
                 return;
             };
 
-            let q = match con.accept_bi().await {
-                Ok((tx, rx)) => Quic::new(tx, rx, con.remote_address()),
+            let remote = incoming.remote_address();
+            if !self.inbound_allowed(remote) {
+                incoming.refuse();
+                continue;
+            }
+            let permit = match self.inbound_limiter.admit(remote.ip()) {
+                Ok(permit) => permit,
                 Err(e) => {
-                    error!("Failed to accept bidirectional quic stream: {e}");
+                    debug!("Rejecting inbound quic connection from {remote}: {e}");
+                    self.metrics.peer_manager_inbound_connection_rejected(e);
+                    incoming.refuse();
                     continue;
                 }
             };
 
-            let tx_bytes = Arc::new(AtomicU64::new(0));
-            let rx_bytes = Arc::new(AtomicU64::new(0));
-            let new_peer = match Peer::new(
-                router_data_tx.clone(),
-                router_control_tx.clone(),
-                q,
-                dead_peer_sink.clone(),
-                tx_bytes.clone(),
-                rx_bytes.clone(),
-            ) {
-                Ok(peer) => peer,
-                Err(e) => {
-                    error!("Failed to spawn peer: {e}");
-                    continue;
+            let this = self.clone();
+            let peer_config = peer_config.clone();
+
+            // Set up the connection in a separate task, so a slow remote can't block other
+            // remotes from connecting.
+            tokio::spawn(async move {
+                // Hold the permit until the peer is set up.
+                let _permit = permit;
+
+                let handshake = async {
+                    let con = match incoming.await {
+                        Ok(con) => con,
+                        Err(e) => {
+                            debug!("Failed to accept quic connection: {e}");
+                            return None;
+                        }
+                    };
+
+                    match con.accept_bi().await {
+                        Ok((tx, rx)) => Some((Quic::new(tx, rx, con.remote_address()), con)),
+                        Err(e) => {
+                            error!("Failed to accept bidirectional quic stream: {e}");
+                            None
+                        }
+                    }
+                };
+                let (mut q, con) =
+                    match tokio::time::timeout(INBOUND_HANDSHAKE_TIMEOUT, handshake).await {
+                        Ok(Some(res)) => res,
+                        Ok(None) => return,
+                        Err(_) => {
+                            debug!("Quic handshake with {remote} timed out");
+                            return;
+                        }
+                    };
+                let session = match quic_session_binding(&con) {
+                    Ok(session) => session,
+                    Err(e) => {
+                        debug!("Could not bind authentication to quic session with {remote}: {e}");
+                        return;
+                    }
+                };
+                if !this
+                    .authenticate_peer(&mut q, remote, true, Some(session))
+                    .await
+                {
+                    return;
                 }
-            };
-            info!("Accepted new inbound quic peer {}", con.remote_address());
-            self.add_peer(
-                Endpoint::new(Protocol::Quic, con.remote_address()),
-                PeerType::Inbound,
-                ConnectionTraffic { tx_bytes, rx_bytes },
-                Some(new_peer),
-            )
+
+                let ct = ConnectionTraffic::default();
+                let new_peer = match Peer::new(ct.peer_config(peer_config), q) {
+                    Ok(peer) => peer,
+                    Err(e) => {
+                        error!("Failed to spawn peer: {e}");
+                        return;
+                    }
+                };
+                info!("Accepted new inbound quic peer {}", con.remote_address());
+                this.add_peer(
+                    Endpoint::new(Protocol::Quic, con.remote_address()),
+                    PeerType::Inbound,
+                    ct,
+                    Some(new_peer),
+                    this.quic_socket
+                        .as_ref()
+                        .and_then(|socket| socket.local_addr().ok()),
+                )
+            });
         }
     }
 
-    /// Add a new peer identifier we discovered.
+    /// The proxy through which connections to `address` are made, if any. Link local addresses
+    /// are always connected to directly, since a proxy can't reach them.
+    fn proxy_for(&self, address: &Address) -> Option<&Socks5Proxy> {
+        match address.socket_addr() {
+            Some(SocketAddr::V6(addr)) if addr.ip().segments()[0] & 0xffc0 == 0xfe80 => None,
+            _ => self.proxy.as_ref(),
+        }
+    }
+
+    /// Open a tcp connection to one of `addrs`, which are the resolved addresses of `address`, or
+    /// of `proxy` if it is set. In the latter case, the proxy is then asked to connect to
+    /// `address`. The returned address is the one the connection was opened to.
+    async fn dial_tcp(
+        &self,
+        addrs: Vec<SocketAddr>,
+        address: &Address,
+        proxy: Option<&Socks5Proxy>,
+    ) -> io::Result<(SocketAddr, TcpStream)> {
+        let (remote, mut stream) = happy_eyeballs(addrs, |addr| {
+            TcpStream::connect(addr)
+                .map(|result| result.and_then(|socket| set_fw_mark(socket, self.firewall_mark)))
+        })
+        .await?;
+        if let Some(proxy) = proxy {
+            proxy::connect(&mut stream, address, proxy.credentials.as_ref()).await?;
+        }
+
+        Ok((remote, stream))
+    }
+
+    /// Authenticate the remote of a new connection with the peering key, if one is configured.
+    /// The authentication is bound to the `session` of the connection, if it is encrypted.
+    /// Returns false if the remote could not be authenticated, in which case the connection must
+    /// be closed. Inbound remotes which fail to authenticate too often are rejected for a while.
+    async fn authenticate_peer<C>(
+        &self,
+        con: &mut C,
+        remote: SocketAddr,
+        inbound: bool,
+        session: Option<SessionBinding>,
+    ) -> bool
+    where
+        C: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let Some(peering_key) = &self.peering_key else {
+            return true;
+        };
+
+        match tokio::time::timeout(
+            PEER_AUTHENTICATION_TIMEOUT,
+            authenticate(con, peering_key, !inbound, session.as_ref()),
+        )
+        .await
+        {
+            Ok(Ok(())) => {
+                debug!("Authenticated peer {remote}");
+                true
+            }
+            Ok(Err(AuthenticationError::InvalidProof)) => {
+                warn!("Closing connection with {remote}, it does not know the peering key");
+                if inbound {
+                    self.inbound_limiter.authentication_failed(remote.ip());
+                }
+                self.metrics
+                    .peer_manager_peer_authentication_failed(inbound);
+                false
+            }
+            Ok(Err(e)) => {
+                debug!("Could not authenticate peer {remote}: {e}");
+                false
+            }
+            Err(_) => {
+                debug!("Authentication of peer {remote} timed out");
+                if inbound {
+                    self.inbound_limiter.authentication_failed(remote.ip());
+                }
+                self.metrics
+                    .peer_manager_peer_authentication_failed(inbound);
+                false
+            }
+        }
+    }
+
+    /// Add a new peer identifier we discovered. For inbound peers, `listen_addr` is the local
+    /// address of the listener on which the connection was accepted.
     fn add_peer(
         &self,
         endpoint: Endpoint,
         discovery_type: PeerType,
         con_traffic: ConnectionTraffic,
         peer: Option<Peer>,
+        listen_addr: Option<SocketAddr>,
     ) {
         self.metrics.peer_manager_peer_added(discovery_type.clone());
         let mut peers = self.peers.lock().unwrap();
+        // The access list might have been replaced while the inbound connection was set up. Since
+        // the list is replaced while holding the peers lock, checking it again here means a
+        // denied peer is never added.
+        if discovery_type == PeerType::Inbound {
+            if let Some(sa) = endpoint.address().socket_addr() {
+                if !self.inbound_allowed(sa) {
+                    if let Some(p) = peer {
+                        p.died(DisconnectReason::Closed);
+                    }
+                    return;
+                }
+            }
+        }
         // Filter out link local IP's we already know (because of reverse detection)
         if discovery_type == PeerType::LinkLocalDiscovery {
-            if let IpAddr::V6(ip) = endpoint.address().ip() {
-                if ip.octets()[..8] == [0xfe, 0x80, 0, 0, 0, 0, 0, 0] {
+            if let Some(SocketAddr::V6(addr)) = endpoint.address().socket_addr() {
+                if addr.ip().octets()[..8] == [0xfe, 0x80, 0, 0, 0, 0, 0, 0] {
                     for known_endpoint in peers.keys() {
-                        if known_endpoint.address().ip() == endpoint.address().ip()
+                        if known_endpoint.address().socket_addr().map(|sa| sa.ip())
+                            == Some(IpAddr::V6(*addr.ip()))
                             && known_endpoint.proto() == endpoint.proto()
                         {
                             trace!("Refusing to add link local discovered address {endpoint} as there already is a reverse connection {known_endpoint}");
@@ -875,7 +1891,7 @@ where
             }
         }
         // Only if we don't know it yet.
-        if let Entry::Vacant(e) = peers.entry(endpoint) {
+        if let Entry::Vacant(e) = peers.entry(endpoint.clone()) {
             e.insert(PeerInfo {
                 pt: discovery_type,
                 connecting: false,
@@ -885,7 +1901,13 @@ where
                     PeerRef::new()
                 },
                 connection_attempts: 0,
+                next_connection_attempt: None,
                 con_traffic,
+                discovered_public_key: None,
+                duplicate_of: PeerRef::new(),
+                mtu: None,
+                priority: 0,
+                listen_addr,
             });
             if let Some(p) = peer {
                 self.router.lock().unwrap().add_peer_interface(p);
@@ -896,7 +1918,7 @@ where
             // is the same as the previous one, which generally happens with our Quic setup. In
             // this case, the old connection needs to be replaced.
             let old_peer_info = peers.insert(
-                endpoint,
+                endpoint.clone(),
                 PeerInfo {
                     pt: discovery_type,
                     connecting: false,
@@ -906,7 +1928,13 @@ where
                         PeerRef::new()
                     },
                     connection_attempts: 0,
+                    next_connection_attempt: None,
                     con_traffic,
+                    discovered_public_key: None,
+                    duplicate_of: PeerRef::new(),
+                    mtu: None,
+                    priority: 0,
+                    listen_addr,
                 },
             );
             // If we have a new peer notify insert the new one in the router, then notify it that
@@ -1007,10 +2035,15 @@ where
             warn!("Could not disable multicast loop: {e}");
         }
 
-        let mut beacon = [0; PEER_DISCOVERY_BEACON_SIZE];
-        beacon[..8].copy_from_slice(MYCELIUM_MULTICAST_DISCOVERY_MAGIC);
-        beacon[8..10].copy_from_slice(&self.tcp_listen_port.to_be_bytes());
-        beacon[10..50].copy_from_slice(&rid.as_bytes());
+        // Nodes which receive the beacon connect to the tcp port in it, so no beacons are sent if
+        // inbound tcp connections are not accepted. Beacons of other nodes are still handled.
+        let beacon = self.discovery_port().map(|port| {
+            let mut beacon = [0; PEER_DISCOVERY_BEACON_SIZE];
+            beacon[..8].copy_from_slice(MYCELIUM_MULTICAST_DISCOVERY_MAGIC);
+            beacon[8..10].copy_from_slice(&port.to_be_bytes());
+            beacon[10..50].copy_from_slice(&rid.as_bytes());
+            beacon
+        });
 
         let mut send_timer = tokio::time::interval(LL_PEER_DISCOVERY_BEACON_INTERVAL);
         send_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
@@ -1022,11 +2055,14 @@ where
                     if let Err(e) = join_new_interfaces(&mut joined_interfaces) {
                         error!("Issue while joining new IPv6 multicast interfaces: {e}");
                     };
+                    let Some(beacon) = &beacon else {
+                        continue;
+                    };
                     for iface in &joined_interfaces {
                         let dst = SocketAddrV6::new(multicast_destination, peer_discovery_port, 0, *iface);
                         debug!("Sending multicast discovery beacon to {dst}");
                         if let Err(e) = sock.send_to(
-                            &beacon,
+                            beacon,
                             dst,
                         )
                         .await {
@@ -1050,6 +2086,19 @@ where
         }
     }
 
+    /// The port announced in discovery beacons. Discovered nodes connect to the link local
+    /// address of the node, so this is the port of the first tcp listener which accepts
+    /// connections on link local addresses, if any.
+    fn discovery_port(&self) -> Option<u16> {
+        self.tcp_listen_addrs
+            .iter()
+            .find(|addr| match addr.ip() {
+                IpAddr::V6(ip) => ip.is_unspecified() || ip.segments()[0] & 0xffc0 == 0xfe80,
+                IpAddr::V4(_) => false,
+            })
+            .map(SocketAddr::port)
+    }
+
     /// Validates an incoming discovery packet. If the packet is valid, the peer is added to the
     /// `PeerManager`.
     fn handle_discovery_packet(&self, packet: &[u8], mut remote: SocketAddr) {
@@ -1089,22 +2138,51 @@ where
         // Override the port. Care must be taken since link local IPv6 expects the
         // scope_id to be set.
         remote.set_port(port);
+        let endpoint = Endpoint::new(
+            if self.private_network_config.is_some() {
+                Protocol::Tls
+            } else {
+                Protocol::Tcp
+            },
+            remote,
+        );
+        // A node with multiple interfaces on the same link sends a beacon on every one of them, so
+        // only add one peer per announced public key. The public key is used rather than the
+        // router id, since the latter changes when the remote restarts.
+        let remote_pk = remote_rid.to_pubkey();
+        if let Some(known_endpoint) = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, pi)| pi.discovered_public_key == Some(remote_pk))
+            .map(|(known_endpoint, _)| known_endpoint)
+        {
+            if known_endpoint != &endpoint {
+                trace!("Ignoring discovered address {endpoint} of {remote_rid}, as it is already known as {known_endpoint}");
+            }
+            return;
+        }
+        debug!(
+            "Discovered {remote_rid} with address {} at {endpoint}",
+            remote_pk.address()
+        );
         self.add_peer(
-            Endpoint::new(
-                if self.private_network_config.is_some() {
-                    Protocol::Tls
-                } else {
-                    Protocol::Tcp
-                },
-                remote,
-            ),
+            endpoint.clone(),
             PeerType::LinkLocalDiscovery,
-            ConnectionTraffic {
-                tx_bytes: Arc::new(AtomicU64::new(0)),
-                rx_bytes: Arc::new(AtomicU64::new(0)),
-            },
+            ConnectionTraffic::default(),
+            None,
             None,
         );
+        if let Some(pi) = self
+            .peers
+            .lock()
+            .unwrap()
+            .get_mut(&endpoint)
+            .filter(|pi| pi.pt == PeerType::LinkLocalDiscovery)
+        {
+            pi.discovered_public_key = Some(remote_pk);
+        }
     }
 }
 
@@ -1178,6 +2256,181 @@ fn set_fw_mark<S>(socket: S, _mark: Option<u32>) -> io::Result<S> {
     Ok(socket)
 }
 
+/// Bind a listener for inbound tcp connections from peers on the given address.
+fn bind_tcp_listener(addr: SocketAddr, firewall_mark: Option<u32>) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // Like `TcpListener::bind`, so the node can be restarted while old connections linger.
+    #[cfg(not(windows))]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+
+    set_fw_mark(socket.listen(1024)?, firewall_mark)
+}
+
+/// Enable TCP keepalive on the connection to a peer, so connections which silently stopped working
+/// are closed by the OS, even if we have nothing to send.
+fn set_keepalive(stream: &TcpStream) -> io::Result<()> {
+    let keepalive = socket2::TcpKeepalive::new()
+        .with_time(TCP_KEEPALIVE_TIME)
+        .with_interval(TCP_KEEPALIVE_INTERVAL);
+    socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+/// Decide which connections to a remote node to close, if there are multiple. Returns the kept
+/// connection, and the redundant connections to close. If the remote node has to close the
+/// redundant connections, [`None`] is returned.
+///
+/// Both nodes must make the same decision, or they could close all connections at the same time.
+/// The node with the lowest public key keeps its first outbound connection. If all connections
+/// were opened by the same node, that node keeps its first one.
+fn redundant_connections<T>(
+    node_public_key: &PublicKey,
+    remote_public_key: &PublicKey,
+    mut outbound: Vec<T>,
+    mut inbound: Vec<T>,
+) -> Option<(T, Vec<T>)> {
+    if outbound.is_empty() {
+        // The remote opened all connections, so it closes the redundant ones.
+        return None;
+    }
+    if inbound.is_empty() || node_public_key.as_bytes() < remote_public_key.as_bytes() {
+        let kept = outbound.remove(0);
+        outbound.extend(inbound);
+        Some((kept, outbound))
+    } else {
+        // The remote keeps one of its outbound connections, which are inbound connections for us.
+        // Remember one of them, the remote closes the others.
+        Some((inbound.remove(0), outbound))
+    }
+}
+
+/// Get the time to wait before reconnecting to a peer, after the given amount of successive failed
+/// connection attempts.
+///
+/// The backoff grows exponentially with the amount of failed attempts, up to
+/// [`MAX_RECONNECT_BACKOFF`]. Half of the backoff is randomized, so nodes which lost their
+/// connections at the same time (e.g. because of a shared outage) don't all reconnect at the same
+/// time.
+fn reconnect_backoff(failed_attempts: usize) -> Duration {
+    let exponent = failed_attempts.saturating_sub(1).min(16) as u32;
+    let backoff = INITIAL_RECONNECT_BACKOFF
+        .saturating_mul(1 << exponent)
+        .min(MAX_RECONNECT_BACKOFF);
+    backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0)
+}
+
+/// Convert a [`SystemTime`] to seconds since the UNIX epoch.
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Connect to the first of the given addresses which accepts a connection, using the "happy
+/// eyeballs" algorithm from RFC 8305. Addresses are tried in order, alternating between address
+/// families. A new attempt is started if the previous one did not complete within
+/// [`CONNECTION_ATTEMPT_DELAY`], or if it failed. The first established connection is returned
+/// together with the address it is connected to, and all other attempts are cancelled.
+///
+/// If all attempts fail, the error of the last failed attempt is returned.
+///
+/// # Panics
+///
+/// Panics if no addresses are given.
+async fn happy_eyeballs<T, E, F, Fut>(
+    addrs: Vec<SocketAddr>,
+    connect: F,
+) -> Result<(SocketAddr, T), E>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut addrs = interleave_address_families(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut next_addr = addrs.next();
+    let mut last_err = None;
+
+    loop {
+        if let Some(addr) = next_addr.take() {
+            attempts.push(connect(addr).map(move |res| (addr, res)));
+        }
+        if attempts.is_empty() {
+            return Err(
+                last_err.expect("At least 1 address is given, so at least 1 attempt failed; qed")
+            );
+        }
+
+        tokio::select! {
+            Some((addr, res)) = attempts.next() => match res {
+                // Returning drops the other attempts, which cancels them.
+                Ok(con) => return Ok((addr, con)),
+                Err(e) => {
+                    trace!("Connection attempt to {addr} failed");
+                    last_err = Some(e);
+                    next_addr = addrs.next();
+                }
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if addrs.len() > 0 => {
+                next_addr = addrs.next();
+            }
+        }
+    }
+}
+
+/// Build the client config used for outbound Quic connections.
+fn quic_client_config() -> Result<quinn::ClientConfig, NoInitialCipherSuite> {
+    let provider = rustls::crypto::CryptoProvider::get_default()
+        .expect("We have a quic socket so there is a crypto provider installed");
+    let qcc = QuicClientConfig::try_from(
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(SkipServerVerification::new(provider.clone()))
+            .with_no_client_auth(),
+    )?;
+    let mut config = quinn::ClientConfig::new(Arc::new(qcc));
+    // Todo: tweak transport config
+    let mut transport_config = TransportConfig::default();
+    transport_config.max_concurrent_uni_streams(0_u8.into());
+    // Larger than needed for now, just in case
+    transport_config.max_concurrent_bidi_streams(5_u8.into());
+    // Connection timeout, set to higher than Hello interval to ensure connection does not randomly
+    // time out.
+    transport_config.max_idle_timeout(Some(Duration::from_secs(60).try_into().unwrap()));
+    transport_config.mtu_discovery_config(Some(MtuDiscoveryConfig::default()));
+    transport_config.keep_alive_interval(Some(Duration::from_secs(20)));
+    // we don't use datagrams.
+    transport_config.datagram_receive_buffer_size(None);
+    transport_config.datagram_send_buffer_size(0);
+    config.transport_config(Arc::new(transport_config));
+    Ok(config)
+}
+
+/// Reorder addresses so they alternate between IPv6 and IPv4, starting with the family of the
+/// first address. The relative order of addresses of the same family is kept.
+fn interleave_address_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let mut other = other.into_iter();
+    for addr in preferred {
+        interleaved.push(addr);
+        interleaved.extend(other.next());
+    }
+    interleaved.extend(other);
+
+    interleaved
+}
+
 /// Dummy certificate verifier that treats any certificate as valid.
 #[derive(Debug)]
 struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
@@ -1292,3 +2545,217 @@ impl fmt::Display for PeerNotFound {
 }
 
 impl std::error::Error for PeerNotFound {}
+
+impl fmt::Display for ReplacePeerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(e) => e.fmt(f),
+            Self::Exists(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ReplacePeerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NotFound(e) => Some(e),
+            Self::Exists(e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for SetMtuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(e) => e.fmt(f),
+            Self::TooSmall => write!(f, "Link MTU must be at least {MIN_LINK_MTU}"),
+        }
+    }
+}
+
+impl std::error::Error for SetMtuError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NotFound(e) => Some(e),
+            Self::TooSmall => None,
+        }
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedProtocol(proto) => {
+                write!(f, "Node can't connect to endpoints using protocol {proto}")
+            }
+            Self::Resolve(e) => write!(f, "Could not resolve endpoint address: {e}"),
+            Self::Connect(e) => write!(f, "Could not connect to endpoint: {e}"),
+            Self::Timeout => f.write_str("Timed out connecting to endpoint"),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Resolve(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use crate::{
+        access_list::AccessList,
+        crypto::PublicKey,
+        endpoint::Endpoint,
+        router::tests::{NoMetrics, TestRouter},
+    };
+
+    /// Create a [`PeerManager`](super::PeerManager) without listeners, peer discovery and
+    /// configured peers, together with the router it uses.
+    fn test_peer_manager() -> (super::PeerManager<NoMetrics>, TestRouter) {
+        let router = TestRouter::builder().build();
+        let pm = super::PeerManager::new(
+            (*router).clone(),
+            super::PeerManagerConfig {
+                static_peers: vec![],
+                tcp_listen_addrs: vec![],
+                quic_listen_port: Some(0),
+                peer_discovery_port: None,
+                private_network_config: None,
+                peering_key: None,
+                metrics: NoMetrics,
+                firewall_mark: None,
+                inbound_connection_limits: super::InboundConnectionLimits::default(),
+                dial_timeouts: super::DialTimeouts::default(),
+                proxy: None,
+                access_list: AccessList::default(),
+            },
+        )
+        .expect("Can create a peer manager");
+
+        (pm, router)
+    }
+
+    #[test]
+    fn reconnect_backoff_grows_and_is_capped() {
+        for (attempts, base) in [(1, 5), (2, 10), (3, 20), (7, 300), (100, 300)] {
+            let base = std::time::Duration::from_secs(base);
+            let backoff = super::reconnect_backoff(attempts);
+            assert!(backoff >= base / 2, "{backoff:?} < {base:?} / 2");
+            assert!(backoff <= base, "{backoff:?} > {base:?}");
+        }
+    }
+
+    #[test]
+    fn interleave_address_families() {
+        let addrs = [
+            "[2001:db8::1]:9651",
+            "[2001:db8::2]:9651",
+            "[2001:db8::3]:9651",
+            "192.0.2.1:9651",
+        ]
+        .into_iter()
+        .map(|addr| SocketAddr::from_str(addr).unwrap())
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            super::interleave_address_families(addrs.clone()),
+            vec![addrs[0], addrs[3], addrs[1], addrs[2]]
+        );
+    }
+
+    #[test]
+    fn redundant_connections_agree_on_kept_connection() {
+        let low = PublicKey::from([1; 32]);
+        let high = PublicKey::from([2; 32]);
+
+        // Connections opened by both nodes: the node with the lowest key keeps its outbound one.
+        assert_eq!(
+            super::redundant_connections(&low, &high, vec!["out"], vec!["in"]),
+            Some(("out", vec!["in"]))
+        );
+        assert_eq!(
+            super::redundant_connections(&high, &low, vec!["out"], vec!["in"]),
+            Some(("in", vec!["out"]))
+        );
+
+        // Connections opened by one node: only that node closes connections.
+        assert_eq!(
+            super::redundant_connections(&high, &low, vec!["out1", "out2"], vec![]),
+            Some(("out1", vec!["out2"]))
+        );
+        assert_eq!(
+            super::redundant_connections::<&str>(&low, &high, vec![], vec!["in1", "in2"]),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn happy_eyeballs_returns_first_success() {
+        let addrs = ["[2001:db8::1]:9651", "192.0.2.1:9651"]
+            .into_iter()
+            .map(|addr| SocketAddr::from_str(addr).unwrap())
+            .collect::<Vec<_>>();
+
+        // The first address never connects, so the second one is tried after a delay.
+        let res = super::happy_eyeballs(addrs.clone(), |addr| async move {
+            if addr.is_ipv6() {
+                std::future::pending::<Result<SocketAddr, ()>>().await
+            } else {
+                Ok(addr)
+            }
+        })
+        .await;
+        assert_eq!(res, Ok((addrs[1], addrs[1])));
+
+        // If all attempts fail, the last error is returned.
+        let res = super::happy_eyeballs(addrs.clone(), |addr| async move {
+            Err::<(), _>(addr.port() + u16::from(addr.is_ipv4()))
+        })
+        .await;
+        assert_eq!(res, Err(9652));
+    }
+
+    /// Replacing an unknown peer fails, and replacing a peer with the same endpoint keeps its
+    /// connection. Only an actual replacement closes the connection.
+    #[tokio::test]
+    async fn replace_peer_keeps_connection_to_same_endpoint() {
+        let (pm, router) = test_peer_manager();
+        let endpoint = Endpoint::from_str("tcp://192.0.2.1:9651").unwrap();
+        let new_endpoint = Endpoint::from_str("tcp://192.0.2.2:9651").unwrap();
+
+        assert!(matches!(
+            pm.replace_peer(&endpoint, new_endpoint.clone()),
+            Err(super::ReplacePeerError::NotFound(_))
+        ));
+
+        pm.add_peer(endpoint.clone())
+            .expect("Peer is not known yet");
+        let (con, _remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        pm.inner
+            .peers
+            .lock()
+            .unwrap()
+            .get_mut(&endpoint)
+            .expect("Peer was added")
+            .pr = peer.refer();
+
+        pm.replace_peer(&endpoint, endpoint.clone())
+            .expect("Peer can be replaced by itself");
+        assert!(peer.alive());
+        assert!(pm.inner.peers.lock().unwrap().contains_key(&endpoint));
+
+        pm.replace_peer(&endpoint, new_endpoint.clone())
+            .expect("Peer can be replaced");
+        assert!(!peer.alive());
+        let peers = pm.inner.peers.lock().unwrap();
+        assert!(!peers.contains_key(&endpoint));
+        assert!(peers.contains_key(&new_endpoint));
+    }
+}
@@ -0,0 +1,238 @@
+//! Limits on inbound connections, so remotes can't exhaust our file descriptors and memory by
+//! opening a large amount of connections.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+/// Default maximum amount of inbound connections accepted from a single source in a
+/// [`RATE_LIMIT_WINDOW`].
+pub const DEFAULT_MAX_INBOUND_CONNECTIONS_PER_IP: u32 = 10;
+
+/// Default maximum amount of inbound connections which are being set up at the same time.
+pub const DEFAULT_MAX_INBOUND_HANDSHAKES: usize = 64;
+
+/// Maximum amount of failed authentications of connections from a single source in a
+/// [`RATE_LIMIT_WINDOW`]. Once this is reached, connections from the source are rejected until the
+/// window is over.
+const MAX_AUTHENTICATION_FAILURES_PER_IP: u32 = 3;
+
+/// The window in which the amount of connections from a single source is limited.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Amount of tracked sources after which sources which did not connect in the current
+/// [`RATE_LIMIT_WINDOW`] are forgotten.
+const TRACKED_SOURCES_CLEANUP_THRESHOLD: usize = 1024;
+
+/// Limits applied to inbound connections.
+#[derive(Debug, Clone, Copy)]
+pub struct InboundConnectionLimits {
+    /// Maximum amount of connections accepted from a single source per minute. IPv6 sources are
+    /// grouped per /64, since a single host can generally use any address in its /64.
+    pub max_connections_per_ip: u32,
+    /// Maximum amount of inbound connections which are being set up at the same time. Once a
+    /// connection is set up, it no longer counts towards this limit.
+    pub max_concurrent_handshakes: usize,
+}
+
+impl Default for InboundConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections_per_ip: DEFAULT_MAX_INBOUND_CONNECTIONS_PER_IP,
+            max_concurrent_handshakes: DEFAULT_MAX_INBOUND_HANDSHAKES,
+        }
+    }
+}
+
+/// Decides if an inbound connection is accepted, based on the configured
+/// [`InboundConnectionLimits`].
+pub struct InboundLimiter {
+    max_connections_per_ip: u32,
+    /// Start of the current window, and amount of connections accepted in it, per source.
+    sources: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    /// Start of the current window, and amount of failed authentications in it, per source.
+    authentication_failures: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    /// Permits for inbound connections being set up.
+    handshakes: Arc<Semaphore>,
+}
+
+/// Reason an inbound connection is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InboundRejected {
+    /// The source opened too many connections recently.
+    RateLimited,
+    /// Too many inbound connections are being set up already.
+    TooManyHandshakes,
+    /// Too many connections from the source failed to authenticate recently.
+    AuthenticationFailures,
+}
+
+impl InboundRejected {
+    /// A short, stable name of the reason, suitable as label value.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::TooManyHandshakes => "too_many_handshakes",
+            Self::AuthenticationFailures => "authentication_failures",
+        }
+    }
+}
+
+impl InboundLimiter {
+    /// Create a new `InboundLimiter` enforcing the given limits.
+    pub fn new(limits: InboundConnectionLimits) -> Self {
+        Self {
+            max_connections_per_ip: limits.max_connections_per_ip,
+            sources: Mutex::new(HashMap::new()),
+            authentication_failures: Mutex::new(HashMap::new()),
+            handshakes: Arc::new(Semaphore::new(limits.max_concurrent_handshakes)),
+        }
+    }
+
+    /// Check if a new connection from `ip` can be accepted. If it can, a permit is returned which
+    /// must be held until the connection is fully set up.
+    pub fn admit(&self, ip: IpAddr) -> Result<OwnedSemaphorePermit, InboundRejected> {
+        let now = Instant::now();
+        if *count_in_window(&mut self.authentication_failures.lock().unwrap(), ip, now)
+            >= MAX_AUTHENTICATION_FAILURES_PER_IP
+        {
+            return Err(InboundRejected::AuthenticationFailures);
+        }
+        {
+            let mut sources = self.sources.lock().unwrap();
+            let connections = count_in_window(&mut sources, ip, now);
+            if *connections >= self.max_connections_per_ip {
+                return Err(InboundRejected::RateLimited);
+            }
+            *connections += 1;
+        }
+
+        self.handshakes
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| InboundRejected::TooManyHandshakes)
+    }
+
+    /// Record that a connection from `ip` failed to authenticate. Once this happens too often,
+    /// new connections from the source are rejected for a while.
+    pub fn authentication_failed(&self, ip: IpAddr) {
+        *count_in_window(
+            &mut self.authentication_failures.lock().unwrap(),
+            ip,
+            Instant::now(),
+        ) += 1;
+    }
+}
+
+/// The counter of the source of `ip` in the current [`RATE_LIMIT_WINDOW`]. A new window is started
+/// if the previous one is over.
+fn count_in_window(
+    sources: &mut HashMap<IpAddr, (Instant, u32)>,
+    ip: IpAddr,
+    now: Instant,
+) -> &mut u32 {
+    if sources.len() >= TRACKED_SOURCES_CLEANUP_THRESHOLD {
+        sources
+            .retain(|_, (window_start, _)| now.duration_since(*window_start) < RATE_LIMIT_WINDOW);
+    }
+    let (window_start, count) = sources.entry(source_key(ip)).or_insert((now, 0));
+    if now.duration_since(*window_start) >= RATE_LIMIT_WINDOW {
+        *window_start = now;
+        *count = 0;
+    }
+    count
+}
+
+/// The key used to track connections from `ip`. IPv4 mapped IPv6 addresses are treated as the
+/// IPv4 address, and other IPv6 addresses are reduced to their /64.
+fn source_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.into(),
+            None => Ipv6Addr::from(u128::from(ip) & !(u64::MAX as u128)).into(),
+        },
+    }
+}
+
+impl fmt::Display for InboundRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RateLimited => f.write_str("too many connections from the same source"),
+            Self::TooManyHandshakes => f.write_str("too many connections being set up"),
+            Self::AuthenticationFailures => {
+                f.write_str("too many failed authentications from the same source")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{
+        InboundConnectionLimits, InboundLimiter, InboundRejected,
+        MAX_AUTHENTICATION_FAILURES_PER_IP,
+    };
+
+    #[test]
+    fn limits_connections_per_source_and_handshakes() {
+        let limiter = InboundLimiter::new(InboundConnectionLimits {
+            max_connections_per_ip: 2,
+            max_concurrent_handshakes: 3,
+        });
+        let ip = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1));
+        // Different address in the same /64.
+        let neighbour_ip = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 2));
+
+        let first = limiter.admit(ip).expect("First connection is accepted");
+        let second = limiter
+            .admit(neighbour_ip)
+            .expect("Second connection is accepted");
+        assert_eq!(limiter.admit(ip).unwrap_err(), InboundRejected::RateLimited);
+
+        let third = limiter
+            .admit(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .expect("Connection from a different source is accepted");
+        assert_eq!(
+            limiter
+                .admit(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)))
+                .unwrap_err(),
+            InboundRejected::TooManyHandshakes
+        );
+
+        // Finishing a handshake frees up a permit.
+        drop((first, second, third));
+        assert!(limiter
+            .admit(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)))
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_sources_which_fail_to_authenticate() {
+        let limiter = InboundLimiter::new(InboundConnectionLimits::default());
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        for _ in 0..MAX_AUTHENTICATION_FAILURES_PER_IP {
+            assert!(limiter.admit(ip).is_ok());
+            limiter.authentication_failed(ip);
+        }
+        assert_eq!(
+            limiter.admit(ip).unwrap_err(),
+            InboundRejected::AuthenticationFailures
+        );
+        assert!(limiter
+            .admit(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)))
+            .is_ok());
+    }
+}
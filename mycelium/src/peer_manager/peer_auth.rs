@@ -0,0 +1,186 @@
+//! Authentication of peers with a pre-shared key, so only nodes which know the key can peer with
+//! the node, regardless of their public key.
+//!
+//! Right after a connection is set up, both sides send a random challenge. Each side then answers
+//! the challenge of the remote with a MAC over both challenges, keyed with the pre-shared key. The
+//! role of the sender is part of the MAC, so an answer can't be reflected back to its sender. For
+//! QUIC and TLS connections, keying material exported from the session is part of the MAC as
+//! well, so a node in the middle can't relay the authentication into another session. The
+//! connection is only used for peering once the answer of the remote is verified.
+
+use std::{fmt, io};
+
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Pre-shared key which remotes must prove knowledge of before they are accepted as peer.
+pub type PeeringKey = [u8; 32];
+
+/// Size of the challenge sent by both sides.
+const CHALLENGE_SIZE: usize = 32;
+
+/// Context mixed in the answer to a challenge, so the key can't be abused to answer challenges of
+/// other protocols.
+const AUTH_CONTEXT: &[u8] = b"mycelium peer authentication v1";
+
+/// Size of the keying material exported from the session of a connection.
+const SESSION_BINDING_SIZE: usize = 32;
+
+/// Label used to export keying material from the session of a connection, see RFC 5705.
+const SESSION_BINDING_LABEL: &str = "EXPORTER-mycelium-peer-authentication";
+
+/// Keying material exported from the encrypted session of a connection, which binds the
+/// authentication to that session.
+pub type SessionBinding = [u8; SESSION_BINDING_SIZE];
+
+/// Error returned if the remote of a connection could not be authenticated.
+#[derive(Debug)]
+pub enum AuthenticationError {
+    /// The connection failed while the challenges were exchanged.
+    Io(io::Error),
+    /// The remote answered our challenge with an invalid proof, so it doesn't know the key.
+    InvalidProof,
+}
+
+/// Prove knowledge of `key` to the remote of `con`, and verify the remote knows it as well.
+/// Exactly one side of the connection must be the `initiator`. Both sides must use the same
+/// `session` binding, which is `None` if the connection is not encrypted.
+pub async fn authenticate<C>(
+    con: &mut C,
+    key: &PeeringKey,
+    initiator: bool,
+    session: Option<&SessionBinding>,
+) -> Result<(), AuthenticationError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut challenge = [0; CHALLENGE_SIZE];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    con.write_all(&challenge).await?;
+    con.flush().await?;
+
+    let mut remote_challenge = [0; CHALLENGE_SIZE];
+    con.read_exact(&mut remote_challenge).await?;
+
+    let answer = proof(key, initiator, session, &remote_challenge, &challenge);
+    con.write_all(answer.as_bytes()).await?;
+    con.flush().await?;
+
+    let mut remote_answer = [0; blake3::OUT_LEN];
+    con.read_exact(&mut remote_answer).await?;
+
+    // Comparing blake3 hashes happens in constant time.
+    if proof(key, !initiator, session, &challenge, &remote_challenge)
+        != blake3::Hash::from(remote_answer)
+    {
+        return Err(AuthenticationError::InvalidProof);
+    }
+
+    Ok(())
+}
+
+/// The answer to `challenge`, by the side which sent `own_challenge`.
+fn proof(
+    key: &PeeringKey,
+    initiator: bool,
+    session: Option<&SessionBinding>,
+    challenge: &[u8; CHALLENGE_SIZE],
+    own_challenge: &[u8; CHALLENGE_SIZE],
+) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(key);
+    hasher.update(AUTH_CONTEXT);
+    hasher.update(&[initiator as u8]);
+    match session {
+        Some(binding) => hasher.update(&[1]).update(binding),
+        None => hasher.update(&[0]),
+    };
+    hasher.update(challenge);
+    hasher.update(own_challenge);
+    hasher.finalize()
+}
+
+/// Export the [`SessionBinding`] of a QUIC connection.
+pub fn quic_session_binding(con: &quinn::Connection) -> io::Result<SessionBinding> {
+    let mut binding = [0; SESSION_BINDING_SIZE];
+    con.export_keying_material(&mut binding, SESSION_BINDING_LABEL.as_bytes(), &[])
+        .map_err(|_| io::Error::other("could not export keying material of the QUIC session"))?;
+    Ok(binding)
+}
+
+/// Export the [`SessionBinding`] of a TLS connection.
+#[cfg(feature = "private-network")]
+pub fn tls_session_binding(ssl: &openssl::ssl::SslRef) -> io::Result<SessionBinding> {
+    let mut binding = [0; SESSION_BINDING_SIZE];
+    ssl.export_keying_material(&mut binding, SESSION_BINDING_LABEL, None)
+        .map_err(io::Error::other)?;
+    Ok(binding)
+}
+
+impl From<io::Error> for AuthenticationError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "connection failed during authentication: {e}"),
+            Self::InvalidProof => f.write_str("remote does not know the peering key"),
+        }
+    }
+}
+
+impl std::error::Error for AuthenticationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::InvalidProof => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authenticate, AuthenticationError};
+
+    #[tokio::test]
+    async fn only_peers_with_the_same_key_are_authenticated() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (res_a, res_b) = tokio::join!(
+            authenticate(&mut a, &[1; 32], true, None),
+            authenticate(&mut b, &[1; 32], false, None)
+        );
+        assert!(res_a.is_ok());
+        assert!(res_b.is_ok());
+
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (res_a, res_b) = tokio::join!(
+            authenticate(&mut a, &[1; 32], true, None),
+            authenticate(&mut b, &[2; 32], false, None)
+        );
+        assert!(matches!(res_a, Err(AuthenticationError::InvalidProof)));
+        assert!(matches!(res_b, Err(AuthenticationError::InvalidProof)));
+    }
+
+    /// An authentication relayed by a node in the middle fails, since the sessions on both sides
+    /// of the relay differ.
+    #[tokio::test]
+    async fn authentication_is_bound_to_session() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (res_a, res_b) = tokio::join!(
+            authenticate(&mut a, &[1; 32], true, Some(&[3; 32])),
+            authenticate(&mut b, &[1; 32], false, Some(&[3; 32]))
+        );
+        assert!(res_a.is_ok());
+        assert!(res_b.is_ok());
+
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (res_a, res_b) = tokio::join!(
+            authenticate(&mut a, &[1; 32], true, Some(&[3; 32])),
+            authenticate(&mut b, &[1; 32], false, Some(&[4; 32]))
+        );
+        assert!(matches!(res_a, Err(AuthenticationError::InvalidProof)));
+        assert!(matches!(res_b, Err(AuthenticationError::InvalidProof)));
+    }
+}
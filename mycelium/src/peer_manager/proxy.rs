@@ -0,0 +1,299 @@
+//! Connecting to peers through a SOCKS5 proxy (RFC 1928), for nodes which can only reach the
+//! internet through one.
+//!
+//! Only the CONNECT command is used, so this works for Tcp and Tls peers, but not for Quic peers.
+//! Hostnames of peers are passed to the proxy as is, so they are resolved by the proxy rather than
+//! the node. If credentials are configured, the proxy must accept username/password
+//! authentication (RFC 1929), otherwise it must accept unauthenticated clients.
+
+use std::{fmt, io, net::SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::endpoint::Address;
+
+/// Version byte of the SOCKS5 protocol.
+const SOCKS_VERSION: u8 = 5;
+/// Version byte of the username/password authentication subnegotiation.
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+/// Authentication method without authentication.
+const METHOD_NO_AUTHENTICATION: u8 = 0;
+/// Authentication method with a username and password.
+const METHOD_USERNAME_PASSWORD: u8 = 2;
+/// Method selected by the proxy if it accepts none of the offered methods.
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+/// The CONNECT command.
+const COMMAND_CONNECT: u8 = 1;
+/// Address type of an IPv4 address.
+const ADDRESS_TYPE_IPV4: u8 = 1;
+/// Address type of a domain name.
+const ADDRESS_TYPE_DOMAIN: u8 = 3;
+/// Address type of an IPv6 address.
+const ADDRESS_TYPE_IPV6: u8 = 4;
+/// Reply code of a successful request.
+const REPLY_SUCCEEDED: u8 = 0;
+/// Reply code if the target refused the connection.
+const REPLY_CONNECTION_REFUSED: u8 = 5;
+/// Maximum length of a domain name, username or password, which are prefixed with a single byte
+/// length.
+const MAX_FIELD_LENGTH: usize = 255;
+
+/// A SOCKS5 proxy through which outbound connections to peers are made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    /// Address of the proxy.
+    pub address: Address,
+    /// Credentials to authenticate with at the proxy, if it requires them.
+    pub credentials: Option<ProxyCredentials>,
+}
+
+/// Username and password to authenticate with at a [`Socks5Proxy`]. Both must be between 1 and 255
+/// bytes long.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyCredentials {
+    /// The username.
+    pub username: String,
+    /// The password.
+    pub password: String,
+}
+
+impl ProxyCredentials {
+    /// Check if the username and password can be sent to a proxy.
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_FIELD_LENGTH).contains(&self.username.len())
+            && (1..=MAX_FIELD_LENGTH).contains(&self.password.len())
+    }
+}
+
+// Don't leak the password in logs.
+impl fmt::Debug for ProxyCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Ask the proxy on the other side of `con` to connect to `target`. Once this returns
+/// successfully, `con` is connected to `target`.
+///
+/// If the proxy reports the target refused the connection, an error of kind
+/// [`io::ErrorKind::ConnectionRefused`] is returned, like when connecting directly.
+pub async fn connect<C>(
+    con: &mut C,
+    target: &Address,
+    credentials: Option<&ProxyCredentials>,
+) -> io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let method = if credentials.is_some() {
+        METHOD_USERNAME_PASSWORD
+    } else {
+        METHOD_NO_AUTHENTICATION
+    };
+    con.write_all(&[SOCKS_VERSION, 1, method]).await?;
+    con.flush().await?;
+
+    let mut selection = [0; 2];
+    con.read_exact(&mut selection).await?;
+    if selection[0] != SOCKS_VERSION {
+        return Err(protocol_error("proxy does not speak SOCKS5"));
+    }
+    match (selection[1], credentials) {
+        (METHOD_NO_AUTHENTICATION, None) => {}
+        (METHOD_USERNAME_PASSWORD, Some(credentials)) => authenticate(con, credentials).await?,
+        (METHOD_NOT_ACCEPTABLE, None) => {
+            return Err(protocol_error("proxy requires authentication"));
+        }
+        (METHOD_NOT_ACCEPTABLE, Some(_)) => {
+            return Err(protocol_error(
+                "proxy does not accept username/password authentication",
+            ));
+        }
+        (method, _) => {
+            return Err(protocol_error(format!(
+                "proxy selected unsupported authentication method {method}"
+            )));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    encode_address(&mut request, target)?;
+    con.write_all(&request).await?;
+    con.flush().await?;
+
+    let mut reply = [0; 4];
+    con.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(protocol_error("proxy does not speak SOCKS5"));
+    }
+    match reply[1] {
+        REPLY_SUCCEEDED => {}
+        REPLY_CONNECTION_REFUSED => {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "connection refused by target of proxy",
+            ))
+        }
+        code => {
+            return Err(io::Error::other(format!(
+                "proxy failed to connect to target, reply code {code}"
+            )))
+        }
+    }
+
+    // The reply ends with the address the proxy bound for the connection, which we don't need.
+    let bound_address_len = match reply[3] {
+        ADDRESS_TYPE_IPV4 => 4,
+        ADDRESS_TYPE_IPV6 => 16,
+        ADDRESS_TYPE_DOMAIN => con.read_u8().await? as usize,
+        address_type => {
+            return Err(protocol_error(format!(
+                "proxy replied with unknown address type {address_type}"
+            )));
+        }
+    };
+    let mut bound_address = vec![0; bound_address_len + 2];
+    con.read_exact(&mut bound_address).await?;
+
+    Ok(())
+}
+
+/// Authenticate at the proxy with a username and password, as described in RFC 1929.
+async fn authenticate<C>(con: &mut C, credentials: &ProxyCredentials) -> io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    if !credentials.is_valid() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "proxy username and password must be between 1 and 255 bytes",
+        ));
+    }
+
+    let mut request = vec![USERNAME_PASSWORD_VERSION];
+    request.push(credentials.username.len() as u8);
+    request.extend_from_slice(credentials.username.as_bytes());
+    request.push(credentials.password.len() as u8);
+    request.extend_from_slice(credentials.password.as_bytes());
+    con.write_all(&request).await?;
+    con.flush().await?;
+
+    let mut reply = [0; 2];
+    con.read_exact(&mut reply).await?;
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "proxy rejected the credentials",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Append the SOCKS5 encoding of `address` to `buf`.
+fn encode_address(buf: &mut Vec<u8>, address: &Address) -> io::Result<()> {
+    match address {
+        Address::Socket(SocketAddr::V4(addr)) => {
+            buf.push(ADDRESS_TYPE_IPV4);
+            buf.extend_from_slice(&addr.ip().octets());
+        }
+        Address::Socket(SocketAddr::V6(addr)) => {
+            buf.push(ADDRESS_TYPE_IPV6);
+            buf.extend_from_slice(&addr.ip().octets());
+        }
+        Address::Host(host, _) => {
+            if host.len() > MAX_FIELD_LENGTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "hostname is too long to send to proxy",
+                ));
+            }
+            buf.push(ADDRESS_TYPE_DOMAIN);
+            buf.push(host.len() as u8);
+            buf.extend_from_slice(host.as_bytes());
+        }
+    }
+    buf.extend_from_slice(&address.port().to_be_bytes());
+
+    Ok(())
+}
+
+/// Create an error for a proxy which does not follow the protocol.
+fn protocol_error(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{connect, ProxyCredentials};
+    use crate::endpoint::Address;
+
+    #[tokio::test]
+    async fn connects_to_hostname_with_credentials() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        let target = Address::Host("peer.example.com".to_string(), 9651);
+        let credentials = ProxyCredentials {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        };
+
+        let proxy = async move {
+            let mut greeting = [0; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 2]);
+            proxy.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = [0; 11];
+            proxy.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            proxy.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0; 5 + 16 + 2];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..5], &[5, 1, 0, 3, 16]);
+            assert_eq!(&request[5..21], b"peer.example.com");
+            assert_eq!(&request[21..], &9651_u16.to_be_bytes());
+            proxy
+                .write_all(&[5, 0, 0, 1, 192, 0, 2, 1, 0x1f, 0x90])
+                .await
+                .unwrap();
+            proxy
+        };
+
+        let (res, _proxy) = tokio::join!(connect(&mut client, &target, Some(&credentials)), proxy);
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_reported() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        let target = Address::Socket("[2001:db8::1]:9651".parse().unwrap());
+
+        let proxy = async move {
+            let mut greeting = [0; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            proxy.write_all(&[5, 0]).await.unwrap();
+
+            let mut request = [0; 4 + 16 + 2];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..4], &[5, 1, 0, 4]);
+            proxy
+                .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            proxy
+        };
+
+        let (res, _proxy) = tokio::join!(connect(&mut client, &target, None), proxy);
+        assert_eq!(
+            res.unwrap_err().kind(),
+            std::io::ErrorKind::ConnectionRefused
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Bandwidth limits on the data exchanged with a single peer, so a misbehaving peer can't
+//! saturate the link of the node.
+
+use std::{sync::Mutex, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Minimum amount of bytes which can be sent in a burst, regardless of the configured limit. This
+/// makes sure a single large packet is never dropped just because the limit is lower than its size.
+const MIN_BURST_SIZE: u64 = 1 << 16;
+
+/// Window over which the current rate of traffic is measured.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Bandwidth limits of the data exchanged with a peer, in bytes per second. Limits which are not
+/// set are unlimited. Control traffic is never limited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    /// Maximum rate of data sent to the peer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<u64>,
+    /// Maximum rate of data received from the peer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx: Option<u64>,
+}
+
+/// Token bucket rate limiters for both directions of the traffic with a peer. Packets which
+/// exceed the limit are dropped.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    tx: TokenBucket,
+    rx: TokenBucket,
+}
+
+#[derive(Debug, Default)]
+struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug, Default)]
+struct BucketState {
+    /// Configured limit in bytes per second, if any.
+    limit: Option<u64>,
+    /// Amount of bytes which can currently be passed.
+    tokens: f64,
+    /// Last time tokens were added to the bucket.
+    last_refill: Option<Instant>,
+    /// Start of the current rate measurement window, and the amount of bytes passed in it.
+    window: Option<(Instant, u64)>,
+    /// Amount of bytes passed per second in the last full window.
+    rate: u64,
+    /// Total amount of bytes dropped because the limit was exceeded.
+    dropped: u64,
+}
+
+impl RateLimiter {
+    /// Get the configured [`RateLimit`].
+    pub fn limit(&self) -> RateLimit {
+        RateLimit {
+            tx: self.tx.state.lock().unwrap().limit,
+            rx: self.rx.state.lock().unwrap().limit,
+        }
+    }
+
+    /// Set a new [`RateLimit`]. This applies immediately.
+    pub fn set_limit(&self, limit: RateLimit) {
+        self.tx.set_limit(limit.tx);
+        self.rx.set_limit(limit.rx);
+    }
+
+    /// Check if `len` bytes of data can be sent to the peer. If this returns false, the data must
+    /// be dropped.
+    pub fn allow_tx(&self, len: usize) -> bool {
+        self.tx.allow(len as u64, Instant::now())
+    }
+
+    /// Check if `len` bytes of data received from the peer can be accepted. If this returns false,
+    /// the data must be dropped.
+    pub fn allow_rx(&self, len: usize) -> bool {
+        self.rx.allow(len as u64, Instant::now())
+    }
+
+    /// Current rate of data sent to the peer, in bytes per second.
+    pub fn tx_rate(&self) -> u64 {
+        self.tx.rate(Instant::now())
+    }
+
+    /// Current rate of data received from the peer, in bytes per second.
+    pub fn rx_rate(&self) -> u64 {
+        self.rx.rate(Instant::now())
+    }
+
+    /// Total amount of bytes which were not sent to the peer because the limit was exceeded.
+    pub fn tx_dropped(&self) -> u64 {
+        self.tx.state.lock().unwrap().dropped
+    }
+
+    /// Total amount of bytes received from the peer which were dropped because the limit was
+    /// exceeded.
+    pub fn rx_dropped(&self) -> u64 {
+        self.rx.state.lock().unwrap().dropped
+    }
+}
+
+impl TokenBucket {
+    fn set_limit(&self, limit: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit;
+        // Start with a full bucket.
+        state.tokens = limit.map(burst_size).unwrap_or_default() as f64;
+        state.last_refill = None;
+    }
+
+    fn allow(&self, len: u64, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(limit) = state.limit {
+            if let Some(last_refill) = state.last_refill {
+                let elapsed = now.saturating_duration_since(last_refill).as_secs_f64();
+                state.tokens =
+                    (state.tokens + elapsed * limit as f64).min(burst_size(limit) as f64);
+            }
+            state.last_refill = Some(now);
+
+            if state.tokens < len as f64 {
+                state.dropped += len;
+                return false;
+            }
+            state.tokens -= len as f64;
+        }
+
+        state.roll_window(now);
+        if let Some((_, bytes)) = &mut state.window {
+            *bytes += len;
+        }
+
+        true
+    }
+
+    fn rate(&self, now: Instant) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.roll_window(now);
+        state.rate
+    }
+}
+
+impl BucketState {
+    /// Start a new rate measurement window if the current one is over.
+    fn roll_window(&mut self, now: Instant) {
+        match self.window {
+            Some((start, bytes)) if now.saturating_duration_since(start) >= RATE_WINDOW => {
+                let elapsed = now.saturating_duration_since(start);
+                // If nothing was passed for more than a window, the rate is 0.
+                self.rate = if elapsed < RATE_WINDOW * 2 {
+                    (bytes as f64 / elapsed.as_secs_f64()) as u64
+                } else {
+                    0
+                };
+                self.window = Some((now, 0));
+            }
+            Some(_) => {}
+            None => self.window = Some((now, 0)),
+        }
+    }
+}
+
+/// Size of the token bucket for a limit.
+fn burst_size(limit: u64) -> u64 {
+    limit.max(MIN_BURST_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{TokenBucket, MIN_BURST_SIZE};
+
+    #[test]
+    fn excess_traffic_is_dropped() {
+        let bucket = TokenBucket::default();
+        bucket.set_limit(Some(100_000));
+        let start = Instant::now();
+
+        // A full bucket allows a burst of the limit.
+        assert!(bucket.allow(100_000, start));
+        assert!(!bucket.allow(1, start));
+        assert_eq!(bucket.state.lock().unwrap().dropped, 1);
+
+        // After half a second, half the limit is available again.
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.allow(50_000, later));
+        assert!(!bucket.allow(1_000, later));
+        assert_eq!(bucket.state.lock().unwrap().dropped, 1_001);
+
+        // The rate is averaged over the first window, which lasted until the rate is requested.
+        assert_eq!(bucket.rate(start + Duration::from_millis(1_500)), 100_000);
+        assert_eq!(bucket.rate(start + Duration::from_secs(5)), 0);
+    }
+
+    #[test]
+    fn low_limit_allows_large_packet() {
+        let bucket = TokenBucket::default();
+        bucket.set_limit(Some(1_000));
+
+        assert!(bucket.allow(MIN_BURST_SIZE, Instant::now()));
+    }
+}
@@ -0,0 +1,59 @@
+//! Routes which are configured by an operator instead of learned from peers, for instance to
+//! send the traffic for a subnet to a gateway.
+//!
+//! A pinned route sends the traffic for a [`Subnet`] to a directly connected peer, identified by
+//! its overlay address. While the peer is connected, the route is always selected, regardless of
+//! the routes learned from other peers, and it is advertised to other peers like a learned route.
+//! If the peer disconnects, the route is retracted and the learned routes are used again, until
+//! the peer reconnects.
+//!
+//! The local node is the origin of the route, so it is advertised with the router id and seqno of
+//! the local node, and the cost of the link to the peer as metric. Other nodes only accept routes
+//! for subnets containing the overlay address of the origin, so pinned routes for other subnets,
+//! for instance to send traffic for another network to a gateway, are only used by the local node.
+
+use core::fmt;
+use std::net::IpAddr;
+
+use crate::subnet::Subnet;
+
+/// A route for a [`Subnet`] through a peer, which is selected whenever the peer is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinnedRoute {
+    /// The IPv6 subnet which is routed.
+    pub subnet: Subnet,
+    /// Overlay address of the peer used as next hop.
+    pub next_hop: IpAddr,
+}
+
+impl PinnedRoute {
+    /// Checks if the subnet and the next hop of the route are IPv6.
+    pub fn is_valid(&self) -> bool {
+        self.subnet.address().is_ipv6() && self.next_hop.is_ipv6()
+    }
+}
+
+/// Marker error to indicate a [`PinnedRoute`] is not valid, because its subnet or next hop is not
+/// IPv6.
+#[derive(Debug)]
+pub struct InvalidPinnedRoute;
+
+/// Marker error to indicate there is no [`PinnedRoute`] for a [`Subnet`].
+#[derive(Debug)]
+pub struct PinnedRouteNotFound;
+
+impl fmt::Display for InvalidPinnedRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Subnet and next hop of pinned route must be IPv6")
+    }
+}
+
+impl std::error::Error for InvalidPinnedRoute {}
+
+impl fmt::Display for PinnedRouteNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("No pinned route for subnet")
+    }
+}
+
+impl std::error::Error for PinnedRouteNotFound {}
@@ -0,0 +1,252 @@
+//! Policies which decide which route the [`Router`](crate::router::Router) selects for a subnet.
+
+use std::cmp::Ordering;
+
+use tracing::debug;
+
+use crate::{metric::Metric, routing_table::RouteEntry};
+
+/// The amount a metric of a route needs to improve before [`MetricSelector`] will consider
+/// switching to it.
+const SIGNIFICANT_METRIC_IMPROVEMENT: Metric = Metric::new(10);
+
+/// Default priority tolerance of the [`MetricSelector`].
+pub const DEFAULT_PRIORITY_TOLERANCE: u16 = 50;
+
+/// This trait is used by the [`Router`](crate::router::Router) to decide which of the routes for a
+/// subnet is selected, and in which order the other routes are used as fallback.
+///
+/// The router only offers routes which are feasible and have a finite metric as candidates, so a
+/// policy can't introduce routing loops.
+pub trait RouteSelector {
+    /// Compare two routes for the same subnet. The preferred route must be ordered first. This
+    /// defines the order in which fallback routes are used.
+    fn compare(&self, a: &RouteEntry, b: &RouteEntry) -> Ordering;
+
+    /// Select the route to use out of the `candidates` for a subnet. `current` is the currently
+    /// selected route, if it is still feasible, which allows a policy to stick to it. It is not
+    /// necessarily one of the candidates.
+    ///
+    /// The returned route must either be one of the candidates or the current route. By default,
+    /// the candidate which is ordered first by [`RouteSelector::compare`] is selected.
+    fn select<'a>(
+        &self,
+        candidates: &[&'a RouteEntry],
+        current: Option<&'a RouteEntry>,
+    ) -> Option<&'a RouteEntry> {
+        let _ = current;
+        candidates.iter().copied().min_by(|a, b| self.compare(a, b))
+    }
+}
+
+/// The default [`RouteSelector`], which prefers the route with the lowest metric, including the
+/// cost of the link to the neighbour. To avoid flapping, the current route is only replaced if
+/// another route is significantly better, or directly connected. Ties between routes with the
+/// same metric are broken by the highest priority of the neighbour, the lowest router id, and
+/// then the lowest connection identifier of the neighbour, so the same route is selected
+/// regardless of the order of the candidates.
+///
+/// Neighbours can be given a priority to steer traffic towards them. Out of the routes which cost
+/// at most the priority tolerance more than the cheapest route, the route through the neighbour
+/// with the highest priority is selected, even if that is not the current route. Routes which
+/// cost more are only selected based on their metric.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricSelector {
+    /// Maximum amount by which the cost of a route can exceed the cost of the cheapest route for
+    /// the priority of its neighbour to be considered.
+    priority_tolerance: Metric,
+}
+
+impl MetricSelector {
+    /// Create a new `MetricSelector` with the given priority tolerance. A tolerance of 0 means
+    /// priorities only break ties between routes with the same cost.
+    pub fn new(priority_tolerance: u16) -> Self {
+        Self {
+            // An infinite tolerance would make every route acceptable, which is the same as the
+            // largest finite one.
+            priority_tolerance: Metric::new(priority_tolerance.min(u16::MAX - 1)),
+        }
+    }
+
+    /// Total cost to reach the destination over a route.
+    fn cost(route: &RouteEntry) -> Metric {
+        route.metric() + Metric::from(route.neighbour().link_cost())
+    }
+
+    /// Get the preferred candidate: the route through the neighbour with the highest priority
+    /// out of the routes within the priority tolerance of the cheapest route.
+    fn preferred<'a>(&self, candidates: &[&'a RouteEntry]) -> Option<&'a RouteEntry> {
+        let cheapest = candidates.iter().map(|re| Self::cost(re)).min()?;
+        let max_cost = cheapest + self.priority_tolerance;
+        candidates
+            .iter()
+            .copied()
+            .filter(|re| Self::cost(re) <= max_cost)
+            .min_by(|a, b| {
+                b.neighbour()
+                    .priority()
+                    .cmp(&a.neighbour().priority())
+                    .then_with(|| self.compare(a, b))
+            })
+    }
+}
+
+impl Default for MetricSelector {
+    fn default() -> Self {
+        Self::new(DEFAULT_PRIORITY_TOLERANCE)
+    }
+}
+
+impl RouteSelector for MetricSelector {
+    fn compare(&self, a: &RouteEntry, b: &RouteEntry) -> Ordering {
+        Self::cost(a)
+            .cmp(&Self::cost(b))
+            .then_with(|| b.neighbour().priority().cmp(&a.neighbour().priority()))
+            .then_with(|| {
+                a.source()
+                    .router_id()
+                    .as_bytes()
+                    .cmp(&b.source().router_id().as_bytes())
+            })
+            .then_with(|| {
+                a.neighbour()
+                    .connection_identifier()
+                    .cmp(b.neighbour().connection_identifier())
+            })
+    }
+
+    fn select<'a>(
+        &self,
+        candidates: &[&'a RouteEntry],
+        current: Option<&'a RouteEntry>,
+    ) -> Option<&'a RouteEntry> {
+        let best = self.preferred(candidates);
+
+        if let (Some(best), Some(current)) = (best, current) {
+            // If we swap to an actually different route, only do so if the neighbour has a higher
+            // priority, the metric is significantly better, OR if it is directly connected
+            // (metric 0).
+            if (best.source() != current.source() || best.neighbour() != current.neighbour())
+                && best.neighbour().priority() <= current.neighbour().priority()
+                && !(Self::cost(best) < Self::cost(current) - SIGNIFICANT_METRIC_IMPROVEMENT
+                    || best.metric().is_direct())
+            {
+                debug!("maintaining currently selected route since new route is not significantly better");
+                return Some(current);
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        time::Duration,
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::{MetricSelector, RouteSelector};
+    use crate::{
+        crypto::PublicKey,
+        metric::Metric,
+        peer::{Peer, PeerConfig},
+        router_id::RouterId,
+        routing_table::RouteEntry,
+        sequence_number::SeqNo,
+        source_table::SourceKey,
+        subnet::Subnet,
+    };
+
+    /// Create a route for `subnet` with the given metric, announced by `router_id`.
+    fn route(subnet: Subnet, router_id: RouterId, metric: Metric) -> RouteEntry {
+        let (router_data_tx, _) = mpsc::channel(1);
+        let (router_control_tx, _) = mpsc::channel(1);
+        let (dead_peer_sink, _) = mpsc::channel(1);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbour = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con1,
+        )
+        .expect("Can create a dummy peer");
+        RouteEntry::new(
+            SourceKey::new(subnet, router_id),
+            neighbour,
+            metric,
+            SeqNo::new(),
+            false,
+            Duration::from_secs(60),
+        )
+    }
+
+    #[tokio::test]
+    async fn ties_are_broken_deterministically() {
+        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+            .expect("Valid subnet definition");
+        let a = route(
+            subnet,
+            RouterId::new(PublicKey::from([1; 32])),
+            Metric::new(20),
+        );
+        let b = route(
+            subnet,
+            RouterId::new(PublicKey::from([2; 32])),
+            Metric::new(20),
+        );
+
+        let selector = MetricSelector::default();
+        for _ in 0..10 {
+            for candidates in [[&a, &b], [&b, &a]] {
+                let selected = selector
+                    .select(&candidates, None)
+                    .expect("A route is selected");
+                assert_eq!(selected.neighbour(), a.neighbour());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn priority_wins_within_tolerance() {
+        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+            .expect("Valid subnet definition");
+        let cheap = route(
+            subnet,
+            RouterId::new(PublicKey::from([1; 32])),
+            Metric::new(100),
+        );
+        let preferred = route(
+            subnet,
+            RouterId::new(PublicKey::from([2; 32])),
+            Metric::new(120),
+        );
+        let far = route(
+            subnet,
+            RouterId::new(PublicKey::from([3; 32])),
+            Metric::new(200),
+        );
+        preferred.neighbour().set_priority(1);
+        far.neighbour().set_priority(2);
+
+        let selector = MetricSelector::new(50);
+        let candidates = [&cheap, &preferred, &far];
+        let selected = selector
+            .select(&candidates, None)
+            .expect("A route is selected");
+        assert_eq!(selected.neighbour(), preferred.neighbour());
+        // A higher priority replaces the current route, even if it is not significantly better.
+        let selected = selector
+            .select(&candidates, Some(&cheap))
+            .expect("A route is selected");
+        assert_eq!(selected.neighbour(), preferred.neighbour());
+
+        // Outside of the tolerance, the metric decides.
+        let selector = MetricSelector::new(10);
+        let selected = selector
+            .select(&candidates, None)
+            .expect("A route is selected");
+        assert_eq!(selected.neighbour(), cheap.neighbour());
+    }
+}
@@ -1,66 +1,393 @@
+#[cfg(feature = "testing")]
+use crate::fault_injection::{FaultInjectionConfig, FaultInjector};
 use crate::{
-    babel::{self, Hello, Ihu, RouteRequest, SeqNoRequest, Update},
-    crypto::{PacketBuffer, PublicKey, SecretKey, SharedSecret},
+    access_list::AccessList,
+    babel::{self, RouteRequest, SeqNoRequest, Update},
+    crypto::{Cipher, PacketBuffer, PublicKey, SecretKey, SharedSecret, ENCRYPTION_OVERHEAD},
+    event_log::{EventLog, LoggedEvent, RoutingEvent},
     filters::RouteUpdateFilter,
+    flap_damping::{FlapDamping, FlapDampingConfig, SuppressedRoute},
+    hello_history::HelloHistoryConfig,
+    in_global_subnet,
+    jitter::{Jitter, JitteredInterval, HELLO_JITTER, UPDATE_JITTER},
     metric::Metric,
-    metrics::Metrics,
+    metric_offset::MetricOffsets,
+    metrics::{DropReason, Metrics},
     packet::{ControlPacket, DataPacket},
-    peer::Peer,
+    path_mtu::PathMtuCache,
+    peer::{DisconnectReason, Peer, PeerConfig},
+    pinned_route::{InvalidPinnedRoute, PinnedRoute, PinnedRouteNotFound},
+    route_selection::RouteSelector,
     router_id::RouterId,
-    routing_table::{RouteEntry, RouteExpirationType, RouteKey, RoutingTable},
+    routing_table::{RouteExpirationType, RouteKey, RoutingTable},
     seqno_cache::{SeqnoCache, SeqnoRequestCacheKey},
     sequence_number::SeqNo,
     source_table::{FeasibilityDistance, SourceKey, SourceTable},
+    stats::{NodeStats, StatsCounters},
     subnet::Subnet,
+    triggered_updates::TriggeredUpdates,
 };
+use bytes::BytesMut;
 use etherparse::{
     icmpv6::{DestUnreachableCode, TimeExceededCode},
     Icmpv6Type,
 };
 use left_right::{ReadHandle, WriteHandle};
 use std::{
+    collections::HashMap,
     error::Error,
-    net::IpAddr,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::{IpAddr, Ipv6Addr},
     sync::{Arc, Mutex, RwLock},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, error::TrySendError, Receiver, Sender},
 };
-use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
 use tracing::{debug, error, info, trace, warn};
 
-/// Time between HELLO messags, in seconds
-const HELLO_INTERVAL: u64 = 20;
-/// Time filled in in IHU packet
-const IHU_INTERVAL: Duration = Duration::from_secs(HELLO_INTERVAL * 3);
-/// Max time used in UPDATE packets. For local (static) routes this is the timeout they are
-/// advertised with.
-const UPDATE_INTERVAL: Duration = Duration::from_secs(HELLO_INTERVAL * 3 * 5);
-/// Time between route table dumps to peers.
-const ROUTE_PROPAGATION_INTERVAL: Duration = UPDATE_INTERVAL;
-/// Amount of seconds that can elapse before we consider a [`Peer`] as dead from the routers POV.
-/// Since IHU's are sent in response to HELLO packets, this MUST be greater than the
-/// [`HELLO_INTERVAL`].
-///
-/// We allow missing 1 hello, + some latency, so 2 HELLO's + 3 seconds for latency.
-const DEAD_PEER_THRESHOLD: Duration = Duration::from_secs(HELLO_INTERVAL * 2 + 3);
-/// The duration between checks for dead peers in the router. This check only looks for peers where
-/// time since the last IHU exceeds DEAD_PEER_THRESHOLD.
+pub use crate::routing_table::RouteEntry;
+
+/// Default time between HELLO messages.
+pub const DEFAULT_HELLO_INTERVAL: Duration = Duration::from_secs(20);
+/// Default time filled in in IHU packets.
+pub const DEFAULT_IHU_INTERVAL: Duration = Duration::from_secs(60);
+/// Default max time used in UPDATE packets, and time between route table dumps to peers. For
+/// local (static) routes this is the timeout they are advertised with.
+pub const DEFAULT_UPDATE_INTERVAL: Duration = Duration::from_secs(300);
+/// Smallest allowed [`Intervals`] value. Lower values would flood peers with control packets.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest allowed [`Intervals`] value. Intervals are sent as centiseconds in a 16 bit field.
+pub const MAX_INTERVAL: Duration = Duration::from_secs(655);
+/// Default minimum time between triggered updates sent to a [`Peer`]. Route changes within this
+/// time are sent as a single batch.
+pub const DEFAULT_TRIGGERED_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+/// Default amount of hello intervals without an IHU from a [`Peer`], after which it is considered
+/// dead. This allows missing 1 HELLO.
+pub const DEFAULT_DEAD_PEER_HELLOS: u32 = 2;
+/// Latency allowed for an IHU in reply to a HELLO, before a [`Peer`] is considered dead. A peer is
+/// dead if no IHU is received for [`Intervals::dead_peer_hellos`] hello intervals + this value.
+const DEAD_PEER_LATENCY_ALLOWANCE: Duration = Duration::from_secs(3);
+/// The maximum duration between checks for dead peers in the router. This check only looks for
+/// peers where time since the last IHU exceeds the dead peer threshold.
 const DEAD_PEER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Amount of time to wait between consecutive seqno bumps of the local router seqno.
 const SEQNO_BUMP_TIMEOUT: Duration = Duration::from_secs(4);
 
+/// Interval at which the local router seqno is bumped, regardless of seqno requests. This makes
+/// sure routes to the local subnets eventually become feasible again for all nodes, even if seqno
+/// requests are lost.
+const SEQNO_BUMP_INTERVAL: Duration = Duration::from_secs(300);
+
 /// Metric change of more than 10 is considered a large change.
 const BIG_METRIC_CHANGE_TRESHOLD: Metric = Metric::new(10);
 
-/// The amount a metric of a route needs to improve before we will consider switching to it.
-const SIGNIFICANT_METRIC_IMPROVEMENT: Metric = Metric::new(10);
+/// The default multiplier applied to the interval of an update to get the hold time of the route.
+/// According to <https://datatracker.ietf.org/doc/html/rfc8966#section-appendix.b> a good value
+/// would be 3.5.
+pub const DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER: f64 = 3.5;
 
 /// Hold retracted routes for 1 minute before purging them from the [`RoutingTable`].
 const RETRACTED_ROUTE_HOLD_TIME: Duration = Duration::from_secs(60);
 
+/// Time after which the timer of a pinned route is reset. Pinned routes don't expire, but every
+/// route in the [`RoutingTable`] needs a timer.
+const PINNED_ROUTE_HOLD_TIME: Duration = Duration::from_secs(60 * 60);
+
 /// The interval specified in updates if the update won't be repeated.
 const INTERVAL_NOT_REPEATING: Duration = Duration::from_millis(0);
 
+/// Default capacity of the queues between the tasks which handle packets, see [`QueueSizes`].
+pub const DEFAULT_QUEUE_SIZE: usize = 1000;
+
+/// Maximum amount of control packets queued towards a peer for which a dump of all selected routes
+/// is still queued. Dumps to peers with a larger backlog are skipped, so the queue of a peer which
+/// can't keep up doesn't grow without bound.
+const MAX_ROUTE_DUMP_BACKLOG: usize = 1000;
+
+/// Maximum amount of equal cost paths traffic to a single subnet is spread over.
+const MAX_ECMP_PATHS: usize = 4;
+
+/// Amount of [`RouteEvent`]s buffered for subscribers. Subscribers which fall behind by more than
+/// this amount of events miss the oldest ones.
+const ROUTE_EVENT_BUFFER_SIZE: usize = 1000;
+
+/// Metric advertised for transit routes while the router is draining. This is the highest finite
+/// metric, so neighbours prefer any other route, but can still use ours if they have no other.
+const DRAIN_METRIC: Metric = Metric::new(u16::MAX - 1);
+
+/// Time without forwarded transit packets after which a draining router is considered drained.
+const DRAIN_QUIET_PERIOD: Duration = Duration::from_secs(10);
+
+/// How selected routes are advertised to the neighbour they are selected through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitHorizon {
+    /// Routes are not advertised to the neighbour they are selected through.
+    #[default]
+    Simple,
+    /// Routes are advertised to the neighbour they are selected through with an infinite metric,
+    /// so the neighbour immediately drops a route it might have through us.
+    PoisonReverse,
+}
+
+/// Intervals at which the router sends periodic control packets. These are also advertised in the
+/// sent TLVs, so peers know when to expect the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Intervals {
+    /// Time between HELLO messages sent to peers.
+    pub hello: Duration,
+    /// Interval advertised in IHU messages.
+    pub ihu: Duration,
+    /// Time between periodic route updates sent to peers. This is also the interval advertised
+    /// in updates, so routes expire after a multiple of it.
+    pub update: Duration,
+    /// Amount of hello intervals without an IHU from a peer, after which the peer is considered
+    /// dead and its connection is closed. This must be at least 1.
+    pub dead_peer_hellos: u32,
+    /// Minimum time between triggered updates sent to a peer. Updates for routes which change
+    /// within this time are sent as a single batch once it passed, retractions are always sent
+    /// right away. Zero sends every triggered update right away. This must not be larger than the
+    /// update interval.
+    pub triggered_update: Duration,
+}
+
+impl Intervals {
+    /// Time after which a [`Peer`] is considered dead if no IHU is received from it. Since IHU's
+    /// are sent in response to HELLO packets, this is greater than the hello interval.
+    fn dead_peer_threshold(&self) -> Duration {
+        self.hello * self.dead_peer_hellos + DEAD_PEER_LATENCY_ALLOWANCE
+    }
+
+    /// Time after which a [`Peer`] is considered degraded if no IHU is received from it, i.e. the
+    /// reply to a single HELLO is missing.
+    fn degraded_peer_threshold(&self) -> Duration {
+        self.hello + DEAD_PEER_LATENCY_ALLOWANCE
+    }
+}
+
+impl Default for Intervals {
+    fn default() -> Self {
+        Self {
+            hello: DEFAULT_HELLO_INTERVAL,
+            ihu: DEFAULT_IHU_INTERVAL,
+            update: DEFAULT_UPDATE_INTERVAL,
+            dead_peer_hellos: DEFAULT_DEAD_PEER_HELLOS,
+            triggered_update: DEFAULT_TRIGGERED_UPDATE_INTERVAL,
+        }
+    }
+}
+
+/// Capacities of the queues between the tasks which handle packets. Bigger queues absorb bursts
+/// of packets better, at the cost of memory and latency. Every size must be at least 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueSizes {
+    /// Data packets received from peers which still need to be routed. If this queue is full,
+    /// peers stop reading from their connection until there is space again.
+    pub router_data: usize,
+    /// Control packets received from peers which still need to be dispatched, and the queues of
+    /// the tasks which process updates, route requests and seqno requests. If one of the latter
+    /// is full, dispatching waits until there is space again, and once the former is full as
+    /// well, peers stop reading from their connection. Control packets are never dropped.
+    pub router_control: usize,
+    /// Data packets to be sent to a single peer. If this queue is full, new packets for the peer
+    /// are dropped.
+    pub peer_data: usize,
+    /// Data packets which are routed to the local node, before they are processed. If this queue
+    /// is full, new packets are dropped.
+    pub node_tun: usize,
+    /// Packets waiting to be written to the TUN interface, per queue of the interface.
+    pub tun: usize,
+}
+
+impl Default for QueueSizes {
+    fn default() -> Self {
+        Self {
+            router_data: DEFAULT_QUEUE_SIZE,
+            router_control: DEFAULT_QUEUE_SIZE,
+            peer_data: DEFAULT_QUEUE_SIZE,
+            node_tun: DEFAULT_QUEUE_SIZE,
+            tun: DEFAULT_QUEUE_SIZE,
+        }
+    }
+}
+
+/// Queues towards the tasks which process the TLV types that can take a while to handle.
+struct ControlQueues {
+    update: Sender<(Update, Peer)>,
+    route_request: Sender<(RouteRequest, Peer)>,
+    seqno_request: Sender<(SeqNoRequest, Peer)>,
+}
+
+/// The kind of change to the selected route of a [`Subnet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteEventKind {
+    /// A route was selected for a subnet which did not have a selected route.
+    Added,
+    /// The selected route of a subnet changed, either because a different route got selected, or
+    /// because the metric or seqno of the selected route changed.
+    Changed,
+    /// The subnet no longer has a selected route.
+    Removed,
+}
+
+/// The state of a route in the routing table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteState {
+    /// The route is selected, and used to forward packets.
+    Selected,
+    /// The route is feasible, but not selected. It can be selected if the selected route goes
+    /// away or becomes worse.
+    Fallback,
+    /// The route is not feasible, so it can't be selected until the source announces a newer
+    /// seqno, or the feasibility distance is reset.
+    Unfeasible,
+    /// The route was retracted by the neighbour, i.e. it has an infinite metric. It is kept for a
+    /// while before it is removed.
+    Retracted,
+    /// The route is a [`PinnedRoute`] configured by the operator. It is selected, and used to
+    /// forward packets, as long as the next hop is connected.
+    Pinned,
+}
+
+/// The state of the babel neighbour relationship with a peer. This is the view of the routing
+/// protocol on the link, as opposed to the view of the transport in
+/// [`PeerStats`](crate::peer_manager::PeerStats).
+#[derive(Debug, Clone, PartialEq)]
+pub struct NeighbourInfo {
+    /// Connection identifier of the neighbour.
+    pub neighbour: String,
+    /// Cost of the link as computed by this node from the round trip time and the loss of Hellos.
+    /// This is sent to the neighbour in IHUs.
+    pub rx_cost: u16,
+    /// Cost of the link as computed by the neighbour, as advertised in the last IHU received from
+    /// it, if any.
+    pub tx_cost: Option<u16>,
+    /// Cost added to the metric of routes learned from the neighbour. This is currently the same as
+    /// the rx cost, the tx cost is not taken into account.
+    pub link_cost: u16,
+    /// Smoothed round trip time of the link.
+    pub rtt: Duration,
+    /// Fraction of the recent Hellos sent by the neighbour which was received.
+    pub hello_reception: f64,
+    /// Sequence number of the last Hello received from the neighbour, if any.
+    pub last_hello_seqno: Option<u16>,
+    /// Time since the last Hello was received from the neighbour, if any.
+    pub last_hello: Option<Duration>,
+    /// Interval advertised in the last Hello received from the neighbour, if any.
+    pub hello_interval: Option<Duration>,
+    /// Time since the last IHU was received from the neighbour, or since the neighbour connected
+    /// if no IHU was received yet.
+    pub last_ihu: Duration,
+    /// Interval advertised in the last IHU received from the neighbour, if any.
+    pub ihu_interval: Option<Duration>,
+}
+
+/// A change to the selected route of a [`Subnet`]. Subscribe to these with
+/// [`Router::subscribe_route_events`].
+#[derive(Debug, Clone)]
+pub struct RouteEvent {
+    kind: RouteEventKind,
+    route: RouteEntry,
+}
+
+impl RouteEvent {
+    /// The kind of change to the selected route.
+    pub fn kind(&self) -> RouteEventKind {
+        self.kind
+    }
+
+    /// The new selected route. For [`RouteEventKind::Removed`], this is the previously selected
+    /// route.
+    pub fn route(&self) -> &RouteEntry {
+        &self.route
+    }
+}
+
+/// Handle to check if a [`Router`] is still running. Unlike the router itself, this can be shared
+/// between threads.
+#[derive(Clone)]
+pub struct RouterHealth {
+    router_control_tx: Sender<(ControlPacket, Peer)>,
+    router_data_tx: Sender<(DataPacket, Peer)>,
+    node_tun: Sender<(DataPacket, Option<Peer>)>,
+    peer_interfaces: Arc<RwLock<Vec<Peer>>>,
+}
+
+impl RouterHealth {
+    /// Checks if the tasks which process the control and data packets received from peers are
+    /// still running.
+    pub fn is_running(&self) -> bool {
+        !self.router_control_tx.is_closed() && !self.router_data_tx.is_closed()
+    }
+
+    /// Checks if packets for the local node can still be delivered, i.e. the task which writes
+    /// them to the TUN interface is still running.
+    pub fn tun_is_up(&self) -> bool {
+        !self.node_tun.is_closed()
+    }
+
+    /// Get the amount of peer interfaces known on the router.
+    pub fn peer_count(&self) -> usize {
+        self.peer_interfaces.read().unwrap().len()
+    }
+}
+
+/// Progress of draining a [`Router`], see [`Router::start_drain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrainStatus {
+    /// Time since draining started.
+    pub draining_for: Duration,
+    /// Amount of transit packets, i.e. packets of other nodes, forwarded since draining started.
+    pub transit_packets: u64,
+    /// Whether no transit packets were forwarded for some time, so no neighbour uses this router
+    /// as next hop anymore.
+    pub drained: bool,
+}
+
+/// State of a draining [`Router`].
+struct DrainState {
+    started: Instant,
+    transit_packets: u64,
+    last_transit_packet: Option<Instant>,
+}
+
+/// Configuration of a [`Router`], see [`Router::new`].
+pub struct RouterConfig<M> {
+    /// The subnet of the local node.
+    pub node_subnet: Subnet,
+    /// Routes originated by this node, as destination and optional source prefix.
+    pub static_routes: Vec<(Subnet, Option<Subnet>)>,
+    /// The keypair of the local node.
+    pub node_keypair: (SecretKey, PublicKey),
+    /// Filters which are applied to received updates, updates are only accepted if every filter
+    /// allows them.
+    pub update_filters: Vec<Box<dyn RouteUpdateFilter + Send + Sync>>,
+    /// Selects the route to use for a subnet from the known routes.
+    pub route_selector: Box<dyn RouteSelector + Send + Sync>,
+    /// How routes are advertised back to the peer they were learned from.
+    pub split_horizon: SplitHorizon,
+    /// Multiplier applied to the interval of an update to get the hold time of the route.
+    pub route_hold_time_multiplier: f64,
+    /// Intervals at which periodic control packets are sent.
+    pub intervals: Intervals,
+    /// Capacities of the queues between the tasks which handle packets.
+    pub queue_sizes: QueueSizes,
+    /// Suppression of routes which keep changing.
+    pub flap_damping: FlapDampingConfig,
+    /// Tracking of Hellos received from peers.
+    pub hello_history: HelloHistoryConfig,
+    /// Offsets added to the metric of routes through some neighbours during route selection.
+    pub metric_offsets: MetricOffsets,
+    /// Maximum amount of routes in the routing table, if it is limited.
+    pub max_routes: Option<usize>,
+    /// Amount of recent routing events which are kept. If this is 0, no events are kept.
+    pub event_log_size: usize,
+    /// Records metrics of the router.
+    pub metrics: M,
+}
+
 pub struct Router<M> {
     inner_w: Arc<Mutex<WriteHandle<RouterInner, RouterOpLogEntry>>>,
     inner_r: ReadHandle<RouterInner>,
@@ -68,19 +395,60 @@ pub struct Router<M> {
     source_table: Arc<RwLock<SourceTable>>,
     // Router SeqNo and last time it was bumped
     router_seqno: Arc<RwLock<(SeqNo, Instant)>>,
-    static_routes: Vec<Subnet>,
+    /// Routes originated by this node, as destination and optional source prefix.
+    static_routes: Vec<(Subnet, Option<Subnet>)>,
+    /// Whether the default route is one of the static routes, i.e. this node is an exit node.
+    exit_node: bool,
     router_id: RouterId,
     node_keypair: (SecretKey, PublicKey),
-    router_data_tx: Sender<DataPacket>,
-    router_control_tx: UnboundedSender<(ControlPacket, Peer)>,
-    node_tun: UnboundedSender<DataPacket>,
+    router_data_tx: Sender<(DataPacket, Peer)>,
+    router_control_tx: Sender<(ControlPacket, Peer)>,
+    node_tun: Sender<(DataPacket, Option<Peer>)>,
     node_tun_subnet: Subnet,
     update_filters: Arc<Vec<Box<dyn RouteUpdateFilter + Send + Sync>>>,
+    route_selector: Arc<dyn RouteSelector + Send + Sync>,
     /// Channel injected into peers, so they can notify the router if they exit.
     dead_peer_sink: mpsc::Sender<Peer>,
     /// Channel to notify the router of expired SourceKey's.
     expired_source_key_sink: mpsc::Sender<SourceKey>,
     seqno_cache: SeqnoCache,
+    route_events: broadcast::Sender<RouteEvent>,
+    split_horizon: SplitHorizon,
+    /// Multiplier applied to the interval of an update to get the hold time of the route.
+    route_hold_time_multiplier: f64,
+    /// Intervals at which periodic control packets are sent.
+    intervals: Intervals,
+    /// Capacities of the queues between the tasks which handle packets.
+    queue_sizes: QueueSizes,
+    /// Penalties of routes which changed recently, used to suppress routes which keep changing.
+    flap_damping: Arc<FlapDamping>,
+    /// Parameters of the tracking of Hellos received from peers.
+    hello_history: HelloHistoryConfig,
+    /// Offsets added to the metric of routes through some neighbours during route selection.
+    metric_offsets: Arc<RwLock<MetricOffsets>>,
+    /// Routes configured by the operator, which are selected while their next hop is connected.
+    pinned_routes: Arc<RwLock<Vec<PinnedRoute>>>,
+    /// Maximum amount of routes in the routing table, if it is limited.
+    max_routes: Option<usize>,
+    /// Set once the router is draining, so it stops being used as transit hop.
+    drain: Arc<RwLock<Option<DrainState>>>,
+    /// [`Cipher`] negotiated to encrypt data for remote nodes, by the subnet of the node. Entries
+    /// are removed once there is no route to the subnet anymore.
+    ciphers: Arc<RwLock<HashMap<Subnet, Cipher>>>,
+    /// MTU of the paths to remote nodes, learned from Packet Too Big messages.
+    path_mtu: Arc<PathMtuCache>,
+    /// When triggered updates were last sent to peers, and the updates which are pending.
+    triggered_updates: Arc<TriggeredUpdates>,
+    /// Node-wide counters of the handled traffic.
+    stats: Arc<StatsCounters>,
+    /// Recent routing events.
+    event_log: Arc<EventLog>,
+    /// Injects faults on packets sent to peers.
+    #[cfg(feature = "testing")]
+    fault_injector: Arc<FaultInjector>,
+    /// Seed for the hash used to spread flows over equal cost paths. This is random per router,
+    /// so routers along a path don't all make the same choice.
+    ecmp_seed: u64,
     metrics: M,
 }
 
@@ -88,18 +456,36 @@ impl<M> Router<M>
 where
     M: Metrics + Clone + Send + 'static,
 {
+    /// Create a new `Router` with the given [`RouterConfig`]. Data packets for the local node are
+    /// delivered to `node_tun`, together with the [`Peer`] they were received from, if they were
+    /// not sent by the local node itself.
     pub fn new(
-        node_tun: UnboundedSender<DataPacket>,
-        node_tun_subnet: Subnet,
-        static_routes: Vec<Subnet>,
-        node_keypair: (SecretKey, PublicKey),
-        update_filters: Vec<Box<dyn RouteUpdateFilter + Send + Sync>>,
-        metrics: M,
+        node_tun: Sender<(DataPacket, Option<Peer>)>,
+        config: RouterConfig<M>,
     ) -> Result<Self, Box<dyn Error>> {
+        let RouterConfig {
+            node_subnet: node_tun_subnet,
+            static_routes,
+            node_keypair,
+            update_filters,
+            route_selector,
+            split_horizon,
+            route_hold_time_multiplier,
+            intervals,
+            queue_sizes,
+            flap_damping,
+            hello_history,
+            metric_offsets,
+            max_routes,
+            event_log_size,
+            metrics,
+        } = config;
+
         // Tx is passed onto each new peer instance. This enables peers to send control packets to the router.
-        let (router_control_tx, router_control_rx) = mpsc::unbounded_channel();
+        let (router_control_tx, router_control_rx) = mpsc::channel(queue_sizes.router_control);
         // Tx is passed onto each new peer instance. This enables peers to send data packets to the router.
-        let (router_data_tx, router_data_rx) = mpsc::channel::<DataPacket>(1000);
+        let (router_data_tx, router_data_rx) =
+            mpsc::channel::<(DataPacket, Peer)>(queue_sizes.router_data);
         let (expired_source_key_sink, expired_source_key_stream) = mpsc::channel(1);
         let (expired_route_entry_sink, expired_route_entry_stream) = mpsc::channel(1);
         let (dead_peer_sink, dead_peer_stream) = mpsc::channel(1);
@@ -117,6 +503,7 @@ where
             peer_interfaces: Arc::new(RwLock::new(Vec::new())),
             source_table: Arc::new(RwLock::new(SourceTable::new())),
             router_seqno: Arc::new(RwLock::new((SeqNo::new(), Instant::now()))),
+            exit_node: static_routes.iter().any(|(sr, _)| sr.prefix_len() == 0),
             static_routes,
             router_id,
             node_keypair,
@@ -127,7 +514,27 @@ where
             dead_peer_sink,
             expired_source_key_sink,
             seqno_cache,
+            route_events: broadcast::channel(ROUTE_EVENT_BUFFER_SIZE).0,
             update_filters: Arc::new(update_filters),
+            route_selector: Arc::from(route_selector),
+            split_horizon,
+            route_hold_time_multiplier,
+            intervals,
+            queue_sizes,
+            flap_damping: Arc::new(FlapDamping::new(flap_damping)),
+            hello_history,
+            metric_offsets: Arc::new(RwLock::new(metric_offsets)),
+            pinned_routes: Arc::new(RwLock::new(Vec::new())),
+            max_routes,
+            drain: Arc::new(RwLock::new(None)),
+            ciphers: Arc::new(RwLock::new(HashMap::new())),
+            path_mtu: Arc::new(PathMtuCache::new()),
+            triggered_updates: Arc::new(TriggeredUpdates::new(intervals.triggered_update)),
+            stats: Arc::new(StatsCounters::new()),
+            event_log: Arc::new(EventLog::new(event_log_size)),
+            #[cfg(feature = "testing")]
+            fault_injector: Arc::new(FaultInjector::new(FaultInjectionConfig::default())),
+            ecmp_seed: rand::random(),
             metrics,
         };
 
@@ -141,7 +548,12 @@ where
             router_data_rx,
         ));
         tokio::spawn(Router::propagate_static_routes(router.clone()));
+
+        tokio::spawn(Router::bump_router_seqno_periodically(router.clone()));
         tokio::spawn(Router::propagate_selected_routes(router.clone()));
+        if !intervals.triggered_update.is_zero() {
+            tokio::spawn(Router::send_pending_triggered_updates(router.clone()));
+        }
 
         tokio::spawn(Router::check_for_dead_peers(router.clone()));
 
@@ -160,19 +572,39 @@ where
         Ok(router)
     }
 
-    pub fn router_control_tx(&self) -> UnboundedSender<(ControlPacket, Peer)> {
+    pub fn router_control_tx(&self) -> Sender<(ControlPacket, Peer)> {
         self.router_control_tx.clone()
     }
 
-    pub fn router_data_tx(&self) -> Sender<DataPacket> {
+    pub fn router_data_tx(&self) -> Sender<(DataPacket, Peer)> {
         self.router_data_tx.clone()
     }
 
+    /// Capacities of the queues between the tasks which handle packets.
+    pub fn queue_sizes(&self) -> QueueSizes {
+        self.queue_sizes
+    }
+
     pub fn node_tun_subnet(&self) -> Subnet {
         self.node_tun_subnet
     }
 
-    pub fn node_tun(&self) -> UnboundedSender<DataPacket> {
+    /// Checks if this node announces the default route, so packets for addresses outside of the
+    /// global subnet are delivered to the local node.
+    pub fn is_exit_node(&self) -> bool {
+        self.exit_node
+    }
+
+    /// Checks if the default route of this exit node is announced for traffic from `src`, i.e.
+    /// there is a default route without source prefix, or one with a source prefix containing
+    /// `src`.
+    fn exit_allows_source(&self, src: IpAddr) -> bool {
+        self.static_routes
+            .iter()
+            .any(|(sr, sp)| sr.prefix_len() == 0 && sp.is_none_or(|sp| sp.contains_ip(src)))
+    }
+
+    pub fn node_tun(&self) -> Sender<(DataPacket, Option<Peer>)> {
         self.node_tun.clone()
     }
 
@@ -181,18 +613,59 @@ where
         self.peer_interfaces.read().unwrap().clone()
     }
 
+    /// Get a [`RouterHealth`] handle to check if the router is still running.
+    pub fn health(&self) -> RouterHealth {
+        RouterHealth {
+            router_control_tx: self.router_control_tx.clone(),
+            router_data_tx: self.router_data_tx.clone(),
+            node_tun: self.node_tun.clone(),
+            peer_interfaces: self.peer_interfaces.clone(),
+        }
+    }
+
+    /// Get the [`NeighbourInfo`] of all peers known on the router.
+    pub fn neighbours(&self) -> Vec<NeighbourInfo> {
+        self.peer_interfaces
+            .read()
+            .unwrap()
+            .iter()
+            .map(|peer| {
+                let link_cost = peer.link_cost();
+                let last_hello = peer.last_hello();
+                NeighbourInfo {
+                    neighbour: peer.connection_identifier().clone(),
+                    rx_cost: link_cost,
+                    tx_cost: peer.tx_cost().map(Into::into),
+                    link_cost,
+                    rtt: peer.rtt(),
+                    hello_reception: peer.hello_reception(),
+                    last_hello_seqno: last_hello.map(|(seqno, _)| seqno.into()),
+                    last_hello: last_hello.map(|(_, time)| time.elapsed()),
+                    hello_interval: peer.hello_interval(),
+                    last_ihu: peer.time_last_received_ihu().elapsed(),
+                    ihu_interval: peer.ihu_interval(),
+                }
+            })
+            .collect()
+    }
+
     /// Add a peer interface to the router.
     pub fn add_peer_interface(&self, peer: Peer) {
         debug!("Adding peer {} to router", peer.connection_identifier());
+        #[cfg(feature = "testing")]
+        peer.set_fault_injector(self.fault_injector.clone());
         self.peer_interfaces.write().unwrap().push(peer.clone());
         self.metrics.router_peer_added();
+        self.event_log.record(RoutingEvent::PeerUp {
+            connection: peer.connection_identifier().clone(),
+        });
 
         // Request route table dump of peer
         debug!(
             "Requesting route table dump from {}",
             peer.connection_identifier()
         );
-        if let Err(e) = peer.send_control_packet(RouteRequest::new(None).into()) {
+        if let Err(e) = self.send_control_packet(&peer, RouteRequest::new(None).into()) {
             error!(
                 "Failed to request route table dump from {}: {e}",
                 peer.connection_identifier()
@@ -200,6 +673,48 @@ where
         }
     }
 
+    /// Get the [`Metrics`] implementation used by the router.
+    pub(crate) fn metrics(&self) -> &M {
+        &self.metrics
+    }
+
+    /// Get the logged routing events which happened after `since`, or all logged events if it is
+    /// not set, oldest first.
+    pub fn routing_events(&self, since: Option<SystemTime>) -> Vec<LoggedEvent> {
+        self.event_log.events(since)
+    }
+
+    /// Get a snapshot of the node-wide counters of the handled traffic.
+    pub fn stats(&self) -> NodeStats {
+        self.stats.snapshot()
+    }
+
+    /// Record a dropped data packet, both in the [`Metrics`] and the node-wide counters.
+    pub(crate) fn packet_dropped(&self, reason: DropReason) {
+        self.metrics.packet_dropped(reason);
+        self.stats.dropped(reason);
+    }
+
+    /// Send a control packet to a peer, and record it in the node-wide counters.
+    ///
+    /// Control packets holding a source prefix are silently left out for peers which did not
+    /// announce they can decode them, since those would fail to decode the rest of the packet.
+    fn send_control_packet(
+        &self,
+        peer: &Peer,
+        control_packet: ControlPacket,
+    ) -> Result<(), Box<dyn Error>> {
+        if control_packet.source_prefix().is_some() && !peer.accepts_source_specific_routes() {
+            trace!(
+                "Not sending source-specific control packet to {}",
+                peer.connection_identifier()
+            );
+            return Ok(());
+        }
+        self.stats.control_sent(&control_packet);
+        Ok(peer.send_control_packet(control_packet)?)
+    }
+
     /// Get the public key used by the router
     pub fn node_public_key(&self) -> PublicKey {
         self.node_keypair.1
@@ -246,6 +761,18 @@ where
         &self.dead_peer_sink
     }
 
+    /// Get the [`PeerConfig`] for a new [`Peer`] of this `Router`.
+    pub fn peer_config(&self) -> PeerConfig {
+        PeerConfig {
+            queue_sizes: self.queue_sizes,
+            ..PeerConfig::new(
+                self.router_data_tx(),
+                self.router_control_tx(),
+                self.dead_peer_sink.clone(),
+            )
+        }
+    }
+
     /// Remove a peer from the Router.
     fn remove_peer_interface(&self, peer: &Peer) {
         debug!(
@@ -256,8 +783,10 @@ where
         self.metrics.router_peer_removed();
     }
 
-    /// Get a list of all selected route entries.
+    /// Get a list of all selected route entries. The metric of the routes includes the
+    /// configured metric offset.
     pub fn load_selected_routes(&self) -> Vec<RouteEntry> {
+        let metric_offsets = self.metric_offsets.read().unwrap();
         let inner = self
             .inner_r
             .enter()
@@ -267,12 +796,46 @@ where
             .routing_table
             .iter()
             .filter(|(_, _, re)| re.selected())
-            .map(|(_, _, re)| re.clone())
+            .map(|(_, _, re)| self.with_metric_offset(re, &metric_offsets))
             .collect()
     }
 
-    /// Get a list of all fallback route entries.
+    /// Get the amount of selected routes.
+    pub fn selected_route_count(&self) -> usize {
+        self.inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles")
+            .routing_table
+            .iter()
+            .filter(|(_, _, re)| re.selected())
+            .count()
+    }
+
+    /// Get the amount of selected routes per neighbour, keyed by the connection identifier of the
+    /// neighbour.
+    pub fn selected_routes_per_peer(&self) -> HashMap<String, usize> {
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+
+        let mut counts = HashMap::new();
+        for (_, _, re) in inner
+            .routing_table
+            .iter()
+            .filter(|(_, _, re)| re.selected())
+        {
+            *counts
+                .entry(re.neighbour().connection_identifier().clone())
+                .or_default() += 1;
+        }
+        counts
+    }
+
+    /// Get a list of all fallback route entries. The metric of the routes includes the configured
+    /// metric offset.
     pub fn load_fallback_routes(&self) -> Vec<RouteEntry> {
+        let metric_offsets = self.metric_offsets.read().unwrap();
         let inner = self
             .inner_r
             .enter()
@@ -282,15 +845,51 @@ where
             .routing_table
             .iter()
             .filter(|(_, _, re)| !re.selected())
-            .map(|(_, _, re)| re.clone())
+            .map(|(_, _, re)| self.with_metric_offset(re, &metric_offsets))
+            .collect()
+    }
+
+    /// Get a list of all route entries, including routes which can't be selected, together with
+    /// their [`RouteState`]. The metric of the routes includes the configured metric offset.
+    pub fn load_routes(&self) -> Vec<(RouteEntry, RouteState)> {
+        let metric_offsets = self.metric_offsets.read().unwrap();
+        // Lock the source table before entering the read handle, see
+        // [`Router::select_forwarding_route`].
+        let source_table = self.source_table.read().unwrap();
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+
+        inner
+            .routing_table
+            .iter()
+            .map(|(_, _, re)| {
+                // A retracted route can still be selected, until the retraction is propagated.
+                let state = if re.metric().is_infinite() {
+                    RouteState::Retracted
+                } else if re.pinned() {
+                    RouteState::Pinned
+                } else if re.selected() {
+                    RouteState::Selected
+                } else if !source_table.route_feasible(re) {
+                    RouteState::Unfeasible
+                } else {
+                    RouteState::Fallback
+                };
+                (self.with_metric_offset(re, &metric_offsets), state)
+            })
             .collect()
     }
 
     /// Task which periodically checks for dead peers in the Router.
     async fn check_for_dead_peers(self) {
+        let dead_peer_threshold = self.intervals.dead_peer_threshold();
+        let degraded_peer_threshold = self.intervals.degraded_peer_threshold();
+        let mut check_interval =
+            periodic_interval(DEAD_PEER_CHECK_INTERVAL.min(self.intervals.hello));
         loop {
-            // check for dead peers every second
-            tokio::time::sleep(DEAD_PEER_CHECK_INTERVAL).await;
+            check_interval.tick().await;
 
             trace!("Checking for dead peers");
 
@@ -298,13 +897,21 @@ where
                 // a peer is assumed dead when the peer's last sent ihu exceeds a threshold
                 let mut dead_peers = Vec::new();
                 for peer in self.peer_interfaces.read().unwrap().iter() {
+                    peer.check_hello_timeout(&self.hello_history);
+                    let ihu_age = peer.time_last_received_ihu().elapsed();
                     // check if the peer's last_received_ihu is greater than the threshold
-                    if peer.time_last_received_ihu().elapsed() > DEAD_PEER_THRESHOLD {
+                    if ihu_age > dead_peer_threshold {
                         // peer is dead
-                        info!("Peer {} is dead", peer.connection_identifier());
+                        info!(
+                            "Peer {} is dead, no IHU received for {}s",
+                            peer.connection_identifier(),
+                            dead_peer_threshold.as_secs()
+                        );
                         // Notify peer it's dead in case it's not aware of that yet.
-                        peer.died();
+                        peer.died(DisconnectReason::HelloTimeout);
                         dead_peers.push(peer.clone());
+                    } else if ihu_age > degraded_peer_threshold {
+                        peer.degraded();
                     }
                 }
                 dead_peers
@@ -316,1703 +923,4409 @@ where
         }
     }
 
-    /// Remove a dead peer from the router.
-    pub fn handle_dead_peer(&self, dead_peer: Peer) {
-        self.metrics.router_peer_died();
-        debug!(
-            "Cleaning up peer {} which is reportedly dead",
-            dead_peer.connection_identifier()
-        );
+    /// Subscribe to changes of the selected routes in the router.
+    ///
+    /// Events are buffered for a limited time. If the subscriber does not keep up, the oldest
+    /// events are dropped, and the receiver will return a
+    /// [`Lagged`](broadcast::error::RecvError::Lagged) error.
+    pub fn subscribe_route_events(&self) -> broadcast::Receiver<RouteEvent> {
+        self.route_events.subscribe()
+    }
 
-        // Scope for the mutex lock
-        let subnets_to_select = {
-            let mut inner_w = self.inner_w.lock().unwrap();
+    /// Publish pending changes to the routing table. Changes to the selected route of the given
+    /// subnets and source prefixes are recorded for flap damping and in the event log, and
+    /// subscribers to route events are notified of them.
+    fn publish_routing_table(
+        &self,
+        inner_w: &mut WriteHandle<RouterInner, RouterOpLogEntry>,
+        subnets: &[(Subnet, Option<Subnet>)],
+    ) {
+        let selected_route = |inner_w: &WriteHandle<RouterInner, RouterOpLogEntry>,
+                              (subnet, source_prefix)| {
+            inner_w
+                .enter()
+                .expect("We enter through a write handle so this can never be None")
+                .routing_table
+                .entries(subnet, source_prefix)
+                .into_iter()
+                .find(|re| re.selected())
+        };
 
-            let inner = self.inner_r.enter().expect(
-                "Write handle is saved on the router so it is not dropped before the read handles",
-            );
+        let old_routes = subnets
+            .iter()
+            .map(|subnet| selected_route(inner_w, *subnet))
+            .collect::<Vec<_>>();
 
-            let mut subnets_to_select = Vec::new();
+        inner_w.publish();
 
-            for (rk, _, re) in inner.routing_table.iter() {
-                if rk.neighbour() == &dead_peer {
-                    if re.selected() {
-                        subnets_to_select.push(rk.subnet());
-                        inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
-                            rk,
-                            re.seqno(),
-                            Metric::infinite(),
-                            re.source().router_id(),
-                            RETRACTED_ROUTE_HOLD_TIME,
-                        ));
-                    } else {
-                        inner_w.append(RouterOpLogEntry::RemoveRoute(rk));
-                    }
-                }
-            }
-            // Make sure we release the read handle, so a publish on the write handle eventually
-            // succeeds.
-            drop(inner);
-            inner_w.publish();
-            self.remove_peer_interface(&dead_peer);
+        for (subnet, old_route) in subnets.iter().zip(old_routes) {
+            let new_route = selected_route(inner_w, *subnet);
+            self.record_route_change(old_route.as_ref(), new_route.as_ref());
+            self.log_route_change(old_route.as_ref(), new_route.as_ref());
 
-            subnets_to_select
-        };
+            // Don't bother sending events if nobody is interested in the changes.
+            if self.route_events.receiver_count() == 0 {
+                continue;
+            }
 
-        // And run required route selection
-        for subnet in subnets_to_select {
-            self.route_selection(subnet);
+            let (kind, route) = match (old_route, new_route) {
+                (None, Some(new)) => (RouteEventKind::Added, new),
+                (Some(old), None) => (RouteEventKind::Removed, old),
+                (Some(old), Some(new))
+                    if old.neighbour() != new.neighbour()
+                        || old.metric() != new.metric()
+                        || old.seqno() != new.seqno() =>
+                {
+                    (RouteEventKind::Changed, new)
+                }
+                _ => continue,
+            };
+            // This only fails if all subscribers are gone in the meantime.
+            let _ = self.route_events.send(RouteEvent { kind, route });
         }
     }
 
-    /// Run route selection for a given subnet.
-    ///
-    /// This will cause a triggered update if needed.
-    fn route_selection(&self, subnet: Subnet) {
-        self.metrics.router_route_selection_ran();
-        debug!("Running route selection for {subnet}");
-        let mut inner_w = self.inner_w.lock().unwrap();
-
-        let routes = inner_w
-            .enter()
-            .expect("Deref through write handle so there always is a write handle in scope here")
-            .routing_table
-            .entries(subnet);
+    /// Log a change of the selected route of a subnet in the event log, if a route through a
+    /// different neighbour got selected, or the subnet no longer has a usable route.
+    fn log_route_change(&self, old_route: Option<&RouteEntry>, new_route: Option<&RouteEntry>) {
+        let old_route = old_route.filter(|re| !re.metric().is_infinite());
+        let new_route = new_route.filter(|re| !re.metric().is_infinite());
+        let event = match (old_route, new_route) {
+            (Some(old), Some(new)) if old.neighbour() == new.neighbour() => return,
+            (_, Some(new)) => RoutingEvent::RouteSelected {
+                subnet: new.source().subnet(),
+                source_prefix: new.source().source_prefix(),
+                next_hop: new.neighbour().connection_identifier().clone(),
+                metric: new.metric().into(),
+                seqno: new.seqno().into(),
+            },
+            (Some(old), None) => RoutingEvent::RouteRetracted {
+                subnet: old.source().subnet(),
+                source_prefix: old.source().source_prefix(),
+                next_hop: old.neighbour().connection_identifier().clone(),
+            },
+            (None, None) => return,
+        };
+        self.event_log.record(event);
+    }
 
-        // No routes for subnet, nothing to do here.
-        if routes.is_empty() {
-            return;
+    /// Record a change of the selected route of a subnet for flap damping, if the route through a
+    /// neighbour became usable or is no longer usable. A retracted route is not usable, even if it
+    /// is still selected.
+    fn record_route_change(&self, old_route: Option<&RouteEntry>, new_route: Option<&RouteEntry>) {
+        let old_route = old_route.filter(|re| !re.metric().is_infinite());
+        let new_route = new_route.filter(|re| !re.metric().is_infinite());
+        match (old_route, new_route) {
+            (Some(old), Some(new)) if old.neighbour() == new.neighbour() => {}
+            (old, new) => {
+                for re in [old, new].into_iter().flatten() {
+                    self.flap_damping.record_change(
+                        re.source().subnet(),
+                        re.source().source_prefix(),
+                        &re.neighbour().identifier(),
+                    );
+                }
+            }
         }
+    }
 
-        // If there is no selected route there is nothing to do here. We keep expired routes in the
-        // table for a while so updates of those should already have propagated to peers.
-        if let Some(new_selected) = self.find_best_route(
-            &routes,
-            if routes[0].selected() {
-                Some(&routes[0])
-            } else {
-                None
-            },
-        ) {
-            if new_selected.neighbour() == routes[0].neighbour() && routes[0].selected() {
-                debug!(
-                    "New selected route for {subnet} is the same as the route alreayd installed"
-                );
-                return;
-            }
+    /// List the routes which are currently suppressed because they changed too often.
+    pub fn suppressed_routes(&self) -> Vec<SuppressedRoute> {
+        self.flap_damping.suppressed_routes()
+    }
 
-            if new_selected.metric().is_infinite()
-                && routes[0].metric().is_infinite()
-                && routes[0].selected()
-            {
-                debug!("New selected route for {subnet} is retracted, like the previously selected route");
+    /// Start draining the router before it is shut down. Transit routes, i.e. routes to subnets
+    /// of other nodes, are advertised with a near infinite metric from now on, so neighbours stop
+    /// using this router as next hop if they have another route. Routes to the local subnets are
+    /// advertised as before, so this node stays reachable. Draining can't be stopped.
+    pub fn start_drain(&self) {
+        {
+            let mut drain = self.drain.write().unwrap();
+            if drain.is_some() {
                 return;
             }
-
-            inner_w.append(RouterOpLogEntry::SelectRoute(RouteKey::new(
-                subnet,
-                new_selected.neighbour().clone(),
-            )));
-        } else if routes[0].selected() {
-            // This means we went from a selected route to a non-selected route. Unselect route and
-            // trigger update.
-            // At this point we also send a seqno request to all peers which advertised this route
-            // to us, to try and get an updated entry. This uses the source key of the unselected
-            // entry.
-            self.send_seqno_request(routes[0].source(), None, None);
-            inner_w.append(RouterOpLogEntry::UnselectRoute(RouteKey::new(
-                subnet,
-                routes[0].neighbour().clone(),
-            )));
+            info!("Draining router, advertising transit routes with near infinite metric");
+            *drain = Some(DrainState {
+                started: Instant::now(),
+                transit_packets: 0,
+                last_transit_packet: None,
+            });
         }
 
-        inner_w.publish();
+        // Don't wait for the periodic updates, so neighbours switch to other routes right away.
+        self.propagate_selected_routes_to_peers();
+    }
 
-        self.trigger_update(subnet, None);
+    /// Get the [`DrainStatus`] of the router, if it is draining.
+    pub fn drain_status(&self) -> Option<DrainStatus> {
+        self.drain.read().unwrap().as_ref().map(|drain| {
+            let quiet_since = drain.last_transit_packet.unwrap_or(drain.started);
+            DrainStatus {
+                draining_for: drain.started.elapsed(),
+                transit_packets: drain.transit_packets,
+                drained: quiet_since.elapsed() >= DRAIN_QUIET_PERIOD,
+            }
+        })
     }
 
-    /// Remove expired source keys from the router state.
-    async fn process_expired_source_keys(
-        self,
-        mut expired_source_key_stream: mpsc::Receiver<SourceKey>,
-    ) {
-        while let Some(sk) = expired_source_key_stream.recv().await {
-            debug!("Removing expired source entry {sk}");
-            self.source_table.write().unwrap().remove(&sk);
-            self.metrics.router_source_key_expired();
-        }
-        warn!("Expired source key processing halted");
+    /// Get the [`Cipher`] negotiated to encrypt data for the node with the given overlay address,
+    /// if data was received from it.
+    pub fn cipher(&self, remote: Ipv6Addr) -> Option<Cipher> {
+        self.ciphers
+            .read()
+            .expect("Ciphers lock is not poisoned; qed")
+            .get(&node_subnet(remote))
+            .copied()
     }
 
-    /// Remove expired route keys from the router state.
-    async fn process_expired_route_keys(
-        self,
-        mut expired_route_key_stream: mpsc::Receiver<(RouteKey, RouteExpirationType)>,
-    ) {
-        while let Some((rk, expiration_type)) = expired_route_key_stream.recv().await {
-            self.metrics
-                .router_route_key_expired(matches!(expiration_type, RouteExpirationType::Remove));
-            debug!("Got expiration event for route {rk}");
-            let subnet = rk.subnet();
-            let mut inner = self.inner_w.lock().unwrap();
-            // Load current key
-            let entry = inner
-                .enter()
-                .expect("We enter through a write handle so this can never be None")
-                .routing_table
-                .get(&rk)
-                .cloned();
-            if entry.is_none() {
-                continue;
-            }
-            let entry = entry.unwrap();
-            if !entry.metric().is_infinite()
-                && matches!(expiration_type, RouteExpirationType::Retract)
-            {
-                debug!("Route {rk} expired, increasing metric to infinity");
-                inner.append(RouterOpLogEntry::UpdateRouteEntry(
-                    rk,
-                    entry.seqno(),
-                    Metric::infinite(),
-                    entry.source().router_id(),
-                    RETRACTED_ROUTE_HOLD_TIME,
-                ));
-            } else if entry.metric().is_infinite()
-                && matches!(expiration_type, RouteExpirationType::Remove)
-            {
-                debug!("Route {rk} expired, removing retracted route");
-                inner.append(RouterOpLogEntry::RemoveRoute(rk));
-            } else {
-                continue;
-            }
-            inner.publish();
-            // Re run route selection if this was the selected route. We should do this before
-            // publishing to potentially select a new route, however a time based expiraton of a
-            // selected route generally means no other routes are viable anyway, so the short lived
-            // black hole this could create is not really a concern.
-            if entry.selected() {
-                self.metrics.router_selected_route_expired();
-                let routes = inner
-                    .enter()
-                    .expect("We enter through a write handle so this can never be None")
-                    .routing_table
-                    .entries(subnet);
-                // Only inject selected route if we are simply retracting it, otherwise it is
-                // actually already removed.
-                if let Some(r) = self.find_best_route(
-                    &routes,
-                    if matches!(expiration_type, RouteExpirationType::Retract) {
-                        Some(&entry)
-                    } else {
-                        None
-                    },
-                ) {
-                    debug!("Rerun route selection after expiration event");
-                    inner
-                        .append(RouterOpLogEntry::SelectRoute(RouteKey::new(
-                            subnet,
-                            r.neighbour().clone(),
-                        )))
-                        .publish();
-                    // If the entry wasn't retracted yet, notify our peers.
-                    if !entry.metric().is_infinite() {
-                        self.trigger_update(subnet, None);
-                    }
-                }
-            }
-        }
-        warn!("Expired route key processing halted");
+    /// Get the [`Cipher`] negotiated to encrypt data for every node from which data was received,
+    /// by the subnet of the node.
+    pub fn ciphers(&self) -> HashMap<Subnet, Cipher> {
+        self.ciphers
+            .read()
+            .expect("Ciphers lock is not poisoned; qed")
+            .clone()
     }
 
-    /// Process notifications about peers who are dead. This allows peers who can self-diagnose
-    /// connection states to notify us, and allow for more efficient cleanup.
-    async fn process_dead_peers(self, mut dead_peer_stream: mpsc::Receiver<Peer>) {
-        while let Some(dead_peer) = dead_peer_stream.recv().await {
-            self.handle_dead_peer(dead_peer);
+    /// Record the [`Cipher`] negotiated to encrypt data for the node with the given overlay
+    /// address.
+    pub(crate) fn set_cipher(&self, remote: Ipv6Addr, cipher: Cipher) {
+        // Avoid taking the write lock if nothing changes, which is by far the common case.
+        if self.cipher(remote) == Some(cipher) {
+            return;
         }
-        warn!("Processing of dead peers halted");
+
+        self.ciphers
+            .write()
+            .expect("Ciphers lock is not poisoned; qed")
+            .insert(node_subnet(remote), cipher);
     }
 
-    /// Task which ingests and processes control packets. This spawns another background task for
-    /// every TLV type, and forwards the inbound packets to the proper background task.
-    async fn handle_incoming_control_packet(
-        self,
-        mut router_control_rx: UnboundedReceiver<(ControlPacket, Peer)>,
-    ) {
-        let (hello_tx, hello_rx) = mpsc::unbounded_channel();
-        let (ihu_tx, ihu_rx) = mpsc::unbounded_channel();
-        let (update_tx, update_rx) = mpsc::unbounded_channel();
-        let (rr_tx, rr_rx) = mpsc::unbounded_channel();
-        let (sn_tx, sn_rx) = mpsc::unbounded_channel();
-
-        tokio::spawn(self.clone().hello_processor(hello_rx));
-        tokio::spawn(self.clone().ihu_processor(ihu_rx));
-        tokio::spawn(self.clone().update_processor(update_rx));
-        tokio::spawn(self.clone().route_request_processor(rr_rx));
-        tokio::spawn(self.clone().seqno_request_processor(sn_rx));
+    /// Forget the [`Cipher`]s negotiated with nodes to which there is no route anymore. If such a
+    /// node becomes reachable again, the cipher is negotiated again once data is received from
+    /// it. This must be called after routes are removed from the routing table.
+    fn prune_ciphers(&self) {
+        let mut ciphers = self
+            .ciphers
+            .write()
+            .expect("Ciphers lock is not poisoned; qed");
+        if ciphers.is_empty() {
+            return;
+        }
 
-        while let Some((control_packet, source_peer)) = router_control_rx.recv().await {
-            // First update metrics with the remaining outstanding TLV's
-            self.metrics.router_received_tlv();
-            trace!(
-                "Received control packet from {}",
-                source_peer.connection_identifier()
-            );
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+        ciphers.retain(|subnet, _| !inner.routing_table.entries(*subnet, None).is_empty());
+    }
 
-            // Route packet to proper work queue.
-            match control_packet {
-                babel::Tlv::Hello(hello) => {
-                    if hello_tx.send((hello, source_peer)).is_err() {
-                        break;
-                    };
-                }
-                babel::Tlv::Ihu(ihu) => {
-                    if ihu_tx.send((ihu, source_peer)).is_err() {
-                        break;
-                    };
-                }
-                babel::Tlv::Update(update) => {
-                    if update_tx.send((update, source_peer)).is_err() {
-                        break;
-                    };
-                }
-                babel::Tlv::RouteRequest(route_request) => {
-                    if rr_tx.send((route_request, source_peer)).is_err() {
-                        break;
-                    };
-                }
-                babel::Tlv::SeqNoRequest(seqno_request) => {
-                    if sn_tx.send((seqno_request, source_peer)).is_err() {
-                        break;
-                    };
-                }
-            }
-        }
+    /// Get the MTU of the path to the given overlay address, if it is known to be smaller than a
+    /// regular packet. This is the maximum size of the payload of a packet before encryption.
+    pub(crate) fn path_mtu(&self, dst: Ipv6Addr) -> Option<usize> {
+        self.path_mtu.get(dst, tokio::time::Instant::now())
     }
 
-    /// Background task to process hello TLV's.
-    async fn hello_processor(self, mut hello_rx: UnboundedReceiver<(Hello, Peer)>) {
-        while let Some((hello, source_peer)) = hello_rx.recv().await {
-            let start = std::time::Instant::now();
+    /// Record the MTU of the path to the given overlay address, as reported in a Packet Too Big
+    /// message.
+    pub(crate) fn update_path_mtu(&self, dst: Ipv6Addr, mtu: usize) {
+        debug!("Path MTU to {dst} is {mtu}");
+        self.path_mtu.update(dst, mtu, tokio::time::Instant::now());
+    }
 
-            if !source_peer.alive() {
-                trace!("Dropping Hello TLV since sender is dead.");
-                self.metrics.router_tlv_source_died();
-                continue;
-            }
+    /// Get the configured [`MetricOffsets`].
+    pub fn metric_offsets(&self) -> MetricOffsets {
+        self.metric_offsets.read().unwrap().clone()
+    }
 
-            self.handle_incoming_hello(hello, source_peer);
+    /// Replace the [`MetricOffsets`]. Route selection is run again for all subnets, so the new
+    /// offsets apply immediately.
+    pub fn set_metric_offsets(&self, metric_offsets: MetricOffsets) {
+        *self.metric_offsets.write().unwrap() = metric_offsets;
+        self.reselect_routes();
+    }
 
-            self.metrics
-                .router_time_spent_handling_tlv(start.elapsed(), "hello");
+    /// Run route selection again for every subnet in the routing table, e.g. because the inputs
+    /// of the route selection other than the routes themselves changed.
+    pub fn reselect_routes(&self) {
+        let mut keys = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles")
+            .routing_table
+            .iter()
+            .map(|(rk, _, _)| (rk.subnet(), rk.source_prefix()))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        for (subnet, source_prefix) in keys {
+            self.route_selection(subnet, source_prefix);
         }
     }
 
-    /// Background task to process IHU TLV's.
-    async fn ihu_processor(self, mut ihu_rx: UnboundedReceiver<(Ihu, Peer)>) {
-        while let Some((ihu, source_peer)) = ihu_rx.recv().await {
-            let start = std::time::Instant::now();
+    /// Get the configured [`PinnedRoute`]s.
+    pub fn pinned_routes(&self) -> Vec<PinnedRoute> {
+        self.pinned_routes.read().unwrap().clone()
+    }
 
-            if !source_peer.alive() {
-                trace!("Dropping IHU TLV since sender is dead.");
-                self.metrics.router_tlv_source_died();
-                continue;
-            }
+    /// Add [`PinnedRoute`]s, replacing existing pinned routes for the same subnets. Routes
+    /// through connected peers are selected and advertised right away. If any route is invalid,
+    /// none of the routes are added.
+    pub fn add_pinned_routes(&self, routes: Vec<PinnedRoute>) -> Result<(), InvalidPinnedRoute> {
+        if !routes.iter().all(PinnedRoute::is_valid) {
+            return Err(InvalidPinnedRoute);
+        }
 
-            self.handle_incoming_ihu(ihu, source_peer);
+        for route in routes {
+            let replaced = self
+                .pinned_routes
+                .read()
+                .unwrap()
+                .iter()
+                .any(|pr| pr.subnet == route.subnet && pr.next_hop != route.next_hop);
+            // The route through the old next hop must be removed before the new one is installed.
+            if replaced {
+                let _ = self.remove_pinned_route(route.subnet);
+            }
+            let mut pinned_routes = self.pinned_routes.write().unwrap();
+            pinned_routes.retain(|pr| pr.subnet != route.subnet);
+            pinned_routes.push(route);
+        }
 
-            self.metrics
-                .router_time_spent_handling_tlv(start.elapsed(), "ihu");
+        for peer in self.peer_interfaces() {
+            self.install_pinned_routes(&peer);
         }
+
+        Ok(())
     }
 
-    /// Background task to process Update TLV's.
-    async fn update_processor(self, mut update_rx: UnboundedReceiver<(Update, Peer)>) {
-        while let Some((update, source_peer)) = update_rx.recv().await {
-            let start = std::time::Instant::now();
+    /// Remove the [`PinnedRoute`] for a [`Subnet`]. Route selection runs again for the subnet, so
+    /// a route learned from a peer is selected if there is one, otherwise the route is retracted.
+    pub fn remove_pinned_route(&self, subnet: Subnet) -> Result<(), PinnedRouteNotFound> {
+        {
+            let mut pinned_routes = self.pinned_routes.write().unwrap();
+            let len = pinned_routes.len();
+            pinned_routes.retain(|pr| pr.subnet != subnet);
+            if pinned_routes.len() == len {
+                return Err(PinnedRouteNotFound);
+            }
+        }
 
-            if !source_peer.alive() {
-                trace!("Dropping Update TLV since sender is dead.");
-                self.metrics.router_tlv_source_died();
-                continue;
+        let learned_routes = {
+            let mut inner_w = self.inner_w.lock().unwrap();
+            let routes = inner_w
+                .enter()
+                .expect("We enter through a write handle so this can never be None")
+                .routing_table
+                .entries(subnet, None);
+            for re in routes.iter().filter(|re| re.pinned()) {
+                inner_w.append(RouterOpLogEntry::RemoveRoute(RouteKey::new(
+                    subnet,
+                    re.neighbour().clone(),
+                )));
             }
+            self.publish_routing_table(&mut inner_w, &[(subnet, None)]);
 
-            self.handle_incoming_update(update, source_peer);
+            routes.iter().any(|re| !re.pinned())
+        };
+        self.prune_ciphers();
 
-            self.metrics
-                .router_time_spent_handling_tlv(start.elapsed(), "update");
+        if learned_routes {
+            self.route_selection(subnet, None);
+        } else {
+            // Without any routes left, route selection does nothing, so the retraction is sent
+            // here.
+            self.trigger_update(subnet, None, None);
         }
+
+        Ok(())
     }
 
-    /// Background task to process Route Request TLV's.
-    async fn route_request_processor(self, mut rr_rx: UnboundedReceiver<(RouteRequest, Peer)>) {
-        while let Some((rr, source_peer)) = rr_rx.recv().await {
-            let start = std::time::Instant::now();
+    /// Install and select the [`PinnedRoute`]s which have the given [`Peer`] as next hop. This
+    /// does nothing if the overlay address of the peer is not known yet.
+    fn install_pinned_routes(&self, peer: &Peer) {
+        let Some(peer_key) = peer.public_key() else {
+            return;
+        };
+        let peer_address = IpAddr::from(peer_key.address());
+        let routes = self
+            .pinned_routes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|pr| pr.next_hop == peer_address)
+            .copied()
+            .collect::<Vec<_>>();
+        if routes.is_empty() {
+            return;
+        }
 
-            if !source_peer.alive() {
-                trace!("Dropping Route request TLV since sender is dead.");
-                self.metrics.router_tlv_source_died();
-                continue;
+        {
+            let mut inner_w = self.inner_w.lock().unwrap();
+            let shared_secret = self.node_keypair.0.shared_secret(&peer_key);
+            for route in &routes {
+                debug!(
+                    "Installing pinned route for {} via {}",
+                    route.subnet,
+                    peer.connection_identifier()
+                );
+                let rk = RouteKey::new(route.subnet, peer.clone());
+                // We originate the route, so it gets our router id and seqno. The link cost to
+                // the peer is added to the metric when the route is advertised.
+                let mut re = RouteEntry::new(
+                    SourceKey::new(route.subnet, self.router_id),
+                    peer.clone(),
+                    Metric::new(0),
+                    self.router_seqno(),
+                    false,
+                    PINNED_ROUTE_HOLD_TIME,
+                );
+                re.set_pinned(true);
+                inner_w.append(RouterOpLogEntry::InsertRoute(
+                    rk.clone(),
+                    re,
+                    peer_key,
+                    shared_secret.clone(),
+                ));
+                inner_w.append(RouterOpLogEntry::SelectRoute(rk));
             }
+            self.publish_routing_table(
+                &mut inner_w,
+                &routes
+                    .iter()
+                    .map(|route| (route.subnet, None))
+                    .collect::<Vec<_>>(),
+            );
+        }
 
-            self.handle_incoming_route_request(rr, source_peer);
-
-            self.metrics
-                .router_time_spent_handling_tlv(start.elapsed(), "route_request");
+        for route in routes {
+            self.trigger_update(route.subnet, None, None);
         }
     }
 
-    /// Background task to process Seqno Request TLV's.
-    async fn seqno_request_processor(self, mut sn_rx: UnboundedReceiver<(SeqNoRequest, Peer)>) {
-        while let Some((sn, source_peer)) = sn_rx.recv().await {
-            let start = std::time::Instant::now();
-
-            if !source_peer.alive() {
-                trace!("Dropping Route request TLV since sender is dead.");
-                self.metrics.router_tlv_source_died();
-                continue;
-            }
+    /// Get the [`FaultInjectionConfig`] applied to packets sent to peers.
+    #[cfg(feature = "testing")]
+    pub fn fault_injection(&self) -> FaultInjectionConfig {
+        self.fault_injector.config()
+    }
 
-            self.handle_incoming_seqno_request(sn, source_peer);
+    /// Replace the [`FaultInjectionConfig`] applied to packets sent to peers. This applies to all
+    /// current and future peers.
+    #[cfg(feature = "testing")]
+    pub fn set_fault_injection(&self, config: FaultInjectionConfig) {
+        self.fault_injector.set_config(config)
+    }
 
-            self.metrics
-                .router_time_spent_handling_tlv(start.elapsed(), "seqno");
-        }
+    /// Add the configured metric offset to the metric of a copy of `route`.
+    fn with_metric_offset(&self, route: &RouteEntry, metric_offsets: &MetricOffsets) -> RouteEntry {
+        let mut route = route.clone();
+        route.update_metric(route.metric() + metric_offsets.route_offset(&route));
+        route
     }
 
-    /// Handle a received hello TLV
-    fn handle_incoming_hello(&self, _: babel::Hello, source_peer: Peer) {
-        self.metrics.router_process_hello();
-        // Upon receiving and Hello message from a peer, this node has to send a IHU back
-        // TODO: properly calculate RX cost, for now just set the link cost.
-        let ihu = ControlPacket::new_ihu(source_peer.link_cost().into(), IHU_INTERVAL, None);
-        if source_peer.send_control_packet(ihu).is_err() {
-            trace!(
-                "Failed to send IHU reply to peer: {}",
-                source_peer.connection_identifier()
+    /// Remove a dead peer from the router.
+    ///
+    /// Selected routes through the peer are replaced by the best fallback route, if there is one,
+    /// in the same change of the routing table which retracts them. This way there is no moment
+    /// where packets for those subnets can't be forwarded.
+    pub fn handle_dead_peer(&self, dead_peer: Peer) {
+        self.metrics.router_peer_died();
+        self.event_log.record(RoutingEvent::PeerDown {
+            connection: dead_peer.connection_identifier().clone(),
+        });
+        debug!(
+            "Cleaning up peer {} which is reportedly dead",
+            dead_peer.connection_identifier()
+        );
+
+        // Scope for the mutex lock
+        let (subnets_to_select, failed_over) = {
+            let mut inner_w = self.inner_w.lock().unwrap();
+
+            let inner = self.inner_r.enter().expect(
+                "Write handle is saved on the router so it is not dropped before the read handles",
             );
+
+            let mut subnets_to_select = Vec::new();
+            let mut failed_over = Vec::new();
+
+            for (rk, _, re) in inner.routing_table.iter() {
+                if rk.neighbour() == &dead_peer {
+                    if re.selected() {
+                        subnets_to_select.push((rk.subnet(), rk.source_prefix()));
+                        inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
+                            rk.clone(),
+                            re.seqno(),
+                            Metric::infinite(),
+                            re.source().router_id(),
+                            RETRACTED_ROUTE_HOLD_TIME,
+                        ));
+
+                        let fallbacks = inner
+                            .routing_table
+                            .entries(rk.subnet(), rk.source_prefix())
+                            .into_iter()
+                            .filter(|fallback| fallback.neighbour() != &dead_peer)
+                            .collect::<Vec<_>>();
+                        if let Some(fallback) = self.find_best_route(&fallbacks, None) {
+                            debug!(
+                                "Failing over route for {} to {}",
+                                rk.subnet(),
+                                fallback.neighbour().connection_identifier()
+                            );
+                            inner_w.append(RouterOpLogEntry::SelectRoute(
+                                RouteKey::new(rk.subnet(), fallback.neighbour().clone())
+                                    .with_source_prefix(rk.source_prefix()),
+                            ));
+                            failed_over.push((rk.subnet(), rk.source_prefix()));
+                        }
+                    } else {
+                        inner_w.append(RouterOpLogEntry::RemoveRoute(rk));
+                    }
+                }
+            }
+            // Make sure we release the read handle, so a publish on the write handle eventually
+            // succeeds.
+            drop(inner);
+            self.publish_routing_table(&mut inner_w, &subnets_to_select);
+            self.remove_peer_interface(&dead_peer);
+            self.prune_ciphers();
+
+            (subnets_to_select, failed_over)
+        };
+
+        // Announce the routes which failed over, and run route selection for the subnets which
+        // lost their selected route without a fallback.
+        for (subnet, source_prefix) in subnets_to_select {
+            if failed_over.contains(&(subnet, source_prefix)) {
+                self.trigger_update(subnet, source_prefix, None);
+            } else {
+                self.route_selection(subnet, source_prefix);
+            }
         }
     }
 
-    /// Handle a received IHU TLV
-    fn handle_incoming_ihu(&self, _: babel::Ihu, source_peer: Peer) {
-        self.metrics.router_process_ihu();
-        // reset the IHU timer associated with the peer
-        // measure time between Hello and and IHU and set the link cost
-        let time_diff = tokio::time::Instant::now()
-            .duration_since(source_peer.time_last_received_hello())
-            .as_millis();
+    /// Retract all routes learned from a [`Peer`], in response to a wildcard retraction. The peer
+    /// itself is kept, so it can advertise routes again later.
+    fn handle_wildcard_retraction(&self, source_peer: &Peer) {
+        debug!(
+            "Retracting all routes from {} after wildcard retraction",
+            source_peer.connection_identifier()
+        );
 
-        source_peer.set_link_cost(time_diff as u16);
+        let subnets_to_select = {
+            let mut inner_w = self.inner_w.lock().unwrap();
 
-        // set the last_received_ihu for this peer
-        source_peer.set_time_last_received_ihu(tokio::time::Instant::now());
-    }
+            let inner = self.inner_r.enter().expect(
+                "Write handle is saved on the router so it is not dropped before the read handles",
+            );
 
-    /// Process a route request. We reply with an Update if we have a selected route for the
-    /// requested subnet. If no subnet is requested (in other words the wildcard address), we dump
-    /// the full routing table.
-    fn handle_incoming_route_request(&self, route_request: babel::RouteRequest, source_peer: Peer) {
-        self.metrics
-            .router_process_route_request(route_request.prefix().is_none());
-        // Handle the case of a single subnet.
-        if let Some(subnet) = route_request.prefix() {
-            let update = if let Some(sre) = self
-                .inner_r
-                .enter()
-                .expect("We deref through a write handle so this is always Some; qed")
-                .routing_table
-                .lookup_selected(subnet.address())
-            {
-                trace!("Advertising selected route for {subnet} after route request");
-                // Optimization: Don't send an update if the selected route next-hop is the peer
-                // who requested the route, as per the babel protocol the update will never be
-                // accepted.
-                if sre.neighbour() == &source_peer {
-                    trace!("Not advertising route since the next-hop is the requesting peer");
-                    return;
+            let mut subnets_to_select = Vec::new();
+
+            for (rk, _, re) in inner.routing_table.iter() {
+                // Pinned routes are not learned from the peer, so it can't retract them.
+                if rk.neighbour() != source_peer || re.metric().is_infinite() || re.pinned() {
+                    continue;
                 }
-                babel::Update::new(
-                    advertised_update_interval(sre),
-                    sre.seqno(),
-                    sre.metric() + Metric::from(sre.neighbour().link_cost()),
-                    subnet,
-                    sre.source().router_id(),
-                )
-            }
-            // Could be a request for a static route/subnet.
-            else if let Some(static_route) = self
-                .static_routes
-                .iter()
-                .find(|sr| sr.contains_subnet(&subnet))
-            {
-                trace!(
-                    "Advertising static route {static_route} in response to route request for {subnet}"
-                );
-                babel::Update::new(
-                    UPDATE_INTERVAL, // Static route is advertised with the default interval
-                    self.router_seqno.read().unwrap().0, // Updates receive the seqno of the router
-                    Metric::from(0), // Static route has no further hop costs
-                    *static_route,
-                    self.router_id,
-                )
+                if re.selected() {
+                    subnets_to_select.push((rk.subnet(), rk.source_prefix()));
+                }
+                inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
+                    rk,
+                    re.seqno(),
+                    Metric::infinite(),
+                    re.source().router_id(),
+                    RETRACTED_ROUTE_HOLD_TIME,
+                ));
             }
-            // If the requested route is not present, send a retraction
-            else {
-                trace!(
-                    "Sending retraction for unknown subnet {subnet} in response to route request"
-                );
-                babel::Update::new(
-                    INTERVAL_NOT_REPEATING,
-                    self.router_seqno.read().unwrap().0, // Retractions receive the seqno of the router
-                    Metric::infinite(),                  // Static route has no further hop costs
-                    subnet,                              // Advertise the exact subnet requested
-                    self.router_id, // Our own router ID, since we advertise this
-                )
-            };
+            // Make sure we release the read handle, so a publish on the write handle eventually
+            // succeeds.
+            drop(inner);
+            self.publish_routing_table(&mut inner_w, &subnets_to_select);
 
-            self.send_update(&source_peer, update);
-        } else {
-            // Requested a full route table dump
-            trace!("Dumping route table after wildcard route request");
-            self.propagate_selected_routes_to_peer(&source_peer);
-            self.propagate_static_route_to_peer(&source_peer);
+            subnets_to_select
+        };
+
+        // Select a new route for subnets which used the peer, this also notifies our peers.
+        for (subnet, source_prefix) in subnets_to_select {
+            self.route_selection(subnet, source_prefix);
         }
     }
 
-    /// Handle a received SeqNo request TLV.
-    fn handle_incoming_seqno_request(&self, mut seqno_request: SeqNoRequest, source_peer: Peer) {
-        self.metrics.router_process_seqno_request();
-        // According to the babel rfc, we shoudl maintain a table of recent SeqNo requests and
-        // periodically retry requests without reply. We will however not do this for now and rely
-        // on the fact that we have stable links in general.
+    /// Remove all routes learned from peers, both selected and fallback routes, retract the ones
+    /// which were selected, and request a full route table dump from all peers to learn them
+    /// again. Returns the amount of removed routes. Pinned routes are kept.
+    ///
+    /// Until routes are learned again, packets to the affected subnets can't be forwarded.
+    pub fn flush_routes(&self) -> usize {
+        let (removed, selected_subnets) = {
+            let mut inner_w = self.inner_w.lock().unwrap();
 
-        let inner = self
-            .inner_r
-            .enter()
-            .expect("Write handle is saved on router so it is not dropped before the read handles");
+            let inner = self.inner_r.enter().expect(
+                "Write handle is saved on the router so it is not dropped before the read handles",
+            );
 
-        // If we have a selected route for the prefix, and its router id is different from the
-        // requested router id, or the router id is the same and the entries sequence number is
-        // not smaller than the requested sequence number, send an update for the route
-        // to the peer (triggered update).
-        if let Some(route_entry) = inner
-            .routing_table
-            .lookup_selected(seqno_request.prefix().address())
-        {
-            if !route_entry.metric().is_infinite()
-                && (seqno_request.router_id() != route_entry.source().router_id()
-                    || !route_entry.seqno().lt(&seqno_request.seqno()))
-            {
-                // we have a more up to date route or a different route, send an update
-                debug!(
-                    "Replying to seqno request for seqno {} of {} with update packet",
-                    seqno_request.seqno(),
-                    seqno_request.prefix()
-                );
-                let update = babel::Update::new(
-                    advertised_update_interval(route_entry),
-                    route_entry.seqno(), // updates receive the seqno of the router
-                    route_entry.metric() + Metric::from(source_peer.link_cost()),
-                    // the cost of the route is the cost of the route + the cost of the link to the peer
-                    route_entry.source().subnet(),
-                    // we looked for the router_id, which is a public key, in the dest_pubkey_map
-                    // if the router_id is not in the map, then the route came from the node itself
-                    route_entry.source().router_id(),
-                );
-                drop(inner);
+            let mut removed = 0;
+            let mut selected_subnets = Vec::new();
+            for (rk, _, re) in inner.routing_table.iter() {
+                if re.pinned() {
+                    continue;
+                }
+                if re.selected() {
+                    selected_subnets.push((rk.subnet(), rk.source_prefix()));
+                }
+                inner_w.append(RouterOpLogEntry::RemoveRoute(rk));
+                removed += 1;
+            }
+            // Make sure we release the read handle, so a publish on the write handle eventually
+            // succeeds.
+            drop(inner);
+            self.publish_routing_table(&mut inner_w, &selected_subnets);
 
-                self.send_update(&source_peer, update);
+            (removed, selected_subnets)
+        };
+        self.prune_ciphers();
 
-                self.metrics.router_seqno_request_reply_local();
+        // There is no route left for these subnets, so this sends a retraction. Peers which
+        // route through us must not keep using a route we no longer have.
+        for (subnet, source_prefix) in selected_subnets {
+            self.trigger_update(subnet, source_prefix, None);
+        }
 
-                return;
+        info!("Flushed {removed} routes, requesting route table dumps from peers");
+        for peer in self.peer_interfaces() {
+            if let Err(e) = self.send_control_packet(&peer, RouteRequest::new(None).into()) {
+                error!(
+                    "Failed to request route table dump from {}: {e}",
+                    peer.connection_identifier()
+                );
             }
         }
 
-        // Otherwise, if the router id in the request matches the router id in our selected route
-        // and the requested sequence number is larger than the one on our selected route, compare
-        // the router id with our own router id. If it matches, bump our own sequence number by 1.
-        // At this point, we also send an update for the route (triggered update), to distribute
-        // the route.
-        //
-        // Note that we currently don't install local routes in the routing table and as such
-        // can't check on this. Therefore this condition is reworked. We always advertise local
-        // routes with the current router id and the current router seqno. So we check if the
-        // prefix is part of our static routes, if the router id is our own, and if the
-        // requested seqno is greater than our own.
-        let (router_seqno, last_seqno_bump) = *self.router_seqno.read().unwrap();
-        if seqno_request.router_id() == self.router_id
-            && seqno_request.seqno().gt(&router_seqno)
-            && self.static_routes.contains(&seqno_request.prefix())
-        {
-            if last_seqno_bump.elapsed() >= SEQNO_BUMP_TIMEOUT {
-                trace!("Ignoring seqno bump request which happened too fast");
-                return;
-            }
-            // Bump router seqno
-            // TODO: should we only send an update to the peer who sent the seqno request
-            // instad of updating all our peers?
-            drop(inner);
-            debug!("Bumping local router sequence number");
-            // Scope the write lock on seqno
-            {
-                let mut router_seqno = self.router_seqno.write().unwrap();
-                // First check again if we should bump
-                if router_seqno.1.elapsed() >= SEQNO_BUMP_TIMEOUT {
-                    trace!("Ignoring seqno bump request which happened too fast");
-                    return;
+        removed
+    }
+
+    /// Remove all routes learned from peers to subnets which are not allowed by the given
+    /// [`AccessList`], and retract the ones which were selected. Returns the amount of removed
+    /// routes. Pinned routes are kept.
+    pub fn remove_denied_routes(&self, access_list: &AccessList) -> usize {
+        let (removed, selected_subnets) = {
+            let mut inner_w = self.inner_w.lock().unwrap();
+
+            let inner = self.inner_r.enter().expect(
+                "Write handle is saved on the router so it is not dropped before the read handles",
+            );
+
+            let mut removed = 0;
+            let mut selected_subnets = Vec::new();
+            for (rk, _, re) in inner.routing_table.iter() {
+                if re.pinned() || access_list.allows_subnet(&rk.subnet()) {
+                    continue;
+                }
+                if re.selected() {
+                    selected_subnets.push((rk.subnet(), rk.source_prefix()));
                 }
-                // Bump seqno
-                router_seqno.0 += 1;
-                // Set last modified time
-                router_seqno.1 = Instant::now();
+                inner_w.append(RouterOpLogEntry::RemoveRoute(rk));
+                removed += 1;
             }
+            // Make sure we release the read handle, so a publish on the write handle eventually
+            // succeeds.
+            drop(inner);
+            self.publish_routing_table(&mut inner_w, &selected_subnets);
 
-            self.propagate_static_routes_to_peers();
-
-            self.metrics.router_seqno_request_bump_seqno();
+            (removed, selected_subnets)
+        };
+        self.prune_ciphers();
 
-            return;
+        if removed > 0 {
+            info!("Removed {removed} routes which are no longer allowed by the access list");
         }
-
-        // Otherwise, if the router-id from the request is not our own, we check the hop count
-        // field. If it is at least 2, we decrement it by 1, and forward the packet. To do so, we
-        // try to find a route to the subnet. First we check for a feasible route and send the
-        // packet there if the next hop is not the sender of this packet. Otherwise, we check for
-        // any route which might potentially be unfeasible, which also did not originate the
-        // packet.
-        if seqno_request.hop_count() < 2 {
-            self.metrics.router_seqno_request_dropped_ttl();
-            return;
+        // There is no route left for these subnets, so this sends a retraction.
+        for (subnet, source_prefix) in selected_subnets {
+            self.trigger_update(subnet, source_prefix, None);
         }
 
-        if seqno_request.router_id() != self.router_id {
-            seqno_request.decrement_hop_count();
+        removed
+    }
 
-            let srck = SeqnoRequestCacheKey {
-                router_id: seqno_request.router_id(),
-                subnet: seqno_request.prefix(),
-                seqno: seqno_request.seqno(),
-            };
+    /// Run route selection for a given subnet and source prefix.
+    ///
+    /// This will cause a triggered update if needed.
+    fn route_selection(&self, subnet: Subnet, source_prefix: Option<Subnet>) {
+        self.metrics.router_route_selection_ran();
+        debug!("Running route selection for {subnet}");
+        let mut inner_w = self.inner_w.lock().unwrap();
 
-            let mut visited_peers = vec![];
-            if let Some((last_sent, visited)) = self.seqno_cache.info(&srck) {
-                if last_sent.elapsed() < SEQNO_BUMP_TIMEOUT {
-                    visited_peers = visited;
-                }
-            }
+        let routes = inner_w
+            .enter()
+            .expect("Deref through write handle so there always is a write handle in scope here")
+            .routing_table
+            .entries(subnet, source_prefix);
+
+        // No routes for subnet, nothing to do here.
+        if routes.is_empty() {
+            return;
+        }
 
-            let possible_routes = inner.routing_table.entries(seqno_request.prefix());
+        // If there is no selected route there is nothing to do here. We keep expired routes in the
+        // table for a while so updates of those should already have propagated to peers.
+        if let Some(new_selected) = self.find_best_route(
+            &routes,
+            if routes[0].selected() {
+                Some(&routes[0])
+            } else {
+                None
+            },
+        ) {
+            if new_selected.neighbour() == routes[0].neighbour() && routes[0].selected() {
+                debug!(
+                    "New selected route for {subnet} is the same as the route alreayd installed"
+                );
+                return;
+            }
 
+            if new_selected.metric().is_infinite()
+                && routes[0].metric().is_infinite()
+                && routes[0].selected()
             {
-                let source_table = self.source_table.read().unwrap();
-                // First only consider feasible routes.
-                for re in possible_routes.iter().filter(|re| {
-                    !visited_peers.contains(re.neighbour())
-                        && source_table.route_feasible(re)
-                        && re.neighbour() != &source_peer
-                        && re.neighbour().alive()
-                        && !re.metric().is_infinite()
-                }) {
-                    debug!(
-                        "Forwarding seqno request {} for {} to {}",
-                        seqno_request.seqno(),
-                        seqno_request.prefix(),
-                        re.neighbour().connection_identifier()
-                    );
-                    if re
-                        .neighbour()
-                        .send_control_packet(seqno_request.clone().into())
-                        .is_err()
-                    {
-                        trace!(
-                            "Failed to foward seqno request to {}",
-                            re.neighbour().connection_identifier(),
-                        );
-                        continue;
-                    }
+                debug!("New selected route for {subnet} is retracted, like the previously selected route");
+                return;
+            }
 
-                    self.seqno_cache
-                        .forward(srck, re.neighbour().clone(), Some(source_peer));
+            inner_w.append(RouterOpLogEntry::SelectRoute(
+                RouteKey::new(subnet, new_selected.neighbour().clone())
+                    .with_source_prefix(source_prefix),
+            ));
+        } else if routes[0].selected() {
+            // This means we went from a selected route to a non-selected route. Unselect route and
+            // trigger update.
+            // At this point we also send a seqno request to all peers which advertised this route
+            // to us, to try and get an updated entry. This uses the source key of the unselected
+            // entry.
+            self.send_seqno_request(routes[0].source(), None, None);
+            inner_w.append(RouterOpLogEntry::UnselectRoute(
+                RouteKey::new(subnet, routes[0].neighbour().clone())
+                    .with_source_prefix(source_prefix),
+            ));
+        }
 
-                    self.metrics.router_seqno_request_forward_feasible();
+        self.publish_routing_table(&mut inner_w, &[(subnet, source_prefix)]);
 
-                    return;
-                }
-            }
+        self.trigger_update(subnet, source_prefix, None);
+    }
 
-            // Finally consider infeasible routes as well.
-            for re in possible_routes.iter().filter(|re| {
-                !visited_peers.contains(re.neighbour())
-                    && re.neighbour() != &source_peer
-                    && re.neighbour().alive()
-                    && !re.metric().is_infinite()
-            }) {
-                debug!(
-                    "Forwarding seqno request {} for {} to {}",
-                    seqno_request.seqno(),
-                    seqno_request.prefix(),
-                    re.neighbour().connection_identifier()
-                );
-                if re
-                    .neighbour()
-                    .send_control_packet(seqno_request.clone().into())
-                    .is_err()
-                {
-                    trace!(
-                        "Failed to foward seqno request to infeasible peer {}",
-                        re.neighbour().connection_identifier(),
-                    );
-                    continue;
+    /// Remove expired source keys from the router state.
+    async fn process_expired_source_keys(
+        self,
+        mut expired_source_key_stream: mpsc::Receiver<SourceKey>,
+    ) {
+        while let Some(sk) = expired_source_key_stream.recv().await {
+            debug!("Removing expired source entry {sk}");
+            self.source_table.write().unwrap().remove(&sk);
+            self.metrics.router_source_key_expired();
+            // Without a feasibility distance for the source, fallback routes which were
+            // unfeasible might now be selected.
+            self.route_selection(sk.subnet(), sk.source_prefix());
+        }
+        warn!("Expired source key processing halted");
+    }
+
+    /// Remove expired route keys from the router state.
+    async fn process_expired_route_keys(
+        self,
+        mut expired_route_key_stream: mpsc::Receiver<(RouteKey, RouteExpirationType)>,
+    ) {
+        while let Some((rk, expiration_type)) = expired_route_key_stream.recv().await {
+            self.metrics
+                .router_route_key_expired(matches!(expiration_type, RouteExpirationType::Remove));
+            debug!("Got expiration event for route {rk}");
+            let subnet = rk.subnet();
+            let source_prefix = rk.source_prefix();
+            let mut inner = self.inner_w.lock().unwrap();
+            // Load current key
+            let entry = inner
+                .enter()
+                .expect("We enter through a write handle so this can never be None")
+                .routing_table
+                .get(&rk)
+                .cloned();
+            if entry.is_none() {
+                continue;
+            }
+            let entry = entry.unwrap();
+            if entry.pinned() && !entry.metric().is_infinite() {
+                trace!("Resetting timer of pinned route {rk}");
+                inner.append(RouterOpLogEntry::UpdateRouteEntry(
+                    rk,
+                    entry.seqno(),
+                    entry.metric(),
+                    entry.source().router_id(),
+                    PINNED_ROUTE_HOLD_TIME,
+                ));
+                self.publish_routing_table(&mut inner, &[(subnet, source_prefix)]);
+                continue;
+            }
+            if !entry.metric().is_infinite()
+                && matches!(expiration_type, RouteExpirationType::Retract)
+            {
+                debug!("Route {rk} expired, increasing metric to infinity");
+                inner.append(RouterOpLogEntry::UpdateRouteEntry(
+                    rk,
+                    entry.seqno(),
+                    Metric::infinite(),
+                    entry.source().router_id(),
+                    RETRACTED_ROUTE_HOLD_TIME,
+                ));
+            } else if entry.metric().is_infinite()
+                && matches!(expiration_type, RouteExpirationType::Remove)
+            {
+                debug!("Route {rk} expired, removing retracted route");
+                inner.append(RouterOpLogEntry::RemoveRoute(rk));
+            } else {
+                continue;
+            }
+            self.publish_routing_table(&mut inner, &[(subnet, source_prefix)]);
+            if matches!(expiration_type, RouteExpirationType::Remove) {
+                self.prune_ciphers();
+            }
+            // Re run route selection if this was the selected route. We should do this before
+            // publishing to potentially select a new route, however a time based expiraton of a
+            // selected route generally means no other routes are viable anyway, so the short lived
+            // black hole this could create is not really a concern.
+            if entry.selected() {
+                self.metrics.router_selected_route_expired();
+                let routes = inner
+                    .enter()
+                    .expect("We enter through a write handle so this can never be None")
+                    .routing_table
+                    .entries(subnet, source_prefix);
+                // Only inject selected route if we are simply retracting it, otherwise it is
+                // actually already removed.
+                if let Some(r) = self.find_best_route(
+                    &routes,
+                    if matches!(expiration_type, RouteExpirationType::Retract) {
+                        Some(&entry)
+                    } else {
+                        None
+                    },
+                ) {
+                    debug!("Rerun route selection after expiration event");
+                    inner.append(RouterOpLogEntry::SelectRoute(
+                        RouteKey::new(subnet, r.neighbour().clone())
+                            .with_source_prefix(source_prefix),
+                    ));
+                    self.publish_routing_table(&mut inner, &[(subnet, source_prefix)]);
+                    // If the entry wasn't retracted yet, notify our peers.
+                    if !entry.metric().is_infinite() {
+                        self.trigger_update(subnet, source_prefix, None);
+                    }
                 }
+            }
+        }
+        warn!("Expired route key processing halted");
+    }
 
-                self.seqno_cache
-                    .forward(srck, re.neighbour().clone(), Some(source_peer));
+    /// Process notifications about peers who are dead. This allows peers who can self-diagnose
+    /// connection states to notify us, and allow for more efficient cleanup.
+    async fn process_dead_peers(self, mut dead_peer_stream: mpsc::Receiver<Peer>) {
+        while let Some(dead_peer) = dead_peer_stream.recv().await {
+            self.handle_dead_peer(dead_peer);
+        }
+        warn!("Processing of dead peers halted");
+    }
 
-                self.metrics.router_seqno_request_forward_unfeasible();
+    /// Task which ingests and processes control packets. Hellos and IHUs are handled right away,
+    /// since they are cheap to handle and the liveness of peers depends on them. For the other TLV
+    /// types, another background task is spawned, and the inbound packets are forwarded to it. If
+    /// the queue of such a task is full, dispatching waits until there is space, so peers stop
+    /// reading from their connection instead of control packets being dropped.
+    async fn handle_incoming_control_packet(
+        self,
+        mut router_control_rx: Receiver<(ControlPacket, Peer)>,
+    ) {
+        let (update_tx, update_rx) = mpsc::channel(self.queue_sizes.router_control);
+        let (rr_tx, rr_rx) = mpsc::channel(self.queue_sizes.router_control);
+        let (sn_tx, sn_rx) = mpsc::channel(self.queue_sizes.router_control);
 
-                return;
+        tokio::spawn(self.clone().update_processor(update_rx));
+        tokio::spawn(self.clone().route_request_processor(rr_rx));
+        tokio::spawn(self.clone().seqno_request_processor(sn_rx));
+
+        let queues = ControlQueues {
+            update: update_tx,
+            route_request: rr_tx,
+            seqno_request: sn_tx,
+        };
+
+        while let Some((control_packet, source_peer)) = router_control_rx.recv().await {
+            if !self
+                .dispatch_control_packet(control_packet, source_peer, &queues)
+                .await
+            {
+                break;
             }
         }
-
-        self.metrics.router_seqno_request_unhandled();
     }
 
-    /// Finds the best feasible route from a list of [`route entries`](RouteEntry). It is possible
-    /// for this method to select a retracted route. In this case, retraction updates should be
-    /// send out.
-    ///
-    /// This method only selects a different best route if it is significantly better compared to
-    /// the current route.
-    fn find_best_route<'a>(
+    /// Handle a control packet received from a peer, or forward it to the work queue of its TLV
+    /// type. This waits if that queue is full. Returns false if the task processing the TLV type
+    /// is gone.
+    async fn dispatch_control_packet(
         &self,
-        routes: &'a [RouteEntry],
-        current: Option<&'a RouteEntry>,
-    ) -> Option<&'a RouteEntry> {
-        // Since retracted routes have the highest possible metrics, this will only select one if
-        // no non-retracted routes are feasible.
-        let source_table = self.source_table.read().unwrap();
-        let best = routes
-            .iter()
-            // Infinite metrics are technically feasible, but for route selection we explicitly
-            // don't want infinite metrics as those routes are unreachable.
-            .filter(|re| !re.metric().is_infinite() && source_table.route_feasible(re))
-            .min_by_key(|re| re.metric() + Metric::from(re.neighbour().link_cost()));
-
-        if let (Some(best), Some(current)) = (best, current) {
-            // If we swap to an actually different route, only do so if the metric is
-            // significantly better OR if it is directly connected (metric 0).
-            if (best.source() != current.source() || best.neighbour() != current.neighbour())
-                && !(best.metric() + Metric::from(best.neighbour().link_cost())
-                    < current.metric() + Metric::from(current.neighbour().link_cost())
-                        - SIGNIFICANT_METRIC_IMPROVEMENT
-                    || best.metric().is_direct())
-            {
-                debug!("maintaining currently selected route since new route is not significantly better");
-                return Some(current);
+        control_packet: ControlPacket,
+        source_peer: Peer,
+        queues: &ControlQueues,
+    ) -> bool {
+        // First update metrics with the remaining outstanding TLV's
+        self.metrics.router_received_tlv();
+        self.stats.control_received(&control_packet);
+        trace!(
+            "Received control packet from {}",
+            source_peer.connection_identifier()
+        );
+
+        // Route packet to proper work queue.
+        match control_packet {
+            babel::Tlv::Hello(hello) => {
+                let start = std::time::Instant::now();
+
+                if !source_peer.alive() {
+                    trace!("Dropping Hello TLV since sender is dead.");
+                    self.metrics.router_tlv_source_died();
+                    return true;
+                }
+
+                self.handle_incoming_hello(hello, source_peer);
+
+                self.metrics
+                    .router_time_spent_handling_tlv(start.elapsed(), "hello");
+                true
             }
-        }
+            babel::Tlv::Ihu(ihu) => {
+                let start = std::time::Instant::now();
 
-        best
-    }
+                if !source_peer.alive() {
+                    trace!("Dropping IHU TLV since sender is dead.");
+                    self.metrics.router_tlv_source_died();
+                    return true;
+                }
 
-    /// Handle a received update TLV
-    fn handle_incoming_update(&self, update: babel::Update, source_peer: Peer) {
-        self.metrics.router_process_update();
-        // Check if we actually allow this update based on filters.
-        for filter in &*self.update_filters {
-            if !filter.allow(&update) {
-                debug!("Update denied by filter");
-                return;
+                self.handle_incoming_ihu(ihu, source_peer);
+
+                self.metrics
+                    .router_time_spent_handling_tlv(start.elapsed(), "ihu");
+                true
             }
+            babel::Tlv::Update(update) => queues.update.send((update, source_peer)).await.is_ok(),
+            babel::Tlv::RouteRequest(route_request) => queues
+                .route_request
+                .send((route_request, source_peer))
+                .await
+                .is_ok(),
+            babel::Tlv::SeqNoRequest(seqno_request) => queues
+                .seqno_request
+                .send((seqno_request, source_peer))
+                .await
+                .is_ok(),
         }
+    }
 
-        let metric = update.metric();
-        let router_id = update.router_id();
-        let seqno = update.seqno();
-        let subnet = update.subnet();
+    /// Background task to process Update TLV's.
+    async fn update_processor(self, mut update_rx: Receiver<(Update, Peer)>) {
+        while let Some((update, source_peer)) = update_rx.recv().await {
+            let start = std::time::Instant::now();
 
-        // create route key from incoming update control struct
-        let update_route_key = RouteKey::new(subnet, source_peer.clone());
-        // used later to filter out static route
-        if self.route_key_is_from_static_route(&update_route_key) {
-            return;
+            if !source_peer.alive() {
+                trace!("Dropping Update TLV since sender is dead.");
+                self.metrics.router_tlv_source_died();
+                continue;
+            }
+
+            self.handle_incoming_update(update, source_peer);
+
+            self.metrics
+                .router_time_spent_handling_tlv(start.elapsed(), "update");
         }
+    }
 
-        // We accepted the update, check if we have a seqno request sent for this update
-        let interested_peers = self.seqno_cache.remove(&SeqnoRequestCacheKey {
-            router_id,
-            subnet,
-            seqno,
-        });
+    /// Background task to process Route Request TLV's.
+    async fn route_request_processor(self, mut rr_rx: Receiver<(RouteRequest, Peer)>) {
+        while let Some((rr, source_peer)) = rr_rx.recv().await {
+            let start = std::time::Instant::now();
 
-        let mut inner_w = self.inner_w.lock().expect("Mutex isn't poisoned");
+            if !source_peer.alive() {
+                trace!("Dropping Route request TLV since sender is dead.");
+                self.metrics.router_tlv_source_died();
+                continue;
+            }
 
-        // We load all routes here from the routing table in memory. Because we hold the mutex for the
-        // writer, this view is accurate and we can't diverge until the mutex is released. We will
-        // then apply every action on the list of route entries both to the local list, and as a
-        // RouterOpLogEntry. This is needed because publishing intermediate results might cause
-        // readers to observe intermediate state, which could lead to problems.
-        let (mut routing_table_entries, update_feasible) = {
-            let inner = inner_w
-                .enter()
-                .expect("We deref through a write handle so this enter never fails");
-            (
-                inner.routing_table.entries(subnet),
-                self.source_table
-                    .read()
-                    .unwrap()
-                    .is_update_feasible(&update),
-            )
-        };
+            self.handle_incoming_route_request(rr, source_peer);
 
-        // Take a deep copy of the old selected route if there is one, deep copy since we will
-        // potentially mutate it.
-        let old_selected_route = routing_table_entries
-            .iter()
-            .find(|entry| entry.selected())
-            .cloned();
+            self.metrics
+                .router_time_spent_handling_tlv(start.elapsed(), "route_request");
+        }
+    }
 
-        let maybe_existing_entry_idx = routing_table_entries
-            .iter()
-            .position(|entry| entry.neighbour() == &source_peer);
+    /// Background task to process Seqno Request TLV's.
+    async fn seqno_request_processor(self, mut sn_rx: Receiver<(SeqNoRequest, Peer)>) {
+        while let Some((sn, source_peer)) = sn_rx.recv().await {
+            let start = std::time::Instant::now();
 
-        debug!(
-             "Got update packet from {} for subnet {subnet} with metric {metric} and seqno {seqno} and router-id {router_id}. Route entry exists: {} and update is feasible: {update_feasible}",
-             source_peer.connection_identifier(),
-             maybe_existing_entry_idx.is_some(),
-         );
+            if !source_peer.alive() {
+                trace!("Dropping Route request TLV since sender is dead.");
+                self.metrics.router_tlv_source_died();
+                continue;
+            }
 
-        // Track if we unselected the current existing route. This is required to avoid an issue
-        // where we try to unselect the selected route twice if it is lost.
-        let mut existing_route_unselected = false;
+            self.handle_incoming_seqno_request(sn, source_peer);
 
-        if let Some(existing_entry_idx) = maybe_existing_entry_idx {
-            let existing_entry = &mut routing_table_entries[existing_entry_idx];
-            // Unfeasible updates to the selected route are not applied, but we do request a seqno
-            // bump.
-            if existing_entry.selected()
-                && !update_feasible
-                && existing_entry.source().router_id() == router_id
+            self.metrics
+                .router_time_spent_handling_tlv(start.elapsed(), "seqno");
+        }
+    }
+
+    /// Handle a received hello TLV
+    fn handle_incoming_hello(&self, hello: babel::Hello, source_peer: Peer) {
+        self.metrics.router_process_hello();
+        source_peer.hello_received(hello.seqno(), hello.interval(), &self.hello_history);
+        source_peer.set_accepts_packed_tlvs(hello.accepts_packed_tlvs());
+        let accepted_source_specific_routes =
+            source_peer.set_accepts_source_specific_routes(hello.accepts_source_specific_routes());
+        // Upon receiving and Hello message from a peer, this node has to send a IHU back. The rx
+        // cost is the link cost, which is derived from the measured round trip time.
+        let ihu = ControlPacket::new_ihu(source_peer.link_cost().into(), self.intervals.ihu, None);
+        if self.send_control_packet(&source_peer, ihu).is_err() {
+            trace!(
+                "Failed to send IHU reply to peer: {}",
+                source_peer.connection_identifier()
+            );
+        }
+
+        // Routes sent before the peer announced it can decode source-specific routes left those
+        // out, so send them now.
+        if hello.accepts_source_specific_routes() && !accepted_source_specific_routes {
+            debug!(
+                "{} accepts source-specific routes, sending routes again",
+                source_peer.connection_identifier()
+            );
+            self.dump_routes_to_peer(&source_peer);
+        }
+    }
+
+    /// Handle a received IHU TLV
+    fn handle_incoming_ihu(&self, ihu: babel::Ihu, source_peer: Peer) {
+        self.metrics.router_process_ihu();
+        source_peer.set_tx_cost(ihu.rx_cost(), ihu.interval());
+        // Reset the IHU timer associated with the peer. This also measures the time between the
+        // last Hello and this IHU, which updates the link cost.
+        source_peer.ihu_received(tokio::time::Instant::now());
+    }
+
+    /// Process a route request. We reply with an Update if we have a selected route for the
+    /// requested subnet. If no subnet is requested (in other words the wildcard address), we dump
+    /// the full routing table.
+    fn handle_incoming_route_request(&self, route_request: babel::RouteRequest, source_peer: Peer) {
+        self.metrics
+            .router_process_route_request(route_request.prefix().is_none());
+        // Handle the case of a single subnet.
+        if let Some(subnet) = route_request.prefix() {
+            let source_prefix = route_request.source_prefix();
+            let inner = self
+                .inner_r
+                .enter()
+                .expect("We deref through a write handle so this is always Some; qed");
+            // A source-specific route is only answered with the exact route which is requested.
+            let sre = match source_prefix {
+                Some(_) => inner
+                    .routing_table
+                    .entries(subnet, source_prefix)
+                    .into_iter()
+                    .find(|re| re.selected()),
+                None => inner
+                    .routing_table
+                    .lookup_selected(subnet.address())
+                    .cloned(),
+            };
+            drop(inner);
+            let update = if let Some(sre) = sre {
+                trace!("Advertising selected route for {subnet} after route request");
+                let update = babel::Update::new(
+                    advertised_update_interval(&sre, self.intervals.update),
+                    sre.seqno(),
+                    sre.metric() + Metric::from(sre.neighbour().link_cost()),
+                    subnet,
+                    sre.source().router_id(),
+                )
+                .with_source_prefix(source_prefix);
+                // As per the babel protocol, the requesting peer will never accept the update if
+                // the selected route next-hop is the peer itself.
+                if sre.neighbour() == &source_peer {
+                    trace!("Requesting peer is the next-hop of the selected route");
+                    self.send_update_to_next_hop(&source_peer, update);
+                    return;
+                }
+                update
+            }
+            // Could be a request for a static route/subnet. The default route of an exit node
+            // contains every subnet, so it only answers requests for the default route itself.
+            else if let Some((static_route, static_source_prefix)) =
+                self.static_routes.iter().find(|(sr, sp)| {
+                    *sp == source_prefix
+                        && sr.contains_subnet(&subnet)
+                        && (sr.prefix_len() > 0 || *sr == subnet)
+                })
             {
-                self.send_seqno_request(existing_entry.source(), Some(source_peer), None);
+                trace!(
+                    "Advertising static route {static_route} in response to route request for {subnet}"
+                );
+                babel::Update::new(
+                    self.intervals.update, // Static route is advertised with the default interval
+                    self.router_seqno.read().unwrap().0, // Updates receive the seqno of the router
+                    Metric::from(0),       // Static route has no further hop costs
+                    *static_route,
+                    self.router_id,
+                )
+                .with_source_prefix(*static_source_prefix)
+            }
+            // If the requested route is not present, send a retraction
+            else {
+                trace!(
+                    "Sending retraction for unknown subnet {subnet} in response to route request"
+                );
+                babel::Update::new(
+                    INTERVAL_NOT_REPEATING,
+                    self.router_seqno.read().unwrap().0, // Retractions receive the seqno of the router
+                    Metric::infinite(),                  // Static route has no further hop costs
+                    subnet,                              // Advertise the exact subnet requested
+                    self.router_id, // Our own router ID, since we advertise this
+                )
+                .with_source_prefix(source_prefix)
+            };
+
+            self.send_update(&source_peer, update);
+        } else {
+            // Requested a full route table dump
+            trace!("Dumping route table after wildcard route request");
+            self.dump_routes_to_peer(&source_peer);
+        }
+    }
+
+    /// Send all selected routes and static routes to a single peer, as is done in response to a
+    /// wildcard route request from the peer.
+    pub fn dump_routes_to_peer(&self, peer: &Peer) {
+        self.propagate_selected_routes_to_peer(peer);
+        self.propagate_static_route_to_peer(peer);
+    }
+
+    /// Handle a received SeqNo request TLV.
+    fn handle_incoming_seqno_request(&self, mut seqno_request: SeqNoRequest, source_peer: Peer) {
+        self.metrics.router_process_seqno_request();
+        // According to the babel rfc, we shoudl maintain a table of recent SeqNo requests and
+        // periodically retry requests without reply. We will however not do this for now and rely
+        // on the fact that we have stable links in general.
+
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+
+        // If we have a selected route for the prefix, and its router id is different from the
+        // requested router id, or the router id is the same and the entries sequence number is
+        // not smaller than the requested sequence number, send an update for the route
+        // to the peer (triggered update).
+        let selected = match seqno_request.source_prefix() {
+            Some(source_prefix) => inner
+                .routing_table
+                .entries(seqno_request.prefix(), Some(source_prefix))
+                .into_iter()
+                .find(|re| re.selected()),
+            None => inner
+                .routing_table
+                .lookup_selected(seqno_request.prefix().address())
+                .cloned(),
+        };
+        if let Some(route_entry) = &selected {
+            if !route_entry.metric().is_infinite()
+                && self.is_advertised(route_entry)
+                && (seqno_request.router_id() != route_entry.source().router_id()
+                    || !route_entry.seqno().lt(&seqno_request.seqno()))
+            {
+                // we have a more up to date route or a different route, send an update
+                debug!(
+                    "Replying to seqno request for seqno {} of {} with update packet",
+                    seqno_request.seqno(),
+                    seqno_request.prefix()
+                );
+                let update = babel::Update::new(
+                    advertised_update_interval(route_entry, self.intervals.update),
+                    route_entry.seqno(), // updates receive the seqno of the router
+                    route_entry.metric() + Metric::from(source_peer.link_cost()),
+                    // the cost of the route is the cost of the route + the cost of the link to the peer
+                    route_entry.source().subnet(),
+                    // we looked for the router_id, which is a public key, in the dest_pubkey_map
+                    // if the router_id is not in the map, then the route came from the node itself
+                    route_entry.source().router_id(),
+                )
+                .with_source_prefix(route_entry.source().source_prefix());
+                drop(inner);
+
+                self.send_update(&source_peer, update);
+
+                self.metrics.router_seqno_request_reply_local();
+
                 return;
             }
+        }
+
+        // Otherwise, if the router id in the request matches the router id in our selected route
+        // and the requested sequence number is larger than the one on our selected route, compare
+        // the router id with our own router id. If it matches, bump our own sequence number by 1.
+        // At this point, we also send an update for the route (triggered update), to distribute
+        // the route.
+        //
+        // Note that we currently don't install local routes in the routing table and as such
+        // can't check on this. Therefore this condition is reworked. We always advertise local
+        // routes with the current router id and the current router seqno. So we check if the
+        // prefix is part of our static routes, if the router id is our own, and if the
+        // requested seqno is greater than our own. Pinned routes are advertised the same way.
+        if seqno_request.router_id() == self.router_id
+            && seqno_request.seqno().gt(&self.router_seqno())
+            && (self
+                .static_routes
+                .contains(&(seqno_request.prefix(), seqno_request.source_prefix()))
+                || (seqno_request.source_prefix().is_none()
+                    && self
+                        .pinned_routes
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .any(|pr| pr.subnet == seqno_request.prefix())))
+        {
+            // TODO: should we only send an update to the peer who sent the seqno request
+            // instad of updating all our peers?
+            drop(inner);
+            if self.bump_router_seqno() {
+                self.metrics.router_seqno_request_bump_seqno();
+            }
+
+            return;
+        }
+
+        // Otherwise, if the router-id from the request is not our own, we check the hop count
+        // field. If it is at least 2, we decrement it by 1, and forward the packet. To do so, we
+        // try to find a route to the subnet. First we check for a feasible route and send the
+        // packet there if the next hop is not the sender of this packet. Otherwise, we check for
+        // any route which might potentially be unfeasible, which also did not originate the
+        // packet.
+        if seqno_request.hop_count() < 2 {
+            self.metrics.router_seqno_request_dropped_ttl();
+            return;
+        }
+
+        if seqno_request.router_id() != self.router_id {
+            seqno_request.decrement_hop_count();
+
+            let srck = SeqnoRequestCacheKey {
+                router_id: seqno_request.router_id(),
+                subnet: seqno_request.prefix(),
+                source_prefix: seqno_request.source_prefix(),
+                seqno: seqno_request.seqno(),
+            };
+
+            let mut visited_peers = vec![];
+            if let Some((last_sent, visited)) = self.seqno_cache.info(&srck) {
+                if last_sent.elapsed() < SEQNO_BUMP_TIMEOUT {
+                    visited_peers = visited;
+                }
+            }
+
+            let possible_routes = inner
+                .routing_table
+                .entries(seqno_request.prefix(), seqno_request.source_prefix());
+
+            {
+                let source_table = self.source_table.read().unwrap();
+                // First only consider feasible routes.
+                for re in possible_routes.iter().filter(|re| {
+                    !visited_peers.contains(re.neighbour())
+                        && source_table.route_feasible(re)
+                        && re.neighbour() != &source_peer
+                        && re.neighbour().alive()
+                        && !re.metric().is_infinite()
+                }) {
+                    debug!(
+                        "Forwarding seqno request {} for {} to {}",
+                        seqno_request.seqno(),
+                        seqno_request.prefix(),
+                        re.neighbour().connection_identifier()
+                    );
+                    if self
+                        .send_control_packet(re.neighbour(), seqno_request.clone().into())
+                        .is_err()
+                    {
+                        trace!(
+                            "Failed to foward seqno request to {}",
+                            re.neighbour().connection_identifier(),
+                        );
+                        continue;
+                    }
+
+                    self.seqno_cache
+                        .forward(srck, re.neighbour().clone(), Some(source_peer));
+
+                    self.metrics.router_seqno_request_forward_feasible();
+
+                    return;
+                }
+            }
+
+            // Finally consider infeasible routes as well.
+            for re in possible_routes.iter().filter(|re| {
+                !visited_peers.contains(re.neighbour())
+                    && re.neighbour() != &source_peer
+                    && re.neighbour().alive()
+                    && !re.metric().is_infinite()
+            }) {
+                debug!(
+                    "Forwarding seqno request {} for {} to {}",
+                    seqno_request.seqno(),
+                    seqno_request.prefix(),
+                    re.neighbour().connection_identifier()
+                );
+                if self
+                    .send_control_packet(re.neighbour(), seqno_request.clone().into())
+                    .is_err()
+                {
+                    trace!(
+                        "Failed to foward seqno request to infeasible peer {}",
+                        re.neighbour().connection_identifier(),
+                    );
+                    continue;
+                }
+
+                self.seqno_cache
+                    .forward(srck, re.neighbour().clone(), Some(source_peer));
+
+                self.metrics.router_seqno_request_forward_unfeasible();
+
+                return;
+            }
+        }
+
+        self.metrics.router_seqno_request_unhandled();
+    }
+
+    /// Finds the best feasible route from a list of [`route entries`](RouteEntry). It is possible
+    /// for this method to select a retracted route. In this case, retraction updates should be
+    /// send out.
+    ///
+    /// The actual choice is made by the configured [`RouteSelector`], out of the feasible routes
+    /// with a finite metric. Routes which are suppressed because they changed too often are not
+    /// considered, unless they are the current route. A pinned route is always chosen if its next
+    /// hop is alive.
+    fn find_best_route<'a>(
+        &self,
+        routes: &'a [RouteEntry],
+        current: Option<&'a RouteEntry>,
+    ) -> Option<&'a RouteEntry> {
+        if let Some(pinned) = routes
+            .iter()
+            .find(|re| re.pinned() && !re.metric().is_infinite() && re.neighbour().alive())
+        {
+            return Some(pinned);
+        }
+
+        let source_table = self.source_table.read().unwrap();
+        let candidates = routes
+            .iter()
+            // Infinite metrics are technically feasible, but for route selection we explicitly
+            // don't want infinite metrics as those routes are unreachable.
+            .filter(|re| !re.metric().is_infinite() && source_table.route_feasible(re))
+            .filter(|re| {
+                current.is_some_and(|current| current.neighbour() == re.neighbour())
+                    || !self.flap_damping.is_suppressed(re)
+            })
+            .collect::<Vec<_>>();
+
+        // An unfeasible current route can't be kept, regardless of how good the alternative is, as
+        // that could cause a routing loop.
+        let current = current.filter(|re| source_table.route_feasible(re));
+
+        let metric_offsets = self.metric_offsets.read().unwrap();
+        if metric_offsets.is_empty() {
+            return self.route_selector.select(&candidates, current);
+        }
+
+        // The selector only looks at the routes themselves, so it is given copies of the routes
+        // with the offsets applied. The selected copy is then mapped back to the original route.
+        let offset_candidates = candidates
+            .iter()
+            .map(|re| self.with_metric_offset(re, &metric_offsets))
+            .collect::<Vec<_>>();
+        let offset_current = current.map(|re| self.with_metric_offset(re, &metric_offsets));
+        let selected = self.route_selector.select(
+            &offset_candidates.iter().collect::<Vec<_>>(),
+            offset_current.as_ref(),
+        )?;
+        current
+            .into_iter()
+            .chain(candidates)
+            .find(|re| re.neighbour() == selected.neighbour())
+    }
+
+    /// Handle a received update TLV
+    /// Checks if the origin of an update is allowed to announce its subnet. Nodes can only
+    /// originate routes for subnets containing the overlay address derived from their own key, so
+    /// the router id of the update must own the subnet. A peer announcing a direct route claims to
+    /// be the origin, so its public key must also match the router id, if it is known. Retractions
+    /// can be sent by any node, as they only indicate the node doesn't have a route.
+    ///
+    /// Violations are logged with the public key of the peer which sent the update.
+    fn update_origin_is_valid(&self, update: &babel::Update, source_peer: &Peer) -> bool {
+        if update.metric().is_infinite() {
+            return true;
+        }
+
+        let router_id = update.router_id();
+        let peer_key = source_peer
+            .public_key()
+            .map(|pk| pk.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        if !update
+            .subnet()
+            .contains_ip(router_id.to_pubkey().address().into())
+        {
+            warn!(
+                "Peer {} (public key {peer_key}) announced {} on behalf of router {router_id}, which does not own it",
+                source_peer.connection_identifier(),
+                update.subnet(),
+            );
+            return false;
+        }
+        if update.metric().is_direct() {
+            if let Some(pk) = source_peer.public_key() {
+                if pk != router_id.to_pubkey() {
+                    warn!(
+                        "Peer {} (public key {peer_key}) announced a direct route for {} on behalf of router {router_id}",
+                        source_peer.connection_identifier(),
+                        update.subnet(),
+                    );
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn handle_incoming_update(&self, update: babel::Update, source_peer: Peer) {
+        self.metrics.router_process_update();
+        // Wildcard updates don't refer to a subnet, so they are handled before the filters.
+        if update.is_wildcard() {
+            if update.is_wildcard_retraction() {
+                self.handle_wildcard_retraction(&source_peer);
+            } else {
+                debug!(
+                    "Ignoring wildcard update with finite metric from {}",
+                    source_peer.connection_identifier()
+                );
+            }
+            return;
+        }
+        // Check if we actually allow this update based on filters.
+        for filter in &*self.update_filters {
+            if !filter.allow(&update) {
+                debug!(
+                    "Update for {} from {} denied by filter",
+                    update.subnet(),
+                    source_peer.connection_identifier()
+                );
+                self.metrics.router_update_denied_by_filter();
+                return;
+            }
+        }
+        if !self.update_origin_is_valid(&update, &source_peer) {
+            self.metrics.router_update_origin_rejected();
+            return;
+        }
+
+        let metric = update.metric();
+        let router_id = update.router_id();
+        let seqno = update.seqno();
+        let subnet = update.subnet();
+        let source_prefix = update.source_prefix();
+
+        // A direct route is announced by the router which owns the subnet, which identifies the
+        // node at the other end of the connection. We verified the router owns the subnet above.
+        if metric.is_direct() && source_peer.public_key().is_none() {
+            source_peer.set_public_key(router_id.to_pubkey());
+            self.install_pinned_routes(&source_peer);
+        }
+
+        // create route key from incoming update control struct
+        let update_route_key =
+            RouteKey::new(subnet, source_peer.clone()).with_source_prefix(source_prefix);
+        // used later to filter out static route
+        if self.route_key_is_from_static_route(&update_route_key) {
+            return;
+        }
+
+        // We accepted the update, check if we have a seqno request sent for this update.
+        let interested_peers = self.seqno_cache.remove(&SeqnoRequestCacheKey {
+            router_id,
+            subnet,
+            source_prefix,
+            seqno,
+        });
+
+        let mut inner_w = self.inner_w.lock().expect("Mutex isn't poisoned");
+
+        // We load all routes here from the routing table in memory. Because we hold the mutex for the
+        // writer, this view is accurate and we can't diverge until the mutex is released. We will
+        // then apply every action on the list of route entries both to the local list, and as a
+        // RouterOpLogEntry. This is needed because publishing intermediate results might cause
+        // readers to observe intermediate state, which could lead to problems.
+        let (mut routing_table_entries, update_feasible) = {
+            let inner = inner_w
+                .enter()
+                .expect("We deref through a write handle so this enter never fails");
+            (
+                inner.routing_table.entries(subnet, source_prefix),
+                self.source_table
+                    .read()
+                    .unwrap()
+                    .is_update_feasible(&update),
+            )
+        };
+
+        // Take a deep copy of the old selected route if there is one, deep copy since we will
+        // potentially mutate it.
+        let old_selected_route = routing_table_entries
+            .iter()
+            .find(|entry| entry.selected())
+            .cloned();
+
+        let maybe_existing_entry_idx = routing_table_entries
+            .iter()
+            .position(|entry| entry.neighbour() == &source_peer);
+
+        debug!(
+             "Got update packet from {} for subnet {subnet} with metric {metric} and seqno {seqno} and router-id {router_id}. Route entry exists: {} and update is feasible: {update_feasible}",
+             source_peer.connection_identifier(),
+             maybe_existing_entry_idx.is_some(),
+         );
+
+        // A pinned route is configured by the operator, so it is not changed by updates.
+        if maybe_existing_entry_idx.is_some_and(|idx| routing_table_entries[idx].pinned()) {
+            debug!("Ignoring update for pinned route to {subnet}");
+            return;
+        }
+
+        // Track if we unselected the current existing route. This is required to avoid an issue
+        // where we try to unselect the selected route twice if it is lost.
+        let mut existing_route_unselected = false;
+        // The selected route of another subnet which was evicted to make room for a new route.
+        let mut evicted_selected_route = None;
+        let mut route_evicted = false;
+
+        if let Some(existing_entry_idx) = maybe_existing_entry_idx {
+            let existing_entry = &mut routing_table_entries[existing_entry_idx];
+            // Unfeasible updates to the selected route are not applied, but we do request a seqno
+            // bump.
+            if existing_entry.selected()
+                && !update_feasible
+                && existing_entry.source().router_id() == router_id
+            {
+                self.send_seqno_request(existing_entry.source(), Some(source_peer), None);
+                return;
+            }
+
+            // Otherwise we just update the entry.
+            if existing_entry.metric().is_infinite() && update.metric().is_infinite() {
+                // Retraction for retracted route, don't do anything. If we don't filter this
+                // retracted routes can stay stuck if peer keep sending retractions to eachother
+                // for this route.
+                return;
+            }
+            existing_entry.update_seqno(seqno);
+            existing_entry.update_metric(metric);
+            existing_entry.update_router_id(router_id);
+            let rk = RouteKey::new(subnet, source_peer).with_source_prefix(source_prefix);
+            inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
+                rk.clone(),
+                seqno,
+                metric,
+                router_id,
+                route_hold_time(&update, self.route_hold_time_multiplier),
+            ));
+            // If the update is unfeasible the route must be unselected.
+            if existing_entry.selected() && !update_feasible {
+                existing_route_unselected = true;
+                existing_entry.set_selected(false);
+                inner_w.append(RouterOpLogEntry::UnselectRoute(rk));
+            }
+        } else {
+            // If there is no entry yet ignore retractions.
+            if metric.is_infinite() {
+                debug!("Received retraction for unknown route - neighbour");
+                return;
+            }
+
+            // If the routing table is full, the new route is only stored if a less preferred route
+            // can be evicted to make room for it.
+            if self.routing_table_full(&inner_w) {
+                let Some((evicted_rk, evicted_re)) =
+                    self.evictable_route(&inner_w, metric, update_feasible)
+                else {
+                    debug!(
+                        "Routing table is full, refusing route to {subnet} from {}",
+                        source_peer.connection_identifier()
+                    );
+                    self.metrics.router_route_refused();
+                    return;
+                };
+                info!(
+                    "Routing table is full, evicting route {evicted_rk} with metric {} to make room for route to {subnet}",
+                    evicted_re.metric()
+                );
+                self.metrics.router_route_evicted();
+                inner_w.append(RouterOpLogEntry::RemoveRoute(evicted_rk.clone()));
+                route_evicted = true;
+                if evicted_rk.subnet() == subnet && evicted_rk.source_prefix() == source_prefix {
+                    routing_table_entries.retain(|re| re.neighbour() != evicted_rk.neighbour());
+                    // The removed route can't be unselected anymore.
+                    existing_route_unselected |= evicted_re.selected();
+                } else if evicted_re.selected() {
+                    evicted_selected_route = Some((evicted_rk, evicted_re));
+                }
+            }
+
+            // Unfeasible updates are stored as a fallback route, so the route can be selected
+            // once it becomes feasible.
+            if !update_feasible {
+                debug!("Received unfeasible update for unknown route - neighbour");
+                // An unfeasible update while we don't have a selected route means we are starved:
+                // the neighbour has a route which we can't use because our feasibility distance
+                // for the source is too strict. Request a seqno bump from the source so the route
+                // becomes feasible (RFC 8966 section 3.8.2.1).
+                if old_selected_route.is_none() {
+                    self.send_seqno_request(
+                        SourceKey::new(subnet, router_id).with_source_prefix(source_prefix),
+                        Some(source_peer.clone()),
+                        None,
+                    );
+                }
+            }
+
+            // Create new entry in the route table
+            let re = RouteEntry::new(
+                SourceKey::new(subnet, router_id).with_source_prefix(source_prefix),
+                source_peer.clone(),
+                metric,
+                seqno,
+                false,
+                route_hold_time(&update, self.route_hold_time_multiplier),
+            );
+            routing_table_entries.push(re.clone());
+
+            let ss = self.node_keypair.0.shared_secret(&router_id.to_pubkey());
+            inner_w.append(RouterOpLogEntry::InsertRoute(
+                RouteKey::new(subnet, source_peer).with_source_prefix(source_prefix),
+                re,
+                router_id.to_pubkey(),
+                ss,
+            ));
+        }
+
+        // Now that we applied the update, run route selection. This must use the current state of
+        // the selected route, as the update might have made it unfeasible.
+        let current_selected_route = routing_table_entries.iter().find(|entry| entry.selected());
+        let new_selected_route =
+            self.find_best_route(&routing_table_entries, current_selected_route);
+        if let Some(nbr) = new_selected_route {
+            // Install this route in the routing table. We don't update the local copy anymore as
+            // we don't use it afterwards.
+            inner_w.append(RouterOpLogEntry::SelectRoute(
+                RouteKey::new(subnet, nbr.neighbour().clone()).with_source_prefix(source_prefix),
+            ));
+        } else if let Some(osr) = old_selected_route.as_ref() {
+            // If there is no new selected route, but there was one previously, update the routing
+            // table. This is not covered above, as there only unfeasible updates cause a selected
+            // route to be unselected. However, this may also be the result of a feasible update
+            // (e.g. retraction with no feasible fallback routes).
+            // Only do this if we did not unselect the existing route previously.
+            // Regardless if we unselect the route here or not, we lost a selected route for the
+            // subnet, so we try to refresh the route by sending out a seqno request to all
+            // neigbours who advertised the route at some point.
+            self.send_seqno_request(osr.source(), None, None);
+            if !existing_route_unselected {
+                inner_w.append(RouterOpLogEntry::UnselectRoute(
+                    RouteKey::new(subnet, osr.neighbour().clone())
+                        .with_source_prefix(source_prefix),
+                ));
+            }
+        };
+
+        // Already publish here, we won't make any other adjustments to the routing table.
+        match &evicted_selected_route {
+            Some((rk, _)) => self.publish_routing_table(
+                &mut inner_w,
+                &[(subnet, source_prefix), (rk.subnet(), rk.source_prefix())],
+            ),
+            None => self.publish_routing_table(&mut inner_w, &[(subnet, source_prefix)]),
+        }
+        if route_evicted {
+            self.prune_ciphers();
+        }
+
+        // Peers must stop using us for the subnet of an evicted selected route. There are no other
+        // routes for it, as those are evicted first.
+        if let Some((rk, re)) = evicted_selected_route {
+            if !re.metric().is_infinite() {
+                self.trigger_update(rk.subnet(), rk.source_prefix(), None);
+            }
+        }
+
+        // At this point we are done, though we would like to understand if we need to send a
+        // triggered update to our peers. This is done if there is a sufficiently large change. We
+        // consider a sufficiently large change to be:
+        // - change in router_id,
+        // - aquired a route, i.e. previously there was no selected route but there is now,
+        // - lost the route (i.e. it is retracted).
+        // - significant metric change
+        // What doesn't constitue a large change:
+        // - small metric change
+        // - seqno increase (unless it is requested by a peer)
+        // TODO: we don't memorize seqno requests for now so consider broadcasting this anyway
+        let trigger_update = match (&old_selected_route, new_selected_route) {
+            (Some(old_route), Some(new_route)) => {
+                if new_route.neighbour() != old_route.neighbour() {
+                    info!(
+                        "Selected route for {subnet} changed next-hop from {} to {}",
+                        old_route.neighbour().connection_identifier(),
+                        new_route.neighbour().connection_identifier()
+                    );
+                }
+                // Router id changed.
+                new_route.source().router_id() != old_route.source().router_id()
+                // TODO: remove | seqno changed
+                    || new_route.seqno().gt(&old_route.seqno())
+                    || new_route.metric().delta(&old_route.metric()) > BIG_METRIC_CHANGE_TRESHOLD
+            }
+            (None, Some(new_route)) => {
+                info!(
+                    "Acquired route to {subnet} via {}",
+                    new_route.neighbour().connection_identifier()
+                );
+                true
+            }
+            (Some(old_route), None) => {
+                info!(
+                    "Lost route to {subnet} via {}",
+                    old_route.neighbour().connection_identifier()
+                );
+                true
+            }
+            (None, None) => false,
+        };
+
+        if trigger_update {
+            debug!("Send triggered update for {subnet} in response to update");
+            self.trigger_update(subnet, source_prefix, None);
+        } else if interested_peers.is_some() {
+            debug!(
+                "Send update to peers who registered interest through a seqno request for {subnet}"
+            );
+            self.trigger_update(subnet, source_prefix, interested_peers);
+            // If we have some interest in an update because we forwarded a seqno request but we
+            // aren't triggering an update, notify just the interested peers.
+        }
+    }
+
+    /// Check if the routing table holds the maximum amount of routes, if that is limited.
+    fn routing_table_full(&self, inner_w: &WriteHandle<RouterInner, RouterOpLogEntry>) -> bool {
+        self.max_routes.is_some_and(|max_routes| {
+            inner_w
+                .enter()
+                .expect("We enter through a write handle so this can never be None")
+                .routing_table
+                .route_count()
+                >= max_routes
+        })
+    }
+
+    /// Find the route which is evicted from a full routing table to make room for a new route with
+    /// the given metric, if any.
+    ///
+    /// Unfeasible routes are never stored in a full routing table. Otherwise, the least preferred
+    /// route is evicted: fallback and retracted routes come first, then routes with a higher
+    /// metric, and then routes which were updated least recently. A selected route is only
+    /// evicted if its metric is higher than the metric of the new route. Pinned routes are never
+    /// evicted.
+    fn evictable_route(
+        &self,
+        inner_w: &WriteHandle<RouterInner, RouterOpLogEntry>,
+        metric: Metric,
+        feasible: bool,
+    ) -> Option<(RouteKey, RouteEntry)> {
+        if !feasible {
+            return None;
+        }
+
+        let inner = inner_w
+            .enter()
+            .expect("We enter through a write handle so this can never be None");
+        let (rk, re) = inner.routing_table.evictable_route()?;
+
+        (!re.selected() || re.metric().is_infinite() || re.metric() > metric)
+            .then(|| (rk, re.clone()))
+    }
+
+    /// Trigger an update for the given [`Subnet`] and source prefix. If `peers` is [`None`], send
+    /// the update to all peers the `Router` knows.
+    fn trigger_update(
+        &self,
+        subnet: Subnet,
+        source_prefix: Option<Subnet>,
+        peers: Option<Vec<Peer>>,
+    ) {
+        self.metrics.router_triggered_update();
+        let peers = peers.unwrap_or_else(|| self.peer_interfaces.read().unwrap().clone());
+        let retraction = self
+            .selected_route(subnet, source_prefix)
+            .filter(|sre| self.is_advertised(sre))
+            .is_none();
+        let peers = self.triggered_updates.schedule(
+            (subnet, source_prefix),
+            peers,
+            retraction,
+            tokio::time::Instant::now(),
+        );
+        if !peers.is_empty() {
+            self.propagate_selected_route(subnet, source_prefix, Some(peers));
+        }
+    }
+
+    /// Send a seqno request for a subnet. This can be sent to a given peer, or to all peers for
+    /// the subnet if no peer is given.
+    ///
+    /// The SourceKey must exist in the source table.
+    fn send_seqno_request(
+        &self,
+        source: SourceKey,
+        to: Option<Peer>,
+        request_origin: Option<Peer>,
+    ) {
+        let fd = match self.source_table.read().unwrap().get(&source) {
+            Some(fd) => *fd,
+            None => {
+                // This can happen if you only have 1 peer, or in case we haven't announced our
+                // subnets yet.
+                debug!("Requesting seqno for source key {source} which does not exist in the source table");
+                // Since we don't know the real seqno, just use the default. Either the peer will
+                // reply if it has an adequate route, or it will forward the request.
+                FeasibilityDistance::new(Metric::new(0), SeqNo::new())
+            }
+        };
+
+        let sn: ControlPacket =
+            SeqNoRequest::new(fd.seqno() + 1, source.router_id(), source.subnet())
+                .with_source_prefix(source.source_prefix())
+                .into();
+
+        let srck = SeqnoRequestCacheKey {
+            router_id: source.router_id(),
+            subnet: source.subnet(),
+            source_prefix: source.source_prefix(),
+            seqno: fd.seqno() + 1,
+        };
+
+        let seqno_info = self.seqno_cache.info(&srck);
+
+        let targets = if let Some(target) = to {
+            // Don't repeat the same request to the same peer before the peer had a chance to
+            // bump its seqno.
+            if let Some((last_sent, visited)) = &seqno_info {
+                if last_sent.elapsed() < SEQNO_BUMP_TIMEOUT && visited.contains(&target) {
+                    debug!(
+                        "Not repeating seqno request for {} to {}",
+                        source.subnet(),
+                        target.connection_identifier()
+                    );
+                    return;
+                }
+            }
+            vec![target]
+        } else {
+            // If we don't have a dedicated peer to send to, just send to every peer which
+            // announced the subnet. But avoid repetitions. Additionally, if we don't have any
+            // peer which announced the subnet (because all anounces are unfeasible)
+            let mut peers_sent = vec![];
+            if let Some((last_sent, visited)) = seqno_info {
+                // If it's more than some time since we last sent an update to any peer for this
+                // seqno request, send it again. We use the seqno bump timeout here, since that is
+                // the quickest time between bumps from a peer.
+                if last_sent.elapsed() < SEQNO_BUMP_TIMEOUT {
+                    peers_sent = visited;
+                }
+            };
+
+            let known_routes = self
+                .inner_r
+                .enter()
+                .expect("Write handle is saved on router so this read always succeeds; qed")
+                .routing_table
+                .entries(source.subnet(), source.source_prefix());
+
+            // Make sure a broadcast only happens in case the local node originated the request.
+            if known_routes.is_empty() && request_origin.is_none() {
+                // If we don't know the route just ask all our peers.
+                self.peer_interfaces
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .cloned()
+                    .collect()
+            } else {
+                known_routes
+                    .into_iter()
+                    .filter_map(|re| {
+                        if !peers_sent.contains(re.neighbour()) {
+                            Some(re.neighbour().clone())
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        for peer in targets {
+            debug!(
+                "Sending seqno_request to {} for seqno {} of {}",
+                peer.connection_identifier(),
+                fd.seqno() + 1,
+                source.subnet(),
+            );
+
+            if self.send_control_packet(&peer, sn.clone()).is_err() {
+                trace!(
+                    "Failed to send seqno request to {}",
+                    peer.connection_identifier()
+                );
+                continue;
+            }
+
+            self.seqno_cache.forward(srck, peer, request_origin.clone());
+        }
+    }
+
+    /// Checks if a route key is an exact match for a static route.
+    fn route_key_is_from_static_route(&self, route_key: &RouteKey) -> bool {
+        self.static_routes
+            .contains(&(route_key.subnet(), route_key.source_prefix()))
+    }
+
+    /// Route a data packet originating from the local node.
+    pub fn route_packet(&self, data_packet: DataPacket) {
+        self.route_packet_from(data_packet, None);
+    }
+
+    /// Route a data packet, which was received from the `ingress` [`Peer`] if it was not sent by
+    /// the local node.
+    fn route_packet_from(&self, mut data_packet: DataPacket, ingress: Option<Peer>) {
+        let node_tun_subnet = self.node_tun_subnet();
+
+        trace!(
+            "Incoming data packet {} -> {}",
+            data_packet.src_ip,
+            data_packet.dst_ip,
+        );
+
+        // Packets for the local node are delivered as is, only packets which are forwarded use up
+        // a hop. The hop limit of the packet delivered to the TUN interface is set to the hop
+        // limit of the data packet, so it shows the amount of hops the packet took. An exit node
+        // also delivers packets for addresses outside of the global subnet, so the host can
+        // forward them to its uplink, if the default route is announced for their source.
+        if node_tun_subnet.contains_ip(data_packet.dst_ip.into())
+            || (self.exit_node
+                && !in_global_subnet(data_packet.dst_ip.into())
+                && self.exit_allows_source(data_packet.src_ip.into()))
+        {
+            self.metrics.router_route_packet_local();
+            let size = data_packet.wire_size();
+            match self.node_tun.try_send((data_packet, ingress)) {
+                Ok(()) => self.stats.local(size),
+                Err(TrySendError::Full(_)) => {
+                    trace!("Dropping data packet since the TUN interface queue is full");
+                    self.metrics.router_route_packet_queue_full();
+                    self.packet_dropped(DropReason::QueueFull);
+                }
+                Err(e) => {
+                    error!("Error sending data packet to TUN interface: {:?}", e);
+                }
+            }
+        } else {
+            // Routing loops can't forward a packet forever, since it eventually runs out of hops.
+            if data_packet.hop_limit < 2 {
+                self.metrics.router_route_packet_ttl_expired();
+                self.packet_dropped(DropReason::HopLimitExceeded);
+                self.time_exceeded(data_packet);
+                return;
+            }
+            data_packet.hop_limit -= 1;
+
+            match self.select_forwarding_route(
+                IpAddr::V6(data_packet.dst_ip),
+                IpAddr::V6(data_packet.src_ip),
+                data_packet.flow,
+            ) {
+                Some(route_entry) => {
+                    if let Some(mtu) = route_entry.neighbour().mtu() {
+                        if data_packet.wire_size() > mtu as usize {
+                            self.packet_dropped(DropReason::TooLarge);
+                            self.packet_too_big(data_packet, mtu);
+                            return;
+                        }
+                    }
+                    self.metrics.router_route_packet_forward();
+                    let transit = !node_tun_subnet.contains_ip(data_packet.src_ip.into());
+                    if transit {
+                        if let Some(drain) = self.drain.write().unwrap().as_mut() {
+                            drain.transit_packets += 1;
+                            drain.last_transit_packet = Some(Instant::now());
+                        }
+                    }
+                    let size = data_packet.wire_size();
+                    match route_entry.neighbour().send_data_packet(data_packet) {
+                        Ok(()) => self.stats.forwarded(size, transit),
+                        // Drop the packet rather than waiting, so a single slow peer can't stall
+                        // forwarding to all other peers.
+                        Err(TrySendError::Full(_)) => {
+                            trace!(
+                                "Dropping data packet since the queue to peer {} is full",
+                                route_entry.neighbour().connection_identifier()
+                            );
+                            self.metrics.router_route_packet_queue_full();
+                            self.packet_dropped(DropReason::QueueFull);
+                        }
+                        Err(e) => {
+                            error!(
+                                "Error sending data packet to peer {}: {:?}",
+                                route_entry.neighbour().connection_identifier(),
+                                e
+                            );
+                        }
+                    }
+                }
+                None => {
+                    self.metrics.router_route_packet_no_route();
+                    self.packet_dropped(DropReason::NoRoute);
+                    self.no_route_to_host(data_packet);
+                }
+            }
+        }
+    }
+
+    /// Handle a received data packet.
+    async fn handle_incoming_data_packet(self, mut router_data_rx: Receiver<(DataPacket, Peer)>) {
+        while let Some((data_packet, peer)) = router_data_rx.recv().await {
+            self.stats.data_received(data_packet.wire_size());
+            self.route_packet_from(data_packet, Some(peer));
+        }
+        warn!("Router data receiver stream ended");
+    }
+
+    /// Handle a packet who's TTL is too low.
+    fn time_exceeded(&self, data_packet: DataPacket) {
+        trace!("Refusing to forward expired packet");
+        self.oob_icmp(
+            Icmpv6Type::TimeExceeded(TimeExceededCode::HopLimitExceeded),
+            data_packet,
+        )
+    }
+
+    /// Handle a packet if we have no route for the destination address.
+    fn no_route_to_host(&self, data_packet: DataPacket) {
+        trace!(
+            "Could not forward data packet, no route found for {}",
+            data_packet.dst_ip
+        );
+
+        self.oob_icmp(
+            Icmpv6Type::DestinationUnreachable(DestUnreachableCode::NoRoute),
+            data_packet,
+        )
+    }
+
+    /// Handle a packet which is too large for the link to the next hop, which has the given MTU.
+    fn packet_too_big(&self, data_packet: DataPacket, link_mtu: u16) {
+        trace!(
+            "Could not forward data packet for {}, it exceeds the link MTU of {link_mtu}",
+            data_packet.dst_ip
+        );
+
+        // The origin controls the size of the payload before encryption, so that is what the
+        // MTU is expressed in.
+        let mtu =
+            (link_mtu as usize).saturating_sub(DataPacket::WIRE_OVERHEAD + ENCRYPTION_OVERHEAD);
+        // ICMP sent to ourselves can't be decrypted, so packets of this node are handled here.
+        if self
+            .node_tun_subnet()
+            .contains_ip(data_packet.src_ip.into())
+        {
+            self.update_path_mtu(data_packet.dst_ip, mtu);
+            return;
+        }
+
+        self.oob_icmp(Icmpv6Type::PacketTooBig { mtu: mtu as u32 }, data_packet)
+    }
+
+    /// Send an oob icmp packet of the specified type in reply to the given DataPakcet.
+    fn oob_icmp(&self, icmp_type: Icmpv6Type, mut data_packet: DataPacket) {
+        let src_ip = if let IpAddr::V6(ip) = self.node_tun_subnet.address() {
+            ip
+        } else {
+            panic!("IPv4 not supported yet")
+        };
+
+        let icmp_header =
+            etherparse::PacketBuilder::ipv6(src_ip.octets(), data_packet.src_ip.octets(), 64)
+                .icmpv6(icmp_type);
+
+        let mut pb = PacketBuffer::new();
+        // Don't exceed MIN_MTU for the constructed packet
+        // TODO: use proper consts
+        if data_packet.raw_data.len() > (1280 - 48) {
+            // Just drop raw_data, we don't need it anymore in this case and by doing this we have
+            // a unified code path later. Also we release the no longer used memory just a tad bit
+            // slower, though it's unlikely that this matters.
+            data_packet.raw_data = BytesMut::new();
+        }
+        let serialized_icmp_size = icmp_header.size(data_packet.raw_data.len());
+        pb.set_size(serialized_icmp_size + 16);
+        pb.buffer_mut()[..16].copy_from_slice(&data_packet.dst_ip.octets());
+        let mut ps = &mut pb.buffer_mut()[16..16 + serialized_icmp_size];
+        if let Err(e) = icmp_header.write(&mut ps, &data_packet.raw_data) {
+            error!("Failed to write ICMP packet {e}");
+            return;
+        }
+
+        // TODO: import consts
+        let mut header = pb.header_mut();
+        header[0] = 1;
+        header[1] = 2;
+
+        // Get shared secret from node and dest address
+        let shared_secret = match self.get_shared_secret_from_dest(data_packet.src_ip.into()) {
+            Some(ss) => ss,
+            None => {
+                debug!(
+                    "No entry found for destination address {}, dropping packet",
+                    data_packet.src_ip
+                );
+                return;
+            }
+        };
+
+        let cipher = self.cipher(data_packet.src_ip).unwrap_or(Cipher::Aes256Gcm);
+        let enc = shared_secret.encrypt_with(cipher, pb);
+
+        self.route_packet(DataPacket {
+            dst_ip: data_packet.src_ip,
+            src_ip,
+            hop_limit: 64,
+            flow: 0,
+            raw_data: enc,
+        });
+    }
+
+    /// Get's the best route for a destination IP if one is present.
+    pub fn select_best_route(&self, dest_ip: IpAddr) -> Option<RouteEntry> {
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+        inner
+            .routing_table
+            .lookup_selected(dest_ip)
+            .and_then(|entry| {
+                if entry.metric().is_infinite() {
+                    None
+                } else {
+                    Some(entry.clone())
+                }
+            })
+    }
+
+    /// Get's the best route for traffic from a source IP to a destination IP if one is present.
+    /// Unlike [`Router::select_best_route`], this also considers source-specific routes.
+    pub fn select_best_route_from(&self, dest_ip: IpAddr, src_ip: IpAddr) -> Option<RouteEntry> {
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+        inner
+            .routing_table
+            .lookup_selected_from(dest_ip, src_ip)
+            .and_then(|entry| {
+                if entry.metric().is_infinite() {
+                    None
+                } else {
+                    Some(entry.clone())
+                }
+            })
+    }
+
+    /// Check if a packet from `src_ip` to `dest_ip` can currently be forwarded, i.e. it is destined
+    /// for the local subnet, or there is a usable selected route for the destination.
+    pub fn has_forwarding_route(&self, dest_ip: IpAddr, src_ip: IpAddr) -> bool {
+        if self.node_tun_subnet.contains_ip(dest_ip) {
+            return true;
+        }
+
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+        let routable = inner
+            .routing_table
+            .lookup_selected_entries_from(dest_ip, src_ip)
+            .and_then(|mut entries| entries.next())
+            .is_some_and(|selected| !selected.metric().is_infinite());
+        routable
+    }
+
+    /// Select the route to forward a packet from `src_ip` to `dest_ip` over.
+    ///
+    /// If other feasible routes for the same subnet and source prefix have the same metric as the
+    /// selected route, traffic is spread over them (equal cost multipath). The route is picked
+    /// based on a hash of the source, destination and `flow` identifier of the packet, so all
+    /// packets of a flow take the same path.
+    fn select_forwarding_route(
+        &self,
+        dest_ip: IpAddr,
+        src_ip: IpAddr,
+        flow: u8,
+    ) -> Option<RouteEntry> {
+        // Lock the source table before entering the read handle. Publishing the routing table
+        // waits for readers to leave, so waiting for the lock while in the read handle could
+        // deadlock with a writer holding the lock.
+        let source_table = self.source_table.read().unwrap();
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+        let mut entries = inner
+            .routing_table
+            .lookup_selected_entries_from(dest_ip, src_ip)?;
+        let selected = entries.next()?;
+        if selected.metric().is_infinite() {
+            return None;
+        }
+
+        let (paths, amount) = equal_cost_paths(
+            &source_table,
+            &self.metric_offsets.read().unwrap(),
+            selected,
+            entries,
+        );
+        if amount == 1 {
+            return Some(selected.clone());
+        }
+        let mut hasher = DefaultHasher::new();
+        (self.ecmp_seed, src_ip, dest_ip, flow).hash(&mut hasher);
+        Some(paths[(hasher.finish() % amount as u64) as usize].clone())
+    }
+
+    /// Get the amount of paths traffic using the selected route for the given subnet and source
+    /// prefix is spread over. This is more than 1 if equal cost multipath is used, and 0 if there
+    /// is no usable selected route.
+    pub fn equal_cost_path_count(&self, subnet: Subnet, source_prefix: Option<Subnet>) -> usize {
+        let source_table = self.source_table.read().unwrap();
+        let entries = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles")
+            .routing_table
+            .entries(subnet, source_prefix);
+        let mut entries = entries.iter();
+        match entries.next() {
+            Some(selected) if selected.selected() && !selected.metric().is_infinite() => {
+                equal_cost_paths(
+                    &source_table,
+                    &self.metric_offsets.read().unwrap(),
+                    selected,
+                    entries,
+                )
+                .1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Get's the best fallback route for a destination IP if one is present. This is the route
+    /// ordered first by the [`RouteSelector`] for the most specific subnet containing the IP which
+    /// is not currently selected. Routes with an infinite metric are never returned.
+    pub fn select_best_fallback_route(&self, dest_ip: IpAddr) -> Option<RouteEntry> {
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so it is not dropped before the read handles");
+        let metric_offsets = self.metric_offsets.read().unwrap();
+        inner
+            .routing_table
+            .lookup_entries(dest_ip)
+            .into_iter()
+            .filter(|re| !re.selected() && !re.metric().is_infinite())
+            .min_by(|a, b| {
+                self.route_selector.compare(
+                    &self.with_metric_offset(a, &metric_offsets),
+                    &self.with_metric_offset(b, &metric_offsets),
+                )
+            })
+    }
+
+    /// Get the current seqno of the local router, which is used for the routes to the local
+    /// subnets.
+    pub fn router_seqno(&self) -> SeqNo {
+        self.router_seqno.read().unwrap().0
+    }
+
+    /// Bump the seqno of the local router, and announce the local subnets with the new seqno to
+    /// all peers. To avoid flooding the network with updates, the seqno is only bumped if the last
+    /// bump was at least [`SEQNO_BUMP_TIMEOUT`] ago. Returns whether the seqno was bumped.
+    pub fn bump_router_seqno(&self) -> bool {
+        // Scope the write lock on seqno
+        {
+            let mut router_seqno = self.router_seqno.write().unwrap();
+            if router_seqno.1.elapsed() < SEQNO_BUMP_TIMEOUT {
+                trace!("Ignoring seqno bump request which happened too fast");
+                return false;
+            }
+            router_seqno.0 += 1;
+            router_seqno.1 = Instant::now();
+            debug!("Bumped local router sequence number to {}", router_seqno.0);
+            self.event_log.record(RoutingEvent::SeqnoBumped {
+                seqno: router_seqno.0.into(),
+            });
+        }
+
+        self.propagate_static_routes_to_peers();
+        self.refresh_pinned_routes();
+
+        true
+    }
+
+    /// Give the advertised [`PinnedRoute`]s the current seqno of the local router, and advertise
+    /// them to all peers.
+    fn refresh_pinned_routes(&self) {
+        let seqno = self.router_seqno();
+        let subnets = {
+            let mut inner_w = self.inner_w.lock().unwrap();
+            let pinned = inner_w
+                .enter()
+                .expect("We enter through a write handle so this can never be None")
+                .routing_table
+                .iter()
+                .filter(|(_, _, re)| {
+                    re.pinned() && !re.metric().is_infinite() && self.is_advertised(re)
+                })
+                .map(|(rk, _, re)| (rk, re.metric()))
+                .collect::<Vec<_>>();
+            if pinned.is_empty() {
+                return;
+            }
+            let subnets = pinned
+                .iter()
+                .map(|(rk, _)| (rk.subnet(), rk.source_prefix()))
+                .collect::<Vec<_>>();
+            for (rk, metric) in pinned {
+                inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
+                    rk,
+                    seqno,
+                    metric,
+                    self.router_id,
+                    PINNED_ROUTE_HOLD_TIME,
+                ));
+            }
+            self.publish_routing_table(&mut inner_w, &subnets);
+            subnets
+        };
+
+        for (subnet, source_prefix) in subnets {
+            self.propagate_selected_route(subnet, source_prefix, None);
+        }
+    }
+
+    /// Checks if a route can be advertised to peers. [`PinnedRoute`]s are originated by us, and
+    /// other nodes only accept routes for subnets containing the overlay address of their origin.
+    /// Pinned routes for other subnets are only used by the local node.
+    fn is_advertised(&self, re: &RouteEntry) -> bool {
+        !re.pinned()
+            || re
+                .source()
+                .subnet()
+                .contains_ip(self.router_id.to_pubkey().address().into())
+    }
+
+    /// Task to bump the seqno of the local router periodically.
+    async fn bump_router_seqno_periodically(self) {
+        let mut bump_interval = periodic_interval(SEQNO_BUMP_INTERVAL);
+        loop {
+            bump_interval.tick().await;
+            self.bump_router_seqno();
+        }
+    }
+
+    /// Task to propagate the static routes periodically
+    async fn propagate_static_routes(self) {
+        let mut propagation_interval = JitteredInterval::new(
+            self.intervals.update,
+            Jitter::new(UPDATE_JITTER, rand::random()),
+        );
+        loop {
+            propagation_interval.tick().await;
+
+            trace!("Propagating static routes");
+
+            for peer in self.peer_interfaces.read().unwrap().iter() {
+                self.propagate_static_route_to_peer(peer)
+            }
+        }
+    }
+
+    /// Propagate all static routes to all known peers.
+    fn propagate_static_routes_to_peers(&self) {
+        for peer in self.peer_interfaces.read().unwrap().iter() {
+            self.propagate_static_route_to_peer(peer);
+        }
+    }
+
+    /// Task to propagate selected routes periodically
+    async fn propagate_selected_routes(self) {
+        let mut propagation_interval = JitteredInterval::new(
+            self.intervals.update,
+            Jitter::new(UPDATE_JITTER, rand::random()),
+        );
+        loop {
+            propagation_interval.tick().await;
+
+            trace!("Propagating selected routes");
+
+            let start = Instant::now();
+            self.propagate_selected_routes_to_peers();
+            self.metrics
+                .router_time_spent_periodic_propagating_selected_routes(start.elapsed());
+        }
+    }
+
+    /// Task which sends the triggered updates which were delayed because the minimum interval
+    /// between triggered updates to the peer had not passed yet.
+    async fn send_pending_triggered_updates(self) {
+        let min_interval = self.triggered_updates.min_interval();
+        // Check a couple of times per interval, so updates aren't delayed much longer than needed.
+        let mut ticker = periodic_interval(min_interval / 4);
+        loop {
+            ticker.tick().await;
+
+            for (peer, routes) in self.triggered_updates.due(tokio::time::Instant::now()) {
+                trace!(
+                    "Sending batch of {} triggered updates to {}",
+                    routes.len(),
+                    peer.connection_identifier()
+                );
+                for (subnet, source_prefix) in routes {
+                    self.propagate_selected_route(subnet, source_prefix, Some(vec![peer.clone()]));
+                }
+            }
+        }
+    }
+
+    /// Task which periodically sends a Hello TLV to all known peers. If fast Hellos are enabled,
+    /// peers on a lossy link are sent Hellos twice per hello interval. The time between Hellos is
+    /// jittered, so the Hellos of different nodes don't synchronize.
+    async fn start_periodic_hello_sender(self) {
+        let hello_interval = self.intervals.hello;
+        let fast_hellos = self.hello_history.fast_hello_threshold.is_some();
+        let mut hello_ticker = JitteredInterval::new(
+            if fast_hellos {
+                hello_interval / 2
+            } else {
+                hello_interval
+            },
+            Jitter::new(HELLO_JITTER, rand::random()),
+        );
+        // Only peers on a lossy link are sent a Hello on the extra ticks of fast Hellos.
+        let mut extra_tick = false;
+        loop {
+            hello_ticker.tick().await;
+
+            for peer in self.peer_interfaces.read().unwrap().iter() {
+                let interval = if self.hello_history.wants_fast_hellos(peer.hello_reception()) {
+                    hello_interval / 2
+                } else if extra_tick {
+                    continue;
+                } else {
+                    hello_interval
+                };
+                let hello = ControlPacket::new_hello(peer, interval);
+                peer.set_time_last_sent_hello(tokio::time::Instant::now());
+
+                if self.send_control_packet(&peer, hello).is_err() {
+                    trace!(
+                        "Failed to send Hello TLV to dead peer {}",
+                        peer.connection_identifier()
+                    );
+                }
+            }
+            extra_tick = fast_hellos && !extra_tick;
+        }
+    }
+
+    /// Propagates the static routes to all known peers.
+    fn propagate_selected_routes_to_peers(&self) {
+        for peer in self.peer_interfaces.read().unwrap().iter() {
+            self.propagate_selected_routes_to_peer(peer);
+        }
+    }
+
+    /// Propagate the static routes to a single peer
+    fn propagate_static_route_to_peer(&self, peer: &Peer) {
+        for (sr, source_prefix) in self.static_routes.iter() {
+            let update = babel::Update::new(
+                self.intervals.update,
+                self.router_seqno.read().unwrap().0, // updates receive the seqno of the router
+                Metric::from(0),                     // Static route has no further hop costs
+                *sr,
+                self.router_id,
+            )
+            .with_source_prefix(*source_prefix);
+            self.send_update(peer, update);
+        }
+    }
+
+    /// Send a wildcard retraction to the given [`Peer`], which retracts all routes we advertise to
+    /// it at once. This is used before intentionally closing the connection to a peer, so it
+    /// doesn't have to wait for the routes to expire before it stops using us as next hop.
+    pub fn retract_routes_to_peer(&self, peer: &Peer) {
+        if !peer.alive() {
+            trace!("Cowardly refusing to sent update to peer which we know is dead");
+            self.metrics.router_update_dead_peer();
+            return;
+        }
+
+        debug!("Retracting routes to {}", peer.connection_identifier());
+        // The source table is not updated, since the retraction does not refer to a route.
+        let retraction = babel::Update::new_wildcard_retraction(
+            self.router_seqno.read().unwrap().0,
+            self.router_id,
+        );
+        if self
+            .send_control_packet(peer, ControlPacket::Update(retraction))
+            .is_err()
+        {
+            trace!(
+                "Failed to send wildcard retraction to peer {}",
+                peer.connection_identifier()
+            );
+        }
+    }
 
-            // Otherwise we just update the entry.
-            if existing_entry.metric().is_infinite() && update.metric().is_infinite() {
-                // Retraction for retracted route, don't do anything. If we don't filter this
-                // retracted routes can stay stuck if peer keep sending retractions to eachother
-                // for this route.
-                return;
+    /// Get the selected route for a subnet, and source prefix in case of a source-specific route.
+    fn selected_route(&self, subnet: Subnet, source_prefix: Option<Subnet>) -> Option<RouteEntry> {
+        let inner = self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on the router so read handle is always available; qed");
+        match source_prefix {
+            None => inner
+                .routing_table
+                .lookup_selected(subnet.address())
+                .cloned(),
+            Some(_) => inner
+                .routing_table
+                .entries(subnet, source_prefix)
+                .into_iter()
+                .find(|re| re.selected()),
+        }
+    }
+
+    /// Propagate a selected route. Unless peers are specified, all knwon peers in the router are
+    /// used.
+    fn propagate_selected_route(
+        &self,
+        subnet: Subnet,
+        source_prefix: Option<Subnet>,
+        peers: Option<Vec<Peer>>,
+    ) {
+        let (update, maybe_neigh) = if let Some(sre) = self
+            .selected_route(subnet, source_prefix)
+            .filter(|sre| self.is_advertised(sre))
+        {
+            let update = babel::Update::new(
+                advertised_update_interval(&sre, self.intervals.update),
+                sre.seqno(),
+                sre.metric() + Metric::from(sre.neighbour().link_cost()),
+                sre.source().subnet(),
+                sre.source().router_id(),
+            )
+            .with_source_prefix(sre.source().source_prefix());
+            (update, Some(sre.neighbour().clone()))
+        } else {
+            // This can happen if the only feasible route gets an infinite metric, as those are
+            // never selected, or if the selected route is a pinned route which isn't advertised.
+            info!("Retracting route for {subnet}");
+            let update = babel::Update::new(
+                self.intervals.update,
+                self.router_seqno.read().unwrap().0,
+                Metric::infinite(),
+                subnet,
+                self.router_id,
+            )
+            .with_source_prefix(source_prefix);
+            (update, None)
+        };
+
+        let send_update = |peer: &Peer| {
+            if let Some(ref neigh) = maybe_neigh {
+                if peer == neigh {
+                    self.send_update_to_next_hop(peer, update.clone());
+                    return;
+                }
             }
-            existing_entry.update_seqno(seqno);
-            existing_entry.update_metric(metric);
-            existing_entry.update_router_id(router_id);
-            let rk = RouteKey::new(subnet, source_peer);
-            inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
-                rk.clone(),
-                seqno,
-                metric,
-                router_id,
-                route_hold_time(&update),
-            ));
-            // If the update is unfeasible the route must be unselected.
-            if existing_entry.selected() && !update_feasible {
-                existing_route_unselected = true;
-                existing_entry.set_selected(false);
-                inner_w.append(RouterOpLogEntry::UnselectRoute(rk));
+            debug!(
+                "Propagating route update for {} to {}",
+                subnet,
+                peer.connection_identifier()
+            );
+            self.send_update(peer, update.clone());
+        };
+
+        if let Some(peers) = peers {
+            for peer in peers {
+                send_update(&peer);
             }
         } else {
-            // If there is no entry yet ignore unfeasible updates and retractions.
-            if metric.is_infinite() || !update_feasible {
-                debug!("Received unfeasible update | retraction for unknown route - neighbour");
-                return;
+            for peer in self.peer_interfaces.read().unwrap().iter() {
+                send_update(peer);
             }
+        };
+    }
 
-            // Create new entry in the route table
-            let re = RouteEntry::new(
-                SourceKey::new(subnet, router_id),
-                source_peer.clone(),
-                metric,
-                seqno,
-                false,
-                route_hold_time(&update),
+    /// Propagate all selected routes to a single peer. This is skipped if more
+    /// than [`MAX_ROUTE_DUMP_BACKLOG`] control packets are still queued for the peer, as those
+    /// most likely hold the previous dump.
+    fn propagate_selected_routes_to_peer(&self, peer: &Peer) {
+        let backlog = peer.queued_control_packets();
+        if backlog > MAX_ROUTE_DUMP_BACKLOG {
+            debug!(
+                "Not propagating selected routes to {}, {backlog} control packets are still queued",
+                peer.connection_identifier()
             );
-            routing_table_entries.push(re.clone());
+            self.metrics.router_route_dump_skipped();
+            return;
+        }
 
-            let ss = self.node_keypair.0.shared_secret(&router_id.to_pubkey());
-            inner_w.append(RouterOpLogEntry::InsertRoute(
-                RouteKey::new(subnet, source_peer),
-                re,
-                router_id.to_pubkey(),
-                ss,
-            ));
+        for (srk, _, sre) in self
+            .inner_r
+            .enter()
+            .expect("Write handle is saved on router so read handle is always available; qed")
+            .routing_table
+            .iter()
+            .filter(|(_, _, sre)| sre.selected() && self.is_advertised(sre))
+        {
+            let neigh_link_cost = Metric::from(sre.neighbour().link_cost());
+            let update = babel::Update::new(
+                advertised_update_interval(sre, self.intervals.update),
+                sre.seqno(),
+                // the cost of the route is the cost of the route + the cost of the link to the next-hop
+                sre.metric() + neigh_link_cost,
+                srk.subnet(),
+                sre.source().router_id(),
+            )
+            .with_source_prefix(srk.source_prefix());
+            if peer == sre.neighbour() {
+                self.send_update_to_next_hop(peer, update);
+                continue;
+            }
+            debug!(
+                "Propagating route update for {} to {} | D({}, {})",
+                srk.subnet(),
+                peer.connection_identifier(),
+                sre.seqno(),
+                sre.metric() + neigh_link_cost,
+            );
+            self.send_update(peer, update);
         }
+    }
 
-        // Now that we applied the update, run route selection.
-        let new_selected_route =
-            self.find_best_route(&routing_table_entries, old_selected_route.as_ref());
-        if let Some(nbr) = new_selected_route {
-            // Install this route in the routing table. We don't update the local copy anymore as
-            // we don't use it afterwards.
-            inner_w.append(RouterOpLogEntry::SelectRoute(RouteKey::new(
-                subnet,
-                nbr.neighbour().clone(),
-            )));
-        } else if let Some(osr) = old_selected_route.as_ref() {
-            // If there is no new selected route, but there was one previously, update the routing
-            // table. This is not covered above, as there only unfeasible updates cause a selected
-            // route to be unselected. However, this may also be the result of a feasible update
-            // (e.g. retraction with no feasible fallback routes).
-            // Only do this if we did not unselect the existing route previously.
-            // Regardless if we unselect the route here or not, we lost a selected route for the
-            // subnet, so we try to refresh the route by sending out a seqno request to all
-            // neigbours who advertised the route at some point.
-            self.send_seqno_request(osr.source(), None, None);
-            if !existing_route_unselected {
-                inner_w.append(RouterOpLogEntry::UnselectRoute(RouteKey::new(
-                    subnet,
-                    osr.neighbour().clone(),
-                )));
+    /// Send an update for a route to the next hop of that route, according to the configured
+    /// [`SplitHorizon`] mode.
+    ///
+    /// The next hop will never select the route through us, as that would cause a routing loop.
+    /// With [`SplitHorizon::Simple`], nothing is sent. With [`SplitHorizon::PoisonReverse`], the
+    /// update is sent with an infinite metric, so the next hop drops any route it has through us
+    /// right away, instead of waiting for it to expire or for route selection to replace it.
+    fn send_update_to_next_hop(&self, peer: &Peer, update: babel::Update) {
+        if self.split_horizon == SplitHorizon::Simple {
+            trace!("Not advertising route since the peer is the next-hop");
+            return;
+        }
+
+        if !peer.alive() {
+            trace!("Cowardly refusing to sent update to peer which we know is dead");
+            self.metrics.router_update_dead_peer();
+            return;
+        }
+
+        // The source table is not updated, since a poisoned update does not advertise a route.
+        let poisoned = babel::Update::new(
+            update.interval(),
+            update.seqno(),
+            Metric::infinite(),
+            update.subnet(),
+            update.router_id(),
+        )
+        .with_source_prefix(update.source_prefix());
+        trace!(
+            "Sending poisoned update for {} to next-hop {}",
+            poisoned.subnet(),
+            peer.connection_identifier()
+        );
+        if self
+            .send_control_packet(peer, ControlPacket::Update(poisoned))
+            .is_err()
+        {
+            trace!(
+                "Failed to send update to dead peer {}",
+                peer.connection_identifier()
+            );
+        }
+    }
+
+    /// Send an update to a peer.
+    ///
+    /// The source table is updated as described in the RFC once the update is queued for the peer,
+    /// so it only holds advertisements which are actually sent. While the router is draining, updates for transit routes are sent with [`DRAIN_METRIC`].
+    fn send_update(&self, peer: &Peer, mut update: babel::Update) {
+        // Sanity check, verify what we are doing is actually usefull
+        if !peer.alive() {
+            trace!("Cowardly refusing to sent update to peer which we know is dead");
+            self.metrics.router_update_dead_peer();
+            return;
+        }
+
+        if update.router_id() != self.router_id
+            && update.metric() < DRAIN_METRIC
+            && self.drain.read().unwrap().is_some()
+        {
+            update = babel::Update::new(
+                update.interval(),
+                update.seqno(),
+                DRAIN_METRIC,
+                update.subnet(),
+                update.router_id(),
+            )
+            .with_source_prefix(update.source_prefix());
+        }
+
+        // The peer can't decode the update, so it is not advertised to it.
+        if update.source_prefix().is_some() && !peer.accepts_source_specific_routes() {
+            trace!("Not sending source-specific update to peer which does not support it");
+            return;
+        }
+
+        let metric = update.metric();
+        let seqno = update.seqno();
+        let source_key = SourceKey::new(update.subnet(), update.router_id())
+            .with_source_prefix(update.source_prefix());
+
+        // send the update to the peer
+        trace!("Sending update to peer");
+        if self
+            .send_control_packet(peer, ControlPacket::Update(update))
+            .is_err()
+        {
+            // An error indicates the peer is dead
+            trace!(
+                "Failed to send update to dead peer {}",
+                peer.connection_identifier()
+            );
+            return;
+        }
+
+        // After sending an update, the source table might need to be updated
+        let mut source_table = self.source_table.write().unwrap();
+
+        if let Some(source_entry) = source_table.get(&source_key) {
+            // if seqno of the update is greater than the seqno in the source table, update the source table
+            if seqno.gt(&source_entry.seqno()) {
+                source_table.insert(
+                    source_key,
+                    FeasibilityDistance::new(metric, seqno),
+                    self.expired_source_key_sink.clone(),
+                );
             }
+            // if seqno of the update is equal to the seqno in the source table, update the source table if the metric (of the update) is lower
+            else if seqno == source_entry.seqno() && source_entry.metric() > metric {
+                source_table.insert(
+                    source_key,
+                    // Technically the seqno in the feasibility distance comes from the source
+                    // entry, but that gives a borrow conflict so we use seqno from the update,
+                    // which we just verified is the same.
+                    FeasibilityDistance::new(metric, seqno),
+                    self.expired_source_key_sink.clone(),
+                )
+            }
+            // We also reset the garbage collection timer (unless the update is a retraction)
+            else if !metric.is_infinite() {
+                source_table.reset_timer(source_key, self.expired_source_key_sink.clone());
+            }
+        }
+        // no entry for this source key, so insert it
+        else {
+            source_table.insert(
+                source_key,
+                FeasibilityDistance::new(metric, seqno),
+                self.expired_source_key_sink.clone(),
+            )
+        };
+    }
+}
+
+/// Collect the paths traffic using the `selected` route is spread over. These are the
+/// selected route, and up to [`MAX_ECMP_PATHS`] - 1 of the `other` routes for the same subnet
+/// and source prefix which are feasible, and have the same metric, including the metric offset,
+/// through a live neighbour. The amount of collected paths is returned as well.
+fn equal_cost_paths<'a>(
+    source_table: &SourceTable,
+    metric_offsets: &MetricOffsets,
+    selected: &'a RouteEntry,
+    others: impl Iterator<Item = &'a RouteEntry>,
+) -> ([&'a RouteEntry; MAX_ECMP_PATHS], usize) {
+    let mut paths = [selected; MAX_ECMP_PATHS];
+    let mut amount = 1;
+
+    let cost = |re: &RouteEntry| {
+        re.metric() + Metric::from(re.neighbour().link_cost()) + metric_offsets.route_offset(re)
+    };
+    let metric = cost(selected);
+    for re in others
+        .filter(|re| {
+            !re.metric().is_infinite()
+                && re.neighbour().alive()
+                && cost(re) == metric
+                && source_table.route_feasible(re)
+        })
+        .take(MAX_ECMP_PATHS - 1)
+    {
+        paths[amount] = re;
+        amount += 1;
+    }
+
+    (paths, amount)
+}
+
+/// Manual clone implementation to avoid placing a where bound on `Router`, which would in turn
+/// require a where bound on all structs which end up containing Router.
+impl<M> Clone for Router<M>
+where
+    M: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner_w: self.inner_w.clone(),
+            inner_r: self.inner_r.clone(),
+            peer_interfaces: self.peer_interfaces.clone(),
+            source_table: self.source_table.clone(),
+            router_seqno: self.router_seqno.clone(),
+            static_routes: self.static_routes.clone(),
+            exit_node: self.exit_node,
+            router_id: self.router_id,
+            node_keypair: self.node_keypair.clone(),
+            router_data_tx: self.router_data_tx.clone(),
+            router_control_tx: self.router_control_tx.clone(),
+            node_tun: self.node_tun.clone(),
+            node_tun_subnet: self.node_tun_subnet,
+            update_filters: self.update_filters.clone(),
+            route_selector: self.route_selector.clone(),
+            dead_peer_sink: self.dead_peer_sink.clone(),
+            expired_source_key_sink: self.expired_source_key_sink.clone(),
+            seqno_cache: self.seqno_cache.clone(),
+            route_events: self.route_events.clone(),
+            split_horizon: self.split_horizon,
+            route_hold_time_multiplier: self.route_hold_time_multiplier,
+            intervals: self.intervals,
+            flap_damping: self.flap_damping.clone(),
+            hello_history: self.hello_history,
+            metric_offsets: self.metric_offsets.clone(),
+            pinned_routes: self.pinned_routes.clone(),
+            max_routes: self.max_routes,
+            drain: self.drain.clone(),
+            ciphers: self.ciphers.clone(),
+            path_mtu: self.path_mtu.clone(),
+            triggered_updates: self.triggered_updates.clone(),
+            stats: self.stats.clone(),
+            event_log: self.event_log.clone(),
+            #[cfg(feature = "testing")]
+            fault_injector: self.fault_injector.clone(),
+            ecmp_seed: self.ecmp_seed,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+pub struct RouterInner {
+    routing_table: RoutingTable<(PublicKey, SharedSecret)>,
+    expired_route_entry_sink: mpsc::Sender<(RouteKey, RouteExpirationType)>,
+}
+
+impl RouterInner {
+    pub fn new(
+        expired_route_entry_sink: mpsc::Sender<(RouteKey, RouteExpirationType)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let router_inner = RouterInner {
+            routing_table: RoutingTable::new(),
+            expired_route_entry_sink,
         };
 
-        // Already publish here, we won't make any other adjustments to the routing table.
-        inner_w.publish();
+        Ok(router_inner)
+    }
+}
 
-        // At this point we are done, though we would like to understand if we need to send a
-        // triggered update to our peers. This is done if there is a sufficiently large change. We
-        // consider a sufficiently large change to be:
-        // - change in router_id,
-        // - aquired a route, i.e. previously there was no selected route but there is now,
-        // - lost the route (i.e. it is retracted).
-        // - significant metric change
-        // What doesn't constitue a large change:
-        // - small metric change
-        // - seqno increase (unless it is requested by a peer)
-        // TODO: we don't memorize seqno requests for now so consider broadcasting this anyway
-        let trigger_update = match (&old_selected_route, new_selected_route) {
-            (Some(old_route), Some(new_route)) => {
-                if new_route.neighbour() != old_route.neighbour() {
-                    info!(
-                        "Selected route for {subnet} changed next-hop from {} to {}",
-                        old_route.neighbour().connection_identifier(),
-                        new_route.neighbour().connection_identifier()
-                    );
-                }
-                // Router id changed.
-                new_route.source().router_id() != old_route.source().router_id()
-                // TODO: remove | seqno changed
-                    || new_route.seqno().gt(&old_route.seqno())
-                    || new_route.metric().delta(&old_route.metric()) > BIG_METRIC_CHANGE_TRESHOLD
-            }
-            (None, Some(new_route)) => {
-                info!(
-                    "Acquired route to {subnet} via {}",
-                    new_route.neighbour().connection_identifier()
+enum RouterOpLogEntry {
+    /// Insert a new entry in the routing table.
+    InsertRoute(RouteKey, RouteEntry, PublicKey, SharedSecret),
+    /// Removes a route with the given route key.
+    RemoveRoute(RouteKey),
+    /// Unselect the route defined by the route key.
+    UnselectRoute(RouteKey),
+    /// Select the route defined by the route key.
+    SelectRoute(RouteKey),
+    /// Update the route entry associated to the given route key in the fallback route table, if
+    /// one exists
+    UpdateRouteEntry(RouteKey, SeqNo, Metric, RouterId, Duration),
+}
+
+impl left_right::Absorb<RouterOpLogEntry> for RouterInner {
+    fn absorb_first(&mut self, operation: &mut RouterOpLogEntry, _: &Self) {
+        match operation {
+            RouterOpLogEntry::InsertRoute(rk, re, pk, ss) => {
+                self.routing_table.insert(
+                    rk.clone(),
+                    (*pk, ss.clone()),
+                    re.clone(),
+                    self.expired_route_entry_sink.clone(),
                 );
-                true
             }
-            (Some(old_route), None) => {
-                info!(
-                    "Lost route to {subnet} via {}",
-                    old_route.neighbour().connection_identifier()
-                );
-                true
+            RouterOpLogEntry::RemoveRoute(rk) => {
+                self.routing_table.remove(rk);
             }
-            (None, None) => false,
-        };
+            RouterOpLogEntry::UnselectRoute(rk) => {
+                self.routing_table.unselect_route(rk);
+            }
+            RouterOpLogEntry::SelectRoute(rk) => {
+                self.routing_table.select_route(rk);
+            }
+            RouterOpLogEntry::UpdateRouteEntry(rk, seqno, metric, pk, expiration) => {
+                self.routing_table.update(rk, |re| {
+                    re.update_seqno(*seqno);
+                    re.update_metric(*metric);
+                    re.update_router_id(*pk);
+                    re.update_expiration(*expiration);
+                });
+            }
+        }
+    }
 
-        if trigger_update {
-            debug!("Send triggered update for {subnet} in response to update");
-            self.trigger_update(subnet, None);
-        } else if interested_peers.is_some() {
-            debug!(
-                "Send update to peers who registered interest through a seqno request for {subnet}"
-            );
-            self.trigger_update(subnet, interested_peers);
-            // If we have some interest in an update because we forwarded a seqno request but we
-            // aren't triggering an update, notify just the interested peers.
+    fn sync_with(&mut self, first: &Self) {
+        *self = first.clone()
+    }
+}
+
+impl Clone for RouterInner {
+    fn clone(&self) -> Self {
+        let RouterInner {
+            routing_table,
+            expired_route_entry_sink,
+        } = self;
+        let mut new_routing_table = RoutingTable::new();
+        for (k, e, v) in routing_table.iter() {
+            new_routing_table.insert(k, e.clone(), v.clone(), expired_route_entry_sink.clone());
+        }
+        RouterInner {
+            routing_table: new_routing_table,
+            expired_route_entry_sink: expired_route_entry_sink.clone(),
         }
     }
+}
 
-    /// Trigger an update for the given [`Subnet`]. If `peers` is [`None`], send the update to all
-    /// peers the `Router` knows.
-    fn trigger_update(&self, subnet: Subnet, peers: Option<Vec<Peer>>) {
-        self.metrics.router_triggered_update();
-        self.propagate_selected_route(subnet, peers);
+/// Calculate the hold time for a [`RouteEntry`] from an [`Update`](babel::Update), by applying
+/// the `multiplier` to the interval of the update.
+fn route_hold_time(update: &babel::Update, multiplier: f64) -> Duration {
+    // In case of a retracted route: in general this should not be added to the routing table, so
+    // the only reason this is called is because a route was retracted through an update. Even if
+    // the peer won't send this again, hold the route for some time so it can get flushed properly.
+    if update.metric().is_infinite() {
+        RETRACTED_ROUTE_HOLD_TIME
+    } else {
+        // Route expiry time -> multiplier times advertised Update interval.
+        Duration::from_millis((update.interval().as_millis() as f64 * multiplier) as u64)
     }
+}
 
-    /// Send a seqno request for a subnet. This can be sent to a given peer, or to all peers for
-    /// the subnet if no peer is given.
-    ///
-    /// The SourceKey must exist in the source table.
-    fn send_seqno_request(
-        &self,
-        source: SourceKey,
-        to: Option<Peer>,
-        request_origin: Option<Peer>,
-    ) {
-        let fd = match self.source_table.read().unwrap().get(&source) {
-            Some(fd) => *fd,
-            None => {
-                // This can happen if you only have 1 peer, or in case we haven't announced our
-                // subnets yet.
-                debug!("Requesting seqno for source key {source} which does not exist in the source table");
-                // Since we don't know the real seqno, just use the default. Either the peer will
-                // reply if it has an adequate route, or it will forward the request.
-                FeasibilityDistance::new(Metric::new(0), SeqNo::new())
-            }
-        };
+/// Create an [`Interval`](tokio::time::Interval) which first ticks after `period`, and then every
+/// `period`. Ticks are scheduled relative to the start rather than the previous tick, so the
+/// interval doesn't drift. If ticks are missed, they are skipped instead of sent in a burst.
+fn periodic_interval(period: Duration) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    interval
+}
 
-        let sn: ControlPacket =
-            SeqNoRequest::new(fd.seqno() + 1, source.router_id(), source.subnet()).into();
+/// Calculates the interval to use when announcing updates on (selected) routes, given the
+/// configured update interval.
+fn advertised_update_interval(sre: &RouteEntry, update_interval: Duration) -> Duration {
+    // We actually just need to set the value of the update interval, since that is the upper bound
+    // on when we will advertise the route again.
+    // One caveat is an expired route. If an entry is expired, it means that it will change state
+    // so: Infinite metric -> route entry will be removed. Finite metric -> route entry will be
+    // retracted but will be announced again. In practice this shouldn't really happen anyway.
+    if sre.metric().is_infinite() && sre.expires().as_nanos() == 0 {
+        INTERVAL_NOT_REPEATING
+    } else {
+        update_interval
+    }
+}
 
-        let srck = SeqnoRequestCacheKey {
-            router_id: source.router_id(),
-            subnet: source.subnet(),
-            seqno: fd.seqno() + 1,
-        };
+/// The /64 [`Subnet`] of the node which owns the given overlay address.
+fn node_subnet(address: Ipv6Addr) -> Subnet {
+    Subnet::new(address.into(), 64).expect("64 is a valid IPv6 prefix size; qed")
+}
 
-        let seqno_info = self.seqno_cache.info(&srck);
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        time::{Duration, Instant},
+    };
 
-        let targets = if let Some(target) = to {
-            vec![target]
-        } else {
-            // If we don't have a dedicated peer to send to, just send to every peer which
-            // announced the subnet. But avoid repetitions. Additionally, if we don't have any
-            // peer which announced the subnet (because all anounces are unfeasible)
-            let mut peers_sent = vec![];
-            if let Some((last_sent, visited)) = seqno_info {
-                // If it's more than some time since we last sent an update to any peer for this
-                // seqno request, send it again. We use the seqno bump timeout here, since that is
-                // the quickest time between bumps from a peer.
-                if last_sent.elapsed() < SEQNO_BUMP_TIMEOUT {
-                    peers_sent = visited;
-                }
-            };
+    use bytes::BytesMut;
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+    use tokio_util::codec::Framed;
 
-            let known_routes = self
-                .inner_r
-                .enter()
-                .expect("Write handle is saved on router so this read always succeeds; qed")
-                .routing_table
-                .entries(source.subnet());
+    use crate::{
+        access_list::AccessList,
+        babel::{self, RouteRequest, SeqNoRequest, Update},
+        crypto::{Cipher, PublicKey, SecretKey},
+        event_log::{RoutingEvent, DEFAULT_EVENT_LOG_SIZE},
+        flap_damping::FlapDampingConfig,
+        hello_history::HelloHistoryConfig,
+        metric::Metric,
+        metric_offset::{MetricOffset, MetricOffsets},
+        metrics::Metrics,
+        packet::{self, DataPacket, Packet},
+        peer::{DisconnectReason, Peer, PeerConfig},
+        pinned_route::PinnedRoute,
+        route_selection::MetricSelector,
+        router_id::RouterId,
+        sequence_number::SeqNo,
+        source_table::SourceKey,
+        subnet::Subnet,
+    };
 
-            // Make sure a broadcast only happens in case the local node originated the request.
-            if known_routes.is_empty() && request_origin.is_none() {
-                // If we don't know the route just ask all our peers.
-                self.peer_interfaces
-                    .read()
-                    .unwrap()
-                    .iter()
-                    .cloned()
-                    .collect()
-            } else {
-                known_routes
-                    .into_iter()
-                    .filter_map(|re| {
-                        if !peers_sent.contains(re.neighbour()) {
-                            Some(re.neighbour().clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
+    #[derive(Clone)]
+    pub(crate) struct NoMetrics;
+    impl Metrics for NoMetrics {}
+
+    /// A [`Router`](super::Router) to test. This dereferences to the router, and keeps the
+    /// receiving ends of the channels of the router and its peers open during the test.
+    pub(crate) struct TestRouter<M = NoMetrics> {
+        router: super::Router<M>,
+        node_tun_rx: mpsc::Receiver<(DataPacket, Option<Peer>)>,
+        dead_peer_sink: mpsc::Sender<Peer>,
+        _dead_peer_stream: mpsc::Receiver<Peer>,
+    }
+
+    impl TestRouter {
+        /// Start building a `TestRouter`. Everything which is not set on the builder uses the
+        /// default value of the node.
+        pub(crate) fn builder() -> TestRouterBuilder {
+            TestRouterBuilder {
+                node_subnet: Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0).into(), 64)
+                    .expect("Valid subnet definition"),
+                static_routes: vec![],
+                flap_damping: FlapDampingConfig::default(),
+                max_routes: None,
+                event_log_size: DEFAULT_EVENT_LOG_SIZE,
+                metrics: NoMetrics,
             }
-        };
+        }
+    }
 
-        for peer in targets {
-            debug!(
-                "Sending seqno_request to {} for seqno {} of {}",
-                peer.connection_identifier(),
-                fd.seqno() + 1,
-                source.subnet(),
-            );
+    impl<M> TestRouter<M>
+    where
+        M: Metrics + Clone + Send + Sync + 'static,
+    {
+        /// Create a [`Peer`] of the router on the given connection. The peer is not added as peer
+        /// interface of the router.
+        pub(crate) fn peer(&self, con: tokio::io::DuplexStream) -> Peer {
+            Peer::new(
+                PeerConfig {
+                    dead_peer_sink: self.dead_peer_sink.clone(),
+                    ..self.router.peer_config()
+                },
+                con,
+            )
+            .expect("Can create a dummy peer")
+        }
+    }
 
-            if peer.send_control_packet(sn.clone()).is_err() {
-                trace!(
-                    "Failed to send seqno request to {}",
-                    peer.connection_identifier()
-                );
-                continue;
-            }
+    impl<M> std::ops::Deref for TestRouter<M> {
+        type Target = super::Router<M>;
 
-            self.seqno_cache.forward(srck, peer, request_origin.clone());
+        fn deref(&self) -> &Self::Target {
+            &self.router
         }
     }
 
-    /// Checks if a route key is an exact match for a static route.
-    fn route_key_is_from_static_route(&self, route_key: &RouteKey) -> bool {
-        for sr in self.static_routes.iter() {
-            if sr == &route_key.subnet() {
-                return true;
-            }
+    /// Builder for a [`TestRouter`].
+    pub(crate) struct TestRouterBuilder<M = NoMetrics> {
+        node_subnet: Subnet,
+        static_routes: Vec<(Subnet, Option<Subnet>)>,
+        flap_damping: FlapDampingConfig,
+        max_routes: Option<usize>,
+        event_log_size: usize,
+        metrics: M,
+    }
+
+    impl<M> TestRouterBuilder<M>
+    where
+        M: Metrics + Clone + Send + Sync + 'static,
+    {
+        fn node_subnet(mut self, node_subnet: Subnet) -> Self {
+            self.node_subnet = node_subnet;
+            self
+        }
+
+        fn static_routes(mut self, static_routes: Vec<(Subnet, Option<Subnet>)>) -> Self {
+            self.static_routes = static_routes;
+            self
         }
-        false
-    }
 
-    pub fn route_packet(&self, mut data_packet: DataPacket) {
-        let node_tun_subnet = self.node_tun_subnet();
+        fn flap_damping(mut self, flap_damping: FlapDampingConfig) -> Self {
+            self.flap_damping = flap_damping;
+            self
+        }
 
-        trace!(
-            "Incoming data packet {} -> {}",
-            data_packet.src_ip,
-            data_packet.dst_ip,
-        );
+        fn max_routes(mut self, max_routes: usize) -> Self {
+            self.max_routes = Some(max_routes);
+            self
+        }
 
-        if data_packet.hop_limit < 2 {
-            self.metrics.router_route_packet_ttl_expired();
-            self.time_exceeded(data_packet);
-            return;
+        fn event_log_size(mut self, event_log_size: usize) -> Self {
+            self.event_log_size = event_log_size;
+            self
         }
-        data_packet.hop_limit -= 1;
 
-        if node_tun_subnet.contains_ip(data_packet.dst_ip.into()) {
-            self.metrics.router_route_packet_local();
-            if let Err(e) = self.node_tun().send(data_packet) {
-                error!("Error sending data packet to TUN interface: {:?}", e);
-            }
-        } else {
-            match self.select_best_route(IpAddr::V6(data_packet.dst_ip)) {
-                Some(route_entry) => {
-                    self.metrics.router_route_packet_forward();
-                    if let Err(e) = route_entry.neighbour().send_data_packet(data_packet) {
-                        error!(
-                            "Error sending data packet to peer {}: {:?}",
-                            route_entry.neighbour().connection_identifier(),
-                            e
-                        );
-                    }
-                }
-                None => {
-                    self.metrics.router_route_packet_no_route();
-                    self.no_route_to_host(data_packet);
-                }
+        fn metrics<N>(self, metrics: N) -> TestRouterBuilder<N> {
+            TestRouterBuilder {
+                node_subnet: self.node_subnet,
+                static_routes: self.static_routes,
+                flap_damping: self.flap_damping,
+                max_routes: self.max_routes,
+                event_log_size: self.event_log_size,
+                metrics,
             }
         }
-    }
 
-    /// Handle a received data packet.
-    async fn handle_incoming_data_packet(self, mut router_data_rx: Receiver<DataPacket>) {
-        while let Some(data_packet) = router_data_rx.recv().await {
-            self.route_packet(data_packet);
+        /// Create the [`TestRouter`], with a new node key.
+        pub(crate) fn build(self) -> TestRouter<M> {
+            let (node_tun, node_tun_rx) = mpsc::channel(1);
+            let (dead_peer_sink, dead_peer_stream) = mpsc::channel(16);
+            let sk = SecretKey::new();
+            let pk = PublicKey::from(&sk);
+            let router = super::Router::new(
+                node_tun,
+                super::RouterConfig {
+                    node_subnet: self.node_subnet,
+                    static_routes: self.static_routes,
+                    node_keypair: (sk, pk),
+                    update_filters: vec![],
+                    route_selector: Box::new(MetricSelector::default()),
+                    split_horizon: super::SplitHorizon::Simple,
+                    route_hold_time_multiplier: super::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER,
+                    intervals: super::Intervals::default(),
+                    queue_sizes: super::QueueSizes::default(),
+                    flap_damping: self.flap_damping,
+                    hello_history: HelloHistoryConfig::default(),
+                    metric_offsets: MetricOffsets::default(),
+                    max_routes: self.max_routes,
+                    event_log_size: self.event_log_size,
+                    metrics: self.metrics,
+                },
+            )
+            .expect("Can create a router");
+
+            TestRouter {
+                router,
+                node_tun_rx,
+                dead_peer_sink,
+                _dead_peer_stream: dead_peer_stream,
+            }
         }
-        warn!("Router data receiver stream ended");
     }
 
-    /// Handle a packet who's TTL is too low.
-    fn time_exceeded(&self, data_packet: DataPacket) {
-        trace!("Refusing to forward expired packet");
-        self.oob_icmp(
-            Icmpv6Type::TimeExceeded(TimeExceededCode::HopLimitExceeded),
-            data_packet,
+    #[test]
+    fn calculate_route_hold_time() {
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+        let seqno = SeqNo::new();
+        let metric = Metric::new(0);
+        let subnet = Subnet::new(
+            IpAddr::V6(Ipv6Addr::new(
+                0x400, 0x0123, 0x4567, 0x89AB, 0xCDEF, 0, 0, 0,
+            )),
+            64,
         )
-    }
+        .expect("Valid subnet definition");
+        let update = Update::new(Duration::from_secs(60), seqno, metric, subnet, router_id);
+        assert_eq!(
+            Duration::from_millis(210_000),
+            super::route_hold_time(&update, super::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER)
+        );
+        let update = Update::new(Duration::from_secs(1), seqno, metric, subnet, router_id);
+        assert_eq!(
+            Duration::from_millis(3_500),
+            super::route_hold_time(&update, super::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER)
+        );
+        // Since update is expressed in centiseconds, we lose precision and
+        // Duration::from_milis(478) is equal to Duration::from_millis(470);
+        let update = Update::new(Duration::from_millis(478), seqno, metric, subnet, router_id);
+        assert_eq!(
+            Duration::from_millis(1_645),
+            super::route_hold_time(&update, super::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER)
+        );
+        // Other multipliers can be used.
+        let update = Update::new(Duration::from_secs(60), seqno, metric, subnet, router_id);
+        assert_eq!(
+            Duration::from_secs(300),
+            super::route_hold_time(&update, 5.0)
+        );
 
-    /// Handle a packet if we have no route for the destination address.
-    fn no_route_to_host(&self, data_packet: DataPacket) {
-        trace!(
-            "Could not forward data packet, no route found for {}",
-            data_packet.dst_ip
+        // Retractions are also held for some time
+        let update = Update::new(
+            Duration::from_millis(0),
+            seqno,
+            Metric::infinite(),
+            subnet,
+            router_id,
+        );
+        assert_eq!(
+            super::RETRACTED_ROUTE_HOLD_TIME,
+            super::route_hold_time(&update, super::DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER)
         );
+    }
 
-        self.oob_icmp(
-            Icmpv6Type::DestinationUnreachable(DestUnreachableCode::NoRoute),
-            data_packet,
+    #[tokio::test]
+    async fn calculate_advertised_update_interval() {
+        // Set up a dummy peer since that is needed to create a `RouteEntry`
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbor = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con1,
         )
+        .expect("Can create a dummy peer");
+        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+            .expect("Valid subnet definition");
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+        let source = SourceKey::new(subnet, router_id);
+        let metric = Metric::new(0);
+        let seqno = SeqNo::new();
+        let selected = true;
+
+        let expiration = Duration::from_secs(15);
+        let re = super::RouteEntry::new(
+            source,
+            neighbor.clone(),
+            metric,
+            seqno,
+            selected,
+            expiration,
+        );
+        // We can't match exactly here since everything takes a non instant amount of time to do,
+        // but basically verify that the calculated interval is within expected parameters.
+        let advertised_interval =
+            super::advertised_update_interval(&re, super::DEFAULT_UPDATE_INTERVAL);
+        assert_eq!(advertised_interval, super::DEFAULT_UPDATE_INTERVAL);
+
+        // Expired route with finite metric
+        let expiration = Duration::from_secs(0);
+        let re = super::RouteEntry::new(
+            source,
+            neighbor.clone(),
+            metric,
+            seqno,
+            selected,
+            expiration,
+        );
+        let advertised_interval =
+            super::advertised_update_interval(&re, super::DEFAULT_UPDATE_INTERVAL);
+        assert_eq!(advertised_interval, super::DEFAULT_UPDATE_INTERVAL);
+
+        // Expired route with infinite metric
+        let re = super::RouteEntry::new(
+            source,
+            neighbor.clone(),
+            Metric::infinite(),
+            seqno,
+            selected,
+            expiration,
+        );
+        let advertised_interval =
+            super::advertised_update_interval(&re, super::DEFAULT_UPDATE_INTERVAL);
+        assert_eq!(advertised_interval, super::INTERVAL_NOT_REPEATING);
+
+        // Check that the interval is properly capped
+        let expiration = Duration::from_secs(600);
+        let re = super::RouteEntry::new(source, neighbor, metric, seqno, selected, expiration);
+        // We can't match exactly here since everything takes a non instant amount of time to do,
+        // but basically verify that the calculated interval is within expected parameters.
+        let advertised_interval =
+            super::advertised_update_interval(&re, super::DEFAULT_UPDATE_INTERVAL);
+        assert_eq!(advertised_interval, super::DEFAULT_UPDATE_INTERVAL);
     }
 
-    /// Send an oob icmp packet of the specified type in reply to the given DataPakcet.
-    fn oob_icmp(&self, icmp_type: Icmpv6Type, mut data_packet: DataPacket) {
-        let src_ip = if let IpAddr::V6(ip) = self.node_tun_subnet.address() {
-            ip
-        } else {
-            panic!("IPv4 not supported yet")
-        };
+    #[tokio::test]
+    async fn wildcard_retraction_retracts_all_routes_of_peer() {
+        let router = TestRouter::builder().build();
 
-        let icmp_header =
-            etherparse::PacketBuilder::ipv6(src_ip.octets(), data_packet.src_ip.octets(), 64)
-                .icmpv6(icmp_type);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbour = router.peer(con1);
+        router.add_peer_interface(neighbour.clone());
 
-        let mut pb = PacketBuffer::new();
-        // Don't exceed MIN_MTU for the constructed packet
-        // TODO: use proper consts
-        if data_packet.raw_data.len() > (1280 - 48) {
-            // Just drop raw_data, we don't need it anymore in this case and by doing this we have
-            // a unified code path later. Also we release the no longer used memory just a tad bit
-            // slower, though it's unlikely that this matters.
-            data_packet.raw_data = vec![];
+        // Every subnet is owned by the router which originates it.
+        let subnets = [1, 2].map(|i| {
+            Subnet::new(PublicKey::from([i; 32]).address().into(), 64)
+                .expect("Valid subnet definition")
+        });
+        for (i, subnet) in subnets.iter().enumerate() {
+            let update = Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(i as u16 + 1),
+                *subnet,
+                RouterId::new(PublicKey::from([i as u8 + 1; 32])),
+            );
+            router.handle_incoming_update(update, neighbour.clone());
         }
-        let serialized_icmp_size = icmp_header.size(data_packet.raw_data.len());
-        pb.set_size(serialized_icmp_size + 16);
-        pb.buffer_mut()[..16].copy_from_slice(&data_packet.dst_ip.octets());
-        let mut ps = &mut pb.buffer_mut()[16..16 + serialized_icmp_size];
-        if let Err(e) = icmp_header.write(&mut ps, &data_packet.raw_data) {
-            error!("Failed to write ICMP packet {e}");
-            return;
+        for subnet in &subnets {
+            assert!(router.select_best_route(subnet.address()).is_some());
         }
 
-        // TODO: import consts
-        let mut header = pb.header_mut();
-        header[0] = 1;
-        header[1] = 2;
+        router.handle_incoming_update(
+            Update::new_wildcard_retraction(SeqNo::new(), RouterId::new(PublicKey::from([1; 32]))),
+            neighbour.clone(),
+        );
 
-        // Get shared secret from node and dest address
-        let shared_secret = match self.get_shared_secret_from_dest(data_packet.src_ip.into()) {
-            Some(ss) => ss,
-            None => {
-                debug!(
-                    "No entry found for destination address {}, dropping packet",
-                    data_packet.src_ip
-                );
-                return;
-            }
-        };
+        for subnet in &subnets {
+            assert!(router.select_best_route(subnet.address()).is_none());
+        }
+        assert!(router
+            .load_selected_routes()
+            .iter()
+            .chain(router.load_fallback_routes().iter())
+            .all(|re| re.metric().is_infinite()));
+    }
 
-        let enc = shared_secret.encrypt(pb);
+    /// Classic count to infinity: after the route through one neighbour is retracted, another
+    /// neighbour still advertises the route it learned from us. Selecting that route would form a
+    /// loop, so it must be kept as an unfeasible fallback until the source bumps its seqno.
+    #[tokio::test]
+    async fn unfeasible_update_does_not_form_routing_loop() {
+        let router = TestRouter::builder().build();
 
-        self.route_packet(DataPacket {
-            dst_ip: data_packet.src_ip,
-            src_ip,
-            hop_limit: 64,
-            raw_data: enc,
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let (con_c, _remote_c) = tokio::io::duplex(1500);
+        let [peer_b, peer_c] = [con_b, con_c].map(|con| router.peer(con));
+        router.add_peer_interface(peer_b.clone());
+        router.add_peer_interface(peer_c.clone());
+
+        let subnet = Subnet::new(PublicKey::from([1; 32]).address().into(), 64)
+            .expect("Valid subnet definition");
+        let source = RouterId::new(PublicKey::from([1; 32]));
+        let update =
+            |seqno, metric| Update::new(Duration::from_secs(60), seqno, metric, subnet, source);
+
+        // Learn the route through B. Selecting it advertises it to C, which sets the feasibility
+        // distance.
+        router.handle_incoming_update(update(SeqNo::new(), Metric::new(10)), peer_b.clone());
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Route through B is selected");
+        assert_eq!(selected.neighbour(), &peer_b);
+        let fd = *router
+            .source_table
+            .read()
+            .unwrap()
+            .get(&SourceKey::new(subnet, source))
+            .expect("Feasibility distance is recorded when the route is advertised");
+
+        // B loses the route.
+        router.handle_incoming_update(update(SeqNo::new(), Metric::infinite()), peer_b.clone());
+        assert!(router.select_best_route(subnet.address()).is_none());
+
+        // C still advertises the route it learned from us, with a metric which is not better than
+        // what we advertised.
+        router.handle_incoming_update(
+            update(fd.seqno(), fd.metric() + Metric::new(5)),
+            peer_c.clone(),
+        );
+        assert!(router.select_best_route(subnet.address()).is_none());
+        assert!(router
+            .load_fallback_routes()
+            .iter()
+            .any(|re| re.neighbour() == &peer_c && !re.metric().is_infinite()));
+        let states = router
+            .load_routes()
+            .into_iter()
+            .map(|(re, state)| (re.neighbour().clone(), state))
+            .collect::<Vec<_>>();
+        assert!(states.contains(&(peer_b.clone(), super::RouteState::Retracted)));
+        assert!(states.contains(&(peer_c.clone(), super::RouteState::Unfeasible)));
+
+        // Once the source bumps its seqno, the route through C is feasible again.
+        router.handle_incoming_update(update(fd.seqno() + 1, Metric::new(20)), peer_c.clone());
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Route through C is selected");
+        assert_eq!(selected.neighbour(), &peer_c);
+    }
+
+    /// A metric offset for the subnet of a neighbour makes the router prefer an otherwise equal
+    /// route through another neighbour, and is included in the metric of listed routes.
+    #[tokio::test]
+    async fn metric_offset_changes_selected_route() {
+        let router = TestRouter::builder().build();
+
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let (con_c, _remote_c) = tokio::io::duplex(1500);
+        let [peer_b, peer_c] = [con_b, con_c].map(|con| {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            peer
+        });
+        router.add_peer_interface(peer_b.clone());
+        router.add_peer_interface(peer_c.clone());
+
+        let subnet = Subnet::new(PublicKey::from([1; 32]).address().into(), 64)
+            .expect("Valid subnet definition");
+        let update = Update::new(
+            Duration::from_secs(60),
+            SeqNo::new(),
+            Metric::new(10),
+            subnet,
+            RouterId::new(PublicKey::from([1; 32])),
+        );
+        router.handle_incoming_update(update.clone(), peer_b.clone());
+        router.handle_incoming_update(update, peer_c.clone());
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Route through B is selected");
+        assert_eq!(selected.neighbour(), &peer_b);
+
+        let peer_b_subnet = Subnet::new(peer_b.public_key().unwrap().address().into(), 64)
+            .expect("Valid subnet definition");
+        router.set_metric_offsets(MetricOffsets {
+            offsets: vec![MetricOffset {
+                subnet: peer_b_subnet,
+                offset: 100,
+            }],
         });
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Route through C is selected");
+        assert_eq!(selected.neighbour(), &peer_c);
+        assert_eq!(selected.metric(), Metric::new(10));
+
+        let fallback = router.load_fallback_routes();
+        assert_eq!(fallback.len(), 1);
+        assert_eq!(fallback[0].neighbour(), &peer_b);
+        assert_eq!(fallback[0].metric(), Metric::new(110));
     }
 
-    /// Get's the best route for a destination IP if one is present.
-    pub fn select_best_route(&self, dest_ip: IpAddr) -> Option<RouteEntry> {
-        let inner = self
-            .inner_r
-            .enter()
-            .expect("Write handle is saved on router so it is not dropped before the read handles");
-        inner
-            .routing_table
-            .lookup_selected(dest_ip)
-            .and_then(|entry| {
-                if entry.metric().is_infinite() {
-                    None
-                } else {
-                    Some(entry.clone())
-                }
-            })
+    /// Nodes can only originate routes for their own subnet. Updates for a subnet which is not
+    /// owned by their router id, and direct routes announced by a peer on behalf of another router,
+    /// are rejected, while routes relayed from other routers are accepted.
+    #[tokio::test]
+    async fn update_origin_must_own_subnet() {
+        let router = TestRouter::builder().build();
+
+        let (con, _remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        let peer_key = PublicKey::from(&SecretKey::new());
+        peer.set_public_key(peer_key);
+        router.add_peer_interface(peer.clone());
+
+        let peer_subnet =
+            Subnet::new(peer_key.address().into(), 64).expect("Valid subnet definition");
+        let victim_key = PublicKey::from(&SecretKey::new());
+        let victim_subnet =
+            Subnet::new(victim_key.address().into(), 64).expect("Valid subnet definition");
+        let update = |subnet, metric, router_id| {
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                metric,
+                subnet,
+                RouterId::new(router_id),
+            )
+        };
+
+        // The peer announces its own subnet.
+        router.handle_incoming_update(update(peer_subnet, Metric::new(0), peer_key), peer.clone());
+        assert!(router.select_best_route(peer_subnet.address()).is_some());
+
+        // The peer announces the subnet of the victim as its own.
+        router.handle_incoming_update(
+            update(victim_subnet, Metric::new(10), peer_key),
+            peer.clone(),
+        );
+        assert!(router.select_best_route(victim_subnet.address()).is_none());
+
+        // The peer pretends to be the victim.
+        router.handle_incoming_update(
+            update(victim_subnet, Metric::new(0), victim_key),
+            peer.clone(),
+        );
+        assert!(router.select_best_route(victim_subnet.address()).is_none());
+
+        // The peer relays the route of the victim.
+        router.handle_incoming_update(
+            update(victim_subnet, Metric::new(10), victim_key),
+            peer.clone(),
+        );
+        assert!(router.select_best_route(victim_subnet.address()).is_some());
     }
 
-    /// Task to propagate the static routes periodically
-    async fn propagate_static_routes(self) {
-        loop {
-            tokio::time::sleep(ROUTE_PROPAGATION_INTERVAL).await;
+    /// When the next hop of a selected route dies, the fallback route is selected right away,
+    /// without waiting for a later route selection.
+    #[tokio::test]
+    async fn dead_next_hop_fails_over_to_fallback() {
+        let router = TestRouter::builder().build();
+
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let (con_c, _remote_c) = tokio::io::duplex(1500);
+        let [peer_b, peer_c] = [con_b, con_c].map(|con| {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            peer
+        });
+        router.add_peer_interface(peer_b.clone());
+        router.add_peer_interface(peer_c.clone());
+
+        let subnet = Subnet::new(PublicKey::from([1; 32]).address().into(), 64)
+            .expect("Valid subnet definition");
+        let update = Update::new(
+            Duration::from_secs(60),
+            SeqNo::new(),
+            Metric::new(10),
+            subnet,
+            RouterId::new(PublicKey::from([1; 32])),
+        );
+        router.handle_incoming_update(update.clone(), peer_b.clone());
+        router.handle_incoming_update(update, peer_c.clone());
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("A route is selected");
+        let (dead, fallback) = if selected.neighbour() == &peer_b {
+            (peer_b, peer_c)
+        } else {
+            (peer_c, peer_b)
+        };
+
+        router.handle_dead_peer(dead);
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Fallback route is selected");
+        assert_eq!(selected.neighbour(), &fallback);
+        assert_eq!(selected.metric(), Metric::new(10));
+    }
+
+    /// The cipher negotiated with a node is forgotten once there is no route to the node anymore,
+    /// but not while another route remains.
+    #[tokio::test]
+    async fn cipher_is_forgotten_when_node_becomes_unreachable() {
+        let router = TestRouter::builder().build();
+
+        let remote = PublicKey::from(&SecretKey::new());
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let (con_c, _remote_c) = tokio::io::duplex(1500);
+        let [_peer_b, peer_c] = [(con_b, remote), (con_c, PublicKey::from(&SecretKey::new()))].map(
+            |(con, peer_key)| {
+                let peer = router.peer(con);
+                peer.set_public_key(peer_key);
+                router.add_peer_interface(peer.clone());
+                peer
+            },
+        );
+
+        // The remote is reachable through a pinned route to peer b, and a route learned from
+        // peer c.
+        router
+            .add_pinned_routes(vec![PinnedRoute {
+                subnet: remote.subnet(),
+                next_hop: remote.address().into(),
+            }])
+            .expect("Pinned route is valid");
+        router.handle_incoming_update(
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(10),
+                remote.subnet(),
+                RouterId::new(remote),
+            ),
+            peer_c.clone(),
+        );
+        router.set_cipher(remote.address(), Cipher::ChaCha20Poly1305);
+        assert_eq!(
+            router.cipher(remote.address()),
+            Some(Cipher::ChaCha20Poly1305)
+        );
 
-            trace!("Propagating static routes");
+        // The learned route is not selected, so it is removed, but the pinned route remains.
+        router.handle_dead_peer(peer_c);
+        assert_eq!(
+            router.cipher(remote.address()),
+            Some(Cipher::ChaCha20Poly1305)
+        );
 
-            for peer in self.peer_interfaces.read().unwrap().iter() {
-                self.propagate_static_route_to_peer(peer)
-            }
-        }
+        router
+            .remove_pinned_route(remote.subnet())
+            .expect("Pinned route exists");
+        assert_eq!(router.cipher(remote.address()), None);
+        assert!(router.ciphers().is_empty());
     }
 
-    /// Propagate all static routes to all known peers.
-    fn propagate_static_routes_to_peers(&self) {
-        for peer in self.peer_interfaces.read().unwrap().iter() {
-            self.propagate_static_route_to_peer(peer);
-        }
-    }
+    /// A pinned route is selected over learned routes, isn't changed by updates, and survives a
+    /// flush of the learned routes.
+    #[tokio::test]
+    async fn pinned_route_is_always_selected() {
+        let router = TestRouter::builder().build();
+
+        let (con_gw, _remote_gw) = tokio::io::duplex(1500);
+        let (con_c, _remote_c) = tokio::io::duplex(1500);
+        let [gateway, peer_c] = [con_gw, con_c].map(|con| {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            router.add_peer_interface(peer.clone());
+            peer
+        });
 
-    /// Task to propagate selected routes periodically
-    async fn propagate_selected_routes(self) {
-        loop {
-            tokio::time::sleep(ROUTE_PROPAGATION_INTERVAL).await;
+        // The subnet of the route doesn't need to contain the address of the gateway.
+        let gateway_address = gateway.public_key().unwrap().address();
+        let remote = PublicKey::from(&SecretKey::new());
+        let subnet = remote.subnet();
+        let update = |metric| {
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                metric,
+                subnet,
+                RouterId::new(remote),
+            )
+        };
 
-            trace!("Propagating selected routes");
+        // The next hop must be an IPv6 address.
+        assert!(router
+            .add_pinned_routes(vec![PinnedRoute {
+                subnet,
+                next_hop: Ipv4Addr::LOCALHOST.into(),
+            }])
+            .is_err());
 
-            let start = Instant::now();
-            self.propagate_selected_routes_to_peers();
-            self.metrics
-                .router_time_spent_periodic_propagating_selected_routes(start.elapsed());
-        }
+        router.handle_incoming_update(update(Metric::new(1)), peer_c.clone());
+        router
+            .add_pinned_routes(vec![PinnedRoute {
+                subnet,
+                next_hop: gateway_address.into(),
+            }])
+            .expect("Pinned route is valid");
+        assert_eq!(router.pinned_routes().len(), 1);
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Pinned route is selected");
+        assert_eq!(selected.neighbour(), &gateway);
+        assert_eq!(selected.source().router_id(), router.router_id());
+        assert_eq!(selected.seqno(), router.router_seqno());
+        // Other nodes don't accept routes from us for a subnet of another node.
+        assert!(!router.is_advertised(&selected));
+        assert!(router
+            .load_routes()
+            .iter()
+            .any(|(re, state)| re.neighbour() == &gateway && *state == super::RouteState::Pinned));
+
+        // Updates through the gateway don't replace the pinned route, even if they retract it.
+        router.handle_incoming_update(update(Metric::infinite()), gateway.clone());
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Pinned route is selected");
+        assert_eq!(selected.neighbour(), &gateway);
+
+        router
+            .remove_pinned_route(subnet)
+            .expect("Pinned route exists");
+        assert!(router.remove_pinned_route(subnet).is_err());
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Learned route is selected");
+        assert_eq!(selected.neighbour(), &peer_c);
+
+        router
+            .add_pinned_routes(vec![PinnedRoute {
+                subnet,
+                next_hop: gateway_address.into(),
+            }])
+            .expect("Pinned route is valid");
+        assert_eq!(router.flush_routes(), 1);
+        let selected = router
+            .select_best_route(subnet.address())
+            .expect("Pinned route is kept");
+        assert_eq!(selected.neighbour(), &gateway);
     }
 
-    /// Task which periodically sends a Hello TLV to all known peers
-    async fn start_periodic_hello_sender(self) {
-        let hello_interval = Duration::from_secs(HELLO_INTERVAL);
-        loop {
-            tokio::time::sleep(hello_interval).await;
-
-            for peer in self.peer_interfaces.read().unwrap().iter() {
-                let hello = ControlPacket::new_hello(peer, hello_interval);
-                peer.set_time_last_received_hello(tokio::time::Instant::now());
+    /// A pinned route for a subnet containing our own address is advertised as our own route, with
+    /// the cost of the link to the gateway as metric.
+    #[tokio::test]
+    async fn pinned_route_is_advertised_as_own_route() {
+        let router = TestRouter::builder().build();
+
+        let (con_gw, _remote_gw) = tokio::io::duplex(1500);
+        let (con_c, remote_c) = tokio::io::duplex(1500);
+        let [gateway, _peer_c] = [con_gw, con_c].map(|con| {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            router.add_peer_interface(peer.clone());
+            peer
+        });
 
-                if peer.send_control_packet(hello).is_err() {
-                    trace!(
-                        "Failed to send Hello TLV to dead peer {}",
-                        peer.connection_identifier()
-                    );
+        let default_route =
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0).expect("Valid subnet definition");
+        router
+            .add_pinned_routes(vec![PinnedRoute {
+                subnet: default_route,
+                next_hop: gateway.public_key().unwrap().address().into(),
+            }])
+            .expect("Pinned route is valid");
+
+        let mut remote_c = Framed::new(remote_c, packet::Codec::new());
+        let update = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                match remote_c.next().await {
+                    Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update))))
+                        if update.subnet() == default_route =>
+                    {
+                        return update
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("Connection closed without update: {other:?}"),
                 }
             }
-        }
+        })
+        .await
+        .expect("Pinned route is advertised");
+        assert_eq!(update.router_id(), router.router_id());
+        assert_eq!(update.seqno(), router.router_seqno());
+        assert_eq!(update.metric(), Metric::from(gateway.link_cost()));
     }
 
-    /// Propagates the static routes to all known peers.
-    fn propagate_selected_routes_to_peers(&self) {
-        for peer in self.peer_interfaces.read().unwrap().iter() {
-            self.propagate_selected_routes_to_peer(peer);
-        }
-    }
+    /// Once the routing table is full, new routes only replace less preferred routes, so the best
+    /// routes are kept when it is flooded.
+    #[tokio::test]
+    async fn full_routing_table_keeps_best_routes() {
+        let router = TestRouter::builder().max_routes(4).build();
+
+        let (con_a, _remote_a) = tokio::io::duplex(1500);
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let [peer_a, peer_b] = [con_a, con_b].map(|con| {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            router.add_peer_interface(peer.clone());
+            peer
+        });
 
-    /// Propagate the static routes to a single peer
-    fn propagate_static_route_to_peer(&self, peer: &Peer) {
-        for sr in self.static_routes.iter() {
-            let update = babel::Update::new(
-                UPDATE_INTERVAL,
-                self.router_seqno.read().unwrap().0, // updates receive the seqno of the router
-                Metric::from(0),                     // Static route has no further hop costs
-                *sr,
-                self.router_id,
-            );
-            self.send_update(peer, update);
+        let origins = [50, 80, 10, 70, 30, 60, 20, 40, 90].map(|metric| {
+            let origin = PublicKey::from(&SecretKey::new());
+            let subnet = Subnet::new(origin.address().into(), 64).expect("Valid subnet definition");
+            (subnet, origin, Metric::new(metric))
+        });
+        let update = |(subnet, origin, _), metric| {
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                metric,
+                subnet,
+                RouterId::new(origin),
+            )
+        };
+        let selected_metrics = || {
+            let mut metrics = router
+                .load_selected_routes()
+                .iter()
+                .map(|re| re.metric())
+                .collect::<Vec<_>>();
+            metrics.sort();
+            metrics
+        };
+
+        // The last route is worse than all routes in the full table, so it is refused.
+        for origin in origins {
+            router.handle_incoming_update(update(origin, origin.2), peer_a.clone());
         }
+        assert_eq!(router.load_routes().len(), 4);
+        assert_eq!(
+            selected_metrics(),
+            [10, 20, 30, 40].map(Metric::new).to_vec()
+        );
+
+        // A fallback route through another peer is not stored, but a better route through it
+        // replaces the worst selected route of the same subnet.
+        router.handle_incoming_update(update(origins[2], Metric::new(100)), peer_b.clone());
+        assert_eq!(router.load_routes().len(), 4);
+        router.handle_incoming_update(update(origins[7], Metric::new(5)), peer_b.clone());
+        assert_eq!(router.load_routes().len(), 4);
+        assert_eq!(
+            selected_metrics(),
+            [5, 10, 20, 30].map(Metric::new).to_vec()
+        );
+        let selected = router
+            .select_best_route(origins[7].0.address())
+            .expect("Better route is selected");
+        assert_eq!(selected.neighbour(), &peer_b);
     }
 
-    /// Propagate a selected route. Unless peers are specified, all knwon peers in the router are
-    /// used.
-    fn propagate_selected_route(&self, subnet: Subnet, peers: Option<Vec<Peer>>) {
-        let (update, maybe_neigh) = if let Some(sre) = self
-            .inner_r
-            .enter()
-            .expect("Write handle is saved on the router so read handle is always available; qed")
-            .routing_table
-            .lookup_selected(subnet.address())
-        {
-            let update = babel::Update::new(
-                advertised_update_interval(sre),
-                sre.seqno(),
-                sre.metric() + Metric::from(sre.neighbour().link_cost()),
-                sre.source().subnet(),
-                sre.source().router_id(),
-            );
-            (update, Some(sre.neighbour().clone()))
-        } else {
-            // This can happen if the only feasible route gets an infinite metric, as those are
-            // never selected.
-            info!("Retracting route for {subnet}");
-            let update = babel::Update::new(
-                UPDATE_INTERVAL,
-                self.router_seqno.read().unwrap().0,
-                Metric::infinite(),
-                subnet,
-                self.router_id,
-            );
-            (update, None)
-        };
+    /// Packets which share an IPv6 flow label are identified as the same flow, even if their ports
+    /// differ, so they take the same path if traffic is spread over multiple paths.
+    #[tokio::test]
+    async fn packets_with_same_flow_label_take_same_path() {
+        let router = TestRouter::builder().build();
 
-        let send_update = |peer: &Peer| {
-            // Don't send updates for a route to the next hop of the route, as that peer will never
-            // select the route through us (that would caus a routing loop). The protocol can
-            // handle this just fine, leaving this out is essentially an easy optimization.
-            if let Some(ref neigh) = maybe_neigh {
-                if peer == neigh {
-                    return;
-                }
-            }
-            debug!(
-                "Propagating route update for {} to {}",
-                subnet,
-                peer.connection_identifier()
-            );
-            self.send_update(peer, update.clone());
+        let (con_a, _remote_a) = tokio::io::duplex(1500);
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let origin = PublicKey::from(&SecretKey::new());
+        let subnet = Subnet::new(origin.address().into(), 64).expect("Valid subnet definition");
+        let update = Update::new(
+            Duration::from_secs(60),
+            SeqNo::new(),
+            Metric::new(10),
+            subnet,
+            RouterId::new(origin),
+        );
+        for con in [con_a, con_b] {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            router.add_peer_interface(peer.clone());
+            router.handle_incoming_update(update.clone(), peer);
+        }
+        assert_eq!(router.equal_cost_path_count(subnet, None), 2);
+
+        let src = Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1);
+        let dst = origin.address();
+        // IPv6 header followed by a UDP header, without payload.
+        let packet = |flow_label: u32, src_port: u16| {
+            let mut packet = vec![0; 48];
+            packet[0..4].copy_from_slice(&(0x6000_0000 | flow_label).to_be_bytes());
+            packet[6] = 17;
+            packet[7] = 64;
+            packet[8..24].copy_from_slice(&src.octets());
+            packet[24..40].copy_from_slice(&dst.octets());
+            packet[40..42].copy_from_slice(&src_port.to_be_bytes());
+            packet[42..44].copy_from_slice(&9651u16.to_be_bytes());
+            packet
         };
-
-        if let Some(peers) = peers {
-            for peer in peers {
-                send_update(&peer);
-            }
-        } else {
-            for peer in self.peer_interfaces.read().unwrap().iter() {
-                send_update(peer);
-            }
+        let path = |packet: &[u8]| {
+            router
+                .select_forwarding_route(dst.into(), src.into(), crate::data::flow_id(packet))
+                .expect("Route to destination exists")
+                .neighbour()
+                .clone()
         };
+
+        let first = packet(0xABCDE, 1000);
+        let second = packet(0xABCDE, 2000);
+        assert_ne!(crate::data::flow_id(&first), 0);
+        assert_eq!(crate::data::flow_id(&first), crate::data::flow_id(&second));
+        assert_eq!(path(&first), path(&second));
+
+        // Without a flow label, the ports identify the flow, and different flows are spread over
+        // both paths.
+        let paths = (0..64)
+            .map(|src_port| path(&packet(0, src_port)))
+            .collect::<Vec<_>>();
+        assert!(paths.iter().any(|peer| peer != &paths[0]));
     }
 
-    /// Propagate all selected routes to all peers known in the router.
-    fn propagate_selected_routes_to_peer(&self, peer: &Peer) {
-        for (srk, _, sre) in self
-            .inner_r
-            .enter()
-            .expect("Write handle is saved on router so read handle is always available; qed")
-            .routing_table
-            .iter()
-            .filter(|(_, _, sre)| sre.selected())
-        {
-            let neigh_link_cost = Metric::from(sre.neighbour().link_cost());
-            // Don't send updates for a route to the next hop of the route, as that peer will never
-            // select the route through us (that would caus a routing loop). The protocol can
-            // handle this just fine, leaving this out is essentially an easy optimization.
-            if peer == sre.neighbour() {
-                continue;
+    /// A draining router advertises transit routes with a near infinite metric, and counts the
+    /// transit packets it still forwards.
+    #[tokio::test]
+    async fn draining_router_advertises_transit_routes_as_unattractive() {
+        let router = TestRouter::builder().build();
+
+        let (con_a, _remote_a) = tokio::io::duplex(1500);
+        let (con_b, remote_b) = tokio::io::duplex(1500);
+        let [peer_a, peer_b] = [con_a, con_b].map(|con| {
+            let peer = router.peer(con);
+            peer.set_public_key(PublicKey::from(&SecretKey::new()));
+            peer
+        });
+        router.add_peer_interface(peer_a.clone());
+        router.add_peer_interface(peer_b);
+
+        let origin = PublicKey::from(&SecretKey::new());
+        let subnet = Subnet::new(origin.address().into(), 64).expect("Valid subnet definition");
+        router.handle_incoming_update(
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(10),
+                subnet,
+                RouterId::new(origin),
+            ),
+            peer_a,
+        );
+        assert!(router.drain_status().is_none());
+
+        router.start_drain();
+        let mut remote_b = Framed::new(remote_b, packet::Codec::new());
+        tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                match remote_b.next().await {
+                    Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update))))
+                        if update.subnet() == subnet && update.metric() == super::DRAIN_METRIC =>
+                    {
+                        return
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("Unexpected packet from router: {other:?}"),
+                }
             }
-            let update = babel::Update::new(
-                advertised_update_interval(sre),
-                sre.seqno(),
-                // the cost of the route is the cost of the route + the cost of the link to the next-hop
-                sre.metric() + neigh_link_cost,
-                srk.subnet(),
-                sre.source().router_id(),
-            );
-            debug!(
-                "Propagating route update for {} to {} | D({}, {})",
-                srk.subnet(),
-                peer.connection_identifier(),
-                sre.seqno(),
-                sre.metric() + neigh_link_cost,
-            );
-            self.send_update(peer, update);
-        }
-    }
+        })
+        .await
+        .expect("Transit route is advertised with the drain metric");
 
-    /// Send an update to a peer.
-    ///
-    /// This updates updates the source table before sending the udpate as described in the RFC.
-    fn send_update(&self, peer: &Peer, update: babel::Update) {
-        // Sanity check, verify what we are doing is actually usefull
-        if !peer.alive() {
-            trace!("Cowardly refusing to sent update to peer which we know is dead");
-            self.metrics.router_update_dead_peer();
-            return;
-        }
+        router.route_packet(DataPacket {
+            dst_ip: origin.address(),
+            src_ip: Ipv6Addr::new(0x400, 2, 0, 0, 0, 0, 0, 1),
+            hop_limit: 64,
+            flow: 0,
+            raw_data: BytesMut::from(&[0; 64][..]),
+        });
+        let status = router.drain_status().expect("Router is draining");
+        assert_eq!(status.transit_packets, 1);
+        assert!(!status.drained);
+    }
 
-        // Before sending an update, the source table might need to be updated
-        let metric = update.metric();
-        let seqno = update.seqno();
-        let router_id = update.router_id();
-        let subnet = update.subnet();
+    /// A packet which runs out of hops while being forwarded is dropped, and its source is sent an
+    /// ICMPv6 Time Exceeded packet. Packets for the local node don't use up a hop, so they are
+    /// delivered even if the hop limit would not allow forwarding them.
+    #[tokio::test]
+    async fn expired_packet_is_answered_with_time_exceeded() {
+        let mut router = TestRouter::builder().build();
 
-        let source_key = SourceKey::new(subnet, router_id);
-        let mut source_table = self.source_table.write().unwrap();
+        let (con_b, _remote_b) = tokio::io::duplex(1500);
+        let (con_c, remote_c) = tokio::io::duplex(1500);
+        let [peer_b, peer_c] = [con_b, con_c].map(|con| router.peer(con));
+        router.add_peer_interface(peer_b.clone());
+        router.add_peer_interface(peer_c.clone());
 
-        if let Some(source_entry) = source_table.get(&source_key) {
-            // if seqno of the update is greater than the seqno in the source table, update the source table
-            if seqno.gt(&source_entry.seqno()) {
-                source_table.insert(
-                    source_key,
-                    FeasibilityDistance::new(metric, seqno),
-                    self.expired_source_key_sink.clone(),
-                );
-            }
-            // if seqno of the update is equal to the seqno in the source table, update the source table if the metric (of the update) is lower
-            else if seqno == source_entry.seqno() && source_entry.metric() > metric {
-                source_table.insert(
-                    source_key,
-                    // Technically the seqno in the feasibility distance comes from the source
-                    // entry, but that gives a borrow conflict so we use seqno from the update,
-                    // which we just verified is the same.
-                    FeasibilityDistance::new(metric, seqno),
-                    self.expired_source_key_sink.clone(),
-                )
-            }
-            // We also reset the garbage collection timer (unless the update is a retraction)
-            else if !metric.is_infinite() {
-                source_table.reset_timer(source_key, self.expired_source_key_sink.clone());
-            }
-        }
-        // no entry for this source key, so insert it
-        else {
-            source_table.insert(
-                source_key,
-                FeasibilityDistance::new(metric, seqno),
-                self.expired_source_key_sink.clone(),
-            )
-        };
+        // The destination is reachable through B, the source of the packet through C.
+        let dst_subnet = Subnet::new(Ipv6Addr::new(0x400, 1, 0, 0, 0, 0, 0, 0).into(), 64)
+            .expect("Valid subnet definition");
+        router.handle_incoming_update(
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(10),
+                dst_subnet,
+                RouterId::new(PublicKey::from([1; 32])),
+            ),
+            peer_b.clone(),
+        );
+        let src_sk = SecretKey::new();
+        let src_pk = PublicKey::from(&src_sk);
+        let src_subnet = Subnet::new(src_pk.address().into(), 64).expect("Valid subnet definition");
+        router.handle_incoming_update(
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(10),
+                src_subnet,
+                RouterId::new(src_pk),
+            ),
+            peer_c.clone(),
+        );
 
-        // send the update to the peer
-        trace!("Sending update to peer");
-        if peer
-            .send_control_packet(ControlPacket::Update(update))
-            .is_err()
-        {
-            // An error indicates the peer is dead
-            trace!(
-                "Failed to send update to dead peer {}",
-                peer.connection_identifier()
-            );
-        }
-    }
-}
+        let dst_ip = Ipv6Addr::new(0x400, 1, 0, 0, 0, 0, 0, 1);
+        router.route_packet(DataPacket {
+            dst_ip,
+            src_ip: src_pk.address(),
+            hop_limit: 1,
+            flow: 0,
+            raw_data: BytesMut::from(&[0; 64][..]),
+        });
 
-/// Manual clone implementation to avoid placing a where bound on `Router`, which would in turn
-/// require a where bound on all structs which end up containing Router.
-impl<M> Clone for Router<M>
-where
-    M: Clone,
-{
-    fn clone(&self) -> Self {
-        Self {
-            inner_w: self.inner_w.clone(),
-            inner_r: self.inner_r.clone(),
-            peer_interfaces: self.peer_interfaces.clone(),
-            source_table: self.source_table.clone(),
-            router_seqno: self.router_seqno.clone(),
-            static_routes: self.static_routes.clone(),
-            router_id: self.router_id,
-            node_keypair: self.node_keypair.clone(),
-            router_data_tx: self.router_data_tx.clone(),
-            router_control_tx: self.router_control_tx.clone(),
-            node_tun: self.node_tun.clone(),
-            node_tun_subnet: self.node_tun_subnet,
-            update_filters: self.update_filters.clone(),
-            dead_peer_sink: self.dead_peer_sink.clone(),
-            expired_source_key_sink: self.expired_source_key_sink.clone(),
-            seqno_cache: self.seqno_cache.clone(),
-            metrics: self.metrics.clone(),
-        }
+        let mut remote_c = Framed::new(remote_c, packet::Codec::new());
+        let icmp = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                match remote_c.next().await {
+                    Some(Ok(Packet::DataPacket(dp))) => return dp,
+                    Some(Ok(Packet::ControlPacket(_))) => continue,
+                    other => panic!("Unexpected packet from router: {other:?}"),
+                }
+            }
+        })
+        .await
+        .expect("ICMP packet is sent to the source");
+        assert_eq!(icmp.dst_ip, src_pk.address());
+
+        let content = src_sk
+            .shared_secret(&router.node_public_key())
+            .decrypt(icmp.raw_data)
+            .expect("ICMP packet is encrypted for the source");
+        // Out of band ICMP, prefixed with the original destination.
+        assert_eq!(content.header()[1], 2);
+        assert_eq!(content[..16], dst_ip.octets());
+        // Next header is ICMPv6, with type Time Exceeded and code Hop Limit Exceeded.
+        assert_eq!(content[16 + 6], 58);
+        assert_eq!(content[16 + 40..16 + 42], [3, 0]);
+
+        // The local node is not a hop for packets destined to it.
+        router.route_packet(DataPacket {
+            dst_ip: Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1),
+            src_ip: src_pk.address(),
+            hop_limit: 1,
+            flow: 0,
+            raw_data: BytesMut::from(&[0; 64][..]),
+        });
+        let (delivered, ingress) = router
+            .node_tun_rx
+            .try_recv()
+            .expect("Packet for the local node is delivered");
+        assert_eq!(delivered.hop_limit, 1);
+        // The packet was sent by the local node, not received from a peer.
+        assert!(ingress.is_none());
     }
-}
 
-pub struct RouterInner {
-    routing_table: RoutingTable<(PublicKey, SharedSecret)>,
-    expired_route_entry_sink: mpsc::Sender<(RouteKey, RouteExpirationType)>,
-}
+    /// A seqno request for a local subnet with a newer seqno bumps the router seqno, but not more
+    /// often than [`SEQNO_BUMP_TIMEOUT`](super::SEQNO_BUMP_TIMEOUT) allows.
+    #[tokio::test]
+    async fn seqno_request_for_local_subnet_bumps_router_seqno() {
+        let subnet = Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0).into(), 64)
+            .expect("Valid subnet definition");
+        let router = TestRouter::builder()
+            .node_subnet(subnet)
+            .static_routes(vec![(subnet, None)])
+            .build();
 
-impl RouterInner {
-    pub fn new(
-        expired_route_entry_sink: mpsc::Sender<(RouteKey, RouteExpirationType)>,
-    ) -> Result<Self, Box<dyn Error>> {
-        let router_inner = RouterInner {
-            routing_table: RoutingTable::new(),
-            expired_route_entry_sink,
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbour = router.peer(con1);
+        router.add_peer_interface(neighbour.clone());
+
+        // Pretend the seqno was last bumped long enough ago.
+        router.router_seqno.write().unwrap().1 = Instant::now() - super::SEQNO_BUMP_TIMEOUT;
+        let seqno = router.router_seqno();
+
+        router.handle_incoming_seqno_request(
+            SeqNoRequest::new(seqno + 1, router.router_id(), subnet),
+            neighbour.clone(),
+        );
+        assert_eq!(router.router_seqno(), seqno + 1);
+
+        // Requests for an older seqno, or which arrive too fast, don't bump the seqno.
+        router.handle_incoming_seqno_request(
+            SeqNoRequest::new(seqno, router.router_id(), subnet),
+            neighbour.clone(),
+        );
+        router.handle_incoming_seqno_request(
+            SeqNoRequest::new(seqno + 2, router.router_id(), subnet),
+            neighbour,
+        );
+        assert_eq!(router.router_seqno(), seqno + 1);
+    }
+
+    /// Updates are never dropped. Once the queue of the update processor is full, dispatching
+    /// waits until there is space again.
+    #[tokio::test]
+    async fn full_update_queue_holds_up_dispatching() {
+        let router = TestRouter::builder().build();
+
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbour = router.peer(con1);
+
+        // Nothing reads the queues yet, like a processor which can't keep up.
+        let (update_tx, mut update_rx) = mpsc::channel(1);
+        let (route_request_tx, _route_request_rx) = mpsc::channel(1);
+        let (seqno_request_tx, _seqno_request_rx) = mpsc::channel(1);
+        let queues = super::ControlQueues {
+            update: update_tx,
+            route_request: route_request_tx,
+            seqno_request: seqno_request_tx,
         };
 
-        Ok(router_inner)
+        let subnet = Subnet::new(PublicKey::from([1; 32]).address().into(), 64)
+            .expect("Valid subnet definition");
+        let update = |metric| {
+            packet::ControlPacket::from(Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(metric),
+                subnet,
+                RouterId::new(PublicKey::from([1; 32])),
+            ))
+        };
+
+        assert!(
+            router
+                .dispatch_control_packet(update(1), neighbour.clone(), &queues)
+                .await
+        );
+        let dispatch = router.dispatch_control_packet(update(2), neighbour.clone(), &queues);
+        tokio::pin!(dispatch);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut dispatch)
+                .await
+                .is_err(),
+            "Dispatching waits while the queue is full"
+        );
+
+        let (first, _) = update_rx.recv().await.expect("Update is queued");
+        assert_eq!(first.metric(), Metric::new(1));
+        assert!(dispatch.await);
+        let (second, _) = update_rx.recv().await.expect("Update is queued");
+        assert_eq!(second.metric(), Metric::new(2));
     }
-}
 
-enum RouterOpLogEntry {
-    /// Insert a new entry in the routing table.
-    InsertRoute(RouteKey, RouteEntry, PublicKey, SharedSecret),
-    /// Removes a route with the given route key.
-    RemoveRoute(RouteKey),
-    /// Unselect the route defined by the route key.
-    UnselectRoute(RouteKey),
-    /// Select the route defined by the route key.
-    SelectRoute(RouteKey),
-    /// Update the route entry associated to the given route key in the fallback route table, if
-    /// one exists
-    UpdateRouteEntry(RouteKey, SeqNo, Metric, RouterId, Duration),
-}
+    /// Only the configured amount of routing events is kept, and none if the size is 0.
+    #[tokio::test]
+    async fn event_log_keeps_configured_amount_of_events() {
+        for (event_log_size, logged) in [(0, 0), (1, 1), (16, 2)] {
+            let router = TestRouter::builder().event_log_size(event_log_size).build();
 
-impl left_right::Absorb<RouterOpLogEntry> for RouterInner {
-    fn absorb_first(&mut self, operation: &mut RouterOpLogEntry, _: &Self) {
-        match operation {
-            RouterOpLogEntry::InsertRoute(rk, re, pk, ss) => {
-                self.routing_table.insert(
-                    rk.clone(),
-                    (*pk, ss.clone()),
-                    re.clone(),
-                    self.expired_route_entry_sink.clone(),
-                );
-            }
-            RouterOpLogEntry::RemoveRoute(rk) => {
-                self.routing_table.remove(rk);
-            }
-            RouterOpLogEntry::UnselectRoute(rk) => {
-                self.routing_table.unselect_route(rk);
-            }
-            RouterOpLogEntry::SelectRoute(rk) => {
-                self.routing_table.select_route(rk);
-            }
-            RouterOpLogEntry::UpdateRouteEntry(rk, seqno, metric, pk, expiration) => {
-                if let Some(re) = self.routing_table.get_mut(rk) {
-                    re.update_seqno(*seqno);
-                    re.update_metric(*metric);
-                    re.update_router_id(*pk);
-                    re.update_expiration(*expiration);
-                    self.routing_table
-                        .reset_route_timer(rk, self.expired_route_entry_sink.clone());
-                }
+            for _ in 0..2 {
+                let (con, _remote) = tokio::io::duplex(1500);
+                router.add_peer_interface(router.peer(con));
             }
+
+            let events = router.routing_events(None);
+            assert_eq!(events.len(), logged);
+            assert!(events
+                .iter()
+                .all(|e| matches!(e.event, RoutingEvent::PeerUp { .. })));
         }
     }
 
-    fn sync_with(&mut self, first: &Self) {
-        *self = first.clone()
-    }
-}
+    /// Closing the connection to a peer on purpose sends a wildcard retraction of the routes
+    /// advertised to it, before the connection is closed.
+    #[tokio::test]
+    async fn wildcard_retraction_is_sent_before_connection_closes() {
+        let router = TestRouter::builder().build();
 
-impl Clone for RouterInner {
-    fn clone(&self) -> Self {
-        let RouterInner {
-            routing_table,
-            expired_route_entry_sink,
-        } = self;
-        let mut new_routing_table = RoutingTable::new();
-        for (k, e, v) in routing_table.iter() {
-            new_routing_table.insert(k, e.clone(), v.clone(), expired_route_entry_sink.clone());
-        }
-        RouterInner {
-            routing_table: new_routing_table,
-            expired_route_entry_sink: expired_route_entry_sink.clone(),
+        let (con, remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        router.add_peer_interface(peer.clone());
+
+        router.retract_routes_to_peer(&peer);
+        peer.died(DisconnectReason::Closed);
+        tokio::time::timeout(Duration::from_secs(2), peer.closed())
+            .await
+            .expect("Connection is closed");
+
+        let mut remote = Framed::new(remote, packet::Codec::new());
+        loop {
+            match remote.next().await {
+                Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update))))
+                    if update.is_wildcard_retraction() =>
+                {
+                    break
+                }
+                Some(Ok(_)) => continue,
+                other => panic!("Connection closed without wildcard retraction: {other:?}"),
+            }
         }
     }
-}
 
-/// Calculate the hold time for a [`RouteEntry`] from an [`Update`](babel::Update) .
-fn route_hold_time(update: &babel::Update) -> Duration {
-    // According to https://datatracker.ietf.org/doc/html/rfc8966#section-appendix.b a good value
-    // would be 3.5 times the update inteval.
-    // In case of a retracted route: in general this should not be added to the routing table, so
-    // the only reason this is called is because a route was retracted through an update. Even if
-    // the peer won't send this again, hold the route for some time so it can get flushed properly.
-    if update.metric().is_infinite() {
-        RETRACTED_ROUTE_HOLD_TIME
-    } else {
-        // Route expiry time -> 3.5 times advertised Update interval.
-        Duration::from_millis((update.interval().as_millis() * 7 / 2) as u64)
-    }
-}
+    /// Routes to subnets which are denied by a new access list are removed, and retracted if they
+    /// were selected. Routes to other subnets are kept.
+    #[tokio::test]
+    async fn denied_routes_are_removed_and_retracted() {
+        let router = TestRouter::builder().build();
+
+        let (con, remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        router.add_peer_interface(peer.clone());
+
+        let denied = PublicKey::from(&SecretKey::new());
+        let allowed = PublicKey::from(&SecretKey::new());
+        for remote_key in [denied, allowed] {
+            router.handle_incoming_update(
+                Update::new(
+                    Duration::from_secs(60),
+                    SeqNo::new(),
+                    Metric::new(10),
+                    remote_key.subnet(),
+                    RouterId::new(remote_key),
+                ),
+                peer.clone(),
+            );
+            assert!(router.selected_route(remote_key.subnet(), None).is_some());
+        }
 
-/// Calculates the interval to use when announcing updates on (selected) routes.
-fn advertised_update_interval(sre: &RouteEntry) -> Duration {
-    // We actually just need to set the value of the update interval, since that is the upper bound
-    // on when we will advertise the route again.
-    // One caveat is an expired route. If an entry is expired, it means that it will change state
-    // so: Infinite metric -> route entry will be removed. Finite metric -> route entry will be
-    // retracted but will be announced again. In practice this shouldn't really happen anyway.
-    if sre.metric().is_infinite() && sre.expires().as_nanos() == 0 {
-        INTERVAL_NOT_REPEATING
-    } else {
-        UPDATE_INTERVAL
+        let removed = router.remove_denied_routes(&AccessList {
+            allow: vec![],
+            deny: vec![denied.subnet()],
+        });
+        assert_eq!(removed, 1);
+        assert!(router.selected_route(denied.subnet(), None).is_none());
+        assert!(router.load_fallback_routes().is_empty());
+        assert!(router.selected_route(allowed.subnet(), None).is_some());
+
+        let mut remote = Framed::new(remote, packet::Codec::new());
+        tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                match remote.next().await {
+                    Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update))))
+                        if update.subnet() == denied.subnet() && update.metric().is_infinite() =>
+                    {
+                        break
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("Connection closed without retraction: {other:?}"),
+                }
+            }
+        })
+        .await
+        .expect("Denied route is retracted");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        net::{IpAddr, Ipv6Addr},
-        sync::{atomic::AtomicU64, Arc},
-        time::Duration,
-    };
+    /// Flushing the routing table removes the learned routes, retracts the selected ones and
+    /// requests a route table dump from the peers.
+    #[tokio::test]
+    async fn flushed_routes_are_retracted() {
+        let router = TestRouter::builder().build();
+
+        let (con, remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        router.add_peer_interface(peer.clone());
+
+        let remote_key = PublicKey::from(&SecretKey::new());
+        router.handle_incoming_update(
+            Update::new(
+                Duration::from_secs(60),
+                SeqNo::new(),
+                Metric::new(10),
+                remote_key.subnet(),
+                RouterId::new(remote_key),
+            ),
+            peer,
+        );
+        assert!(router.selected_route(remote_key.subnet(), None).is_some());
+
+        assert_eq!(router.flush_routes(), 1);
+        assert!(router.selected_route(remote_key.subnet(), None).is_none());
+        assert!(router.load_fallback_routes().is_empty());
+
+        // A route table dump was already requested when the peer was added.
+        let mut remote = Framed::new(remote, packet::Codec::new());
+        let (mut retracted, mut dump_requests) = (false, 0);
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !(retracted && dump_requests == 2) {
+                match remote.next().await {
+                    Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update))))
+                        if update.subnet() == remote_key.subnet()
+                            && update.metric().is_infinite() =>
+                    {
+                        retracted = true
+                    }
+                    Some(Ok(Packet::ControlPacket(babel::Tlv::RouteRequest(request))))
+                        if request.prefix().is_none() =>
+                    {
+                        dump_requests += 1
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("Connection closed without retraction: {other:?}"),
+                }
+            }
+        })
+        .await
+        .expect("Flushed route is retracted and routes are requested again");
+    }
 
-    use tokio::sync::mpsc;
+    /// A route request for a source-specific route is answered with the static route for that
+    /// source prefix, and not with the route for another source prefix.
+    #[tokio::test]
+    async fn source_specific_route_request_is_answered() {
+        let default_route =
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0).expect("Valid subnet definition");
+        let source_prefix = Subnet::new(Ipv6Addr::new(0x400, 0x1, 0, 0, 0, 0, 0, 0).into(), 32)
+            .expect("Valid subnet definition");
+        let router = TestRouter::builder()
+            .static_routes(vec![(default_route, Some(source_prefix))])
+            .build();
 
-    use crate::{
-        babel::Update, crypto::PublicKey, metric::Metric, peer::Peer, router_id::RouterId,
-        sequence_number::SeqNo, source_table::SourceKey, subnet::Subnet,
-    };
+        let (con, remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        peer.set_accepts_source_specific_routes(true);
 
-    #[test]
-    fn calculate_route_hold_time() {
-        let router_id = RouterId::new(PublicKey::from([0; 32]));
-        let seqno = SeqNo::new();
-        let metric = Metric::new(0);
-        let subnet = Subnet::new(
-            IpAddr::V6(Ipv6Addr::new(
-                0x400, 0x0123, 0x4567, 0x89AB, 0xCDEF, 0, 0, 0,
-            )),
-            64,
-        )
-        .expect("Valid subnet definition");
-        let update = Update::new(Duration::from_secs(60), seqno, metric, subnet, router_id);
-        assert_eq!(
-            Duration::from_millis(210_000),
-            super::route_hold_time(&update)
-        );
-        let update = Update::new(Duration::from_secs(1), seqno, metric, subnet, router_id);
-        assert_eq!(
-            Duration::from_millis(3_500),
-            super::route_hold_time(&update)
-        );
-        // Since update is expressed in centiseconds, we lose precision and
-        // Duration::from_milis(478) is equal to Duration::from_millis(470);
-        let update = Update::new(Duration::from_millis(478), seqno, metric, subnet, router_id);
-        assert_eq!(
-            Duration::from_millis(1_645),
-            super::route_hold_time(&update)
+        router.handle_incoming_route_request(
+            RouteRequest::new(Some(default_route)).with_source_prefix(Some(source_prefix)),
+            peer.clone(),
         );
+        router.handle_incoming_route_request(RouteRequest::new(Some(default_route)), peer);
+
+        async fn next_update(
+            remote: &mut Framed<tokio::io::DuplexStream, packet::Codec>,
+        ) -> babel::Update {
+            loop {
+                match tokio::time::timeout(Duration::from_secs(2), remote.next())
+                    .await
+                    .expect("Update is received")
+                {
+                    Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update)))) => break update,
+                    Some(Ok(_)) => continue,
+                    other => panic!("Connection closed without update: {other:?}"),
+                }
+            }
+        }
 
-        // Retractions are also held for some time
-        let update = Update::new(
-            Duration::from_millis(0),
-            seqno,
-            Metric::infinite(),
-            subnet,
-            router_id,
-        );
-        assert_eq!(
-            super::RETRACTED_ROUTE_HOLD_TIME,
-            super::route_hold_time(&update)
-        );
+        let mut remote = Framed::new(remote, packet::Codec::new());
+        let update = next_update(&mut remote).await;
+        assert_eq!(update.subnet(), default_route);
+        assert_eq!(update.source_prefix(), Some(source_prefix));
+        assert_eq!(update.metric(), Metric::new(0));
+
+        // There is no default route for all sources, so that is retracted.
+        let update = next_update(&mut remote).await;
+        assert_eq!(update.subnet(), default_route);
+        assert_eq!(update.source_prefix(), None);
+        assert!(update.metric().is_infinite());
     }
 
+    /// Source-specific routes are only sent to a peer once its Hellos announce it can decode them.
     #[tokio::test]
-    async fn calculate_advertised_update_interval() {
-        // Set up a dummy peer since that is needed to create a `RouteEntry`
-        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
-        let (router_control_tx, _router_control_rx) = mpsc::unbounded_channel();
-        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
-        let (con1, _con2) = tokio::io::duplex(1500);
-        let neighbor = Peer::new(
-            router_data_tx,
-            router_control_tx,
-            con1,
-            dead_peer_sink,
-            Arc::new(AtomicU64::new(0)),
-            Arc::new(AtomicU64::new(0)),
-        )
-        .expect("Can create a dummy peer");
-        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+    async fn source_specific_routes_wait_for_peer_support() {
+        let default_route =
+            Subnet::new(Ipv6Addr::UNSPECIFIED.into(), 0).expect("Valid subnet definition");
+        let source_prefix = Subnet::new(Ipv6Addr::new(0x400, 0x1, 0, 0, 0, 0, 0, 0).into(), 32)
             .expect("Valid subnet definition");
-        let router_id = RouterId::new(PublicKey::from([0; 32]));
-        let source = SourceKey::new(subnet, router_id);
-        let metric = Metric::new(0);
-        let seqno = SeqNo::new();
-        let selected = true;
+        let router = TestRouter::builder()
+            .static_routes(vec![(default_route, Some(source_prefix))])
+            .build();
 
-        let expiration = Duration::from_secs(15);
-        let re = super::RouteEntry::new(
-            source,
-            neighbor.clone(),
-            metric,
-            seqno,
-            selected,
-            expiration,
-        );
-        // We can't match exactly here since everything takes a non instant amount of time to do,
-        // but basically verify that the calculated interval is within expected parameters.
-        let advertised_interval = super::advertised_update_interval(&re);
-        assert_eq!(advertised_interval, super::UPDATE_INTERVAL);
+        let (con, remote) = tokio::io::duplex(1500);
+        let peer = router.peer(con);
+        let mut remote = Framed::new(remote, packet::Codec::new());
 
-        // Expired route with finite metric
-        let expiration = Duration::from_secs(0);
-        let re = super::RouteEntry::new(
-            source,
-            neighbor.clone(),
-            metric,
-            seqno,
-            selected,
-            expiration,
+        router.handle_incoming_route_request(
+            RouteRequest::new(Some(default_route)).with_source_prefix(Some(source_prefix)),
+            peer.clone(),
+        );
+        router.handle_incoming_hello(babel::Hello::new_unicast(SeqNo::new(), 400), peer.clone());
+        assert!(matches!(
+            tokio::time::timeout(Duration::from_secs(1), remote.next())
+                .await
+                .expect("IHU is received"),
+            Some(Ok(Packet::ControlPacket(babel::Tlv::Ihu(_))))
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), remote.next())
+                .await
+                .is_err(),
+            "Source-specific route is not sent"
         );
-        let advertised_interval = super::advertised_update_interval(&re);
-        assert_eq!(advertised_interval, super::UPDATE_INTERVAL);
 
-        // Expired route with infinite metric
-        let re = super::RouteEntry::new(
-            source,
-            neighbor.clone(),
-            Metric::infinite(),
-            seqno,
-            selected,
-            expiration,
+        router.handle_incoming_hello(
+            babel::Hello::new_unicast(SeqNo::new(), 400).with_source_specific_routes(),
+            peer,
         );
-        let advertised_interval = super::advertised_update_interval(&re);
-        assert_eq!(advertised_interval, super::INTERVAL_NOT_REPEATING);
+        let update = loop {
+            match tokio::time::timeout(Duration::from_secs(2), remote.next())
+                .await
+                .expect("Update is received")
+            {
+                Some(Ok(Packet::ControlPacket(babel::Tlv::Update(update)))) => break update,
+                Some(Ok(_)) => continue,
+                other => panic!("Connection closed without update: {other:?}"),
+            }
+        };
+        assert_eq!(update.subnet(), default_route);
+        assert_eq!(update.source_prefix(), Some(source_prefix));
+    }
 
-        // Check that the interval is properly capped
-        let expiration = Duration::from_secs(600);
-        let re = super::RouteEntry::new(source, neighbor, metric, seqno, selected, expiration);
-        // We can't match exactly here since everything takes a non instant amount of time to do,
-        // but basically verify that the calculated interval is within expected parameters.
-        let advertised_interval = super::advertised_update_interval(&re);
-        assert_eq!(advertised_interval, super::UPDATE_INTERVAL);
+    /// The flap damping penalty of routes through a peer is kept when the connection to the peer
+    /// is reestablished, even though the new connection has another identifier.
+    #[tokio::test]
+    async fn flap_damping_penalty_survives_reconnect() {
+        let router = TestRouter::builder()
+            .flap_damping(FlapDampingConfig {
+                suppress_threshold: 2500,
+                ..FlapDampingConfig::default()
+            })
+            .build();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Can bind a local listener");
+        let listen_addr = listener.local_addr().expect("Listener has an address");
+        // Every connection uses another source port, so it has another identifier.
+        let mut connections = Vec::new();
+        let mut remotes = Vec::new();
+        for _ in 0..2 {
+            connections.push(
+                tokio::net::TcpStream::connect(listen_addr)
+                    .await
+                    .expect("Can connect to local listener"),
+            );
+            remotes.push(listener.accept().await.expect("Can accept connection"));
+        }
+
+        let remote = PublicKey::from(&SecretKey::new());
+        let mut connect = || {
+            let peer = router.peer(connections.remove(0));
+            peer.set_public_key(remote);
+            router.add_peer_interface(peer.clone());
+            peer
+        };
+        let update = |seqno| {
+            Update::new(
+                Duration::from_secs(60),
+                seqno,
+                Metric::new(10),
+                remote.subnet(),
+                RouterId::new(remote),
+            )
+        };
+
+        // The route is selected, and retracted once the connection dies.
+        let first = connect();
+        router.handle_incoming_update(update(SeqNo::new()), first.clone());
+        router.handle_dead_peer(first.clone());
+        assert!(router.suppressed_routes().is_empty());
+
+        // Selecting the route again through the new connection exceeds the threshold.
+        let second = connect();
+        assert_ne!(
+            first.connection_identifier(),
+            second.connection_identifier()
+        );
+        router.handle_incoming_update(update(SeqNo::new() + 1), second.clone());
+        let suppressed = router.suppressed_routes();
+        assert_eq!(suppressed.len(), 1);
+        assert_eq!(suppressed[0].subnet, remote.subnet());
+        assert_eq!(suppressed[0].neighbour, second.identifier());
     }
 }
@@ -1,18 +1,25 @@
 use ip_network_table_deps_treebitmap::IpLookupTable;
-use tokio::{sync::mpsc, task::JoinHandle};
+use tokio::{
+    sync::{mpsc, Notify},
+    task::JoinHandle,
+};
 use tracing::{error, warn};
 
 use crate::{
-    metric::Metric, peer::Peer, router_id::RouterId, sequence_number::SeqNo,
-    source_table::SourceKey, subnet::Subnet,
+    in_global_subnet, metric::Metric, peer::Peer, router_id::RouterId, sequence_number::SeqNo,
+    source_table::SourceKey, subnet::Subnet, GLOBAL_SUBNET_PREFIX_LEN,
 };
 use core::fmt;
 use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
     net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
 /// Information about a routes expiration.
+#[derive(Debug, Clone, Copy)]
 pub enum RouteExpirationType {
     /// Route should be retracted.
     Retract,
@@ -23,6 +30,7 @@ pub enum RouteExpirationType {
 #[derive(Debug, Clone, PartialEq)]
 pub struct RouteKey {
     subnet: Subnet,
+    source_prefix: Option<Subnet>,
     neighbor: Peer,
 }
 
@@ -30,11 +38,20 @@ impl Eq for RouteKey {}
 
 impl fmt::Display for RouteKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_fmt(format_args!(
-            "{} via {}",
-            self.subnet,
-            self.neighbor.connection_identifier()
-        ))
+        if let Some(source_prefix) = self.source_prefix {
+            f.write_fmt(format_args!(
+                "{} from {} via {}",
+                self.subnet,
+                source_prefix,
+                self.neighbor.connection_identifier()
+            ))
+        } else {
+            f.write_fmt(format_args!(
+                "{} via {}",
+                self.subnet,
+                self.neighbor.connection_identifier()
+            ))
+        }
     }
 }
 
@@ -45,14 +62,44 @@ pub struct RouteEntry {
     metric: Metric,
     seqno: SeqNo,
     selected: bool,
+    /// The route is configured by the operator instead of learned from the neighbour, see
+    /// [`PinnedRoute`](crate::pinned_route::PinnedRoute).
+    pinned: bool,
     expires: Instant,
 }
 
+/// Timer which notifies the router once a [`RouteEntry`] expires. The timer runs in a background
+/// task, which is kept when the timer is reset, so refreshing a route does not spawn a new task.
+/// The task is aborted when the timer is dropped.
+struct RouteTimer {
+    state: Arc<RouteTimerState>,
+    handle: JoinHandle<()>,
+}
+
+/// State shared between a [`RouteTimer`] and its background task.
+struct RouteTimerState {
+    /// The time at which the route expires, and what needs to happen to it at that time.
+    expiration: Mutex<(tokio::time::Instant, RouteExpirationType)>,
+    /// Wakes up the background task when the expiration is changed.
+    reset: Notify,
+}
+
 impl RouteKey {
     /// Create a new `RouteKey` with the given values.
     #[inline]
     pub const fn new(subnet: Subnet, neighbor: Peer) -> Self {
-        Self { subnet, neighbor }
+        Self {
+            subnet,
+            source_prefix: None,
+            neighbor,
+        }
+    }
+
+    /// Set the source prefix of this `RouteKey`, making it the key of a source-specific route.
+    #[inline]
+    pub fn with_source_prefix(mut self, source_prefix: Option<Subnet>) -> Self {
+        self.source_prefix = source_prefix;
+        self
     }
 
     /// Returns the [`Subnet`] associated with this `RouteKey`.
@@ -61,6 +108,13 @@ impl RouteKey {
         self.subnet
     }
 
+    /// Returns the source prefix associated with this `RouteKey`, if it is the key of a
+    /// source-specific route.
+    #[inline]
+    pub const fn source_prefix(&self) -> Option<Subnet> {
+        self.source_prefix
+    }
+
     /// Returns the [`neighbour`](Peer) associated with this `RouteKey`.
     #[inline]
     pub fn neighbour(&self) -> &Peer {
@@ -84,6 +138,7 @@ impl RouteEntry {
             metric,
             seqno,
             selected,
+            pinned: false,
             expires: Instant::now() + expiration,
         }
     }
@@ -113,6 +168,12 @@ impl RouteEntry {
         self.selected
     }
 
+    /// Indicates this `RouteEntry` is a pinned route, which is configured by the operator instead
+    /// of learned from the neighbour.
+    pub const fn pinned(&self) -> bool {
+        self.pinned
+    }
+
     /// Updates the metric of this `RouteEntry` to the given value.
     pub fn update_metric(&mut self, metric: Metric) {
         self.metric = metric;
@@ -130,8 +191,8 @@ impl RouteEntry {
 
     /// Updates when this `RouteEntry` expires.
     ///
-    /// This is only the marker for the entry itself, after calling this you also need to call
-    /// [`RoutingTable::reset_route_timer`].
+    /// This is only the marker for the entry itself, the timer of the route is reset if this is
+    /// called through [`RoutingTable::update`].
     pub fn update_expiration(&mut self, expiration: Duration) {
         self.expires = Instant::now() + expiration;
     }
@@ -141,6 +202,11 @@ impl RouteEntry {
         self.selected = selected
     }
 
+    /// Sets whether or not this `RouteEntry` is a pinned route.
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned
+    }
+
     /// Get the remaining [`Duration`] until this `RouteEntry` expires, unles it is reset.
     pub fn expires(&self) -> Duration {
         // Explicitly use saturating_duration_since instead of a regular subtraction here, since
@@ -154,8 +220,41 @@ impl RouteEntry {
 /// Associated data can be saved on the `RoutingTable`. This is provided when the [`RouteKey`] is
 /// inserted for the first time. It is removed when there no longer are any
 /// [`RouteEntries`](RouteEntry) for the given [`RouteKey`].
+///
+/// Source-specific routes are kept in a separate table per source prefix. Selection of a route
+/// happens independently for every (destination, source prefix) pair.
+///
+/// Routes for subnets which are larger than the global subnet, like the default route announced by
+/// exit nodes, are only used to look up addresses outside of the global subnet. Traffic for
+/// unknown overlay addresses is thus never sent to an exit node.
 pub struct RoutingTable<T> {
+    /// Destination-only routes.
     table: IpLookupTable<Ipv6Addr, TableEntry<T>>,
+    /// Source-specific routes, by source prefix.
+    source_tables: HashMap<Subnet, IpLookupTable<Ipv6Addr, TableEntry<T>>>,
+    /// Amount of [`RouteEntries`](RouteEntry) in all tables.
+    route_count: usize,
+    /// Routes which can be evicted from the table, in the order in which they are evicted.
+    eviction_order: EvictionOrder,
+}
+
+/// Index of the [`RouteKeys`](RouteKey) of all routes which are not pinned, ordered by their
+/// [`EvictionRank`].
+#[derive(Default)]
+struct EvictionOrder {
+    /// Keys of the routes by their rank. Multiple routes only share a rank if they expire at
+    /// exactly the same time.
+    routes: BTreeMap<EvictionRank, Vec<RouteKey>>,
+}
+
+/// Rank of a [`RouteEntry`] in the [`EvictionOrder`], the route with the highest rank is evicted
+/// first. Routes which are not used come first, then routes with a higher metric, and then routes
+/// which expire sooner, i.e. which were updated least recently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct EvictionRank {
+    unused: bool,
+    metric: Metric,
+    expires: Reverse<Instant>,
 }
 
 /// An entry in the RoutingTable.
@@ -166,7 +265,7 @@ struct TableEntry<T> {
     /// Additional data saved for the RouteKey
     extra_data: T,
     /// Route entries saved for the route key
-    entries: Vec<(RouteEntry, JoinHandle<()>)>,
+    entries: Vec<(RouteEntry, RouteTimer)>,
 }
 
 impl<T> RoutingTable<T> {
@@ -174,9 +273,51 @@ impl<T> RoutingTable<T> {
     pub fn new() -> Self {
         Self {
             table: IpLookupTable::new(),
+            source_tables: HashMap::new(),
+            route_count: 0,
+            eviction_order: EvictionOrder::default(),
+        }
+    }
+
+    /// The amount of [`RouteEntries`](RouteEntry) in the table, for all subnets and source
+    /// prefixes.
+    pub fn route_count(&self) -> usize {
+        self.route_count
+    }
+
+    /// Get the table holding the routes for the given source prefix, if there is one.
+    fn table(
+        &self,
+        source_prefix: Option<Subnet>,
+    ) -> Option<&IpLookupTable<Ipv6Addr, TableEntry<T>>> {
+        match source_prefix {
+            None => Some(&self.table),
+            Some(source_prefix) => self.source_tables.get(&source_prefix),
+        }
+    }
+
+    /// Get the table holding the routes for the given source prefix mutably, if there is one.
+    fn table_mut(
+        &mut self,
+        source_prefix: Option<Subnet>,
+    ) -> Option<&mut IpLookupTable<Ipv6Addr, TableEntry<T>>> {
+        match source_prefix {
+            None => Some(&mut self.table),
+            Some(source_prefix) => self.source_tables.get_mut(&source_prefix),
         }
     }
 
+    /// Iterate over all tables, together with their source prefix.
+    fn tables(
+        &self,
+    ) -> impl Iterator<Item = (Option<Subnet>, &'_ IpLookupTable<Ipv6Addr, TableEntry<T>>)> {
+        std::iter::once((None, &self.table)).chain(
+            self.source_tables
+                .iter()
+                .map(|(source_prefix, table)| (Some(*source_prefix), table)),
+        )
+    }
+
     /// Get a  reference to the [`RouteEntry`] associated with the [`RouteKey`] if one is
     /// present in the table.
     pub fn get(&self, key: &RouteKey) -> Option<&RouteEntry> {
@@ -185,7 +326,7 @@ impl<T> RoutingTable<T> {
             _ => return None,
         };
 
-        self.table
+        self.table(key.source_prefix)?
             .exact_match(addr, key.subnet.prefix_len() as u32)?
             .entries
             .iter()
@@ -193,22 +334,6 @@ impl<T> RoutingTable<T> {
             .find(|entry| entry.neighbor == key.neighbor)
     }
 
-    /// Get a mutable reference to the [`RouteEntry`] associated with the [`RouteKey`] if one is
-    /// present in the table.
-    pub fn get_mut(&mut self, key: &RouteKey) -> Option<&mut RouteEntry> {
-        let addr = match key.subnet.address() {
-            IpAddr::V6(addr) => addr,
-            _ => return None,
-        };
-
-        self.table
-            .exact_match_mut(addr, key.subnet.prefix_len() as u32)?
-            .entries
-            .iter_mut()
-            .map(|(entry, _)| entry)
-            .find(|entry| entry.neighbor == key.neighbor)
-    }
-
     /// Insert a new [`RouteEntry`] in the table. If there is already an entry for the
     /// [`RouteKey`], the existing entry is removed.
     ///
@@ -229,24 +354,11 @@ impl<T> RoutingTable<T> {
             _ => return,
         };
         let selected = entry.selected;
-        let expiration = tokio::spawn({
-            let key = key.clone();
-            let t = if entry.metric().is_infinite() {
-                RouteExpirationType::Remove
-            } else {
-                RouteExpirationType::Retract
-            };
-            let expires = entry.expires();
-            async move {
-                tokio::time::sleep(expires).await;
-
-                if let Err(e) = expired_route_entry_sink.send((key, t)).await {
-                    error!("Failed to notify router of expired key {e}");
-                }
-            }
-        });
-        match self
-            .table
+        let table = match key.source_prefix {
+            None => &mut self.table,
+            Some(source_prefix) => self.source_tables.entry(source_prefix).or_default(),
+        };
+        match table
             .exact_match_mut(addr, key.subnet.prefix_len() as u32)
             .map(|entry| &mut entry.entries)
         {
@@ -256,12 +368,17 @@ impl<T> RoutingTable<T> {
                     .map(|(entry, _)| entry)
                     .position(|entry| entry.neighbor == key.neighbor)
                 {
-                    // Overwrite entry if one exists for the key but cancel the timer.
-                    entries[idx].1.abort();
-                    entries[idx] = (entry, expiration);
+                    // Overwrite entry if one exists for the key, and reuse its timer.
+                    self.eviction_order.remove(&key, &entries[idx].0);
+                    self.eviction_order.insert(&key, &entry);
+                    entries[idx].1.reset(&entry);
+                    entries[idx].0 = entry;
                     idx
                 } else {
-                    entries.push((entry, expiration));
+                    self.eviction_order.insert(&key, &entry);
+                    let timer = RouteTimer::new(key.clone(), &entry, expired_route_entry_sink);
+                    entries.push((entry, timer));
+                    self.route_count += 1;
                     entries.len() - 1
                 };
                 // In debug mode, verify that we only have 1 selected route at most. We do this by
@@ -279,14 +396,17 @@ impl<T> RoutingTable<T> {
                 }
             }
             None => {
-                self.table.insert(
+                self.eviction_order.insert(&key, &entry);
+                let timer = RouteTimer::new(key.clone(), &entry, expired_route_entry_sink);
+                table.insert(
                     addr,
                     key.subnet.prefix_len() as u32,
                     TableEntry {
                         extra_data,
-                        entries: vec![(entry, expiration)],
+                        entries: vec![(entry, timer)],
                     },
                 );
+                self.route_count += 1;
             }
         };
     }
@@ -300,8 +420,8 @@ impl<T> RoutingTable<T> {
             IpAddr::V6(addr) => addr,
             _ => return None,
         };
-        match self
-            .table
+        let table = self.table_mut(key.source_prefix)?;
+        let removed = match table
             .exact_match_mut(addr, key.subnet.prefix_len() as u32)
             .map(|entry| &mut entry.entries)
         {
@@ -314,41 +434,53 @@ impl<T> RoutingTable<T> {
                 // NOTE: we don't care if val is some, we only care that no empty set is left
                 // behind.
                 if entries.is_empty() {
-                    self.table.remove(addr, key.subnet.prefix_len() as u32);
+                    table.remove(addr, key.subnet.prefix_len() as u32);
                 } else if entries.len() + 2 < entries.capacity() {
                     // Clean up some wasted space if a lot of routes get removed.
                     entries.shrink_to(entries.len() + 2);
                 }
 
-                // Cancel timer for the entry
-                elem.map(|(entry, expiration)| {
-                    expiration.abort();
-                    entry
-                })
+                // Dropping the timer cancels it.
+                elem.map(|(entry, _)| entry)
             }
             None => None,
+        };
+        // Don't keep empty tables around for source prefixes which are no longer used.
+        if let Some(source_prefix) = key.source_prefix {
+            if table.is_empty() {
+                self.source_tables.remove(&source_prefix);
+            }
         }
+        if let Some(entry) = &removed {
+            self.eviction_order.remove(key, entry);
+            self.route_count -= 1;
+        }
+
+        removed
     }
 
     /// Create an iterator over all key value pairs in the table.
     // TODO: remove this?
     pub fn iter(&self) -> impl Iterator<Item = (RouteKey, &'_ T, &'_ RouteEntry)> {
-        self.table.iter().flat_map(|(addr, prefix, entry)| {
-            entry.entries.iter().map(move |(value, _)| {
-                (
-                    RouteKey::new(
-                        Subnet::new(addr.into(), prefix as u8)
-                            .expect("Only proper subnets are inserted in the table; qed"),
-                        value.neighbor.clone(),
-                    ),
-                    &entry.extra_data,
-                    value,
-                )
+        self.tables().flat_map(|(source_prefix, table)| {
+            table.iter().flat_map(move |(addr, prefix, entry)| {
+                entry.entries.iter().map(move |(value, _)| {
+                    (
+                        RouteKey::new(
+                            Subnet::new(addr.into(), prefix as u8)
+                                .expect("Only proper subnets are inserted in the table; qed"),
+                            value.neighbor.clone(),
+                        )
+                        .with_source_prefix(source_prefix),
+                        &entry.extra_data,
+                        value,
+                    )
+                })
             })
         })
     }
 
-    /// Look up a selected route for an [`IpAddr`] in the `RoutingTable`.
+    /// Look up a selected destination-only route for an [`IpAddr`] in the `RoutingTable`.
     ///
     /// Currently only IPv6 is supported, looking up an IPv4 address always returns [`Option::None`].
     /// In the event where 2 distinct routes are inserted with selected set to true, the entry with
@@ -359,7 +491,12 @@ impl<T> RoutingTable<T> {
             IpAddr::V6(addr) => addr,
             _ => return None,
         };
-        let entries = &self.table.longest_match(addr)?.2.entries;
+        let entries = &self
+            .table
+            .longest_match(addr)
+            .filter(|(_, plen, _)| route_applies(addr, *plen))?
+            .2
+            .entries;
         if entries.is_empty() {
             // This is a logic error in our code, but don't crash as it is recoverable.
             warn!("Empty route entry list for {ip}, this is a bug");
@@ -372,18 +509,96 @@ impl<T> RoutingTable<T> {
         }
     }
 
+    /// Look up a selected route for traffic from `src` to `dst` in the `RoutingTable`, taking
+    /// source-specific routes into account.
+    ///
+    /// Routes for a more specific destination are always preferred. If multiple routes exist for
+    /// the most specific destination, the one with the most specific source prefix containing
+    /// `src` is used, falling back to less specific source prefixes if that one does not have a
+    /// selected route. Destination-only routes are considered to have a source prefix matching all
+    /// addresses.
+    ///
+    /// Currently only IPv6 is supported, looking up an IPv4 address always returns [`Option::None`].
+    pub fn lookup_selected_from(&self, dst: IpAddr, src: IpAddr) -> Option<&RouteEntry> {
+        self.lookup_selected_entries_from(dst, src)?.next()
+    }
+
+    /// Look up all [`route entries`](RouteEntry) for the subnet and source prefix of the selected
+    /// route for traffic from `src` to `dst`, as returned by
+    /// [`RoutingTable::lookup_selected_from`]. The selected route is the first entry.
+    pub fn lookup_selected_entries_from(
+        &self,
+        dst: IpAddr,
+        src: IpAddr,
+    ) -> Option<impl Iterator<Item = &RouteEntry>> {
+        let addr = match dst {
+            IpAddr::V6(addr) => addr,
+            _ => return None,
+        };
+        let mut candidates = self
+            .tables()
+            .filter(|(source_prefix, _)| match source_prefix {
+                Some(source_prefix) => source_prefix.contains_ip(src),
+                None => true,
+            })
+            .filter_map(|(source_prefix, table)| {
+                let (_, plen, entry) = table
+                    .longest_match(addr)
+                    .filter(|(_, plen, _)| route_applies(addr, *plen))?;
+                Some((plen, source_prefix.map_or(0, |sp| sp.prefix_len()), entry))
+            })
+            .collect::<Vec<_>>();
+        // Most specific destination first, then most specific source.
+        candidates.sort_by(|(dst_a, src_a, _), (dst_b, src_b, _)| {
+            dst_b.cmp(dst_a).then(src_b.cmp(src_a))
+        });
+
+        candidates
+            .into_iter()
+            .map(|(_, _, entry)| &entry.entries)
+            .find(|entries| matches!(entries.first(), Some((entry, _)) if entry.selected))
+            .map(|entries| entries.iter().map(|(entry, _)| entry))
+    }
+
+    /// Look up all [`route entries`](RouteEntry) for the most specific subnet containing an
+    /// [`IpAddr`] in the `RoutingTable`. Only destination-only routes are considered. If a route is selected, it will be the first entry in the
+    /// list.
+    ///
+    /// Currently only IPv6 is supported, looking up an IPv4 address always returns an empty list.
+    pub fn lookup_entries(&self, ip: IpAddr) -> Vec<RouteEntry> {
+        let addr = match ip {
+            IpAddr::V6(addr) => addr,
+            _ => return vec![],
+        };
+
+        self.table
+            .longest_match(addr)
+            .filter(|(_, plen, _)| route_applies(addr, *plen))
+            .map(|(_, _, entry)| entry.entries.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .map(|(entry, _)| entry)
+            .cloned()
+            .collect()
+    }
+
     /// Look up extra data for an [`IpAddr`] in the `RoutingTable`.
     ///
     /// Currently only IPv6 is supported, looking up an IPv4 address always returns [`Option::None`].
     /// Extra data is set when the route is inserted and no [`RouteKey`] is present in the table.
-    /// It remains valid until all present [`RouteEntries`](RouteEntry) have been removed.
+    /// It remains valid until all present [`RouteEntries`](RouteEntry) have been removed. Both
+    /// destination-only and source-specific routes are considered.
     pub fn lookup_extra_data(&self, ip: IpAddr) -> Option<&T> {
         let addr = match ip {
             IpAddr::V6(addr) => addr,
             _ => return None,
         };
 
-        Some(&self.table.longest_match(addr)?.2.extra_data)
+        self.tables()
+            .filter_map(|(_, table)| table.longest_match(addr))
+            .filter(|(_, plen, _)| route_applies(addr, *plen))
+            .max_by_key(|(_, plen, _)| *plen)
+            .map(|(_, _, entry)| &entry.extra_data)
     }
 
     /// Unselects a route defined by the [`RouteKey`]. This means there will no longer be a
@@ -401,8 +616,8 @@ impl<T> RoutingTable<T> {
             _ => panic!("RouteKey must exist, so it can't be IPv4"),
         };
         let entries = &mut self
-            .table
-            .exact_match_mut(addr, key.subnet.prefix_len() as u32)
+            .table_mut(key.source_prefix)
+            .and_then(|table| table.exact_match_mut(addr, key.subnet.prefix_len() as u32))
             .expect("There is an entry for the provided RouteKey")
             .entries;
         // No need for bounds check, RouteKey must exist so there must be at least 1 element.
@@ -416,7 +631,11 @@ impl<T> RoutingTable<T> {
             entries[0].0.selected,
             "Attempted to unselected a route which isn't selected"
         );
+        let old_entry = entries[0].0.clone();
         entries[0].0.selected = false;
+        let new_entry = entries[0].0.clone();
+        self.eviction_order.remove(key, &old_entry);
+        self.eviction_order.insert(key, &new_entry);
     }
 
     /// Selects a route defined by the [`RouteKey`]. This means the route defined by the [`RouteKey`]
@@ -433,37 +652,53 @@ impl<T> RoutingTable<T> {
             _ => panic!("RouteKey must exist, so it can't be IPv4"),
         };
         let entries = &mut self
-            .table
-            .exact_match_mut(addr, key.subnet.prefix_len() as u32)
+            .table_mut(key.source_prefix)
+            .and_then(|table| table.exact_match_mut(addr, key.subnet.prefix_len() as u32))
             .expect("There is an entry for the provided RouteKey")
             .entries;
         // No need for bounds check, RouteKey must exist so there must be at least 1 element.
         // The message on this assert assumes the invariant that the selected route is the first
         // element holds
-        // Unconditionally set the selected flag on the first element to false. If there is no
+        // Set the selected flag on the first element to false. If there is no
         // selected route, this is a no-op, otherwise it makes sure there is only 1 selected route
         // when we toggle the potentially new route.
-        entries[0].0.selected = false;
+        let unselected = entries[0].0.selected.then(|| {
+            let old_entry = entries[0].0.clone();
+            entries[0].0.selected = false;
+            (old_entry, entries[0].0.clone())
+        });
         let entry_idx = entries
             .iter()
             .position(|(entry, _)| entry.neighbor == key.neighbor)
             .expect("Route entry must be present in route table to select it");
+        let old_entry = entries[entry_idx].0.clone();
         entries[entry_idx].0.selected = true;
+        let new_entry = entries[entry_idx].0.clone();
         // Maintain invariant that selected route comes first.
         entries.swap(0, entry_idx);
+
+        // Keep the eviction order in sync with the changed selection.
+        if let Some((old_entry, new_entry)) = unselected {
+            let old_key = RouteKey::new(key.subnet, new_entry.neighbor.clone())
+                .with_source_prefix(key.source_prefix);
+            self.eviction_order.remove(&old_key, &old_entry);
+            self.eviction_order.insert(&old_key, &new_entry);
+        }
+        self.eviction_order.remove(key, &old_entry);
+        self.eviction_order.insert(key, &new_entry);
     }
 
-    /// Get all entries associated with a [`Subnet`]. If a route is selected, it will be the first
-    /// entry in the list.
-    pub fn entries(&self, subnet: Subnet) -> Vec<RouteEntry> {
+    /// Get all entries associated with a [`Subnet`] and source prefix. If a route is selected, it
+    /// will be the first entry in the list.
+    pub fn entries(&self, subnet: Subnet, source_prefix: Option<Subnet>) -> Vec<RouteEntry> {
         let addr = match subnet.network() {
             IpAddr::V6(addr) => addr,
             // Panic is fine here as we documented that the RouteKey must exist and that is
             // obviously not the case.
             _ => panic!("RouteKey must exist, so it can't be IPv4"),
         };
-        self.table
-            .exact_match(addr, subnet.prefix_len() as u32)
+        self.table(source_prefix)
+            .and_then(|table| table.exact_match(addr, subnet.prefix_len() as u32))
             .map(|entry| entry.entries.as_slice())
             .unwrap_or(&[])
             .iter()
@@ -472,80 +707,184 @@ impl<T> RoutingTable<T> {
             .collect()
     }
 
-    /// Resets the timer associated with a route.
-    pub fn reset_route_timer(
-        &mut self,
-        key: &RouteKey,
-        expired_route_entry_sink: mpsc::Sender<(RouteKey, RouteExpirationType)>,
-    ) {
+    /// Update the [`RouteEntry`] associated with the [`RouteKey`], if one is present in the table.
+    /// The timer of the route is reset to the new expiration of the route afterwards.
+    pub fn update(&mut self, key: &RouteKey, update: impl FnOnce(&mut RouteEntry)) {
         let addr = match key.subnet.network() {
             IpAddr::V6(addr) => addr,
             _ => return,
         };
 
-        if let Some(entries) = self
-            .table
-            .exact_match_mut(addr, key.subnet.prefix_len() as u32)
-            .map(|entry| &mut entry.entries)
-        {
-            if let Some(idx) = entries
-                .iter()
-                .map(|(entry, _)| entry)
-                .position(|entry| entry.neighbor == key.neighbor)
-            {
-                // Cancel old entry timer and keep track of the new one.
-                entries[idx].1.abort();
-                let t = if entries[idx].0.metric().is_infinite() {
-                    RouteExpirationType::Remove
-                } else {
-                    RouteExpirationType::Retract
-                };
-                let expiration = tokio::spawn({
-                    let key = key.clone();
-                    let expires = entries[idx].0.expires();
-                    async move {
-                        tokio::time::sleep(expires).await;
-
-                        if let Err(e) = expired_route_entry_sink.send((key, t)).await {
-                            error!("Failed to notify router of expired key {e}");
+        let Some((entry, timer)) = self
+            .table_mut(key.source_prefix)
+            .and_then(|table| table.exact_match_mut(addr, key.subnet.prefix_len() as u32))
+            .and_then(|entry| {
+                entry
+                    .entries
+                    .iter_mut()
+                    .find(|(entry, _)| entry.neighbor == key.neighbor)
+            })
+        else {
+            return;
+        };
+        let old_entry = entry.clone();
+        update(entry);
+        timer.reset(entry);
+        let new_entry = entry.clone();
+
+        self.eviction_order.remove(key, &old_entry);
+        self.eviction_order.insert(key, &new_entry);
+    }
+
+    /// Get the route which is evicted first if the table is full, if there is any route which can
+    /// be evicted. Pinned routes are never evicted.
+    pub fn evictable_route(&self) -> Option<(RouteKey, &RouteEntry)> {
+        let key = self.eviction_order.first()?;
+        let entry = self
+            .get(key)
+            .expect("Routes in the eviction order are present in the table; qed");
+        Some((key.clone(), entry))
+    }
+}
+
+impl EvictionOrder {
+    /// Add the route with the given [`RouteKey`] to the index, unless it is pinned.
+    fn insert(&mut self, key: &RouteKey, entry: &RouteEntry) {
+        if let Some(rank) = EvictionRank::of(entry) {
+            self.routes.entry(rank).or_default().push(key.clone());
+        }
+    }
+
+    /// Remove the route with the given [`RouteKey`] from the index. `entry` must be the
+    /// [`RouteEntry`] of the route at the time it was inserted in the index.
+    fn remove(&mut self, key: &RouteKey, entry: &RouteEntry) {
+        let Some(rank) = EvictionRank::of(entry) else {
+            return;
+        };
+        if let Some(keys) = self.routes.get_mut(&rank) {
+            keys.retain(|k| k != key);
+            if keys.is_empty() {
+                self.routes.remove(&rank);
+            }
+        }
+    }
+
+    /// The key of the route which is evicted first.
+    fn first(&self) -> Option<&RouteKey> {
+        self.routes
+            .last_key_value()
+            .and_then(|(_, keys)| keys.first())
+    }
+}
+
+impl EvictionRank {
+    /// The rank of the given [`RouteEntry`], or [`None`] if it is pinned and thus never evicted.
+    fn of(entry: &RouteEntry) -> Option<Self> {
+        (!entry.pinned).then_some(Self {
+            unused: !entry.selected || entry.metric.is_infinite(),
+            metric: entry.metric,
+            expires: Reverse(entry.expires),
+        })
+    }
+}
+
+impl RouteTimer {
+    /// Start a new timer for a [`RouteEntry`]. Once the entry expires, its key is sent on the
+    /// `expired_route_entry_sink`.
+    fn new(
+        key: RouteKey,
+        entry: &RouteEntry,
+        expired_route_entry_sink: mpsc::Sender<(RouteKey, RouteExpirationType)>,
+    ) -> Self {
+        let state = Arc::new(RouteTimerState {
+            expiration: Mutex::new(Self::expiration(entry)),
+            reset: Notify::new(),
+        });
+        let handle = tokio::spawn({
+            let state = state.clone();
+            async move {
+                loop {
+                    let (expires, t) = *state.expiration.lock().unwrap();
+                    if expires > tokio::time::Instant::now() {
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(expires) => {},
+                            _ = state.reset.notified() => {},
                         }
+                        continue;
                     }
-                });
-                entries[idx].1 = expiration;
-            };
+
+                    if let Err(e) = expired_route_entry_sink.send((key.clone(), t)).await {
+                        error!("Failed to notify router of expired key {e}");
+                    }
+                    // The route might be kept around after it expired, in which case the timer
+                    // will be reset.
+                    state.reset.notified().await;
+                }
+            }
+        });
+
+        Self { state, handle }
+    }
+
+    /// Reset the timer to the current expiration of the given [`RouteEntry`].
+    fn reset(&self, entry: &RouteEntry) {
+        *self.state.expiration.lock().unwrap() = Self::expiration(entry);
+        self.state.reset.notify_one();
+    }
+
+    /// Get the time at which a [`RouteEntry`] expires, and what needs to happen to it at that
+    /// time.
+    fn expiration(entry: &RouteEntry) -> (tokio::time::Instant, RouteExpirationType) {
+        let t = if entry.metric().is_infinite() {
+            RouteExpirationType::Remove
+        } else {
+            RouteExpirationType::Retract
         };
+        (tokio::time::Instant::now() + entry.expires(), t)
+    }
+}
+
+impl Drop for RouteTimer {
+    fn drop(&mut self) {
+        self.handle.abort();
     }
 }
 
+/// Checks if a route for a subnet with the given prefix length, which contains `addr`, can be
+/// used for `addr`.
+fn route_applies(addr: Ipv6Addr, prefix_len: u32) -> bool {
+    prefix_len >= GLOBAL_SUBNET_PREFIX_LEN as u32 || !in_global_subnet(addr.into())
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
         net::{IpAddr, Ipv6Addr},
-        sync::{atomic::AtomicU64, Arc},
         time::Duration,
     };
 
     use tokio::sync::mpsc;
 
     use crate::{
-        crypto::PublicKey, metric::Metric, peer::Peer, router_id::RouterId, sequence_number::SeqNo,
-        source_table::SourceKey, subnet::Subnet,
+        crypto::PublicKey,
+        metric::Metric,
+        peer::{Peer, PeerConfig},
+        router_id::RouterId,
+        sequence_number::SeqNo,
+        source_table::SourceKey,
+        subnet::Subnet,
     };
 
     #[tokio::test]
     async fn route_expiration() {
         // Set up a dummy peer since that is needed to create a `RouteEntry`
         let (router_data_tx, _router_data_rx) = mpsc::channel(1);
-        let (router_control_tx, _router_control_rx) = mpsc::unbounded_channel();
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
         let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
         let (con1, _con2) = tokio::io::duplex(1500);
         let neighbor = Peer::new(
-            router_data_tx,
-            router_control_tx,
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
             con1,
-            dead_peer_sink,
-            Arc::new(AtomicU64::new(0)),
-            Arc::new(AtomicU64::new(0)),
         )
         .expect("Can create a dummy peer");
         let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
@@ -573,4 +912,220 @@ mod tests {
         let re = super::RouteEntry::new(source, neighbor, metric, seqno, selected, expiration);
         assert!(re.expires().as_nanos() == 0);
     }
+
+    #[tokio::test]
+    async fn lookup_falls_back_to_less_specific_source_prefix() {
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbor = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con1,
+        )
+        .expect("Can create a dummy peer");
+        let (expired_sink, _expired_stream) = mpsc::channel(1);
+        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+            .expect("Valid subnet definition");
+        let wide_source = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x500, 0, 0, 0, 0, 0, 0, 0)), 32)
+            .expect("Valid subnet definition");
+        let narrow_source = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x500, 0, 1, 0, 0, 0, 0, 0)), 48)
+            .expect("Valid subnet definition");
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+
+        let mut table = super::RoutingTable::new();
+        for (source_prefix, metric, selected) in [
+            (None, 10, true),
+            (Some(wide_source), 20, true),
+            (Some(narrow_source), 30, false),
+        ] {
+            table.insert(
+                super::RouteKey::new(subnet, neighbor.clone()).with_source_prefix(source_prefix),
+                (),
+                super::RouteEntry::new(
+                    SourceKey::new(subnet, router_id).with_source_prefix(source_prefix),
+                    neighbor.clone(),
+                    Metric::new(metric),
+                    SeqNo::new(),
+                    selected,
+                    Duration::from_secs(60),
+                ),
+                expired_sink.clone(),
+            );
+        }
+
+        let dst = IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1));
+        let lookup = |src| {
+            table
+                .lookup_selected_from(dst, IpAddr::V6(src))
+                .map(|re| re.metric())
+        };
+        // Narrow source prefix has no selected route, so the wide one is used.
+        assert_eq!(
+            lookup(Ipv6Addr::new(0x500, 0, 1, 0, 0, 0, 0, 1)),
+            Some(Metric::new(20))
+        );
+        assert_eq!(
+            lookup(Ipv6Addr::new(0x500, 0, 2, 0, 0, 0, 0, 1)),
+            Some(Metric::new(20))
+        );
+        // Traffic from outside any source prefix uses the destination-only route.
+        assert_eq!(
+            lookup(Ipv6Addr::new(0x600, 0, 0, 0, 0, 0, 0, 1)),
+            Some(Metric::new(10))
+        );
+        assert_eq!(
+            table.lookup_selected(dst).map(|re| re.metric()),
+            Some(Metric::new(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn default_route_is_only_used_outside_global_subnet() {
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbor = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con1,
+        )
+        .expect("Can create a dummy peer");
+        let (expired_sink, _expired_stream) = mpsc::channel(1);
+        let default_route =
+            Subnet::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0).expect("Valid subnet definition");
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+
+        let mut table = super::RoutingTable::new();
+        table.insert(
+            super::RouteKey::new(default_route, neighbor.clone()),
+            (),
+            super::RouteEntry::new(
+                SourceKey::new(default_route, router_id),
+                neighbor,
+                Metric::new(10),
+                SeqNo::new(),
+                true,
+                Duration::from_secs(60),
+            ),
+            expired_sink,
+        );
+
+        let outside = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let overlay = IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1));
+        assert!(table.lookup_selected(outside).is_some());
+        assert!(table.lookup_selected_from(outside, overlay).is_some());
+        assert!(table.lookup_extra_data(outside).is_some());
+        assert!(table.lookup_selected(overlay).is_none());
+        assert!(table.lookup_selected_from(overlay, overlay).is_none());
+        assert!(table.lookup_extra_data(overlay).is_none());
+        assert!(table.lookup_entries(overlay).is_empty());
+    }
+
+    #[tokio::test]
+    async fn reset_route_timer_delays_expiration() {
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbor = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con1,
+        )
+        .expect("Can create a dummy peer");
+        let (expired_sink, mut expired_stream) = mpsc::channel(1);
+        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+            .expect("Valid subnet definition");
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+        let key = super::RouteKey::new(subnet, neighbor.clone());
+
+        let mut table = super::RoutingTable::new();
+        table.insert(
+            key.clone(),
+            (),
+            super::RouteEntry::new(
+                SourceKey::new(subnet, router_id),
+                neighbor,
+                Metric::new(10),
+                SeqNo::new(),
+                true,
+                Duration::from_millis(50),
+            ),
+            expired_sink,
+        );
+
+        // Refresh the route before it expires.
+        table.update(&key, |re| re.update_expiration(Duration::from_millis(300)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(expired_stream.try_recv().is_err());
+
+        let (expired_key, _) = tokio::time::timeout(Duration::from_secs(1), expired_stream.recv())
+            .await
+            .expect("Route expires after the new hold time")
+            .expect("Expired route is sent");
+        assert_eq!(expired_key, key);
+
+        // The timer can be reset after it fired.
+        table.update(&key, |re| re.update_expiration(Duration::from_millis(50)));
+        let (expired_key, _) = tokio::time::timeout(Duration::from_secs(1), expired_stream.recv())
+            .await
+            .expect("Route expires again after the timer is reset")
+            .expect("Expired route is sent");
+        assert_eq!(expired_key, key);
+    }
+
+    /// The eviction order is kept up to date when routes are selected, updated and removed, and
+    /// never contains pinned routes.
+    #[tokio::test]
+    async fn eviction_order_follows_route_changes() {
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con1, _con2) = tokio::io::duplex(1500);
+        let neighbor = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con1,
+        )
+        .expect("Can create a dummy peer");
+        let (expired_sink, _expired_stream) = mpsc::channel(3);
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+
+        let mut table = super::RoutingTable::new();
+        let mut insert = |i: u16, metric: u16, selected: bool, pinned: bool| {
+            let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, i, 0, 0, 0, 0, 0, 0)), 64)
+                .expect("Valid subnet definition");
+            let key = super::RouteKey::new(subnet, neighbor.clone());
+            let mut entry = super::RouteEntry::new(
+                SourceKey::new(subnet, router_id),
+                neighbor.clone(),
+                Metric::new(metric),
+                SeqNo::new(),
+                selected,
+                Duration::from_secs(60),
+            );
+            entry.set_pinned(pinned);
+            table.insert(key.clone(), (), entry, expired_sink.clone());
+            key
+        };
+        let a = insert(1, 10, true, false);
+        let b = insert(2, 20, false, false);
+        insert(3, 100, false, true);
+
+        let evicted = |table: &super::RoutingTable<()>| table.evictable_route().map(|(k, _)| k);
+        // Unselected routes go first.
+        assert_eq!(evicted(&table), Some(b.clone()));
+        // Then the route with the highest metric.
+        table.select_route(&b);
+        assert_eq!(evicted(&table), Some(b.clone()));
+        table.update(&b, |re| re.update_metric(Metric::new(5)));
+        assert_eq!(evicted(&table), Some(a.clone()));
+        table.unselect_route(&b);
+        assert_eq!(evicted(&table), Some(b.clone()));
+        table.remove(&b);
+        assert_eq!(evicted(&table), Some(a.clone()));
+        // The pinned route is never evicted.
+        table.remove(&a);
+        assert_eq!(evicted(&table), None);
+        assert_eq!(table.route_count(), 1);
+    }
 }
@@ -22,6 +22,8 @@ const SEQNO_DEDUP_TTL: Duration = Duration::from_secs(60);
 pub struct SeqnoRequestCacheKey {
     pub router_id: RouterId,
     pub subnet: Subnet,
+    /// The source prefix of the requested route, if it is source-specific.
+    pub source_prefix: Option<Subnet>,
     pub seqno: SeqNo,
 }
 
@@ -172,6 +174,10 @@ impl std::fmt::Debug for SeqnoRequestCacheKey {
         f.debug_struct("SeqnoRequestCacheKey")
             .field("router_id", &self.router_id.to_string())
             .field("subnet", &self.subnet.to_string())
+            .field(
+                "source_prefix",
+                &self.source_prefix.map(|sp| sp.to_string()),
+            )
             .field("seqno", &self.seqno.to_string())
             .finish()
     }
@@ -15,6 +15,8 @@ const SOURCE_HOLD_DURATION: Duration = Duration::from_secs(60 * 30);
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub struct SourceKey {
     subnet: Subnet,
+    /// The source prefix of a source-specific route.
+    source_prefix: Option<Subnet>,
     router_id: RouterId,
 }
 
@@ -48,7 +50,17 @@ impl FeasibilityDistance {
 impl SourceKey {
     /// Create a new `SourceKey`.
     pub const fn new(subnet: Subnet, router_id: RouterId) -> Self {
-        Self { subnet, router_id }
+        Self {
+            subnet,
+            source_prefix: None,
+            router_id,
+        }
+    }
+
+    /// Set the source prefix of this `SourceKey`, making it the key of a source-specific route.
+    pub const fn with_source_prefix(mut self, source_prefix: Option<Subnet>) -> Self {
+        self.source_prefix = source_prefix;
+        self
     }
 
     /// Returns the [`RouterId`] for this `SourceKey`.
@@ -61,6 +73,11 @@ impl SourceKey {
         self.subnet
     }
 
+    /// Returns the source prefix for this `SourceKey`, if it is the key of a source-specific route.
+    pub const fn source_prefix(&self) -> Option<Subnet> {
+        self.source_prefix
+    }
+
     /// Updates the [`RouterId`] of this `SourceKey`
     pub fn set_router_id(&mut self, router_id: RouterId) {
         self.router_id = router_id
@@ -130,7 +147,8 @@ impl SourceTable {
         // Before an update is accepted it should be checked against the feasbility condition
         // If an entry in the source table with the same source key exists, we perform the feasbility check
         // If no entry exists yet, the update is accepted as there is no better alternative available (yet)
-        let source_key = SourceKey::new(update.subnet(), update.router_id());
+        let source_key = SourceKey::new(update.subnet(), update.router_id())
+            .with_source_prefix(update.source_prefix());
         match self.get(&source_key) {
             Some(entry) => {
                 (update.seqno().gt(&entry.seqno()))
@@ -156,10 +174,17 @@ impl SourceTable {
 
 impl fmt::Display for SourceKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_fmt(format_args!(
-            "{} advertised by {}",
-            self.subnet, self.router_id
-        ))
+        if let Some(source_prefix) = self.source_prefix {
+            f.write_fmt(format_args!(
+                "{} from {} advertised by {}",
+                self.subnet, source_prefix, self.router_id
+            ))
+        } else {
+            f.write_fmt(format_args!(
+                "{} advertised by {}",
+                self.subnet, self.router_id
+            ))
+        }
     }
 }
 
@@ -171,18 +196,14 @@ mod tests {
         babel,
         crypto::SecretKey,
         metric::Metric,
-        peer::Peer,
+        peer::{Peer, PeerConfig},
         router_id::RouterId,
         routing_table::RouteEntry,
         sequence_number::SeqNo,
         source_table::{FeasibilityDistance, SourceKey, SourceTable},
         subnet::Subnet,
     };
-    use std::{
-        net::Ipv6Addr,
-        sync::{atomic::AtomicU64, Arc},
-        time::Duration,
-    };
+    use std::{net::Ipv6Addr, time::Duration};
 
     /// A retraction is always considered to be feasible.
     #[tokio::test]
@@ -372,16 +393,12 @@ mod tests {
         );
 
         let (router_data_tx, _router_data_rx) = mpsc::channel(1);
-        let (router_control_tx, _router_control_rx) = mpsc::unbounded_channel();
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
         let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
         let (con1, _con2) = tokio::io::duplex(1500);
         let neighbor = Peer::new(
-            router_data_tx,
-            router_control_tx,
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
             con1,
-            dead_peer_sink,
-            Arc::new(AtomicU64::new(0)),
-            Arc::new(AtomicU64::new(0)),
         )
         .expect("Can create a dummy peer");
 
@@ -417,16 +434,12 @@ mod tests {
         );
 
         let (router_data_tx, _router_data_rx) = mpsc::channel(1);
-        let (router_control_tx, _router_control_rx) = mpsc::unbounded_channel();
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
         let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
         let (con1, _con2) = tokio::io::duplex(1500);
         let neighbor = Peer::new(
-            router_data_tx,
-            router_control_tx,
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
             con1,
-            dead_peer_sink,
-            Arc::new(AtomicU64::new(0)),
-            Arc::new(AtomicU64::new(0)),
         )
         .expect("Can create a dummy peer");
 
@@ -462,16 +475,12 @@ mod tests {
         );
 
         let (router_data_tx, _router_data_rx) = mpsc::channel(1);
-        let (router_control_tx, _router_control_rx) = mpsc::unbounded_channel();
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
         let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
         let (con1, _con2) = tokio::io::duplex(1500);
         let neighbor = Peer::new(
-            router_data_tx,
-            router_control_tx,
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
             con1,
-            dead_peer_sink,
-            Arc::new(AtomicU64::new(0)),
-            Arc::new(AtomicU64::new(0)),
         )
         .expect("Can create a dummy peer");
 
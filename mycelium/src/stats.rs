@@ -0,0 +1,214 @@
+//! Node-wide counters of the traffic handled by a [`Node`](crate::Node).
+//!
+//! Where the [`Metrics`](crate::metrics::Metrics) are meant to be scraped by a monitoring system,
+//! these counters are always kept, so they can be inspected without one. All counters are
+//! monotonic since the node started.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{babel, metrics::DropReason, packet::ControlPacket};
+
+/// Size of the header of a TLV, which is not included in its wire size.
+const TLV_HEADER_SIZE: u64 = 2;
+
+/// An amount of packets, and their total size in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Traffic {
+    /// Amount of packets.
+    pub packets: u64,
+    /// Total size of the packets, in bytes.
+    pub bytes: u64,
+}
+
+/// Snapshot of the node-wide counters, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeStats {
+    /// Time at which the node started, i.e. since when the counters count.
+    pub started: SystemTime,
+    /// Data packets sent to peers, both the ones sent by the local node and transit packets.
+    pub forwarded: Traffic,
+    /// Data packets sent to peers which were received from another node, i.e. for which this
+    /// node is a transit hop. This is part of `forwarded`.
+    pub transit: Traffic,
+    /// Data packets delivered to the local node.
+    pub local: Traffic,
+    /// Data packets received from peers.
+    pub data_received: Traffic,
+    /// Data packets which were dropped, by reason. Every reason is present, also if no packet
+    /// was dropped for it.
+    pub dropped: Vec<(DropReason, u64)>,
+    /// Control TLVs sent to peers.
+    pub control_sent: Traffic,
+    /// Control TLVs received from peers.
+    pub control_received: Traffic,
+    /// Route updates sent to peers. These are part of `control_sent`.
+    pub updates_sent: u64,
+    /// Route updates received from peers. These are part of `control_received`.
+    pub updates_received: u64,
+}
+
+/// Counters for an amount of packets and bytes.
+#[derive(Debug, Default)]
+struct TrafficCounter {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl TrafficCounter {
+    fn record(&self, bytes: u64) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> Traffic {
+        Traffic {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The live counters, from which a [`NodeStats`] snapshot is taken.
+#[derive(Debug)]
+pub(crate) struct StatsCounters {
+    started: SystemTime,
+    forwarded: TrafficCounter,
+    transit: TrafficCounter,
+    local: TrafficCounter,
+    data_received: TrafficCounter,
+    /// Indexed by the [`DropReason`], in the order of [`DropReason::ALL`].
+    dropped: [AtomicU64; DropReason::ALL.len()],
+    control_sent: TrafficCounter,
+    control_received: TrafficCounter,
+    updates_sent: AtomicU64,
+    updates_received: AtomicU64,
+}
+
+impl StatsCounters {
+    /// Create new `StatsCounters`, which count from now.
+    pub(crate) fn new() -> Self {
+        Self {
+            started: SystemTime::now(),
+            forwarded: TrafficCounter::default(),
+            transit: TrafficCounter::default(),
+            local: TrafficCounter::default(),
+            data_received: TrafficCounter::default(),
+            dropped: Default::default(),
+            control_sent: TrafficCounter::default(),
+            control_received: TrafficCounter::default(),
+            updates_sent: AtomicU64::new(0),
+            updates_received: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a data packet of `bytes` which is sent to a peer. `transit` is set if the packet
+    /// was received from another node.
+    pub(crate) fn forwarded(&self, bytes: usize, transit: bool) {
+        self.forwarded.record(bytes as u64);
+        if transit {
+            self.transit.record(bytes as u64);
+        }
+    }
+
+    /// Record a data packet of `bytes` which is delivered to the local node.
+    pub(crate) fn local(&self, bytes: usize) {
+        self.local.record(bytes as u64);
+    }
+
+    /// Record a data packet of `bytes` which is received from a peer.
+    pub(crate) fn data_received(&self, bytes: usize) {
+        self.data_received.record(bytes as u64);
+    }
+
+    /// Record a data packet which is dropped.
+    pub(crate) fn dropped(&self, reason: DropReason) {
+        self.dropped[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a control packet which is sent to a peer.
+    pub(crate) fn control_sent(&self, packet: &ControlPacket) {
+        self.control_sent
+            .record(packet.wire_size() as u64 + TLV_HEADER_SIZE);
+        if matches!(packet, babel::Tlv::Update(_)) {
+            self.updates_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a control packet which is received from a peer.
+    pub(crate) fn control_received(&self, packet: &ControlPacket) {
+        self.control_received
+            .record(packet.wire_size() as u64 + TLV_HEADER_SIZE);
+        if matches!(packet, babel::Tlv::Update(_)) {
+            self.updates_received.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Take a snapshot of the counters.
+    pub(crate) fn snapshot(&self) -> NodeStats {
+        NodeStats {
+            started: self.started,
+            forwarded: self.forwarded.load(),
+            transit: self.transit.load(),
+            local: self.local.load(),
+            data_received: self.data_received.load(),
+            dropped: DropReason::ALL
+                .into_iter()
+                .zip(&self.dropped)
+                .map(|(reason, count)| (reason, count.load(Ordering::Relaxed)))
+                .collect(),
+            control_sent: self.control_sent.load(),
+            control_received: self.control_received.load(),
+            updates_sent: self.updates_sent.load(Ordering::Relaxed),
+            updates_received: self.updates_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatsCounters, Traffic};
+    use crate::metrics::DropReason;
+
+    #[test]
+    fn counters_are_aggregated() {
+        let counters = StatsCounters::new();
+        counters.forwarded(100, false);
+        counters.forwarded(200, true);
+        counters.local(50);
+        counters.dropped(DropReason::NoRoute);
+        counters.dropped(DropReason::NoRoute);
+        counters.dropped(DropReason::QueueFull);
+
+        let stats = counters.snapshot();
+        assert_eq!(
+            stats.forwarded,
+            Traffic {
+                packets: 2,
+                bytes: 300
+            }
+        );
+        assert_eq!(
+            stats.transit,
+            Traffic {
+                packets: 1,
+                bytes: 200
+            }
+        );
+        assert_eq!(stats.local.bytes, 50);
+        assert_eq!(stats.dropped.len(), DropReason::ALL.len());
+        for (reason, count) in stats.dropped {
+            let expected = match reason {
+                DropReason::NoRoute => 2,
+                DropReason::QueueFull => 1,
+                _ => 0,
+            };
+            assert_eq!(count, expected, "{}", reason.as_str());
+        }
+    }
+}
@@ -9,7 +9,7 @@
 //! might not be optimal for other uses.
 
 use core::fmt;
-use std::{hash::Hash, net::IpAddr};
+use std::{hash::Hash, net::IpAddr, str::FromStr};
 
 use ipnet::IpNet;
 
@@ -25,6 +25,10 @@ pub struct Subnet {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PrefixLenError;
 
+/// An error returned when parsing a [`Subnet`] from a string fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubnetParseError;
+
 impl Subnet {
     /// Create a new `Subnet` from the given [`IpAddr`] and prefix length.
     pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Subnet, PrefixLenError> {
@@ -148,6 +152,24 @@ impl fmt::Display for Subnet {
     }
 }
 
+impl FromStr for Subnet {
+    type Err = SubnetParseError;
+
+    /// Parse a `Subnet` in CIDR notation, e.g. `10.0.0.0/8`. A plain IP address is parsed as a
+    /// `Subnet` containing only that address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(inner) = IpNet::from_str(s) {
+            return Ok(Self { inner });
+        }
+
+        IpAddr::from_str(s)
+            .map(|ip| Self {
+                inner: IpNet::from(ip),
+            })
+            .map_err(|_| SubnetParseError)
+    }
+}
+
 impl fmt::Display for PrefixLenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("Invalid prefix length for this address")
@@ -156,6 +178,14 @@ impl fmt::Display for PrefixLenError {
 
 impl std::error::Error for PrefixLenError {}
 
+impl fmt::Display for SubnetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Invalid subnet, expected an IP address with optional prefix length")
+    }
+}
+
+impl std::error::Error for SubnetParseError {}
+
 impl PartialEq for Subnet {
     fn eq(&self, other: &Self) -> bool {
         // Quic check, subnets of different sizes are never equal.
@@ -220,4 +250,26 @@ mod tests {
         assert_eq!(subnet_1, subnet_5);
         assert_ne!(subnet_1, subnet_6);
     }
+
+    #[test]
+    fn parse_subnet() {
+        assert_eq!(
+            "10.1.0.0/16".parse(),
+            Ok(Subnet::new(Ipv4Addr::new(10, 1, 0, 0).into(), 16).unwrap())
+        );
+        assert_eq!(
+            "400::/7".parse(),
+            Ok(Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0).into(), 7).unwrap())
+        );
+        assert_eq!(
+            "10.1.2.3".parse(),
+            Ok(Subnet::new(Ipv4Addr::new(10, 1, 2, 3).into(), 32).unwrap())
+        );
+        assert_eq!(
+            "400::1".parse(),
+            Ok(Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 1).into(), 128).unwrap())
+        );
+        assert!("10.1.2.3/33".parse::<Subnet>().is_err());
+        assert!("not a subnet".parse::<Subnet>().is_err());
+    }
 }
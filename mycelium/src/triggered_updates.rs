@@ -0,0 +1,204 @@
+//! Rate limiting of triggered updates.
+//!
+//! When the selected route of a subnet changes, an update is sent to the peers right away, instead
+//! of waiting for the next periodic update. If many routes change in a short time, e.g. because a
+//! peer with a large part of the network behind it flaps, this would send a burst of updates to
+//! every peer, which in turn trigger updates on their peers. As recommended in
+//! [RFC 8966](https://www.rfc-editor.org/rfc/rfc8966#section-3.8.2.1), triggered updates are
+//! therefore sent at most once per [`Intervals::triggered_update`](crate::router::Intervals) to
+//! a peer. Routes which change within that time are remembered, and the updates for all of them
+//! are sent in a single batch once the time has passed. Since the update is built when the batch
+//! is sent, a route which changes multiple times in the window is only announced once, with its
+//! latest state.
+//!
+//! Retractions are urgent, as peers might otherwise keep sending traffic into a black hole, so
+//! they are never delayed.
+
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::{peer::Peer, subnet::Subnet};
+
+/// Identifies the route for which a triggered update is pending: the subnet, and the source
+/// prefix of a source-specific route.
+pub(crate) type TriggeredRoute = (Subnet, Option<Subnet>);
+
+/// Triggered update state of a single peer.
+struct PeerUpdates {
+    peer: Peer,
+    /// When the last triggered update was sent to the peer.
+    last_sent: Instant,
+    /// Routes for which an update is sent once the interval since `last_sent` passed.
+    pending: HashSet<TriggeredRoute>,
+}
+
+/// Tracks when triggered updates were last sent to peers, and which updates are pending, see the
+/// [module docs](self).
+pub(crate) struct TriggeredUpdates {
+    min_interval: Duration,
+    peers: Mutex<Vec<PeerUpdates>>,
+}
+
+impl TriggeredUpdates {
+    /// Create a new `TriggeredUpdates` which allows a triggered update to a peer once per
+    /// `min_interval`. A zero interval never delays updates.
+    pub(crate) fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            peers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The minimum time between triggered updates to a peer.
+    pub(crate) fn min_interval(&self) -> Duration {
+        self.min_interval
+    }
+
+    /// Schedule a triggered update for `route` to the given peers. This returns the peers to
+    /// which the update must be sent right away. For the other peers the update is pending until
+    /// it is returned by [`TriggeredUpdates::due`].
+    ///
+    /// An `urgent` update, i.e. a retraction, is always sent right away. A pending update for the
+    /// route is then dropped, as it is superseded.
+    pub(crate) fn schedule(
+        &self,
+        route: TriggeredRoute,
+        peers: Vec<Peer>,
+        urgent: bool,
+        now: Instant,
+    ) -> Vec<Peer> {
+        if self.min_interval.is_zero() {
+            return peers;
+        }
+
+        let mut states = self
+            .peers
+            .lock()
+            .expect("Triggered update lock is not poisoned; qed");
+        peers
+            .into_iter()
+            .filter(|peer| {
+                let Some(state) = states.iter_mut().find(|state| &state.peer == peer) else {
+                    states.push(PeerUpdates {
+                        peer: peer.clone(),
+                        last_sent: now,
+                        pending: HashSet::new(),
+                    });
+                    return true;
+                };
+                if urgent {
+                    state.pending.remove(&route);
+                    true
+                } else if now.saturating_duration_since(state.last_sent) >= self.min_interval {
+                    state.last_sent = now;
+                    true
+                } else {
+                    state.pending.insert(route);
+                    false
+                }
+            })
+            .collect()
+    }
+
+    /// Take the pending updates of the peers for which the minimum interval passed, so they can
+    /// be sent as a single batch. Peers which are no longer alive, or which don't need to be
+    /// tracked anymore, are removed.
+    pub(crate) fn due(&self, now: Instant) -> Vec<(Peer, Vec<TriggeredRoute>)> {
+        let mut states = self
+            .peers
+            .lock()
+            .expect("Triggered update lock is not poisoned; qed");
+        let mut due = Vec::new();
+        states.retain_mut(|state| {
+            if !state.peer.alive() {
+                return false;
+            }
+            if now.saturating_duration_since(state.last_sent) < self.min_interval {
+                return true;
+            }
+            if state.pending.is_empty() {
+                return false;
+            }
+            state.last_sent = now;
+            due.push((state.peer.clone(), state.pending.drain().collect()));
+            true
+        });
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        time::Duration,
+    };
+
+    use tokio::{sync::mpsc, time::Instant};
+
+    use super::TriggeredUpdates;
+    use crate::{
+        peer::{Peer, PeerConfig},
+        subnet::Subnet,
+    };
+
+    #[tokio::test]
+    async fn triggered_updates_are_coalesced() {
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::channel(1);
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con, _remote) = tokio::io::duplex(1500);
+        let peer = Peer::new(
+            PeerConfig::new(router_data_tx, router_control_tx, dead_peer_sink),
+            con,
+        )
+        .expect("Can create a dummy peer");
+        let route = |i| {
+            (
+                Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, i, 0, 0, 0, 0, 0, 0)), 64)
+                    .expect("Valid subnet definition"),
+                None,
+            )
+        };
+
+        let interval = Duration::from_secs(1);
+        let updates = TriggeredUpdates::new(interval);
+        let now = Instant::now();
+        // The first update is sent right away, the next ones within the interval are batched,
+        // also when the same route changes again.
+        assert_eq!(
+            updates
+                .schedule(route(1), vec![peer.clone()], false, now)
+                .len(),
+            1
+        );
+        for i in [1, 2, 3, 2] {
+            assert!(updates
+                .schedule(route(i), vec![peer.clone()], false, now + interval / 2)
+                .is_empty());
+        }
+        // Retractions are not delayed, and replace the pending update.
+        assert_eq!(
+            updates
+                .schedule(route(3), vec![peer.clone()], true, now + interval / 2)
+                .len(),
+            1
+        );
+        assert!(updates.due(now + interval / 2).is_empty());
+
+        let due = updates.due(now + interval);
+        assert_eq!(due.len(), 1);
+        assert!(due[0].0 == peer);
+        let mut routes = due[0].1.clone();
+        routes.sort();
+        assert_eq!(routes, vec![route(1), route(2)]);
+        // The batch starts a new interval.
+        assert!(updates
+            .schedule(route(1), vec![peer.clone()], false, now + interval * 3 / 2)
+            .is_empty());
+        assert!(updates.due(now + interval * 3 / 2).is_empty());
+        assert_eq!(updates.due(now + interval * 2)[0].1, vec![route(1)]);
+    }
+}
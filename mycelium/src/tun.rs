@@ -8,11 +8,23 @@ pub struct TunConfig {
     pub name: String,
     pub node_subnet: Subnet,
     pub route_subnet: Subnet,
+    pub mtu: u16,
+    /// Amount of queues to open on the TUN device.
+    #[cfg(target_os = "linux")]
+    pub queues: usize,
+    /// Routing table and metric of the route to the route subnet.
+    #[cfg(target_os = "linux")]
+    pub route: crate::TunRouteConfig,
+    /// Capacity of the queue of packets waiting to be written to (a single queue of) the TUN
+    /// device.
+    pub queue_size: usize,
 }
 
 #[cfg(any(target_os = "android", target_os = "ios"))]
 pub struct TunConfig {
     pub tun_fd: i32,
+    /// Capacity of the queue of packets waiting to be written to the TUN device.
+    pub queue_size: usize,
 }
 #[cfg(target_os = "linux")]
 mod linux;
@@ -21,13 +21,13 @@ use crate::crypto::PacketBuffer;
 use crate::subnet::Subnet;
 use crate::tun::TunConfig;
 
-// TODO
-const LINK_MTU: i32 = 1400;
-
-/// The 4 byte packet header written before a packet is sent on the TUN
-// TODO: figure out structure and values, but for now this seems to work.
+/// The 4 byte packet header written before a packet is sent on the TUN. This is the address
+/// family of the packet, AF_INET6.
 const HEADER: [u8; 4] = [0, 0, 0, 30];
 
+/// The 4 byte packet header written before an IPv4 packet is sent on the TUN, AF_INET.
+const IPV4_HEADER: [u8; 4] = [0, 0, 0, 2];
+
 const IN6_IFF_NODAD: u32 = 0x0020; // netinet6/in6_var.h
 const IN6_IFF_SECURED: u32 = 0x0400; // netinet6/in6_var.h
 const ND6_INFINITE_LIFETIME: u32 = 0xFFFFFFFF; // netinet6/nd6.h
@@ -87,7 +87,7 @@ pub async fn new(
             "TUN device name must be of the form 'utunXXX...' where X is a digit",
         ))?;
     }
-    let mut tun = match create_tun_interface(&tun_config.name) {
+    let mut tun = match create_tun_interface(&tun_config.name, tun_config.mtu) {
         Ok(tun) => tun,
         Err(e) => {
             error!(
@@ -100,9 +100,11 @@ pub async fn new(
     let iface = Iface::by_name(&tun_config.name)?;
     iface.add_address(tun_config.node_subnet, tun_config.route_subnet)?;
 
-    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(1000);
+    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(tun_config.queue_size);
     let (tun_stream, stream_receiver) = mpsc::unbounded_channel();
 
+    let mtu = tun_config.mtu as usize;
+
     // Spawn a single task to manage the TUN interface
     tokio::spawn(async move {
         let mut buf_hold = None;
@@ -110,7 +112,7 @@ pub async fn new(
             let mut buf = if let Some(buf) = buf_hold.take() {
                 buf
             } else {
-                PacketBuffer::new()
+                PacketBuffer::with_capacity(mtu)
             };
 
             select! {
@@ -119,7 +121,12 @@ pub async fn new(
                         None => return,
                         Some(data) => {
                             // We need to append a 4 byte header here
-                            if let Err(e) = tun.write_vectored(&[IoSlice::new(&HEADER), IoSlice::new(&data)]).await {
+                            let header = if data.first().map(|b| b >> 4) == Some(4) {
+                                &IPV4_HEADER
+                            } else {
+                                &HEADER
+                            };
+                            if let Err(e) = tun.write_vectored(&[IoSlice::new(header), IoSlice::new(&data)]).await {
                                 error!("Failed to send data to tun interface {e}");
                             }
                         }
@@ -175,12 +182,15 @@ fn validate_utun_name(input: &str) -> bool {
 }
 
 /// Create a new TUN interface
-fn create_tun_interface(name: &str) -> Result<tun::AsyncDevice, Box<dyn std::error::Error>> {
+fn create_tun_interface(
+    name: &str,
+    mtu: u16,
+) -> Result<tun::AsyncDevice, Box<dyn std::error::Error>> {
     let mut config = tun::Configuration::default();
     config
         .name(name)
         .layer(tun::Layer::L3)
-        .mtu(LINK_MTU)
+        .mtu(mtu as i32)
         .queues(1)
         .up();
     let tun = tun::create_as_async(&config)?;
@@ -36,7 +36,7 @@ pub async fn new(
 > {
     let mut tun = create_tun_interface(tun_config.tun_fd)?;
 
-    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(1000);
+    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(tun_config.queue_size);
     let (tun_stream, stream_receiver) = mpsc::unbounded_channel();
 
     // Spawn a single task to manage the TUN interface
@@ -1,21 +1,24 @@
 //! Linux specific tun interface setup.
 
-use std::io;
+use std::{io, net::IpAddr};
 
 use futures::{Sink, Stream, TryStreamExt};
+use netlink_packet_route::route::RouteAttribute;
 use rtnetlink::Handle;
 use tokio::{select, sync::mpsc};
 use tokio_tun::{Tun, TunBuilder};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::crypto::PacketBuffer;
+use crate::data::flow_hash;
 use crate::subnet::Subnet;
 use crate::tun::TunConfig;
+use crate::TunRouteConfig;
 
-// TODO
-const LINK_MTU: i32 = 1400;
-
-/// Create a new tun interface and set required routes
+/// Create a new tun interface and set required routes. If more than 1 queue is configured, the
+/// interface is opened in multi queue mode, and every queue is read and written by its own task.
+/// Packets written to the interface are spread over the queues by flow, so the packets of a
+/// single flow stay in order.
 ///
 /// # Panics
 ///
@@ -29,8 +32,8 @@ pub async fn new(
     ),
     Box<dyn std::error::Error>,
 > {
-    let tun = match create_tun_interface(&tun_config.name) {
-        Ok(tun) => tun,
+    let tuns = match create_tun_interface(&tun_config.name, tun_config.mtu, tun_config.queues) {
+        Ok(tuns) => tuns,
         Err(e) => {
             error!(
                 "Could not create tun device named \"{}\", make sure the name is not yet in use, and you have sufficient privileges to create a network device",
@@ -40,19 +43,24 @@ pub async fn new(
         }
     };
 
+    disable_router_solicitations(&tun_config.name);
+
     let (conn, handle, _) = rtnetlink::new_connection()?;
     let netlink_task_handle = tokio::spawn(conn);
 
     let tun_index = link_index_by_name(handle.clone(), tun_config.name).await?;
 
+    // If the route is installed explicitly, only assign the address itself to the interface, so
+    // the kernel does not add a route for the prefix to the main table.
+    let address_prefix_len = if tun_config.route.is_custom() {
+        128
+    } else {
+        tun_config.route_subnet.prefix_len()
+    };
     if let Err(e) = add_address(
         handle.clone(),
         tun_index,
-        Subnet::new(
-            tun_config.node_subnet.address(),
-            tun_config.route_subnet.prefix_len(),
-        )
-        .unwrap(),
+        Subnet::new(tun_config.node_subnet.address(), address_prefix_len).unwrap(),
     )
     .await
     {
@@ -63,50 +71,56 @@ pub async fn new(
         return Err(e);
     }
 
+    if tun_config.route.is_custom() {
+        if let Err(e) = add_route(
+            handle.clone(),
+            tun_index,
+            tun_config.route_subnet,
+            tun_config.route,
+        )
+        .await
+        {
+            error!(
+                "Failed to add route for {0} to TUN interface: {e}",
+                tun_config.route_subnet
+            );
+            return Err(e);
+        }
+    }
+
     // We are done with our netlink connection, abort the task so we can properly clean up.
     netlink_task_handle.abort();
 
-    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(1000);
+    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(tun_config.queue_size);
     let (tun_stream, stream_receiver) = mpsc::unbounded_channel();
 
-    // Spawn a single task to manage the TUN interface
-    tokio::spawn(async move {
-        let mut buf_hold = None;
-        loop {
-            let mut buf = if let Some(buf) = buf_hold.take() {
-                buf
-            } else {
-                PacketBuffer::new()
-            };
-
-            select! {
-                data = sink_receiver.recv() => {
-                    match data {
-                        None => return,
-                        Some(data) => {
-                            if let Err(e) = tun.send(&data).await {
-                                error!("Failed to send data to tun interface {e}");
-                            }
-                        }
-                    }
-                    // Save the buffer as we didn't  use it
-                    buf_hold = Some(buf);
-                }
-                read_result = tun.recv(buf.buffer_mut()) => {
-                    let rr = read_result.map(|n| {
-                        buf.set_size(n);
-                        buf
-                    });
-
-                    if tun_stream.send(rr).is_err() {
-                        error!("Could not forward data to tun stream, receiver is gone");
-                        break;
-                    };
+    let mtu = tun_config.mtu as usize;
+
+    if tuns.len() == 1 {
+        let tun = tuns
+            .into_iter()
+            .next()
+            .expect("There is exactly 1 queue; qed");
+        tokio::spawn(run_queue(tun, mtu, sink_receiver, tun_stream));
+    } else {
+        let mut queue_senders = Vec::with_capacity(tuns.len());
+        for tun in tuns {
+            let (queue_sender, queue_receiver) = mpsc::channel(tun_config.queue_size);
+            tokio::spawn(run_queue(tun, mtu, queue_receiver, tun_stream.clone()));
+            queue_senders.push(queue_sender);
+        }
+
+        // Spread the packets written to the interface over the queues.
+        tokio::spawn(async move {
+            while let Some(packet) = sink_receiver.recv().await {
+                let queue = (flow_hash(&packet) % queue_senders.len() as u64) as usize;
+                if queue_senders[queue].send(packet).await.is_err() {
+                    error!("Could not forward data to tun queue {queue}, queue is gone");
+                    break;
                 }
             }
-        }
-        info!("Stop reading from / writing to tun interface");
-    });
+        });
+    }
 
     Ok((
         tokio_stream::wrappers::UnboundedReceiverStream::new(stream_receiver),
@@ -114,17 +128,81 @@ pub async fn new(
     ))
 }
 
-/// Create a new TUN interface
-fn create_tun_interface(name: &str) -> Result<Tun, Box<dyn std::error::Error>> {
-    let tun = TunBuilder::new()
+/// Read packets from a single queue of the TUN interface, and write the packets received on
+/// `sink_receiver` to it, until either side is closed.
+async fn run_queue(
+    tun: Tun,
+    mtu: usize,
+    mut sink_receiver: mpsc::Receiver<PacketBuffer>,
+    tun_stream: mpsc::UnboundedSender<io::Result<PacketBuffer>>,
+) {
+    let mut buf_hold = None;
+    loop {
+        let mut buf = if let Some(buf) = buf_hold.take() {
+            buf
+        } else {
+            PacketBuffer::with_capacity(mtu)
+        };
+
+        select! {
+            data = sink_receiver.recv() => {
+                match data {
+                    None => return,
+                    Some(data) => {
+                        if let Err(e) = tun.send(&data).await {
+                            error!("Failed to send data to tun interface {e}");
+                        }
+                    }
+                }
+                // Save the buffer as we didn't  use it
+                buf_hold = Some(buf);
+            }
+            read_result = tun.recv(buf.buffer_mut()) => {
+                let rr = read_result.map(|n| {
+                    buf.set_size(n);
+                    buf
+                });
+
+                if tun_stream.send(rr).is_err() {
+                    error!("Could not forward data to tun stream, receiver is gone");
+                    break;
+                };
+            }
+        }
+    }
+    info!("Stop reading from / writing to tun interface");
+}
+
+/// Create a new TUN interface with the given amount of queues. This returns a [`Tun`] for every
+/// queue.
+fn create_tun_interface(
+    name: &str,
+    mtu: u16,
+    queues: usize,
+) -> Result<Vec<Tun>, Box<dyn std::error::Error>> {
+    let builder = TunBuilder::new()
         .name(name)
         .tap(false)
-        .mtu(LINK_MTU)
+        .mtu(mtu as i32)
         .packet_info(false)
-        .up()
-        .try_build()?;
+        .up();
 
-    Ok(tun)
+    if queues > 1 {
+        Ok(builder.try_build_mq(queues)?)
+    } else {
+        Ok(vec![builder.try_build()?])
+    }
+}
+
+/// Stop the kernel from accepting Router Advertisements on the interface, which also stops it from
+/// sending Router Solicitations. There is no link layer, so there are no routers to discover, and
+/// the data plane drops these messages anyway. Failing to do so is not fatal, e.g. if `/proc/sys`
+/// is read only in a container.
+fn disable_router_solicitations(name: &str) {
+    let path = format!("/proc/sys/net/ipv6/conf/{name}/accept_ra");
+    if let Err(e) = std::fs::write(&path, "0") {
+        warn!("Failed to disable router advertisements on TUN interface {name}: {e}");
+    }
 }
 
 /// Retrieve the link index of an interface with the given name
@@ -157,3 +235,37 @@ async fn add_address(
         .execute()
         .await?)
 }
+
+/// Add a route for an IPv6 subnet through an interface, in the configured routing table and with
+/// the configured metric.
+///
+/// The kernel removes the route when the interface is removed, which happens when the TUN
+/// interface is closed.
+async fn add_route(
+    handle: Handle,
+    link_index: u32,
+    subnet: Subnet,
+    route: TunRouteConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let IpAddr::V6(address) = subnet.address() else {
+        return Err(
+            io::Error::new(io::ErrorKind::InvalidInput, "route subnet must be IPv6").into(),
+        );
+    };
+    let mut request = handle
+        .route()
+        .add()
+        .v6()
+        .destination_prefix(address, subnet.prefix_len())
+        .output_interface(link_index);
+    if let Some(table) = route.table {
+        request = request.table_id(table);
+    }
+    if let Some(metric) = route.metric {
+        request
+            .message_mut()
+            .attributes
+            .push(RouteAttribute::Priority(metric));
+    }
+    Ok(request.execute().await?)
+}
@@ -7,9 +7,6 @@ use tracing::{error, info, warn};
 use crate::tun::TunConfig;
 use crate::{crypto::PacketBuffer, subnet::Subnet};
 
-// TODO
-const LINK_MTU: usize = 1400;
-
 /// Type of the tunnel used, specified when creating the tunnel.
 const WINDOWS_TUNNEL_TYPE: &str = "Mycelium";
 
@@ -36,7 +33,8 @@ pub async fn new(
     let tun = wintun::Adapter::create(&wintun, &tun_config.name, WINDOWS_TUNNEL_TYPE, None)?;
     info!("Created wintun tunnel interface");
     // Configure created network adapter.
-    tun.set_mtu(LINK_MTU)?;
+    tun.set_mtu(tun_config.mtu as usize)?;
+    let mtu = tun_config.mtu as usize;
     // Set address, this will use a `netsh` command under the hood unfortunately.
     // TODO: fix in library
     // tun.set_network_addresses_tuple(node_subnet.address(), route_subnet.mask(), None)?;
@@ -49,7 +47,7 @@ pub async fn new(
     let rx_session = Arc::new(tun.start_session(wintun::MAX_RING_CAPACITY)?);
     let tx_session = rx_session.clone();
 
-    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(1000);
+    let (tun_sink, mut sink_receiver) = mpsc::channel::<PacketBuffer>(tun_config.queue_size);
     let (tun_stream, stream_receiver) = mpsc::unbounded_channel();
 
     // Ingress path
@@ -58,8 +56,8 @@ pub async fn new(
             let packet = rx_session
                 .receive_blocking()
                 .map(|tun_packet| {
-                    let mut buffer = PacketBuffer::new();
-                    // SAFETY: The configured MTU is smaller than the static PacketBuffer size.
+                    let mut buffer = PacketBuffer::with_capacity(mtu);
+                    // SAFETY: The buffer is sized for the configured MTU, so the packet fits.
                     let packet_len = tun_packet.bytes().len();
                     buffer.buffer_mut()[..packet_len].copy_from_slice(tun_packet.bytes());
                     buffer.set_size(packet_len);
@@ -1,6 +1,8 @@
 use std::io;
 use std::net::Ipv4Addr;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{
     error::Error,
     net::{IpAddr, SocketAddr},
@@ -9,14 +11,48 @@ use std::{
 
 use clap::{Args, Parser, Subcommand};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncReadExt;
 #[cfg(target_family = "unix")]
 use tokio::signal::{self, unix::SignalKind};
+use tokio::sync::Mutex;
 use tracing::{debug, error, warn};
 
 use crypto::PublicKey;
-use mycelium::endpoint::Endpoint;
-use mycelium::{crypto, Node};
+use mycelium::access_list::AccessList;
+use mycelium::capture::{
+    PacketCaptureConfig, DEFAULT_CAPTURE_MAX_FILES, DEFAULT_CAPTURE_MAX_FILE_SIZE,
+};
+use mycelium::echo::{EchoReplyConfig, DEFAULT_ECHO_REPLY_RATE};
+use mycelium::endpoint::{Address, Endpoint};
+use mycelium::event_log::DEFAULT_EVENT_LOG_SIZE;
+use mycelium::flap_damping::{
+    FlapDampingConfig, DEFAULT_HALF_LIFE, DEFAULT_REUSE_THRESHOLD, DEFAULT_SUPPRESS_THRESHOLD,
+};
+use mycelium::hello_history::{
+    HelloHistoryConfig, DEFAULT_HELLO_HISTORY_WINDOW, DEFAULT_HELLO_LOSS_COST,
+    MAX_HELLO_HISTORY_WINDOW,
+};
+use mycelium::ipv4_mapping::{Ipv4Mapping, Ipv4Mappings};
+use mycelium::message::{
+    MessageStoreConfig, DEFAULT_MESSAGE_STORE_MAX_AGE, DEFAULT_MESSAGE_STORE_MAX_SIZE,
+};
+use mycelium::metric_offset::{MetricOffset, MetricOffsets};
+use mycelium::peer_manager::{
+    DialTimeouts, InboundConnectionLimits, ProxyCredentials, Socks5Proxy,
+    DEFAULT_DIAL_CONNECT_TIMEOUT, DEFAULT_DIAL_HANDSHAKE_TIMEOUT,
+    DEFAULT_MAX_INBOUND_CONNECTIONS_PER_IP, DEFAULT_MAX_INBOUND_HANDSHAKES,
+};
+use mycelium::route_selection::{MetricSelector, DEFAULT_PRIORITY_TOLERANCE};
+use mycelium::router::{
+    Intervals, QueueSizes, SplitHorizon, DEFAULT_DEAD_PEER_HELLOS, DEFAULT_HELLO_INTERVAL,
+    DEFAULT_IHU_INTERVAL, DEFAULT_QUEUE_SIZE, DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER,
+    DEFAULT_TRIGGERED_UPDATE_INTERVAL, DEFAULT_UPDATE_INTERVAL, MAX_INTERVAL, MIN_INTERVAL,
+};
+use mycelium::subnet::Subnet;
+use mycelium::{
+    crypto, Node, TunRouteConfig, DEFAULT_READY_MIN_PEERS, DEFAULT_TUN_MTU, MAX_PACKET_WORKERS,
+    MIN_TUN_MTU,
+};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
@@ -33,6 +69,10 @@ const DEFAULT_HTTP_API_SERVER_ADDRESS: SocketAddr =
 
 const DEFAULT_KEY_FILE: &str = "priv_key.bin";
 
+/// Time given to peers to process the route retractions sent to them when shutting down, before
+/// the process exits.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
 /// Default name of tun interface
 #[cfg(not(target_os = "macos"))]
 const TUN_NAME: &str = "tun0";
@@ -40,6 +80,15 @@ const TUN_NAME: &str = "tun0";
 #[cfg(target_os = "macos")]
 const TUN_NAME: &str = "utun3";
 
+/// Output format of the logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    /// Human readable logs.
+    Compact,
+    /// Logs formatted as json objects, one per line.
+    Json,
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Cli {
@@ -56,6 +105,13 @@ struct Cli {
     #[arg(long = "silent", default_value_t = false)]
     silent: bool,
 
+    /// The format of the logs.
+    ///
+    /// The json format emits every log event as a single line json object, which can be shipped
+    /// to log aggregators as is.
+    #[arg(long = "log-format", value_enum, default_value_t = LogFormat::Compact)]
+    log_format: LogFormat,
+
     #[clap(flatten)]
     node_args: NodeArguments,
 
@@ -72,10 +128,30 @@ pub enum Command {
         #[arg(long = "json")]
         json: bool,
 
+        /// Path to a private key file to inspect instead of the key file of the node.
+        #[arg(long = "key", value_name = "FILE", conflicts_with = "key")]
+        key_path: Option<PathBuf>,
+
         /// The key to inspect.
         key: Option<String>,
     },
 
+    /// Generate a new private key, without starting the node, and show its public key and the
+    /// derived address and subnet.
+    Keygen {
+        /// Path of the file to save the private key to. This can be used as key file of a node.
+        #[arg(long = "out", value_name = "FILE")]
+        out: PathBuf,
+
+        /// Overwrite the file if it already exists.
+        #[arg(long = "force")]
+        force: bool,
+
+        /// Output in json format.
+        #[arg(long = "json")]
+        json: bool,
+    },
+
     /// Actions on the message subsystem
     Message {
         #[command(subcommand)]
@@ -88,7 +164,7 @@ pub enum Command {
         command: PeersCommand,
     },
 
-    /// Actions related to routes (selected, fallback)
+    /// Actions related to routes (selected, fallback, suppressed, all)
     Routes {
         #[command(subcommand)]
         command: RoutesCommand,
@@ -150,6 +226,13 @@ pub enum PeersCommand {
     Add { peers: Vec<String> },
     /// Remove peer(s)
     Remove { peers: Vec<String> },
+    /// List the routing protocol state of the connected peers, such as link costs and Hello/IHU
+    /// timers
+    Neighbours {
+        /// Print the neighbours in JSON format
+        #[arg(long = "json", default_value_t = false)]
+        json: bool,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -166,6 +249,22 @@ pub enum RoutesCommand {
         #[arg(long = "json", default_value_t = false)]
         json: bool,
     },
+    /// Print all routes which are suppressed because they changed too often
+    Suppressed {
+        /// Print suppressed routes in JSON format
+        #[arg(long = "json", default_value_t = false)]
+        json: bool,
+    },
+    /// Print all routes known to the node, including routes which can't be selected, with their
+    /// state
+    All {
+        /// Only print routes for subnets within this subnet
+        #[arg(long = "subnet")]
+        subnet: Option<String>,
+        /// Print routes in JSON format
+        #[arg(long = "json", default_value_t = false)]
+        json: bool,
+    },
 }
 
 #[derive(Debug, Args)]
@@ -178,6 +277,26 @@ pub struct NodeArguments {
     #[arg(short = 't', long = "tcp-listen-port", default_value_t = DEFAULT_TCP_LISTEN_PORT)]
     tcp_listen_port: u16,
 
+    /// Addresses to listen on for tcp connections, e.g. to only accept peers on some networks.
+    ///
+    /// Every address is either an IP address, which uses the port set with `--tcp-listen-port`,
+    /// or a socket address like `[2001:db8::1]:9652`.
+    #[arg(
+        long = "tcp-listen-address",
+        num_args = 1..,
+        default_value = "::",
+        value_parser = parse_listen_address
+    )]
+    tcp_listen_addresses: Vec<(IpAddr, Option<u16>)>,
+
+    /// Don't accept inbound tcp connections from peers.
+    ///
+    /// This is meant for nodes which can't be reached by other nodes, e.g. because they are behind
+    /// NAT, and only connect to peers themselves. Link local peer discovery beacons are not sent
+    /// either, though beacons of other nodes are still used to connect to them.
+    #[arg(long = "disable-tcp-listener", default_value_t = false)]
+    disable_tcp_listener: bool,
+
     /// Port to listen on for quic connections.
     #[arg(short = 'q', long = "quic-listen-port", default_value_t = DEFAULT_QUIC_LISTEN_PORT)]
     quic_listen_port: u16,
@@ -198,6 +317,78 @@ pub struct NodeArguments {
     #[arg(long = "api-addr", default_value_t = DEFAULT_HTTP_API_SERVER_ADDRESS)]
     api_addr: SocketAddr,
 
+    /// API key required to access the admin endpoints of the HTTP API.
+    ///
+    /// If this is set, requests to the admin endpoints must present this key as bearer token in
+    /// the `Authorization` header. Subcommands use this to authenticate against a running node.
+    /// If this is not set, the admin endpoints are accessible without authentication.
+    #[arg(
+        long = "api-key",
+        env = "MYCELIUM_API_KEY",
+        hide_env_values = true,
+        value_parser = clap::builder::NonEmptyStringValueParser::new()
+    )]
+    api_key: Option<String>,
+
+    /// Origins from which browsers are allowed to access the HTTP API, e.g.
+    /// `https://dashboard.example.com`.
+    ///
+    /// This is needed for web applications served from a different origin to use the API. Use `*`
+    /// to allow all origins. By default, no cross-origin access is allowed.
+    #[arg(long = "api-allowed-origins", num_args = 1..)]
+    api_allowed_origins: Vec<String>,
+
+    /// Only serve the API endpoints which expose the state of the node, like the peer and route
+    /// lists.
+    ///
+    /// Endpoints which modify the node, like adding or removing peers, and the message endpoints
+    /// are not served at all. The gRPC API rejects methods which modify the node. This is useful to expose the API to less trusted consumers, e.g.
+    /// dashboards.
+    #[arg(long = "api-read-only", default_value_t = false)]
+    api_read_only: bool,
+
+    /// Amount of peers the node must be connected to before the `/readyz` endpoint of the HTTP
+    /// API reports it as ready.
+    ///
+    /// If 0, the node is ready as soon as it is running.
+    #[arg(long = "ready-min-peers", default_value_t = DEFAULT_READY_MIN_PEERS)]
+    ready_min_peers: usize,
+
+    /// Address of the gRPC API server. The gRPC API is only served if this is set.
+    ///
+    /// The gRPC API exposes the same operations as the admin endpoints of the HTTP API, and is
+    /// protected by the same API key.
+    #[cfg(feature = "grpc")]
+    #[arg(long = "grpc-addr")]
+    grpc_addr: Option<SocketAddr>,
+
+    /// Path of a Unix domain socket to serve the HTTP API on, instead of the TCP address set with
+    /// `--api-addr`.
+    ///
+    /// This keeps the API off the network entirely. Note that the subcommands of this binary can't
+    /// connect to a Unix socket, and always use the address set with `--api-addr`.
+    #[cfg(target_family = "unix")]
+    #[arg(long = "api-socket", conflicts_with = "api_tls_cert")]
+    api_socket: Option<PathBuf>,
+
+    /// Permissions of the Unix domain socket set with `--api-socket`, in octal, e.g. 600 to only
+    /// allow the user running mycelium to use the API.
+    #[cfg(target_family = "unix")]
+    #[arg(long = "api-socket-mode", requires = "api_socket", value_parser = parse_file_mode)]
+    api_socket_mode: Option<u32>,
+
+    /// Path of a PEM encoded certificate chain, to serve the HTTP API over HTTPS.
+    ///
+    /// The key of the certificate is set with `--api-tls-key`. On Unix platforms, the certificate
+    /// and key are reloaded when the process receives `SIGHUP`, e.g. after renewal. Note that the
+    /// subcommands of this binary can't connect to the API over HTTPS.
+    #[arg(long = "api-tls-cert", requires = "api_tls_key")]
+    api_tls_cert: Option<PathBuf>,
+
+    /// Path of the PEM encoded private key of the certificate set with `--api-tls-cert`.
+    #[arg(long = "api-tls-key", requires = "api_tls_cert")]
+    api_tls_key: Option<PathBuf>,
+
     /// Run without creating a TUN interface.
     ///
     /// The system will participate in the network as usual, but won't be able to send out L3
@@ -214,6 +405,32 @@ pub struct NodeArguments {
     #[arg(long = "tun-name", default_value = TUN_NAME)]
     tun_name: String,
 
+    /// MTU of the TUN interface, if one is created.
+    ///
+    /// The MTU must be at least 1280, which is the minimum MTU for IPv6. Packets larger than what
+    /// fits in a single data packet are fragmented before they are sent to peers, and
+    /// reassembled by the receiver.
+    #[arg(
+        long = "tun-mtu",
+        default_value_t = DEFAULT_TUN_MTU,
+        value_parser = clap::value_parser!(u16).range(MIN_TUN_MTU as i64..)
+    )]
+    tun_mtu: u16,
+
+    /// Routing table to add the route to the overlay through the TUN interface to, if one is
+    /// created. Only supported on Linux.
+    ///
+    /// By default, the route is added to the main table. Adding it to a dedicated table allows to
+    /// use policy routing, and lets multiple instances run on the same host with their own TUN
+    /// interface. The route is removed together with the TUN interface when the node stops.
+    #[arg(long = "tun-route-table")]
+    tun_route_table: Option<u32>,
+
+    /// Metric of the route to the overlay through the TUN interface, if one is created. Only
+    /// supported on Linux.
+    #[arg(long = "tun-route-metric")]
+    tun_route_metric: Option<u32>,
+
     /// Enable a private network, with this name.
     ///
     /// If this flag is set, the system will run in "private network mode", and use Tls connections
@@ -246,6 +463,390 @@ pub struct NodeArguments {
     /// This option only has an effect on Linux.
     #[arg(long = "firewall-mark")]
     firewall_mark: Option<u32>,
+
+    /// Maximum amount of inbound connections accepted from a single IP per minute.
+    ///
+    /// IPv6 addresses are grouped per /64. Excess connections are closed immediately.
+    #[arg(long = "max-inbound-connections-per-ip", default_value_t = DEFAULT_MAX_INBOUND_CONNECTIONS_PER_IP)]
+    max_inbound_connections_per_ip: u32,
+
+    /// Maximum amount of inbound connections which are being set up at the same time.
+    ///
+    /// New inbound connections are closed immediately while this limit is reached.
+    #[arg(long = "max-inbound-handshakes", default_value_t = DEFAULT_MAX_INBOUND_HANDSHAKES)]
+    max_inbound_handshakes: usize,
+
+    /// Maximum time to establish a connection to a peer, in seconds.
+    ///
+    /// For Quic, this includes the Quic handshake. Connection attempts which time out are retried
+    /// later, like other failed attempts.
+    #[arg(
+        long = "dial-connect-timeout",
+        default_value_t = DEFAULT_DIAL_CONNECT_TIMEOUT.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    dial_connect_timeout: u64,
+
+    /// Maximum time to set up a connection to a peer once it is established, in seconds.
+    ///
+    /// This covers the TLS handshake in a private network, and proving knowledge of the peering
+    /// key. Connection attempts which time out are retried later, like other failed attempts.
+    #[arg(
+        long = "dial-handshake-timeout",
+        default_value_t = DEFAULT_DIAL_HANDSHAKE_TIMEOUT.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    dial_handshake_timeout: u64,
+
+    /// Address of a SOCKS5 proxy to connect to tcp peers through, like `proxy.example.com:1080`.
+    ///
+    /// This is meant for networks in which the internet can only be reached through a proxy.
+    /// Hostnames of peers are resolved by the proxy. Link local peers are still connected to
+    /// directly, and quic peers are not connected to at all. Inbound connections are not affected,
+    /// use `--disable-tcp-listener` to refuse them.
+    #[arg(long = "proxy")]
+    proxy: Option<Address>,
+
+    /// Username to authenticate with at the proxy set with `--proxy`.
+    #[arg(long = "proxy-username", requires_all = ["proxy", "proxy_password"])]
+    proxy_username: Option<String>,
+
+    /// Password to authenticate with at the proxy set with `--proxy`.
+    #[arg(
+        long = "proxy-password",
+        env = "MYCELIUM_PROXY_PASSWORD",
+        hide_env_values = true,
+        requires = "proxy_username"
+    )]
+    proxy_password: Option<String>,
+
+    /// Only accept inbound peers from these underlay subnets or IPs.
+    ///
+    /// If not set, inbound peers from any IP which is not explicitly denied are accepted.
+    #[arg(long = "peer-allow", num_args = 1..)]
+    peer_allow: Vec<Subnet>,
+
+    /// Refuse inbound peers from these underlay subnets or IPs.
+    ///
+    /// This takes precedence over --peer-allow.
+    #[arg(long = "peer-deny", num_args = 1..)]
+    peer_deny: Vec<Subnet>,
+
+    /// Only accept routes for overlay subnets contained in these subnets.
+    ///
+    /// If not set, routes for any subnet which is not explicitly denied are accepted.
+    #[arg(long = "route-allow", num_args = 1..)]
+    route_allow: Vec<Subnet>,
+
+    /// Refuse routes for overlay subnets which overlap with these subnets.
+    ///
+    /// This takes precedence over --route-allow.
+    #[arg(long = "route-deny", num_args = 1..)]
+    route_deny: Vec<Subnet>,
+
+    /// Advertise routes to the peer they are selected through with an infinite metric.
+    ///
+    /// By default, routes are not advertised at all to the peer they are selected through (split
+    /// horizon). With poison reverse, that peer is explicitly told it can't route through this
+    /// node, so it drops such routes right away. This speeds up reconvergence, at the cost of
+    /// some extra control traffic.
+    #[arg(long = "poison-reverse", default_value_t = false)]
+    poison_reverse: bool,
+
+    /// Multiplier applied to the interval advertised in route updates, to get the time after
+    /// which a route expires if it is not refreshed.
+    ///
+    /// Routes of a peer which silently stops refreshing them are removed after this time. Lower
+    /// values remove such routes faster, but make it more likely that a route expires when a few
+    /// updates are lost. Must be at least 1.
+    #[arg(
+        long = "route-hold-time-multiplier",
+        default_value_t = DEFAULT_ROUTE_HOLD_TIME_MULTIPLIER,
+        value_parser = parse_route_hold_time_multiplier
+    )]
+    route_hold_time_multiplier: f64,
+
+    /// Time between Hello messages sent to peers, in seconds.
+    ///
+    /// Peers which don't answer `--dead-peer-hellos` consecutive Hello messages are considered
+    /// dead, so lower values detect dead peers faster at the cost of more control traffic. Must be
+    /// between 1 and 655.
+    #[arg(
+        long = "hello-interval",
+        default_value_t = DEFAULT_HELLO_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(MIN_INTERVAL.as_secs()..=MAX_INTERVAL.as_secs())
+    )]
+    hello_interval: u64,
+
+    /// Interval advertised in IHU messages sent to peers, in seconds. Must be between 1 and 655.
+    #[arg(
+        long = "ihu-interval",
+        default_value_t = DEFAULT_IHU_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(MIN_INTERVAL.as_secs()..=MAX_INTERVAL.as_secs())
+    )]
+    ihu_interval: u64,
+
+    /// Amount of consecutive Hello messages a peer may leave unanswered before it is considered
+    /// dead, and the connection to it is closed. Must be at least 1.
+    #[arg(
+        long = "dead-peer-hellos",
+        default_value_t = DEFAULT_DEAD_PEER_HELLOS,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    dead_peer_hellos: u32,
+
+    /// Time between periodic route updates sent to peers, in seconds.
+    ///
+    /// This is also the interval advertised in the updates, which peers use to compute when the
+    /// routes expire. Must be between 1 and 655.
+    #[arg(
+        long = "update-interval",
+        default_value_t = DEFAULT_UPDATE_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(MIN_INTERVAL.as_secs()..=MAX_INTERVAL.as_secs())
+    )]
+    update_interval: u64,
+
+    /// Minimum time between triggered updates sent to a peer, in milliseconds.
+    ///
+    /// Updates for routes which change within this time are sent to the peer as a single batch,
+    /// which reduces the amount of updates when many routes change at once. Retractions are always
+    /// sent right away. Set to 0 to send every triggered update right away. Must not be larger
+    /// than the update interval.
+    #[arg(
+        long = "triggered-update-interval",
+        default_value_t = DEFAULT_TRIGGERED_UPDATE_INTERVAL.as_millis() as u64
+    )]
+    triggered_update_interval: u64,
+
+    /// Penalty above which a route which keeps changing is suppressed.
+    ///
+    /// Every time a route through a peer gets selected, or is no longer selected, a penalty of
+    /// 1000 is added to it, which halves every flap damping half life. Suppressed routes are not
+    /// selected until their penalty decays below the reuse threshold.
+    #[arg(long = "flap-suppress-threshold", default_value_t = DEFAULT_SUPPRESS_THRESHOLD)]
+    flap_suppress_threshold: u32,
+
+    /// Penalty below which a suppressed route can be selected again. Must be lower than the
+    /// suppress threshold.
+    #[arg(long = "flap-reuse-threshold", default_value_t = DEFAULT_REUSE_THRESHOLD)]
+    flap_reuse_threshold: u32,
+
+    /// Time after which the penalty of a route has decayed to half its value, in seconds.
+    #[arg(
+        long = "flap-half-life",
+        default_value_t = DEFAULT_HALF_LIFE.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    flap_half_life: u64,
+
+    /// Amount of recent Hellos from a peer used to compute the fraction of Hellos received over
+    /// the link to it. Must be between 1 and 64.
+    #[arg(
+        long = "hello-history-window",
+        default_value_t = DEFAULT_HELLO_HISTORY_WINDOW,
+        value_parser = clap::value_parser!(u8).range(1..=MAX_HELLO_HISTORY_WINDOW as i64)
+    )]
+    hello_history_window: u8,
+
+    /// Cost added to the link to a peer if all recent Hellos from it were lost. Links with partial
+    /// loss get a proportional part of this cost.
+    #[arg(long = "hello-loss-cost", default_value_t = DEFAULT_HELLO_LOSS_COST)]
+    hello_loss_cost: u16,
+
+    /// Send Hellos twice as often to peers of which less than this fraction of the recent Hellos
+    /// was received. Must be between 0 and 1.
+    #[arg(long = "fast-hello-threshold", value_parser = parse_fraction)]
+    fast_hello_threshold: Option<f64>,
+
+    /// Add an offset to the metric of routes through peers in a subnet, as SUBNET=OFFSET.
+    ///
+    /// Routes through peers with an overlay address in the subnet are less likely to be selected
+    /// than otherwise equal routes through other peers. If multiple subnets contain the address of
+    /// a peer, the most specific one applies.
+    #[arg(long = "metric-offset", num_args = 1.., value_parser = parse_metric_offset)]
+    metric_offsets: Vec<MetricOffset>,
+
+    /// Maximum amount by which the metric of a route through a peer with a higher priority can
+    /// exceed the metric of the best route, for the route to be selected because of its priority.
+    ///
+    /// The priority of a peer is set through the API. Routes which exceed this are selected
+    /// based on their metric only.
+    #[arg(long = "priority-tolerance", default_value_t = DEFAULT_PRIORITY_TOLERANCE)]
+    priority_tolerance: u16,
+
+    /// Maximum amount of routes in the routing table, including fallback routes.
+    ///
+    /// Once this is reached, a new route is only learned if a less preferred route can be
+    /// evicted, i.e. a fallback route, or a selected route with a higher metric. By default, the
+    /// amount of routes is not limited.
+    #[arg(long = "max-routes")]
+    max_routes: Option<usize>,
+
+    /// Amount of recent routing events kept in the event log.
+    ///
+    /// The event log holds route selections and retractions, peers connecting and disconnecting,
+    /// and seqno bumps of the node, and can be inspected with the `/api/v1/admin/events` API
+    /// endpoint. Once it is full, the oldest events are dropped. Set this to 0 to disable it.
+    #[arg(long = "event-log-size", default_value_t = DEFAULT_EVENT_LOG_SIZE)]
+    event_log_size: usize,
+
+    /// Map an IPv4 address to the node with an overlay IP, as IPV4=OVERLAY_IP.
+    ///
+    /// IPv4 packets from the TUN interface to a mapped address are sent to its node. IPv4 packets
+    /// from other nodes are only accepted if their source address is mapped to the sending node,
+    /// so both nodes need each other's mapping. IPv4 addresses and routes on the TUN interface
+    /// must be configured separately.
+    #[arg(long = "ipv4-mapping", num_args = 1.., value_parser = parse_ipv4_mapping)]
+    ipv4_mappings: Vec<Ipv4Mapping>,
+
+    /// Announce a default route (::/0), so other nodes can use this node as exit node to reach
+    /// IPv6 addresses outside of the overlay.
+    ///
+    /// Packets for those addresses are written to the TUN interface. The host must forward them
+    /// to its uplink and NAT them, e.g. by enabling IPv6 forwarding and masquerading traffic from
+    /// 400::/7. Other nodes only use the default route if they set --accept-default-route.
+    #[arg(long = "exit-node", default_value_t = false)]
+    exit_node: bool,
+
+    /// Only announce the default route of an exit node for traffic from these overlay subnets.
+    ///
+    /// The default route is announced as a source-specific route for each subnet, so other nodes
+    /// only send traffic from these subnets through this node. Older nodes which don't support
+    /// source-specific routes don't learn the default route, and traffic from other subnets is not
+    /// forwarded by the exit node. Requires --exit-node.
+    #[arg(long = "exit-source-prefix", num_args = 1.., requires = "exit_node")]
+    exit_source_prefixes: Vec<Subnet>,
+
+    /// Accept default routes (::/0) announced by exit nodes.
+    ///
+    /// The default route is only used for addresses outside of the overlay, which must be routed
+    /// to the TUN interface on the host, and is also forwarded to peers which accept it. Without
+    /// this, default routes are ignored, so nodes can't attract traffic unexpectedly.
+    #[arg(long = "accept-default-route", default_value_t = false)]
+    accept_default_route: bool,
+
+    /// Compress data sent to other nodes which support it, if this makes the data smaller.
+    ///
+    /// This saves bandwidth on slow links, such as cellular or satellite connections, at the cost
+    /// of some CPU time. Data which is already compressed or encrypted is sent as is.
+    #[arg(long = "compress-data", default_value_t = false)]
+    compress_data: bool,
+
+    /// Amount of tasks which process data packets in each direction.
+    ///
+    /// Encryption and decryption of data packets is spread over these tasks, so more cores can be
+    /// used. On Linux, the TUN interface is opened with this many queues. Packets of the same flow
+    /// are always processed by the same task, so they stay in order. Must be between 1 and 256.
+    #[arg(
+        long = "packet-workers",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..=MAX_PACKET_WORKERS as i64)
+    )]
+    packet_workers: u16,
+
+    /// Capacity of the queue of data packets received from peers which still need to be routed.
+    ///
+    /// If this queue is full, peers stop reading from their connection until there is space
+    /// again.
+    #[arg(long = "router-data-queue-size", default_value_t = DEFAULT_QUEUE_SIZE)]
+    router_data_queue_size: usize,
+
+    /// Capacity of the queue of control packets received from peers, and of the queues of
+    /// received route updates, route requests and seqno requests.
+    ///
+    /// Control packets are never dropped. Once these queues are full, peers stop reading from
+    /// their connection until there is space again.
+    #[arg(long = "router-control-queue-size", default_value_t = DEFAULT_QUEUE_SIZE)]
+    router_control_queue_size: usize,
+
+    /// Capacity of the queue of data packets to be sent to a single peer.
+    ///
+    /// If this queue is full, new packets for the peer are dropped.
+    #[arg(long = "peer-data-queue-size", default_value_t = DEFAULT_QUEUE_SIZE)]
+    peer_data_queue_size: usize,
+
+    /// Capacity of the queue of data packets for the local node, before they are processed.
+    ///
+    /// If this queue is full, new packets are dropped.
+    #[arg(long = "node-tun-queue-size", default_value_t = DEFAULT_QUEUE_SIZE)]
+    node_tun_queue_size: usize,
+
+    /// Capacity of the queue of packets waiting to be written to the TUN interface, per queue of
+    /// the interface.
+    #[arg(long = "tun-queue-size", default_value_t = DEFAULT_QUEUE_SIZE)]
+    tun_queue_size: usize,
+
+    /// Path of a file in which received messages which are not read yet are stored, so they are
+    /// not lost if the node restarts.
+    ///
+    /// The file is encrypted with a key derived from the node key. If this is not set, messages
+    /// are only kept in memory.
+    #[arg(long = "message-store")]
+    message_store: Option<PathBuf>,
+
+    /// Maximum total size of the stored messages, in bytes. Once this is exceeded, the oldest
+    /// messages are dropped.
+    #[arg(long = "message-store-max-size", default_value_t = DEFAULT_MESSAGE_STORE_MAX_SIZE)]
+    message_store_max_size: usize,
+
+    /// Maximum time a received message is kept if it is not read, in seconds.
+    #[arg(
+        long = "message-store-max-age",
+        default_value_t = DEFAULT_MESSAGE_STORE_MAX_AGE.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    message_store_max_age: u64,
+
+    /// Path of a file to which the unencrypted IP packets exchanged with other nodes are written,
+    /// in pcap format.
+    ///
+    /// This is meant to debug connectivity of applications over the overlay with tools like
+    /// Wireshark. The capture contains all traffic of this node in plain text, so only enable this
+    /// while debugging. Packets which are only forwarded by this node can't be captured.
+    #[arg(long = "packet-capture")]
+    packet_capture: Option<PathBuf>,
+
+    /// Only capture packets from or to an overlay IP in one of these subnets. If not set, all
+    /// packets are captured.
+    #[arg(long = "packet-capture-filter", num_args = 1..)]
+    packet_capture_filter: Vec<Subnet>,
+
+    /// Maximum size of a packet capture file, in bytes. Once this is reached, the file is rotated.
+    #[arg(long = "packet-capture-max-file-size", default_value_t = DEFAULT_CAPTURE_MAX_FILE_SIZE)]
+    packet_capture_max_file_size: u64,
+
+    /// Amount of packet capture files which are kept, including the one being written. Rotated
+    /// files get a numeric suffix, and the oldest one is removed once this is exceeded.
+    #[arg(
+        long = "packet-capture-max-files",
+        default_value_t = DEFAULT_CAPTURE_MAX_FILES as u16,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    packet_capture_max_files: u16,
+
+    /// Maximum amount of ICMPv6 echo requests for the overlay address of the node which are
+    /// answered per second.
+    ///
+    /// The node answers these itself, so it can be pinged over the overlay even if the host does
+    /// not answer, or there is no TUN interface. Requests beyond this rate are dropped, so the node
+    /// can't be used to amplify a ping flood. If 0, echo requests are written to the TUN interface
+    /// like any other packet, so the host answers them.
+    #[arg(long = "echo-reply-rate", default_value_t = DEFAULT_ECHO_REPLY_RATE)]
+    echo_reply_rate: u32,
+
+    /// Also write the echo requests answered by the node to the TUN interface.
+    ///
+    /// If the host answers them as well, the sender receives duplicate replies.
+    #[arg(long = "echo-forward-to-tun", default_value_t = false)]
+    echo_forward_to_tun: bool,
+
+    /// The path to the file with the pre-shared key peers must know.
+    ///
+    /// If this is set, every peer must prove it knows this key right after the connection is set
+    /// up, otherwise the connection is closed. This works with all transports. The key is expected
+    /// to be exactly 32 bytes, and must be the same on all nodes of the network.
+    #[arg(long = "peering-key-file")]
+    peering_key_file: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -260,16 +861,22 @@ async fn main() -> Result<(), Box<dyn Error>> {
         tracing::Level::INFO
     };
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::Layer::new().compact().with_filter(
-                EnvFilter::builder()
-                    .with_default_directive(level.into())
-                    .from_env()
-                    .expect("invalid RUST_LOG"),
-            ),
-        )
-        .init();
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env()
+        .expect("invalid RUST_LOG");
+    let log_layer = match cli.log_format {
+        LogFormat::Compact => tracing_subscriber::fmt::Layer::new()
+            .compact()
+            .with_filter(filter)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::Layer::new()
+            .json()
+            .with_filter(filter)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry().with(log_layer).init();
 
     let key_path = if let Some(path) = cli.key_file {
         path
@@ -279,7 +886,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // Load the keypair for this node, or generate a new one if the file does not exist.
     let node_keys = if key_path.exists() {
-        let sk = load_key_file(&key_path).await?;
+        let sk = crypto::SecretKey::load_from_file(&key_path).await?;
         let pk = crypto::PublicKey::from(&sk);
         debug!("Loaded key file at {key_path:?}");
         Some((sk, pk))
@@ -289,9 +896,15 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     if let Some(cmd) = cli.command {
         match cmd {
-            Command::Inspect { json, key } => {
+            Command::Inspect {
+                json,
+                key_path,
+                key,
+            } => {
                 let key = if let Some(key) = key {
                     PublicKey::try_from(key.as_str())?
+                } else if let Some(key_path) = key_path {
+                    PublicKey::from(&crypto::SecretKey::load_from_file(&key_path).await?)
                 } else if let Some((_, node_pub_key)) = node_keys {
                     node_pub_key
                 } else {
@@ -306,6 +919,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
                 return Ok(());
             }
+            Command::Keygen { out, force, json } => {
+                return mycelium_cli::keygen(&out, force, json).await;
+            }
             Command::Message { command } => match command {
                 MessageCommand::Send {
                     wait,
@@ -346,21 +962,71 @@ async fn main() -> Result<(), Box<dyn Error>> {
             },
             Command::Peers { command } => match command {
                 PeersCommand::List { json } => {
-                    return mycelium_cli::list_peers(cli.node_args.api_addr, json).await;
+                    return mycelium_cli::list_peers(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        json,
+                    )
+                    .await;
                 }
                 PeersCommand::Add { peers } => {
-                    return mycelium_cli::add_peers(cli.node_args.api_addr, peers).await;
+                    return mycelium_cli::add_peers(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        peers,
+                    )
+                    .await;
                 }
                 PeersCommand::Remove { peers } => {
-                    return mycelium_cli::remove_peers(cli.node_args.api_addr, peers).await;
+                    return mycelium_cli::remove_peers(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        peers,
+                    )
+                    .await;
+                }
+                PeersCommand::Neighbours { json } => {
+                    return mycelium_cli::list_neighbours(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        json,
+                    )
+                    .await;
                 }
             },
             Command::Routes { command } => match command {
                 RoutesCommand::Selected { json } => {
-                    return mycelium_cli::list_selected_routes(cli.node_args.api_addr, json).await;
+                    return mycelium_cli::list_selected_routes(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        json,
+                    )
+                    .await;
                 }
                 RoutesCommand::Fallback { json } => {
-                    return mycelium_cli::list_fallback_routes(cli.node_args.api_addr, json).await;
+                    return mycelium_cli::list_fallback_routes(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        json,
+                    )
+                    .await;
+                }
+                RoutesCommand::Suppressed { json } => {
+                    return mycelium_cli::list_suppressed_routes(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        json,
+                    )
+                    .await;
+                }
+                RoutesCommand::All { subnet, json } => {
+                    return mycelium_cli::list_all_routes(
+                        cli.node_args.api_addr,
+                        cli.node_args.api_key.as_deref(),
+                        subnet,
+                        json,
+                    )
+                    .await;
                 }
             },
         }
@@ -376,22 +1042,116 @@ async fn main() -> Result<(), Box<dyn Error>> {
         _ => None,
     };
 
+    let peering_key = match &cli.node_args.peering_key_file {
+        Some(path) => Some(load_key_file(path).await?),
+        None => None,
+    };
+
     let node_secret_key = if let Some((node_secret_key, _)) = node_keys {
         node_secret_key
     } else {
         warn!("Node key file {key_path:?} not found, generating new keys");
         let secret_key = crypto::SecretKey::new();
-        save_key_file(&secret_key, &key_path).await?;
+        secret_key.save_to_file(&key_path).await?;
         secret_key
     };
 
-    let _api = if let Some(metrics_api_addr) = cli.node_args.metrics_api_address {
+    let api_tcp_listen_addr = match (cli.node_args.api_tls_cert, cli.node_args.api_tls_key) {
+        (Some(cert_path), Some(key_path)) => mycelium_api::ListenAddr::Tls {
+            addr: cli.node_args.api_addr,
+            cert_path,
+            key_path,
+        },
+        _ => cli.node_args.api_addr.into(),
+    };
+    #[cfg(target_family = "unix")]
+    let api_listen_addr = match cli.node_args.api_socket {
+        Some(path) => mycelium_api::ListenAddr::Unix {
+            path,
+            mode: cli.node_args.api_socket_mode,
+        },
+        None => api_tcp_listen_addr,
+    };
+    #[cfg(not(target_family = "unix"))]
+    let api_listen_addr = api_tcp_listen_addr;
+    let api_mode = if cli.node_args.api_read_only {
+        mycelium_api::ApiMode::ReadOnly
+    } else {
+        mycelium_api::ApiMode::Full
+    };
+
+    let split_horizon = if cli.node_args.poison_reverse {
+        SplitHorizon::PoisonReverse
+    } else {
+        SplitHorizon::Simple
+    };
+    let intervals = Intervals {
+        hello: Duration::from_secs(cli.node_args.hello_interval),
+        ihu: Duration::from_secs(cli.node_args.ihu_interval),
+        update: Duration::from_secs(cli.node_args.update_interval),
+        dead_peer_hellos: cli.node_args.dead_peer_hellos,
+        triggered_update: Duration::from_millis(cli.node_args.triggered_update_interval),
+    };
+    let queue_sizes = QueueSizes {
+        router_data: cli.node_args.router_data_queue_size,
+        router_control: cli.node_args.router_control_queue_size,
+        peer_data: cli.node_args.peer_data_queue_size,
+        node_tun: cli.node_args.node_tun_queue_size,
+        tun: cli.node_args.tun_queue_size,
+    };
+    let flap_damping = FlapDampingConfig {
+        suppress_threshold: cli.node_args.flap_suppress_threshold,
+        reuse_threshold: cli.node_args.flap_reuse_threshold,
+        half_life: Duration::from_secs(cli.node_args.flap_half_life),
+    };
+    let hello_history = HelloHistoryConfig {
+        window: cli.node_args.hello_history_window,
+        loss_cost: cli.node_args.hello_loss_cost,
+        fast_hello_threshold: cli.node_args.fast_hello_threshold,
+    };
+    let message_store = cli.node_args.message_store.map(|path| MessageStoreConfig {
+        path,
+        max_size: cli.node_args.message_store_max_size,
+        max_age: Duration::from_secs(cli.node_args.message_store_max_age),
+    });
+    let packet_capture = cli
+        .node_args
+        .packet_capture
+        .map(|path| PacketCaptureConfig {
+            path,
+            max_file_size: cli.node_args.packet_capture_max_file_size,
+            max_files: cli.node_args.packet_capture_max_files as usize,
+            filter: cli.node_args.packet_capture_filter,
+        });
+    let tcp_listen_addrs = if cli.node_args.disable_tcp_listener {
+        vec![]
+    } else {
+        cli.node_args
+            .tcp_listen_addresses
+            .into_iter()
+            .map(|(ip, port)| SocketAddr::new(ip, port.unwrap_or(cli.node_args.tcp_listen_port)))
+            .collect()
+    };
+    let proxy = cli.node_args.proxy.map(|address| Socks5Proxy {
+        address,
+        credentials: cli
+            .node_args
+            .proxy_username
+            .zip(cli.node_args.proxy_password)
+            .map(|(username, password)| ProxyCredentials { username, password }),
+    });
+    let echo_reply = (cli.node_args.echo_reply_rate > 0).then_some(EchoReplyConfig {
+        max_rate: cli.node_args.echo_reply_rate,
+        forward_to_tun: cli.node_args.echo_forward_to_tun,
+    });
+
+    let (api, _grpc) = if let Some(metrics_api_addr) = cli.node_args.metrics_api_address {
         let metrics = mycelium_metrics::PrometheusExporter::new();
         let config = mycelium::Config {
             node_key: node_secret_key,
             peers: cli.node_args.static_peers,
             no_tun: cli.node_args.no_tun,
-            tcp_listen_port: cli.node_args.tcp_listen_port,
+            tcp_listen_addrs,
             quic_listen_port: Some(cli.node_args.quic_listen_port),
             peer_discovery_port: if cli.node_args.disable_peer_discovery {
                 None
@@ -399,19 +1159,86 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 Some(cli.node_args.peer_discovery_port)
             },
             tun_name: cli.node_args.tun_name,
+            tun_mtu: cli.node_args.tun_mtu,
+            tun_route: TunRouteConfig {
+                table: cli.node_args.tun_route_table,
+                metric: cli.node_args.tun_route_metric,
+            },
             private_network_config,
+            peering_key,
             metrics: metrics.clone(),
             firewall_mark: cli.node_args.firewall_mark,
+            inbound_connection_limits: InboundConnectionLimits {
+                max_connections_per_ip: cli.node_args.max_inbound_connections_per_ip,
+                max_concurrent_handshakes: cli.node_args.max_inbound_handshakes,
+            },
+            dial_timeouts: DialTimeouts {
+                connect: Duration::from_secs(cli.node_args.dial_connect_timeout),
+                handshake: Duration::from_secs(cli.node_args.dial_handshake_timeout),
+            },
+            proxy,
+            peer_access_list: AccessList {
+                allow: cli.node_args.peer_allow,
+                deny: cli.node_args.peer_deny,
+            },
+            route_access_list: AccessList {
+                allow: cli.node_args.route_allow,
+                deny: cli.node_args.route_deny,
+            },
+            split_horizon,
+            route_selector: Box::new(MetricSelector::new(cli.node_args.priority_tolerance)),
+            route_hold_time_multiplier: cli.node_args.route_hold_time_multiplier,
+            intervals,
+            queue_sizes,
+            flap_damping,
+            hello_history,
+            metric_offsets: MetricOffsets {
+                offsets: cli.node_args.metric_offsets,
+            },
+            max_routes: cli.node_args.max_routes,
+            event_log_size: cli.node_args.event_log_size,
+            ipv4_mappings: Ipv4Mappings {
+                mappings: cli.node_args.ipv4_mappings,
+            },
+            exit_node: cli.node_args.exit_node,
+            exit_source_prefixes: cli.node_args.exit_source_prefixes,
+            accept_default_route: cli.node_args.accept_default_route,
+            compress_data: cli.node_args.compress_data,
+            packet_workers: cli.node_args.packet_workers as usize,
+            message_store,
+            packet_capture,
+            echo_reply,
+            ready_min_peers: cli.node_args.ready_min_peers,
         };
         metrics.spawn(metrics_api_addr);
-        let node = Node::new(config).await?;
-        mycelium_api::Http::spawn(node, cli.node_args.api_addr)
+        let node = Arc::new(Mutex::new(Node::new(config).await?));
+        #[cfg(feature = "grpc")]
+        let grpc = cli.node_args.grpc_addr.map(|grpc_addr| {
+            mycelium_api::Grpc::spawn(
+                node.clone(),
+                grpc_addr,
+                cli.node_args.api_key.clone(),
+                api_mode,
+            )
+        });
+        #[cfg(not(feature = "grpc"))]
+        let grpc = ();
+        (
+            mycelium_api::Http::spawn_shared(
+                node,
+                api_listen_addr,
+                cli.node_args.api_key,
+                cli.node_args.api_allowed_origins,
+                api_mode,
+            ),
+            grpc,
+        )
     } else {
         let config = mycelium::Config {
             node_key: node_secret_key,
             peers: cli.node_args.static_peers,
             no_tun: cli.node_args.no_tun,
-            tcp_listen_port: cli.node_args.tcp_listen_port,
+            tcp_listen_addrs,
             quic_listen_port: Some(cli.node_args.quic_listen_port),
             peer_discovery_port: if cli.node_args.disable_peer_discovery {
                 None
@@ -419,12 +1246,79 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 Some(cli.node_args.peer_discovery_port)
             },
             tun_name: cli.node_args.tun_name,
+            tun_mtu: cli.node_args.tun_mtu,
+            tun_route: TunRouteConfig {
+                table: cli.node_args.tun_route_table,
+                metric: cli.node_args.tun_route_metric,
+            },
             private_network_config,
+            peering_key,
             metrics: mycelium_metrics::NoMetrics,
             firewall_mark: cli.node_args.firewall_mark,
+            inbound_connection_limits: InboundConnectionLimits {
+                max_connections_per_ip: cli.node_args.max_inbound_connections_per_ip,
+                max_concurrent_handshakes: cli.node_args.max_inbound_handshakes,
+            },
+            dial_timeouts: DialTimeouts {
+                connect: Duration::from_secs(cli.node_args.dial_connect_timeout),
+                handshake: Duration::from_secs(cli.node_args.dial_handshake_timeout),
+            },
+            proxy,
+            peer_access_list: AccessList {
+                allow: cli.node_args.peer_allow,
+                deny: cli.node_args.peer_deny,
+            },
+            route_access_list: AccessList {
+                allow: cli.node_args.route_allow,
+                deny: cli.node_args.route_deny,
+            },
+            split_horizon,
+            route_selector: Box::new(MetricSelector::new(cli.node_args.priority_tolerance)),
+            route_hold_time_multiplier: cli.node_args.route_hold_time_multiplier,
+            intervals,
+            queue_sizes,
+            flap_damping,
+            hello_history,
+            metric_offsets: MetricOffsets {
+                offsets: cli.node_args.metric_offsets,
+            },
+            max_routes: cli.node_args.max_routes,
+            event_log_size: cli.node_args.event_log_size,
+            ipv4_mappings: Ipv4Mappings {
+                mappings: cli.node_args.ipv4_mappings,
+            },
+            exit_node: cli.node_args.exit_node,
+            exit_source_prefixes: cli.node_args.exit_source_prefixes,
+            accept_default_route: cli.node_args.accept_default_route,
+            compress_data: cli.node_args.compress_data,
+            packet_workers: cli.node_args.packet_workers as usize,
+            message_store,
+            packet_capture,
+            echo_reply,
+            ready_min_peers: cli.node_args.ready_min_peers,
         };
-        let node = Node::new(config).await?;
-        mycelium_api::Http::spawn(node, cli.node_args.api_addr)
+        let node = Arc::new(Mutex::new(Node::new(config).await?));
+        #[cfg(feature = "grpc")]
+        let grpc = cli.node_args.grpc_addr.map(|grpc_addr| {
+            mycelium_api::Grpc::spawn(
+                node.clone(),
+                grpc_addr,
+                cli.node_args.api_key.clone(),
+                api_mode,
+            )
+        });
+        #[cfg(not(feature = "grpc"))]
+        let grpc = ();
+        (
+            mycelium_api::Http::spawn_shared(
+                node,
+                api_listen_addr,
+                cli.node_args.api_key,
+                cli.node_args.api_allowed_origins,
+                api_mode,
+            ),
+            grpc,
+        )
     };
 
     // TODO: put in dedicated file so we can only rely on certain signals on unix platforms
@@ -447,9 +1341,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    // Shutting down the node retracts our routes from all peers before closing the connections,
+    // and waits until the retractions are sent. Give the peers a moment to process them before
+    // the runtime is torn down.
+    api.shutdown().await;
+    tokio::time::sleep(SHUTDOWN_GRACE_PERIOD).await;
+
     Ok(())
 }
 
+/// Parse an octal file mode, e.g. `600` or `0o600`.
+#[cfg(target_family = "unix")]
+fn parse_file_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("{s} is not a valid octal file mode"))
+}
+
 async fn load_key_file<T>(path: &Path) -> Result<T, io::Error>
 where
     T: From<[u8; 32]>,
@@ -461,25 +1370,58 @@ where
     Ok(T::from(secret_bytes))
 }
 
-async fn save_key_file(key: &crypto::SecretKey, path: &Path) -> io::Result<()> {
-    #[cfg(target_family = "unix")]
-    {
-        use tokio::fs::OpenOptions;
-
-        let mut file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .mode(0o600) // rw by the owner, not readable by group or others
-            .open(path)
-            .await?;
-        file.write_all(key.as_bytes()).await?;
-    }
-    #[cfg(not(target_family = "unix"))]
-    {
-        let mut file = File::create(path).await?;
-        file.write_all(key.as_bytes()).await?;
+/// Parse a metric offset of the form `subnet=offset`.
+fn parse_metric_offset(s: &str) -> Result<MetricOffset, String> {
+    let (subnet, offset) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{s} is not of the form SUBNET=OFFSET"))?;
+    Ok(MetricOffset {
+        subnet: subnet
+            .parse()
+            .map_err(|e| format!("{subnet} is not a valid subnet: {e}"))?,
+        offset: offset
+            .parse()
+            .map_err(|e| format!("{offset} is not a valid offset: {e}"))?,
+    })
+}
+
+/// Parse an IPv4 mapping of the form `ipv4=overlay_ip`.
+fn parse_ipv4_mapping(s: &str) -> Result<Ipv4Mapping, String> {
+    let (ipv4, overlay_ip) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{s} is not of the form IPV4=OVERLAY_IP"))?;
+    Ok(Ipv4Mapping {
+        ipv4: ipv4
+            .parse()
+            .map_err(|e| format!("{ipv4} is not a valid IPv4 address: {e}"))?,
+        overlay_ip: overlay_ip
+            .parse()
+            .map_err(|e| format!("{overlay_ip} is not a valid IPv6 address: {e}"))?,
+    })
+}
+
+/// Parse a listen address, which is an IP address, optionally with a port.
+fn parse_listen_address(s: &str) -> Result<(IpAddr, Option<u16>), String> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok((addr.ip(), Some(addr.port())));
     }
+    s.parse()
+        .map(|ip| (ip, None))
+        .map_err(|_| format!("{s} is not a valid IP address or socket address"))
+}
 
-    Ok(())
+/// Parse a route hold time multiplier, which must be a finite number of at least 1.
+fn parse_route_hold_time_multiplier(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|multiplier| multiplier.is_finite() && *multiplier >= 1.0)
+        .ok_or_else(|| format!("{s} is not a number of at least 1"))
+}
+
+/// Parse a fraction, which must be a number between 0 and 1.
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|fraction| (0.0..=1.0).contains(fraction))
+        .ok_or_else(|| format!("{s} is not a number between 0 and 1"))
 }